@@ -1,81 +1,1107 @@
+import gzip
+import json
+from collections.abc import Callable, Iterable, Iterator, Mapping
+from contextlib import contextmanager
+from dataclasses import dataclass, field
 from pathlib import Path
+from typing import Literal, cast, overload
 
 import numpy as np
+import numpy.typing as npt
 
 from . import fastar_loader as _rust  # type: ignore
 
+# Exceptions raised by the loaders, all subclasses of FastarLoaderError and RuntimeError
+FastarLoaderError = _rust.FastarLoaderError
+NameNotFoundError = _rust.NameNotFoundError
+ContigNotFoundError = _rust.ContigNotFoundError
+OutOfBoundsError = _rust.OutOfBoundsError
+CacheCorruptedError = _rust.CacheCorruptedError
+StorageError = _rust.StorageError
+InvalidSequenceError = _rust.InvalidSequenceError
+
+# Cancels batch reads from another thread, see FastarLoader.read_sequence_all and WindowSampler
+CancellationToken = _rust.CancellationToken
+
+# Module-level coordinate conventions, see set_coordinates
+_coordinates: dict[str, bool | None] = {"one_based": None, "end_inclusive": None}
+
+
+def set_coordinates(one_based: bool | None = None, end_inclusive: bool | None = None) -> None:
+    """Set the default coordinate conventions of `read_sequence` of the loaders and of the
+    standalone function, which calls can still override.
+
+    Args:
+        one_based: Whether starts are 1-based as in samtools instead of 0-based as in BED files,
+            both for numeric starts and in region strings. `None` restores the default of
+            0-based numeric starts and 1-based region strings.
+        end_inclusive: Whether the ends of region strings are included. `None` restores the
+            default, which follows `one_based`.
+    """
+    _coordinates["one_based"] = one_based
+    _coordinates["end_inclusive"] = end_inclusive
+
+
+def _resolve_coordinates(
+    one_based: bool | None, end_inclusive: bool | None, is_region: bool
+) -> tuple[bool, bool]:
+    """The conventions of a call, falling back to the module-level ones and the defaults."""
+    if one_based is None:
+        one_based = _coordinates["one_based"]
+    if one_based is None:
+        one_based = is_region
+    if end_inclusive is None:
+        end_inclusive = _coordinates["end_inclusive"]
+    if end_inclusive is None:
+        end_inclusive = one_based
+    return one_based, end_inclusive
+
 
 def read_sequence(
     fasta_path: str | Path,
     contig: str,
-    start: int,
-    length: int,
+    start: int | None = None,
+    length: int | None = None,
     gzi_path: str | Path | None = None,
     fai_path: str | Path | None = None,
+    one_based: bool | None = None,
+    end_inclusive: bool | None = None,
 ) -> np.ndarray:
-    fasta_path = str(fasta_path)
-    if gzi_path is None:
-        gzi_path = f"{fasta_path}.gzi"
-    else:
-        gzi_path = str(gzi_path)
-    if fai_path is None:
-        fai_path = f"{fasta_path}.fai"
+    """Read a region of a BGZF-compressed FASTA file.
+
+    Args:
+        fasta_path: Path or URL of the FASTA file.
+        contig: The contig to read from, or without `start` and `length`, a region string such
+            as `"chr1:1,001-2,000"` or a whole contig.
+        start: Start of the region in the contig, 0-based by default.
+        length: Length of the region.
+        gzi_path: The GZI index, by default next to the FASTA file.
+        fai_path: The FAI index, by default next to the FASTA file.
+        one_based: Whether starts are 1-based as in samtools instead of 0-based as in BED files.
+            By default, numeric starts are 0-based and region strings 1-based, unless changed
+            with `set_coordinates`.
+        end_inclusive: Whether the ends of region strings are included, by default if they
+            are 1-based.
+
+    Missing indices of local files are built like `samtools faidx` would.
+    """
+    start = _check_region(start, length, one_based)
+    one_based, end_inclusive = _resolve_coordinates(one_based, end_inclusive, is_region=True)
+    return _rust.read_sequence(
+        str(fasta_path),
+        contig,
+        start,
+        length,
+        None if gzi_path is None else str(gzi_path),
+        None if fai_path is None else str(fai_path),
+        one_based,
+        end_inclusive,
+    )
+
+
+def _gc_content(counts: np.ndarray) -> np.ndarray:
+    """Fraction of `C` and `G` among the `ACGT` bases of base counts, see `composition`."""
+    acgt = counts[..., :4].sum(axis=-1)
+    with np.errstate(divide="ignore", invalid="ignore"):
+        return (counts[..., 1] + counts[..., 2]) / acgt
+
+
+def _check_region(start: int | None, length: int | None, one_based: bool | None) -> int | None:
+    """The 0-based start of a region given by `start` and `length`."""
+    if (start is None) != (length is None):
+        raise ValueError("start and length must be given together")
+    if start is None or not _resolve_coordinates(one_based, None, is_region=False)[0]:
+        return start
+    if start < 1:
+        raise ValueError(f"Start {start} is not 1-based")
+    return start - 1
+
+
+def _resolve_region(
+    index_map: object,
+    name: str,
+    contig: str,
+    start: int | None,
+    length: int | None,
+    one_based: bool | None,
+    end_inclusive: bool | None,
+) -> tuple[str, int, int]:
+    """(contig, 0-based start, length) of a contig with `start` and `length`, or of a region
+    string."""
+    start = _check_region(start, length, one_based)
+    if start is not None and length is not None:
+        return contig, start, length
+    one_based, end_inclusive = _resolve_coordinates(one_based, end_inclusive, is_region=True)
+    return index_map.resolve_region(  # type: ignore[attr-defined]
+        name, contig, one_based, end_inclusive
+    )
+
+
+def _stream(
+    loader: "FastarLoader | TrackLoader",
+    queries_or_sampler: "Iterable[tuple[str, str, int, int]] | WindowSampler",
+    prefetch: int,
+    batch_size: int,
+    num_batches: int | None,
+) -> Iterator[np.ndarray]:
+    """The stream of `FastarLoader.stream` and `TrackLoader.stream`."""
+    if isinstance(queries_or_sampler, WindowSampler):
+        if queries_or_sampler.loader is not loader:
+            raise ValueError("The sampler samples from another loader")
+        return _rust.Stream.sampler(queries_or_sampler._sampler, batch_size, prefetch, num_batches)
+    queries = [
+        (name, contig.encode(), start, length) for name, contig, start, length in queries_or_sampler
+    ]
+    return _rust.Stream.queries(loader._index_map, queries, prefetch)
+
+
+@dataclass(frozen=True)
+class Segment:
+    """A shared memory segment created by fastar_loader, see `list_segments`."""
+
+    name: str
+    path: str
+    # PID of the creating process, which removes the segment when its loader is dropped
+    pid: int
+    # Creation time in seconds since the epoch
+    created: int
+    size: int
+    # Whether the creator is still running, otherwise the segment is an orphan
+    alive: bool
+
+
+def list_segments() -> list[Segment]:
+    """Shared memory segments created by fastar_loader in `/dev/shm` or
+    `$FASTAR_LOADER_SHMEM_DIR`, including orphans of crashed processes."""
+    return [Segment(*segment) for segment in _rust.list_segments()]
+
+
+def cleanup_segments(prefix: str = "fastar-loader-", older_than: float = 0.0) -> list[Segment]:
+    """Remove orphaned shared memory segments, whose creator is no longer running.
+
+    Args:
+        prefix: Only remove segments whose name starts with this prefix, e.g.,
+            `"fastar-loader-1234-"` for the segments of process 1234.
+        older_than: Only remove segments created at least this many seconds ago.
+
+    Returns:
+        The removed segments. Processes still attached to them keep their mappings.
+    """
+    if older_than < 0:
+        raise ValueError("older_than must not be negative")
+    return [Segment(*segment) for segment in _rust.cleanup_segments(prefix, older_than)]
+
+
+def start_tracing(path: str | Path, format: str = "chrome") -> None:
+    """Export spans of building and reading to `path` until `stop_tracing`, to profile loaders.
+
+    Spans cover discovering files (`glob`), indexing each file (`index`), looking up a
+    region (`query`) and decompressing it (`decompress`), each with the thread it ran on.
+
+    Args:
+        path: File to write the trace to, which is overwritten.
+        format: `"chrome"` for a trace to open in Perfetto or chrome://tracing, or `"log"`
+            for a text file with the duration of each span.
+    """
+    if format not in ("chrome", "log"):
+        raise ValueError(f"Unknown trace format {format!r}, expected 'chrome' or 'log'")
+    _rust.start_tracing(str(path), format)
+
+
+def stop_tracing() -> None:
+    """Stop exporting spans and flush the trace file. Does nothing if tracing is not active."""
+    _rust.stop_tracing()
+
+
+@contextmanager
+def tracing(path: str | Path, format: str = "chrome") -> Iterator[None]:
+    """Export spans to `path` within a `with` block, see `start_tracing`."""
+    start_tracing(path, format)
+    try:
+        yield
+    finally:
+        stop_tracing()
+
+
+def set_retry_policy(
+    attempts: int = 1,
+    delay: float = 0.1,
+    backoff: float = 2.0,
+    errors: Iterable[str] = ("ESTALE", "EIO"),
+) -> None:
+    """Retry reads of data files which fail with transient errors, e.g., stale file handles
+    of NFS or Lustre. The policy applies to all loaders of the process; retries are logged as
+    warnings and counted as `retries` in `FastarLoader.metrics`.
+
+    Args:
+        attempts: Attempts of each read including the first one, where 1 disables retries.
+        delay: Seconds to wait before the first retry.
+        backoff: Factor by which the delay grows with every further retry.
+        errors: Names of the OS errors to retry, out of `EAGAIN`, `ECONNRESET`, `EHOSTDOWN`,
+            `EINTR`, `EIO`, `ENOTCONN`, `ESTALE` and `ETIMEDOUT`.
+    """
+    _rust.set_retry_policy(attempts, delay, backoff, list(errors))
+
+
+def set_num_threads(n: int | None, read: int | None = None) -> None:
+    """Run cache builds on a dedicated pool of `n` threads and batch reads, such as
+    `read_sequence_all` or `WindowSampler.sample`, on a pool of `read` threads, which defaults
+    to `n`. Use this if the process runs rayon itself, e.g., polars. With `None`, the
+    operations run on the global rayon pool again. Loaders with `io_threads` keep their own
+    pool for reads.
+    """
+    if n is not None and n <= 0 or read is not None and read <= 0:
+        raise ValueError("The number of threads must be positive")
+    _rust.set_num_threads(n, n if read is None else read)
+
+
+@dataclass(frozen=True)
+class PaddedBatch:
+    """Sequences of unequal length padded to a common length, see `collate`."""
+
+    # One sequence per row, padded at the end
+    values: np.ndarray
+    # The length of each sequence before padding
+    lengths: np.ndarray
+    # Whether each value belongs to its sequence rather than the padding, if requested
+    mask: np.ndarray | None = None
+
+
+def _pad_byte(pad: int | str) -> int:
+    return ord(pad) if isinstance(pad, str) else pad
+
+
+def collate(
+    sequences: Iterable[np.ndarray],
+    pad: int | str = 0,
+    length: int | None = None,
+    mask: bool = False,
+) -> PaddedBatch:
+    """Pad uint8 `sequences` at the end with `pad`, a byte or a character such as `"N"`, to
+    `length`, or to the longest sequence without `length`, in Rust. With `mask`, the batch also
+    has a boolean mask of the values, e.g., as the attention mask of a transformer. Sequences
+    longer than `length` raise a `ValueError`.
+    """
+    values, lengths, value_mask = _rust.collate(
+        [np.ascontiguousarray(sequence, dtype=np.uint8) for sequence in sequences],
+        _pad_byte(pad),
+        length,
+        mask,
+    )
+    return PaddedBatch(values, lengths, value_mask)
+
+
+@dataclass(frozen=True, kw_only=True)
+class LoadOptions:
+    """How a loader builds, caches and stores its index, see `FastarLoader.from_options`.
+
+    Options left at `None` default to building a cache and memory-mapping it with a progress
+    bar, or, if `names` are given, to keeping just these names in memory without a cache or
+    progress bar. Unknown values and incompatible combinations raise a `ValueError`.
+
+    Reads of a `FastarLoader` return bases other than `ACGT` as stored with `ambiguity="keep"`,
+    replace them by `N` with `"n"`, or, with `"sample"`, resolve IUPAC ambiguity codes such as
+    `R` to one of their bases, chosen by a hash of `ambiguity_seed` and the position so that
+    every read of a position returns the same base. Statistics such as `composition` and
+    digests are computed from the stored bases.
+
+    With an `alphabet` such as `"ACGTNacgtn"`, reads are strict: every read checks that it
+    returns exactly the requested number of bases, all of them in the alphabet, before applying
+    the ambiguity policy, and raises an `InvalidSequenceError` with the contig and position
+    otherwise. Independently, reads raise an `InvalidSequenceError` if the lines of a FASTA file
+    do not match its index, e.g., for CRLF line endings which the `.fai` does not count.
+
+    With `check_indices`, the `.fai` and `.gzi` index of each FASTA file are checked against
+    each other while indexing: every contig of the `.fai` must have a plausible line layout and
+    end within the uncompressed size implied by the `.gzi`. Files which fail are rejected or,
+    with `strict=False`, skipped with the reason. The checks run when the cache is built.
+
+    With `duplicates="warn"`, which requires `digests=True`, loading warns about contigs with
+    the same sequence as another contig, e.g., in pangenome collections, see
+    `FastarLoader.duplicate_contigs`. This reads the digests of all entries, including all
+    shards, at every load, so it cannot be combined with `lazy=True`.
+
+    Files are found below the root through symbolic links to files and directories, unless
+    `follow_symlinks=False`. File names which are not valid UTF-8, e.g., Latin-1 names, are
+    named with each invalid byte escaped as a private-use character from U+EF80 to U+EFFF.
+
+    With `io_threads`, the batch reads of the loader run on a dedicated pool of that many
+    threads instead of the pool of `set_num_threads` or the global rayon pool.
+
+    The genomes in `preload` are decompressed into shared memory at load time, see
+    `FastarLoader.preload`, with at most `preload_budget` bytes. Loading waits for them, even
+    with `background=True`.
+
+    With `block_cache` in bytes, decompressed BGZF blocks are cached in shared memory of at most
+    that size, evicting the least recently read blocks. Workers which unpickle the loader, e.g., of
+    a DataLoader, attach to the same cache, so each block is decompressed once for all of them
+    instead of once per worker. See `FastarLoader.block_cache_stats`.
+
+    With `readahead`, once reads of a contig are sequential, i.e., each starts at or shortly
+    after the previous one as in whole-chromosome sweeps, the next `readahead` BGZF blocks are
+    decompressed in the background, into the block cache if enabled or else into memory of the
+    process, so that the following reads do not wait for them.
+    """
+
+    strict: bool = True
+    force_build: bool = False
+    no_cache: bool | None = None
+    min_contig_length: int = 0
+    num_workers: int | None = None
+    io_threads: int | None = None
+    show_progress: bool | None = None
+    on_progress: Callable[[int, int, str], None] | None = None
+    storage_method: str | None = None
+    names: list[str] | None = None
+    checksum: str = "xxh3"
+    verify_checksum: bool = True
+    repair: bool = True
+    num_shards: int | None = None
+    include: list[str] = field(default_factory=list)
+    exclude: list[str] = field(default_factory=list)
+    follow_symlinks: bool = True
+    lazy: bool = False
+    background: bool = False
+    # Only supported by FastarLoader
+    digests: bool = False
+    composition: bool = False
+    check_indices: bool = False
+    bundle: str | Path | None = None
+    ambiguity: str = "keep"
+    ambiguity_seed: int = 0
+    alphabet: str | None = None
+    duplicates: str = "ignore"
+    preload: list[str] = field(default_factory=list)
+    preload_budget: int | None = None
+    block_cache: int | None = None
+    readahead: int | None = None
+
+    def __post_init__(self) -> None:
+        _rust.check_load_options(self)
+
+
+def _roots(path: str | Path | Mapping[str, str | Path]) -> list[tuple[str, str]]:
+    """Normalize a single root or a mapping from name prefix to root to (prefix, root) pairs."""
+    if isinstance(path, (str, Path)):
+        return [("", str(path))]
+    return [(prefix, str(root)) for prefix, root in path.items()]
+
+
+_SUMMARY_COLUMNS = ("name", "num_contigs", "total_length", "file_size")
+_STATS_KEYS = (
+    "num_names",
+    "num_contigs",
+    "total_length",
+    "archive_size",
+    "storage_method",
+    "cache_paths",
+)
+
+
+def _write_chrom_sizes(
+    contigs: Callable[[str], list[tuple[str, int]]], directory: str | Path, names: Iterable[str]
+) -> list[Path]:
+    """Write the contigs of each name to a UCSC chrom.sizes file `{name}.chrom.sizes` below
+    `directory`, creating directories for names with a root prefix, and return the paths."""
+    paths = []
+    for name in names:
+        path = Path(directory) / f"{name}.chrom.sizes"
+        path.parent.mkdir(parents=True, exist_ok=True)
+        with open(path, "w") as f:
+            f.writelines(f"{contig}\t{length}\n" for contig, length in contigs(name))
+        paths.append(path)
+    return paths
+
+
+def _write_summary(
+    summary: list[dict[str, str | int | None]], path: str | Path, format: str | None
+) -> None:
+    """Write a summary as a TSV file with a header, or as a JSON list of objects. By default,
+    the format is JSON for paths ending in `.json` and TSV otherwise."""
+    if format is None:
+        format = "json" if Path(path).suffix == ".json" else "tsv"
+    if format == "json":
+        with open(path, "w") as f:
+            json.dump(summary, f, indent=2)
+    elif format == "tsv":
+        with open(path, "w") as f:
+            f.write("\t".join(_SUMMARY_COLUMNS) + "\n")
+            for row in summary:
+                values = (
+                    "" if row[column] is None else str(row[column]) for column in _SUMMARY_COLUMNS
+                )
+                f.write("\t".join(values) + "\n")
     else:
-        fai_path = str(fai_path)
-    return _rust.read_sequence(fasta_path, gzi_path, fai_path, contig, start, length)
+        raise ValueError(f"Unknown summary format {format!r}, expected 'tsv' or 'json'")
 
 
 class FastarLoader:
     def __init__(
         self,
-        path: str | Path,
+        path: str | Path | Mapping[str, str | Path],
         strict: bool = True,
         force_build: bool = False,
         no_cache: bool | None = None,
         min_contig_length: int = 0,
         num_workers: int | None = None,
+        io_threads: int | None = None,
         show_progress: bool | None = None,
+        on_progress: Callable[[int, int, str], None] | None = None,
         storage_method: str | None = None,
         names: list[str] | None = None,
+        validate_handle: bool = False,
+        checksum: str = "xxh3",
+        verify_checksum: bool = True,
+        repair: bool = True,
+        num_shards: int | None = None,
+        include: list[str] | None = None,
+        exclude: list[str] | None = None,
+        follow_symlinks: bool = True,
+        lazy: bool = False,
+        background: bool = False,
+        digests: bool = False,
+        composition: bool = False,
+        check_indices: bool = False,
+        bundle: str | Path | None = None,
+        ambiguity: str = "keep",
+        ambiguity_seed: int = 0,
+        alphabet: str | None = None,
+        duplicates: str = "ignore",
+        preload: Iterable[str] | None = None,
+        preload_budget: int | None = None,
+        block_cache: int | None = None,
+        readahead: int | None = None,
     ):
-        if names is None:
-            if no_cache is None:
-                no_cache = False
-            if storage_method is None:
-                storage_method = "mmap"
-            if show_progress is None:
-                show_progress = True
-        else:
-            if no_cache is None:
-                no_cache = True
-            if storage_method is None:
-                storage_method = "memory"
-            if show_progress is None:
-                show_progress = False
-        self._path = str(path)
-        self._index_map = _rust.FastaMap.load(
-            self._path,
-            strict,
-            force_build,
-            no_cache,
-            min_contig_length,
-            num_workers,
-            show_progress,
-            storage_method,
-            names,
+        options = LoadOptions(
+            strict=strict,
+            force_build=force_build,
+            no_cache=no_cache,
+            min_contig_length=min_contig_length,
+            num_workers=num_workers,
+            io_threads=io_threads,
+            show_progress=show_progress,
+            on_progress=on_progress,
+            storage_method=storage_method,
+            names=names,
+            checksum=checksum,
+            verify_checksum=verify_checksum,
+            repair=repair,
+            num_shards=num_shards,
+            include=include or [],
+            exclude=exclude or [],
+            follow_symlinks=follow_symlinks,
+            lazy=lazy,
+            background=background,
+            digests=digests,
+            composition=composition,
+            check_indices=check_indices,
+            bundle=bundle,
+            ambiguity=ambiguity,
+            ambiguity_seed=ambiguity_seed,
+            alphabet=alphabet,
+            duplicates=duplicates,
+            preload=list(preload or []),
+            preload_budget=preload_budget,
+            block_cache=block_cache,
+            readahead=readahead,
         )
+        self._load(path, options, validate_handle)
+
+    @classmethod
+    def from_options(
+        cls,
+        path: str | Path | Mapping[str, str | Path],
+        options: LoadOptions,
+        validate_handle: bool = False,
+    ) -> "FastarLoader":
+        """Load the index of `path` as configured by `options`, which may be shared by loaders."""
+        loader = cls.__new__(cls)
+        loader._load(path, options, validate_handle)
+        return loader
+
+    def _load(
+        self,
+        path: str | Path | Mapping[str, str | Path],
+        options: LoadOptions,
+        validate_handle: bool,
+    ) -> None:
+        self._roots = _roots(path)
+        # Validate the archive with bytecheck when attaching to it in another process
+        self._validate_handle = validate_handle
+        self._index_map = _rust.FastaMap.load(self._roots, options)
+
+    def ready(self) -> bool:
+        """Whether the index is built when loading with `background=True`."""
+        return self._index_map.ready()
+
+    def wait(self) -> None:
+        """Block until the index is built, raising any error of the build."""
+        self._index_map.wait()
+
+    @property
+    def skipped(self) -> list[tuple[str, str]]:
+        """Names skipped with `strict=False` while building, as (name, reason) pairs."""
+        return self._index_map.skipped
 
     @property
     def names(self) -> list[str]:
         return self._index_map.names
 
+    def groups(self) -> list[str]:
+        """Directories below the root which contain names at any depth, with a trailing slash
+        and sorted, e.g., `clade/` and `clade/genus/` for the name `clade/genus/genome`."""
+        return self._index_map.groups()
+
+    def names_in_group(self, group: str) -> list[str]:
+        """Names in the directory `group` or below it, given with or without trailing slash."""
+        return self._index_map.names_in_group(group)
+
+    def group_weights(self, weights: Mapping[str, float]) -> dict[str, float]:
+        """Weights of names for `WindowSampler` which sample each group with its weight, split
+        equally between its names, e.g., `{"bacteria": 1, "archaea": 1}` to sample both
+        clades equally regardless of their number of genomes. Names outside of the groups are
+        not sampled. Groups which contain each other or no names raise a `ValueError`."""
+        return self._index_map.group_weights(dict(weights))
+
+    @property
+    def storage_method(self) -> str:
+        """Storage method in use, e.g., the one chosen by `storage_method="auto"`."""
+        return self._index_map.storage_method
+
+    @property
+    def ambiguity(self) -> tuple[str, int]:
+        """Policy for bases other than `ACGT` in reads and its seed, see `LoadOptions`."""
+        return self._index_map.ambiguity
+
+    @property
+    def alphabet(self) -> str | None:
+        """Alphabet of strict reads, see `LoadOptions`."""
+        return self._index_map.alphabet
+
     def contigs(self, name: str) -> list[tuple[str, int]]:
         return [
             (contig.decode("utf-8"), length) for contig, length in self._index_map.contigs(name)
         ]
 
-    def read_sequence(self, name: str, contig: str, start: int, length: int) -> np.ndarray:
-        return self._index_map.read_sequence(name, contig.encode(), start, length)
+    def contigs_array(self, name: str) -> tuple[np.ndarray, np.ndarray]:
+        """Contig names as an object array of bytes and lengths as an uint64 array.
+
+        Faster than `contigs` for entries with many contigs, as no tuple is created per contig.
+        """
+        return self._index_map.contigs_array(name)
+
+    def contig_dict(self, name: str) -> dict[str, int]:
+        """Mapping from contig name to length."""
+        return {
+            contig.decode("utf-8"): length for contig, length in self._index_map.contigs(name)
+        }
+
+    def metadata(self, name: str) -> dict[str, str]:
+        """Fields of the metadata sidecar `XXX.meta.tsv` of `name`, which is read at build
+        time. Empty if there is no sidecar."""
+        return dict(self._index_map.metadata(name))
+
+    def filter_by_metadata(self, key: str, values: str | Iterable[str]) -> list[str]:
+        """Names whose metadata field `key` is `values` or one of `values`, e.g., a clade."""
+        if isinstance(values, str):
+            values = [values]
+        return self._index_map.filter_by_metadata(key, list(values))
+
+    def digest(self, name: str, contig: str, algorithm: str = "sha512t24u") -> str:
+        """Digest of the upper-cased sequence of `contig`, either the GA4GH identifier
+        (`"sha512t24u"`, e.g., `SQ.aKF4...`) or the MD5 digest (`"md5"`) as used by CRAM.
+        Requires loading with `digests=True`."""
+        md5, sq = self._index_map.digest(name, contig.encode())
+        if algorithm == "md5":
+            return md5
+        if algorithm == "sha512t24u":
+            return sq
+        raise ValueError(f"Unsupported digest algorithm: {algorithm}")
+
+    def find_digest(self, digest: str) -> tuple[str, str] | None:
+        """(name, contig) of the sequence with an MD5 or GA4GH `digest`, or `None`."""
+        found = self._index_map.find_digest(digest)
+        if found is None:
+            return None
+        name, contig = found
+        return name, bytes(contig).decode("utf-8")
+
+    def duplicate_contigs(self) -> list[list[tuple[str, str]]]:
+        """Groups of (name, contig) with identical sequences by their digest, each sorted by
+        name, e.g., to report the redundancy of a pangenome collection. Empty contigs are not
+        counted. Requires loading with `digests=True`."""
+        return [
+            [(name, bytes(contig).decode("utf-8")) for name, contig in group]
+            for group in self._index_map.duplicate_contigs()
+        ]
+
+    def read_by_digest(self, digest: str, start: int, length: int) -> np.ndarray:
+        """Read a sequence by its MD5 or GA4GH digest, with or without the `ga4gh:` prefix.
+        Requires loading with `digests=True`."""
+        return self._index_map.read_by_digest(digest, start, length)
+
+    def serve_refget(self, address: str = "127.0.0.1:0", num_threads: int = 4):
+        """Serve the sequences over the GA4GH refget protocol from an embedded HTTP server,
+        e.g., as a node-local daemon. Requires loading with `digests=True` and a build with the
+        `refget` feature. Returns the server, whose `address` is the bound `host:port`; it runs
+        until `stop()` is called or it is garbage collected."""
+        if not hasattr(_rust, "RefgetServer"):
+            raise RuntimeError("fastar_loader was built without the refget feature")
+        return _rust.RefgetServer(self._index_map, address, num_threads)
+
+    def contig_length(self, name: str, contig: str) -> int:
+        length = self._index_map.contig_length(name, contig.encode())
+        if length is None:
+            raise ContigNotFoundError(f"Contig not found: {contig}")
+        return length
+
+    def has_contig(self, name: str, contig: str) -> bool:
+        return self._index_map.contig_length(name, contig.encode()) is not None
+
+    @overload
+    def read_sequence(
+        self,
+        name: str,
+        contig: str,
+        start: int | None = None,
+        length: int | None = None,
+        one_based: bool | None = None,
+        end_inclusive: bool | None = None,
+        timeout: float | None = None,
+        as_memoryview: Literal[False] = False,
+    ) -> np.ndarray: ...
+
+    @overload
+    def read_sequence(
+        self,
+        name: str,
+        contig: str,
+        start: int | None = None,
+        length: int | None = None,
+        one_based: bool | None = None,
+        end_inclusive: bool | None = None,
+        timeout: float | None = None,
+        *,
+        as_memoryview: Literal[True],
+    ) -> memoryview: ...
+
+    def read_sequence(
+        self,
+        name: str,
+        contig: str,
+        start: int | None = None,
+        length: int | None = None,
+        one_based: bool | None = None,
+        end_inclusive: bool | None = None,
+        timeout: float | None = None,
+        as_memoryview: bool = False,
+    ) -> np.ndarray | memoryview:
+        """Read `length` bases starting at `start` of `contig`, which is 0-based unless
+        `one_based` is set.
+
+        Without `start` and `length`, `contig` is a samtools-style region string instead, e.g.,
+        `"chr1:1,001-2,000"`, which is 1-based and inclusive by default, and 0-based and
+        half-open as in BED files with `one_based=False`. `end_inclusive` overrides whether the
+        end is included. Regions without an end extend to the end of the contig, e.g., `"chr1"`.
+        The defaults can be changed with `set_coordinates`.
+
+        With `timeout` in seconds, a read which does not finish in time, e.g., on a hung
+        network filesystem, raises `TimeoutError` instead of blocking the caller.
+
+        With `as_memoryview`, the bases are returned as a read-only `memoryview` of bytes
+        instead of a numpy array, for consumers which do not depend on numpy. It supports the
+        buffer protocol, e.g., `bytes(view)`, without copying the bases.
+        """
+        contig, start, length = _resolve_region(
+            self._index_map, name, contig, start, length, one_based, end_inclusive
+        )
+        return self._index_map.read_sequence(
+            name, contig.encode(), start, length, timeout, as_memoryview
+        )
+
+    def stream(
+        self,
+        queries_or_sampler: "Iterable[tuple[str, str, int, int]] | WindowSampler",
+        prefetch: int = 4,
+        batch_size: int = 32,
+        num_batches: int | None = None,
+    ) -> Iterator[np.ndarray]:
+        """Iterate over reads which are decoded ahead on a background thread, to overlap them
+        with the work of the caller like a DataLoader, but without Python workers.
+
+        `queries_or_sampler` is either an iterable of (name, contig, 0-based start, length)
+        queries, whose sequences are returned in order, or a `WindowSampler` of this loader,
+        whose batches of `batch_size` windows are returned as by `sample_sequences`, endlessly
+        or `num_batches` times. Up to `prefetch` items are read ahead, queries in parallel.
+        Errors of the reads are raised by the iteration, which ends the stream.
+        """
+        return _stream(self, queries_or_sampler, prefetch, batch_size, num_batches)
+
+    def read_sequence_all(
+        self,
+        contig: str,
+        start: int,
+        length: int,
+        names: Iterable[str] | None = None,
+        pad: str | None = None,
+        one_based: bool | None = None,
+        cancel: CancellationToken | None = None,
+    ) -> tuple[list[str], list[np.ndarray] | np.ndarray]:
+        """Read the same region from several genomes in parallel, e.g., for comparative
+        genomics, as the names and their sequences.
+
+        Without `names`, all genomes which have `contig` are read, and genomes without it are
+        skipped. The region is cut at the end of each contig, so sequences may be shorter than
+        `length` or empty. With a `pad` character such as `"N"`, they are padded to `length`
+        and returned as a single (names, length) array instead of a list. Cancelling `cancel`
+        from another thread stops the reads with an error.
+        """
+        zero_based_start = _check_region(start, length, one_based)
+        pairs = self._index_map.read_sequence_all(
+            contig.encode(),
+            zero_based_start,
+            length,
+            None if names is None else list(names),
+            cancel,
+        )
+        result_names = [name for name, _ in pairs]
+        sequences = [sequence for _, sequence in pairs]
+        if pad is None:
+            return result_names, sequences
+        return result_names, collate(sequences, pad, length).values
+
+    def read_batch(
+        self,
+        queries: Iterable[tuple[str, str, int, int]],
+        pad: int | str = "N",
+        length: int | None = None,
+        mask: bool = False,
+        cancel: CancellationToken | None = None,
+    ) -> PaddedBatch:
+        """Read (name, contig, 0-based start, length) queries of unequal lengths in parallel and
+        pad them into a single array in Rust, see `collate`. Cancelling `cancel` from another
+        thread stops the reads with an error.
+        """
+        values, lengths, value_mask = self._index_map.read_batch(
+            [(name, contig.encode(), start, n) for name, contig, start, n in queries],
+            _pad_byte(pad),
+            length,
+            mask,
+            cancel,
+        )
+        return PaddedBatch(values, lengths, value_mask)
+
+    def gaps(
+        self, name: str, contig: str | None = None, min_length: int = 1
+    ) -> np.ndarray | dict[str, np.ndarray]:
+        """Runs of at least `min_length` `N` bases as an uint64 array with one (start, end) row
+        per run. Without `contig`, all contigs of `name` are scanned in parallel and a mapping
+        from contig to runs is returned. The sequences are scanned on every call.
+        """
+        if contig is not None:
+            return self._index_map.gaps(name, contig.encode(), min_length)
+        return {
+            contig.decode("utf-8"): gaps
+            for contig, gaps in self._index_map.genome_gaps(name, min_length)
+        }
+
+    def verify(
+        self,
+        deep: bool = False,
+        names: Iterable[str] | None = None,
+        num_blocks: int = 16,
+        seed: int | None = None,
+    ) -> dict[str, list[str]]:
+        """Check that the FASTA files match their indices, e.g., after a truncated download or
+        copy: the end-of-file marker must be present and the file must contain every byte the
+        `.fai` index refers to. With `deep`, `num_blocks` random blocks of each file are also
+        decompressed, which detects corrupted data at the cost of reading it.
+
+        Returns a mapping from name to a description of its problems, which is empty if all
+        files of `names`, by default all names, are intact.
+        """
+        names = list(names) if names is not None else None
+        return dict(self._index_map.verify(names, deep, num_blocks, seed))
+
+    def composition(
+        self,
+        name: str,
+        contig: str,
+        start: int | None = None,
+        length: int | None = None,
+        window: int | None = None,
+        one_based: bool | None = None,
+    ) -> tuple[np.ndarray, np.ndarray]:
+        """GC content and base counts of a region in windows of `window` bases, by default a
+        single window, e.g., for GC-matched sampling. The region is given as in
+        `read_sequence`, and the last window may be shorter.
+
+        Returns the GC content as a float array with one value per window, which is NaN for
+        windows without `ACGT` bases, and the counts of `A`, `C`, `G`, `T` and other bases as an
+        uint64 array with one row per window. Case is ignored.
+        """
+        contig, start, length = _resolve_region(
+            self._index_map, name, contig, start, length, one_based, None
+        )
+        if window is None:
+            window = max(length, 1)
+        counts = self._index_map.composition(name, contig.encode(), start, length, window)
+        return _gc_content(counts), counts
+
+    def genome_composition(self, name: str) -> dict[str, np.ndarray]:
+        """Counts of `A`, `C`, `G`, `T` and other bases of every contig of `name` as a mapping
+        from contig to counts, which are computed once when building the cache. Requires loading
+        with `composition=True`; sum the counts for whole-genome statistics, and see
+        `composition` for GC content.
+        """
+        return {
+            contig.decode("utf-8"): counts
+            for contig, counts in self._index_map.genome_composition(name)
+        }
+
+    def count_kmers(
+        self,
+        name: str,
+        k: int,
+        contig: str | None = None,
+        start: int | None = None,
+        length: int | None = None,
+        one_based: bool | None = None,
+    ) -> np.ndarray:
+        """Counts of all `4**k` k-mers as an uint64 array, in lexicographic order of the k-mers
+        over `ACGT`, e.g., `AAC` at index 1 for `k=3`. Case is ignored, and k-mers with other
+        bases such as `N` are skipped. The sequence is streamed in Rust without reading it into
+        memory, and `k` may be up to 12.
+
+        Counts cover the region `start` and `length` of `contig`, the whole `contig`, which may
+        also be a region string as in `read_sequence`, or without `contig`, all contigs of
+        `name` scanned in parallel.
+        """
+        if contig is None:
+            if start is not None or length is not None:
+                raise ValueError("start and length require a contig")
+            return self._index_map.genome_kmers(name, k)
+        contig, start, length = _resolve_region(
+            self._index_map, name, contig, start, length, one_based, None
+        )
+        return self._index_map.count_kmers(name, contig.encode(), start, length, k)
+
+    def iter_windows(
+        self,
+        window_length: int,
+        stride: int | None = None,
+        name: str | None = None,
+        contig: str | None = None,
+    ) -> Iterator[tuple[str, str, int, np.ndarray]]:
+        """Walk over windows of `window_length` every `stride` values, as (name, contig, start,
+        values). Covers `contig` of `name`, all contigs of `name`, or the whole collection in the
+        order of `global_id`. Each contig is decompressed once in a single pass instead of
+        seeking per window. Only full windows are returned.
+        """
+        windows = _rust.WindowIterator(
+            self._index_map,
+            window_length,
+            stride,
+            name,
+            contig.encode() if contig is not None else None,
+        )
+        for window_name, window_contig, start, values in windows:
+            yield window_name, window_contig.decode("utf-8"), start, values
+
+    def global_length(self) -> int:
+        """Total length of all contigs, laid out end to end in the order of `global_id`."""
+        return self._index_map.global_length()
+
+    def global_id(self, name: str, contig: str) -> int:
+        """Stable id of a contig: contigs are numbered in the order of the sorted names and the
+        file order of their contigs."""
+        return self._index_map.global_id(name, contig.encode())
+
+    def global_contig(self, id: int) -> tuple[str, str]:
+        result = self._index_map.global_contig(id)
+        if result is None:
+            raise IndexError(f"Global id out of range: {id}")
+        name, contig = result
+        return name, contig.decode("utf-8")
+
+    def global_start(self, name: str, contig: str) -> int:
+        return self._index_map.global_start(name, contig.encode())
+
+    def locate(self, global_pos: int) -> tuple[str, str, int]:
+        """Name, contig and offset within the contig of a global position."""
+        name, contig, offset = self._index_map.locate(global_pos)
+        return name, contig.decode("utf-8"), offset
+
+    def read_at(self, global_pos: int, length: int) -> np.ndarray:
+        """Read at a global position. The region must not cross the end of a contig."""
+        return self._index_map.read_at(global_pos, length)
+
+    def union(
+        self,
+        other: "FastarLoader",
+        on_conflict: str = "error",
+        prefixes: tuple[str, str] | None = None,
+        storage_method: str = "memory",
+    ) -> "FastarLoader":
+        """Combine with another loader without re-indexing.
+
+        Duplicate names raise an error (`on_conflict="error"`), keep the entry of this loader
+        (`"first"`), or are avoided by prefixing the names of both loaders (`"prefix"`).
+        """
+        loader = FastarLoader.__new__(FastarLoader)
+        loader.__dict__.update(self.__dict__)
+        loader._index_map = self._index_map.union(
+            other._index_map, on_conflict, prefixes, storage_method
+        )
+        return loader
+
+    def with_added(self, names: Iterable[str]) -> "FastarLoader":
+        """A loader with the entries `names` indexed, e.g., files added to the root since it was
+        loaded, without re-indexing the other entries. Names which are loaded already are
+        re-indexed. The entries are indexed with the options the root was built with, such as
+        `min_contig_length`.
+
+        A root loaded from a cache file has the file replaced, so that the next load of the root
+        finds it up to date.
+        """
+        loader = FastarLoader.__new__(FastarLoader)
+        loader.__dict__.update(self.__dict__)
+        loader._index_map = self._index_map.with_added(list(names))
+        return loader
+
+    def with_removed(self, names: Iterable[str]) -> "FastarLoader":
+        """A loader without the entries `names`, without re-indexing the other entries. A root
+        loaded from a cache file has the file replaced, see `with_added`."""
+        loader = FastarLoader.__new__(FastarLoader)
+        loader.__dict__.update(self.__dict__)
+        loader._index_map = self._index_map.with_removed(list(names))
+        return loader
+
+    def export_bundle(self, path: str | Path) -> None:
+        """Write the index as a bundle, from which a loader with `bundle=path` on another
+        machine with the same files is created without building the index."""
+        self._index_map.export_bundle(str(path))
+
+    def preload(self, names: Iterable[str], budget: int | None = None) -> None:
+        """Decompress the genomes `names` into shared memory, so that their reads are served by
+        copying the bases instead of decompressing BGZF blocks, e.g., for a dozen hot genomes.
+
+        With `budget` in bytes, the least recently read genomes are evicted when the preloaded
+        genomes exceed it, and a genome larger than the budget raises a `ValueError`. Workers
+        which unpickle the loader attach to the same shared memory.
+        """
+        self._index_map.preload(list(names), budget)
+
+    def preloaded(self) -> list[tuple[str, int]]:
+        """Names and sizes in bytes of the preloaded genomes, most recently read first."""
+        return self._index_map.preloaded()
+
+    def block_cache_stats(self) -> dict[str, int] | None:
+        """The size in bytes of the block cache of `LoadOptions.block_cache` and its hits and
+        misses, counted over all processes sharing it, or `None` without a block cache."""
+        stats = self._index_map.block_cache_stats()
+        if stats is None:
+            return None
+        return dict(zip(("size", "hits", "misses"), stats))
+
+    def write_packed(self, path: str | Path, names: Iterable[str]) -> None:
+        """Re-encode the genomes `names` to two bits per base and write them to `path`, to be
+        opened with `PackedLoader`. For hot genomes, e.g., hg38, random reads then decode the
+        bases directly instead of decompressing BGZF blocks, at the cost of about a quarter of
+        the uncompressed size on disk."""
+        self._index_map.write_packed(list(names), str(path))
+
+    def stats(self) -> dict[str, object]:
+        """Statistics of the loaded index for monitoring: the number of names, contigs and
+        bases (`total_length`), the size of the index in bytes (`archive_size`), the storage
+        method and the paths of the cache files. Counts which would load all shards or index
+        all entries of a lazy loader are `None`, so the call is always cheap."""
+        return dict(zip(_STATS_KEYS, self._index_map.stats()))
+
+    def metrics(self) -> dict[str, int]:
+        """Counters of the read paths, e.g., to tell whether data loading or the model is the
+        bottleneck: the number of `queries` served, the `bytes_decoded` and
+        `blocks_decompressed` for them, `cache_hits` and `cache_misses` of the block cache of
+        object store roots, reads retried by `set_retry_policy` as `retries`, and the number
+        of currently `open_files`. The counters are shared by all loaders of the process and
+        count from its start or the last `reset_metrics`."""
+        return dict(_rust.metrics())
+
+    def reset_metrics(self) -> None:
+        """Reset the counters of `metrics` to zero, except for `open_files`."""
+        _rust.reset_metrics()
+
+    def content_hash(self) -> str:
+        """Hash of the contents of the index as 32 hex digits, e.g., to record in experiment
+        metadata which index a run used. It only depends on the names and the contents of their
+        index files, as well as the options which change them such as `min_contig_length` or
+        `digests`, and not on the storage, sharding, path or modification times of the files.
+        Loads all shards and indexes all entries of a lazy loader."""
+        return self._index_map.content_hash()
+
+    def summary(self) -> list[dict[str, str | int | None]]:
+        """Number of contigs, total length and file size in bytes of every name, computed from
+        the index without reading the data files. The file size is `None` for missing files and
+        object store roots."""
+        return [dict(zip(_SUMMARY_COLUMNS, row)) for row in self._index_map.summary()]
+
+    def write_summary(self, path: str | Path, format: str | None = None) -> None:
+        """Write the `summary` as a TSV file with a header (`format="tsv"`) or as JSON
+        (`"json"`), by default chosen by the suffix of `path`."""
+        _write_summary(self.summary(), path, format)
+
+    def write_chrom_sizes(
+        self, directory: str | Path, names: Iterable[str] | None = None
+    ) -> list[Path]:
+        """Write the contigs of each of `names`, by default all names, to a UCSC chrom.sizes
+        file `{name}.chrom.sizes` below `directory`, e.g., for bedtools or deeptools. Returns
+        the paths of the files."""
+        return _write_chrom_sizes(self.contigs, directory, self.names if names is None else names)
+
+    def __len__(self) -> int:
+        return len(self._index_map)
+
+    def __contains__(self, name: object) -> bool:
+        return isinstance(name, str) and name in self._index_map
+
+    def __iter__(self) -> Iterator[str]:
+        return iter(self._index_map)
+
+    def __repr__(self) -> str:
+        return f"<FastarLoader {self._index_map!r}>"
+
+    @property
+    def handle(self) -> list[tuple[str, str, bytes]] | None:
+        """Handle to attach to the index from another process with `from_handle`, or `None` if
+        the storage is not shared."""
+        return self._index_map.handle
+
+    @classmethod
+    def from_handle(
+        cls,
+        handle: list[tuple[str, str, bytes]],
+        path: str | Path | Mapping[str, str | Path] | None = None,
+        validate_handle: bool = False,
+        ambiguity: str = "keep",
+        ambiguity_seed: int = 0,
+        alphabet: str | None = None,
+        io_threads: int | None = None,
+    ) -> "FastarLoader":
+        """Attach to the index of another loader, with `path` if its root was moved since.
+
+        A few index files below the root are compared with the index, so a wrong root raises an
+        error instead of returning wrong sequences. The ambiguity policy, alphabet and
+        `io_threads` are not part of the handle and have to be passed again.
+        """
+        moved = [] if path is None else _roots(path)
+        loader = cls.__new__(cls)
+        loader._index_map = _rust.FastaMap.from_handle(
+            handle, validate_handle, moved, ambiguity, ambiguity_seed, alphabet, io_threads
+        )
+        moved_paths = dict(moved)
+        loader._roots = [(prefix, moved_paths.get(prefix, root)) for prefix, root, _ in handle]
+        loader._validate_handle = validate_handle
+        return loader
 
     def __getstate__(self) -> dict[str, object]:
         d = self.__dict__.copy()
@@ -85,65 +1111,530 @@ class FastarLoader:
                 "Cannot serialize FastarLoader with non-shared storage (e.g., in-memory storage)!"
             )
         d["_index_map"] = handle
-        d["_root"] = self._index_map.root
+        d["_ambiguity"] = self._index_map.ambiguity
+        d["_alphabet"] = self._index_map.alphabet
+        d["_preloaded"] = self._index_map.preload_handle
+        d["_block_cache"] = self._index_map.block_cache_handle
+        d["_readahead"] = self._index_map.readahead
+        d["_io_threads"] = self._index_map.io_threads
         return d
 
     def __setstate__(self, state: dict[str, object]) -> None:
-        state["_index_map"] = _rust.FastaMap.from_handle(state["_index_map"], state["_root"])
+        ambiguity, seed = cast(tuple[str, int], state.pop("_ambiguity", ("keep", 0)))
+        state["_index_map"] = _rust.FastaMap.from_handle(
+            state["_index_map"],
+            state.get("_validate_handle", False),
+            [],
+            ambiguity,
+            seed,
+            state.pop("_alphabet", None),
+            state.pop("_io_threads", None),
+        )
+        # Attach to the genomes preloaded by the pickling process instead of decompressing them
+        preloaded = cast(
+            tuple[int | None, list[tuple[str, bytes]]], state.pop("_preloaded", (None, []))
+        )
+        state["_index_map"].attach_preloaded(*preloaded)
+        block_cache = state.pop("_block_cache", None)
+        if block_cache is not None:
+            state["_index_map"].attach_block_cache(block_cache)
+        readahead = state.pop("_readahead", None)
+        if readahead is not None:
+            state["_index_map"].enable_readahead(readahead)
         self.__dict__.update(state)
 
 
+@dataclass(frozen=True)
+class AlignmentReport:
+    """Mismatches between a track and a FASTA loader, see `TrackLoader.validate_against`."""
+
+    # Names of the FASTA loader missing from the track, and vice versa
+    missing_names: list[str]
+    extra_names: list[str]
+    # (name, contig) of shared names missing from the track, and vice versa
+    missing_contigs: list[tuple[str, str]]
+    extra_contigs: list[tuple[str, str]]
+    # (name, contig, expected bytes, track bytes) of shared contigs with another length
+    length_mismatches: list[tuple[str, str, int, int]]
+
+    @property
+    def ok(self) -> bool:
+        """Whether the track covers every contig of the FASTA loader with the right length.
+        Additional names and contigs of the track are allowed."""
+        return not (self.missing_names or self.missing_contigs or self.length_mismatches)
+
+
 class TrackLoader:
     def __init__(
         self,
-        path: str | Path,
+        path: str | Path | Mapping[str, str | Path],
         strict: bool = True,
         force_build: bool = False,
         no_cache: bool | None = None,
         min_contig_length: int = 0,
         num_workers: int | None = None,
+        io_threads: int | None = None,
         show_progress: bool | None = None,
+        on_progress: Callable[[int, int, str], None] | None = None,
         storage_method: str | None = None,
         names: list[str] | None = None,
+        validate_handle: bool = False,
+        checksum: str = "xxh3",
+        verify_checksum: bool = True,
+        repair: bool = True,
+        num_shards: int | None = None,
+        include: list[str] | None = None,
+        exclude: list[str] | None = None,
+        follow_symlinks: bool = True,
+        lazy: bool = False,
+        background: bool = False,
+        bundle: str | Path | None = None,
     ):
-        if names is None:
-            if no_cache is None:
-                no_cache = False
-            if storage_method is None:
-                storage_method = "mmap"
-            if show_progress is None:
-                show_progress = True
-        else:
-            if no_cache is None:
-                no_cache = True
-            if storage_method is None:
-                storage_method = "memory"
-            if show_progress is None:
-                show_progress = False
-        self._path = str(path)
-        self._index_map = _rust.TrackMap.load(
-            self._path,
-            strict,
-            force_build,
-            no_cache,
-            min_contig_length,
-            num_workers,
-            show_progress,
-            storage_method,
-            names,
+        options = LoadOptions(
+            strict=strict,
+            force_build=force_build,
+            no_cache=no_cache,
+            min_contig_length=min_contig_length,
+            num_workers=num_workers,
+            io_threads=io_threads,
+            show_progress=show_progress,
+            on_progress=on_progress,
+            storage_method=storage_method,
+            names=names,
+            checksum=checksum,
+            verify_checksum=verify_checksum,
+            repair=repair,
+            num_shards=num_shards,
+            include=include or [],
+            exclude=exclude or [],
+            follow_symlinks=follow_symlinks,
+            lazy=lazy,
+            background=background,
+            bundle=bundle,
         )
+        self._load(path, options, validate_handle)
+
+    @classmethod
+    def from_options(
+        cls,
+        path: str | Path | Mapping[str, str | Path],
+        options: LoadOptions,
+        validate_handle: bool = False,
+    ) -> "TrackLoader":
+        """Load the index of `path` as configured by `options`, which may be shared by loaders."""
+        loader = cls.__new__(cls)
+        loader._load(path, options, validate_handle)
+        return loader
+
+    def _load(
+        self,
+        path: str | Path | Mapping[str, str | Path],
+        options: LoadOptions,
+        validate_handle: bool,
+    ) -> None:
+        self._roots = _roots(path)
+        # Validate the archive with bytecheck when attaching to it in another process
+        self._validate_handle = validate_handle
+        self._index_map = _rust.TrackMap.load(self._roots, options)
+
+    def ready(self) -> bool:
+        """Whether the index is built when loading with `background=True`."""
+        return self._index_map.ready()
+
+    def wait(self) -> None:
+        """Block until the index is built, raising any error of the build."""
+        self._index_map.wait()
+
+    @property
+    def skipped(self) -> list[tuple[str, str]]:
+        """Names skipped with `strict=False` while building, as (name, reason) pairs."""
+        return self._index_map.skipped
 
     @property
     def names(self) -> list[str]:
         return self._index_map.names
 
+    def groups(self) -> list[str]:
+        """Directories below the root which contain names at any depth, with a trailing slash
+        and sorted, e.g., `clade/` and `clade/genus/` for the name `clade/genus/genome`."""
+        return self._index_map.groups()
+
+    def names_in_group(self, group: str) -> list[str]:
+        """Names in the directory `group` or below it, given with or without trailing slash."""
+        return self._index_map.names_in_group(group)
+
+    def group_weights(self, weights: Mapping[str, float]) -> dict[str, float]:
+        """Weights of names for `WindowSampler` which sample each group with its weight, split
+        equally between its names, e.g., `{"bacteria": 1, "archaea": 1}` to sample both
+        clades equally regardless of their number of genomes. Names outside of the groups are
+        not sampled. Groups which contain each other or no names raise a `ValueError`."""
+        return self._index_map.group_weights(dict(weights))
+
+    @property
+    def storage_method(self) -> str:
+        """Storage method in use, e.g., the one chosen by `storage_method="auto"`."""
+        return self._index_map.storage_method
+
     def contigs(self, name: str) -> list[tuple[str, int]]:
         return [
             (contig.decode("utf-8"), length) for contig, length in self._index_map.contigs(name)
         ]
 
-    def read_sequence(self, name: str, contig: str, start: int, length: int) -> np.ndarray:
-        return self._index_map.read_sequence(name, contig.encode(), start, length)
+    def contigs_array(self, name: str) -> tuple[np.ndarray, np.ndarray]:
+        """Contig names as an object array of bytes and lengths as an uint64 array.
+
+        Faster than `contigs` for entries with many contigs, as no tuple is created per contig.
+        """
+        return self._index_map.contigs_array(name)
+
+    def contig_dict(self, name: str) -> dict[str, int]:
+        """Mapping from contig name to length."""
+        return {
+            contig.decode("utf-8"): length for contig, length in self._index_map.contigs(name)
+        }
+
+    def contig_length(self, name: str, contig: str) -> int:
+        length = self._index_map.contig_length(name, contig.encode())
+        if length is None:
+            raise ContigNotFoundError(f"Contig not found: {contig}")
+        return length
+
+    def has_contig(self, name: str, contig: str) -> bool:
+        return self._index_map.contig_length(name, contig.encode()) is not None
+
+    @overload
+    def read_sequence(
+        self,
+        name: str,
+        contig: str,
+        start: int | None = None,
+        length: int | None = None,
+        step: int = 1,
+        value_size: int = 1,
+        reverse: bool = False,
+        channels: list[int] | None = None,
+        one_based: bool | None = None,
+        end_inclusive: bool | None = None,
+        timeout: float | None = None,
+        as_memoryview: Literal[False] = False,
+    ) -> np.ndarray: ...
+
+    @overload
+    def read_sequence(
+        self,
+        name: str,
+        contig: str,
+        start: int | None = None,
+        length: int | None = None,
+        step: int = 1,
+        value_size: int = 1,
+        reverse: bool = False,
+        channels: list[int] | None = None,
+        one_based: bool | None = None,
+        end_inclusive: bool | None = None,
+        timeout: float | None = None,
+        *,
+        as_memoryview: Literal[True],
+    ) -> memoryview: ...
+
+    def read_sequence(
+        self,
+        name: str,
+        contig: str,
+        start: int | None = None,
+        length: int | None = None,
+        step: int = 1,
+        value_size: int = 1,
+        reverse: bool = False,
+        channels: list[int] | None = None,
+        one_based: bool | None = None,
+        end_inclusive: bool | None = None,
+        timeout: float | None = None,
+        as_memoryview: bool = False,
+    ) -> np.ndarray | memoryview:
+        """Read `length` bytes starting at `start`. With `step`, only every `step`-th value of
+        `value_size` bytes is returned, e.g., `step=128, value_size=4` for a low-resolution view
+        of a float32 track. For bin maxima instead, see `read_aggregated`. Without `start` and
+        `length`, `contig` is a region string, with the conventions of
+        `FastarLoader.read_sequence`.
+
+        With `reverse`, the values are returned in reverse order to match a reverse-complemented
+        sequence. For tracks with several interleaved channels per position, `channels` gives
+        the permutation of the channels of each position, e.g., `[1, 0]` to swap the strands of
+        stranded coverage. `timeout` and `as_memoryview` are as in `FastarLoader.read_sequence`.
+        """
+        contig, start, length = _resolve_region(
+            self._index_map, name, contig, start, length, one_based, end_inclusive
+        )
+        return self._index_map.read_sequence(
+            name,
+            contig.encode(),
+            start,
+            length,
+            step,
+            value_size,
+            reverse,
+            list(channels) if channels is not None else None,
+            timeout,
+            as_memoryview,
+        )
+
+    def stream(
+        self,
+        queries_or_sampler: "Iterable[tuple[str, str, int, int]] | WindowSampler",
+        prefetch: int = 4,
+        batch_size: int = 32,
+        num_batches: int | None = None,
+    ) -> Iterator[np.ndarray]:
+        """Iterate over reads which are decoded ahead on a background thread, see
+        `FastarLoader.stream`. Queries return the bytes of the values as stored."""
+        return _stream(self, queries_or_sampler, prefetch, batch_size, num_batches)
+
+    def validate_against(
+        self, fasta: FastarLoader, dtype: npt.DTypeLike = np.float32
+    ) -> AlignmentReport:
+        """Cross-check names, contigs and contig lengths against a FASTA loader, expecting one
+        value of `dtype` per base."""
+        missing_names, extra_names, missing_contigs, extra_contigs, length_mismatches = (
+            self._index_map.validate_against(fasta._index_map, np.dtype(dtype).itemsize)
+        )
+        return AlignmentReport(
+            missing_names=missing_names,
+            extra_names=extra_names,
+            missing_contigs=[(name, contig.decode("utf-8")) for name, contig in missing_contigs],
+            extra_contigs=[(name, contig.decode("utf-8")) for name, contig in extra_contigs],
+            length_mismatches=[
+                (name, contig.decode("utf-8"), expected, actual)
+                for name, contig, expected, actual in length_mismatches
+            ],
+        )
+
+    def read_aggregated(
+        self,
+        name: str,
+        contig: str,
+        start: int,
+        length: int,
+        bin_size: int,
+        op: str = "mean",
+        dtype: npt.DTypeLike = np.float32,
+    ) -> np.ndarray:
+        """Read `length` values of `dtype` starting at `start` and reduce them in bins of
+        `bin_size` values with `op` ("mean", "max", "min" or "sum"), as a float32 array of
+        `length // bin_size` bins. `length` must be a multiple of `bin_size`, and NaN values are
+        ignored.
+        """
+        return self._index_map.read_aggregated(
+            name, contig.encode(), start, length, bin_size, op, np.dtype(dtype).name
+        )
+
+    def read_stacked(
+        self,
+        names: list[str],
+        contig: str,
+        start: int,
+        length: int,
+        dtype: npt.DTypeLike = np.float32,
+    ) -> np.ndarray:
+        """Read the same region of several tracks in parallel as a (tracks, length) array of
+        `dtype`. Unlike `read_sequence`, `start` and `length` count values, not bytes.
+        """
+        dtype = np.dtype(dtype)
+        stacked = self._index_map.read_stacked(
+            list(names), contig.encode(), start, length, dtype.itemsize
+        )
+        return stacked.view(dtype)
+
+    def write_rle(self, name: str, path: str | Path, dtype: npt.DTypeLike = np.float32) -> None:
+        """Encode the track `name` as runs of equal values of `dtype` and write it to `path`, to
+        be opened with `RleTrackLoader`. For run-heavy tracks such as mappability, the runs take
+        a fraction of the storage of the dense track."""
+        self._index_map.write_rle(name, str(path), np.dtype(dtype).itemsize)
+
+    def iter_windows(
+        self,
+        window_length: int,
+        stride: int | None = None,
+        name: str | None = None,
+        contig: str | None = None,
+    ) -> Iterator[tuple[str, str, int, np.ndarray]]:
+        """Walk over windows of `window_length` every `stride` values, as (name, contig, start,
+        values). Covers `contig` of `name`, all contigs of `name`, or the whole collection in the
+        order of `global_id`. Each contig is decompressed once in a single pass instead of
+        seeking per window. Only full windows are returned.
+        """
+        windows = _rust.WindowIterator(
+            self._index_map,
+            window_length,
+            stride,
+            name,
+            contig.encode() if contig is not None else None,
+        )
+        for window_name, window_contig, start, values in windows:
+            yield window_name, window_contig.decode("utf-8"), start, values
+
+    def global_length(self) -> int:
+        """Total length of all contigs, laid out end to end in the order of `global_id`."""
+        return self._index_map.global_length()
+
+    def global_id(self, name: str, contig: str) -> int:
+        """Stable id of a contig: contigs are numbered in the order of the sorted names and the
+        file order of their contigs."""
+        return self._index_map.global_id(name, contig.encode())
+
+    def global_contig(self, id: int) -> tuple[str, str]:
+        result = self._index_map.global_contig(id)
+        if result is None:
+            raise IndexError(f"Global id out of range: {id}")
+        name, contig = result
+        return name, contig.decode("utf-8")
+
+    def global_start(self, name: str, contig: str) -> int:
+        return self._index_map.global_start(name, contig.encode())
+
+    def locate(self, global_pos: int) -> tuple[str, str, int]:
+        """Name, contig and offset within the contig of a global position."""
+        name, contig, offset = self._index_map.locate(global_pos)
+        return name, contig.decode("utf-8"), offset
+
+    def read_at(self, global_pos: int, length: int) -> np.ndarray:
+        """Read at a global position. The region must not cross the end of a contig."""
+        return self._index_map.read_at(global_pos, length)
+
+    def union(
+        self,
+        other: "TrackLoader",
+        on_conflict: str = "error",
+        prefixes: tuple[str, str] | None = None,
+        storage_method: str = "memory",
+    ) -> "TrackLoader":
+        """Combine with another loader without re-indexing.
+
+        Duplicate names raise an error (`on_conflict="error"`), keep the entry of this loader
+        (`"first"`), or are avoided by prefixing the names of both loaders (`"prefix"`).
+        """
+        loader = TrackLoader.__new__(TrackLoader)
+        loader.__dict__.update(self.__dict__)
+        loader._index_map = self._index_map.union(
+            other._index_map, on_conflict, prefixes, storage_method
+        )
+        return loader
+
+    def with_added(self, names: Iterable[str]) -> "TrackLoader":
+        """A loader with the entries `names` indexed, e.g., files added to the root since it was
+        loaded, without re-indexing the other entries. Names which are loaded already are
+        re-indexed. The entries are indexed with the options the root was built with, such as
+        `min_contig_length`.
+
+        A root loaded from a cache file has the file replaced, so that the next load of the root
+        finds it up to date.
+        """
+        loader = TrackLoader.__new__(TrackLoader)
+        loader.__dict__.update(self.__dict__)
+        loader._index_map = self._index_map.with_added(list(names))
+        return loader
+
+    def with_removed(self, names: Iterable[str]) -> "TrackLoader":
+        """A loader without the entries `names`, without re-indexing the other entries. A root
+        loaded from a cache file has the file replaced, see `with_added`."""
+        loader = TrackLoader.__new__(TrackLoader)
+        loader.__dict__.update(self.__dict__)
+        loader._index_map = self._index_map.with_removed(list(names))
+        return loader
+
+    def export_bundle(self, path: str | Path) -> None:
+        """Write the index as a bundle, from which a loader with `bundle=path` on another
+        machine with the same files is created without building the index."""
+        self._index_map.export_bundle(str(path))
+
+    def stats(self) -> dict[str, object]:
+        """Statistics of the loaded index for monitoring: the number of names, contigs and
+        bases (`total_length`), the size of the index in bytes (`archive_size`), the storage
+        method and the paths of the cache files. Counts which would load all shards or index
+        all entries of a lazy loader are `None`, so the call is always cheap."""
+        return dict(zip(_STATS_KEYS, self._index_map.stats()))
+
+    def metrics(self) -> dict[str, int]:
+        """Counters of the read paths, e.g., to tell whether data loading or the model is the
+        bottleneck: the number of `queries` served, the `bytes_decoded` and
+        `blocks_decompressed` for them, `cache_hits` and `cache_misses` of the block cache of
+        object store roots, reads retried by `set_retry_policy` as `retries`, and the number
+        of currently `open_files`. The counters are shared by all loaders of the process and
+        count from its start or the last `reset_metrics`."""
+        return dict(_rust.metrics())
+
+    def reset_metrics(self) -> None:
+        """Reset the counters of `metrics` to zero, except for `open_files`."""
+        _rust.reset_metrics()
+
+    def content_hash(self) -> str:
+        """Hash of the contents of the index as 32 hex digits, e.g., to record in experiment
+        metadata which index a run used. It only depends on the names and the contents of their
+        index files, as well as the options which change them such as `min_contig_length` or
+        `digests`, and not on the storage, sharding, path or modification times of the files.
+        Loads all shards and indexes all entries of a lazy loader."""
+        return self._index_map.content_hash()
+
+    def summary(self) -> list[dict[str, str | int | None]]:
+        """Number of contigs, total length and file size in bytes of every name, computed from
+        the index without reading the data files. The file size is `None` for missing files and
+        object store roots."""
+        return [dict(zip(_SUMMARY_COLUMNS, row)) for row in self._index_map.summary()]
+
+    def write_summary(self, path: str | Path, format: str | None = None) -> None:
+        """Write the `summary` as a TSV file with a header (`format="tsv"`) or as JSON
+        (`"json"`), by default chosen by the suffix of `path`."""
+        _write_summary(self.summary(), path, format)
+
+    def write_chrom_sizes(
+        self, directory: str | Path, names: Iterable[str] | None = None
+    ) -> list[Path]:
+        """Write the contigs of each of `names`, by default all names, to a UCSC chrom.sizes
+        file `{name}.chrom.sizes` below `directory`, e.g., for bedtools or deeptools. Returns
+        the paths of the files."""
+        return _write_chrom_sizes(self.contigs, directory, self.names if names is None else names)
+
+    def __len__(self) -> int:
+        return len(self._index_map)
+
+    def __contains__(self, name: object) -> bool:
+        return isinstance(name, str) and name in self._index_map
+
+    def __iter__(self) -> Iterator[str]:
+        return iter(self._index_map)
+
+    def __repr__(self) -> str:
+        return f"<TrackLoader {self._index_map!r}>"
+
+    @property
+    def handle(self) -> list[tuple[str, str, bytes]] | None:
+        """Handle to attach to the index from another process with `from_handle`, or `None` if
+        the storage is not shared."""
+        return self._index_map.handle
+
+    @classmethod
+    def from_handle(
+        cls,
+        handle: list[tuple[str, str, bytes]],
+        path: str | Path | Mapping[str, str | Path] | None = None,
+        validate_handle: bool = False,
+        io_threads: int | None = None,
+    ) -> "TrackLoader":
+        """Attach to the index of another loader, with `path` if its root was moved since.
+
+        A few index files below the root are compared with the index, so a wrong root raises an
+        error instead of returning wrong sequences. `io_threads` is not part of the handle and
+        has to be passed again.
+        """
+        moved = [] if path is None else _roots(path)
+        loader = cls.__new__(cls)
+        loader._index_map = _rust.TrackMap.from_handle(
+            handle, validate_handle, moved, io_threads
+        )
+        moved_paths = dict(moved)
+        loader._roots = [(prefix, moved_paths.get(prefix, root)) for prefix, root, _ in handle]
+        loader._validate_handle = validate_handle
+        return loader
 
     def __getstate__(self) -> dict[str, object]:
         d = self.__dict__.copy()
@@ -153,9 +1644,455 @@ class TrackLoader:
                 "Cannot serialize TrackLoader with non-shared storage (e.g., in-memory storage)!"
             )
         d["_index_map"] = handle
-        d["_root"] = self._index_map.root
+        d["_io_threads"] = self._index_map.io_threads
         return d
 
     def __setstate__(self, state: dict[str, object]) -> None:
-        state["_index_map"] = _rust.TrackMap.from_handle(state["_index_map"], state["_root"])
+        state["_index_map"] = _rust.TrackMap.from_handle(
+            state["_index_map"],
+            state.get("_validate_handle", False),
+            [],
+            state.pop("_io_threads", None),
+        )
         self.__dict__.update(state)
+
+
+def benchmark(
+    loader: FastarLoader | TrackLoader,
+    n_queries: int = 1000,
+    length: int = 1000,
+    seed: int = 0,
+    threads: int = 1,
+) -> dict[str, float]:
+    """Measure random-read throughput and latency of a loader entirely in Rust.
+
+    Benchmarks written in Python tend to measure the overhead of the interpreter and numpy
+    rather than the loader. Here, `n_queries` windows of `length` values are sampled uniformly
+    over the collection, seeded by `seed`, and then read with `threads` threads, of which only
+    the reads are timed. Reads of a `FastarLoader` apply its ambiguity policy and alphabet.
+
+    Returns:
+        The setup (`n_queries`, `length`, `threads`), the wall time in seconds (`elapsed`),
+        the throughput (`queries_per_second`, `bytes_per_second`) and the latency of single
+        reads in seconds (`latency_mean`, `latency_p50`, `latency_p90`, `latency_p99`,
+        `latency_max`).
+    """
+    if n_queries <= 0 or length <= 0 or threads <= 0:
+        raise ValueError("n_queries, length and threads must be positive")
+    return dict(_rust.benchmark(loader._index_map, n_queries, length, seed, threads))
+
+
+@dataclass(frozen=True, kw_only=True)
+class Mutations:
+    """Random mutations of sampled windows to augment training data, see `WindowSampler`.
+
+    Each base is substituted by another base with probability `substitution_rate`, and an
+    insertion or deletion of 1 to `max_indel_length` bases starts at each base with probability
+    `indel_rate`. Windows shortened by deletions are padded with `N`, and windows lengthened by
+    insertions are truncated, so windows keep their length. Only `ACGT` are substituted, keeping
+    their case. Invalid rates raise a `ValueError`.
+    """
+
+    substitution_rate: float = 0.0
+    indel_rate: float = 0.0
+    max_indel_length: int = 1
+
+    def __post_init__(self) -> None:
+        _rust.check_mutations(self)
+
+
+class WindowSampler:
+    """Random windows of a fixed length from a loader.
+
+    By default, every window of the collection is equally likely, i.e., contigs are weighted by
+    their length. With `weights`, a name is first chosen by its weight and then a window within
+    it uniformly; names without weight are never sampled. For sampling stratified by directory,
+    e.g., by clade, see `FastarLoader.group_weights`. Windows are identified by the global contig
+    id (see `FastarLoader.global_id`) and the start within the contig. With `max_n_fraction`,
+    windows with a larger fraction of `N` bases are replaced by new samples, which requires
+    reading them while sampling. With `blacklist`, a mapping from name to a BED file (optionally
+    gzipped), windows overlapping a listed region are replaced as well. With `mutations`, the
+    windows of a `FastarLoader` are mutated in Rust before they are returned by `read_windows`
+    and `sample_sequences`, seeded by `seed`.
+    """
+
+    def __init__(
+        self,
+        loader: FastarLoader | TrackLoader,
+        window_length: int,
+        weights: Mapping[str, float] | None = None,
+        seed: int | None = None,
+        max_n_fraction: float | None = None,
+        blacklist: Mapping[str, str | Path] | None = None,
+        mutations: Mutations | None = None,
+    ):
+        self.loader = loader
+        self._sampler = _rust.WindowSampler(
+            loader._index_map,
+            window_length,
+            dict(weights) if weights is not None else None,
+            seed,
+            max_n_fraction,
+            (
+                {name: _read_bed(path) for name, path in blacklist.items()}
+                if blacklist is not None
+                else None
+            ),
+            mutations,
+        )
+
+    @property
+    def window_length(self) -> int:
+        return self._sampler.window_length
+
+    @property
+    def num_sampled(self) -> int:
+        return self._sampler.num_sampled
+
+    def state_dict(self) -> bytes:
+        """Sampler state to resume sampling deterministically, e.g., after a preemption."""
+        return self._sampler.state_dict()
+
+    def load_state_dict(self, state: bytes) -> None:
+        """Restore a state of a sampler with the same window length and weights."""
+        self._sampler.load_state_dict(state)
+
+    def sample_indices(self, batch_size: int) -> tuple[np.ndarray, np.ndarray]:
+        """Global contig ids as an uint32 array and starts as an uint64 array."""
+        return self._sampler.sample_indices(batch_size)
+
+    def sample_windows(self, batch_size: int) -> list[tuple[str, str, int]]:
+        """Windows as (name, contig, start)."""
+        ids, starts = self.sample_indices(batch_size)
+        return [
+            (*self.loader.global_contig(int(id)), int(start)) for id, start in zip(ids, starts)
+        ]
+
+    def read_windows(
+        self, ids: np.ndarray, starts: np.ndarray, cancel: CancellationToken | None = None
+    ) -> np.ndarray:
+        """Read windows given by global contig ids and starts, one window per row. Cancelling
+        `cancel` from another thread stops the reads with an error."""
+        return self._sampler.read_windows(
+            np.ascontiguousarray(ids, dtype=np.uint32),
+            np.ascontiguousarray(starts, dtype=np.uint64),
+            cancel,
+        )
+
+    def filter_windows(
+        self, ids: np.ndarray, starts: np.ndarray, max_n_fraction: float
+    ) -> np.ndarray:
+        """Boolean mask of the windows with a fraction of `N` bases of at most `max_n_fraction`."""
+        return self._sampler.filter_windows(
+            np.ascontiguousarray(ids, dtype=np.uint32),
+            np.ascontiguousarray(starts, dtype=np.uint64),
+            max_n_fraction,
+        )
+
+    def blacklisted(
+        self, ids: np.ndarray, starts: np.ndarray, length: int | None = None
+    ) -> np.ndarray:
+        """Boolean mask of the regions of `length` (by default the window length) given by global
+        contig ids and starts which overlap the blacklist, e.g., to also filter reads.
+        """
+        return self._sampler.blacklisted(
+            np.ascontiguousarray(ids, dtype=np.uint32),
+            np.ascontiguousarray(starts, dtype=np.uint64),
+            self.window_length if length is None else length,
+        )
+
+    def blacklist_intervals(self, name: str, contig: str) -> np.ndarray:
+        """Merged blacklisted intervals of a contig, with one (start, end) row per interval."""
+        return self._sampler.blacklist_intervals(self.loader.global_id(name, contig))
+
+    def sample_sequences(
+        self, batch_size: int, cancel: CancellationToken | None = None
+    ) -> np.ndarray:
+        """Read `batch_size` random windows, one window per row, see `read_windows`."""
+        return self._sampler.sample(batch_size, cancel)[2]
+
+
+class PositionSampler:
+    """Random positions of a track, with probability proportional to the track values.
+
+    The values are decoded as little-endian `dtype` (e.g., coverage as float32), where NaN counts
+    as zero and negative values are rejected. On construction, the whole track is read once to
+    sum its values in blocks of `block_size` values, so a sample only reads a single block.
+    Positions count values, not bytes, and contigs are identified by the global contig id (see
+    `TrackLoader.global_id`).
+    """
+
+    def __init__(
+        self,
+        loader: TrackLoader,
+        dtype: npt.DTypeLike = np.float32,
+        block_size: int = 4096,
+        seed: int | None = None,
+    ):
+        self.loader = loader
+        self._sampler = _rust.PositionSampler(
+            loader._index_map, np.dtype(dtype).name, block_size, seed
+        )
+
+    @property
+    def total(self) -> float:
+        """Sum of all values of the track."""
+        return self._sampler.total
+
+    def sample_indices(self, batch_size: int) -> tuple[np.ndarray, np.ndarray]:
+        """Global contig ids as an uint32 array and positions as an uint64 array."""
+        return self._sampler.sample_indices(batch_size)
+
+    def sample_positions(self, batch_size: int) -> list[tuple[str, str, int]]:
+        """Positions as (name, contig, position)."""
+        ids, positions = self.sample_indices(batch_size)
+        return [
+            (*self.loader.global_contig(int(id)), int(position))
+            for id, position in zip(ids, positions)
+        ]
+
+
+class GcMatchedSampler:
+    """Negative windows matched to positive windows on GC content, e.g., for regulatory genomics
+    models.
+
+    On construction, the whole collection is read once to bin the candidate windows of
+    `window_length`, starting every `stride` bases (by default `window_length`, which must be a
+    multiple of `stride`), into `num_bins` bins of equal width by their GC content, i.e., the
+    fraction of `C` and `G` among the `ACGT` bases. Candidates with a larger fraction of other
+    bases than `max_n_fraction` are left out. Negatives have the same length as the positives,
+    are drawn from the bin of their positive or from the nearest non-empty bin, and never overlap
+    any of the positives of the same call.
+    """
+
+    def __init__(
+        self,
+        loader: FastarLoader,
+        window_length: int,
+        stride: int | None = None,
+        num_bins: int = 20,
+        max_n_fraction: float = 0.1,
+        seed: int | None = None,
+    ):
+        self.loader = loader
+        self._sampler = _rust.GcMatchedSampler(
+            loader._index_map, window_length, stride, num_bins, max_n_fraction, seed
+        )
+
+    @property
+    def window_length(self) -> int:
+        return self._sampler.window_length
+
+    @property
+    def bin_sizes(self) -> list[int]:
+        """Number of candidate windows in each GC bin, from low to high GC content."""
+        return self._sampler.bin_sizes
+
+    def gc_content(self, ids: np.ndarray, starts: np.ndarray) -> np.ndarray:
+        """GC content of windows given by global contig ids and starts, NaN without `ACGT`."""
+        return self._sampler.gc_content(
+            np.ascontiguousarray(ids, dtype=np.uint32),
+            np.ascontiguousarray(starts, dtype=np.uint64),
+        )
+
+    def sample_indices(
+        self, ids: np.ndarray, starts: np.ndarray, num_per_positive: int = 1
+    ) -> tuple[np.ndarray, np.ndarray]:
+        """`num_per_positive` negatives for each positive window given by global contig ids and
+        starts (see `FastarLoader.global_id`), as global contig ids and starts grouped by
+        positive."""
+        return self._sampler.sample_indices(
+            np.ascontiguousarray(ids, dtype=np.uint32),
+            np.ascontiguousarray(starts, dtype=np.uint64),
+            num_per_positive,
+        )
+
+    def sample_windows(
+        self, positives: Iterable[tuple[str, str, int]], num_per_positive: int = 1
+    ) -> list[tuple[str, str, int]]:
+        """Negatives for positive windows given as (name, contig, start), in the same form."""
+        positives = list(positives)
+        ids = [self.loader.global_id(name, contig) for name, contig, _ in positives]
+        starts = [start for _, _, start in positives]
+        ids, starts = self.sample_indices(np.array(ids), np.array(starts), num_per_positive)
+        return [
+            (*self.loader.global_contig(int(id)), int(start)) for id, start in zip(ids, starts)
+        ]
+
+
+class GenomeDataset:
+    """A FASTA loader bound to track loaders with one value per base, e.g., coverage.
+
+    `tracks` maps a track key to a loader and the dtype of its values. On construction, every
+    track is checked to cover all contigs of the FASTA loader with a matching length, so that a
+    region of the sequence and the tracks cannot get out of step.
+    """
+
+    def __init__(
+        self,
+        fasta: FastarLoader,
+        tracks: Mapping[str, tuple[TrackLoader, npt.DTypeLike]],
+    ):
+        self.fasta = fasta
+        self.tracks = {key: (loader, np.dtype(dtype)) for key, (loader, dtype) in tracks.items()}
+        self._dataset = _rust.GenomeDataset(
+            fasta._index_map,
+            [(loader._index_map, dtype.itemsize) for loader, dtype in self.tracks.values()],
+        )
+
+    def read(
+        self, name: str, contig: str, start: int, length: int
+    ) -> tuple[np.ndarray, dict[str, np.ndarray]]:
+        """Read `length` bases starting at `start` and the values of each track for them."""
+        sequence, tracks = self._dataset.read(name, contig.encode(), start, length)
+        return sequence, {
+            key: values.view(dtype)
+            for (key, (_, dtype)), values in zip(self.tracks.items(), tracks)
+        }
+
+
+class SparseTrackLoader:
+    """A track of intervals with a value each from a bedGraph file, e.g., peaks or sparse
+    coverage, which would mostly be a constant as a dense track.
+
+    Plain and bgzip-compressed files are supported, and overlapping intervals are rejected. With
+    `cache_path`, the archived intervals are cached there and only rebuilt if the bedGraph file
+    changed. Reads materialize a dense float32 window with `fill` outside of the intervals.
+    """
+
+    def __init__(self, path: str | Path, cache_path: str | Path | None = None):
+        self.path = str(path)
+        self._track = _rust.SparseTrack(self.path, None if cache_path is None else str(cache_path))
+
+    def contigs(self) -> list[tuple[str, int]]:
+        """Contigs with at least one interval and their number of intervals, in file order."""
+        return [(contig.decode(), count) for contig, count in self._track.contigs()]
+
+    def read_sequence(self, contig: str, start: int, length: int, fill: float = 0.0) -> np.ndarray:
+        """Read `length` values starting at `start` as a float32 array."""
+        return self._track.read_sequence(contig.encode(), start, length, fill)
+
+
+class RleTrackLoader:
+    """A single track stored as runs of equal values, written by `TrackLoader.write_rle`.
+
+    Reads locate the first run by binary search and decode the runs on the fly, so they return
+    the same bytes as `TrackLoader.read_sequence` for the dense track.
+    """
+
+    def __init__(self, path: str | Path):
+        self.path = str(path)
+        self._track = _rust.RleTrack(self.path)
+
+    @property
+    def value_size(self) -> int:
+        """Size of a value in bytes."""
+        return self._track.value_size
+
+    @property
+    def num_runs(self) -> int:
+        """Number of runs over all contigs."""
+        return self._track.num_runs
+
+    def contigs(self) -> list[tuple[str, int]]:
+        """Contigs and their length in bytes."""
+        return [(contig.decode(), length) for contig, length in self._track.contigs()]
+
+    def read_sequence(self, contig: str, start: int, length: int) -> np.ndarray:
+        """Read `length` bytes starting at `start`."""
+        return self._track.read_sequence(contig.encode(), start, length)
+
+
+class MappedTrackLoader:
+    """A single uncompressed track `XXX.track` with the index `XXX.track.idx` in the format of
+    `.track.gz.idx`, e.g., created with `zcat XXX.track.gz > XXX.track` and a copy of the index.
+
+    The track is memory-mapped, and `read_sequence` returns a read-only view into the map
+    instead of a copy, which keeps the map alive for as long as the view exists. The file must
+    not be truncated or rewritten while it is mapped.
+    """
+
+    def __init__(self, path: str | Path):
+        self.path = str(path)
+        self._track = _rust.MappedTrack(self.path)
+
+    def contigs(self) -> list[tuple[str, int]]:
+        """Contigs and their length in bytes."""
+        return [(contig.decode(), length) for contig, length in self._track.contigs()]
+
+    def read_sequence(self, contig: str, start: int, length: int) -> np.ndarray:
+        """`length` bytes starting at `start` as a read-only view, without copying them. Use
+        `.view(dtype)` for values of other types."""
+        return self._track.read_sequence(contig.encode(), start, length)
+
+
+class PackedLoader:
+    """Genomes packed to two bits per base, written by `FastarLoader.write_packed`.
+
+    Bases other than `ACGT`, such as `N`, and soft-masked (lowercase) bases are stored as runs
+    next to the packed bases, so reads return the same bytes as `FastarLoader.read_sequence`
+    for the FASTA files, without touching BGZF.
+    """
+
+    def __init__(self, path: str | Path):
+        self.path = str(path)
+        self._genomes = _rust.PackedGenomes(self.path)
+
+    def names(self) -> list[str]:
+        """The packed genomes."""
+        return self._genomes.names()
+
+    def contigs(self, name: str) -> list[tuple[str, int]]:
+        """Contigs of `name` and their length."""
+        return [(contig.decode(), length) for contig, length in self._genomes.contigs(name)]
+
+    def read_sequence(self, name: str, contig: str, start: int, length: int) -> np.ndarray:
+        """Read `length` bases starting at the 0-based `start` of `contig`."""
+        return self._genomes.read_sequence(name, contig.encode(), start, length)
+
+
+def _read_bed(path: str | Path) -> bytes:
+    with open(path, "rb") as f:
+        data = f.read()
+    return gzip.decompress(data) if data[:2] == b"\x1f\x8b" else data
+
+
+class WindowPartition:
+    """The windows of one worker in one epoch, for iterating over a collection in parallel.
+
+    All contigs are tiled into windows of `window_length` every `stride` values (by default
+    non-overlapping), shuffled by `seed` and `epoch`, and dealt to the `num_workers` workers in
+    turn. Every worker gets the same number of windows, so up to `num_workers - 1` windows are
+    left out in each epoch. With DDP, use `num_workers = world_size * loader_workers` and
+    `worker_id = rank * loader_workers + loader_worker_id`.
+    """
+
+    def __init__(
+        self,
+        loader: FastarLoader | TrackLoader,
+        window_length: int,
+        num_workers: int,
+        worker_id: int,
+        epoch: int = 0,
+        seed: int = 0,
+        stride: int | None = None,
+    ):
+        self.loader = loader
+        self._partition = _rust.WindowPartition(
+            loader._index_map, window_length, num_workers, worker_id, epoch, seed, stride
+        )
+
+    @property
+    def window_length(self) -> int:
+        return self._partition.window_length
+
+    def __len__(self) -> int:
+        return len(self._partition)
+
+    def indices(self, offset: int, count: int) -> tuple[np.ndarray, np.ndarray]:
+        """Global contig ids and starts of `count` windows starting at `offset`."""
+        return self._partition.indices(offset, count)
+
+    def read(self, offset: int, count: int) -> np.ndarray:
+        """Read `count` windows starting at `offset`, one window per row."""
+        return self._partition.read(offset, count)
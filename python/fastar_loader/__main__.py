@@ -0,0 +1,16 @@
+"""The `fastar-loader` command line interface, e.g., `fastar-loader build /data/genomes`.
+
+Run `fastar-loader --help` for the commands and their options.
+"""
+
+import sys
+
+from . import fastar_loader as _rust  # type: ignore
+
+
+def main() -> None:
+    sys.exit(_rust.cli(sys.argv[1:]))
+
+
+if __name__ == "__main__":
+    main()
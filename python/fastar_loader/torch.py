@@ -0,0 +1,95 @@
+from collections.abc import Iterator, Mapping
+from pathlib import Path
+
+import numpy as np
+import torch
+from torch.utils.data import IterableDataset, get_worker_info
+
+from . import FastarLoader, Mutations, WindowSampler
+from . import fastar_loader as _rust  # type: ignore
+
+_ENCODINGS = ("raw", "index", "one_hot")
+
+
+class GenomeIterableDataset(IterableDataset[torch.Tensor]):
+    """Batches of random windows of a `FastarLoader` as tensors, for a `DataLoader` with
+    `batch_size=None`.
+
+    Sampling, reading, encoding and batching run in Rust on a background thread of each worker,
+    `prefetch` batches ahead, so the workers only hand the finished batches to PyTorch. The
+    windows are sampled as by `WindowSampler` with `weights`, `max_n_fraction`, `blacklist` and
+    `mutations`, and encoded by `encoding`:
+
+    - `"raw"`: the bases as ASCII, as (batch_size, window_length) uint8.
+    - `"index"`: `ACGT` as 0 to 3 and other bases as 4, ignoring case, as
+      `util.ascii_to_sequence`, as (batch_size, window_length) uint8.
+    - `"one_hot"`: one channel per base of `ACGT`, all zero for other bases, as
+      (batch_size, window_length, 4) uint8.
+
+    An epoch has `num_batches` batches, which are dealt to the workers of the `DataLoader` in
+    turn. Each worker samples with its own seed, derived from `seed`, the epoch (see
+    `set_epoch`) and its worker id, so workers never repeat each other's windows.
+    """
+
+    def __init__(
+        self,
+        loader: FastarLoader,
+        window_length: int,
+        batch_size: int,
+        num_batches: int,
+        encoding: str = "index",
+        seed: int = 0,
+        weights: Mapping[str, float] | None = None,
+        max_n_fraction: float | None = None,
+        blacklist: Mapping[str, str | Path] | None = None,
+        mutations: Mutations | None = None,
+        prefetch: int = 2,
+    ):
+        if encoding not in _ENCODINGS:
+            raise ValueError(f"Unknown encoding {encoding!r}, expected one of {_ENCODINGS}")
+        if batch_size <= 0 or num_batches <= 0 or prefetch <= 0:
+            raise ValueError("batch_size, num_batches and prefetch must be positive")
+        self.loader = loader
+        self.window_length = window_length
+        self.batch_size = batch_size
+        self.num_batches = num_batches
+        self.encoding = encoding
+        self.seed = seed
+        self.weights = weights
+        self.max_n_fraction = max_n_fraction
+        self.blacklist = blacklist
+        self.mutations = mutations
+        self.prefetch = prefetch
+        self.epoch = 0
+
+    def set_epoch(self, epoch: int) -> None:
+        """Sample the windows of `epoch`, to be called before iterating over each epoch."""
+        self.epoch = epoch
+
+    def __len__(self) -> int:
+        return self.num_batches
+
+    def __iter__(self) -> Iterator[torch.Tensor]:
+        info = get_worker_info()
+        worker_id, num_workers = (0, 1) if info is None else (info.id, info.num_workers)
+        seed = np.random.SeedSequence([self.seed, self.epoch, worker_id]).generate_state(
+            1, dtype=np.uint64
+        )[0]
+        sampler = WindowSampler(
+            self.loader,
+            self.window_length,
+            weights=self.weights,
+            seed=int(seed),
+            max_n_fraction=self.max_n_fraction,
+            blacklist=self.blacklist,
+            mutations=self.mutations,
+        )
+        stream = _rust.Stream.sampler(
+            sampler._sampler,
+            self.batch_size,
+            self.prefetch,
+            len(range(worker_id, self.num_batches, num_workers)),
+            self.encoding,
+        )
+        for batch in stream:
+            yield torch.from_numpy(batch)
@@ -0,0 +1,144 @@
+use anyhow::{bail, Result};
+use xxhash_rust::xxh3::{xxh3_64, xxh3_64_with_seed};
+
+use crate::error::Error;
+
+/// Bases of each IUPAC code as a bit mask of `A`, `C`, `G` and `T`, ignoring case. Bytes
+/// without a mask are not ambiguity codes, e.g., `-`.
+const IUPAC_MASKS: [u8; 256] = {
+    let codes: [(u8, u8); 16] = [
+        (b'A', 0b0001),
+        (b'C', 0b0010),
+        (b'G', 0b0100),
+        (b'T', 0b1000),
+        (b'U', 0b1000),
+        (b'R', 0b0101),
+        (b'Y', 0b1010),
+        (b'S', 0b0110),
+        (b'W', 0b1001),
+        (b'K', 0b1100),
+        (b'M', 0b0011),
+        (b'B', 0b1110),
+        (b'D', 0b1101),
+        (b'H', 0b1011),
+        (b'V', 0b0111),
+        (b'N', 0b1111),
+    ];
+    let mut masks = [0; 256];
+    let mut i = 0;
+    while i < codes.len() {
+        let (code, mask) = codes[i];
+        masks[code as usize] = mask;
+        masks[code.to_ascii_lowercase() as usize] = mask;
+        i += 1;
+    }
+    masks
+};
+
+/// How reads treat characters other than `ACGT`, such as IUPAC ambiguity codes. Case is kept
+/// by all policies, so soft-masking survives.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Ambiguity {
+    /// Return the bases as stored.
+    Keep,
+    /// Replace everything but `ACGT` by `N`.
+    MapToN,
+    /// Replace ambiguity codes by one of their bases, chosen by a hash of `seed` and the
+    /// position, such that every read of a position returns the same base. `N`, which marks
+    /// gaps rather than ambiguity, and other characters are replaced by `N`.
+    Sample { seed: u64 },
+}
+
+impl Ambiguity {
+    pub(crate) fn parse(policy: &str, seed: u64) -> Result<Self> {
+        Ok(match policy {
+            "keep" => Ambiguity::Keep,
+            "n" => Ambiguity::MapToN,
+            "sample" => Ambiguity::Sample { seed },
+            _ => bail!(Error::InvalidOptions(format!(
+                "Unknown ambiguity policy {:?}, expected \"keep\", \"n\" or \"sample\"",
+                policy
+            ))),
+        })
+    }
+
+    /// Name and seed of the policy, as accepted by `parse`.
+    pub(crate) fn name(&self) -> (&'static str, u64) {
+        match *self {
+            Ambiguity::Keep => ("keep", 0),
+            Ambiguity::MapToN => ("n", 0),
+            Ambiguity::Sample { seed } => ("sample", seed),
+        }
+    }
+
+    /// Apply the policy to `sequence`, read from `start` of `contig` of `name`.
+    pub(crate) fn apply(&self, name: &str, contig: &[u8], start: u64, sequence: &mut [u8]) {
+        let seed = match *self {
+            Ambiguity::Keep => return,
+            Ambiguity::MapToN => None,
+            Ambiguity::Sample { seed } => {
+                let mut key = Vec::with_capacity(name.len() + contig.len() + 1);
+                key.extend_from_slice(name.as_bytes());
+                key.push(0);
+                key.extend_from_slice(contig);
+                Some(seed ^ xxh3_64(&key))
+            }
+        };
+        for (position, base) in (start..).zip(sequence.iter_mut()) {
+            if matches!(base.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T') {
+                continue;
+            }
+            let mask = IUPAC_MASKS[*base as usize];
+            let resolved = match seed {
+                Some(seed) if mask != 0 && mask != 0b1111 => {
+                    let choice =
+                        xxh3_64_with_seed(&position.to_le_bytes(), seed) % mask.count_ones() as u64;
+                    let bit = (0..4)
+                        .filter(|bit| mask >> bit & 1 == 1)
+                        .nth(choice as usize)
+                        .unwrap();
+                    b"ACGT"[bit]
+                }
+                _ => b'N',
+            };
+            *base = if base.is_ascii_lowercase() {
+                resolved.to_ascii_lowercase()
+            } else {
+                resolved
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(ambiguity: Ambiguity, start: u64, sequence: &[u8]) -> Vec<u8> {
+        let mut sequence = sequence.to_vec();
+        ambiguity.apply("a", b"chr1", start, &mut sequence);
+        sequence
+    }
+
+    #[test]
+    fn test_ambiguity() {
+        let sequence = b"ACGTacgtNnRyU-";
+        assert_eq!(apply(Ambiguity::Keep, 0, sequence), sequence);
+        assert_eq!(apply(Ambiguity::MapToN, 0, sequence), b"ACGTacgtNnNnNN");
+
+        let sample = Ambiguity::parse("sample", 7).unwrap();
+        let sampled = apply(sample, 0, sequence);
+        assert_eq!(&sampled[..10], b"ACGTacgtNn");
+        assert!(b"AG".contains(&sampled[10]));
+        assert!(b"ct".contains(&sampled[11]));
+        assert_eq!(&sampled[12..], b"TN");
+        // Overlapping reads resolve a position to the same base
+        assert_eq!(apply(sample, 10, &sequence[10..]), &sampled[10..]);
+        let many = apply(sample, 0, &[b'R'; 100]);
+        assert!(many.contains(&b'A') && many.contains(&b'G'));
+
+        assert_eq!(Ambiguity::parse("n", 0).unwrap(), Ambiguity::MapToN);
+        assert_eq!(sample.name(), ("sample", 7));
+        assert!(Ambiguity::parse("drop", 0).is_err());
+    }
+}
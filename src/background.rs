@@ -0,0 +1,85 @@
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, Result};
+
+/// A value which is possibly still being computed on a background thread.
+pub(crate) struct Background<T> {
+    value: Arc<OnceLock<Result<T>>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl<T: Send + Sync + 'static> Background<T> {
+    pub(crate) fn ready(value: T) -> Self {
+        Background {
+            value: Arc::new(OnceLock::from(Ok(value))),
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Compute the value with `f` on a new thread.
+    pub(crate) fn spawn(f: impl FnOnce() -> Result<T> + Send + 'static) -> Self {
+        let value = Arc::new(OnceLock::new());
+        let thread = {
+            let value = value.clone();
+            std::thread::spawn(move || {
+                let _ = value.set(f());
+            })
+        };
+        Background {
+            value,
+            thread: Mutex::new(Some(thread)),
+        }
+    }
+
+    /// Whether the computation finished, successfully or not.
+    pub(crate) fn is_ready(&self) -> bool {
+        self.value.get().is_some()
+    }
+
+    /// Block until the computation finished and return its value.
+    /// An error of the computation is returned on every call.
+    pub(crate) fn wait(&self) -> Result<&T, &anyhow::Error> {
+        if self.value.get().is_none() {
+            let mut thread = self.thread.lock().unwrap();
+            if let Some(thread) = thread.take() {
+                if thread.join().is_err() {
+                    let _ = self.value.set(Err(anyhow!("Background thread panicked")));
+                }
+            }
+        }
+        match self.value.get() {
+            Some(result) => result.as_ref(),
+            None => unreachable!("background thread finished without a value"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_background() {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let value = Background::spawn(move || {
+            receiver.recv()?;
+            Ok(42)
+        });
+        assert!(!value.is_ready());
+        sender.send(()).unwrap();
+        assert_eq!(*value.wait().unwrap(), 42);
+        assert!(value.is_ready());
+        assert_eq!(*Background::ready(1).wait().unwrap(), 1);
+
+        let failed = Background::<u32>::spawn(|| bail!("build failed"));
+        assert!(failed.wait().is_err());
+        assert!(failed
+            .wait()
+            .unwrap_err()
+            .to_string()
+            .contains("build failed"));
+    }
+}
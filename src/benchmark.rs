@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use numpy::ndarray::Array1;
+use rayon::prelude::*;
+
+use crate::coordinates::GlobalIndex;
+use crate::error::Error;
+use crate::sampler::WindowSampler;
+
+/// Measure random reads of `length` values from `global` with `threads` threads. The regions
+/// are sampled up front, uniformly over all windows and seeded by `seed`, so only the reads
+/// themselves are timed. `read` reads a region given as name, contig, start and length.
+/// Returns the measurements by name, with throughput in bytes per second and latencies in
+/// seconds.
+pub(crate) fn run<F>(
+    global: &GlobalIndex,
+    n_queries: usize,
+    length: u64,
+    seed: u64,
+    threads: usize,
+    read: F,
+) -> Result<Vec<(&'static str, f64)>>
+where
+    F: Fn(&str, &[u8], u64, u64) -> Result<Array1<u8>> + Sync,
+{
+    if n_queries == 0 || threads == 0 {
+        bail!(Error::InvalidOptions(
+            "n_queries and threads must be positive".to_string()
+        ));
+    }
+    let mut sampler = WindowSampler::new(global, length, None, Some(seed))?;
+    let regions = (0..n_queries)
+        .map(|_| sampler.sample(global))
+        .collect::<Vec<_>>();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    let start = Instant::now();
+    let mut latencies = pool.install(|| {
+        regions
+            .par_iter()
+            .map(|&(id, start)| {
+                let (name, contig) = global.contig(id).unwrap();
+                let query_start = Instant::now();
+                read(name, contig, start, length)?;
+                Ok(query_start.elapsed())
+            })
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let elapsed = start.elapsed().as_secs_f64();
+    latencies.sort_unstable();
+    let bytes = (n_queries as u64 * length) as f64;
+    Ok(vec![
+        ("n_queries", n_queries as f64),
+        ("length", length as f64),
+        ("threads", threads as f64),
+        ("elapsed", elapsed),
+        ("queries_per_second", n_queries as f64 / elapsed),
+        ("bytes_per_second", bytes / elapsed),
+        ("latency_mean", mean(&latencies)),
+        ("latency_p50", percentile(&latencies, 50.0)),
+        ("latency_p90", percentile(&latencies, 90.0)),
+        ("latency_p99", percentile(&latencies, 99.0)),
+        ("latency_max", percentile(&latencies, 100.0)),
+    ])
+}
+
+fn mean(latencies: &[Duration]) -> f64 {
+    latencies.iter().sum::<Duration>().as_secs_f64() / latencies.len() as f64
+}
+
+/// Nearest-rank percentile of the sorted, non-empty `latencies` in seconds.
+fn percentile(latencies: &[Duration], p: f64) -> f64 {
+    let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1].as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let latencies = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&latencies, 50.0), 0.05);
+        assert_eq!(percentile(&latencies, 99.0), 0.099);
+        assert_eq!(percentile(&latencies, 100.0), 0.1);
+        assert_eq!(percentile(&latencies[..1], 50.0), 0.001);
+        assert!((mean(&latencies) - 0.0505).abs() < 1e-12);
+    }
+}
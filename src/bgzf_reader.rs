@@ -0,0 +1,139 @@
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+use noodles::bgzf::{self, gzi, io::Seek as _, VirtualPosition};
+
+/// A BGZF reader which reads across empty blocks, such as the end-of-file markers in the middle
+/// of files concatenated from several BGZF files, e.g., with `cat`. The `.gzi` and the virtual
+/// positions count these markers as blocks without data, so seeking works as for a single file.
+pub(crate) struct BgzfReader<R>(bgzf::io::Reader<R>);
+
+impl<R: Read> BgzfReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        BgzfReader(bgzf::io::Reader::new(inner))
+    }
+
+    pub(crate) fn virtual_position(&self) -> VirtualPosition {
+        self.0.virtual_position()
+    }
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    pub(crate) fn seek_to_virtual_position(
+        &mut self,
+        pos: VirtualPosition,
+    ) -> io::Result<VirtualPosition> {
+        self.0.seek_to_virtual_position(pos)
+    }
+}
+
+impl<R: Read> BufRead for BgzfReader<R> {
+    /// The data of the current block, or of the next block which has data. Empty only at the
+    /// end of the file.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        loop {
+            let before = self.0.virtual_position();
+            // An empty block moves the position to the next block, the end of the file does not
+            if !self.0.fill_buf()?.is_empty() || self.0.virtual_position() <= before {
+                break;
+            }
+        }
+        self.0.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.0.consume(amt);
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let src = self.fill_buf()?;
+        let n = src.len().min(buf.len());
+        buf[..n].copy_from_slice(&src[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+/// A `BgzfReader` which seeks to uncompressed positions by its `.gzi` index, e.g., for the
+/// indexed FASTA reader of noodles.
+pub(crate) struct IndexedBgzfReader<R> {
+    reader: BgzfReader<R>,
+    index: gzi::Index,
+}
+
+impl<R: Read> IndexedBgzfReader<R> {
+    pub(crate) fn new(inner: R, index: gzi::Index) -> Self {
+        IndexedBgzfReader {
+            reader: BgzfReader::new(inner),
+            index,
+        }
+    }
+}
+
+impl<R: Read> BufRead for IndexedBgzfReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt);
+    }
+}
+
+impl<R: Read> Read for IndexedBgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: Read + Seek> Seek for IndexedBgzfReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let SeekFrom::Start(pos) = pos else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "BGZF files can only be seeked from the start",
+            ));
+        };
+        self.reader
+            .seek_to_virtual_position(self.index.query(pos)?)?;
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.gz");
+        let mut writer = bgzf::io::Writer::new(std::fs::File::create(&path).unwrap());
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+        std::fs::read(&path).unwrap()
+    }
+
+    #[test]
+    fn test_concatenated_members() {
+        let first = compress(b">a\nACGT\n");
+        let mut data = first.clone();
+        data.extend(compress(b">b\nTTGG\n"));
+        let mut reader = BgzfReader::new(io::Cursor::new(data));
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b">a\nACGT\n>b\nTTGG\n");
+
+        // The second member starts after the first one, including its end-of-file marker
+        let pos = VirtualPosition::try_from((first.len() as u64, 3)).unwrap();
+        reader.seek_to_virtual_position(pos).unwrap();
+        let mut sequence = [0; 4];
+        reader.read_exact(&mut sequence).unwrap();
+        assert_eq!(&sequence, b"TTGG");
+        assert_eq!(reader.fill_buf().unwrap(), b"\n");
+        reader.consume(1);
+        assert!(reader.fill_buf().unwrap().is_empty());
+    }
+}
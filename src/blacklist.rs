@@ -0,0 +1,150 @@
+use std::io::BufRead;
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+
+use crate::coordinates::GlobalIndex;
+use crate::error::Error;
+
+/// Regions excluded from sampling, e.g., the ENCODE blacklist. The intervals of each contig
+/// are merged and sorted, so a lookup is a binary search over disjoint intervals.
+pub(crate) struct Blacklist {
+    /// Sorted, disjoint intervals `[start, end)`, indexed by global id.
+    intervals: Vec<Vec<(u64, u64)>>,
+}
+
+impl Blacklist {
+    pub(crate) fn new(global: &GlobalIndex) -> Self {
+        Blacklist {
+            intervals: vec![Vec::new(); global.num_contigs()],
+        }
+    }
+
+    /// Add the intervals of a BED file for the contigs of `name`. Contigs which are not part of
+    /// `name` are skipped, as blacklists commonly cover contigs missing from an assembly.
+    pub(crate) fn add_bed<R: BufRead>(
+        &mut self,
+        global: &GlobalIndex,
+        name: &str,
+        bed: R,
+    ) -> Result<()> {
+        let mut skipped = 0;
+        for (i, line) in bed.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty()
+                || line.starts_with('#')
+                || line.starts_with("track")
+                || line.starts_with("browser")
+            {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let [contig, start, end, ..] = fields[..] else {
+                bail!("Line {} of the BED file has less than 3 fields", i + 1);
+            };
+            let parse = |value: &str| {
+                value
+                    .parse::<u64>()
+                    .with_context(|| format!("Invalid position on line {}: {}", i + 1, value))
+            };
+            let (start, end) = (parse(start)?, parse(end)?);
+            match global.id(name, contig.as_bytes()) {
+                Ok(id) => self.add(id, start, end),
+                Err(e) if matches!(e.downcast_ref(), Some(Error::ContigNotFound(_))) => {
+                    skipped += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if skipped > 0 {
+            warn!(
+                "Skipped {} blacklisted intervals on contigs missing from {}",
+                skipped, name
+            );
+        }
+        Ok(())
+    }
+
+    /// Add the interval `[start, end)` of a contig, merging it with overlapping intervals.
+    pub(crate) fn add(&mut self, id: u32, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        let intervals = &mut self.intervals[id as usize];
+        // Intervals touching the new one are merged into it
+        let first = intervals.partition_point(|&(_, e)| e < start);
+        let last = intervals.partition_point(|&(s, _)| s <= end);
+        let (mut start, mut end) = (start, end);
+        if first < last {
+            start = start.min(intervals[first].0);
+            end = end.max(intervals[last - 1].1);
+        }
+        intervals.splice(first..last, [(start, end)]);
+    }
+
+    /// Whether `[start, end)` of a contig overlaps a blacklisted interval.
+    pub(crate) fn overlaps(&self, id: u32, start: u64, end: u64) -> bool {
+        let Some(intervals) = self.intervals.get(id as usize) else {
+            return false;
+        };
+        // The first interval ending after `start` overlaps if it also starts before `end`
+        let i = intervals.partition_point(|&(_, e)| e <= start);
+        intervals.get(i).is_some_and(|&(s, _)| s < end)
+    }
+
+    /// Blacklisted intervals of a contig.
+    pub(crate) fn intervals(&self, id: u32) -> &[(u64, u64)] {
+        self.intervals
+            .get(id as usize)
+            .map_or(&[][..], |intervals| intervals.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global() -> GlobalIndex {
+        let contigs = |name: &str| -> Result<Vec<(&'static [u8], u64)>> {
+            Ok(match name {
+                "a" => vec![(b"chr1".as_slice(), 100), (b"chr2".as_slice(), 50)],
+                _ => unreachable!(),
+            })
+        };
+        GlobalIndex::build(vec!["a".to_string()], contigs).unwrap()
+    }
+
+    #[test]
+    fn test_blacklist() {
+        let global = global();
+        let mut blacklist = Blacklist::new(&global);
+        let bed =
+            b"# comment\nchr1\t10\t20\tname\nchr1 15 30\nchr1\t40\t50\nchrM\t0\t10\n\nchr2\t0\t5\n";
+        blacklist.add_bed(&global, "a", bed.as_slice()).unwrap();
+        assert_eq!(blacklist.intervals(0), &[(10, 30), (40, 50)]);
+        assert_eq!(blacklist.intervals(1), &[(0, 5)]);
+        assert!(blacklist.overlaps(0, 0, 11));
+        assert!(!blacklist.overlaps(0, 0, 10));
+        assert!(!blacklist.overlaps(0, 30, 40));
+        assert!(blacklist.overlaps(0, 29, 41));
+        assert!(blacklist.overlaps(0, 45, 46));
+        assert!(!blacklist.overlaps(0, 50, 100));
+        assert!(!blacklist.overlaps(1, 5, 50));
+
+        // Touching intervals are merged
+        blacklist.add(0, 30, 40);
+        assert_eq!(blacklist.intervals(0), &[(10, 50)]);
+        blacklist.add(0, 0, 100);
+        assert_eq!(blacklist.intervals(0), &[(0, 100)]);
+
+        assert!(blacklist
+            .add_bed(&global, "a", b"chr1\t1\n".as_slice())
+            .is_err());
+        assert!(blacklist
+            .add_bed(&global, "a", b"chr1\tx\t2\n".as_slice())
+            .is_err());
+        assert!(blacklist
+            .add_bed(&global, "b", b"chr1\t1\t2\n".as_slice())
+            .is_err());
+    }
+}
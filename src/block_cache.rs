@@ -0,0 +1,486 @@
+use std::io::BufRead;
+use std::mem::size_of;
+use std::path::Path;
+use std::ptr::{addr_of, addr_of_mut};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::warn;
+use noodles::bgzf::VirtualPosition;
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+use crate::bgzf_reader::BgzfReader;
+use crate::error::Error;
+use crate::remote::{open_data, DataFile};
+use crate::retry::with_retry;
+use crate::storage::{process_alive, MutableStorage, SharableStorage, ShmemStorage, Storage};
+
+/// Identifies the shared memory of a block cache, with the version of its layout.
+const MAGIC: u64 = u64::from_le_bytes(*b"FLBLKC01");
+
+/// Largest uncompressed size of a BGZF block.
+const MAX_BLOCK_SIZE: usize = 1 << 16;
+
+/// Number of blocks per shard, which are searched linearly under the lock of the shard.
+const SLOTS_PER_SHARD: usize = 16;
+
+/// How often a process waiting for the lock of a shard checks whether its holder died.
+const LOCK_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a process waits for the lock of a shard held by a live process before it bypasses
+/// the cache, e.g., if the holder was stopped or its PID was reused.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    num_shards: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[repr(C)]
+struct Slot {
+    /// Hash of the file and the offset of the block, or 0 for an empty slot.
+    key: u64,
+    last_used: u64,
+    /// Compressed offset of the following block.
+    next_offset: u64,
+    len: u64,
+    data: [u8; MAX_BLOCK_SIZE],
+}
+
+#[repr(C)]
+struct Shard {
+    /// Spin lock of the clock and the slots, held while copying a block in or out. Holds the
+    /// PID of its holder, or 0 if it is free, see `SharedBlockCache::with_shard`.
+    lock: AtomicU32,
+    clock: u64,
+    slots: [Slot; SLOTS_PER_SHARD],
+}
+
+/// Smallest size of a cache in bytes, which holds a single shard.
+pub(crate) const MIN_SIZE: u64 = (size_of::<Header>() + size_of::<Shard>()) as u64;
+
+/// Decompressed BGZF blocks by key, see `read_sequence`.
+pub(crate) trait BlockStore: Send + Sync {
+    /// Append the block of `key` to `buf` and return the offset of the following block, if the
+    /// block is stored.
+    fn get(&self, key: u64, buf: &mut Vec<u8>) -> Option<u64>;
+
+    /// The offset of the block following the block of `key`, if the block is stored, without
+    /// counting as a read of it.
+    fn next_offset(&self, key: u64) -> Option<u64>;
+
+    /// Store `block` as `key`, evicting other blocks if necessary.
+    fn insert(&self, key: u64, block: &[u8], next_offset: u64);
+}
+
+/// Releases the lock of a shard when dropped, also when the holder panics.
+struct Unlock<'a>(&'a AtomicU32);
+
+impl Drop for Unlock<'_> {
+    fn drop(&mut self) {
+        self.0.store(0, Ordering::Release);
+    }
+}
+
+/// An LRU cache of decompressed BGZF blocks in shared memory, so that all processes attached to
+/// it, e.g., the workers of a DataLoader, reuse each other's decompressions. Blocks are spread
+/// over shards by hash, and each shard evicts its least recently used block under its own lock.
+pub(crate) struct SharedBlockCache {
+    storage: ShmemStorage,
+    base: *mut u8,
+    num_shards: usize,
+}
+
+// The shared memory is only accessed through the atomics of the header and under the locks of
+// the shards.
+unsafe impl Send for SharedBlockCache {}
+unsafe impl Sync for SharedBlockCache {}
+
+impl SharedBlockCache {
+    /// Create a cache of at most `size` bytes in shared memory.
+    pub(crate) fn new(size: u64) -> Result<Self> {
+        if size < MIN_SIZE {
+            bail!(Error::InvalidOptions(format!(
+                "The block cache needs at least {} bytes",
+                MIN_SIZE
+            )));
+        }
+        let num_shards = ((size - size_of::<Header>() as u64) / size_of::<Shard>() as u64) as usize;
+        // New shared memory is zeroed, so all slots start empty and all shards unlocked
+        let mut storage = ShmemStorage::new(size_of::<Header>() + num_shards * size_of::<Shard>())?;
+        let base = storage.as_mut_ptr();
+        unsafe {
+            (base as *mut Header).write(Header {
+                magic: MAGIC,
+                num_shards: num_shards as u64,
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+            });
+        }
+        Ok(SharedBlockCache {
+            storage,
+            base,
+            num_shards,
+        })
+    }
+
+    /// Attach to a cache created by another process from its handle.
+    pub(crate) fn import(handle: Vec<u8>) -> Result<Self> {
+        let mut storage = ShmemStorage::import(handle)?;
+        let base = storage.as_mut_ptr();
+        if storage.len() < size_of::<Header>() {
+            bail!(Error::Storage("Invalid shared block cache".to_string()));
+        }
+        let header = unsafe { &*(base as *const Header) };
+        let num_shards = header.num_shards as usize;
+        if header.magic != MAGIC
+            || storage.len() < size_of::<Header>() + num_shards * size_of::<Shard>()
+        {
+            bail!(Error::Storage("Invalid shared block cache".to_string()));
+        }
+        Ok(SharedBlockCache {
+            storage,
+            base,
+            num_shards,
+        })
+    }
+
+    /// The handle to attach to the cache from other processes.
+    pub(crate) fn export(&self) -> Vec<u8> {
+        self.storage.export()
+    }
+
+    /// Size of the cache in bytes and its hits and misses over all attached processes.
+    pub(crate) fn stats(&self) -> (u64, u64, u64) {
+        let header = self.header();
+        (
+            (size_of::<Header>() + self.num_shards * size_of::<Shard>()) as u64,
+            header.hits.load(Ordering::Relaxed),
+            header.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*(self.base as *const Header) }
+    }
+
+    /// The shard of `key`.
+    fn shard(&self, key: u64) -> *mut Shard {
+        let index = (key % self.num_shards as u64) as usize;
+        unsafe {
+            self.base
+                .add(size_of::<Header>() + index * size_of::<Shard>()) as *mut Shard
+        }
+    }
+
+    /// Run `f` on the clock and the slots of the shard of `key` while holding its lock, or
+    /// return `None` if a live process holds it for longer than `LOCK_TIMEOUT`. The lock of a
+    /// holder which died, e.g., a worker killed by the OOM killer, is taken over, and the
+    /// slots of the shard are emptied, as the holder may have left one half-written.
+    fn with_shard<R>(&self, key: u64, f: impl FnOnce(&mut u64, &mut [Slot]) -> R) -> Option<R> {
+        let shard = self.shard(key);
+        let pid = std::process::id();
+        unsafe {
+            // The lock is shared with other processes, so it is only accessed as an atomic
+            let lock = &*addr_of!((*shard).lock);
+            let start = Instant::now();
+            let mut checked = start;
+            loop {
+                let holder = match lock.compare_exchange_weak(
+                    0,
+                    pid,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(holder) => holder,
+                };
+                if holder != 0 && checked.elapsed() >= LOCK_CHECK_INTERVAL {
+                    checked = Instant::now();
+                    if !process_alive(holder)
+                        && lock
+                            .compare_exchange(holder, pid, Ordering::Acquire, Ordering::Relaxed)
+                            .is_ok()
+                    {
+                        warn!(
+                            "Process {} died while holding a lock of the block cache, taking it \
+                             over",
+                            holder
+                        );
+                        for slot in &mut *addr_of_mut!((*shard).slots) {
+                            slot.key = 0;
+                            slot.last_used = 0;
+                        }
+                        break;
+                    }
+                    if start.elapsed() >= LOCK_TIMEOUT {
+                        return None;
+                    }
+                }
+                std::thread::yield_now();
+            }
+            let _unlock = Unlock(lock);
+            Some(f(
+                &mut *addr_of_mut!((*shard).clock),
+                &mut *addr_of_mut!((*shard).slots),
+            ))
+        }
+    }
+}
+
+impl BlockStore for SharedBlockCache {
+    fn get(&self, key: u64, buf: &mut Vec<u8>) -> Option<u64> {
+        let next_offset = self.with_shard(key, |clock, slots| {
+            let slot = slots.iter_mut().find(|slot| slot.key == key)?;
+            *clock += 1;
+            slot.last_used = *clock;
+            buf.extend_from_slice(&slot.data[..slot.len as usize]);
+            Some(slot.next_offset)
+        });
+        // A shard which cannot be locked counts as a miss
+        let next_offset = next_offset.flatten();
+        let counter = match next_offset {
+            Some(_) => &self.header().hits,
+            None => &self.header().misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        next_offset
+    }
+
+    fn next_offset(&self, key: u64) -> Option<u64> {
+        self.with_shard(key, |_, slots| {
+            let slot = slots.iter().find(|slot| slot.key == key)?;
+            Some(slot.next_offset)
+        })
+        .flatten()
+    }
+
+    /// Replaces the least recently used block of the shard of `key`, or drops the block if the
+    /// shard cannot be locked.
+    fn insert(&self, key: u64, block: &[u8], next_offset: u64) {
+        self.with_shard(key, |clock, slots| {
+            let slot = match slots.iter().position(|slot| slot.key == key) {
+                Some(i) => &mut slots[i],
+                None => slots.iter_mut().min_by_key(|slot| slot.last_used).unwrap(),
+            };
+            *clock += 1;
+            slot.key = key;
+            slot.last_used = *clock;
+            slot.next_offset = next_offset;
+            slot.len = block.len() as u64;
+            slot.data[..block.len()].copy_from_slice(block);
+        });
+    }
+}
+
+/// Decompresses blocks of a BGZF file, opening it on first use.
+struct BlockReader<'a> {
+    path: &'a Path,
+    /// Hash of the path and the version of the file, from which the keys of its blocks are
+    /// derived.
+    file: u64,
+    reader: Option<BgzfReader<Box<dyn DataFile>>>,
+}
+
+impl<'a> BlockReader<'a> {
+    /// `version` identifies the contents of the file, e.g., the checksum of its entry, so that
+    /// blocks of a file replaced in place are not served from the cache.
+    fn new(path: &'a Path, version: u64) -> Self {
+        BlockReader {
+            path,
+            file: xxh3_64_with_seed(path.as_os_str().as_encoded_bytes(), version),
+            reader: None,
+        }
+    }
+
+    /// The key of the block at the compressed `offset`.
+    fn key(&self, offset: u64) -> u64 {
+        // Keys are never 0, which marks empty slots
+        xxh3_64_with_seed(&offset.to_le_bytes(), self.file) | 1
+    }
+
+    /// Append the block at the compressed `offset` to `block` and return the offset of the
+    /// following block. At the end of the file, nothing is appended.
+    fn read(&mut self, offset: u64, block: &mut Vec<u8>) -> Result<u64> {
+        if self.reader.is_none() {
+            let path = self.path;
+            self.reader = Some(with_retry(path, || Ok(BgzfReader::new(open_data(path)?)))?);
+        }
+        let reader = self.reader.as_mut().unwrap();
+        reader.seek_to_virtual_position(VirtualPosition::from(offset << 16))?;
+        let len = block.len();
+        block.extend_from_slice(reader.fill_buf()?);
+        reader.consume(block.len() - len);
+        Ok(reader.virtual_position().compressed())
+    }
+}
+
+/// Read the `len` uncompressed bytes of `path` starting at `pos`, e.g., of a region planned by
+/// `ArchivedFastaMap::plan`, from the blocks of `store` where possible. Blocks are stored per
+/// `version` of the file, see `BlockReader::new`. Also returns the offset of the block
+/// following the last block read, e.g., to prefetch from there.
+pub(crate) fn read_raw(
+    store: &dyn BlockStore,
+    path: &Path,
+    version: u64,
+    pos: VirtualPosition,
+    len: u64,
+) -> Result<(Vec<u8>, u64)> {
+    let mut reader = BlockReader::new(path, version);
+    let len = len as usize;
+    let mut raw = Vec::with_capacity(len);
+    let mut block = Vec::with_capacity(MAX_BLOCK_SIZE);
+    let (mut offset, mut skip) = (pos.compressed(), pos.uncompressed() as usize);
+    while raw.len() < len {
+        let key = reader.key(offset);
+        block.clear();
+        let next_offset = match store.get(key, &mut block) {
+            Some(next_offset) => next_offset,
+            None => {
+                let next_offset = reader.read(offset, &mut block)?;
+                store.insert(key, &block, next_offset);
+                next_offset
+            }
+        };
+        if block.is_empty() {
+            bail!("End of file reached before reading {} bytes", len);
+        }
+        let block = block.get(skip..).unwrap_or_default();
+        raw.extend_from_slice(&block[..block.len().min(len - raw.len())]);
+        (offset, skip) = (next_offset, 0);
+    }
+    Ok((raw, offset))
+}
+
+/// Decompress up to `num_blocks` blocks of `path` starting at the compressed `offset` into
+/// `store`, skipping blocks which are already stored, until the end of the file.
+pub(crate) fn prefetch(
+    store: &dyn BlockStore,
+    path: &Path,
+    version: u64,
+    mut offset: u64,
+    num_blocks: usize,
+) -> Result<()> {
+    let mut reader = BlockReader::new(path, version);
+    let mut block = Vec::with_capacity(MAX_BLOCK_SIZE);
+    for _ in 0..num_blocks {
+        let key = reader.key(offset);
+        if let Some(next_offset) = store.next_offset(key) {
+            offset = next_offset;
+            continue;
+        }
+        block.clear();
+        let next_offset = reader.read(offset, &mut block)?;
+        if block.is_empty() {
+            break;
+        }
+        store.insert(key, &block, next_offset);
+        offset = next_offset;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use noodles::bgzf;
+
+    use super::*;
+
+    #[test]
+    fn test_shared_block_cache() {
+        let cache = SharedBlockCache::new(MIN_SIZE).unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(cache.get(1, &mut buf), None);
+        cache.insert(1, b"ACGT", 42);
+        assert_eq!(cache.get(1, &mut buf), Some(42));
+        assert_eq!(buf, b"ACGT");
+
+        // The least recently used block is evicted from a full shard
+        for key in 2..=SLOTS_PER_SHARD as u64 {
+            cache.insert(key, b"N", 0);
+        }
+        assert!(cache.get(1, &mut buf).is_some());
+        cache.insert(100, b"A", 0);
+        assert!(cache.get(2, &mut buf).is_none());
+        assert!(cache.get(1, &mut buf).is_some());
+
+        let attached = SharedBlockCache::import(cache.export()).unwrap();
+        buf.clear();
+        assert_eq!(attached.get(100, &mut buf), Some(0));
+        assert_eq!(buf, b"A");
+        let (size, hits, misses) = cache.stats();
+        assert_eq!((size, hits, misses), (MIN_SIZE, 4, 2));
+        assert!(SharedBlockCache::new(MIN_SIZE - 1).is_err());
+    }
+
+    #[test]
+    fn test_abandoned_lock() {
+        let cache = SharedBlockCache::new(MIN_SIZE).unwrap();
+        let mut buf = Vec::new();
+        cache.insert(1, b"ACGT", 42);
+        let lock = unsafe { &*addr_of!((*cache.shard(1)).lock) };
+
+        // A lock held by a process which died is taken over, dropping its blocks
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .arg("--help")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        lock.store(dead, Ordering::Release);
+        assert_eq!(cache.get(1, &mut buf), None);
+        assert_eq!(lock.load(Ordering::Acquire), 0);
+        cache.insert(1, b"ACGT", 42);
+        assert_eq!(cache.get(1, &mut buf), Some(42));
+
+        // A lock held by a live process is given up on after the timeout
+        lock.store(std::process::id(), Ordering::Release);
+        let start = Instant::now();
+        assert_eq!(cache.get(1, &mut buf), None);
+        cache.insert(2, b"A", 0);
+        assert!(start.elapsed() >= LOCK_TIMEOUT);
+        lock.store(0, Ordering::Release);
+        assert_eq!(cache.get(1, &mut buf), Some(42));
+    }
+
+    #[test]
+    fn test_read_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.fna.gz");
+        let mut writer = bgzf::io::Writer::new(std::fs::File::create(&path).unwrap());
+        std::io::Write::write_all(&mut writer, b">a\nACGT\nTTGG\nCC\n").unwrap();
+        writer.finish().unwrap();
+        let cache = SharedBlockCache::new(MIN_SIZE).unwrap();
+        for _ in 0..2 {
+            let pos = VirtualPosition::from(4);
+            let (raw, _) = read_raw(&cache, &path, 1, pos, 8).unwrap();
+            assert_eq!(raw, b"CGT\nTTGG");
+        }
+        let (_, hits, misses) = cache.stats();
+        assert_eq!((hits, misses), (1, 1));
+        assert!(read_raw(&cache, &path, 1, VirtualPosition::from(3), 20).is_err());
+
+        // Prefetched blocks are read without misses
+        let cache = SharedBlockCache::new(MIN_SIZE).unwrap();
+        prefetch(&cache, &path, 1, 0, 4).unwrap();
+        assert!(cache
+            .next_offset(BlockReader::new(&path, 1).key(0))
+            .is_some());
+        read_raw(&cache, &path, 1, VirtualPosition::from(4), 8).unwrap();
+        let (_, hits, misses) = cache.stats();
+        assert_eq!((hits, misses), (1, 0));
+
+        // A file replaced in place has another version and misses the blocks of the old one
+        assert!(cache
+            .next_offset(BlockReader::new(&path, 2).key(0))
+            .is_none());
+        read_raw(&cache, &path, 2, VirtualPosition::from(4), 8).unwrap();
+        let (_, hits, misses) = cache.stats();
+        assert_eq!((hits, misses), (1, 1));
+    }
+}
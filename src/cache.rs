@@ -1,83 +1,466 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::index::{FastaMap, TrackMap};
+use crate::error::Error;
+use crate::index::{
+    ArchivedMap, ArchivedShardDirectory, ConflictPolicy, FastaMap, ShardDirectory, TrackMap,
+    FASTA_INDEX_SUFFIXES, TRACK_INDEX_SUFFIXES,
+};
+use crate::progress::Progress;
+use crate::remote;
 use crate::storage::{
-    type_specific_magic, write_direct, ArchiveStorage, DynamicStorage, MemoryStorage, MmapStorage,
-    ShmemStorage,
+    directory_path, shard_path, shmem_available, type_specific_magic, write_direct, ArchiveStorage,
+    ChecksumAlgorithm, DynamicStorage, LazyStorage, LoadableStorage, MemoryStorage, MmapStorage,
+    Rejection, Schema, ShardedStorage, ShmemStorage,
 };
+use crate::util::{NameSelection, PathFilter};
 use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+use rkyv::api::high::HighValidator;
+use rkyv::bytecheck::CheckBytes;
+use rkyv::de::Pool;
 use rkyv::ser::writer::IoWriter;
 use rkyv::util::AlignedVec;
-use rkyv::Serialize;
 use rkyv::{rancor, Portable};
+use rkyv::{Archive, Serialize};
 use std::fs::File;
 use std::io::BufWriter;
 
-pub(crate) trait MapBuilder {
+pub(crate) trait MapBuilder: Archive + Schema {
+    /// Suffix of the data files, from which the names are discovered.
+    const SUFFIX: &'static str;
+    /// Suffixes of the index files next to each data file.
+    const INDEX_SUFFIXES: &'static [&'static str];
+
+    /// With `digests` and `composition`, the sequence digests and base counts of all contigs
+    /// are computed, and with `check_indices`, the index files are checked, see `FastaMap`.
+    #[allow(clippy::too_many_arguments)]
     fn build(
         dir: &str,
         strict: bool,
         min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
         num_workers: Option<usize>,
-        show_progress: bool,
-        names_list: Option<Vec<String>>,
+        progress: &Progress,
+        selection: &NameSelection,
     ) -> Result<Self>
     where
         Self: Sized;
+
+    /// Build the map and stream it to the cache file at `path` with bounded memory.
+    /// With `resume`, an interrupted build continues from its checkpoint.
+    #[allow(clippy::too_many_arguments)]
+    fn build_to_file(
+        dir: &str,
+        strict: bool,
+        min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
+        num_workers: Option<usize>,
+        progress: &Progress,
+        selection: &NameSelection,
+        path: &Path,
+        algorithm: ChecksumAlgorithm,
+        resume: bool,
+    ) -> Result<()>;
+
+    /// Returns a reason if the cached map no longer matches the selected files in `dir`.
+    fn stale_reason(
+        archived: &Self::Archived,
+        dir: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>>;
+
+    /// Returns a reason if the cached map does not match what `options` request, e.g., it was
+    /// built with another `min_contig_length` or without digests, so that it has to be rebuilt.
+    fn options_reason(archived: &Self::Archived, options: &LoadOptions) -> Option<String>;
+
+    /// Names of entries whose contents do not match their checksum.
+    fn damaged_entries(archived: &Self::Archived) -> Vec<String>;
+
+    /// Re-index the given entries in place, with the options the map was built with.
+    fn rebuild_entries(&mut self, dir: &str, names: &[String]) -> Result<()>;
+
+    /// Index the given entries, which may be new, see `FastaMap::add_entries`.
+    fn add_entries(&mut self, dir: &str, names: &[String]) -> Result<()>;
+
+    /// Drop the given entries.
+    fn remove_entries(&mut self, names: &[String]) -> Result<()>;
+
+    /// Validate a map built on another machine against the sizes of the index files in `dir`.
+    fn localize(&mut self, dir: &str) -> Result<()>;
+
+    /// Combine two maps built from the same root.
+    fn merge(self, other: Self, policy: ConflictPolicy) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Split the map into shards and a directory listing all names.
+    fn into_shards(self, num_shards: usize) -> (ShardDirectory, Vec<Self>)
+    where
+        Self: Sized;
+
+    /// Returns a reason if a sharded cache no longer matches the files in `dir`.
+    fn directory_stale_reason(
+        directory: &ArchivedShardDirectory,
+        dir: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>>;
 }
 
 impl MapBuilder for FastaMap {
+    const SUFFIX: &'static str = ".fna.gz";
+    const INDEX_SUFFIXES: &'static [&'static str] = FASTA_INDEX_SUFFIXES;
+
     fn build(
         dir: &str,
         strict: bool,
         min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
         num_workers: Option<usize>,
-        show_progress: bool,
-        names_list: Option<Vec<String>>,
+        progress: &Progress,
+        selection: &NameSelection,
     ) -> Result<Self> {
         FastaMap::build(
             dir,
             strict,
             min_contig_length,
+            digests,
+            composition,
+            check_indices,
             num_workers,
-            show_progress,
-            names_list,
+            progress,
+            selection,
         )
     }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_to_file(
+        dir: &str,
+        strict: bool,
+        min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
+        num_workers: Option<usize>,
+        progress: &Progress,
+        selection: &NameSelection,
+        path: &Path,
+        algorithm: ChecksumAlgorithm,
+        resume: bool,
+    ) -> Result<()> {
+        FastaMap::build_to_file(
+            dir,
+            strict,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
+            num_workers,
+            progress,
+            selection,
+            path,
+            algorithm,
+            resume,
+        )
+    }
+
+    fn stale_reason(
+        archived: &Self::Archived,
+        dir: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>> {
+        archived.stale_reason(dir, selection)
+    }
+
+    fn options_reason(archived: &Self::Archived, options: &LoadOptions) -> Option<String> {
+        archived.options_reason(
+            options.min_contig_length,
+            options.digests,
+            options.composition,
+            options.check_indices,
+        )
+    }
+
+    fn damaged_entries(archived: &Self::Archived) -> Vec<String> {
+        archived.damaged_entries()
+    }
+
+    fn rebuild_entries(&mut self, dir: &str, names: &[String]) -> Result<()> {
+        self.rebuild_entries(dir, names)
+    }
+
+    fn add_entries(&mut self, dir: &str, names: &[String]) -> Result<()> {
+        self.add_entries(dir, names)
+    }
+
+    fn remove_entries(&mut self, names: &[String]) -> Result<()> {
+        self.remove_entries(names)
+    }
+
+    fn localize(&mut self, dir: &str) -> Result<()> {
+        self.localize(dir)
+    }
+
+    fn merge(self, other: Self, policy: ConflictPolicy) -> Result<Self> {
+        self.merge(other, policy)
+    }
+
+    fn into_shards(self, num_shards: usize) -> (ShardDirectory, Vec<Self>) {
+        self.into_shards(num_shards)
+    }
+
+    fn directory_stale_reason(
+        directory: &ArchivedShardDirectory,
+        dir: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>> {
+        FastaMap::directory_stale_reason(directory, dir, selection)
+    }
 }
 
 impl MapBuilder for TrackMap {
+    const SUFFIX: &'static str = ".track.gz";
+    const INDEX_SUFFIXES: &'static [&'static str] = TRACK_INDEX_SUFFIXES;
+
     fn build(
         dir: &str,
         strict: bool,
         min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
         num_workers: Option<usize>,
-        show_progress: bool,
-        names_list: Option<Vec<String>>,
+        progress: &Progress,
+        selection: &NameSelection,
     ) -> Result<Self> {
+        if digests || composition || check_indices {
+            bail!("Digests, composition and index checks are only supported for FASTA files");
+        }
         TrackMap::build(
             dir,
             strict,
             min_contig_length,
             num_workers,
-            show_progress,
-            names_list,
+            progress,
+            selection,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_to_file(
+        dir: &str,
+        strict: bool,
+        min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
+        num_workers: Option<usize>,
+        progress: &Progress,
+        selection: &NameSelection,
+        path: &Path,
+        algorithm: ChecksumAlgorithm,
+        resume: bool,
+    ) -> Result<()> {
+        if digests || composition || check_indices {
+            bail!("Digests, composition and index checks are only supported for FASTA files");
+        }
+        TrackMap::build_to_file(
+            dir,
+            strict,
+            min_contig_length,
+            num_workers,
+            progress,
+            selection,
+            path,
+            algorithm,
+            resume,
         )
     }
+
+    fn stale_reason(
+        archived: &Self::Archived,
+        dir: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>> {
+        archived.stale_reason(dir, selection)
+    }
+
+    fn options_reason(archived: &Self::Archived, options: &LoadOptions) -> Option<String> {
+        // Digests, composition and index checks are rejected for tracks when building
+        archived.options_reason(options.min_contig_length)
+    }
+
+    fn damaged_entries(archived: &Self::Archived) -> Vec<String> {
+        archived.damaged_entries()
+    }
+
+    fn rebuild_entries(&mut self, dir: &str, names: &[String]) -> Result<()> {
+        self.rebuild_entries(dir, names)
+    }
+
+    fn add_entries(&mut self, dir: &str, names: &[String]) -> Result<()> {
+        self.add_entries(dir, names)
+    }
+
+    fn remove_entries(&mut self, names: &[String]) -> Result<()> {
+        self.remove_entries(names)
+    }
+
+    fn localize(&mut self, dir: &str) -> Result<()> {
+        self.localize(dir)
+    }
+
+    fn merge(self, other: Self, policy: ConflictPolicy) -> Result<Self> {
+        self.merge(other, policy)
+    }
+
+    fn into_shards(self, num_shards: usize) -> (ShardDirectory, Vec<Self>) {
+        self.into_shards(num_shards)
+    }
+
+    fn directory_stale_reason(
+        directory: &ArchivedShardDirectory,
+        dir: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>> {
+        TrackMap::directory_stale_reason(directory, dir, selection)
+    }
 }
 
-#[allow(clippy::too_many_arguments)]
+/// Name of the cache of a `TrackMap` in its root.
+pub(crate) const TRACK_CACHE_NAME: &str = ".track-map-cache";
+
+/// Name of the cache of a `FastaMap` in its root. Maps with digests or composition are cached
+/// separately, as building them reads all sequences.
+pub(crate) fn fasta_cache_name(options: &LoadOptions) -> String {
+    format!(
+        ".fasta-map{}{}-cache",
+        if options.digests { "-digest" } else { "" },
+        if options.composition {
+            "-composition"
+        } else {
+            ""
+        },
+    )
+}
+
+/// How `load` builds, caches and stores a map, shared by all roots of a loader.
+#[derive(Clone, Debug)]
+pub(crate) struct LoadOptions {
+    pub(crate) strict: bool,
+    pub(crate) force_build: bool,
+    pub(crate) no_cache: bool,
+    pub(crate) min_contig_length: u64,
+    pub(crate) digests: bool,
+    pub(crate) composition: bool,
+    pub(crate) check_indices: bool,
+    pub(crate) num_workers: Option<usize>,
+    pub(crate) storage_method: String,
+    /// Glob patterns of paths relative to the root, see `PathFilter`.
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+    /// Whether files below the root are found through symbolic links as well.
+    pub(crate) follow_symlinks: bool,
+    pub(crate) checksum: String,
+    pub(crate) verify_checksum: bool,
+    pub(crate) repair: bool,
+    pub(crate) num_shards: Option<usize>,
+    pub(crate) lazy: bool,
+    /// A bundle installed as the cache instead of building it, see `import_bundle`.
+    pub(crate) bundle: Option<PathBuf>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            strict: true,
+            force_build: false,
+            no_cache: false,
+            min_contig_length: 0,
+            digests: false,
+            composition: false,
+            check_indices: false,
+            num_workers: None,
+            storage_method: "mmap".to_string(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            follow_symlinks: true,
+            checksum: "xxh3".to_string(),
+            verify_checksum: true,
+            repair: true,
+            num_shards: None,
+            lazy: false,
+            bundle: None,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// Reject unknown values and incompatible combinations before loading anything.
+    pub(crate) fn validate(&self) -> std::result::Result<(), Error> {
+        let invalid = |message: String| Err(Error::InvalidOptions(message));
+        let storage_method = self.storage_method.as_str();
+        if !matches!(storage_method, "memory" | "shmem" | "mmap" | "auto") {
+            return invalid(format!("Unknown storage method: {}", storage_method));
+        }
+        if let Err(e) = ChecksumAlgorithm::parse(&self.checksum) {
+            return invalid(e.to_string());
+        }
+        if self.bundle.is_some()
+            && (self.lazy || self.no_cache || self.force_build || self.num_shards.is_some())
+        {
+            return invalid(
+                "A bundle cannot be combined with lazy, no_cache, force_build or num_shards"
+                    .to_string(),
+            );
+        }
+        if self.lazy {
+            if self.num_shards.is_some() {
+                return invalid("lazy=true cannot be combined with num_shards".to_string());
+            }
+            // The remaining options only apply to building and caching
+            return Ok(());
+        }
+        if !self.strict && !self.no_cache {
+            return invalid("strict=false requires no_cache=true".to_string());
+        }
+        if self.no_cache && self.force_build {
+            return invalid("no_cache=true already implies force_build=true".to_string());
+        }
+        if self.no_cache && (storage_method == "mmap" || storage_method == "auto") {
+            return invalid(format!(
+                "storage_method={} requires no_cache=false",
+                storage_method
+            ));
+        }
+        if let Some(num_shards) = self.num_shards {
+            if num_shards == 0 {
+                return invalid("num_shards must be positive".to_string());
+            }
+            if self.no_cache {
+                return invalid("num_shards requires no_cache=false".to_string());
+            }
+            // Shards are always memory-mapped
+            if storage_method != "mmap" && storage_method != "auto" {
+                return invalid("num_shards requires storage_method=mmap".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Load the map of the files below `dir`, or of `names` only, as configured by `options`.
 pub(crate) fn load<T>(
     dir: &str,
     cache_file_name: &str,
-    strict: bool,
-    min_contig_length: u64,
-    num_workers: Option<usize>,
-    show_progress: bool,
-    storage_method: &str,
-    no_cache: bool,
-    force_build: bool,
     names: Option<Vec<String>>,
+    options: &LoadOptions,
+    progress: &Progress,
 ) -> Result<DynamicStorage<T>>
 where
     // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
@@ -103,72 +486,137 @@ where
     >,
     T: Sync + Send,
     T::Archived: 'static + Portable + Send + Sync,
+    T::Archived: for<'v> CheckBytes<HighValidator<'v, rancor::Error>>
+        + rkyv::Deserialize<T, rancor::Strategy<Pool, rancor::Error>>,
     T: MapBuilder + 'static,
+    T::Archived: ArchivedMap,
 {
-    if !strict && !no_cache {
-        bail!("strict=false requires no_cache=true");
-    }
-    if no_cache && force_build {
-        bail!("no_cache=true already implies force_build=true");
-    }
-    if no_cache && storage_method == "mmap" {
-        bail!("storage_method=mmap requires no_cache=false");
+    options.validate()?;
+    let LoadOptions {
+        strict,
+        force_build,
+        no_cache,
+        min_contig_length,
+        digests,
+        composition,
+        check_indices,
+        num_workers,
+        verify_checksum,
+        repair,
+        num_shards,
+        lazy,
+        ..
+    } = *options;
+    let storage_method = options.storage_method.as_str();
+    // A remote root is indexed in a local mirror of its index files
+    let mirror_dir;
+    let dir = if remote::is_remote(dir) {
+        if digests || composition || check_indices {
+            return Err(Error::InvalidOptions(
+                "Digests, composition and index checks are not supported for remote roots"
+                    .to_string(),
+            )
+            .into());
+        }
+        mirror_dir = remote::mirror(dir, T::SUFFIX, T::INDEX_SUFFIXES)?;
+        mirror_dir
+            .to_str()
+            .context("The mirror directory is not valid UTF-8")?
+    } else {
+        dir
+    };
+    let selection = NameSelection::new(
+        names,
+        PathFilter::new(&options.include, &options.exclude)?,
+        options.follow_symlinks,
+    );
+    if lazy {
+        // Only the names are discovered now, each entry is indexed on its first access
+        let names = selection.resolve(dir, T::SUFFIX)?;
+        let dir = dir.to_string();
+        let storage = LazyStorage::new(names, move |name| {
+            let selection =
+                NameSelection::new(Some(vec![name.to_string()]), PathFilter::default(), true);
+            T::build(
+                &dir,
+                true,
+                min_contig_length,
+                digests,
+                composition,
+                check_indices,
+                None,
+                &Progress::default(),
+                &selection,
+            )
+        });
+        return Ok(storage.into());
     }
-    if names.is_some() && !no_cache {
-        bail!("names_list can only be used with no_cache=true");
+    let checksum_algorithm = ChecksumAlgorithm::parse(&options.checksum)?;
+    let cache_path = match selection.key() {
+        None => Path::new(dir).join(format!(
+            "{}-{:016x}",
+            cache_file_name,
+            type_specific_magic::<T>()
+        )),
+        // A subset of files gets its own cache file, keyed by the hash of the selection
+        Some(key) => Path::new(dir).join(format!(
+            "{}-{:016x}-{:016x}",
+            cache_file_name,
+            type_specific_magic::<T>(),
+            key
+        )),
+    };
+    if let Some(num_shards) = num_shards {
+        if !force_build {
+            if let Some(storage) = load_sharded::<T>(&cache_path, dir, &selection, options)? {
+                return Ok(storage.into());
+            }
+        }
+        let map = T::build(
+            dir,
+            strict,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
+            num_workers,
+            progress,
+            &selection,
+        )?;
+        write_sharded(map, &cache_path, num_shards, checksum_algorithm)?;
+        return Ok(ShardedStorage::open(&cache_path, verify_checksum)?.into());
     }
-    let cache_path = Path::new(dir).join(format!(
-        "{}-{:016x}",
-        cache_file_name,
-        type_specific_magic::<T>()
-    ));
     if cache_path.exists() && !no_cache && !force_build {
-        if storage_method == "memory" {
-            match ArchiveStorage::<T, MemoryStorage>::load(&cache_path)
-                .context(format!("Error reading cache {}", cache_path.display()))?
-            {
-                Some(archive) => {
-                    return Ok(archive.into());
-                }
-                None => {
-                    eprintln!("Cache file {} is corrupted.", cache_path.display());
-                }
-            }
-        } else if storage_method == "shmem" {
-            match ArchiveStorage::<T, ShmemStorage>::load(&cache_path)
-                .context(format!("Error reading cache {}", cache_path.display()))?
-            {
-                Some(archive) => {
-                    return Ok(archive.into());
-                }
-                None => {
-                    eprintln!("Cache file {} is corrupted.", cache_path.display());
-                }
-            }
-        } else if storage_method == "mmap" {
-            match ArchiveStorage::<T, MmapStorage>::load(&cache_path)
-                .context(format!("Error reading cache {}", cache_path.display()))?
-            {
-                Some(archive) => {
-                    return Ok(archive.into());
-                }
-                None => {
-                    eprintln!("Cache file {} is corrupted.", cache_path.display());
-                }
-            }
-        } else {
-            bail!("Unknown storage method: {}", storage_method);
+        let storage_method = resolve_storage_method(storage_method, &cache_path)?;
+        let options = CachedOptions {
+            selection: &selection,
+            requested: options,
+            checksum_algorithm,
+            verify_checksum,
+            repair,
+        };
+        let cached = match storage_method {
+            "memory" => load_cached::<T, MemoryStorage>(&cache_path, dir, &options)?,
+            "shmem" => load_cached::<T, ShmemStorage>(&cache_path, dir, &options)?,
+            "mmap" => load_cached::<T, MmapStorage>(&cache_path, dir, &options)?,
+            _ => bail!("Unknown storage method: {}", storage_method),
+        };
+        if let Some(storage) = cached {
+            return Ok(storage);
         }
     }
-    let map = T::build(
-        dir,
-        strict,
-        min_contig_length,
-        num_workers,
-        show_progress,
-        names,
-    )?;
     if no_cache {
+        let map = T::build(
+            dir,
+            strict,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
+            num_workers,
+            progress,
+            &selection,
+        )?;
         if storage_method == "memory" {
             let archive = ArchiveStorage::<T, MemoryStorage>::new(map)
                 .context("Error creating memory storage archive")?;
@@ -183,22 +631,923 @@ where
             bail!("Unknown storage method: {}", storage_method);
         }
     }
-    eprintln!("Writing cache to {}", cache_path.display());
-    write_direct(&map, &cache_path)?;
-    std::mem::drop(map);
+    // Instead of building, a missing or outdated cache file is replaced by the bundle
+    if let Some(bundle) = &options.bundle {
+        import_bundle::<T>(
+            bundle,
+            dir,
+            &selection,
+            options,
+            &cache_path,
+            checksum_algorithm,
+        )?;
+    } else {
+        info!("Writing cache to {}", cache_path.display());
+        T::build_to_file(
+            dir,
+            strict,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
+            num_workers,
+            progress,
+            &selection,
+            &cache_path,
+            checksum_algorithm,
+            !force_build,
+        )?;
+    }
+    let storage_method = resolve_storage_method(storage_method, &cache_path)?;
     if storage_method == "memory" {
-        let archive = ArchiveStorage::<T, MemoryStorage>::load(&cache_path)?
-            .ok_or(anyhow!("Newly written cache is corrupted!"))?;
+        let archive = ArchiveStorage::<T, MemoryStorage>::load(&cache_path, verify_checksum)?
+            .map_err(|rejection| {
+                Error::CacheCorrupted(format!("Newly written cache is rejected: {}", rejection))
+            })?;
         Ok(archive.into())
     } else if storage_method == "shmem" {
-        let archive = ArchiveStorage::<T, ShmemStorage>::load(&cache_path)?
-            .ok_or(anyhow!("Newly written cache is corrupted!"))?;
+        let archive = ArchiveStorage::<T, ShmemStorage>::load(&cache_path, verify_checksum)?
+            .map_err(|rejection| {
+                Error::CacheCorrupted(format!("Newly written cache is rejected: {}", rejection))
+            })?;
         Ok(archive.into())
     } else if storage_method == "mmap" {
-        let archive = ArchiveStorage::<T, MmapStorage>::load(&cache_path)?
-            .ok_or(anyhow!("Newly written cache is corrupted!"))?;
+        let archive = ArchiveStorage::<T, MmapStorage>::load(&cache_path, verify_checksum)?
+            .map_err(|rejection| {
+                Error::CacheCorrupted(format!("Newly written cache is rejected: {}", rejection))
+            })?;
         Ok(archive.into())
     } else {
         bail!("Unknown storage method: {}", storage_method);
     }
 }
+
+/// Space left free in `/dev/shm` by `storage_method=auto`, e.g., for other shared memory.
+const SHMEM_HEADROOM: u64 = 256 << 20;
+
+/// Resolve `storage_method=auto` for the cache file at `cache_path`: shmem if it fits into
+/// `/dev/shm`, and mmap otherwise. Other methods are returned unchanged.
+fn resolve_storage_method<'a>(storage_method: &'a str, cache_path: &Path) -> Result<&'a str> {
+    if storage_method != "auto" {
+        return Ok(storage_method);
+    }
+    let size = std::fs::metadata(cache_path)?.len();
+    let available = shmem_available();
+    let method = match available {
+        Some(available) if size.saturating_add(SHMEM_HEADROOM) <= available => "shmem",
+        _ => "mmap",
+    };
+    info!(
+        "storage_method=auto chose {} for {} bytes with {} free for shared memory",
+        method,
+        size,
+        available.map_or("unknown".to_string(), |available| format!(
+            "{} bytes",
+            available
+        ))
+    );
+    Ok(method)
+}
+
+/// Options needed to load and possibly repair an existing cache file.
+struct CachedOptions<'a> {
+    selection: &'a NameSelection,
+    /// Options of the load, which the cached map must satisfy, see `MapBuilder::options_reason`.
+    requested: &'a LoadOptions,
+    checksum_algorithm: ChecksumAlgorithm,
+    verify_checksum: bool,
+    repair: bool,
+}
+
+/// Load a cache file, returning `None` if it is rejected or stale.
+fn load_cached<T, S>(
+    cache_path: &Path,
+    dir: &str,
+    options: &CachedOptions,
+) -> Result<Option<DynamicStorage<T>>>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T: Sync + Send,
+    T::Archived: 'static + Portable + Send + Sync,
+    T::Archived: for<'v> CheckBytes<HighValidator<'v, rancor::Error>>
+        + rkyv::Deserialize<T, rancor::Strategy<Pool, rancor::Error>>,
+    T: MapBuilder + 'static,
+    S: LoadableStorage + 'static,
+    ArchiveStorage<T, S>: Into<DynamicStorage<T>>,
+{
+    let archive = match ArchiveStorage::<T, S>::load(cache_path, options.verify_checksum)
+        .context(format!("Error reading cache {}", cache_path.display()))?
+    {
+        Ok(archive) => archive,
+        Err(rejection @ Rejection::Checksum { .. }) => {
+            warn!(
+                "Cache file {} rejected ({}), checking entries.",
+                cache_path.display(),
+                rejection
+            );
+            match repair_cache::<T>(cache_path, dir, options) {
+                Ok(true) => match ArchiveStorage::<T, S>::load(cache_path, false)? {
+                    Ok(archive) => archive,
+                    Err(rejection) => {
+                        warn!("Repaired cache rejected ({}), rebuilding.", rejection);
+                        return Ok(None);
+                    }
+                },
+                Ok(false) => {
+                    warn!("Rebuilding cache file {}.", cache_path.display());
+                    return Ok(None);
+                }
+                Err(e) => {
+                    warn!("Repairing cache failed, rebuilding. Error: {:?}", e);
+                    return Ok(None);
+                }
+            }
+        }
+        Err(rejection) => {
+            warn!(
+                "Cache file {} rejected ({}), rebuilding.",
+                cache_path.display(),
+                rejection
+            );
+            return Ok(None);
+        }
+    };
+    if let Some(reason) = T::options_reason(archive.as_ref(), options.requested) {
+        info!(
+            "Cache file {} was built with other options ({}), rebuilding.",
+            cache_path.display(),
+            reason
+        );
+        return Ok(None);
+    }
+    if let Some(reason) = T::stale_reason(archive.as_ref(), dir, options.selection)? {
+        info!(
+            "Cache file {} is outdated ({}), rebuilding.",
+            cache_path.display(),
+            reason
+        );
+        return Ok(None);
+    }
+    Ok(Some(archive.into()))
+}
+
+/// Open a sharded cache with the `num_shards` of `options`, returning `None` if it is missing,
+/// incomplete, stale or built with other options.
+fn load_sharded<T>(
+    cache_path: &Path,
+    dir: &str,
+    selection: &NameSelection,
+    options: &LoadOptions,
+) -> Result<Option<ShardedStorage<T>>>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T: Sync + Send,
+    T::Archived: 'static + Portable + Send + Sync,
+    T::Archived: for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
+    T: MapBuilder + 'static,
+{
+    let Some(num_shards) = options.num_shards else {
+        return Ok(None);
+    };
+    if !directory_path(cache_path).exists() {
+        return Ok(None);
+    }
+    let storage = match ShardedStorage::<T>::open(cache_path, options.verify_checksum) {
+        Ok(storage) => storage,
+        Err(e) => {
+            warn!("Sharded cache rejected, rebuilding. Error: {:?}", e);
+            return Ok(None);
+        }
+    };
+    if storage.directory()?.num_shards() != num_shards {
+        info!(
+            "Sharded cache has {} shards instead of {}, rebuilding.",
+            storage.directory()?.num_shards(),
+            num_shards
+        );
+        return Ok(None);
+    }
+    if let Some(missing) = (0..num_shards)
+        .map(|shard| shard_path(cache_path, shard))
+        .find(|path| !path.exists())
+    {
+        warn!("Shard {} is missing, rebuilding.", missing.display());
+        return Ok(None);
+    }
+    // All shards are built with the same options
+    let shard = match storage.shard(0) {
+        Ok(shard) => shard,
+        Err(e) => {
+            warn!("Shard 0 rejected, rebuilding. Error: {:?}", e);
+            return Ok(None);
+        }
+    };
+    if let Some(reason) = T::options_reason(shard, options) {
+        info!(
+            "Sharded cache was built with other options ({}), rebuilding.",
+            reason
+        );
+        return Ok(None);
+    }
+    if let Some(reason) = T::directory_stale_reason(storage.directory()?, dir, selection)? {
+        info!("Sharded cache is outdated ({}), rebuilding.", reason);
+        return Ok(None);
+    }
+    Ok(Some(storage))
+}
+
+/// Write a map as a sharded cache. The directory is written last so that it marks completion.
+fn write_sharded<T>(
+    map: T,
+    cache_path: &Path,
+    num_shards: usize,
+    checksum_algorithm: ChecksumAlgorithm,
+) -> Result<()>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T::Archived: 'static + Portable,
+    T: MapBuilder,
+{
+    let directory_path = directory_path(cache_path);
+    if directory_path.exists() {
+        std::fs::remove_file(&directory_path)?;
+    }
+    let (directory, shards) = map.into_shards(num_shards);
+    info!(
+        "Writing {} shards to {}.shard-*",
+        num_shards,
+        cache_path.display()
+    );
+    for (i, shard) in shards.into_iter().enumerate() {
+        write_direct(&shard, &shard_path(cache_path, i), checksum_algorithm)?;
+    }
+    // Remove leftover shards from a previous build with more shards
+    let mut i = num_shards;
+    while shard_path(cache_path, i).exists() {
+        std::fs::remove_file(shard_path(cache_path, i))?;
+        i += 1;
+    }
+    write_direct(&directory, &directory_path, checksum_algorithm)?;
+    Ok(())
+}
+
+/// Import a bundle exported on another machine, see `Roots::export_bundle`, as the cache file
+/// at `cache_path`. The bundle must contain exactly the selected names, the sizes of their
+/// index files must match, and it must be built with the `options` of the load.
+fn import_bundle<T>(
+    bundle: &Path,
+    dir: &str,
+    selection: &NameSelection,
+    options: &LoadOptions,
+    cache_path: &Path,
+    checksum_algorithm: ChecksumAlgorithm,
+) -> Result<()>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T: Sync + Send,
+    T::Archived: 'static + Portable + Send + Sync,
+    T::Archived: for<'v> CheckBytes<HighValidator<'v, rancor::Error>>
+        + rkyv::Deserialize<T, rancor::Strategy<Pool, rancor::Error>>,
+    T: MapBuilder + 'static,
+    T::Archived: ArchivedMap,
+{
+    let archive = ArchiveStorage::<T, MemoryStorage>::load(bundle, true)
+        .with_context(|| format!("Error reading bundle {}", bundle.display()))?
+        .map_err(|rejection| anyhow!("Bundle {} is rejected: {}", bundle.display(), rejection))?;
+    let mut selected = selection.resolve(dir, T::SUFFIX)?;
+    selected.sort();
+    let bundled = archive.as_ref().names();
+    if let Some(name) = selected
+        .iter()
+        .find(|name| !archive.as_ref().contains(name))
+    {
+        bail!("Bundle {} does not contain {}", bundle.display(), name);
+    }
+    if bundled.len() != selected.len() {
+        let name = bundled
+            .iter()
+            .find(|name| {
+                selected
+                    .binary_search_by(|s| s.as_str().cmp(*name))
+                    .is_err()
+            })
+            .unwrap();
+        bail!(
+            "Bundle {} contains {}, which is not selected",
+            bundle.display(),
+            name
+        );
+    }
+    if let Some(reason) = T::options_reason(archive.as_ref(), options) {
+        bail!(
+            "Bundle {} was built with other options ({})",
+            bundle.display(),
+            reason
+        );
+    }
+    let mut map = rkyv::deserialize::<T, rancor::Error>(archive.as_ref())?;
+    map.localize(dir)
+        .with_context(|| format!("Bundle {} does not match {}", bundle.display(), dir))?;
+    info!(
+        "Importing bundle {} to {}",
+        bundle.display(),
+        cache_path.display()
+    );
+    write_direct(&map, cache_path, checksum_algorithm)
+}
+
+/// Locate damaged entries in a cache file whose checksum does not match,
+/// and rebuild only those entries if `options.repair` is set.
+/// Returns whether the cache file was repaired.
+fn repair_cache<T>(cache_path: &Path, dir: &str, options: &CachedOptions) -> Result<bool>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T: Sync + Send,
+    T::Archived: 'static + Portable + Send + Sync,
+    T::Archived: for<'v> CheckBytes<HighValidator<'v, rancor::Error>>
+        + rkyv::Deserialize<T, rancor::Strategy<Pool, rancor::Error>>,
+    T: MapBuilder + 'static,
+{
+    let Ok(archive) = ArchiveStorage::<T, MemoryStorage>::load(cache_path, false)? else {
+        return Ok(false);
+    };
+    // The payload is known to be corrupted, so it must not be accessed without validation
+    let archived = archive
+        .access_validated()
+        .context("Cache structure is damaged")?;
+    // The repaired map would be rejected anyway
+    if let Some(reason) = T::options_reason(archived, options.requested) {
+        info!(
+            "Cache was built with other options ({}), not repairing.",
+            reason
+        );
+        return Ok(false);
+    }
+    let damaged = T::damaged_entries(archived);
+    if damaged.is_empty() {
+        warn!("No damaged entries found, the corruption is outside of the entries.");
+        return Ok(false);
+    }
+    for name in &damaged {
+        warn!("Cache entry {} is damaged.", name);
+    }
+    if !options.repair {
+        return Ok(false);
+    }
+    let mut map = rkyv::deserialize::<T, rancor::Error>(archived)?;
+    std::mem::drop(archive);
+    map.rebuild_entries(dir, &damaged)?;
+    info!("Writing repaired cache to {}", cache_path.display());
+    write_direct(&map, cache_path, options.checksum_algorithm)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::copy_test_data;
+
+    fn load_fasta_map(dir: &str, force_build: bool) -> DynamicStorage<FastaMap> {
+        load::<FastaMap>(
+            dir,
+            ".fasta-map-cache",
+            None,
+            &LoadOptions {
+                storage_method: "memory".to_string(),
+                force_build,
+                ..Default::default()
+            },
+            &Progress::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_repair_damaged_entry() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let reference = load_fasta_map(root, true);
+        let cache_path = Path::new(root).join(format!(
+            ".fasta-map-cache-{:016x}",
+            type_specific_magic::<FastaMap>()
+        ));
+
+        // Damage a contig name which only occurs in one entry
+        let mut content = std::fs::read(&cache_path).unwrap();
+        let needle = b"NC_032094.1";
+        let position = content
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .unwrap();
+        content[position] = b'X';
+        std::fs::write(&cache_path, &content).unwrap();
+
+        let repaired = load_fasta_map(root, false);
+        assert_eq!(
+            reference
+                .get("GCF_000182965.3")
+                .unwrap()
+                .contigs("GCF_000182965.3")
+                .unwrap(),
+            repaired
+                .get("GCF_000182965.3")
+                .unwrap()
+                .contigs("GCF_000182965.3")
+                .unwrap()
+        );
+        let repaired_archive =
+            ArchiveStorage::<FastaMap, MemoryStorage>::load(&cache_path, true).unwrap();
+        assert!(repaired_archive.is_ok());
+    }
+
+    #[test]
+    fn test_sharded_cache() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let reference = load_fasta_map(root, true);
+        let load_sharded = |force_build| {
+            load::<FastaMap>(
+                root,
+                ".fasta-map-cache",
+                None,
+                &LoadOptions {
+                    force_build,
+                    num_shards: Some(2),
+                    ..Default::default()
+                },
+                &Progress::default(),
+            )
+            .unwrap()
+        };
+        let built = load_sharded(true);
+        let cache_path = Path::new(root).join(format!(
+            ".fasta-map-cache-{:016x}",
+            type_specific_magic::<FastaMap>()
+        ));
+        assert!(directory_path(&cache_path).exists());
+        assert!(shard_path(&cache_path, 0).exists());
+        assert!(shard_path(&cache_path, 1).exists());
+
+        // The second load opens the existing shards
+        let sharded = load_sharded(false);
+        assert!(matches!(sharded, DynamicStorage::Sharded(_)));
+        assert_eq!(reference.names().unwrap(), built.names().unwrap());
+        assert_eq!(reference.names().unwrap(), sharded.names().unwrap());
+        for name in reference.names().unwrap() {
+            assert_eq!(
+                reference.get(name).unwrap().contigs(name).unwrap(),
+                sharded.get(name).unwrap().contigs(name).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_cache_without_requested_options() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let load_with = |num_shards, digests, composition, check_indices| {
+            load::<FastaMap>(
+                root,
+                ".fasta-map-cache",
+                None,
+                &LoadOptions {
+                    storage_method: "memory".to_string(),
+                    num_shards,
+                    digests,
+                    composition,
+                    check_indices,
+                    ..Default::default()
+                },
+                &Progress::default(),
+            )
+            .unwrap()
+        };
+        let name = "GCA_000146045.2";
+        let contig = b"BK006935.2";
+        for num_shards in [None, Some(2)] {
+            let without = load_with(num_shards, false, false, false);
+            assert!(without.get(name).unwrap().digest(name, contig).is_err());
+
+            // The cache without digests is rebuilt instead of reused
+            let with = load_with(num_shards, true, false, false);
+            assert!(with.get(name).unwrap().digest(name, contig).is_ok());
+
+            // A cache with digests also serves loads without them
+            let reused = load_with(num_shards, false, false, false);
+            assert!(reused.get(name).unwrap().digest(name, contig).is_ok());
+
+            // The same holds for the composition and checked indices
+            assert!(reused.get(name).unwrap().composition(name).is_err());
+            let with = load_with(num_shards, false, true, false);
+            assert!(with.get(name).unwrap().composition(name).is_ok());
+            let reused = load_with(num_shards, false, false, false);
+            assert!(reused.get(name).unwrap().composition(name).is_ok());
+            let unchecked = reused.get(name).unwrap();
+            assert!(unchecked.options_reason(0, false, false, true).is_some());
+            let checked = load_with(num_shards, false, false, true);
+            let checked = checked.get(name).unwrap();
+            assert!(checked.options_reason(0, false, false, true).is_none());
+        }
+    }
+
+    #[test]
+    fn test_cache_with_other_min_contig_length() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let load_with = |num_shards, min_contig_length| {
+            load::<FastaMap>(
+                root,
+                ".fasta-map-cache",
+                None,
+                &LoadOptions {
+                    storage_method: "memory".to_string(),
+                    num_shards,
+                    min_contig_length,
+                    ..Default::default()
+                },
+                &Progress::default(),
+            )
+            .unwrap()
+        };
+        let name = "GCA_000146045.2";
+        let has_short_contig = |storage: &DynamicStorage<FastaMap>| {
+            let contigs = storage.get(name).unwrap().contigs(name).unwrap();
+            contigs.iter().any(|(contig, _)| *contig == b"BK006935.2")
+        };
+        for num_shards in [None, Some(2)] {
+            assert!(has_short_contig(&load_with(num_shards, 0)));
+            // Each load rebuilds the cache built with the other minimum contig length
+            assert!(!has_short_contig(&load_with(num_shards, 300_000)));
+            assert!(has_short_contig(&load_with(num_shards, 0)));
+        }
+    }
+
+    #[test]
+    fn test_streamed_cache_matches_build() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let reference = FastaMap::build(
+            root,
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        let cache_path = Path::new(root).join("streamed-cache");
+        FastaMap::build_to_file(
+            root,
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+            &cache_path,
+            ChecksumAlgorithm::Xxh3,
+            true,
+        )
+        .unwrap();
+        assert!(!Path::new(root).join("streamed-cache.spill").exists());
+
+        let archive = ArchiveStorage::<FastaMap, MemoryStorage>::load(&cache_path, true)
+            .unwrap()
+            .unwrap();
+        let streamed = rkyv::deserialize::<FastaMap, rancor::Error>(archive.as_ref()).unwrap();
+        assert_eq!(reference, streamed);
+    }
+
+    #[test]
+    fn test_reproducible_cache() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let build = |name: &str| {
+            let path = Path::new(root).join(name);
+            FastaMap::build_to_file(
+                root,
+                true,
+                0,
+                true,
+                true,
+                false,
+                None,
+                &Progress::default(),
+                &NameSelection::default(),
+                &path,
+                ChecksumAlgorithm::Xxh3,
+                false,
+            )
+            .unwrap();
+            std::fs::read(path).unwrap()
+        };
+        // Building the same root twice writes byte-identical caches
+        assert_eq!(build("first-cache"), build("second-cache"));
+    }
+
+    #[test]
+    fn test_subset_cache() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let reference = load_fasta_map(root, true);
+        let subset = reference
+            .names()
+            .unwrap()
+            .into_iter()
+            .take(2)
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let load_subset = |names: Vec<String>| {
+            load::<FastaMap>(
+                root,
+                ".fasta-map-cache",
+                Some(names),
+                &LoadOptions::default(),
+                &Progress::default(),
+            )
+            .unwrap()
+        };
+        let loaded = load_subset(subset.clone());
+        assert_eq!(loaded.names().unwrap(), subset);
+        let cache_files = || {
+            let mut files = std::fs::read_dir(root)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| {
+                    path.file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .starts_with(".fasta-map-cache-")
+                })
+                .map(|path| {
+                    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+                    (path, modified)
+                })
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        let files = cache_files();
+        assert_eq!(files.len(), 2);
+
+        // The order of names does not change the cache key, and the cache is reused
+        let reloaded = load_subset(subset.iter().rev().cloned().collect());
+        assert_eq!(reloaded.names().unwrap(), subset);
+        assert_eq!(files, cache_files());
+    }
+
+    #[test]
+    fn test_exclude_filter() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let load_filtered = || {
+            load::<FastaMap>(
+                root,
+                ".fasta-map-cache",
+                None,
+                &LoadOptions {
+                    exclude: vec!["GCF_*".to_string()],
+                    ..Default::default()
+                },
+                &Progress::default(),
+            )
+            .unwrap()
+        };
+        let filtered = load_filtered();
+        assert_eq!(filtered.names().unwrap(), vec!["GCA_000146045.2"]);
+        let cache_path = std::fs::read_dir(root)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with(".fasta-map-cache-")
+            })
+            .unwrap();
+        let modified = std::fs::metadata(&cache_path).unwrap().modified().unwrap();
+
+        // Excluded files are not reported as new, so the cache is reused
+        let reloaded = load_filtered();
+        assert_eq!(reloaded.names().unwrap(), vec!["GCA_000146045.2"]);
+        assert_eq!(
+            modified,
+            std::fs::metadata(&cache_path).unwrap().modified().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cancelled_build() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let progress = Progress::default();
+        progress.cancel().cancel();
+        let result = load::<FastaMap>(
+            root,
+            ".fasta-map-cache",
+            None,
+            &LoadOptions::default(),
+            &progress,
+        );
+        let Err(e) = result else {
+            panic!("A cancelled build must fail");
+        };
+        assert!(format!("{:#}", e).contains("Interrupted"));
+        assert!(!dir.path().join(".fasta-map-cache").exists());
+    }
+
+    #[test]
+    fn test_lazy_load() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let reference = FastaMap::build(
+            root,
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        let reference = ArchiveStorage::<FastaMap, MemoryStorage>::new(reference).unwrap();
+        let lazy = load::<FastaMap>(
+            root,
+            ".fasta-map-cache",
+            None,
+            &LoadOptions {
+                lazy: true,
+                ..Default::default()
+            },
+            &Progress::default(),
+        )
+        .unwrap();
+        assert!(matches!(lazy, DynamicStorage::Lazy(_)));
+        assert_eq!(reference.as_ref().names(), lazy.names().unwrap());
+        for name in reference.as_ref().names() {
+            assert_eq!(
+                reference.as_ref().contigs(name).unwrap(),
+                lazy.get(name).unwrap().contigs(name).unwrap()
+            );
+        }
+        assert!(lazy.get("missing").is_err());
+        assert!(lazy.export(Path::new(root)).unwrap().is_none());
+        // Nothing is written to disk
+        assert!(!std::fs::read_dir(root).unwrap().any(|entry| entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with(".fasta-map-cache")));
+    }
+
+    #[test]
+    fn test_validate_options() {
+        assert!(LoadOptions::default().validate().is_ok());
+        let invalid = [
+            LoadOptions {
+                storage_method: "disk".to_string(),
+                ..Default::default()
+            },
+            LoadOptions {
+                checksum: "md5".to_string(),
+                ..Default::default()
+            },
+            LoadOptions {
+                no_cache: true,
+                ..Default::default()
+            },
+            LoadOptions {
+                num_shards: Some(2),
+                storage_method: "memory".to_string(),
+                ..Default::default()
+            },
+            LoadOptions {
+                lazy: true,
+                num_shards: Some(2),
+                ..Default::default()
+            },
+            LoadOptions {
+                bundle: Some(PathBuf::from("genomes.bundle")),
+                force_build: true,
+                ..Default::default()
+            },
+        ];
+        for options in invalid {
+            assert!(matches!(options.validate(), Err(Error::InvalidOptions(_))));
+        }
+        // Options of building and caching do not apply to lazy loading
+        let lazy = LoadOptions {
+            lazy: true,
+            strict: false,
+            ..Default::default()
+        };
+        assert!(lazy.validate().is_ok());
+    }
+}
@@ -0,0 +1,485 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use rkyv::api::high::HighValidator;
+use rkyv::bytecheck::CheckBytes;
+use rkyv::{rancor, Archive, Portable};
+
+use crate::cache::{self, LoadOptions};
+use crate::error::Error;
+use crate::index::{ArchivedMap, FastaMap, ShardDirectory, TrackMap};
+use crate::progress::Progress;
+use crate::roots::{Roots, Stats};
+use crate::storage::{
+    self, schema_id, type_specific_magic, ArchiveStorage, ChecksumAlgorithm, MmapStorage,
+    Rejection, Storage, FORMAT_VERSION,
+};
+
+const USAGE: &str = "\
+Usage: fastar-loader <command> [options] [root]
+
+Commands:
+  build <root>        Build the cache of a root, as loading it from Python would
+  verify <root>       Check the FASTA files of a root against their indices
+  inspect <root>      Print statistics and the names of a root
+  inspect <cache>     Print the header, entries and manifest of a cache file without
+                      loading it into memory, e.g., of a cache reported as corrupted
+  clean-shmem         Remove orphaned shared memory segments
+
+Options of build, verify and inspect:
+  --tracks                 The root holds tracks instead of FASTA files
+  --include <glob>         Only index matching files, may be repeated
+  --exclude <glob>         Skip matching files, may be repeated
+  --no-follow-symlinks     Skip symbolic links while searching the root
+  --min-contig-length <n>  Skip shorter contigs
+  --digests                Use the cache with sequence digests
+  --composition            Use the cache with base composition
+  --num-workers <n>        Number of threads to index with
+  --num-shards <n>         Split the cache into this many shards
+  --no-strict              Skip invalid files instead of failing
+  --no-progress            Do not show a progress bar
+
+Options of build:
+  --force                  Rebuild the cache even if it is up to date
+  --check-indices          Check the .fai and .gzi indices against the files
+
+Options of verify:
+  --deep                   Decompress randomly chosen blocks as well
+  --num-blocks <n>         Number of blocks to decompress per file (default 16)
+  --seed <n>               Seed of the choice of blocks
+
+Options of clean-shmem:
+  --prefix <prefix>        Only remove segments with this prefix (default fastar-loader-)
+  --older-than <seconds>   Only remove segments at least this old (default 0)
+";
+
+/// Parsed command line of the CLI.
+#[derive(Debug, Default)]
+struct Command {
+    name: String,
+    root: Option<String>,
+    options: LoadOptions,
+    tracks: bool,
+    show_progress: bool,
+    deep: bool,
+    num_blocks: usize,
+    seed: Option<u64>,
+    prefix: String,
+    older_than: f64,
+}
+
+impl Command {
+    fn parse(args: &[String]) -> Result<Self> {
+        let Some(name) = args.first() else {
+            bail!(Error::InvalidOptions("No command given".to_string()));
+        };
+        let mut command = Command {
+            name: name.clone(),
+            show_progress: true,
+            num_blocks: 16,
+            prefix: "fastar-loader-".to_string(),
+            ..Command::default()
+        };
+        let mut args = args[1..].iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| Error::InvalidOptions(format!("{} needs a value", arg)))
+            };
+            let invalid = |e: &dyn std::fmt::Display| {
+                Error::InvalidOptions(format!("Invalid value for {}: {}", arg, e))
+            };
+            match arg.as_str() {
+                "--tracks" => command.tracks = true,
+                "--include" => command.options.include.push(value()?.clone()),
+                "--exclude" => command.options.exclude.push(value()?.clone()),
+                "--no-follow-symlinks" => command.options.follow_symlinks = false,
+                "--min-contig-length" => {
+                    command.options.min_contig_length = value()?.parse().map_err(|e| invalid(&e))?
+                }
+                "--digests" => command.options.digests = true,
+                "--composition" => command.options.composition = true,
+                "--num-workers" => {
+                    command.options.num_workers = Some(value()?.parse().map_err(|e| invalid(&e))?)
+                }
+                "--num-shards" => {
+                    command.options.num_shards = Some(value()?.parse().map_err(|e| invalid(&e))?)
+                }
+                "--no-strict" => command.options.strict = false,
+                "--no-progress" => command.show_progress = false,
+                "--force" => command.options.force_build = true,
+                "--check-indices" => command.options.check_indices = true,
+                "--deep" => command.deep = true,
+                "--num-blocks" => command.num_blocks = value()?.parse().map_err(|e| invalid(&e))?,
+                "--seed" => command.seed = Some(value()?.parse().map_err(|e| invalid(&e))?),
+                "--prefix" => command.prefix = value()?.clone(),
+                "--older-than" => command.older_than = value()?.parse().map_err(|e| invalid(&e))?,
+                arg if arg.starts_with("--") => {
+                    bail!(Error::InvalidOptions(format!("Unknown option {}", arg)))
+                }
+                root if command.root.is_none() => command.root = Some(root.to_string()),
+                arg => bail!(Error::InvalidOptions(format!(
+                    "Unexpected argument {}",
+                    arg
+                ))),
+            }
+        }
+        Ok(command)
+    }
+
+    fn root(&self) -> Result<&str> {
+        self.root
+            .as_deref()
+            .with_context(|| Error::InvalidOptions(format!("{} needs a root directory", self.name)))
+    }
+}
+
+/// Run the CLI with the arguments after the program name and return its exit code: 0 on
+/// success, 1 on errors or files which do not match their indices, and 2 on invalid usage.
+pub fn main(args: Vec<String>) -> i32 {
+    if args.is_empty() || matches!(args[0].as_str(), "-h" | "--help" | "help") {
+        print!("{}", USAGE);
+        return if args.is_empty() { 2 } else { 0 };
+    }
+    match Command::parse(&args).and_then(|command| run(&command)) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            if matches!(e.downcast_ref::<Error>(), Some(Error::InvalidOptions(_))) {
+                eprint!("\n{}", USAGE);
+                2
+            } else {
+                1
+            }
+        }
+    }
+}
+
+fn run(command: &Command) -> Result<i32> {
+    let progress = Progress::new(command.show_progress, None);
+    match command.name.as_str() {
+        "build" => {
+            let root = command.root()?;
+            let skipped = if command.tracks {
+                load_tracks(root, &command.options, &progress)?
+                    .skipped()
+                    .to_vec()
+            } else {
+                load_fasta(root, &command.options, &progress)?
+                    .skipped()
+                    .to_vec()
+            };
+            for (name, reason) in skipped {
+                eprintln!("Skipped {}: {}", name, reason);
+            }
+            Ok(0)
+        }
+        "verify" => {
+            if command.tracks {
+                bail!(Error::InvalidOptions(
+                    "Only FASTA files can be verified".to_string()
+                ));
+            }
+            let roots = load_fasta(command.root()?, &command.options, &progress)?;
+            let deep = command.deep.then(|| {
+                (
+                    command.num_blocks,
+                    command.seed.unwrap_or_else(rand::random),
+                )
+            });
+            let names = roots.names()?;
+            let problems = names
+                .par_iter()
+                .map(|name| {
+                    let (archive, root, name) = roots.get(name)?;
+                    archive.verify(root, name, deep)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut num_failed = 0;
+            for (name, problems) in names.iter().zip(problems) {
+                num_failed += usize::from(!problems.is_empty());
+                for problem in problems {
+                    println!("{}: {}", name, problem);
+                }
+            }
+            eprintln!(
+                "{} of {} files do not match their indices",
+                num_failed,
+                names.len()
+            );
+            Ok(i32::from(num_failed > 0))
+        }
+        "inspect" => {
+            let root = command.root()?;
+            if Path::new(root).is_file() {
+                return inspect_cache(Path::new(root));
+            }
+            if command.tracks {
+                let roots = load_tracks(root, &command.options, &progress)?;
+                print_inspect(roots.stats()?, roots.summary()?);
+            } else {
+                let roots = load_fasta(root, &command.options, &progress)?;
+                print_inspect(roots.stats()?, roots.summary()?);
+            }
+            Ok(0)
+        }
+        "clean-shmem" => {
+            let older_than = Duration::try_from_secs_f64(command.older_than)
+                .map_err(|e| Error::InvalidOptions(format!("--older-than: {}", e)))?;
+            for segment in storage::cleanup_segments(&command.prefix, older_than)? {
+                println!(
+                    "Removed {} of process {} ({} bytes)",
+                    segment.name, segment.pid, segment.size
+                );
+            }
+            Ok(0)
+        }
+        name => bail!(Error::InvalidOptions(format!("Unknown command {}", name))),
+    }
+}
+
+fn load_fasta(root: &str, options: &LoadOptions, progress: &Progress) -> Result<Roots<FastaMap>> {
+    let cache_name = cache::fasta_cache_name(options);
+    let storage = cache::load::<FastaMap>(root, &cache_name, None, options, progress)?;
+    Ok(
+        Roots::new(vec![(String::new(), root.to_string(), storage)])?
+            .with_skipped(progress.take_skipped()),
+    )
+}
+
+fn load_tracks(root: &str, options: &LoadOptions, progress: &Progress) -> Result<Roots<TrackMap>> {
+    let storage = cache::load::<TrackMap>(root, cache::TRACK_CACHE_NAME, None, options, progress)?;
+    Ok(
+        Roots::new(vec![(String::new(), root.to_string(), storage)])?
+            .with_skipped(progress.take_skipped()),
+    )
+}
+
+/// Print the statistics of a root as `key: value` lines, then its names as a TSV table.
+fn print_inspect(stats: Stats, summary: Vec<(String, usize, u64, Option<u64>)>) {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    println!("num_names: {}", stats.num_names);
+    println!(
+        "num_contigs: {}",
+        optional(stats.num_contigs.map(|n| n.to_string()))
+    );
+    println!(
+        "total_length: {}",
+        optional(stats.total_length.map(|n| n.to_string()))
+    );
+    println!(
+        "archive_size: {}",
+        optional(stats.archive_size.map(|n| n.to_string()))
+    );
+    println!("storage_method: {}", stats.storage_method);
+    for path in &stats.cache_paths {
+        println!("cache_path: {}", path);
+    }
+    println!();
+    println!("name\tnum_contigs\ttotal_length\tfile_size");
+    for (name, num_contigs, total_length, file_size) in summary {
+        println!(
+            "{}\t{}\t{}\t{}",
+            name,
+            num_contigs,
+            total_length,
+            optional(file_size.map(|n| n.to_string()))
+        );
+    }
+}
+
+/// Print the header of the cache file at `path` as `key: value` lines, followed by its entries
+/// and manifest if the payload is valid. The file is memory-mapped, not loaded into shared
+/// memory. Returns 1 if a loader would reject the file.
+fn inspect_cache(path: &Path) -> Result<i32> {
+    let header = storage::read_header(path)?;
+    println!("file_size: {}", std::fs::metadata(path)?.len());
+    let kinds = [
+        (type_specific_magic::<FastaMap>(), schema_id::<FastaMap>()),
+        (type_specific_magic::<TrackMap>(), schema_id::<TrackMap>()),
+        (
+            type_specific_magic::<ShardDirectory>(),
+            schema_id::<ShardDirectory>(),
+        ),
+    ];
+    let schema = kinds
+        .iter()
+        .find(|(magic, _)| *magic == header.magic)
+        .map_or("unknown", |(_, schema)| schema.as_str());
+    println!("magic: {:016x} ({})", header.magic, schema);
+    println!(
+        "format_version: {} (expected {})",
+        header.format_version, FORMAT_VERSION
+    );
+    println!("crate_version: {}", header.crate_version);
+    println!("payload_len: {}", header.payload_len);
+    let algorithm = ChecksumAlgorithm::from_id(header.checksum_algorithm);
+    let checksum_len = algorithm.map_or(header.checksum.len(), ChecksumAlgorithm::len);
+    println!(
+        "checksum: {} {}",
+        algorithm.map_or("unknown", |algorithm| algorithm.name()),
+        header.checksum[..checksum_len]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    );
+
+    let magic = header.magic;
+    let accepted = if magic == type_specific_magic::<FastaMap>() {
+        let (accepted, archive) = load_for_inspection(|verify| {
+            ArchiveStorage::<FastaMap, MmapStorage>::load(path, verify)
+        })?;
+        if let Some(archive) = archive {
+            print_entries(archive.access_validated()?)?;
+        }
+        accepted
+    } else if magic == type_specific_magic::<TrackMap>() {
+        let (accepted, archive) = load_for_inspection(|verify| {
+            ArchiveStorage::<TrackMap, MmapStorage>::load(path, verify)
+        })?;
+        if let Some(archive) = archive {
+            print_entries(archive.access_validated()?)?;
+        }
+        accepted
+    } else if magic == type_specific_magic::<ShardDirectory>() {
+        let (accepted, archive) = load_for_inspection(|verify| {
+            ArchiveStorage::<ShardDirectory, MmapStorage>::load(path, verify)
+        })?;
+        if let Some(archive) = archive {
+            let directory = archive.access_validated()?;
+            println!("num_shards: {}", directory.num_shards());
+            print_names_and_manifest(directory.names(), directory.manifest());
+        }
+        accepted
+    } else {
+        println!("state: not a fastar-loader cache of this version");
+        false
+    };
+    Ok(i32::from(!accepted))
+}
+
+/// Load an archive with `load(verify_checksum)` and print whether a loader would accept it,
+/// which is returned along with the archive. After a checksum mismatch, the unverified archive
+/// is still returned if it passes validation, so that its entries can be inspected.
+fn load_for_inspection<T, S>(
+    load: impl Fn(bool) -> Result<std::result::Result<ArchiveStorage<T, S>, Rejection>>,
+) -> Result<(bool, Option<ArchiveStorage<T, S>>)>
+where
+    T: Archive,
+    T::Archived: Portable + for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
+    S: Storage,
+{
+    match load(true)? {
+        Ok(archive) => {
+            println!("state: ok");
+            Ok((true, Some(archive)))
+        }
+        Err(rejection @ Rejection::Checksum { .. }) => {
+            println!("state: {}", rejection);
+            let archive = load(false)?
+                .ok()
+                .filter(|archive| archive.access_validated().is_ok());
+            if archive.is_none() {
+                println!("The payload failed validation");
+            }
+            Ok((false, archive))
+        }
+        Err(rejection) => {
+            println!("state: {}", rejection);
+            Ok((false, None))
+        }
+    }
+}
+
+/// Print the entries of `map` with their number of contigs and total length, then its manifest.
+fn print_entries(map: &impl ArchivedMap) -> Result<()> {
+    let names = map.names();
+    println!("num_entries: {}", names.len());
+    println!();
+    println!("name\tnum_contigs\ttotal_length");
+    for name in &names {
+        let contigs = map.contigs(name)?;
+        let total_length = contigs.iter().map(|(_, length)| length).sum::<u64>();
+        println!("{}\t{}\t{}", name, contigs.len(), total_length);
+    }
+    print_manifest(map.manifest());
+    Ok(())
+}
+
+fn print_names_and_manifest(names: Vec<&str>, manifest: Vec<(&str, Option<u64>, u64)>) {
+    println!("num_entries: {}", names.len());
+    println!();
+    println!("name");
+    for name in names {
+        println!("{}", name);
+    }
+    print_manifest(manifest);
+}
+
+/// Print the index files a cache was built from as a TSV table.
+fn print_manifest(manifest: Vec<(&str, Option<u64>, u64)>) {
+    println!();
+    println!("path\tsize\tmtime_ns");
+    for (path, size, mtime_ns) in manifest {
+        let size = size.map_or("missing".to_string(), |size| size.to_string());
+        println!("{}\t{}\t{}", path, size, mtime_ns);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::copy_test_data;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let command = Command::parse(&args(&[
+            "build",
+            "/data",
+            "--include",
+            "GCA_*",
+            "--num-shards",
+            "4",
+            "--force",
+        ]))
+        .unwrap();
+        assert_eq!(command.root.as_deref(), Some("/data"));
+        assert_eq!(command.options.include, vec!["GCA_*"]);
+        assert_eq!(command.options.num_shards, Some(4));
+        assert!(command.options.force_build);
+        assert!(Command::parse(&args(&["build", "--num-shards"])).is_err());
+        assert!(Command::parse(&args(&["build", "--num-shards", "x"])).is_err());
+        assert!(Command::parse(&args(&["build", "a", "b"])).is_err());
+        assert!(Command::parse(&args(&["build", "--unknown"])).is_err());
+    }
+
+    #[test]
+    fn test_main() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        assert_eq!(main(args(&["build", root, "--no-progress"])), 0);
+        assert!(dir.path().join(".fasta-map-cache").exists());
+        assert_eq!(main(args(&["verify", root, "--no-progress"])), 0);
+        assert_eq!(main(args(&["inspect", root, "--no-progress"])), 0);
+        let cache = dir.path().join(".fasta-map-cache");
+        assert_eq!(main(args(&["inspect", cache.to_str().unwrap()])), 0);
+
+        // A corrupted payload is reported, but its entries are still listed if valid
+        let mut content = std::fs::read(&cache).unwrap();
+        let last = content.len() - 1;
+        content[last] ^= 0xff;
+        std::fs::write(&cache, content).unwrap();
+        assert_eq!(main(args(&["inspect", cache.to_str().unwrap()])), 1);
+        std::fs::write(&cache, b"not a cache").unwrap();
+        assert_eq!(main(args(&["inspect", cache.to_str().unwrap()])), 1);
+        assert_eq!(main(args(&["verify", root, "--tracks"])), 2);
+        assert_eq!(main(args(&["frobnicate"])), 2);
+        assert_eq!(main(args(&["build", "/does/not/exist"])), 1);
+    }
+}
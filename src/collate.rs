@@ -0,0 +1,74 @@
+use anyhow::{bail, Result};
+use numpy::ndarray::{s, Array1, Array2, ArrayView1};
+
+use crate::error::Error;
+
+/// Sequences of unequal length, padded to a common length.
+pub(crate) struct Collated {
+    /// One sequence per row, padded at the end.
+    pub(crate) values: Array2<u8>,
+    /// The length of each sequence before padding.
+    pub(crate) lengths: Array1<u64>,
+}
+
+impl Collated {
+    /// Whether each position holds a value of its sequence rather than padding, e.g., as the
+    /// attention mask of a transformer.
+    pub(crate) fn mask(&self) -> Array2<bool> {
+        let (rows, width) = self.values.dim();
+        Array2::from_shape_fn((rows, width), |(row, column)| {
+            (column as u64) < self.lengths[row]
+        })
+    }
+}
+
+/// Pad `sequences` with `pad` to `length`, or to the longest sequence without `length`.
+pub(crate) fn collate(
+    sequences: &[ArrayView1<u8>],
+    pad: u8,
+    length: Option<usize>,
+) -> Result<Collated> {
+    let longest = sequences
+        .iter()
+        .map(|sequence| sequence.len())
+        .max()
+        .unwrap_or(0);
+    let width = length.unwrap_or(longest);
+    if longest > width {
+        bail!(Error::InvalidOptions(format!(
+            "A sequence of length {} does not fit into length {}",
+            longest, width
+        )));
+    }
+    let mut values = Array2::from_elem((sequences.len(), width), pad);
+    for (mut row, sequence) in values.outer_iter_mut().zip(sequences) {
+        row.slice_mut(s![..sequence.len()]).assign(sequence);
+    }
+    let lengths = sequences
+        .iter()
+        .map(|sequence| sequence.len() as u64)
+        .collect();
+    Ok(Collated { values, lengths })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::ndarray::array;
+
+    #[test]
+    fn test_collate() {
+        let (a, b) = (array![1, 2, 3], array![4]);
+        let collated = collate(&[a.view(), b.view()], 0, None).unwrap();
+        assert_eq!(collated.values, array![[1, 2, 3], [4, 0, 0]]);
+        assert_eq!(collated.lengths, array![3, 1]);
+        assert_eq!(
+            collated.mask(),
+            array![[true, true, true], [true, false, false]]
+        );
+        let collated = collate(&[b.view()], b'N', Some(2)).unwrap();
+        assert_eq!(collated.values, array![[4, b'N']]);
+        assert!(collate(&[a.view()], 0, Some(2)).is_err());
+        assert_eq!(collate(&[], 0, None).unwrap().values.dim(), (0, 0));
+    }
+}
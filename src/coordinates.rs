@@ -0,0 +1,188 @@
+use anyhow::{bail, Result};
+
+use crate::error::Error;
+
+/// A flat coordinate system over all contigs of a map. Every (name, contig) gets a stable id,
+/// assigned in the order of the sorted names and the file order of their contigs, and the
+/// contigs are laid out end to end in that order.
+pub(crate) struct GlobalIndex {
+    names: Vec<String>,
+    /// Range of the contig ids of each name, `names.len() + 1` entries.
+    name_starts: Vec<u32>,
+    /// Index into `names` of each contig.
+    contig_names: Vec<u32>,
+    contigs: Vec<Vec<u8>>,
+    /// Global start of each contig, `contigs.len() + 1` entries ending with the total length.
+    starts: Vec<u64>,
+    /// Contig ids of each name, sorted by contig name within the range of the name.
+    by_contig: Vec<u32>,
+}
+
+impl GlobalIndex {
+    /// Lay out the contigs of `names`, which are returned by `contigs` as (contig, length).
+    pub(crate) fn build<'a, F>(mut names: Vec<String>, contigs: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Result<Vec<(&'a [u8], u64)>>,
+    {
+        names.sort();
+        let mut index = GlobalIndex {
+            name_starts: vec![0],
+            contig_names: Vec::new(),
+            contigs: Vec::new(),
+            starts: vec![0],
+            by_contig: Vec::new(),
+            names: Vec::new(),
+        };
+        let mut total = 0u64;
+        for (i, name) in names.iter().enumerate() {
+            let first = index.contigs.len() as u32;
+            for (contig, length) in contigs(name)? {
+                total += length;
+                index.contig_names.push(i as u32);
+                index.contigs.push(contig.to_vec());
+                index.starts.push(total);
+            }
+            let mut ids = (first..index.contigs.len() as u32).collect::<Vec<_>>();
+            ids.sort_by_key(|&id| &index.contigs[id as usize]);
+            index.by_contig.extend(ids);
+            index.name_starts.push(index.contigs.len() as u32);
+        }
+        index.names = names;
+        Ok(index)
+    }
+
+    /// Total length of all contigs.
+    pub(crate) fn length(&self) -> u64 {
+        self.starts[self.starts.len() - 1]
+    }
+
+    /// Sorted names.
+    pub(crate) fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub(crate) fn contains_name(&self, name: &str) -> bool {
+        self.names
+            .binary_search_by(|probe| probe.as_str().cmp(name))
+            .is_ok()
+    }
+
+    /// Id of `contig` in the entry `name`.
+    pub(crate) fn id(&self, name: &str, contig: &[u8]) -> Result<u32> {
+        let Ok(i) = self
+            .names
+            .binary_search_by(|probe| probe.as_str().cmp(name))
+        else {
+            bail!(Error::NameNotFound(name.to_string()));
+        };
+        let ids = &self.by_contig[self.name_starts[i] as usize..self.name_starts[i + 1] as usize];
+        match ids.binary_search_by(|&id| self.contigs[id as usize].as_slice().cmp(contig)) {
+            Ok(j) => Ok(ids[j]),
+            Err(_) => bail!(Error::ContigNotFound(
+                String::from_utf8_lossy(contig).into_owned()
+            )),
+        }
+    }
+
+    /// Name and contig of the id.
+    pub(crate) fn contig(&self, id: u32) -> Option<(&str, &[u8])> {
+        let contig = self.contigs.get(id as usize)?;
+        let name = &self.names[self.contig_names[id as usize] as usize];
+        Some((name, contig))
+    }
+
+    /// Name, contig and offset within the contig of a global position.
+    pub(crate) fn locate(&self, position: u64) -> Result<(&str, &[u8], u64)> {
+        if position >= self.length() {
+            bail!(Error::GlobalOutOfBounds {
+                position,
+                length: 1,
+                global_length: self.length(),
+            });
+        }
+        let id = self.contig_at(position);
+        let (name, contig) = self.contig(id as u32).unwrap();
+        Ok((name, contig, position - self.starts[id]))
+    }
+
+    /// The last contig starting at or before `position`, which skips empty contigs.
+    fn contig_at(&self, position: u64) -> usize {
+        self.starts.partition_point(|&start| start <= position) - 1
+    }
+
+    /// Like `locate`, but checks that `length` values starting at `position` lie within one
+    /// contig.
+    pub(crate) fn locate_region(&self, position: u64, length: u64) -> Result<(&str, &[u8], u64)> {
+        if position.saturating_add(length) > self.length() {
+            bail!(Error::GlobalOutOfBounds {
+                position,
+                length,
+                global_length: self.length(),
+            });
+        }
+        let (name, contig, offset) = self.locate(position)?;
+        let contig_length = self.contig_length(self.contig_at(position) as u32);
+        if offset + length > contig_length {
+            bail!(Error::OutOfBounds {
+                contig: String::from_utf8_lossy(contig).into_owned(),
+                start: offset,
+                length,
+                contig_length,
+            });
+        }
+        Ok((name, contig, offset))
+    }
+
+    pub(crate) fn num_contigs(&self) -> usize {
+        self.contigs.len()
+    }
+
+    pub(crate) fn contig_length(&self, id: u32) -> u64 {
+        self.starts[id as usize + 1] - self.starts[id as usize]
+    }
+
+    /// Global start of a contig.
+    pub(crate) fn start(&self, id: u32) -> Option<u64> {
+        self.contigs
+            .get(id as usize)
+            .map(|_| self.starts[id as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        let contigs = |name: &str| -> Result<Vec<(&'static [u8], u64)>> {
+            Ok(match name {
+                "a" => vec![
+                    (b"y".as_slice(), 10),
+                    (b"x".as_slice(), 0),
+                    (b"z".as_slice(), 5),
+                ],
+                "b" => vec![(b"x".as_slice(), 7)],
+                _ => unreachable!(),
+            })
+        };
+        let index = GlobalIndex::build(vec!["b".to_string(), "a".to_string()], contigs).unwrap();
+        assert_eq!(index.length(), 22);
+        assert_eq!(index.locate(0).unwrap(), ("a", b"y".as_slice(), 0));
+        // The empty contig is skipped
+        assert_eq!(index.locate(10).unwrap(), ("a", b"z".as_slice(), 0));
+        assert_eq!(index.locate(21).unwrap(), ("b", b"x".as_slice(), 6));
+        assert!(index.locate(22).is_err());
+        assert_eq!(index.id("a", b"z").unwrap(), 2);
+        assert_eq!(index.id("b", b"x").unwrap(), 3);
+        assert_eq!(index.contig(1), Some(("a", b"x".as_slice())));
+        assert_eq!(index.start(3), Some(15));
+        assert!(index.id("a", b"w").is_err());
+        assert!(index.id("c", b"x").is_err());
+        assert!(index.locate_region(8, 3).is_err());
+        assert_eq!(
+            index.locate_region(15, 7).unwrap(),
+            ("b", b"x".as_slice(), 0)
+        );
+    }
+}
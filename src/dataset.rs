@@ -0,0 +1,162 @@
+use anyhow::{bail, Result};
+
+use crate::coordinates::GlobalIndex;
+
+/// Mismatches between a FASTA map and a track with one value of a fixed size per base.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct AlignmentReport {
+    /// Names of the FASTA map missing from the track.
+    pub(crate) missing_names: Vec<String>,
+    /// Names of the track missing from the FASTA map.
+    pub(crate) extra_names: Vec<String>,
+    /// Contigs of shared names missing from the track, as (name, contig).
+    pub(crate) missing_contigs: Vec<(String, Vec<u8>)>,
+    /// Contigs of shared names missing from the FASTA map, as (name, contig).
+    pub(crate) extra_contigs: Vec<(String, Vec<u8>)>,
+    /// Shared contigs of another length, as (name, contig, expected bytes, track bytes).
+    pub(crate) length_mismatches: Vec<(String, Vec<u8>, u64, u64)>,
+}
+
+impl AlignmentReport {
+    /// Compare the names, contigs and contig lengths of a track to a FASTA map.
+    pub(crate) fn compare(fasta: &GlobalIndex, track: &GlobalIndex, value_size: u64) -> Self {
+        let mut report = AlignmentReport::default();
+        for (names, other, missing) in [
+            (fasta, track, &mut report.missing_names),
+            (track, fasta, &mut report.extra_names),
+        ] {
+            missing.extend(
+                names
+                    .names()
+                    .iter()
+                    .filter(|name| !other.contains_name(name))
+                    .cloned(),
+            );
+        }
+        for id in 0..fasta.num_contigs() as u32 {
+            let (name, contig) = fasta.contig(id).unwrap();
+            if !track.contains_name(name) {
+                continue;
+            }
+            let Ok(track_id) = track.id(name, contig) else {
+                report
+                    .missing_contigs
+                    .push((name.to_string(), contig.to_vec()));
+                continue;
+            };
+            let expected = fasta.contig_length(id) * value_size;
+            let track_length = track.contig_length(track_id);
+            if track_length != expected {
+                report.length_mismatches.push((
+                    name.to_string(),
+                    contig.to_vec(),
+                    expected,
+                    track_length,
+                ));
+            }
+        }
+        for id in 0..track.num_contigs() as u32 {
+            let (name, contig) = track.contig(id).unwrap();
+            if fasta.contains_name(name) && fasta.id(name, contig).is_err() {
+                report
+                    .extra_contigs
+                    .push((name.to_string(), contig.to_vec()));
+            }
+        }
+        report
+    }
+}
+
+/// Check that a track covers every contig of a FASTA map with one value of `value_size` bytes
+/// per base. The track may have additional names and contigs.
+pub(crate) fn check_aligned(
+    fasta: &GlobalIndex,
+    track: &GlobalIndex,
+    value_size: u64,
+) -> Result<()> {
+    if value_size == 0 {
+        bail!("The value size of a track must be positive");
+    }
+    let report = AlignmentReport::compare(fasta, track, value_size);
+    if let Some(name) = report.missing_names.first() {
+        bail!("The track does not cover {}", name);
+    }
+    if let Some((name, contig)) = report.missing_contigs.first() {
+        bail!(
+            "The track does not cover contig {} of {}",
+            String::from_utf8_lossy(contig),
+            name
+        );
+    }
+    if let Some((name, contig, expected, track_length)) = report.length_mismatches.first() {
+        bail!(
+            "Contig {} of {} needs {} bytes of track values, but the track has {}",
+            String::from_utf8_lossy(contig),
+            name,
+            expected,
+            track_length
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(contigs: &[(&str, &'static [u8], u64)]) -> GlobalIndex {
+        let mut names = contigs
+            .iter()
+            .map(|(name, _, _)| name.to_string())
+            .collect::<Vec<_>>();
+        names.dedup();
+        GlobalIndex::build(names, |name| {
+            Ok(contigs
+                .iter()
+                .filter(|(n, _, _)| *n == name)
+                .map(|&(_, contig, length)| (contig, length))
+                .collect())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_check_aligned() {
+        let fasta = index(&[("a", b"x", 10), ("a", b"y", 5)]);
+        let aligned = index(&[("a", b"y", 20), ("a", b"x", 40)]);
+        assert!(check_aligned(&fasta, &aligned, 4).is_ok());
+        let extra = index(&[
+            ("a", b"x", 40),
+            ("a", b"y", 20),
+            ("a", b"z", 1),
+            ("b", b"x", 1),
+        ]);
+        assert!(check_aligned(&fasta, &extra, 4).is_ok());
+        let short = index(&[("a", b"x", 40), ("a", b"y", 16)]);
+        assert!(check_aligned(&fasta, &short, 4).is_err());
+        assert!(check_aligned(&fasta, &index(&[("a", b"x", 40)]), 4).is_err());
+        assert!(check_aligned(&fasta, &aligned, 0).is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        let fasta = index(&[("a", b"x", 10), ("a", b"y", 5), ("b", b"x", 3)]);
+        let track = index(&[("a", b"x", 10), ("a", b"z", 2), ("c", b"x", 3)]);
+        let report = AlignmentReport::compare(&fasta, &track, 1);
+        assert_eq!(
+            report,
+            AlignmentReport {
+                missing_names: vec!["b".to_string()],
+                extra_names: vec!["c".to_string()],
+                missing_contigs: vec![("a".to_string(), b"y".to_vec())],
+                extra_contigs: vec![("a".to_string(), b"z".to_vec())],
+                length_mismatches: vec![],
+            }
+        );
+        let report = AlignmentReport::compare(&fasta, &track, 2);
+        assert_eq!(
+            report.length_mismatches,
+            vec![("a".to_string(), b"x".to_vec(), 20, 10)]
+        );
+    }
+}
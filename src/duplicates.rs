@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use log::warn;
+
+use crate::index::FastaMap;
+use crate::roots::Roots;
+
+/// Contigs with identical sequences, e.g., shared by the genomes of a pangenome collection,
+/// found by their GA4GH digest. Each group lists (name, contig) in name order, and the groups
+/// are ordered by their first contig. Empty contigs are not counted. Requires a map with
+/// digests.
+pub(crate) fn duplicate_contigs(roots: &Roots<FastaMap>) -> Result<Vec<Vec<(String, Vec<u8>)>>> {
+    let mut by_digest: HashMap<String, Vec<(String, Vec<u8>)>> = HashMap::new();
+    for fasta_name in roots.names()? {
+        let (archive, _, name) = roots.get(&fasta_name)?;
+        // Both are in the order of the .fai
+        let contigs = archive.contigs(name)?;
+        for ((contig, length), (_, _, sq)) in contigs.into_iter().zip(archive.digests(name)?) {
+            if length > 0 {
+                by_digest
+                    .entry(sq)
+                    .or_default()
+                    .push((fasta_name.clone(), contig.to_vec()));
+            }
+        }
+    }
+    let mut groups: Vec<_> = by_digest
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort();
+            group
+        })
+        .collect();
+    groups.sort();
+    Ok(groups)
+}
+
+/// Warn about contigs with the same sequence as another contig, see `duplicate_contigs`.
+pub(crate) fn warn_duplicates(roots: &Roots<FastaMap>) -> Result<()> {
+    let groups = duplicate_contigs(roots)?;
+    let Some(group) = groups.first() else {
+        return Ok(());
+    };
+    let num_duplicates: usize = groups.iter().map(|group| group.len() - 1).sum();
+    let [(first_name, first_contig), (second_name, second_contig), ..] = group.as_slice() else {
+        return Ok(());
+    };
+    warn!(
+        "{} contigs have the same sequence as another contig ({} distinct sequences), e.g., \
+         {} of {} and {} of {}",
+        num_duplicates,
+        groups.len(),
+        String::from_utf8_lossy(first_contig),
+        first_name,
+        String::from_utf8_lossy(second_contig),
+        second_name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Progress;
+    use crate::storage::{ArchiveStorage, MemoryStorage};
+    use crate::util::{copy_test_data, NameSelection};
+
+    #[test]
+    fn test_duplicate_contigs() {
+        let dir = copy_test_data("test-data/assemblies");
+        for suffix in [".fna.gz", ".fna.gz.gzi", ".fna.gz.fai"] {
+            std::fs::copy(
+                dir.path().join(format!("GCA_000146045.2{}", suffix)),
+                dir.path().join(format!("copy{}", suffix)),
+            )
+            .unwrap();
+        }
+        let build = |digests| {
+            let map = FastaMap::build(
+                dir.path().to_str().unwrap(),
+                true,
+                0,
+                digests,
+                false,
+                false,
+                None,
+                &Progress::default(),
+                &NameSelection::default(),
+            )
+            .unwrap();
+            let storage = ArchiveStorage::<FastaMap, MemoryStorage>::new(map).unwrap();
+            Roots::new(vec![(
+                String::new(),
+                dir.path().to_str().unwrap().to_string(),
+                storage.into(),
+            )])
+            .unwrap()
+        };
+        let roots = build(true);
+        let groups = duplicate_contigs(&roots).unwrap();
+        let (archive, _, _) = roots.get("copy").unwrap();
+        for (contig, _) in archive.contigs("copy").unwrap() {
+            let pair = vec![
+                ("GCA_000146045.2".to_string(), contig.to_vec()),
+                ("copy".to_string(), contig.to_vec()),
+            ];
+            assert!(groups.contains(&pair));
+        }
+        assert!(warn_duplicates(&roots).is_ok());
+        assert!(duplicate_contigs(&build(false)).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{bail, Result};
+use numpy::ndarray::{Array2, Array3, Zip};
+
+use crate::error::Error;
+
+/// Index of each byte for `Encoding::Index`: `ACGT` in either case as 0 to 3, all other bytes,
+/// such as `N`, as 4.
+pub(crate) const BASE_INDEX: [u8; 256] = {
+    let mut index = [4; 256];
+    let bases = *b"ACGT";
+    let mut i = 0;
+    while i < bases.len() {
+        index[bases[i] as usize] = i as u8;
+        index[bases[i].to_ascii_lowercase() as usize] = i as u8;
+        i += 1;
+    }
+    index
+};
+
+/// How batches of windows of bases are encoded for models.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Encoding {
+    /// The bases as stored, as ASCII.
+    Raw,
+    /// `ACGT` as 0 to 3 and other bases as 4, ignoring case.
+    Index,
+    /// One channel per base of `ACGT`, with all channels zero for other bases.
+    OneHot,
+}
+
+/// A batch of encoded windows.
+pub(crate) enum Encoded {
+    /// One window per row.
+    Windows(Array2<u8>),
+    /// One window per row and one channel per base.
+    OneHot(Array3<u8>),
+}
+
+impl Encoding {
+    pub(crate) fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "raw" => Encoding::Raw,
+            "index" => Encoding::Index,
+            "one_hot" => Encoding::OneHot,
+            _ => bail!(Error::InvalidOptions(format!(
+                "Unknown encoding {:?}, expected \"raw\", \"index\" or \"one_hot\"",
+                name
+            ))),
+        })
+    }
+
+    pub(crate) fn encode(self, mut windows: Array2<u8>) -> Encoded {
+        match self {
+            Encoding::Raw => Encoded::Windows(windows),
+            Encoding::Index => {
+                windows.mapv_inplace(|base| BASE_INDEX[base as usize]);
+                Encoded::Windows(windows)
+            }
+            Encoding::OneHot => {
+                let (rows, length) = windows.dim();
+                let mut one_hot = Array3::zeros((rows, length, 4));
+                Zip::from(one_hot.rows_mut())
+                    .and(&windows)
+                    .for_each(|mut channels, &base| {
+                        let index = BASE_INDEX[base as usize] as usize;
+                        if index < 4 {
+                            channels[index] = 1;
+                        }
+                    });
+                Encoded::OneHot(one_hot)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::ndarray::{array, s};
+
+    #[test]
+    fn test_encode() {
+        let windows = array![[b'A', b'c', b'G'], [b'T', b'N', b'-']];
+        let Encoded::Windows(index) = Encoding::Index.encode(windows.clone()) else {
+            panic!("index encoding returned one-hot windows");
+        };
+        assert_eq!(index, array![[0, 1, 2], [3, 4, 4]]);
+        let Encoded::OneHot(one_hot) = Encoding::OneHot.encode(windows) else {
+            panic!("one-hot encoding returned windows");
+        };
+        assert_eq!(one_hot.dim(), (2, 3, 4));
+        assert_eq!(one_hot.slice(s![0, 1, ..]), array![0, 1, 0, 0]);
+        assert_eq!(one_hot.slice(s![1, 1, ..]), array![0, 0, 0, 0]);
+        assert!(Encoding::parse("onehot").is_err());
+    }
+}
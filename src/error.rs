@@ -0,0 +1,91 @@
+use std::fmt;
+
+/// Failures which callers may want to handle specifically. They are raised as dedicated Python
+/// exceptions, also when attached as context to another error.
+#[derive(Debug)]
+pub(crate) enum Error {
+    NameNotFound(String),
+    /// A name which is not a relative path below the root, e.g., containing `..`.
+    InvalidName(String),
+    ContigNotFound(String),
+    OutOfBounds {
+        contig: String,
+        start: u64,
+        length: u64,
+        contig_length: u64,
+    },
+    GlobalOutOfBounds {
+        position: u64,
+        length: u64,
+        global_length: u64,
+    },
+    CacheCorrupted(String),
+    Storage(String),
+    /// Unknown values or incompatible combinations of load options.
+    InvalidOptions(String),
+    /// A read which does not match the expected alphabet or length, see `Alphabet`.
+    InvalidSequence(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NameNotFound(name) => write!(f, "Name not found: {}", name),
+            Error::InvalidName(name) => write!(
+                f,
+                "Invalid name {:?}, names are relative paths below the root",
+                name
+            ),
+            Error::ContigNotFound(contig) => write!(f, "Contig not found: {}", contig),
+            Error::OutOfBounds {
+                contig,
+                start,
+                length,
+                contig_length,
+            } => write!(
+                f,
+                "Region {}:{}+{} is out of bounds of the contig with length {}",
+                contig, start, length, contig_length
+            ),
+            Error::GlobalOutOfBounds {
+                position,
+                length,
+                global_length,
+            } => write!(
+                f,
+                "Global region {}+{} is out of bounds of the total length {}",
+                position, length, global_length
+            ),
+            Error::CacheCorrupted(message) => write!(f, "Cache is corrupted: {}", message),
+            Error::Storage(message) => write!(f, "{}", message),
+            Error::InvalidOptions(message) => write!(f, "{}", message),
+            Error::InvalidSequence(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn test_downcast_through_context() {
+        let error = Err::<(), _>(anyhow!("No such file"))
+            .context(Error::Storage("Could not load storage".to_string()))
+            .context("Error reading cache")
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::Storage(_))
+        ));
+        let error = anyhow::Error::from(Error::NameNotFound("a".to_string())).context("Reading");
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::NameNotFound(_))
+        ));
+    }
+}
@@ -1,8 +1,82 @@
 mod bgzf_index;
+mod composition;
+mod digest;
+mod faidx;
 mod fasta_index;
 mod fasta_map;
+mod integrity;
+mod manifest;
+mod mapped_track;
+mod metadata;
+mod packed_genome;
+mod rle_track;
+mod shards;
+mod sparse_track;
+mod spill;
 mod track_index;
 mod track_map;
 
-pub(crate) use fasta_map::FastaMap;
-pub(crate) use track_map::TrackMap;
+pub(crate) use composition::{count_bases, NUM_BASE_CLASSES};
+pub(crate) use digest::normalize_digest;
+pub(crate) use faidx::ensure_indices;
+pub(crate) use fasta_map::{FastaMap, INDEX_SUFFIXES as FASTA_INDEX_SUFFIXES};
+pub(crate) use mapped_track::MappedTrack;
+pub(crate) use packed_genome::PackedGenomes;
+pub(crate) use rle_track::RleTrack;
+pub(crate) use shards::{shard_of, ArchivedShardDirectory, ShardDirectory};
+pub(crate) use sparse_track::SparseTrack;
+pub(crate) use track_map::{TrackMap, INDEX_SUFFIXES as TRACK_INDEX_SUFFIXES};
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+/// Number of index files compared when attaching to a handle, to reject a wrong root cheaply.
+const SPOT_CHECK_FILES: usize = 8;
+
+/// What to do when merging two maps which both contain the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConflictPolicy {
+    Error,
+    FirstWins,
+}
+
+impl ConflictPolicy {
+    pub(crate) fn parse(name: &str) -> Result<Self> {
+        match name {
+            "error" => Ok(ConflictPolicy::Error),
+            "first" => Ok(ConflictPolicy::FirstWins),
+            _ => bail!("Unknown conflict policy: {}", name),
+        }
+    }
+}
+
+/// Positions of `names` in lexicographic order, to look up contigs by binary search
+/// while keeping the entries themselves in file order.
+fn sorted_positions<'a>(names: impl Iterator<Item = &'a [u8]>) -> Vec<u32> {
+    let names = names.collect::<Vec<_>>();
+    let mut positions = (0..names.len() as u32).collect::<Vec<_>>();
+    positions.sort_by_key(|&i| names[i as usize]);
+    positions
+}
+
+/// Accessors shared by all archived maps.
+pub(crate) trait ArchivedMap {
+    fn names(&self) -> Vec<&str>;
+    fn contains(&self, name: &str) -> bool;
+    /// Total number of contigs over all entries.
+    fn num_contigs(&self) -> usize;
+    /// Total length of all contigs over all entries.
+    fn total_length(&self) -> u64;
+    /// Names and lengths of the contigs of `name`, in file order.
+    fn contigs(&self, name: &str) -> Result<Vec<(&[u8], u64)>>;
+    /// Returns a reason if a few index files below `root` do not match the manifest, see
+    /// `SPOT_CHECK_FILES`.
+    fn spot_check(&self, root: &Path) -> Option<String>;
+    /// Index files the map was built from, see `ArchivedManifest::files`. Empty for shards,
+    /// whose manifest is kept in the directory.
+    fn manifest(&self) -> Vec<(&str, Option<u64>, u64)>;
+    /// Checksum over the contents of the entry `name`, which does not depend on the storage or
+    /// the modification times of its files.
+    fn checksum(&self, name: &str) -> Option<u64>;
+}
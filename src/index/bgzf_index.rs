@@ -1,9 +1,14 @@
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use noodles::bgzf::gzi::Index as NoodlesIndex;
 use noodles::bgzf::VirtualPosition;
 use rkyv::{Archive, Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Every `ANCHOR_INTERVAL`-th record is stored as an anchor, the records in between as deltas.
+/// A query decodes at most `ANCHOR_INTERVAL - 1` deltas after a binary search over the anchors.
+const ANCHOR_INTERVAL: usize = 64;
 
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct Record {
@@ -11,9 +16,15 @@ struct Record {
     uncompressed: u64,
 }
 
+/// The GZI index of a BGZF file. Consecutive block offsets differ by at most a block size, so
+/// most records are stored as LEB128-encoded deltas to the previous record.
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub(super) struct BgzfIndex {
-    entries: Vec<Record>,
+    anchors: Vec<Record>,
+    /// Start of the deltas following each anchor in `deltas`.
+    delta_offsets: Vec<u64>,
+    /// Pairs of compressed and uncompressed deltas.
+    deltas: Vec<u8>,
 }
 
 impl BgzfIndex {
@@ -21,33 +32,309 @@ impl BgzfIndex {
         let index: NoodlesIndex = noodles::bgzf::gzi::fs::read(path)?;
         Ok(BgzfIndex::from(&index))
     }
+
+    fn from_records(records: &[(u64, u64)]) -> Self {
+        let mut index = BgzfIndex {
+            anchors: Vec::with_capacity(records.len().div_ceil(ANCHOR_INTERVAL)),
+            delta_offsets: Vec::with_capacity(records.len().div_ceil(ANCHOR_INTERVAL)),
+            deltas: Vec::new(),
+        };
+        for chunk in records.chunks(ANCHOR_INTERVAL) {
+            let (compressed, uncompressed) = chunk[0];
+            index.anchors.push(Record {
+                compressed,
+                uncompressed,
+            });
+            index.delta_offsets.push(index.deltas.len() as u64);
+            for pair in chunk.windows(2) {
+                // Wrapping keeps the encoding lossless even if the offsets are not increasing
+                write_varint(&mut index.deltas, pair[1].0.wrapping_sub(pair[0].0));
+                write_varint(&mut index.deltas, pair[1].1.wrapping_sub(pair[0].1));
+            }
+        }
+        index
+    }
+}
+
+impl BgzfIndex {
+    /// The record of the last block, if the file has more than one block.
+    pub(super) fn last(&self) -> Result<Option<(u64, u64)>> {
+        let (Some(anchor), Some(&start)) = (self.anchors.last(), self.delta_offsets.last()) else {
+            return Ok(None);
+        };
+        let mut current = (anchor.compressed, anchor.uncompressed);
+        let Some(mut deltas) = self.deltas.get(start as usize..) else {
+            bail!("Delta offsets of gzi index are out of range");
+        };
+        while !deltas.is_empty() {
+            current.0 = current.0.wrapping_add(read_varint(&mut deltas)?);
+            current.1 = current.1.wrapping_add(read_varint(&mut deltas)?);
+        }
+        Ok(Some(current))
+    }
 }
 
 impl From<&NoodlesIndex> for BgzfIndex {
     fn from(index: &NoodlesIndex) -> Self {
-        let entries = index
-            .as_ref()
-            .iter()
-            .map(|(compressed, uncompressed)| Record {
-                compressed: *compressed,
-                uncompressed: *uncompressed,
-            })
-            .collect();
-        BgzfIndex { entries }
+        BgzfIndex::from_records(index.as_ref())
+    }
+}
+
+impl BgzfIndex {
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for record in &self.anchors {
+            hasher.update(&record.compressed.to_le_bytes());
+            hasher.update(&record.uncompressed.to_le_bytes());
+        }
+        for offset in &self.delta_offsets {
+            hasher.update(&offset.to_le_bytes());
+        }
+        hasher.update(&self.deltas);
     }
 }
 
 impl ArchivedBgzfIndex {
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for record in self.anchors.iter() {
+            hasher.update(&u64::from(record.compressed).to_le_bytes());
+            hasher.update(&u64::from(record.uncompressed).to_le_bytes());
+        }
+        for offset in self.delta_offsets.iter() {
+            hasher.update(&u64::from(*offset).to_le_bytes());
+        }
+        hasher.update(&self.deltas);
+    }
+
     pub(super) fn query(&self, pos: u64) -> Result<VirtualPosition> {
-        let i = self.entries.partition_point(|r| r.uncompressed <= pos);
+        let i = self.anchors.partition_point(|r| r.uncompressed <= pos);
         let (compressed, uncompressed) = match i {
             0 => (0u64, 0u64),
-            i => {
-                let entry = &self.entries[i - 1];
-                (u64::from(entry.compressed), u64::from(entry.uncompressed))
+            i => self.decode_block(i - 1, pos)?,
+        };
+        // Blocks hold at most 64 KiB, so a larger gap means that records are missing
+        let block_data_pos = u16::try_from(pos - uncompressed).map_err(|_| {
+            anyhow!(
+                "The gzi index has no block within 64 KiB before uncompressed offset {}, the \
+                 closest starts at {}, so it does not match the file",
+                pos,
+                uncompressed
+            )
+        })?;
+        VirtualPosition::try_from((compressed, block_data_pos)).map_err(|_| {
+            anyhow!(
+                "The block of uncompressed offset {} starts at compressed offset {}, beyond the \
+                 largest offset of a BGZF file",
+                pos,
+                compressed
+            )
+        })
+    }
+
+    /// Number of BGZF blocks which contain the uncompressed bytes from `start` to `end`.
+    pub(super) fn num_blocks(&self, start: u64, end: u64) -> Result<u64> {
+        Ok(self.block_number(end.max(start + 1) - 1)? - self.block_number(start)? + 1)
+    }
+
+    /// The blocks containing the uncompressed bytes from `start` to `end`, as (compressed,
+    /// uncompressed) offsets of their first byte.
+    pub(super) fn blocks(&self, start: u64, end: u64) -> Result<Vec<(u64, u64)>> {
+        let end = end.max(start + 1);
+        let i = self.anchors.partition_point(|r| r.uncompressed <= start);
+        let mut blocks = if i == 0 { vec![(0, 0)] } else { Vec::new() };
+        for (block, anchor) in self.anchors.iter().enumerate().skip(i.saturating_sub(1)) {
+            let mut current = (u64::from(anchor.compressed), u64::from(anchor.uncompressed));
+            let mut deltas = self.deltas(block)?;
+            loop {
+                if current.1 >= end {
+                    return Ok(blocks);
+                }
+                // Only the last block starting at or before `start` contains it
+                if current.1 <= start {
+                    blocks.clear();
+                }
+                blocks.push(current);
+                if deltas.is_empty() {
+                    break;
+                }
+                current.0 = current.0.wrapping_add(read_varint(&mut deltas)?);
+                current.1 = current.1.wrapping_add(read_varint(&mut deltas)?);
             }
+        }
+        Ok(blocks)
+    }
+
+    /// Number of the block containing the uncompressed offset `pos`, where the first block,
+    /// which has no record, is block 0.
+    fn block_number(&self, pos: u64) -> Result<u64> {
+        let i = self.anchors.partition_point(|r| r.uncompressed <= pos);
+        if i == 0 {
+            return Ok(0);
+        }
+        let mut number = ((i - 1) * ANCHOR_INTERVAL + 1) as u64;
+        let mut uncompressed = u64::from(self.anchors[i - 1].uncompressed);
+        let mut deltas = self.deltas(i - 1)?;
+        while !deltas.is_empty() {
+            read_varint(&mut deltas)?;
+            uncompressed = uncompressed.wrapping_add(read_varint(&mut deltas)?);
+            if uncompressed > pos {
+                break;
+            }
+            number += 1;
+        }
+        Ok(number)
+    }
+
+    /// All records as (compressed, uncompressed) offsets of the blocks after the first.
+    pub(super) fn records(&self) -> Result<Vec<(u64, u64)>> {
+        let mut records = Vec::new();
+        for (block, anchor) in self.anchors.iter().enumerate() {
+            let mut current = (u64::from(anchor.compressed), u64::from(anchor.uncompressed));
+            records.push(current);
+            let mut deltas = self.deltas(block)?;
+            while !deltas.is_empty() {
+                current.0 = current.0.wrapping_add(read_varint(&mut deltas)?);
+                current.1 = current.1.wrapping_add(read_varint(&mut deltas)?);
+                records.push(current);
+            }
+        }
+        Ok(records)
+    }
+
+    /// The encoded deltas following anchor `block`.
+    fn deltas(&self, block: usize) -> Result<&[u8]> {
+        let start = u64::from(self.delta_offsets[block]) as usize;
+        let end = self
+            .delta_offsets
+            .get(block + 1)
+            .map_or(self.deltas.len(), |offset| u64::from(*offset) as usize);
+        let Some(deltas) = self.deltas.get(start..end) else {
+            bail!("Delta offsets of gzi index are out of range");
         };
-        let block_data_pos = u16::try_from(pos - uncompressed)?;
-        Ok(VirtualPosition::try_from((compressed, block_data_pos))?)
+        Ok(deltas)
+    }
+
+    /// Decode the records following anchor `block` and return the last one starting at or
+    /// before `pos`.
+    fn decode_block(&self, block: usize, pos: u64) -> Result<(u64, u64)> {
+        let anchor = &self.anchors[block];
+        let mut current = (u64::from(anchor.compressed), u64::from(anchor.uncompressed));
+        let mut deltas = self.deltas(block)?;
+        while !deltas.is_empty() {
+            let compressed = current.0.wrapping_add(read_varint(&mut deltas)?);
+            let uncompressed = current.1.wrapping_add(read_varint(&mut deltas)?);
+            if uncompressed > pos {
+                break;
+            }
+            current = (compressed, uncompressed);
+        }
+        Ok(current)
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for (i, &byte) in buf.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Ok(value);
+        }
+    }
+    bail!("Truncated delta in gzi index");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_matches_linear_search() {
+        // Block sizes vary, as in real BGZF files
+        let records = (1..200u64)
+            .scan((0u64, 0u64), |(compressed, uncompressed), i| {
+                *compressed += 20_000 + (i * 7919) % 30_000;
+                *uncompressed += 65_280;
+                Some((*compressed, *uncompressed))
+            })
+            .collect::<Vec<_>>();
+        let index = BgzfIndex::from_records(&records);
+        assert!(index.deltas.len() < records.len() * 8);
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&index).unwrap();
+        let archived = rkyv::access::<ArchivedBgzfIndex, rkyv::rancor::Error>(&bytes).unwrap();
+        for pos in (0..200 * 65_280).step_by(4_321) {
+            let (compressed, uncompressed) = records
+                .iter()
+                .rev()
+                .find(|(_, uncompressed)| *uncompressed <= pos)
+                .copied()
+                .unwrap_or((0, 0));
+            let expected =
+                VirtualPosition::try_from((compressed, (pos - uncompressed) as u16)).unwrap();
+            assert_eq!(archived.query(pos).unwrap(), expected);
+        }
+        assert_eq!(archived.records().unwrap(), records);
+        // Block 0 ends at the first record
+        assert_eq!(archived.num_blocks(0, 65_280).unwrap(), 1);
+        assert_eq!(archived.num_blocks(65_279, 65_281).unwrap(), 2);
+        assert_eq!(archived.num_blocks(100 * 65_280, 100 * 65_280).unwrap(), 1);
+        assert_eq!(archived.num_blocks(0, 200 * 65_280).unwrap(), 200);
+        for (start, end) in [
+            (0, 1),
+            (65_279, 65_281),
+            (64 * 65_280 + 5, 130 * 65_280),
+            (0, 1 << 40),
+        ] {
+            let blocks = archived.blocks(start, end).unwrap();
+            assert_eq!(
+                blocks.len() as u64,
+                archived.num_blocks(start, end.min(200 * 65_280)).unwrap()
+            );
+            assert_eq!(archived.query(start).unwrap().compressed(), blocks[0].0);
+            assert!(blocks.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        }
+        assert_eq!(index.last().unwrap(), records.last().copied());
+    }
+
+    #[test]
+    fn test_query_large_offsets() {
+        // Blocks beyond 4 GiB of a long contig, a gap without records and a compressed offset
+        // beyond 48 bits
+        let records = [
+            (1 << 32, 5 << 32),
+            ((1 << 32) + 30_000, (5 << 32) + 65_280),
+            (1 << 33, 6 << 32),
+            (1 << 48, 7 << 32),
+        ];
+        let index = BgzfIndex::from_records(&records);
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&index).unwrap();
+        let archived = rkyv::access::<ArchivedBgzfIndex, rkyv::rancor::Error>(&bytes).unwrap();
+        let expected = VirtualPosition::try_from(((1 << 32) + 30_000, 100)).unwrap();
+        assert_eq!(archived.query((5 << 32) + 65_380).unwrap(), expected);
+        let error = archived.query((5 << 32) + 200_000).unwrap_err();
+        assert!(error.to_string().contains("no block within 64 KiB"));
+        let error = archived.query(7 << 32).unwrap_err();
+        assert!(error.to_string().contains("beyond the largest offset"));
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        let mut buf = Vec::new();
+        for value in [0, 1, 127, 128, 65_280, u64::MAX] {
+            write_varint(&mut buf, value);
+        }
+        let mut slice = buf.as_slice();
+        for value in [0, 1, 127, 128, 65_280, u64::MAX] {
+            assert_eq!(read_varint(&mut slice).unwrap(), value);
+        }
+        assert!(slice.is_empty());
+        assert!(read_varint(&mut &[0x80u8][..]).is_err());
     }
 }
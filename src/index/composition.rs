@@ -0,0 +1,72 @@
+use rkyv::{Archive, Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Number of base classes counted by `BaseCounts`: `A`, `C`, `G`, `T` and anything else.
+pub(crate) const NUM_BASE_CLASSES: usize = 5;
+
+/// Class of each byte, ignoring case, with unknown bases such as `N` in the last class.
+const BASE_CLASSES: [u8; 256] = {
+    let mut classes = [4; 256];
+    classes[b'A' as usize] = 0;
+    classes[b'a' as usize] = 0;
+    classes[b'C' as usize] = 1;
+    classes[b'c' as usize] = 1;
+    classes[b'G' as usize] = 2;
+    classes[b'g' as usize] = 2;
+    classes[b'T' as usize] = 3;
+    classes[b't' as usize] = 3;
+    classes
+};
+
+/// Add the bases of `sequence` to `counts`, see `BaseCounts`.
+pub(crate) fn count_bases(sequence: &[u8], counts: &mut [u64; NUM_BASE_CLASSES]) {
+    for &base in sequence {
+        counts[BASE_CLASSES[base as usize] as usize] += 1;
+    }
+}
+
+/// Counts of `A`, `C`, `G`, `T` and other bases of a contig, ignoring case.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub(super) struct BaseCounts {
+    counts: [u64; NUM_BASE_CLASSES],
+}
+
+impl BaseCounts {
+    pub(super) fn update(&mut self, sequence: &[u8]) {
+        count_bases(sequence, &mut self.counts);
+    }
+
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for count in self.counts {
+            hasher.update(&count.to_le_bytes());
+        }
+    }
+}
+
+impl ArchivedBaseCounts {
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for count in self.counts.iter() {
+            hasher.update(&count.to_native().to_le_bytes());
+        }
+    }
+
+    pub(super) fn counts(&self) -> [u64; NUM_BASE_CLASSES] {
+        self.counts.map(|count| count.to_native())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_bases() {
+        let mut counts = BaseCounts::default();
+        counts.update(b"ACgtNn");
+        counts.update(b"GC-");
+        assert_eq!(counts.counts, [1, 2, 2, 1, 3]);
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&counts).unwrap();
+        let archived = rkyv::access::<ArchivedBaseCounts, rkyv::rancor::Error>(&bytes).unwrap();
+        assert_eq!(archived.counts(), [1, 2, 2, 1, 3]);
+    }
+}
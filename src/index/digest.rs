@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use md5::{Digest, Md5};
+use noodles::fasta;
+use rkyv::{Archive, Deserialize, Serialize};
+use sha2::Sha512;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::bgzf_reader::BgzfReader;
+use crate::remote::open_data;
+
+/// Digests of the upper-cased sequence of a contig, as used by refget and CRAM.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub(super) struct SequenceDigest {
+    md5: [u8; 16],
+    /// The first 24 bytes of the SHA-512 digest, see `sha512t24u`.
+    sha512t24: [u8; 24],
+}
+
+/// Incremental computation of a `SequenceDigest`.
+struct Hasher {
+    md5: Md5,
+    sha512: Sha512,
+    buf: Vec<u8>,
+}
+
+impl Hasher {
+    fn new() -> Self {
+        Hasher {
+            md5: Md5::new(),
+            sha512: Sha512::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, sequence: &[u8]) {
+        self.buf.clear();
+        self.buf
+            .extend(sequence.iter().map(|base| base.to_ascii_uppercase()));
+        self.md5.update(&self.buf);
+        self.sha512.update(&self.buf);
+    }
+
+    fn finish(self) -> SequenceDigest {
+        let sha512 = self.sha512.finalize();
+        SequenceDigest {
+            md5: self.md5.finalize().as_slice().try_into().unwrap(),
+            sha512t24: sha512[..24].try_into().unwrap(),
+        }
+    }
+}
+
+impl SequenceDigest {
+    #[cfg(test)]
+    fn of(sequence: &[u8]) -> Self {
+        let mut hasher = Hasher::new();
+        hasher.update(sequence);
+        hasher.finish()
+    }
+
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        hasher.update(&self.md5);
+        hasher.update(&self.sha512t24);
+    }
+}
+
+impl ArchivedSequenceDigest {
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        hasher.update(&self.md5);
+        hasher.update(&self.sha512t24);
+    }
+
+    /// The MD5 digest as lowercase hex.
+    pub(super) fn md5(&self) -> String {
+        self.md5
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The GA4GH identifier `SQ.` followed by the base64url-encoded truncated SHA-512 digest.
+    pub(super) fn sha512t24u(&self) -> String {
+        format!("SQ.{}", base64url(&self.sha512t24))
+    }
+}
+
+/// Base64url encoding without padding.
+fn base64url(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | ((byte as u32) << (16 - 8 * i))
+        });
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[((bits >> (18 - 6 * i)) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+/// Normalize a digest for lookup: MD5 digests as lowercase hex, and others as a GA4GH
+/// identifier with the `SQ.` prefix, which may also be given with a `ga4gh:` prefix or without
+/// any.
+pub(crate) fn normalize_digest(digest: &str) -> String {
+    let digest = digest.strip_prefix("ga4gh:").unwrap_or(digest);
+    if digest.len() == 32 && digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        digest.to_ascii_lowercase()
+    } else if digest.starts_with("SQ.") {
+        digest.to_string()
+    } else {
+        format!("SQ.{}", digest)
+    }
+}
+
+/// Digests of the contigs in a BGZF-compressed FASTA file, by contig name. The whole file is
+/// read once.
+pub(super) fn read_digests(path: &Path) -> Result<HashMap<Vec<u8>, SequenceDigest>> {
+    let digests = scan_contigs(path, Hasher::new, Hasher::update)?;
+    Ok(digests
+        .into_iter()
+        .map(|(name, hasher)| (name, hasher.finish()))
+        .collect())
+}
+
+/// Feed the sequence of every contig of a BGZF-compressed FASTA file in chunks to a state
+/// created by `init`, and return the states by contig name. The whole file is read once.
+pub(super) fn scan_contigs<T>(
+    path: &Path,
+    init: impl Fn() -> T,
+    update: impl Fn(&mut T, &[u8]),
+) -> Result<HashMap<Vec<u8>, T>> {
+    let mut reader = fasta::io::Reader::new(BgzfReader::new(open_data(path)?));
+    let mut states = HashMap::new();
+    let mut definition = String::new();
+    loop {
+        definition.clear();
+        if reader.read_definition(&mut definition)? == 0 {
+            break;
+        }
+        let name = definition
+            .trim_start_matches('>')
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| anyhow!("Invalid FASTA definition: {}", definition))?
+            .as_bytes()
+            .to_vec();
+        let mut state = init();
+        let mut sequence_reader = reader.sequence_reader();
+        loop {
+            let src = sequence_reader.fill_buf()?;
+            if src.is_empty() {
+                break;
+            }
+            update(&mut state, src);
+            let len = src.len();
+            sequence_reader.consume(len);
+        }
+        states.insert(name, state);
+    }
+    Ok(states)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest() {
+        // Test vectors of the refget specification
+        let digest = SequenceDigest::of(b"acgt");
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&digest).unwrap();
+        let archived = rkyv::access::<ArchivedSequenceDigest, rkyv::rancor::Error>(&bytes).unwrap();
+        assert_eq!(archived.md5(), "f1f8f4bf413b16ad135722aa4591043e");
+        assert_eq!(archived.sha512t24u(), "SQ.aKF498dAxcJAqme6QYQ7EZ07-fiw8Kw2");
+        assert_eq!(base64url(b"ab"), "YWI");
+        assert_eq!(base64url(b"abcd"), "YWJjZA");
+    }
+
+    #[test]
+    fn test_normalize_digest() {
+        let md5 = "F1F8F4BF413B16AD135722AA4591043E";
+        assert_eq!(normalize_digest(md5), md5.to_ascii_lowercase());
+        for digest in [
+            "SQ.aKF498dAxcJAqme6QYQ7EZ07-fiw8Kw2",
+            "ga4gh:SQ.aKF498dAxcJAqme6QYQ7EZ07-fiw8Kw2",
+            "aKF498dAxcJAqme6QYQ7EZ07-fiw8Kw2",
+        ] {
+            assert_eq!(
+                normalize_digest(digest),
+                "SQ.aKF498dAxcJAqme6QYQ7EZ07-fiw8Kw2"
+            );
+        }
+    }
+}
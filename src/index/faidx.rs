@@ -0,0 +1,242 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use noodles::fasta;
+
+use crate::bgzf_reader::BgzfReader;
+
+/// Size of the fixed part of a BGZF block header, up to and including `XLEN`.
+const HEADER_SIZE: usize = 12;
+/// The empty block which ends every BGZF file.
+pub(super) const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Build the GZI and FAI indices of the BGZF-compressed FASTA file `fasta` at `gzi` and `fai`,
+/// as `samtools faidx` would, unless they exist already.
+pub(crate) fn ensure_indices(fasta: &Path, gzi: &Path, fai: &Path) -> Result<()> {
+    if !gzi.exists() {
+        info!("Building {}", gzi.display());
+        write_partial(gzi, |writer| write_gzi(fasta, writer))
+            .with_context(|| format!("Could not build {}", gzi.display()))?;
+    }
+    if !fai.exists() {
+        info!("Building {}", fai.display());
+        write_partial(fai, |writer| write_fai(fasta, writer))
+            .with_context(|| format!("Could not build {}", fai.display()))?;
+    }
+    Ok(())
+}
+
+/// Write `path` through a partial file, such that concurrent readers never see a truncated
+/// index.
+fn write_partial(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<()> {
+    let mut partial = OsString::from(path);
+    partial.push(format!(".partial-{}", std::process::id()));
+    let partial = PathBuf::from(partial);
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    let result = write(&mut writer).and_then(|()| Ok(writer.flush()?));
+    drop(writer);
+    match result {
+        Ok(()) => Ok(std::fs::rename(&partial, path)?),
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// Write the GZI index of a BGZF file: the number of blocks after the first, followed by the
+/// compressed and uncompressed offset of each of them, all as little-endian `u64`.
+fn write_gzi(path: &Path, writer: &mut impl Write) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut offsets = Vec::new();
+    let (mut compressed, mut uncompressed) = (0u64, 0u64);
+    loop {
+        let block = next_block(&mut reader, compressed).with_context(|| {
+            format!(
+                "{} is not BGZF-compressed, compress it with bgzip",
+                path.display()
+            )
+        })?;
+        let Some((block_size, size)) = block else {
+            break;
+        };
+        if compressed > 0 {
+            offsets.push((compressed, uncompressed));
+        }
+        compressed += block_size;
+        uncompressed += size;
+    }
+    writer.write_all(&(offsets.len() as u64).to_le_bytes())?;
+    for (compressed, uncompressed) in offsets {
+        writer.write_all(&compressed.to_le_bytes())?;
+        writer.write_all(&uncompressed.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Skip the BGZF block at `offset`, the current position of `reader`, and return its
+/// compressed and uncompressed size, or `None` at the end of the file.
+pub(super) fn next_block(
+    reader: &mut (impl Read + Seek),
+    offset: u64,
+) -> Result<Option<(u64, u64)>> {
+    let mut header = [0u8; HEADER_SIZE];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    if header[..4] != [0x1f, 0x8b, 0x08, 0x04] {
+        bail!("Missing BGZF block header at {}", offset);
+    }
+    let mut extra = vec![0u8; u16::from_le_bytes([header[10], header[11]]) as usize];
+    reader.read_exact(&mut extra)?;
+    let block_size =
+        block_size(&extra).with_context(|| format!("Missing BGZF block size at {}", offset))?;
+    // Skip the compressed data and CRC32 to read the uncompressed size at the end
+    let skip = block_size
+        .checked_sub((HEADER_SIZE + extra.len() + 4) as u64)
+        .with_context(|| format!("Invalid BGZF block size at {}", offset))?;
+    reader.seek(SeekFrom::Current(skip as i64))?;
+    let mut size = [0u8; 4];
+    reader
+        .read_exact(&mut size)
+        .with_context(|| format!("Truncated BGZF block at {}", offset))?;
+    Ok(Some((block_size, u32::from_le_bytes(size) as u64)))
+}
+
+/// The total size of a BGZF block from the `BC` subfield of the extra field of its header.
+fn block_size(extra: &[u8]) -> Option<u64> {
+    let mut rest = extra;
+    while rest.len() >= 4 {
+        let length = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let data = rest.get(4..4 + length)?;
+        if rest[..2] == *b"BC" && length == 2 {
+            return Some(u16::from_le_bytes([data[0], data[1]]) as u64 + 1);
+        }
+        rest = &rest[4 + length..];
+    }
+    None
+}
+
+/// Write the FAI index of a BGZF-compressed FASTA file, with offsets into the uncompressed
+/// sequence.
+fn write_fai(path: &Path, writer: &mut impl Write) -> Result<()> {
+    let mut indexer = fasta::io::Indexer::new(BgzfReader::new(File::open(path)?));
+    let mut records = Vec::new();
+    while let Some(record) = indexer.index_record()? {
+        records.push(record);
+    }
+    fasta::fai::io::Writer::new(writer).write_index(&fasta::fai::Index::from(records))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use noodles::bgzf;
+
+    use super::*;
+    use crate::index::bgzf_index::{ArchivedBgzfIndex, BgzfIndex};
+    use crate::util::copy_test_data;
+
+    #[test]
+    fn test_ensure_indices() {
+        let dir = copy_test_data("test-data/assemblies");
+        let path = dir.path().join("GCA_000146045.2.fna.gz");
+        let gzi = dir.path().join("built.gzi");
+        let fai = dir.path().join("built.fai");
+        ensure_indices(&path, &gzi, &fai).unwrap();
+        assert_eq!(
+            bgzf::gzi::fs::read(&gzi).unwrap(),
+            bgzf::gzi::fs::read(dir.path().join("GCA_000146045.2.fna.gz.gzi")).unwrap()
+        );
+        assert_eq!(
+            fasta::fai::fs::read(&fai).unwrap(),
+            fasta::fai::fs::read(dir.path().join("GCA_000146045.2.fna.gz.fai")).unwrap()
+        );
+
+        // Existing indices are kept, and plain files are rejected
+        let modified = std::fs::metadata(&gzi).unwrap().modified().unwrap();
+        ensure_indices(&path, &gzi, &fai).unwrap();
+        assert_eq!(
+            modified,
+            std::fs::metadata(&gzi).unwrap().modified().unwrap()
+        );
+        let plain = dir.path().join("plain.fna");
+        std::fs::write(&plain, ">chr1\nACGT\n").unwrap();
+        assert!(ensure_indices(&plain, &dir.path().join("plain.gzi"), &fai).is_err());
+        assert!(!dir.path().join("plain.gzi").exists());
+    }
+
+    #[test]
+    fn test_crlf_indices() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crlf.fna.gz");
+        let mut writer = bgzf::io::Writer::new(File::create(&path).unwrap());
+        writer
+            .write_all(b">a\r\nACGT\r\nAC\r\n>b\r\nGG\r\n")
+            .unwrap();
+        writer.finish().unwrap();
+        let (gzi, fai) = (dir.path().join("crlf.gzi"), dir.path().join("crlf.fai"));
+        ensure_indices(&path, &gzi, &fai).unwrap();
+        // Line widths count both bytes of the line breaks, so offsets are not shifted
+        let index = fasta::fai::fs::read(&fai).unwrap();
+        let records: Vec<_> = index
+            .as_ref()
+            .iter()
+            .map(|record| {
+                (
+                    record.length(),
+                    record.offset(),
+                    record.line_bases(),
+                    record.line_width(),
+                )
+            })
+            .collect();
+        assert_eq!(records, vec![(6, 4, 4, 6), (2, 18, 2, 4)]);
+    }
+
+    #[test]
+    fn test_concatenated_indices() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = Vec::new();
+        for (i, part) in [&b">a\nACGT\nAC\n"[..], b">b\nGGTT\n"]
+            .into_iter()
+            .enumerate()
+        {
+            let path = dir.path().join(format!("part{}.fna.gz", i));
+            let mut writer = bgzf::io::Writer::new(File::create(&path).unwrap());
+            writer.write_all(part).unwrap();
+            writer.finish().unwrap();
+            data.extend(std::fs::read(&path).unwrap());
+        }
+        let path = dir.path().join("cat.fna.gz");
+        std::fs::write(&path, &data).unwrap();
+        let (gzi, fai) = (dir.path().join("cat.gzi"), dir.path().join("cat.fai"));
+        ensure_indices(&path, &gzi, &fai).unwrap();
+
+        // The offsets continue across the end-of-file marker of the first part
+        let index = fasta::fai::fs::read(&fai).unwrap();
+        assert_eq!(index.as_ref()[1].offset(), 14);
+        let gzi = BgzfIndex::read(&gzi).unwrap();
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&gzi).unwrap();
+        let gzi = rkyv::access::<ArchivedBgzfIndex, rkyv::rancor::Error>(&bytes).unwrap();
+        let mut reader = BgzfReader::new(File::open(&path).unwrap());
+        reader
+            .seek_to_virtual_position(gzi.query(14).unwrap())
+            .unwrap();
+        let mut sequence = [0; 4];
+        reader.read_exact(&mut sequence).unwrap();
+        assert_eq!(&sequence, b"GGTT");
+    }
+}
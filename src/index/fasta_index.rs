@@ -1,12 +1,19 @@
+use std::collections::HashSet;
 use std::path::Path;
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use noodles::fasta::fai::Index as NoodlesIndex;
-use rkyv::{Archive, Deserialize, Serialize};
+use rkyv::{Archive, Archived, Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::error::Error;
+use crate::index::sorted_positions;
 
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct Record {
-    contig: Vec<u8>,
+    /// Shared between entries with the same contig name, see `ContigNames`.
+    contig: Arc<[u8]>,
     length: u64,
     offset: u64,
     line_bases: u64,
@@ -16,6 +23,8 @@ struct Record {
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub(super) struct FastaIndex {
     entries: Vec<Record>,
+    /// Positions of the entries sorted by contig name.
+    by_name: Vec<u32>,
 }
 
 impl FastaIndex {
@@ -31,19 +40,155 @@ impl FastaIndex {
             .as_ref()
             .iter()
             .map(|record| Record {
-                contig: record.name().to_vec(),
+                contig: record.name().to_vec().into(),
                 length: record.length(),
                 offset: record.offset(),
                 line_bases: record.line_bases(),
                 line_width: record.line_width(),
             })
             .filter(|record| record.length >= min_contig_length)
-            .collect();
-        FastaIndex { entries }
+            .collect::<Vec<_>>();
+        let by_name = sorted_positions(entries.iter().map(|record| record.contig.as_ref()));
+        FastaIndex { entries, by_name }
+    }
+}
+
+impl FastaIndex {
+    /// Check that the line layout of every contig is plausible and that it ends within the
+    /// `size` bytes of the uncompressed FASTA file, e.g., to reject the index of another file.
+    pub(super) fn check(&self, size: u64) -> Result<()> {
+        for record in &self.entries {
+            let contig = String::from_utf8_lossy(&record.contig);
+            // Lines end with `\n` or `\r\n`
+            if record.length > 0
+                && (record.line_bases == 0
+                    || record.line_width < record.line_bases
+                    || record.line_width > record.line_bases + 2)
+            {
+                bail!(
+                    "Contig {} has lines of {} bases in {} bytes",
+                    contig,
+                    record.line_bases,
+                    record.line_width
+                );
+            }
+            let end = end(
+                record.offset,
+                record.length,
+                record.line_bases,
+                record.line_width,
+            );
+            if end > size {
+                bail!(
+                    "Contig {} ends at offset {}, but the uncompressed file has {} bytes",
+                    contig,
+                    end,
+                    size
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Offset in the uncompressed file after the last base of a contig. Saturates for corrupted
+/// records, which then end beyond any file.
+fn end(offset: u64, length: u64, line_bases: u64, line_width: u64) -> u64 {
+    match length {
+        0 => offset,
+        length => {
+            let line_bases = line_bases.max(1);
+            ((length - 1) / line_bases)
+                .saturating_mul(line_width)
+                .saturating_add(offset)
+                .saturating_add((length - 1) % line_bases + 1)
+        }
+    }
+}
+
+/// Interns contig names, so that each distinct name is archived only once, no matter how many
+/// entries contain it. rkyv detects shared names by address, which is unique because the
+/// interned names are kept alive until serialization finished.
+#[derive(Default)]
+pub(super) struct ContigNames {
+    names: HashSet<Arc<[u8]>>,
+}
+
+impl ContigNames {
+    fn intern(&mut self, name: &Arc<[u8]>) -> Arc<[u8]> {
+        match self.names.get(name.as_ref()) {
+            Some(interned) => interned.clone(),
+            None => {
+                self.names.insert(name.clone());
+                name.clone()
+            }
+        }
+    }
+}
+
+impl FastaIndex {
+    /// Names of the contigs in file order.
+    pub(super) fn contig_names(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries.iter().map(|record| record.contig.as_ref())
+    }
+
+    pub(super) fn intern_contigs(&mut self, names: &mut ContigNames) {
+        for record in &mut self.entries {
+            record.contig = names.intern(&record.contig);
+        }
+    }
+
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for record in &self.entries {
+            hasher.update(&(record.contig.len() as u64).to_le_bytes());
+            hasher.update(&record.contig);
+            for value in [
+                record.length,
+                record.offset,
+                record.line_bases,
+                record.line_width,
+            ] {
+                hasher.update(&value.to_le_bytes());
+            }
+        }
+        for position in &self.by_name {
+            hasher.update(&position.to_le_bytes());
+        }
     }
 }
 
 impl ArchivedFastaIndex {
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for record in self.entries.iter() {
+            hasher.update(&(record.contig.len() as u64).to_le_bytes());
+            hasher.update(&record.contig);
+            for value in [
+                record.length,
+                record.offset,
+                record.line_bases,
+                record.line_width,
+            ] {
+                hasher.update(&u64::from(value).to_le_bytes());
+            }
+        }
+        for position in self.by_name.iter() {
+            hasher.update(&u32::from(*position).to_le_bytes());
+        }
+    }
+
+    /// Position of `contig` in file order, by binary search over the sorted positions.
+    pub(super) fn position(&self, contig: &[u8]) -> Option<usize> {
+        let entry = |position: &Archived<u32>| &self.entries[u32::from(*position) as usize];
+        self.by_name
+            .binary_search_by(|position| entry(position).contig.as_ref().cmp(contig))
+            .ok()
+            .map(|i| u32::from(self.by_name[i]) as usize)
+    }
+
+    fn record(&self, contig: &[u8]) -> Option<&ArchivedRecord> {
+        self.position(contig).map(|i| &self.entries[i])
+    }
+
     pub(super) fn contigs(&self) -> Vec<(&[u8], u64)> {
         self.entries
             .iter()
@@ -51,15 +196,168 @@ impl ArchivedFastaIndex {
             .collect()
     }
 
-    pub(super) fn query(&self, contig: &[u8], start: u64) -> Result<u64> {
+    /// Names of the contigs in file order.
+    pub(super) fn contig_names(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries.iter().map(|record| record.contig.as_ref())
+    }
+
+    pub(super) fn contig_length(&self, contig: &[u8]) -> Option<u64> {
+        self.record(contig).map(|record| u64::from(record.length))
+    }
+
+    pub(super) fn num_contigs(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Total length of all contigs.
+    pub(super) fn total_length(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|record| u64::from(record.length))
+            .sum()
+    }
+
+    /// Offset in the uncompressed file after the last base of any contig, which the file must
+    /// reach.
+    pub(super) fn end(&self) -> u64 {
         self.entries
             .iter()
-            .find(|record| record.contig.as_ref() == contig)
-            .ok_or(anyhow::anyhow!("Contig not found"))
             .map(|record| {
-                record.offset
-                    + start / record.line_bases * record.line_width
-                    + start % record.line_bases
+                end(
+                    record.offset.into(),
+                    record.length.into(),
+                    record.line_bases.into(),
+                    record.line_width.into(),
+                )
             })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The number of bases and bytes per line of `contig`, whose last line may be shorter.
+    pub(super) fn line_layout(&self, contig: &[u8]) -> Option<(u64, u64)> {
+        self.record(contig)
+            .map(|record| (u64::from(record.line_bases), u64::from(record.line_width)))
+    }
+
+    /// Offset of `start` in the uncompressed file, checking that `length` nucleotides fit.
+    pub(super) fn query(&self, contig: &[u8], start: u64, length: u64) -> Result<u64> {
+        let record = self
+            .record(contig)
+            .ok_or_else(|| Error::ContigNotFound(String::from_utf8_lossy(contig).into_owned()))?;
+        let contig_length = u64::from(record.length);
+        if start
+            .checked_add(length)
+            .is_none_or(|end| end > contig_length)
+        {
+            bail!(Error::OutOfBounds {
+                contig: String::from_utf8_lossy(contig).into_owned(),
+                start,
+                length,
+                contig_length,
+            });
+        }
+        let (offset, line_bases, line_width) = (
+            u64::from(record.offset),
+            u64::from(record.line_bases),
+            u64::from(record.line_width),
+        );
+        // Empty contigs may have no line layout
+        if contig_length == 0 {
+            return Ok(offset);
+        }
+        if line_bases == 0 {
+            bail!(
+                "Contig {} has lines of 0 bases in the .fai index",
+                String::from_utf8_lossy(contig)
+            );
+        }
+        (start / line_bases)
+            .checked_mul(line_width)
+            .and_then(|bytes| bytes.checked_add(start % line_bases))
+            .and_then(|bytes| bytes.checked_add(offset))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Base {} of contig {} is beyond the largest offset, with lines of {} bytes \
+                     from offset {} in the .fai index",
+                    start,
+                    String::from_utf8_lossy(contig),
+                    line_width,
+                    offset
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(contigs: &[&str]) -> FastaIndex {
+        let entries = contigs
+            .iter()
+            .map(|contig| Record {
+                contig: contig.as_bytes().to_vec().into(),
+                length: 100,
+                offset: 0,
+                line_bases: 60,
+                line_width: 61,
+            })
+            .collect::<Vec<_>>();
+        let by_name = sorted_positions(entries.iter().map(|record| record.contig.as_ref()));
+        FastaIndex { entries, by_name }
+    }
+
+    #[test]
+    fn test_intern_contigs() {
+        let mut indices = vec![index(&["chr2", "chr1"]), index(&["chr1", "chrM"])];
+        let mut names = ContigNames::default();
+        for index in &mut indices {
+            index.intern_contigs(&mut names);
+        }
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&indices).unwrap();
+        let archived =
+            rkyv::access::<rkyv::Archived<Vec<FastaIndex>>, rkyv::rancor::Error>(&bytes).unwrap();
+        let chr1 = |index: &ArchivedFastaIndex| index.record(b"chr1").unwrap().contig.as_ptr();
+        assert_eq!(chr1(&archived[0]), chr1(&archived[1]));
+        assert_eq!(archived[1].contig_length(b"chrM"), Some(100));
+        assert_eq!(archived[0].contig_length(b"chrM"), None);
+    }
+
+    #[test]
+    fn test_check() {
+        // 100 bases in lines of 60 end after 60 + 1 + 40 bytes
+        let mut index = index(&["chr1"]);
+        assert!(index.check(101).is_ok());
+        let error = index.check(100).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Contig chr1 ends at offset 101, but the uncompressed file has 100 bytes"
+        );
+        index.entries[0].line_width = 59;
+        assert!(index.check(1000).is_err());
+        index.entries[0].offset = u64::MAX - 10;
+        assert!(index.check(u64::MAX).is_err());
+    }
+
+    #[test]
+    fn test_query_large_offsets() {
+        let mut index = index(&["chr1", "chr2", "chr3"]);
+        // A contig beyond 4 GiB, as in wheat or axolotl genomes
+        index.entries[0].length = 10_000_000_000;
+        index.entries[0].offset = 6;
+        index.entries[1].length = 0;
+        index.entries[1].line_bases = 0;
+        index.entries[2].offset = u64::MAX - 50;
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&index).unwrap();
+        let archived = rkyv::access::<ArchivedFastaIndex, rkyv::rancor::Error>(&bytes).unwrap();
+        assert_eq!(
+            archived.query(b"chr1", 9_999_999_999, 1).unwrap(),
+            6 + 166_666_666 * 61 + 39
+        );
+        assert!(archived.query(b"chr1", u64::MAX, 2).is_err());
+        assert_eq!(archived.query(b"chr2", 0, 0).unwrap(), 0);
+        let error = archived.query(b"chr3", 99, 1).unwrap_err();
+        assert!(error.to_string().starts_with("Base 99 of contig chr3"));
     }
 }
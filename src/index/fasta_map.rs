@@ -1,156 +1,975 @@
 use crate::index::bgzf_index::BgzfIndex;
-use crate::index::fasta_index::FastaIndex;
-use noodles::{
-    bgzf::{self, io::Seek, VirtualPosition},
-    fasta,
-};
+use crate::index::composition::{BaseCounts, NUM_BASE_CLASSES};
+use crate::index::digest::{read_digests, scan_contigs, SequenceDigest};
+use crate::index::fasta_index::{ContigNames, FastaIndex};
+use crate::index::integrity::{check_eof_marker, uncompressed_size, verify_bgzf};
+use crate::index::manifest::Manifest;
+use crate::index::metadata::Metadata;
+use crate::index::shards::{shard_of, ArchivedShardDirectory, ShardDirectory};
+use crate::index::{ArchivedMap, ConflictPolicy, SPOT_CHECK_FILES};
+use crate::remote::{is_mirror, open_data};
+use noodles::bgzf::VirtualPosition;
 
 use anyhow::Result;
-use anyhow::{anyhow, Context};
-use indicatif::{ProgressBar, ProgressStyle};
+use anyhow::{anyhow, bail, Context};
+use log::{info, warn};
 use numpy::ndarray::Array1;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 use rayon::prelude::*;
-use rkyv::{Archive, Deserialize, Serialize};
+use rkyv::collections::btree_map::ArchivedBTreeMap;
+use rkyv::munge::munge;
+use rkyv::rancor::{Fallible, Source};
+use rkyv::ser::{Allocator, Writer};
+use rkyv::string::ArchivedString;
+use rkyv::{Archive, Deserialize, Place, Serialize};
+use std::cell::RefCell;
 use std::{
-    collections::BTreeMap,
-    fs::File,
-    io::BufRead,
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    io::Read,
     path::{Path, PathBuf},
 };
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
 
-use crate::util::get_relative_name_without_suffix;
+use crate::bgzf_reader::BgzfReader;
+use crate::error::Error;
+use crate::index::spill::{SpillReader, SpillWriter};
+use crate::metrics::METRICS;
+use crate::pools::{self, Operation};
+use crate::progress::Progress;
+use crate::retry::with_retry;
+use crate::storage::{type_specific_magic, write_direct, ChecksumAlgorithm, Schema};
+use crate::util::{check_name, fnv1a, name_path, NameSelection};
+use crate::windows::{strip_line_breaks, SequenceReader};
 
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct Index {
     gzi: BgzfIndex,
     fai: FastaIndex,
+    metadata: Metadata,
+    /// Digests of the contigs in the order of `fai`, or empty if they were not computed.
+    digests: Vec<SequenceDigest>,
+    /// Base counts of the contigs in the order of `fai`, or empty if they were not computed.
+    composition: Vec<BaseCounts>,
+    /// Checksum over the contents of this entry to localize corruption.
+    checksum: u64,
+}
+
+impl Index {
+    fn new(
+        gzi: BgzfIndex,
+        fai: FastaIndex,
+        metadata: Metadata,
+        digests: Vec<SequenceDigest>,
+        composition: Vec<BaseCounts>,
+    ) -> Self {
+        let mut hasher = Xxh3::new();
+        gzi.hash_into(&mut hasher);
+        fai.hash_into(&mut hasher);
+        metadata.hash_into(&mut hasher);
+        for digest in &digests {
+            digest.hash_into(&mut hasher);
+        }
+        for counts in &composition {
+            counts.hash_into(&mut hasher);
+        }
+        Index {
+            gzi,
+            fai,
+            metadata,
+            digests,
+            composition,
+            checksum: hasher.digest(),
+        }
+    }
+}
+
+impl ArchivedIndex {
+    fn is_damaged(&self) -> bool {
+        let mut hasher = Xxh3::new();
+        self.gzi.hash_into(&mut hasher);
+        self.fai.hash_into(&mut hasher);
+        self.metadata.hash_into(&mut hasher);
+        for digest in self.digests.iter() {
+            digest.hash_into(&mut hasher);
+        }
+        for counts in self.composition.iter() {
+            counts.hash_into(&mut hasher);
+        }
+        hasher.digest() != self.checksum
+    }
 }
 
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub(crate) struct FastaMap {
     map: BTreeMap<String, Index>,
+    manifest: Manifest,
+    /// Contigs shorter than this were left out of the entries, and of entries added later.
+    min_contig_length: u64,
+    /// Whether the entries contain the digests of their contigs.
+    digests: bool,
+    /// Whether the entries contain the base counts of their contigs.
+    composition: bool,
+    /// Whether the indices of the entries were checked, see `FastaIndex::check`.
+    check_indices: bool,
+}
+
+/// Suffixes of the index files which are fingerprinted in the manifest. The metadata sidecar is
+/// optional, so its absence is fingerprinted as well.
+pub(crate) const INDEX_SUFFIXES: &[&str] = &[".fna.gz.gzi", ".fna.gz.fai", METADATA_SUFFIX];
+
+/// Suffix of the optional metadata sidecar of a genome, see `Metadata`.
+const METADATA_SUFFIX: &str = ".meta.tsv";
+
+/// Number of entries which are indexed at once when streaming the map to a file.
+const BATCH_SIZE: usize = 1024;
+
+/// Regions spanning at least this many uncompressed bytes are decompressed in parallel.
+const PARALLEL_SPAN: u64 = 4 << 20;
+
+/// Number of consecutive blocks decompressed by each task of a parallel read.
+const PARALLEL_BLOCKS: usize = 16;
+
+/// The bytes of a region in the uncompressed FASTA file, from its first to its last base with
+/// the line breaks in between, see `ArchivedFastaMap::plan`.
+pub(crate) struct RawSpan {
+    pub(crate) path: PathBuf,
+    /// Checksum of the entry, which changes with the indices when the file is rebuilt, to tell
+    /// the blocks of the file apart from those of earlier versions in the block cache.
+    pub(crate) checksum: u64,
+    /// Position of the first base in the BGZF file.
+    pub(crate) pos: VirtualPosition,
+    /// Uncompressed offset of the first base.
+    offset: u64,
+    /// Number of bytes.
+    pub(crate) len: u64,
+    contig: Vec<u8>,
+    start: u64,
+    /// Bases and bytes per line of the contig, as given by the `.fai`.
+    line_bases: u64,
+    line_width: u64,
+}
+
+impl RawSpan {
+    /// The bases of `raw`, the bytes of this span, without line breaks. Raises an
+    /// `InvalidSequence` error if the lines of the file do not match the `.fai`, e.g., for CRLF
+    /// line endings which it does not count, instead of returning shifted bases.
+    pub(crate) fn strip(&self, mut raw: Vec<u8>) -> Result<Array1<u8>> {
+        let column = self.start % self.line_bases.max(1);
+        if raw.len() as u64 != self.len
+            || !strip_line_breaks(&mut raw, column, self.line_bases, self.line_width)
+        {
+            bail!(Error::InvalidSequence(format!(
+                "The lines of {} around 0-based position {} of {} do not match its .fai index \
+                 of {} bases per line in {} bytes, e.g., because of CRLF line endings or lines of \
+                 irregular width; rebuild the .fai",
+                self.path.display(),
+                self.start,
+                String::from_utf8_lossy(&self.contig),
+                self.line_bases,
+                self.line_width
+            )));
+        }
+        Ok(raw.into())
+    }
+}
+
+impl Schema for FastaMap {
+    const NAME: &'static str = "FastaMap";
+    const VERSION: u32 = 10;
+}
+
+/// Serializes to an `ArchivedFastaMap`, but takes the entries from a spill file instead of memory.
+struct FastaMapStream {
+    len: usize,
+    entries: RefCell<SpillReader<Index>>,
+    contig_names: RefCell<ContigNames>,
+    manifest: Manifest,
+    min_contig_length: u64,
+    digests: bool,
+    composition: bool,
+    check_indices: bool,
+}
+
+impl Schema for FastaMapStream {
+    const NAME: &'static str = FastaMap::NAME;
+    const VERSION: u32 = FastaMap::VERSION;
+}
+
+impl Archive for FastaMapStream {
+    type Archived = ArchivedFastaMap;
+    type Resolver = FastaMapResolver;
+
+    fn resolve(&self, resolver: Self::Resolver, out: Place<Self::Archived>) {
+        munge!(let ArchivedFastaMap {
+            map,
+            manifest,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
+        } = out);
+        ArchivedBTreeMap::resolve_from_len(self.len, resolver.map, map);
+        self.manifest.resolve(resolver.manifest, manifest);
+        self.min_contig_length
+            .resolve(resolver.min_contig_length, min_contig_length);
+        self.digests.resolve(resolver.digests, digests);
+        self.composition.resolve(resolver.composition, composition);
+        self.check_indices
+            .resolve(resolver.check_indices, check_indices);
+    }
+}
+
+impl<S> Serialize<S> for FastaMapStream
+where
+    S: Fallible + Allocator + Writer + ?Sized,
+    S::Error: Source,
+    String: Serialize<S>,
+    Index: Serialize<S>,
+    Manifest: Serialize<S>,
+    u64: Serialize<S>,
+    bool: Serialize<S>,
+{
+    fn serialize(&self, serializer: &mut S) -> std::result::Result<Self::Resolver, S::Error> {
+        let mut entries = self.entries.borrow_mut();
+        let mut contig_names = self.contig_names.borrow_mut();
+        let entries = entries.by_ref().map(|(name, mut index)| {
+            index.fai.intern_contigs(&mut contig_names);
+            (name, index)
+        });
+        let map = ArchivedBTreeMap::<ArchivedString, ArchivedIndex>::serialize_from_ordered_iter::<
+            _,
+            String,
+            Index,
+            String,
+            Index,
+            _,
+        >(entries, serializer)?;
+        let manifest = self.manifest.serialize(serializer)?;
+        let min_contig_length = self.min_contig_length.serialize(serializer)?;
+        let digests = self.digests.serialize(serializer)?;
+        let composition = self.composition.serialize(serializer)?;
+        let check_indices = self.check_indices.serialize(serializer)?;
+        Ok(FastaMapResolver {
+            map,
+            manifest,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
+        })
+    }
 }
 
 impl FastaMap {
+    /// With `digests`, the MD5 and GA4GH digests of all contigs are computed, and with
+    /// `composition` their base counts, which reads every FASTA file completely. With
+    /// `check_indices`, the `.fai` and `.gzi` of each file are checked against each other and
+    /// the block headers at the end of the file, see `FastaIndex::check`.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn build(
         root: &str,
         strict: bool,
         min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
         num_workers: Option<usize>,
-        show_progress: bool,
-        names: Option<Vec<String>>,
+        progress: &Progress,
+        selection: &NameSelection,
     ) -> Result<Self> {
-        let root_path = Path::new(root);
-        let names = match names {
-            None => glob::glob(format!("{}/**/*.fna.gz", root).as_str())?
-                .map(|entry| {
-                    entry.map_err(anyhow::Error::from).and_then(|path| {
-                        get_relative_name_without_suffix(&path, root_path, ".fna.gz")
-                    })
-                })
-                .collect::<Result<Vec<_>>>()?,
-            Some(names) => names,
+        let names = selection.resolve(root, ".fna.gz")?;
+        let mut map = BTreeMap::new();
+        Self::index_in_batches(
+            root,
+            &names,
+            strict,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
+            num_workers,
+            progress,
+            names.len().max(1),
+            |batch| {
+                map.extend(batch);
+                Ok(())
+            },
+        )?;
+        let manifest = Manifest::build(Path::new(root), map.keys(), INDEX_SUFFIXES)?;
+        let mut map = FastaMap {
+            map,
+            manifest,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
         };
-        let num_names = names.len();
-
-        // Progress bar setup
-        let pb = if show_progress {
-            let pb = ProgressBar::new(num_names as u64);
-            pb.set_style(
-                ProgressStyle::with_template(
-                    "[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
-                )
-                .unwrap()
-                .progress_chars("##-"),
-            );
-            Some(pb)
+        map.intern_contigs();
+        Ok(map)
+    }
+
+    /// Build the map and write it to the cache file at `path` without holding all entries in
+    /// memory. Entries are indexed in batches and spilled to a temporary file, from which they
+    /// are streamed into the archive. The spill file is kept if the build is interrupted, and
+    /// with `resume`, a later build continues from it.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn build_to_file(
+        root: &str,
+        strict: bool,
+        min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
+        num_workers: Option<usize>,
+        progress: &Progress,
+        selection: &NameSelection,
+        path: &Path,
+        algorithm: ChecksumAlgorithm,
+        resume: bool,
+    ) -> Result<()> {
+        let mut names = selection.resolve(root, ".fna.gz")?;
+        // The archived map is written in key order
+        names.sort();
+        names.dedup();
+        let mut spill_path = path.as_os_str().to_owned();
+        spill_path.push(".spill");
+        let spill_path = PathBuf::from(spill_path);
+        // A checkpoint can only be resumed by a build of the same type and options
+        let tag = fnv1a(
+            format!(
+                "{:016x}:{}:{}:{}:{}",
+                type_specific_magic::<Self>(),
+                min_contig_length,
+                digests,
+                composition,
+                check_indices
+            )
+            .as_bytes(),
+        );
+        let checkpoint = if resume {
+            SpillWriter::resume(&spill_path, tag)?
         } else {
             None
         };
-
-        // Build indices in parallel using rayon. If num_workers is set, use a custom thread pool.
-        let build_indices = || {
-            let results: Result<Vec<Option<(String, Index)>>, anyhow::Error> = names
-                .par_iter()
-                .map(|name| {
-                    let res = match Self::index_name(name, Path::new(root), min_contig_length) {
-                        Ok(index) => Ok(Some((name.to_string(), index))),
-                        Err(e) => {
-                            if strict {
-                                Err(e.context(format!("Error processing track! {}", name)))
-                            } else {
-                                eprintln!(
-                                    "Error processing track: {}. Skipping. Error: {:?}",
-                                    name, e
-                                );
-                                Ok(None)
-                            }
-                        }
-                    };
-                    if let Some(pb) = &pb {
-                        pb.inc(1);
+        let (mut spill, mut indexed) = match checkpoint {
+            Some((spill, done)) => {
+                match Self::checkpoint_stale_reason(root, &names, &done, spill.started_ns()) {
+                    None => {
+                        info!("Resuming build from checkpoint with {} entries", done.len());
+                        (spill, done)
+                    }
+                    Some(reason) => {
+                        warn!("Discarding build checkpoint ({})", reason);
+                        (SpillWriter::create(&spill_path, tag)?, Vec::new())
                     }
-                    res
-                })
-                .collect();
-            results
+                }
+            }
+            None => (SpillWriter::create(&spill_path, tag)?, Vec::new()),
         };
+        // Names are indexed in order, so the checkpoint covers all names up to its last entry
+        let start = indexed
+            .last()
+            .map_or(0, |last| names.partition_point(|name| name <= last));
+        Self::index_in_batches(
+            root,
+            &names[start..],
+            strict,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
+            num_workers,
+            progress,
+            BATCH_SIZE,
+            |batch| {
+                for entry in batch {
+                    spill.push(&entry)?;
+                    indexed.push(entry.0);
+                }
+                spill.checkpoint()
+            },
+        )?;
+        let manifest = Manifest::build(Path::new(root), indexed.iter(), INDEX_SUFFIXES)?;
+        let stream = FastaMapStream {
+            len: indexed.len(),
+            entries: RefCell::new(spill.finish()?),
+            contig_names: RefCell::default(),
+            manifest,
+            min_contig_length,
+            digests,
+            composition,
+            check_indices,
+        };
+        let result = write_direct(&stream, path, algorithm);
+        // A read error in the spill file surfaces as a length mismatch in rkyv, so report it first
+        stream.entries.into_inner().finish()?;
+        result
+    }
 
-        let results = if let Some(workers) = num_workers {
-            let pool = rayon::ThreadPoolBuilder::new()
-                .num_threads(workers)
-                .build()
-                .unwrap();
-            pool.install(build_indices)?
-        } else {
-            build_indices()?
+    /// Returns a reason if the entries of a build checkpoint cannot be reused, because they are
+    /// out of order or their index files were removed or modified after the build started.
+    fn checkpoint_stale_reason(
+        root: &str,
+        names: &[String],
+        done: &[String],
+        started_ns: u64,
+    ) -> Option<String> {
+        if done.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Some("entries are out of order".to_string());
+        }
+        if let Some(name) = done.iter().find(|name| names.binary_search(name).is_err()) {
+            return Some(format!("{} was removed", name));
+        }
+        match Manifest::build(Path::new(root), done, INDEX_SUFFIXES) {
+            Ok(manifest) => manifest
+                .modified_since(started_ns)
+                .map(|path| format!("{} was modified", path)),
+            Err(e) => Some(format!("{:?}", e)),
+        }
+    }
+
+    /// Index `names` in batches of `batch_size` and pass the successfully indexed entries of
+    /// each batch to `sink`, in the order of `names`.
+    #[allow(clippy::too_many_arguments)]
+    fn index_in_batches(
+        root: &str,
+        names: &[String],
+        strict: bool,
+        min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
+        num_workers: Option<usize>,
+        progress: &Progress,
+        batch_size: usize,
+        mut sink: impl FnMut(Vec<(String, Index)>) -> Result<()>,
+    ) -> Result<()> {
+        let tracker = progress.start(names.len() as u64);
+
+        // If num_workers is set, use a custom thread pool, or else the build pool if one is set
+        let pool = match num_workers {
+            Some(workers) => Some(pools::new_pool(workers, Operation::Build)?),
+            None => pools::pool(Operation::Build),
         };
 
-        if let Some(pb) = pb {
-            pb.finish_with_message("Indexing complete");
+        for batch in names.chunks(batch_size) {
+            // Build indices of this batch in parallel using rayon
+            let build_indices = || {
+                let results: Result<Vec<Option<(String, Index)>>, anyhow::Error> = batch
+                    .par_iter()
+                    .map(|name| {
+                        tracker.check_cancelled()?;
+                        let index = Self::index_name(
+                            name,
+                            Path::new(root),
+                            min_contig_length,
+                            digests,
+                            composition,
+                            check_indices,
+                        );
+                        let res = match index {
+                            Ok(index) => Ok(Some((name.to_string(), index))),
+                            Err(e) => {
+                                if strict {
+                                    Err(e.context(format!("Error processing FASTA file {}", name)))
+                                } else {
+                                    warn!(
+                                        "Error processing FASTA file {}. Skipping. Error: {:?}",
+                                        name, e
+                                    );
+                                    tracker.skip(name, format!("{:#}", e));
+                                    Ok(None)
+                                }
+                            }
+                        };
+                        tracker.inc(name);
+                        res
+                    })
+                    .collect();
+                results
+            };
+
+            let results = if let Some(pool) = &pool {
+                pool.install(build_indices)?
+            } else {
+                build_indices()?
+            };
+            sink(results.into_iter().flatten().collect())?;
         }
-        let map = results.into_iter().flatten().collect::<BTreeMap<_, _>>();
-        Ok(FastaMap { map })
+
+        tracker.finish();
+        Ok(())
     }
 
-    fn index_name(name: &str, root: &Path, min_contig_length: u64) -> Result<Index> {
-        let gzi = BgzfIndex::read(root.join(format!("{}.fna.gz.gzi", name)))
+    /// Combine two maps built from the same root. Names present in both are handled by `policy`.
+    pub(crate) fn merge(mut self, other: Self, policy: ConflictPolicy) -> Result<Self> {
+        if self.min_contig_length != other.min_contig_length {
+            bail!(
+                "The maps were built with different minimum contig lengths, {} and {}",
+                self.min_contig_length,
+                other.min_contig_length
+            );
+        }
+        for (name, index) in other.map {
+            match self.map.entry(name) {
+                Entry::Vacant(entry) => {
+                    entry.insert(index);
+                }
+                Entry::Occupied(entry) => {
+                    if policy == ConflictPolicy::Error {
+                        bail!("Name {} is present in both maps", entry.key());
+                    }
+                }
+            }
+        }
+        self.manifest.merge(other.manifest);
+        self.digests &= other.digests;
+        self.composition &= other.composition;
+        self.check_indices &= other.check_indices;
+        self.intern_contigs();
+        Ok(self)
+    }
+
+    /// Split the map into `num_shards` maps by name hash and a directory of all names.
+    /// The manifest is only kept in the directory.
+    pub(crate) fn into_shards(self, num_shards: usize) -> (ShardDirectory, Vec<Self>) {
+        let names = self.map.keys().cloned().collect();
+        let directory = ShardDirectory::new(names, num_shards, self.manifest);
+        let mut shards = (0..num_shards)
+            .map(|_| FastaMap {
+                map: BTreeMap::new(),
+                manifest: Manifest::default(),
+                min_contig_length: self.min_contig_length,
+                digests: self.digests,
+                composition: self.composition,
+                check_indices: self.check_indices,
+            })
+            .collect::<Vec<_>>();
+        for (name, index) in self.map {
+            shards[shard_of(&name, num_shards)].map.insert(name, index);
+        }
+        (directory, shards)
+    }
+
+    /// Check whether the index files below `root` changed since the sharded map was built.
+    /// Only the files in `selection` are checked.
+    pub(crate) fn directory_stale_reason(
+        directory: &ArchivedShardDirectory,
+        root: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>> {
+        directory.stale_reason(root, selection, ".fna.gz", INDEX_SUFFIXES)
+    }
+
+    /// Re-index the given names with the options the map was built with, e.g., after their
+    /// cache entries were found to be damaged. Their indices are checked if those of the map were.
+    pub(crate) fn rebuild_entries(&mut self, root: &str, names: &[String]) -> Result<()> {
+        for name in names {
+            let index = Self::index_name(
+                name,
+                Path::new(root),
+                self.min_contig_length,
+                self.digests,
+                self.composition,
+                self.check_indices,
+            )
+            .with_context(|| format!("Error rebuilding entry {}", name))?;
+            self.map.insert(name.clone(), index);
+        }
+        self.intern_contigs();
+        Ok(())
+    }
+
+    /// Index the given names, e.g., genomes added to the root since the map was built, with the
+    /// options the map was built with, and record their index files in the manifest. Names which
+    /// are in the map are re-indexed.
+    pub(crate) fn add_entries(&mut self, root: &str, names: &[String]) -> Result<()> {
+        for name in names {
+            check_name(name)?;
+        }
+        self.rebuild_entries(root, names)?;
+        self.manifest.remove(names, INDEX_SUFFIXES);
+        self.manifest
+            .merge(Manifest::build(Path::new(root), names, INDEX_SUFFIXES)?);
+        Ok(())
+    }
+
+    /// Drop the given names and their index files from the manifest.
+    pub(crate) fn remove_entries(&mut self, names: &[String]) -> Result<()> {
+        for name in names {
+            if self.map.remove(name).is_none() {
+                bail!(Error::NameNotFound(name.clone()));
+            }
+        }
+        self.manifest.remove(names, INDEX_SUFFIXES);
+        Ok(())
+    }
+
+    /// Validate the manifest of a map built on another machine against the files below `root`,
+    /// see `Manifest::localize`.
+    pub(crate) fn localize(&mut self, root: &str) -> Result<()> {
+        self.manifest = self.manifest.localize(Path::new(root))?;
+        Ok(())
+    }
+
+    /// Share the names of contigs between all entries, see `ContigNames`.
+    fn intern_contigs(&mut self) {
+        let mut names = ContigNames::default();
+        for index in self.map.values_mut() {
+            index.fai.intern_contigs(&mut names);
+        }
+    }
+
+    fn index_name(
+        name: &str,
+        root: &Path,
+        min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
+    ) -> Result<Index> {
+        let _span = tracing::info_span!("index", name).entered();
+        let gzi = BgzfIndex::read(name_path(root, &format!("{}.fna.gz.gzi", name)))
             .context("Failed to read .gzi")?;
-        let fai = FastaIndex::read(root.join(format!("{}.fna.gz.fai", name)), min_contig_length)
-            .context("Failed to read .fai")?;
-        Ok(Index { gzi, fai })
+        let fai = FastaIndex::read(
+            name_path(root, &format!("{}.fna.gz.fai", name)),
+            min_contig_length,
+        )
+        .context("Failed to read .fai")?;
+        let path = name_path(root, &format!("{}.fna.gz", name));
+        // The data files in the mirror of a remote root are empty placeholders
+        if !is_mirror(root) {
+            check_eof_marker(&path)?;
+        }
+        if check_indices {
+            let size = uncompressed_size(&path, gzi.last()?).context("The .gzi does not match")?;
+            fai.check(size).context("The .fai does not match")?;
+        }
+        let metadata = Metadata::read(name_path(root, &format!("{}{}", name, METADATA_SUFFIX)))
+            .context("Failed to read metadata")?;
+        let digests = if digests {
+            let by_contig = read_digests(&path).context("Failed to compute digests")?;
+            in_fai_order(&fai, by_contig)?
+        } else {
+            Vec::new()
+        };
+        let composition = if composition {
+            let by_contig = scan_contigs(&path, BaseCounts::default, BaseCounts::update)
+                .context("Failed to count bases")?;
+            in_fai_order(&fai, by_contig)?
+        } else {
+            Vec::new()
+        };
+        Ok(Index::new(gzi, fai, metadata, digests, composition))
     }
 }
 
-impl ArchivedFastaMap {
-    pub(crate) fn names(&self) -> Vec<&str> {
+/// Values computed per contig of a FASTA file, in the order of the contigs of `fai`.
+fn in_fai_order<T>(fai: &FastaIndex, mut by_contig: HashMap<Vec<u8>, T>) -> Result<Vec<T>> {
+    fai.contig_names()
+        .map(|contig| {
+            by_contig.remove(contig).ok_or_else(|| {
+                anyhow!(
+                    "Contig {} of the .fai is missing from the FASTA file",
+                    String::from_utf8_lossy(contig)
+                )
+            })
+        })
+        .collect()
+}
+
+impl ArchivedMap for ArchivedFastaMap {
+    fn names(&self) -> Vec<&str> {
         self.map.keys().map(|s| s.as_str()).collect()
     }
+    fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    fn num_contigs(&self) -> usize {
+        self.map.values().map(|index| index.fai.num_contigs()).sum()
+    }
+
+    fn total_length(&self) -> u64 {
+        self.map
+            .values()
+            .map(|index| index.fai.total_length())
+            .sum()
+    }
+
+    fn contigs(&self, name: &str) -> Result<Vec<(&[u8], u64)>> {
+        ArchivedFastaMap::contigs(self, name)
+    }
+
+    fn spot_check(&self, root: &Path) -> Option<String> {
+        self.manifest.spot_check(root, SPOT_CHECK_FILES)
+    }
+
+    fn manifest(&self) -> Vec<(&str, Option<u64>, u64)> {
+        self.manifest.files()
+    }
+
+    fn checksum(&self, name: &str) -> Option<u64> {
+        self.map.get(name).map(|index| u64::from(index.checksum))
+    }
+}
+
+impl ArchivedFastaMap {
+    /// Names of all entries whose contents do not match their checksum.
+    pub(crate) fn damaged_entries(&self) -> Vec<String> {
+        self.map
+            .iter()
+            .filter(|(_, index)| index.is_damaged())
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    /// Check whether the index files below `root` changed since this map was built.
+    /// Only the files in `selection` are checked.
+    pub(crate) fn stale_reason(
+        &self,
+        root: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>> {
+        let names = selection.resolve(root, ".fna.gz")?;
+        Ok(self
+            .manifest
+            .stale_reason(Path::new(root), &names, INDEX_SUFFIXES))
+    }
+
+    /// Returns a reason if this map does not match what a load requests, i.e., it was built with
+    /// another `min_contig_length`, or it has no digests or composition or its indices were not
+    /// checked although this is requested, so that it has to be rebuilt.
+    pub(crate) fn options_reason(
+        &self,
+        min_contig_length: u64,
+        digests: bool,
+        composition: bool,
+        check_indices: bool,
+    ) -> Option<String> {
+        let built = u64::from(self.min_contig_length);
+        if built != min_contig_length {
+            return Some(format!(
+                "min_contig_length {} instead of {}",
+                built, min_contig_length
+            ));
+        }
+        if digests && !self.digests {
+            return Some("it has no digests".to_string());
+        }
+        if composition && !self.composition {
+            return Some("it has no composition".to_string());
+        }
+        if check_indices && !self.check_indices {
+            return Some("its indices were not checked".to_string());
+        }
+        None
+    }
 
     pub(crate) fn contigs(&self, name: &str) -> Result<Vec<(&[u8], u64)>> {
         let entry = self
             .map
             .get(name)
-            .ok_or(anyhow::anyhow!(format!("Fasta name not found: {}", name)))?;
+            .ok_or_else(|| Error::NameNotFound(name.to_string()))?;
         Ok(entry.fai.contigs())
     }
 
+    /// Length of `contig` in `name`, or `None` if there is no such contig.
+    pub(crate) fn contig_length(&self, name: &str, contig: &[u8]) -> Result<Option<u64>> {
+        let entry = self
+            .map
+            .get(name)
+            .ok_or_else(|| Error::NameNotFound(name.to_string()))?;
+        Ok(entry.fai.contig_length(contig))
+    }
+
+    /// Fields of the metadata sidecar of `name`, sorted by key.
+    pub(crate) fn metadata(&self, name: &str) -> Result<Vec<(&str, &str)>> {
+        let entry = self
+            .map
+            .get(name)
+            .ok_or_else(|| Error::NameNotFound(name.to_string()))?;
+        Ok(entry.metadata.fields())
+    }
+
+    /// Value of the metadata field `key` of `name`, if it is set.
+    pub(crate) fn metadata_value(&self, name: &str, key: &str) -> Result<Option<&str>> {
+        let entry = self
+            .map
+            .get(name)
+            .ok_or_else(|| Error::NameNotFound(name.to_string()))?;
+        Ok(entry.metadata.get(key))
+    }
+
+    /// MD5 and GA4GH digest of `contig` in `name`.
+    pub(crate) fn digest(&self, name: &str, contig: &[u8]) -> Result<(String, String)> {
+        let entry = self
+            .map
+            .get(name)
+            .ok_or_else(|| Error::NameNotFound(name.to_string()))?;
+        if !self.digests {
+            bail!("The FASTA map was built without digests");
+        }
+        let position = entry
+            .fai
+            .position(contig)
+            .ok_or_else(|| Error::ContigNotFound(String::from_utf8_lossy(contig).into_owned()))?;
+        let digest = &entry.digests[position];
+        Ok((digest.md5(), digest.sha512t24u()))
+    }
+
+    /// Contigs of `name` with their counts of `A`, `C`, `G`, `T` and other bases, in file order.
+    pub(crate) fn composition(&self, name: &str) -> Result<Vec<(&[u8], [u64; NUM_BASE_CLASSES])>> {
+        let entry = self
+            .map
+            .get(name)
+            .ok_or_else(|| Error::NameNotFound(name.to_string()))?;
+        if !self.composition {
+            bail!("The FASTA map was built without composition");
+        }
+        Ok(entry
+            .fai
+            .contig_names()
+            .zip(entry.composition.iter())
+            .map(|(contig, counts)| (contig, counts.counts()))
+            .collect())
+    }
+
+    /// Contigs of `name` with their MD5 and GA4GH digest, in file order.
+    pub(crate) fn digests(&self, name: &str) -> Result<Vec<(&[u8], String, String)>> {
+        let entry = self
+            .map
+            .get(name)
+            .ok_or_else(|| Error::NameNotFound(name.to_string()))?;
+        if !self.digests {
+            bail!("The FASTA map was built without digests");
+        }
+        Ok(entry
+            .fai
+            .contig_names()
+            .zip(entry.digests.iter())
+            .map(|(contig, digest)| (contig, digest.md5(), digest.sha512t24u()))
+            .collect())
+    }
+
+    /// Problems of the FASTA file of `name` below `root` which its indices do not match, see
+    /// `verify_bgzf`. With `deep`, the number of blocks to decompress and a seed, which is
+    /// combined with the name so that each file checks different blocks.
+    pub(crate) fn verify(
+        &self,
+        root: &str,
+        name: &str,
+        deep: Option<(usize, u64)>,
+    ) -> Result<Vec<String>> {
+        check_name(name)?;
+        let entry = self
+            .map
+            .get(name)
+            .ok_or_else(|| Error::NameNotFound(name.to_string()))?;
+        let path = name_path(Path::new(root), &format!("{}.fna.gz", name));
+        let mut rng;
+        let deep = match deep {
+            Some((num_blocks, seed)) => {
+                rng = ChaCha8Rng::seed_from_u64(seed ^ xxh3_64(name.as_bytes()));
+                Some((num_blocks, &mut rng))
+            }
+            None => None,
+        };
+        verify_bgzf(&path, &entry.gzi.records()?, entry.fai.end(), deep)
+    }
+
     pub(crate) fn query(
         &self,
         root: &str,
         fasta_name: &str,
         contig: &[u8],
         start: u64,
+        length: u64,
     ) -> Result<(PathBuf, VirtualPosition)> {
+        let span = self.plan(root, fasta_name, contig, start, length)?;
+        Ok((span.path, span.pos))
+    }
+
+    /// Plan the exact bytes of a region in the uncompressed file from the line geometry of the
+    /// `.fai`, to read them at once instead of reading until enough bases were found.
+    pub(crate) fn plan(
+        &self,
+        root: &str,
+        fasta_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> Result<RawSpan> {
+        let _span = tracing::debug_span!("query", name = fasta_name, start, length).entered();
+        // The name is joined to the root below, so it must not escape it
+        check_name(fasta_name)?;
         // Search in index
         let entry = self
             .map
             .get(fasta_name)
-            .ok_or(anyhow::anyhow!("Fasta name not found"))?;
-        let pos = entry.fai.query(contig, start)?;
+            .ok_or_else(|| Error::NameNotFound(fasta_name.to_string()))?;
+        let pos = entry.fai.query(contig, start, length)?;
         let offset = entry.gzi.query(pos)?;
-        let path = Path::new(root).join(format!("{}.fna.gz", fasta_name));
-        Ok((path, offset))
+        let (line_bases, line_width) = entry.fai.line_layout(contig).unwrap_or_default();
+        // Line breaks are decoded as well, so the span ends after the last base
+        let end = match length {
+            0 => pos,
+            length => entry
+                .fai
+                .query(contig, start + length - 1, 1)?
+                .checked_add(1)
+                .ok_or_else(|| anyhow!("The .fai index of {} overflows", fasta_name))?,
+        };
+        METRICS.record_query(end - pos, entry.gzi.num_blocks(pos, end)?);
+        let path = name_path(Path::new(root), &format!("{}.fna.gz", fasta_name));
+        Ok(RawSpan {
+            path,
+            checksum: u64::from(entry.checksum),
+            pos: offset,
+            offset: pos,
+            len: end - pos,
+            contig: contig.to_vec(),
+            start,
+            line_bases,
+            line_width,
+        })
+    }
+
+    /// Read the bytes of `span` of `fasta_name`, decompressing runs of its blocks in parallel.
+    fn read_raw_parallel(&self, fasta_name: &str, span: &RawSpan) -> Result<Vec<u8>> {
+        let entry = self
+            .map
+            .get(fasta_name)
+            .ok_or_else(|| Error::NameNotFound(fasta_name.to_string()))?;
+        let end = span.offset + span.len;
+        let blocks = entry.gzi.blocks(span.offset, end)?;
+        let mut buf = vec![0; span.len as usize];
+        // Each task reads a run of blocks into its part of `buf`, starting at a block boundary
+        // except for the first
+        let mut tasks = Vec::new();
+        let mut rest = buf.as_mut_slice();
+        for (i, run) in blocks.chunks(PARALLEL_BLOCKS).enumerate() {
+            let (compressed, uncompressed) = run[0];
+            let from = uncompressed.max(span.offset);
+            // The run ends where the next run starts
+            let to = blocks
+                .get((i + 1) * PARALLEL_BLOCKS)
+                .map_or(end, |&(_, next)| next.min(end));
+            let (part, tail) = std::mem::take(&mut rest).split_at_mut((to - from) as usize);
+            let pos = VirtualPosition::try_from((compressed, u16::try_from(from - uncompressed)?))?;
+            tasks.push((pos, part));
+            rest = tail;
+        }
+        tasks.into_par_iter().try_for_each(|(pos, part)| {
+            with_retry(&span.path, || {
+                let mut reader = BgzfReader::new(open_data(&span.path)?);
+                reader.seek_to_virtual_position(pos)?;
+                reader.read_exact(part)?;
+                Ok(())
+            })
+        })?;
+        Ok(buf)
     }
 
     pub(crate) fn read_sequence(
@@ -161,28 +980,40 @@ impl ArchivedFastaMap {
         start: u64,
         length: u64,
     ) -> Result<Array1<u8>> {
-        let (path, pos) = self.query(root, fasta_name, contig, start)?;
-
-        // Open FASTA sequence reader at correct offset
-        let mut bgzf_reader = bgzf::io::Reader::new(File::open(path)?);
-        bgzf_reader.seek_to_virtual_position(pos)?;
-        let mut fasta_reader = fasta::io::Reader::new(bgzf_reader);
-        let mut sequence_reader = fasta_reader.sequence_reader();
-
-        // Read until we have the desired number of nucleotides
-        let mut buf = Vec::with_capacity(length as usize);
-        while buf.len() < length as usize {
-            let src = sequence_reader.fill_buf()?;
-            if src.is_empty() {
-                return Err(anyhow!(
-                    "End of file / sequence reached before reading {} nucleotides",
-                    length
-                ));
-            }
-            let i = (length as usize - buf.len()).min(src.len());
-            buf.extend_from_slice(&src[..i]);
-            sequence_reader.consume(i);
-        }
-        Ok(buf.into())
+        let span = self.plan(root, fasta_name, contig, start, length)?;
+
+        let _span = tracing::debug_span!("decompress", length).entered();
+        // The region spans exactly `span.len` bytes, which are read at once, and in parallel for
+        // long regions, as their blocks are known from the plan
+        let raw = if span.len >= PARALLEL_SPAN {
+            self.read_raw_parallel(fasta_name, &span)?
+        } else {
+            with_retry(&span.path, || {
+                let mut reader = BgzfReader::new(open_data(&span.path)?);
+                reader.seek_to_virtual_position(span.pos)?;
+                let mut raw = Vec::with_capacity(span.len as usize);
+                reader.take(span.len).read_to_end(&mut raw)?;
+                Ok(raw)
+            })?
+        };
+        span.strip(raw)
+    }
+
+    /// Open a reader at `start` of `contig`, to read the following `length` bases sequentially.
+    pub(crate) fn sequence_reader(
+        &self,
+        root: &str,
+        fasta_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> Result<SequenceReader> {
+        let (path, pos) = self.query(root, fasta_name, contig, start, length)?;
+        let reader = with_retry(&path, || {
+            let mut reader = BgzfReader::new(open_data(&path)?);
+            reader.seek_to_virtual_position(pos)?;
+            Ok(reader)
+        })?;
+        Ok(SequenceReader::new(reader, true))
     }
 }
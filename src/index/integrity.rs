@@ -0,0 +1,184 @@
+use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use noodles::bgzf::{self, io::Seek as _, VirtualPosition};
+use rand::seq::index::sample;
+use rand_chacha::ChaCha8Rng;
+
+use crate::index::faidx::{next_block, BGZF_EOF};
+use crate::remote::open_data;
+
+/// Problems of the BGZF file at `path` which its indices do not match, e.g., after a truncated
+/// download: a missing end-of-file marker, and fewer uncompressed bytes after the last block of
+/// the GZI `records` than the FAI index needs to reach `fai_end`. With `deep`, the number of
+/// randomly chosen blocks to decompress and the RNG to choose them, corrupted blocks are found
+/// as well. Errors are only returned if the file cannot be opened.
+pub(super) fn verify_bgzf(
+    path: &Path,
+    records: &[(u64, u64)],
+    fai_end: u64,
+    deep: Option<(usize, &mut ChaCha8Rng)>,
+) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let mut file = open_data(path)?;
+    let size = file.seek(SeekFrom::End(0))?;
+    if !has_eof_marker(&mut file, size) {
+        problems.push("The BGZF end-of-file marker is missing".to_string());
+    }
+
+    let uncompressed = match walk_blocks(&mut file, records.last().copied()) {
+        Ok((compressed, uncompressed)) => {
+            if compressed != size {
+                problems.push(format!(
+                    "The .gzi index ends at offset {}, but the file has {} bytes",
+                    compressed, size
+                ));
+            }
+            uncompressed
+        }
+        Err((e, (_, uncompressed))) => {
+            problems.push(format!("{:#}", e));
+            uncompressed
+        }
+    };
+    if uncompressed < fai_end {
+        problems.push(format!(
+            "The .fai index needs {} uncompressed bytes, but the file has {}",
+            fai_end, uncompressed
+        ));
+    }
+
+    if let Some((num_blocks, rng)) = deep {
+        let mut reader = bgzf::io::Reader::new(open_data(path)?);
+        let num_blocks = num_blocks.min(records.len() + 1);
+        for i in sample(rng, records.len() + 1, num_blocks).into_iter() {
+            // The first block is not part of the GZI index
+            let offset = match i {
+                0 => 0,
+                i => records[i - 1].0,
+            };
+            let result = VirtualPosition::try_from((offset, 0))
+                .map_err(anyhow::Error::from)
+                .and_then(|position| Ok(reader.seek_to_virtual_position(position)?))
+                .and_then(|_| Ok(reader.fill_buf()?.len()));
+            if let Err(e) = result {
+                problems.push(format!(
+                    "The block at offset {} is corrupted: {}",
+                    offset, e
+                ));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// Whether `file` of `size` bytes ends with the BGZF end-of-file marker.
+fn has_eof_marker(file: &mut (impl Read + Seek), size: u64) -> bool {
+    let mut eof = [0u8; BGZF_EOF.len()];
+    size >= eof.len() as u64
+        && file.seek(SeekFrom::End(-(eof.len() as i64))).is_ok()
+        && file.read_exact(&mut eof).is_ok()
+        && eof == BGZF_EOF
+}
+
+/// Check that the BGZF file at `path` ends with the end-of-file marker, as written by `bgzip`.
+/// Files concatenated from several BGZF files also have the markers of all but the last one in
+/// between, which reads skip.
+pub(super) fn check_eof_marker(path: &Path) -> Result<()> {
+    let mut file = open_data(path)?;
+    let size = file.seek(SeekFrom::End(0))?;
+    if !has_eof_marker(&mut file, size) {
+        bail!(
+            "{} does not end with the BGZF end-of-file marker, so it is truncated or was not \
+             compressed with bgzip",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Size of the uncompressed BGZF file at `path`, from the `last` record of its GZI index and
+/// the headers of the blocks after it, which rejects a GZI index of another file.
+pub(super) fn uncompressed_size(path: &Path, last: Option<(u64, u64)>) -> Result<u64> {
+    let mut file = open_data(path)?;
+    let size = file.seek(SeekFrom::End(0))?;
+    match walk_blocks(&mut file, last) {
+        Ok((compressed, uncompressed)) if compressed == size => Ok(uncompressed),
+        Ok((compressed, _)) => bail!(
+            "The .gzi index ends at offset {}, but the file has {} bytes",
+            compressed,
+            size
+        ),
+        Err((e, _)) => Err(e),
+    }
+}
+
+/// Walk the blocks of `file` from the `last` record of its GZI index, or the start, to the
+/// end and return the compressed and uncompressed offsets of the end. On an invalid block,
+/// its error is returned with the offsets before it.
+fn walk_blocks(
+    file: &mut (impl Read + Seek),
+    last: Option<(u64, u64)>,
+) -> std::result::Result<(u64, u64), (anyhow::Error, (u64, u64))> {
+    let (mut compressed, mut uncompressed) = last.unwrap_or((0, 0));
+    if let Err(e) = file.seek(SeekFrom::Start(compressed)) {
+        return Err((e.into(), (compressed, uncompressed)));
+    }
+    loop {
+        match next_block(file, compressed) {
+            Ok(Some((block_size, block_uncompressed))) => {
+                compressed += block_size;
+                uncompressed += block_uncompressed;
+            }
+            Ok(None) => return Ok((compressed, uncompressed)),
+            Err(e) => return Err((e, (compressed, uncompressed))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::check_eof_marker;
+    use crate::index::FastaMap;
+    use crate::progress::Progress;
+    use crate::storage::{ArchiveStorage, MemoryStorage};
+    use crate::util::{copy_test_data, NameSelection};
+
+    #[test]
+    fn test_verify() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let data = FastaMap::build(
+            root,
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        let container: ArchiveStorage<FastaMap, MemoryStorage> = ArchiveStorage::new(data).unwrap();
+        let archive = container.as_ref();
+        let name = "GCA_000146045.2";
+        assert!(archive.verify(root, name, None).unwrap().is_empty());
+        assert!(archive.verify(root, name, Some((4, 0))).unwrap().is_empty());
+
+        // Truncating the file loses the end-of-file marker and the end of the last contig
+        let path = Path::new(root).join(format!("{}.fna.gz", name));
+        let content = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &content[..content.len() / 2]).unwrap();
+        let problems = archive.verify(root, name, None).unwrap();
+        assert_eq!(problems[0], "The BGZF end-of-file marker is missing");
+        assert!(check_eof_marker(&path).is_err());
+        assert!(problems
+            .iter()
+            .any(|p| p.starts_with("The .fai index needs")));
+        assert!(archive.verify(root, "missing", None).is_err());
+    }
+}
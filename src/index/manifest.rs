@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context, Result};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::util::name_path;
+
+/// Size and modification time of a single source file.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct FileFingerprint {
+    size: u64,
+    mtime_ns: u64,
+}
+
+impl FileFingerprint {
+    /// Fingerprint of a file which does not exist, e.g., an optional sidecar.
+    const MISSING: FileFingerprint = FileFingerprint {
+        size: u64::MAX,
+        mtime_ns: 0,
+    };
+
+    fn read(path: &Path) -> Result<Self> {
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::MISSING),
+            Err(e) => return Err(e).with_context(|| format!("Failed to stat {}", path.display())),
+        };
+        let mtime_ns = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Ok(FileFingerprint {
+            size: metadata.len(),
+            mtime_ns,
+        })
+    }
+}
+
+/// Fingerprints of all index files a map was built from, keyed by their path relative to root.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub(super) struct Manifest {
+    files: BTreeMap<String, FileFingerprint>,
+}
+
+impl Manifest {
+    pub(super) fn build<'a>(
+        root: &Path,
+        names: impl IntoIterator<Item = &'a String>,
+        suffixes: &[&str],
+    ) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for name in names {
+            for suffix in suffixes {
+                let relative = format!("{}{}", name, suffix);
+                let fingerprint = FileFingerprint::read(&name_path(root, &relative))?;
+                files.insert(relative, fingerprint);
+            }
+        }
+        Ok(Manifest { files })
+    }
+
+    /// Add the files of `other`, keeping the existing fingerprints of files present in both.
+    pub(super) fn merge(&mut self, other: Manifest) {
+        for (path, fingerprint) in other.files {
+            self.files.entry(path).or_insert(fingerprint);
+        }
+    }
+
+    /// Drop the files of `names`, e.g., of entries removed from the map.
+    pub(super) fn remove<'a>(
+        &mut self,
+        names: impl IntoIterator<Item = &'a String>,
+        suffixes: &[&str],
+    ) {
+        for name in names {
+            for suffix in suffixes {
+                self.files.remove(&format!("{}{}", name, suffix));
+            }
+        }
+    }
+
+    /// Path of the first file which was modified at or after `time_ns`, if any.
+    pub(super) fn modified_since(&self, time_ns: u64) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|(_, fingerprint)| fingerprint.mtime_ns >= time_ns)
+            .map(|(path, _)| path.as_str())
+    }
+
+    /// Check that the files below another `root` match this manifest, e.g., after copying a
+    /// cache to another machine, and return the manifest with their local fingerprints.
+    /// Modification times differ between copies, so only presence and sizes are compared.
+    pub(super) fn localize(&self, root: &Path) -> Result<Manifest> {
+        let mut files = BTreeMap::new();
+        for (relative, fingerprint) in &self.files {
+            let local = FileFingerprint::read(&name_path(root, relative))?;
+            if local.size != fingerprint.size {
+                if local == FileFingerprint::MISSING {
+                    bail!("{} is missing", relative);
+                } else if fingerprint.size == FileFingerprint::MISSING.size {
+                    bail!("{} is new", relative);
+                }
+                bail!(
+                    "{} has {} bytes instead of {}",
+                    relative,
+                    local.size,
+                    fingerprint.size
+                );
+            }
+            files.insert(relative.clone(), local);
+        }
+        Ok(Manifest { files })
+    }
+}
+
+impl ArchivedManifest {
+    /// All files as path relative to the root, size, or `None` if the file was missing, and
+    /// modification time in nanoseconds since the epoch.
+    pub(super) fn files(&self) -> Vec<(&str, Option<u64>, u64)> {
+        self.files
+            .iter()
+            .map(|(relative, fingerprint)| {
+                let size = fingerprint.size.to_native();
+                let size = (size != FileFingerprint::MISSING.size).then_some(size);
+                (relative.as_str(), size, fingerprint.mtime_ns.to_native())
+            })
+            .collect()
+    }
+
+    /// Compare the sizes of up to `count` present files, spread over the manifest, against
+    /// `root`, e.g., to detect that a handle is attached to the wrong root. Modification times
+    /// are not compared, as moving or copying a root may change them.
+    pub(super) fn spot_check(&self, root: &Path, count: usize) -> Option<String> {
+        let present = self
+            .files
+            .iter()
+            .filter(|(_, fingerprint)| fingerprint.size != FileFingerprint::MISSING.size)
+            .collect::<Vec<_>>();
+        let step = present.len().div_ceil(count).max(1);
+        for (relative, archived) in present.into_iter().step_by(step) {
+            let current = FileFingerprint::read(&name_path(root, relative.as_str()))
+                .unwrap_or(FileFingerprint::MISSING);
+            if current == FileFingerprint::MISSING {
+                return Some(format!("{} is missing", relative));
+            }
+            if current.size != archived.size {
+                return Some(format!(
+                    "{} has {} bytes instead of {}",
+                    relative,
+                    current.size,
+                    archived.size.to_native()
+                ));
+            }
+        }
+        None
+    }
+
+    /// Compare the manifest against the current state of `root`.
+    /// Returns a human-readable reason if the files have changed since the manifest was built.
+    pub(super) fn stale_reason(
+        &self,
+        root: &Path,
+        names: &[String],
+        suffixes: &[&str],
+    ) -> Option<String> {
+        let expected_len = names.len() * suffixes.len();
+        if expected_len != self.files.len() {
+            return Some(format!(
+                "number of index files changed from {} to {}",
+                self.files.len(),
+                expected_len
+            ));
+        }
+        for name in names {
+            for suffix in suffixes {
+                let relative = format!("{}{}", name, suffix);
+                let Some(archived) = self.files.get(relative.as_str()) else {
+                    return Some(format!("{} is new", relative));
+                };
+                let current = FileFingerprint::read(&name_path(root, &relative))
+                    .unwrap_or(FileFingerprint::MISSING);
+                if current.size != archived.size || current.mtime_ns != archived.mtime_ns {
+                    return Some(if current == FileFingerprint::MISSING {
+                        format!("{} is missing", relative)
+                    } else if archived.size == FileFingerprint::MISSING.size {
+                        format!("{} is new", relative)
+                    } else {
+                        format!("{} was modified", relative)
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{ArchivedManifest, Manifest};
+    use crate::index::{ArchivedMap, FastaMap};
+    use crate::progress::Progress;
+    use crate::storage::{ArchiveStorage, MemoryStorage};
+    use crate::util::{copy_test_data, NameSelection};
+
+    #[test]
+    fn test_manifest_detects_changes() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let data = FastaMap::build(
+            root,
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        let container: ArchiveStorage<FastaMap, MemoryStorage> = ArchiveStorage::new(data).unwrap();
+        assert_eq!(
+            container
+                .as_ref()
+                .stale_reason(root, &NameSelection::default())
+                .unwrap(),
+            None
+        );
+        let fai = Path::new(root).join("GCA_000146045.2.fna.gz.fai");
+        let size = std::fs::metadata(&fai).unwrap().len();
+        assert!(container
+            .as_ref()
+            .manifest()
+            .iter()
+            .any(|&(path, file_size, _)| path == "GCA_000146045.2.fna.gz.fai"
+                && file_size == Some(size)));
+
+        // Modifying an index file invalidates the manifest
+        let mut content = std::fs::read(&fai).unwrap();
+        content.extend_from_slice(b"\n");
+        std::fs::write(&fai, content).unwrap();
+        assert!(container
+            .as_ref()
+            .stale_reason(root, &NameSelection::default())
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_manifest_detects_new_files() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let data = FastaMap::build(
+            root,
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        let container: ArchiveStorage<FastaMap, MemoryStorage> = ArchiveStorage::new(data).unwrap();
+        for suffix in ["", ".gzi", ".fai"] {
+            std::fs::copy(
+                Path::new(root).join(format!("GCA_000146045.2.fna.gz{}", suffix)),
+                Path::new(root).join(format!("copy.fna.gz{}", suffix)),
+            )
+            .unwrap();
+        }
+        assert!(container
+            .as_ref()
+            .stale_reason(root, &NameSelection::default())
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_localize() {
+        let dir = copy_test_data("test-data/assemblies");
+        let names = vec!["GCA_000146045.2".to_string()];
+        let suffixes = [".fna.gz.fai", ".meta.tsv"];
+        let manifest = Manifest::build(dir.path(), &names, &suffixes).unwrap();
+        // Only the sizes have to match in another copy
+        let copy = copy_test_data("test-data/assemblies");
+        assert_eq!(
+            manifest.localize(copy.path()).unwrap(),
+            Manifest::build(copy.path(), &names, &suffixes).unwrap()
+        );
+        std::fs::write(copy.path().join("GCA_000146045.2.meta.tsv"), "a\tb\n").unwrap();
+        let error = manifest.localize(copy.path()).unwrap_err();
+        assert_eq!(error.to_string(), "GCA_000146045.2.meta.tsv is new");
+    }
+
+    #[test]
+    fn test_spot_check() {
+        let dir = copy_test_data("test-data/assemblies");
+        let names = vec!["GCA_000146045.2".to_string(), "GCF_000182965.3".to_string()];
+        let suffixes = [".fna.gz.fai", ".fna.gz.gzi", ".meta.tsv"];
+        let manifest = Manifest::build(dir.path(), &names, &suffixes).unwrap();
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&manifest).unwrap();
+        let archived = rkyv::access::<ArchivedManifest, rkyv::rancor::Error>(&bytes).unwrap();
+        assert_eq!(archived.spot_check(dir.path(), 2), None);
+        let other = tempfile::TempDir::new().unwrap();
+        assert_eq!(
+            archived.spot_check(other.path(), 2).unwrap(),
+            "GCA_000146045.2.fna.gz.fai is missing"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use memmap2::Mmap;
+
+use super::track_index::TrackIndex;
+use crate::error::Error;
+
+/// An uncompressed track `XXX.track` with the index `XXX.track.idx` in the format of
+/// `.track.gz.idx`. The track is memory-mapped, so reads borrow the values from the map
+/// instead of decompressing or copying them.
+pub(crate) struct MappedTrack {
+    index: TrackIndex,
+    mmap: Mmap,
+}
+
+impl MappedTrack {
+    /// Map the track at `path`, checking that the file holds all contigs of its index.
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let mut index_path = path.as_os_str().to_owned();
+        index_path.push(".idx");
+        let index = TrackIndex::read(&index_path, 0).context("Failed to read .idx")?;
+        let file = File::open(path)?;
+        // Truncating the file while it is mapped would crash reads, as documented for users
+        let mmap = unsafe { Mmap::map(&file)? };
+        if (mmap.len() as u64) < index.end() {
+            bail!(Error::Storage(format!(
+                "{} has {} bytes, but its index needs {}",
+                path.display(),
+                mmap.len(),
+                index.end()
+            )));
+        }
+        Ok(MappedTrack { index, mmap })
+    }
+
+    /// Contigs and their length in bytes.
+    pub(crate) fn contigs(&self) -> Vec<(&[u8], u64)> {
+        self.index.contigs()
+    }
+
+    /// The `length` bytes starting at byte `start` of `contig`, borrowed from the map.
+    pub(crate) fn slice(&self, contig: &[u8], start: u64, length: u64) -> Result<&[u8]> {
+        let offset = self.index.query(contig, start, length)? as usize;
+        Ok(&self.mmap[offset..offset + length as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapped_track() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.track");
+        std::fs::write(&path, b"0123456789").unwrap();
+        std::fs::write(dir.path().join("test.track.idx"), b"a\t0\nb\t4\n\t10\n").unwrap();
+        let track = MappedTrack::open(&path).unwrap();
+        assert_eq!(track.contigs(), vec![(&b"a"[..], 4), (&b"b"[..], 6)]);
+        assert_eq!(track.slice(b"b", 1, 3).unwrap(), b"567");
+        assert_eq!(track.slice(b"a", 4, 0).unwrap(), b"");
+        assert!(track.slice(b"a", 2, 3).is_err());
+        assert!(track.slice(b"c", 0, 1).is_err());
+
+        // The index needs more bytes than the file has
+        std::fs::write(dir.path().join("test.track.idx"), b"a\t0\t12\n").unwrap();
+        assert!(MappedTrack::open(&path).is_err());
+    }
+}
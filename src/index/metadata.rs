@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use rkyv::{Archive, Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Fields of the optional metadata sidecar of a genome, e.g., its taxonomy or custom labels.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub(super) struct Metadata {
+    fields: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    /// Read lines of `key<TAB>value`, skipping empty lines and comments. A missing file is no
+    /// metadata.
+    pub(super) fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Metadata::default()),
+            Err(e) => return Err(e.into()),
+        };
+        Self::parse(BufReader::new(file))
+    }
+
+    fn parse<R: BufRead>(reader: R) -> Result<Self> {
+        let mut fields = BTreeMap::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('\t') else {
+                bail!("Line {} of the metadata has no tab: {}", i + 1, line);
+            };
+            if fields.insert(key.to_string(), value.to_string()).is_some() {
+                bail!("Duplicate metadata key {}", key);
+            }
+        }
+        Ok(Metadata { fields })
+    }
+
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for (key, value) in &self.fields {
+            for field in [key, value] {
+                hasher.update(&(field.len() as u64).to_le_bytes());
+                hasher.update(field.as_bytes());
+            }
+        }
+    }
+}
+
+impl ArchivedMetadata {
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for (key, value) in self.fields.iter() {
+            for field in [key, value] {
+                hasher.update(&(field.len() as u64).to_le_bytes());
+                hasher.update(field.as_bytes());
+            }
+        }
+    }
+
+    /// All fields, sorted by key.
+    pub(super) fn fields(&self) -> Vec<(&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+
+    pub(super) fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(|value| value.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let metadata = Metadata::parse(
+            b"# comment\nclade\tFungi\r\n\naccession\tGCA_1.1\nlabel\ta\tb\n".as_slice(),
+        )
+        .unwrap();
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&metadata).unwrap();
+        let archived = rkyv::access::<ArchivedMetadata, rkyv::rancor::Error>(&bytes).unwrap();
+        assert_eq!(
+            archived.fields(),
+            vec![
+                ("accession", "GCA_1.1"),
+                ("clade", "Fungi"),
+                ("label", "a\tb")
+            ]
+        );
+        assert_eq!(archived.get("clade"), Some("Fungi"));
+        assert_eq!(archived.get("species"), None);
+        assert!(Metadata::parse(b"clade Fungi\n".as_slice()).is_err());
+        assert!(Metadata::parse(b"a\t1\na\t2\n".as_slice()).is_err());
+    }
+}
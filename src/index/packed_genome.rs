@@ -0,0 +1,265 @@
+use anyhow::{bail, Result};
+use numpy::ndarray::Array1;
+use rayon::prelude::*;
+use rkyv::{Archive, Archived, Deserialize, Serialize};
+
+use super::fasta_map::ArchivedFastaMap;
+use crate::encoding::BASE_INDEX;
+use crate::error::Error;
+use crate::storage::Schema;
+
+/// Number of bases which are read at once while packing a contig, a multiple of four.
+const CHUNK_BASES: u64 = 1 << 20;
+
+/// A run `[start, end)` of bases in a contig.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct Run {
+    start: u64,
+    end: u64,
+    /// The uppercase base of the run, for runs of bases other than `ACGT`.
+    base: u8,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct PackedContig {
+    genome: String,
+    name: Vec<u8>,
+    length: u64,
+    /// Four bases per byte, starting at the low bits, with `ACGT` as 0 to 3 in either case.
+    /// Other bases are stored as 0 and restored from `others`.
+    bases: Vec<u8>,
+    /// Runs of equal bases other than `ACGT`, e.g., `N`, in uppercase.
+    others: Vec<Run>,
+    /// Runs of lowercase (soft-masked) bases.
+    masks: Vec<Run>,
+}
+
+/// Genomes packed to two bits per base, with the bases other than `ACGT` and the soft-masking
+/// as runs next to them. Reads decode the bases directly, without decompressing BGZF blocks,
+/// and return the same bytes as the FASTA files.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub(crate) struct PackedGenomes {
+    contigs: Vec<PackedContig>,
+    /// Positions of the contigs sorted by genome and name.
+    by_name: Vec<u32>,
+}
+
+impl Schema for PackedGenomes {
+    const NAME: &'static str = "PackedGenomes";
+    const VERSION: u32 = 1;
+}
+
+/// Extend the last run of `runs` by `position` if it ends there with `base`, or start a new run.
+fn extend_run(runs: &mut Vec<Run>, position: u64, base: u8) {
+    match runs.last_mut() {
+        Some(run) if run.end == position && run.base == base => run.end += 1,
+        _ => runs.push(Run {
+            start: position,
+            end: position + 1,
+            base,
+        }),
+    }
+}
+
+impl PackedContig {
+    fn new(genome: &str, name: &[u8]) -> Self {
+        PackedContig {
+            genome: genome.to_string(),
+            name: name.to_vec(),
+            length: 0,
+            bases: Vec::new(),
+            others: Vec::new(),
+            masks: Vec::new(),
+        }
+    }
+
+    /// Append `bases` to the end of the contig.
+    fn push(&mut self, bases: &[u8]) {
+        for &base in bases {
+            let position = self.length;
+            if position % 4 == 0 {
+                self.bases.push(0);
+            }
+            match BASE_INDEX[base as usize] {
+                4 => extend_run(&mut self.others, position, base.to_ascii_uppercase()),
+                code => *self.bases.last_mut().unwrap() |= code << (2 * (position % 4)),
+            }
+            if base.is_ascii_lowercase() {
+                extend_run(&mut self.masks, position, 0);
+            }
+            self.length += 1;
+        }
+    }
+}
+
+impl PackedGenomes {
+    fn new(contigs: Vec<PackedContig>) -> Self {
+        let mut by_name: Vec<u32> = (0..contigs.len() as u32).collect();
+        by_name.sort_by(|&a, &b| {
+            let (a, b) = (&contigs[a as usize], &contigs[b as usize]);
+            (&a.genome, &a.name).cmp(&(&b.genome, &b.name))
+        });
+        PackedGenomes { contigs, by_name }
+    }
+
+    /// Pack all contigs of `genomes` in parallel, given as the name to store, the archive, its
+    /// root and the name within that root.
+    pub(crate) fn from_fasta(genomes: &[(&str, &ArchivedFastaMap, &str, &str)]) -> Result<Self> {
+        let mut contigs = Vec::new();
+        for &(genome, map, root, name) in genomes {
+            for (contig, length) in map.contigs(name)? {
+                contigs.push((genome, map, root, name, contig, length));
+            }
+        }
+        let contigs = contigs
+            .into_par_iter()
+            .map(|(genome, map, root, name, contig, length)| {
+                let mut packed = PackedContig::new(genome, contig);
+                let mut reader = map.sequence_reader(root, name, contig, 0, length)?;
+                let mut buf = Vec::new();
+                while packed.length < length {
+                    buf.clear();
+                    reader.read((length - packed.length).min(CHUNK_BASES) as usize, &mut buf)?;
+                    packed.push(&buf);
+                }
+                Ok(packed)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PackedGenomes::new(contigs))
+    }
+}
+
+/// The index of the first run of `runs` which ends after `start`.
+fn first_run(runs: &[ArchivedRun], start: u64) -> usize {
+    runs.partition_point(|run| u64::from(run.end) <= start)
+}
+
+impl ArchivedPackedGenomes {
+    fn contig(&self, genome: &str, name: &[u8]) -> Option<&ArchivedPackedContig> {
+        let entry = |position: &Archived<u32>| &self.contigs[u32::from(*position) as usize];
+        self.by_name
+            .binary_search_by(|position| {
+                let contig = entry(position);
+                (contig.genome.as_str(), contig.name.as_slice()).cmp(&(genome, name))
+            })
+            .ok()
+            .map(|i| entry(&self.by_name[i]))
+    }
+
+    /// The packed genomes, in the order they were packed.
+    pub(crate) fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for contig in self.contigs.iter() {
+            if names.last() != Some(&contig.genome.as_str()) {
+                names.push(contig.genome.as_str());
+            }
+        }
+        names
+    }
+
+    /// Contigs of `genome` and their length.
+    pub(crate) fn contigs(&self, genome: &str) -> Result<Vec<(&[u8], u64)>> {
+        let contigs: Vec<_> = self
+            .contigs
+            .iter()
+            .filter(|contig| contig.genome.as_str() == genome)
+            .map(|contig| (contig.name.as_slice(), u64::from(contig.length)))
+            .collect();
+        if contigs.is_empty() {
+            bail!(Error::NameNotFound(genome.to_string()));
+        }
+        Ok(contigs)
+    }
+
+    /// Decode `length` bases starting at `start` of `contig` of `genome`, like reading the FASTA
+    /// file.
+    pub(crate) fn read_sequence(
+        &self,
+        genome: &str,
+        name: &[u8],
+        start: u64,
+        length: u64,
+    ) -> Result<Array1<u8>> {
+        let contig = self
+            .contig(genome, name)
+            .ok_or_else(|| Error::ContigNotFound(String::from_utf8_lossy(name).into_owned()))?;
+        let contig_length = u64::from(contig.length);
+        let Some(end) = start
+            .checked_add(length)
+            .filter(|&end| end <= contig_length)
+        else {
+            bail!(Error::OutOfBounds {
+                contig: String::from_utf8_lossy(name).into_owned(),
+                start,
+                length,
+                contig_length,
+            });
+        };
+        let mut bases: Vec<u8> = (start..end)
+            .map(|position| {
+                let code = (contig.bases[(position / 4) as usize] >> (2 * (position % 4))) & 3;
+                b"ACGT"[code as usize]
+            })
+            .collect();
+        // Bases other than `ACGT` are restored before masking, which lowercases them as well
+        for (runs, is_mask) in [(&contig.others, false), (&contig.masks, true)] {
+            for run in runs[first_run(runs, start)..].iter() {
+                let run_start = u64::from(run.start);
+                if run_start >= end {
+                    break;
+                }
+                let from = (run_start.max(start) - start) as usize;
+                let to = (u64::from(run.end).min(end) - start) as usize;
+                for base in &mut bases[from..to] {
+                    *base = if is_mask {
+                        base.to_ascii_lowercase()
+                    } else {
+                        run.base
+                    };
+                }
+            }
+        }
+        Ok(Array1::from(bases))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packed_genomes() {
+        let sequence = b"ACGTnnNNacRYtgA-";
+        let mut contig = PackedContig::new("g", b"b");
+        contig.push(&sequence[..5]);
+        contig.push(&sequence[5..]);
+        assert_eq!(contig.bases.len(), 4);
+        assert_eq!(contig.others.len(), 4);
+        assert_eq!(contig.masks.len(), 2);
+        let mut other = PackedContig::new("g", b"a");
+        other.push(b"GG");
+        let packed = PackedGenomes::new(vec![contig, other]);
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&packed).unwrap();
+        let archived = rkyv::access::<ArchivedPackedGenomes, rkyv::rancor::Error>(&bytes).unwrap();
+        assert_eq!(archived.names(), vec!["g"]);
+        assert_eq!(
+            archived.contigs("g").unwrap(),
+            vec![(b"b".as_slice(), 16), (b"a".as_slice(), 2)]
+        );
+        for start in 0..sequence.len() {
+            for end in start..=sequence.len() {
+                let read = archived
+                    .read_sequence("g", b"b", start as u64, (end - start) as u64)
+                    .unwrap();
+                assert_eq!(read.to_vec(), sequence[start..end]);
+            }
+        }
+        assert_eq!(
+            archived.read_sequence("g", b"a", 0, 2).unwrap().to_vec(),
+            b"GG"
+        );
+        assert!(archived.read_sequence("g", b"a", 1, 2).is_err());
+        assert!(archived.read_sequence("g", b"c", 0, 1).is_err());
+        assert!(archived.contigs("h").is_err());
+    }
+}
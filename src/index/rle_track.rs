@@ -0,0 +1,233 @@
+use anyhow::{bail, Result};
+use numpy::ndarray::Array1;
+use rayon::prelude::*;
+use rkyv::{Archive, Archived, Deserialize, Serialize};
+
+use super::track_map::ArchivedTrackMap;
+use crate::error::Error;
+use crate::index::sorted_positions;
+use crate::storage::Schema;
+
+/// Number of values which are read at once while encoding a contig.
+const CHUNK_VALUES: u64 = 1 << 20;
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct RleContig {
+    name: Vec<u8>,
+    /// Run `i` covers the values `[run_ends[i - 1], run_ends[i])`, starting at zero.
+    run_ends: Vec<u64>,
+    /// The value of each run.
+    values: Vec<u8>,
+}
+
+/// A track stored as runs of equal values, for run-heavy tracks such as mappability or
+/// annotations. Reads find the first run by binary search and decode the runs on the fly.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub(crate) struct RleTrack {
+    /// Size of a value in bytes, which is the unit of a run.
+    value_size: u64,
+    contigs: Vec<RleContig>,
+    /// Positions of the contigs sorted by name.
+    by_name: Vec<u32>,
+}
+
+impl Schema for RleTrack {
+    const NAME: &'static str = "RleTrack";
+    const VERSION: u32 = 1;
+}
+
+/// Encode `num_values` values of `value_size` bytes, which `read(n, buf)` appends to `buf`.
+fn encode_contig(
+    name: &[u8],
+    num_values: u64,
+    value_size: usize,
+    mut read: impl FnMut(usize, &mut Vec<u8>) -> Result<()>,
+) -> Result<RleContig> {
+    let mut contig = RleContig {
+        name: name.to_vec(),
+        run_ends: Vec::new(),
+        values: Vec::new(),
+    };
+    let mut buf = Vec::new();
+    let mut remaining = num_values;
+    while remaining > 0 {
+        let n = remaining.min(CHUNK_VALUES);
+        buf.clear();
+        read(n as usize * value_size, &mut buf)?;
+        for value in buf.chunks_exact(value_size) {
+            let end = contig.run_ends.last().copied().unwrap_or(0) + 1;
+            match contig.run_ends.last_mut() {
+                Some(run_end) if contig.values[contig.values.len() - value_size..] == *value => {
+                    *run_end = end;
+                }
+                _ => {
+                    contig.run_ends.push(end);
+                    contig.values.extend_from_slice(value);
+                }
+            }
+        }
+        remaining -= n;
+    }
+    Ok(contig)
+}
+
+impl RleTrack {
+    fn new(value_size: u64, contigs: Vec<RleContig>) -> Self {
+        let by_name = sorted_positions(contigs.iter().map(|contig| contig.name.as_slice()));
+        RleTrack {
+            value_size,
+            contigs,
+            by_name,
+        }
+    }
+
+    /// Encode all contigs of `track_name` in parallel, with values of `value_size` bytes.
+    pub(crate) fn from_track(
+        map: &ArchivedTrackMap,
+        root: &str,
+        track_name: &str,
+        value_size: u64,
+    ) -> Result<Self> {
+        if value_size == 0 {
+            bail!("The value size of a track must be positive");
+        }
+        let contigs = map
+            .contigs(track_name)?
+            .into_par_iter()
+            .map(|(contig, length)| {
+                if length % value_size != 0 {
+                    bail!(
+                        "Length {} of contig {} is not a multiple of the value size {}",
+                        length,
+                        String::from_utf8_lossy(contig),
+                        value_size
+                    );
+                }
+                let mut reader = map.sequence_reader(root, track_name, contig, 0, length)?;
+                encode_contig(
+                    contig,
+                    length / value_size,
+                    value_size as usize,
+                    |n, buf| reader.read(n, buf),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RleTrack::new(value_size, contigs))
+    }
+}
+
+impl ArchivedRleTrack {
+    fn contig(&self, name: &[u8]) -> Option<&ArchivedRleContig> {
+        let entry = |position: &Archived<u32>| &self.contigs[u32::from(*position) as usize];
+        self.by_name
+            .binary_search_by(|position| entry(position).name.as_slice().cmp(name))
+            .ok()
+            .map(|i| entry(&self.by_name[i]))
+    }
+
+    pub(crate) fn value_size(&self) -> u64 {
+        u64::from(self.value_size)
+    }
+
+    /// Contigs and their length in bytes, in the order of the original track.
+    pub(crate) fn contigs(&self) -> Vec<(&[u8], u64)> {
+        self.contigs
+            .iter()
+            .map(|contig| {
+                let num_values = contig.run_ends.last().map_or(0, |&end| u64::from(end));
+                (contig.name.as_slice(), num_values * self.value_size())
+            })
+            .collect()
+    }
+
+    /// Total number of runs over all contigs.
+    pub(crate) fn num_runs(&self) -> u64 {
+        self.contigs
+            .iter()
+            .map(|contig| contig.run_ends.len() as u64)
+            .sum()
+    }
+
+    /// Decode `length` bytes starting at byte `start` of `contig`, like reading the dense track.
+    pub(crate) fn read_sequence(&self, name: &[u8], start: u64, length: u64) -> Result<Array1<u8>> {
+        let contig = self
+            .contig(name)
+            .ok_or_else(|| Error::ContigNotFound(String::from_utf8_lossy(name).into_owned()))?;
+        let value_size = self.value_size();
+        let contig_length = contig.run_ends.last().map_or(0, |&end| u64::from(end)) * value_size;
+        let Some(end) = start
+            .checked_add(length)
+            .filter(|&end| end <= contig_length)
+        else {
+            bail!(Error::OutOfBounds {
+                contig: String::from_utf8_lossy(name).into_owned(),
+                start,
+                length,
+                contig_length,
+            });
+        };
+        let mut bytes = Vec::with_capacity(length as usize);
+        // The run of the value which contains `start`
+        let mut run = contig
+            .run_ends
+            .partition_point(|&run_end| u64::from(run_end) * value_size <= start);
+        let mut position = start;
+        while position < end {
+            let run_end = (u64::from(contig.run_ends[run]) * value_size).min(end);
+            let value = &contig.values[run * value_size as usize..][..value_size as usize];
+            while position < run_end {
+                bytes.push(value[(position % value_size) as usize]);
+                position += 1;
+            }
+            run += 1;
+        }
+        Ok(Array1::from(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(name: &[u8], bytes: &[u8], value_size: usize) -> RleContig {
+        let mut offset = 0;
+        encode_contig(
+            name,
+            (bytes.len() / value_size) as u64,
+            value_size,
+            |n, buf| {
+                buf.extend_from_slice(&bytes[offset..offset + n]);
+                offset += n;
+                Ok(())
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rle_track() {
+        let b = [1u8, 0, 1, 0, 1, 0, 2, 0, 2, 0, 1, 0];
+        let contig = encode(b"b", &b, 2);
+        assert_eq!(contig.run_ends, vec![3, 5, 6]);
+        assert_eq!(contig.values, vec![1, 0, 2, 0, 1, 0]);
+        let track = RleTrack::new(2, vec![contig, encode(b"a", &[7; 8], 2)]);
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&track).unwrap();
+        let archived = rkyv::access::<ArchivedRleTrack, rkyv::rancor::Error>(&bytes).unwrap();
+        assert_eq!(
+            archived.contigs(),
+            vec![(b"b".as_slice(), 12), (b"a".as_slice(), 8)]
+        );
+        assert_eq!(archived.num_runs(), 4);
+        for start in 0..b.len() {
+            for end in start..=b.len() {
+                let read = archived
+                    .read_sequence(b"b", start as u64, (end - start) as u64)
+                    .unwrap();
+                assert_eq!(read.to_vec(), b[start..end]);
+            }
+        }
+        assert_eq!(archived.read_sequence(b"a", 3, 2).unwrap().to_vec(), [7, 7]);
+        assert!(archived.read_sequence(b"a", 3, 6).is_err());
+        assert!(archived.read_sequence(b"c", 0, 1).is_err());
+    }
+}
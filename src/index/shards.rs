@@ -0,0 +1,71 @@
+use std::path::Path;
+
+use anyhow::Result;
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::index::manifest::Manifest;
+use crate::index::SPOT_CHECK_FILES;
+use crate::storage::Schema;
+use crate::util::{fnv1a, NameSelection};
+
+/// Top-level file of a sharded cache, listing all names and the manifest of the whole collection.
+/// The entries themselves are stored in `num_shards` separate archives.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub(crate) struct ShardDirectory {
+    names: Vec<String>,
+    num_shards: u32,
+    manifest: Manifest,
+}
+
+impl Schema for ShardDirectory {
+    const NAME: &'static str = "ShardDirectory";
+    const VERSION: u32 = 1;
+}
+
+impl ShardDirectory {
+    pub(super) fn new(names: Vec<String>, num_shards: usize, manifest: Manifest) -> Self {
+        ShardDirectory {
+            names,
+            num_shards: num_shards as u32,
+            manifest,
+        }
+    }
+}
+
+impl ArchivedShardDirectory {
+    pub(crate) fn names(&self) -> Vec<&str> {
+        self.names.iter().map(|s| s.as_str()).collect()
+    }
+
+    pub(crate) fn num_shards(&self) -> usize {
+        self.num_shards.to_native() as usize
+    }
+
+    /// See `ArchivedMap::spot_check`.
+    pub(crate) fn spot_check(&self, root: &Path) -> Option<String> {
+        self.manifest.spot_check(root, SPOT_CHECK_FILES)
+    }
+
+    /// See `ArchivedMap::manifest`.
+    pub(crate) fn manifest(&self) -> Vec<(&str, Option<u64>, u64)> {
+        self.manifest.files()
+    }
+
+    pub(super) fn stale_reason(
+        &self,
+        root: &str,
+        selection: &NameSelection,
+        suffix: &str,
+        index_suffixes: &[&str],
+    ) -> Result<Option<String>> {
+        let names = selection.resolve(root, suffix)?;
+        Ok(self
+            .manifest
+            .stale_reason(Path::new(root), &names, index_suffixes))
+    }
+}
+
+/// Shard a name belongs to, stable across builds.
+pub(crate) fn shard_of(name: &str, num_shards: usize) -> usize {
+    (fnv1a(name.as_bytes()) % num_shards as u64) as usize
+}
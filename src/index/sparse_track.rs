@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use numpy::ndarray::{s, Array1};
+use rkyv::{Archive, Archived, Deserialize, Serialize};
+
+use super::manifest::Manifest;
+use crate::bgzf_reader::BgzfReader;
+use crate::index::sorted_positions;
+use crate::storage::{write_direct, ArchiveStorage, ChecksumAlgorithm, MemoryStorage, Schema};
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct SparseContig {
+    name: Vec<u8>,
+    /// Sorted, non-overlapping intervals `[start, end)` with a value each.
+    starts: Vec<u64>,
+    ends: Vec<u64>,
+    values: Vec<f32>,
+}
+
+/// A track stored as intervals with a value, for tracks which are mostly a constant such as
+/// zero. Positions outside of all intervals are filled in when reading.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub(crate) struct SparseTrack {
+    contigs: Vec<SparseContig>,
+    /// Positions of the contigs sorted by name.
+    by_name: Vec<u32>,
+    /// Fingerprint of the bedGraph file, to detect a stale cache.
+    manifest: Manifest,
+}
+
+impl Schema for SparseTrack {
+    const NAME: &'static str = "SparseTrack";
+    const VERSION: u32 = 1;
+}
+
+impl SparseTrack {
+    /// Read a bedGraph file with tab-separated contig, start, end and value per line.
+    /// Comments, `track` and `browser` lines are skipped, and overlapping intervals are
+    /// rejected.
+    pub(crate) fn from_bedgraph<R: BufRead>(reader: R) -> Result<Self> {
+        let mut intervals = BTreeMap::<Vec<u8>, Vec<(u64, u64, f32)>>::new();
+        // Contigs in file order
+        let mut names = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty()
+                || line.starts_with('#')
+                || line.starts_with("track")
+                || line.starts_with("browser")
+            {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let [contig, start, end, value, ..] = fields[..] else {
+                bail!("Line {} of the bedGraph file has less than 4 fields", i + 1);
+            };
+            let context = || format!("Invalid field on line {}: {}", i + 1, line);
+            let start = start.parse::<u64>().with_context(context)?;
+            let end = end.parse::<u64>().with_context(context)?;
+            let value = value.parse::<f32>().with_context(context)?;
+            if start >= end {
+                continue;
+            }
+            let contig = contig.as_bytes();
+            if !intervals.contains_key(contig) {
+                names.push(contig.to_vec());
+            }
+            intervals
+                .entry(contig.to_vec())
+                .or_default()
+                .push((start, end, value));
+        }
+        let mut contigs = Vec::with_capacity(names.len());
+        for name in names {
+            let mut contig_intervals = intervals.remove(&name).unwrap();
+            contig_intervals.sort_unstable_by_key(|&(start, _, _)| start);
+            if let Some(pair) = contig_intervals
+                .windows(2)
+                .find(|pair| pair[1].0 < pair[0].1)
+            {
+                bail!(
+                    "Overlapping intervals {}-{} and {}-{} on {}",
+                    pair[0].0,
+                    pair[0].1,
+                    pair[1].0,
+                    pair[1].1,
+                    String::from_utf8_lossy(&name)
+                );
+            }
+            contigs.push(SparseContig {
+                name,
+                starts: contig_intervals.iter().map(|i| i.0).collect(),
+                ends: contig_intervals.iter().map(|i| i.1).collect(),
+                values: contig_intervals.iter().map(|i| i.2).collect(),
+            });
+        }
+        let by_name = sorted_positions(contigs.iter().map(|contig| contig.name.as_slice()));
+        Ok(SparseTrack {
+            contigs,
+            by_name,
+            manifest: Manifest::default(),
+        })
+    }
+}
+
+/// Directory and file name of a bedGraph file, as the manifest records paths relative to a root.
+fn split_path(path: &Path) -> Result<(&Path, String)> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Not a file: {}", path.display()))?;
+    let root = path.parent().unwrap_or(Path::new(""));
+    Ok((root, name.to_string_lossy().into_owned()))
+}
+
+impl SparseTrack {
+    /// Read a plain or BGZF-compressed bedGraph file.
+    fn build(path: &Path) -> Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+        let mut magic = [0u8; 2];
+        let is_compressed = file.read(&mut magic)? == 2 && magic == [0x1f, 0x8b];
+        let file = File::open(path)?;
+        let mut track = if is_compressed {
+            SparseTrack::from_bedgraph(BufReader::new(BgzfReader::new(file)))
+        } else {
+            SparseTrack::from_bedgraph(BufReader::new(file))
+        }
+        .with_context(|| format!("Error reading {}", path.display()))?;
+        let (root, name) = split_path(path)?;
+        track.manifest = Manifest::build(root, [&name], &[""])?;
+        Ok(track)
+    }
+
+    /// Load the track of the bedGraph file `path`. With `cache_path`, the archived intervals are
+    /// loaded from there if the file did not change, and otherwise rebuilt and written there.
+    pub(crate) fn open(
+        path: &Path,
+        cache_path: Option<&Path>,
+    ) -> Result<ArchiveStorage<SparseTrack, MemoryStorage>> {
+        let Some(cache_path) = cache_path else {
+            return ArchiveStorage::new(SparseTrack::build(path)?);
+        };
+        if cache_path.exists() {
+            match ArchiveStorage::<SparseTrack, MemoryStorage>::load(cache_path, true)? {
+                Ok(archive) => {
+                    let (root, name) = split_path(path)?;
+                    match archive.as_ref().manifest.stale_reason(root, &[name], &[""]) {
+                        None => return Ok(archive),
+                        Some(reason) => info!("Rebuilding sparse track cache: {}", reason),
+                    }
+                }
+                Err(rejection) => info!("Rebuilding sparse track cache: {}", rejection),
+            }
+        }
+        write_direct(
+            &SparseTrack::build(path)?,
+            cache_path,
+            ChecksumAlgorithm::Xxh3,
+        )?;
+        ArchiveStorage::load(cache_path, true)?.map_err(|rejection| anyhow!(rejection))
+    }
+}
+
+impl ArchivedSparseTrack {
+    fn contig(&self, name: &[u8]) -> Option<&ArchivedSparseContig> {
+        let entry = |position: &Archived<u32>| &self.contigs[u32::from(*position) as usize];
+        self.by_name
+            .binary_search_by(|position| entry(position).name.as_slice().cmp(name))
+            .ok()
+            .map(|i| entry(&self.by_name[i]))
+    }
+
+    /// Contigs with at least one interval and their number of intervals, in file order.
+    pub(crate) fn contigs(&self) -> Vec<(&[u8], usize)> {
+        self.contigs
+            .iter()
+            .map(|contig| (contig.name.as_slice(), contig.starts.len()))
+            .collect()
+    }
+
+    /// Materialize `length` values starting at `start`, with `fill` outside of the intervals.
+    pub(crate) fn read(&self, contig: &[u8], start: u64, length: u64, fill: f32) -> Array1<f32> {
+        let mut values = Array1::from_elem(length as usize, fill);
+        let Some(contig) = self.contig(contig) else {
+            return values;
+        };
+        let end = start.saturating_add(length);
+        // The first interval ending after `start`, as the intervals do not overlap
+        let first = contig.ends.partition_point(|&e| u64::from(e) <= start);
+        for i in first..contig.starts.len() {
+            let interval_start = u64::from(contig.starts[i]);
+            if interval_start >= end {
+                break;
+            }
+            let from = interval_start.max(start) - start;
+            let to = u64::from(contig.ends[i]).min(end) - start;
+            values
+                .slice_mut(s![from as usize..to as usize])
+                .fill(f32::from(contig.values[i]));
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_track() {
+        let bedgraph =
+            b"track type=bedGraph\nb\t5\t8\t2.5\na\t0\t2\t1\na\t10\t12\t3\n\na\t4\t6\t-1\n";
+        let track = SparseTrack::from_bedgraph(bedgraph.as_slice()).unwrap();
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&track).unwrap();
+        let archived = rkyv::access::<ArchivedSparseTrack, rkyv::rancor::Error>(&bytes).unwrap();
+        assert_eq!(
+            archived.contigs(),
+            vec![(b"b".as_slice(), 1), (b"a".as_slice(), 3)]
+        );
+        assert_eq!(
+            archived.read(b"a", 1, 10, 0.0).to_vec(),
+            vec![1.0, 0.0, 0.0, -1.0, -1.0, 0.0, 0.0, 0.0, 0.0, 3.0]
+        );
+        assert_eq!(archived.read(b"b", 7, 3, f32::NAN).to_vec()[0], 2.5);
+        assert!(archived.read(b"b", 7, 3, f32::NAN).to_vec()[1].is_nan());
+        assert_eq!(archived.read(b"c", 0, 2, 7.0).to_vec(), vec![7.0, 7.0]);
+
+        assert!(SparseTrack::from_bedgraph(b"a\t0\t5\t1\na\t4\t6\t1\n".as_slice()).is_err());
+        assert!(SparseTrack::from_bedgraph(b"a\t0\t5\n".as_slice()).is_err());
+        assert!(SparseTrack::from_bedgraph(b"a\t0\t5\tx\n".as_slice()).is_err());
+    }
+}
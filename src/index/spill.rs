@@ -0,0 +1,263 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use rkyv::api::high::{HighSerializer, HighValidator};
+use rkyv::bytecheck::CheckBytes;
+use rkyv::de::Pool;
+use rkyv::ser::allocator::ArenaHandle;
+use rkyv::util::AlignedVec;
+use rkyv::{rancor, Archive, Deserialize, Serialize};
+
+const BUFFER_SIZE: usize = 1 << 20;
+
+// Spill file layout (little endian): tag (u64) | build start time in ns (u64)
+// | entries, each as length (u64) followed by the archived `(String, V)`
+const HEADER_LEN: u64 = 16;
+
+/// Writes named entries one by one to a file, so that they do not need to be kept in memory
+/// until the whole map is serialized. The file doubles as a checkpoint: if the build is
+/// interrupted, it can be resumed with `SpillWriter::resume`.
+pub(crate) struct SpillWriter<V> {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    len: usize,
+    started_ns: u64,
+    _marker: PhantomData<V>,
+}
+
+impl<V> SpillWriter<V>
+where
+    (String, V): for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>,
+    (String, V): Archive,
+    <(String, V) as Archive>::Archived: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+        + Deserialize<(String, V), rancor::Strategy<Pool, rancor::Error>>,
+{
+    /// Create a new spill file, replacing any existing one. `tag` identifies the kind of build.
+    pub(crate) fn create(path: &Path, tag: u64) -> Result<Self> {
+        let started_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        let mut writer = BufWriter::with_capacity(
+            BUFFER_SIZE,
+            File::create(path)
+                .with_context(|| format!("Error creating spill file {}", path.display()))?,
+        );
+        writer.write_all(&tag.to_le_bytes())?;
+        writer.write_all(&started_ns.to_le_bytes())?;
+        Ok(SpillWriter {
+            writer,
+            path: path.to_path_buf(),
+            len: 0,
+            started_ns,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Reopen the spill file of an interrupted build with the same `tag`.
+    /// An incomplete entry at the end, e.g., from a killed process, is discarded.
+    /// Returns the writer positioned after the last complete entry and the names of all
+    /// complete entries, or `None` if there is no usable spill file.
+    pub(crate) fn resume(path: &Path, tag: u64) -> Result<Option<(Self, Vec<String>)>> {
+        let Ok(file) = File::open(path) else {
+            return Ok(None);
+        };
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+        let mut header = [0u8; HEADER_LEN as usize];
+        if reader.read_exact(&mut header).is_err()
+            || u64::from_le_bytes(header[0..8].try_into().unwrap()) != tag
+        {
+            return Ok(None);
+        }
+        let started_ns = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let mut names = Vec::new();
+        let mut end = HEADER_LEN;
+        while let Ok((name, _)) = read_entry::<V>(&mut reader) {
+            names.push(name);
+            end = reader.stream_position()?;
+        }
+        drop(reader);
+
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(end)?;
+        file.seek(SeekFrom::End(0))?;
+        let writer = SpillWriter {
+            writer: BufWriter::with_capacity(BUFFER_SIZE, file),
+            path: path.to_path_buf(),
+            len: names.len(),
+            started_ns,
+            _marker: PhantomData,
+        };
+        Ok(Some((writer, names)))
+    }
+
+    /// Time at which the build writing this file was started, in ns since the epoch.
+    pub(crate) fn started_ns(&self) -> u64 {
+        self.started_ns
+    }
+
+    pub(crate) fn push(&mut self, entry: &(String, V)) -> Result<()> {
+        let bytes = rkyv::to_bytes::<rancor::Error>(entry)?;
+        self.writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        self.writer.write_all(&bytes)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Flush all entries pushed so far, so that a resumed build can pick them up.
+    pub(crate) fn checkpoint(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Finish writing and read the entries back in the order they were pushed.
+    pub(crate) fn finish(mut self) -> Result<SpillReader<V>> {
+        self.writer.flush()?;
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(HEADER_LEN))?;
+        Ok(SpillReader {
+            reader,
+            path: self.path,
+            remaining: self.len,
+            error: None,
+            _marker: PhantomData,
+        })
+    }
+}
+
+fn read_entry<V>(reader: &mut impl Read) -> Result<(String, V)>
+where
+    (String, V): Archive,
+    <(String, V) as Archive>::Archived: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+        + Deserialize<(String, V), rancor::Strategy<Pool, rancor::Error>>,
+{
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    // Read through `take` so that a garbage length in a torn entry does not allocate upfront
+    let mut buffer = Vec::new();
+    reader.take(len).read_to_end(&mut buffer)?;
+    if buffer.len() as u64 != len {
+        bail!(
+            "Incomplete entry: expected {} bytes, found {}",
+            len,
+            buffer.len()
+        );
+    }
+    let mut bytes = AlignedVec::<16>::with_capacity(buffer.len());
+    bytes.extend_from_slice(&buffer);
+    Ok(rkyv::from_bytes::<(String, V), rancor::Error>(&bytes)?)
+}
+
+/// Iterator over the entries of a spill file.
+///
+/// The iterator must report its exact length upfront, so read errors cannot end it early.
+/// Instead, the first error is stored and iteration stops; it can be retrieved with `finish`.
+pub(crate) struct SpillReader<V> {
+    reader: BufReader<File>,
+    path: PathBuf,
+    remaining: usize,
+    error: Option<anyhow::Error>,
+    _marker: PhantomData<V>,
+}
+
+impl<V> SpillReader<V> {
+    /// Returns the first error encountered while reading, if any.
+    /// On success, the spill file is removed as it is no longer needed.
+    pub(crate) fn finish(self) -> Result<()> {
+        match self.error {
+            Some(e) => Err(e.context(format!("Error reading spill file {}", self.path.display()))),
+            None if self.remaining > 0 => Err(anyhow!(
+                "Spill file {} was not read completely",
+                self.path.display()
+            )),
+            None => {
+                drop(self.reader);
+                std::fs::remove_file(&self.path)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<V> Iterator for SpillReader<V>
+where
+    (String, V): Archive,
+    <(String, V) as Archive>::Archived: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+        + Deserialize<(String, V), rancor::Strategy<Pool, rancor::Error>>,
+{
+    type Item = (String, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.error.is_some() {
+            return None;
+        }
+        match read_entry(&mut self.reader) {
+            Ok(entry) => {
+                self.remaining -= 1;
+                Some(entry)
+            }
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<V> ExactSizeIterator for SpillReader<V>
+where
+    (String, V): Archive,
+    <(String, V) as Archive>::Archived: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+        + Deserialize<(String, V), rancor::Strategy<Pool, rancor::Error>>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_discards_incomplete_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spill");
+        let mut spill = SpillWriter::<u64>::create(&path, 1).unwrap();
+        spill.push(&("a".to_string(), 1)).unwrap();
+        spill.push(&("b".to_string(), 2)).unwrap();
+        spill.checkpoint().unwrap();
+        let started_ns = spill.started_ns();
+        drop(spill);
+
+        // Simulate a process killed while writing the next entry
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&100u64.to_le_bytes()).unwrap();
+        file.write_all(b"torn").unwrap();
+        drop(file);
+
+        assert!(SpillWriter::<u64>::resume(&path, 2).unwrap().is_none());
+        let (mut spill, names) = SpillWriter::<u64>::resume(&path, 1).unwrap().unwrap();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(spill.started_ns(), started_ns);
+        spill.push(&("c".to_string(), 3)).unwrap();
+        let mut reader = spill.finish().unwrap();
+        let entries = reader.by_ref().collect::<Vec<_>>();
+        reader.finish().unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("a".to_string(), 1),
+                ("b".to_string(), 2),
+                ("c".to_string(), 3)
+            ]
+        );
+        assert!(!path.exists());
+    }
+}
@@ -4,8 +4,12 @@ use std::{
     path::Path,
 };
 
-use anyhow::Result;
-use rkyv::{Archive, Deserialize, Serialize};
+use anyhow::{anyhow, bail, Result};
+use rkyv::{Archive, Archived, Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::error::Error;
+use crate::index::sorted_positions;
 
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct TrackIndexRecord {
@@ -17,60 +21,200 @@ struct TrackIndexRecord {
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub(super) struct TrackIndex {
     entries: Vec<TrackIndexRecord>,
+    /// Positions of the entries sorted by track name.
+    by_name: Vec<u32>,
 }
 
 impl TrackIndex {
     pub(super) fn read<P: AsRef<Path>>(path: P, min_contig_length: u64) -> Result<Self> {
         let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let mut entries = Vec::new();
+        Self::parse(BufReader::new(file), min_contig_length)
+    }
 
-        // Read names and offsets from the file
-        for line in reader.split(b'\n') {
+    /// Parse lines of `name\toffset` or `name\toffset\tlength` with the offsets into the
+    /// uncompressed track in increasing order. Without a length, a contig extends to the offset
+    /// of the next line, so the last contig needs either a length or a final line with only the
+    /// total size of the track, i.e., `\tsize`.
+    fn parse<R: BufRead>(reader: R, min_contig_length: u64) -> Result<Self> {
+        // Names, offsets and explicit lengths of the contigs
+        let mut lines = Vec::new();
+        let mut total_size = None;
+        for (i, line) in reader.split(b'\n').enumerate() {
             let line = line?;
-            let mut fields = line.splitn(2, |&b| b == b'\t');
-            let name = fields.next().and_then(|field| {
-                if field.is_empty() {
-                    None
-                } else {
-                    Some(field.to_vec())
-                }
-            });
-            if let Some(offset_field) = fields.next() {
-                if let Some(offset) = std::str::from_utf8(offset_field)
+            let line = line.strip_suffix(b"\r").unwrap_or(&line);
+            if line.is_empty() {
+                continue;
+            }
+            if total_size.is_some() {
+                bail!("Line {} of the track index follows the total size", i + 1);
+            }
+            let parse = |field: &[u8]| {
+                std::str::from_utf8(field)
                     .ok()
                     .and_then(|s| s.parse::<u64>().ok())
-                {
-                    entries.push((name, offset));
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Invalid number on line {} of the track index: {}",
+                            i + 1,
+                            String::from_utf8_lossy(field)
+                        )
+                    })
+            };
+            let fields = line.split(|&b| b == b'\t').collect::<Vec<_>>();
+            match fields[..] {
+                [name, offset] if name.is_empty() => total_size = Some(parse(offset)?),
+                [name, offset] => lines.push((name.to_vec(), parse(offset)?, None)),
+                [name, offset, length] if !name.is_empty() => {
+                    lines.push((name.to_vec(), parse(offset)?, Some(parse(length)?)))
                 }
+                _ => bail!(
+                    "Invalid line {} of the track index: {}",
+                    i + 1,
+                    String::from_utf8_lossy(line)
+                ),
             }
         }
 
-        // Create entries by computing length of neighboring offsets
-        let entries = entries
-            .windows(2)
-            .map(|pair| {
-                if let [(Some(name), offset), (_, next_offset)] = pair {
-                    Ok(TrackIndexRecord {
-                        name: name.clone(),
-                        offset: *offset,
-                        length: next_offset - offset,
-                    })
-                } else {
-                    Err(anyhow::anyhow!("Invalid track index format"))
-                }
-            })
-            .filter(|r| match r {
-                Ok(record) => record.length >= min_contig_length,
-                Err(_) => true,
-            })
-            .collect::<Result<Vec<_>>>()?;
+        let mut entries = Vec::with_capacity(lines.len());
+        let next_offsets = lines
+            .iter()
+            .skip(1)
+            .map(|&(_, offset, _)| Some(offset))
+            .chain([total_size])
+            .collect::<Vec<_>>();
+        for ((name, offset, length), next_offset) in lines.into_iter().zip(next_offsets) {
+            if let Some(next_offset) = next_offset.filter(|&next_offset| next_offset < offset) {
+                bail!(
+                    "Offsets of the track index are not increasing: {} of contig {} is followed \
+                     by {}",
+                    offset,
+                    String::from_utf8_lossy(&name),
+                    next_offset
+                );
+            }
+            let length = match (length, next_offset) {
+                (Some(length), _) => length,
+                (None, Some(next_offset)) => next_offset - offset,
+                (None, None) => bail!(
+                    "The length of the last contig {} is missing from the track index",
+                    String::from_utf8_lossy(&name)
+                ),
+            };
+            if next_offset.is_some_and(|next_offset| offset.saturating_add(length) > next_offset) {
+                bail!(
+                    "Contig {} at offset {} with length {} overlaps the next offset of the track \
+                     index",
+                    String::from_utf8_lossy(&name),
+                    offset,
+                    length
+                );
+            }
+            if length >= min_contig_length {
+                entries.push(TrackIndexRecord {
+                    name,
+                    offset,
+                    length,
+                });
+            }
+        }
+
+        let by_name = sorted_positions(entries.iter().map(|record| record.name.as_slice()));
+        Ok(TrackIndex { entries, by_name })
+    }
+}
+
+/// Offset of `start` of a contig at `offset` in the uncompressed file, checking that `length`
+/// values fit.
+fn checked_offset(
+    name: &[u8],
+    offset: u64,
+    contig_length: u64,
+    start: u64,
+    length: u64,
+) -> Result<u64> {
+    if start
+        .checked_add(length)
+        .is_none_or(|end| end > contig_length)
+    {
+        bail!(Error::OutOfBounds {
+            contig: String::from_utf8_lossy(name).into_owned(),
+            start,
+            length,
+            contig_length,
+        });
+    }
+    Ok(offset + start)
+}
+
+impl TrackIndex {
+    /// Find the entry of `name` by binary search over the sorted positions.
+    fn record(&self, name: &[u8]) -> Option<&TrackIndexRecord> {
+        let entry = |&position: &u32| &self.entries[position as usize];
+        self.by_name
+            .binary_search_by(|position| entry(position).name.as_slice().cmp(name))
+            .ok()
+            .map(|i| entry(&self.by_name[i]))
+    }
+
+    pub(super) fn contigs(&self) -> Vec<(&[u8], u64)> {
+        self.entries
+            .iter()
+            .map(|entry| (&entry.name[..], entry.length))
+            .collect()
+    }
 
-        Ok(TrackIndex { entries })
+    /// End of the last contig in the uncompressed file.
+    pub(super) fn end(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| entry.offset.saturating_add(entry.length))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Offset of `start` in the uncompressed file, checking that `length` values fit.
+    pub(super) fn query(&self, name: &[u8], start: u64, length: u64) -> Result<u64> {
+        let entry = self
+            .record(name)
+            .ok_or_else(|| Error::ContigNotFound(String::from_utf8_lossy(name).into_owned()))?;
+        checked_offset(name, entry.offset, entry.length, start, length)
+    }
+
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for record in &self.entries {
+            hasher.update(&(record.name.len() as u64).to_le_bytes());
+            hasher.update(&record.name);
+            hasher.update(&record.offset.to_le_bytes());
+            hasher.update(&record.length.to_le_bytes());
+        }
+        for position in &self.by_name {
+            hasher.update(&position.to_le_bytes());
+        }
     }
 }
 
 impl ArchivedTrackIndex {
+    pub(super) fn hash_into(&self, hasher: &mut Xxh3) {
+        for record in self.entries.iter() {
+            hasher.update(&(record.name.len() as u64).to_le_bytes());
+            hasher.update(&record.name);
+            hasher.update(&u64::from(record.offset).to_le_bytes());
+            hasher.update(&u64::from(record.length).to_le_bytes());
+        }
+        for position in self.by_name.iter() {
+            hasher.update(&u32::from(*position).to_le_bytes());
+        }
+    }
+
+    /// Find the entry of `name` by binary search over the sorted positions.
+    fn record(&self, name: &[u8]) -> Option<&ArchivedTrackIndexRecord> {
+        let entry = |position: &Archived<u32>| &self.entries[u32::from(*position) as usize];
+        self.by_name
+            .binary_search_by(|position| entry(position).name.as_slice().cmp(name))
+            .ok()
+            .map(|i| entry(&self.by_name[i]))
+    }
+
     pub(super) fn contigs(&self) -> Vec<(&[u8], u64)> {
         self.entries
             .iter()
@@ -78,14 +222,74 @@ impl ArchivedTrackIndex {
             .collect()
     }
 
-    pub(super) fn query(&self, name: &[u8], start: u64) -> Result<u64> {
-        let i = self.entries.iter().find(|r| r.name.as_slice() == name);
-        match i {
-            Some(entry) => Ok(u64::from(entry.offset) + start),
-            None => Err(anyhow::anyhow!(
-                "Track not found: {}",
-                String::from_utf8_lossy(name)
-            )),
-        }
+    pub(super) fn contig_length(&self, name: &[u8]) -> Option<u64> {
+        self.record(name).map(|entry| u64::from(entry.length))
+    }
+
+    pub(super) fn num_contigs(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Total length of all contigs.
+    pub(super) fn total_length(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| u64::from(entry.length))
+            .sum()
+    }
+
+    /// Offset of `start` in the uncompressed file, checking that `length` values fit.
+    pub(super) fn query(&self, name: &[u8], start: u64, length: u64) -> Result<u64> {
+        let entry = self
+            .record(name)
+            .ok_or_else(|| Error::ContigNotFound(String::from_utf8_lossy(name).into_owned()))?;
+        checked_offset(
+            name,
+            u64::from(entry.offset),
+            u64::from(entry.length),
+            start,
+            length,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(index: &[u8], min_contig_length: u64) -> Result<Vec<(Vec<u8>, u64)>> {
+        Ok(TrackIndex::parse(index, min_contig_length)?
+            .entries
+            .into_iter()
+            .map(|record| (record.name, record.length))
+            .collect())
+    }
+
+    #[test]
+    fn test_parse() {
+        // Total size as the final line
+        assert_eq!(
+            parse(b"a\t0\nb\t8\n\t20\n", 0).unwrap(),
+            vec![(b"a".to_vec(), 8), (b"b".to_vec(), 12)]
+        );
+        // Explicit lengths, also for the last contig only
+        assert_eq!(
+            parse(b"a\t0\t8\nb\t8\t12\n", 0).unwrap(),
+            vec![(b"a".to_vec(), 8), (b"b".to_vec(), 12)]
+        );
+        assert_eq!(
+            parse(b"a\t0\r\nb\t8\t12", 10).unwrap(),
+            vec![(b"b".to_vec(), 12)]
+        );
+        // Empty contigs
+        assert_eq!(parse(b"a\t0\nb\t0\n\t4\n", 0).unwrap()[0].1, 0);
+
+        // The last contig was dropped before
+        assert!(parse(b"a\t0\nb\t8\n", 0).is_err());
+        assert!(parse(b"a\t8\nb\t0\n\t20\n", 0).is_err());
+        assert!(parse(b"a\t0\t10\nb\t8\n\t20\n", 0).is_err());
+        assert!(parse(b"a\t0\n\t8\nb\t8\t4\n", 0).is_err());
+        assert!(parse(b"a\tx\n\t8\n", 0).is_err());
+        assert!(parse(b"a\n", 0).is_err());
     }
 }
@@ -1,31 +1,143 @@
+use crate::bgzf_reader::BgzfReader;
+use crate::error::Error;
 use crate::index::bgzf_index::BgzfIndex;
-use crate::util::get_relative_name_without_suffix;
-use anyhow::Context;
-use noodles::bgzf::{self, io::Seek, VirtualPosition};
+use crate::metrics::METRICS;
+use crate::pools::{self, Operation};
+use crate::progress::Progress;
+use crate::remote::open_data;
+use crate::retry::with_retry;
+use crate::storage::{type_specific_magic, write_direct, ChecksumAlgorithm, Schema};
+use crate::util::{check_name, fnv1a, name_path, NameSelection};
+use crate::windows::SequenceReader;
+use anyhow::{bail, Context};
+use noodles::bgzf::VirtualPosition;
 
 use anyhow::Result;
-use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, warn};
 use numpy::ndarray::Array1;
 use rayon::prelude::*;
-use rkyv::{Archive, Deserialize, Serialize};
+use rkyv::collections::btree_map::ArchivedBTreeMap;
+use rkyv::munge::munge;
+use rkyv::rancor::{Fallible, Source};
+use rkyv::ser::{Allocator, Writer};
+use rkyv::string::ArchivedString;
+use rkyv::{Archive, Deserialize, Place, Serialize};
+use std::cell::RefCell;
 use std::io::Read;
 use std::{
-    collections::BTreeMap,
-    fs::File,
+    collections::{btree_map::Entry, BTreeMap},
     path::{Path, PathBuf},
 };
+use xxhash_rust::xxh3::Xxh3;
 
+use super::manifest::Manifest;
+use super::shards::{shard_of, ArchivedShardDirectory, ShardDirectory};
+use super::spill::{SpillReader, SpillWriter};
 use super::track_index::TrackIndex;
+use super::{ArchivedMap, ConflictPolicy, SPOT_CHECK_FILES};
 
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct Index {
     gzi: BgzfIndex,
     track_index: TrackIndex,
+    /// Checksum over the contents of this entry to localize corruption.
+    checksum: u64,
+}
+
+impl Index {
+    fn new(gzi: BgzfIndex, track_index: TrackIndex) -> Self {
+        let mut hasher = Xxh3::new();
+        gzi.hash_into(&mut hasher);
+        track_index.hash_into(&mut hasher);
+        Index {
+            gzi,
+            track_index,
+            checksum: hasher.digest(),
+        }
+    }
+}
+
+impl ArchivedIndex {
+    fn is_damaged(&self) -> bool {
+        let mut hasher = Xxh3::new();
+        self.gzi.hash_into(&mut hasher);
+        self.track_index.hash_into(&mut hasher);
+        hasher.digest() != self.checksum
+    }
 }
 
 #[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub(crate) struct TrackMap {
     map: BTreeMap<String, Index>,
+    manifest: Manifest,
+    /// Contigs shorter than this were left out of the entries, and of entries added later.
+    min_contig_length: u64,
+}
+
+/// Suffixes of the index files which are fingerprinted in the manifest.
+pub(crate) const INDEX_SUFFIXES: &[&str] = &[".track.gz.gzi", ".track.gz.idx"];
+
+/// Number of entries which are indexed at once when streaming the map to a file.
+const BATCH_SIZE: usize = 1024;
+
+impl Schema for TrackMap {
+    const NAME: &'static str = "TrackMap";
+    const VERSION: u32 = 6;
+}
+
+/// Serializes to an `ArchivedTrackMap`, but takes the entries from a spill file instead of memory.
+struct TrackMapStream {
+    len: usize,
+    entries: RefCell<SpillReader<Index>>,
+    manifest: Manifest,
+    min_contig_length: u64,
+}
+
+impl Schema for TrackMapStream {
+    const NAME: &'static str = TrackMap::NAME;
+    const VERSION: u32 = TrackMap::VERSION;
+}
+
+impl Archive for TrackMapStream {
+    type Archived = ArchivedTrackMap;
+    type Resolver = TrackMapResolver;
+
+    fn resolve(&self, resolver: Self::Resolver, out: Place<Self::Archived>) {
+        munge!(let ArchivedTrackMap { map, manifest, min_contig_length } = out);
+        ArchivedBTreeMap::resolve_from_len(self.len, resolver.map, map);
+        self.manifest.resolve(resolver.manifest, manifest);
+        self.min_contig_length
+            .resolve(resolver.min_contig_length, min_contig_length);
+    }
+}
+
+impl<S> Serialize<S> for TrackMapStream
+where
+    S: Fallible + Allocator + Writer + ?Sized,
+    S::Error: Source,
+    String: Serialize<S>,
+    Index: Serialize<S>,
+    Manifest: Serialize<S>,
+    u64: Serialize<S>,
+{
+    fn serialize(&self, serializer: &mut S) -> std::result::Result<Self::Resolver, S::Error> {
+        let mut entries = self.entries.borrow_mut();
+        let map = ArchivedBTreeMap::<ArchivedString, ArchivedIndex>::serialize_from_ordered_iter::<
+            _,
+            String,
+            Index,
+            String,
+            Index,
+            _,
+        >(&mut *entries, serializer)?;
+        let manifest = self.manifest.serialize(serializer)?;
+        let min_contig_length = self.min_contig_length.serialize(serializer)?;
+        Ok(TrackMapResolver {
+            map,
+            manifest,
+            min_contig_length,
+        })
+    }
 }
 
 impl TrackMap {
@@ -34,122 +146,421 @@ impl TrackMap {
         strict: bool,
         min_contig_length: u64,
         num_workers: Option<usize>,
-        show_progress: bool,
-        names: Option<Vec<String>>,
+        progress: &Progress,
+        selection: &NameSelection,
     ) -> Result<Self> {
-        let root_path = Path::new(root);
-        let names = match names {
-            None => glob::glob(format!("{}/**/*.track.gz", root).as_str())?
-                .map(|entry| {
-                    entry.map_err(anyhow::Error::from).and_then(|path| {
-                        get_relative_name_without_suffix(&path, root_path, ".track.gz")
-                    })
-                })
-                .collect::<Result<Vec<_>>>()?,
-            Some(names) => names,
-        };
-        let num_names = names.len();
-
-        // Progress bar setup
-        let pb = if show_progress {
-            let pb = ProgressBar::new(num_names as u64);
-            pb.set_style(
-                ProgressStyle::with_template(
-                    "[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
-                )
-                .unwrap()
-                .progress_chars("##-"),
-            );
-            Some(pb)
+        let names = selection.resolve(root, ".track.gz")?;
+        let mut map = BTreeMap::new();
+        Self::index_in_batches(
+            root,
+            &names,
+            strict,
+            min_contig_length,
+            num_workers,
+            progress,
+            names.len().max(1),
+            |batch| {
+                map.extend(batch);
+                Ok(())
+            },
+        )?;
+        let manifest = Manifest::build(Path::new(root), map.keys(), INDEX_SUFFIXES)?;
+        Ok(TrackMap {
+            map,
+            manifest,
+            min_contig_length,
+        })
+    }
+
+    /// Build the map and write it to the cache file at `path` without holding all entries in
+    /// memory. Entries are indexed in batches and spilled to a temporary file, from which they
+    /// are streamed into the archive. The spill file is kept if the build is interrupted, and
+    /// with `resume`, a later build continues from it.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn build_to_file(
+        root: &str,
+        strict: bool,
+        min_contig_length: u64,
+        num_workers: Option<usize>,
+        progress: &Progress,
+        selection: &NameSelection,
+        path: &Path,
+        algorithm: ChecksumAlgorithm,
+        resume: bool,
+    ) -> Result<()> {
+        let mut names = selection.resolve(root, ".track.gz")?;
+        // The archived map is written in key order
+        names.sort();
+        names.dedup();
+        let mut spill_path = path.as_os_str().to_owned();
+        spill_path.push(".spill");
+        let spill_path = PathBuf::from(spill_path);
+        // A checkpoint can only be resumed by a build of the same type and options
+        let tag = fnv1a(
+            format!(
+                "{:016x}:{}",
+                type_specific_magic::<Self>(),
+                min_contig_length
+            )
+            .as_bytes(),
+        );
+        let checkpoint = if resume {
+            SpillWriter::resume(&spill_path, tag)?
         } else {
             None
         };
-
-        // Build indices in parallel using rayon. If num_workers is set, use a custom thread pool.
-        let build_indices = || {
-            let results: Result<Vec<Option<(String, Index)>>, anyhow::Error> = names
-                .par_iter()
-                .map(|name| {
-                    let res = match Self::index_name(name, Path::new(root), min_contig_length) {
-                        Ok(index) => Ok(Some((name.to_string(), index))),
-                        Err(e) => {
-                            if strict {
-                                Err(e.context(format!("Error processing track! {}", name)))
-                            } else {
-                                eprintln!(
-                                    "Error processing track: {}. Skipping. Error: {:?}",
-                                    name, e
-                                );
-                                Ok(None)
-                            }
-                        }
-                    };
-                    if let Some(pb) = &pb {
-                        pb.inc(1);
+        let (mut spill, mut indexed) = match checkpoint {
+            Some((spill, done)) => {
+                match Self::checkpoint_stale_reason(root, &names, &done, spill.started_ns()) {
+                    None => {
+                        info!("Resuming build from checkpoint with {} entries", done.len());
+                        (spill, done)
+                    }
+                    Some(reason) => {
+                        warn!("Discarding build checkpoint ({})", reason);
+                        (SpillWriter::create(&spill_path, tag)?, Vec::new())
                     }
-                    res
-                })
-                .collect();
-            results
+                }
+            }
+            None => (SpillWriter::create(&spill_path, tag)?, Vec::new()),
         };
+        // Names are indexed in order, so the checkpoint covers all names up to its last entry
+        let start = indexed
+            .last()
+            .map_or(0, |last| names.partition_point(|name| name <= last));
+        Self::index_in_batches(
+            root,
+            &names[start..],
+            strict,
+            min_contig_length,
+            num_workers,
+            progress,
+            BATCH_SIZE,
+            |batch| {
+                for entry in batch {
+                    spill.push(&entry)?;
+                    indexed.push(entry.0);
+                }
+                spill.checkpoint()
+            },
+        )?;
+        let manifest = Manifest::build(Path::new(root), indexed.iter(), INDEX_SUFFIXES)?;
+        let stream = TrackMapStream {
+            len: indexed.len(),
+            entries: RefCell::new(spill.finish()?),
+            manifest,
+            min_contig_length,
+        };
+        let result = write_direct(&stream, path, algorithm);
+        // A read error in the spill file surfaces as a length mismatch in rkyv, so report it first
+        stream.entries.into_inner().finish()?;
+        result
+    }
 
-        let results = if let Some(workers) = num_workers {
-            let pool = rayon::ThreadPoolBuilder::new()
-                .num_threads(workers)
-                .build()
-                .unwrap();
-            pool.install(build_indices)?
-        } else {
-            build_indices()?
+    /// Returns a reason if the entries of a build checkpoint cannot be reused, because they are
+    /// out of order or their index files were removed or modified after the build started.
+    fn checkpoint_stale_reason(
+        root: &str,
+        names: &[String],
+        done: &[String],
+        started_ns: u64,
+    ) -> Option<String> {
+        if done.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Some("entries are out of order".to_string());
+        }
+        if let Some(name) = done.iter().find(|name| names.binary_search(name).is_err()) {
+            return Some(format!("{} was removed", name));
+        }
+        match Manifest::build(Path::new(root), done, INDEX_SUFFIXES) {
+            Ok(manifest) => manifest
+                .modified_since(started_ns)
+                .map(|path| format!("{} was modified", path)),
+            Err(e) => Some(format!("{:?}", e)),
+        }
+    }
+
+    /// Index `names` in batches of `batch_size` and pass the successfully indexed entries of
+    /// each batch to `sink`, in the order of `names`.
+    #[allow(clippy::too_many_arguments)]
+    fn index_in_batches(
+        root: &str,
+        names: &[String],
+        strict: bool,
+        min_contig_length: u64,
+        num_workers: Option<usize>,
+        progress: &Progress,
+        batch_size: usize,
+        mut sink: impl FnMut(Vec<(String, Index)>) -> Result<()>,
+    ) -> Result<()> {
+        let tracker = progress.start(names.len() as u64);
+
+        // If num_workers is set, use a custom thread pool, or else the build pool if one is set
+        let pool = match num_workers {
+            Some(workers) => Some(pools::new_pool(workers, Operation::Build)?),
+            None => pools::pool(Operation::Build),
         };
 
-        if let Some(pb) = pb {
-            pb.finish_with_message("Indexing complete");
+        for batch in names.chunks(batch_size) {
+            // Build indices of this batch in parallel using rayon
+            let build_indices = || {
+                let results: Result<Vec<Option<(String, Index)>>, anyhow::Error> = batch
+                    .par_iter()
+                    .map(|name| {
+                        tracker.check_cancelled()?;
+                        let res = match Self::index_name(name, Path::new(root), min_contig_length) {
+                            Ok(index) => Ok(Some((name.to_string(), index))),
+                            Err(e) => {
+                                if strict {
+                                    Err(e.context(format!("Error processing track! {}", name)))
+                                } else {
+                                    warn!(
+                                        "Error processing track: {}. Skipping. Error: {:?}",
+                                        name, e
+                                    );
+                                    tracker.skip(name, format!("{:#}", e));
+                                    Ok(None)
+                                }
+                            }
+                        };
+                        tracker.inc(name);
+                        res
+                    })
+                    .collect();
+                results
+            };
+
+            let results = if let Some(pool) = &pool {
+                pool.install(build_indices)?
+            } else {
+                build_indices()?
+            };
+            sink(results.into_iter().flatten().collect())?;
         }
-        let map = results.into_iter().flatten().collect::<BTreeMap<_, _>>();
-        Ok(TrackMap { map })
+
+        tracker.finish();
+        Ok(())
+    }
+
+    /// Combine two maps built from the same root. Names present in both are handled by `policy`.
+    pub(crate) fn merge(mut self, other: Self, policy: ConflictPolicy) -> Result<Self> {
+        if self.min_contig_length != other.min_contig_length {
+            bail!(
+                "The maps were built with different minimum contig lengths, {} and {}",
+                self.min_contig_length,
+                other.min_contig_length
+            );
+        }
+        for (name, index) in other.map {
+            match self.map.entry(name) {
+                Entry::Vacant(entry) => {
+                    entry.insert(index);
+                }
+                Entry::Occupied(entry) => {
+                    if policy == ConflictPolicy::Error {
+                        bail!("Name {} is present in both maps", entry.key());
+                    }
+                }
+            }
+        }
+        self.manifest.merge(other.manifest);
+        Ok(self)
+    }
+
+    /// Split the map into `num_shards` maps by name hash and a directory of all names.
+    /// The manifest is only kept in the directory.
+    pub(crate) fn into_shards(self, num_shards: usize) -> (ShardDirectory, Vec<Self>) {
+        let names = self.map.keys().cloned().collect();
+        let directory = ShardDirectory::new(names, num_shards, self.manifest);
+        let mut shards = (0..num_shards)
+            .map(|_| TrackMap {
+                map: BTreeMap::new(),
+                manifest: Manifest::default(),
+                min_contig_length: self.min_contig_length,
+            })
+            .collect::<Vec<_>>();
+        for (name, index) in self.map {
+            shards[shard_of(&name, num_shards)].map.insert(name, index);
+        }
+        (directory, shards)
+    }
+
+    /// Check whether the index files below `root` changed since the sharded map was built.
+    /// Only the files in `selection` are checked.
+    pub(crate) fn directory_stale_reason(
+        directory: &ArchivedShardDirectory,
+        root: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>> {
+        directory.stale_reason(root, selection, ".track.gz", INDEX_SUFFIXES)
+    }
+
+    /// Re-index the given names with the options the map was built with, e.g., after their
+    /// cache entries were found to be damaged.
+    pub(crate) fn rebuild_entries(&mut self, root: &str, names: &[String]) -> Result<()> {
+        for name in names {
+            let index = Self::index_name(name, Path::new(root), self.min_contig_length)
+                .with_context(|| format!("Error rebuilding entry {}", name))?;
+            self.map.insert(name.clone(), index);
+        }
+        Ok(())
+    }
+
+    /// Index the given names, e.g., tracks added to the root since the map was built, with the
+    /// options the map was built with, and record their index files in the manifest. Names which
+    /// are in the map are re-indexed.
+    pub(crate) fn add_entries(&mut self, root: &str, names: &[String]) -> Result<()> {
+        for name in names {
+            check_name(name)?;
+        }
+        self.rebuild_entries(root, names)?;
+        self.manifest.remove(names, INDEX_SUFFIXES);
+        self.manifest
+            .merge(Manifest::build(Path::new(root), names, INDEX_SUFFIXES)?);
+        Ok(())
+    }
+
+    /// Drop the given names and their index files from the manifest.
+    pub(crate) fn remove_entries(&mut self, names: &[String]) -> Result<()> {
+        for name in names {
+            if self.map.remove(name).is_none() {
+                bail!(Error::NameNotFound(name.clone()));
+            }
+        }
+        self.manifest.remove(names, INDEX_SUFFIXES);
+        Ok(())
+    }
+
+    /// Validate the manifest of a map built on another machine against the files below `root`,
+    /// see `Manifest::localize`.
+    pub(crate) fn localize(&mut self, root: &str) -> Result<()> {
+        self.manifest = self.manifest.localize(Path::new(root))?;
+        Ok(())
     }
 
     fn index_name(name: &str, root: &Path, min_contig_length: u64) -> Result<Index> {
-        let gzi = BgzfIndex::read(root.join(format!("{}.track.gz.gzi", name)))
+        let _span = tracing::info_span!("index", name).entered();
+        let gzi = BgzfIndex::read(name_path(root, &format!("{}.track.gz.gzi", name)))
             .context("Failed to read .gzi")?;
         let track_index = TrackIndex::read(
-            root.join(format!("{}.track.gz.idx", name)),
+            name_path(root, &format!("{}.track.gz.idx", name)),
             min_contig_length,
         )
         .context("Failed to read .idx")?;
-        Ok(Index { gzi, track_index })
+        Ok(Index::new(gzi, track_index))
     }
 }
 
-impl ArchivedTrackMap {
-    pub(crate) fn names(&self) -> Vec<&str> {
+impl ArchivedMap for ArchivedTrackMap {
+    fn names(&self) -> Vec<&str> {
         self.map.keys().map(|s| s.as_str()).collect()
     }
+    fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    fn num_contigs(&self) -> usize {
+        self.map
+            .values()
+            .map(|index| index.track_index.num_contigs())
+            .sum()
+    }
+
+    fn total_length(&self) -> u64 {
+        self.map
+            .values()
+            .map(|index| index.track_index.total_length())
+            .sum()
+    }
+
+    fn contigs(&self, name: &str) -> Result<Vec<(&[u8], u64)>> {
+        ArchivedTrackMap::contigs(self, name)
+    }
+
+    fn spot_check(&self, root: &Path) -> Option<String> {
+        self.manifest.spot_check(root, SPOT_CHECK_FILES)
+    }
+
+    fn manifest(&self) -> Vec<(&str, Option<u64>, u64)> {
+        self.manifest.files()
+    }
+
+    fn checksum(&self, name: &str) -> Option<u64> {
+        self.map.get(name).map(|index| u64::from(index.checksum))
+    }
+}
+
+impl ArchivedTrackMap {
+    /// Names of all entries whose contents do not match their checksum.
+    pub(crate) fn damaged_entries(&self) -> Vec<String> {
+        self.map
+            .iter()
+            .filter(|(_, index)| index.is_damaged())
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    /// Returns a reason if this map was built with another `min_contig_length` than a load
+    /// requests, so that it has to be rebuilt.
+    pub(crate) fn options_reason(&self, min_contig_length: u64) -> Option<String> {
+        let built = u64::from(self.min_contig_length);
+        (built != min_contig_length).then(|| {
+            format!(
+                "min_contig_length {} instead of {}",
+                built, min_contig_length
+            )
+        })
+    }
+
+    /// Check whether the index files below `root` changed since this map was built.
+    /// Only the files in `selection` are checked.
+    pub(crate) fn stale_reason(
+        &self,
+        root: &str,
+        selection: &NameSelection,
+    ) -> Result<Option<String>> {
+        let names = selection.resolve(root, ".track.gz")?;
+        Ok(self
+            .manifest
+            .stale_reason(Path::new(root), &names, INDEX_SUFFIXES))
+    }
 
     pub(crate) fn contigs(&self, track_name: &str) -> Result<Vec<(&[u8], u64)>> {
-        let entry = self.map.get(track_name).ok_or(anyhow::anyhow!(format!(
-            "Track name not found: {}",
-            track_name
-        )))?;
+        let entry = self
+            .map
+            .get(track_name)
+            .ok_or_else(|| Error::NameNotFound(track_name.to_string()))?;
         Ok(entry.track_index.contigs())
     }
 
+    /// Length of `contig` in `track_name`, or `None` if there is no such contig.
+    pub(crate) fn contig_length(&self, track_name: &str, contig: &[u8]) -> Result<Option<u64>> {
+        let entry = self
+            .map
+            .get(track_name)
+            .ok_or_else(|| Error::NameNotFound(track_name.to_string()))?;
+        Ok(entry.track_index.contig_length(contig))
+    }
+
     pub(crate) fn query(
         &self,
         root: &str,
         track_name: &str,
         contig: &[u8],
         start: u64,
+        length: u64,
     ) -> Result<(PathBuf, VirtualPosition)> {
+        let _span = tracing::debug_span!("query", name = track_name, start, length).entered();
+        // The name is joined to the root below, so it must not escape it
+        check_name(track_name)?;
         // Search in index
         let entry = self
             .map
             .get(track_name)
-            .ok_or(anyhow::anyhow!("Name not found"))?;
-        let pos = entry.track_index.query(contig, start)?;
+            .ok_or_else(|| Error::NameNotFound(track_name.to_string()))?;
+        let pos = entry.track_index.query(contig, start, length)?;
         let offset = entry.gzi.query(pos)?;
-        let path = Path::new(root).join(format!("{}.track.gz", track_name));
+        METRICS.record_query(length, entry.gzi.num_blocks(pos, pos + length)?);
+        let path = name_path(Path::new(root), &format!("{}.track.gz", track_name));
         Ok((path, offset))
     }
 
@@ -161,11 +572,32 @@ impl ArchivedTrackMap {
         start: u64,
         length: u64,
     ) -> Result<Array1<u8>> {
-        let (path, pos) = self.query(root, track_name, contig, start)?;
-        let mut reader = bgzf::io::Reader::new(File::open(path)?);
-        reader.seek_to_virtual_position(pos)?;
-        let mut byte_buffer = vec![0; length as usize];
-        reader.read_exact(&mut byte_buffer)?;
-        Ok(Array1::from(byte_buffer))
+        let (path, pos) = self.query(root, track_name, contig, start, length)?;
+        let _span = tracing::debug_span!("decompress", length).entered();
+        with_retry(&path, || {
+            let mut reader = BgzfReader::new(open_data(&path)?);
+            reader.seek_to_virtual_position(pos)?;
+            let mut byte_buffer = vec![0; length as usize];
+            reader.read_exact(&mut byte_buffer)?;
+            Ok(Array1::from(byte_buffer))
+        })
+    }
+
+    /// Open a reader at `start` of `contig`, to read the following `length` values sequentially.
+    pub(crate) fn sequence_reader(
+        &self,
+        root: &str,
+        track_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> Result<SequenceReader> {
+        let (path, pos) = self.query(root, track_name, contig, start, length)?;
+        let reader = with_retry(&path, || {
+            let mut reader = BgzfReader::new(open_data(&path)?);
+            reader.seek_to_virtual_position(pos)?;
+            Ok(reader)
+        })?;
+        Ok(SequenceReader::new(reader, false))
     }
 }
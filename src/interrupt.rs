@@ -0,0 +1,42 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+/// Cancels a long operation from another thread, e.g., when Python receives a signal. The
+/// operation checks it between units of work, such as files to index or windows to read.
+#[derive(Clone, Default)]
+pub(crate) struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail if the operation was cancelled.
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!("Interrupted");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel() {
+        let cancel = Cancel::default();
+        let clone = cancel.clone();
+        assert!(clone.check().is_ok());
+        cancel.cancel();
+        assert!(clone.is_cancelled());
+        assert!(clone.check().is_err());
+    }
+}
@@ -1,133 +1,874 @@
+mod ambiguity;
+mod background;
+mod benchmark;
+mod bgzf_reader;
+mod blacklist;
+mod block_cache;
 mod cache;
+mod cli;
+mod collate;
+mod coordinates;
+mod dataset;
+mod duplicates;
+mod encoding;
+mod error;
 mod index;
+mod interrupt;
+mod metrics;
+mod mutate;
+mod partition;
+mod pools;
+mod preload;
+mod progress;
+mod readahead;
+#[cfg(feature = "refget")]
+mod refget;
+mod region;
+mod remote;
+mod retry;
+mod roots;
+mod sampler;
 mod storage;
+mod trace;
 mod util;
+mod validation;
+mod values;
+mod windows;
 
-use anyhow::Result;
-use index::{FastaMap, TrackMap};
+use std::collections::HashMap;
+use std::ffi::{c_int, c_void};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use index::{
+    ensure_indices, normalize_digest, ConflictPolicy, FastaMap, MappedTrack, PackedGenomes,
+    RleTrack, SparseTrack, TrackMap,
+};
+use log::debug;
 use noodles::bgzf;
 use noodles::core::{Position, Region};
 use noodles::fasta;
-use numpy::ndarray::Array1;
-use numpy::{IntoPyArray, PyArray1};
-use pyo3::{exceptions::PyRuntimeError, prelude::*};
+use numpy::ndarray::{Array1, Array2, ArrayView1};
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1};
+use pyo3::exceptions::{PyBufferError, PyRuntimeError, PyTimeoutError, PyValueError};
+use pyo3::types::{PyBytes, PyIterator, PyList, PyMemoryView};
+use pyo3::{create_exception, ffi, prelude::*};
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+pub use crate::cli::main as cli_main;
+
+use crate::ambiguity::Ambiguity;
+use crate::background::Background;
+use crate::bgzf_reader::IndexedBgzfReader;
+use crate::blacklist::Blacklist;
+use crate::block_cache::{BlockStore, SharedBlockCache};
+use crate::cache::LoadOptions;
+use crate::collate::{collate, Collated};
+use crate::coordinates::GlobalIndex;
+use crate::dataset::{check_aligned, AlignmentReport};
+use crate::encoding::{Encoded, Encoding};
+use crate::error::Error;
+use crate::interrupt::Cancel;
+use crate::metrics::METRICS;
+use crate::mutate::Mutator;
+use crate::partition::WindowPartition;
+use crate::pools::Operation;
+use crate::preload::{read_bases, Preloads};
+use crate::progress::{Progress, ProgressCallback};
+use crate::readahead::{Access, Readahead};
+use crate::region::ParsedRegion;
+use crate::remote::{is_remote, open_data};
+use crate::retry::RetryPolicy;
+use crate::roots::{RootHandle, Roots, UnionPolicy};
+use crate::sampler::{GcMatchedSampler, PositionSampler, WindowSampler};
+use crate::storage::{
+    write_direct, ArchiveStorage, ChecksumAlgorithm, MemoryStorage, MmapStorage, Segment,
+};
+use crate::validation::Alphabet;
+use crate::values::{aggregate, downsample, Aggregation, ValueType};
+use crate::windows::{
+    block_sums, composition, count_kmers, find_gaps, kmer_counts, SlidingWindows,
+};
+
+create_exception!(fastar_loader, FastarLoaderError, PyRuntimeError);
+create_exception!(fastar_loader, NameNotFoundError, FastarLoaderError);
+create_exception!(fastar_loader, ContigNotFoundError, FastarLoaderError);
+create_exception!(fastar_loader, OutOfBoundsError, FastarLoaderError);
+create_exception!(fastar_loader, CacheCorruptedError, FastarLoaderError);
+create_exception!(fastar_loader, StorageError, FastarLoaderError);
+create_exception!(fastar_loader, InvalidSequenceError, FastarLoaderError);
+
+/// Raise known failures as their dedicated exception and everything else as `RuntimeError`.
+fn to_py_err(e: &anyhow::Error) -> PyErr {
+    // Exceptions of Python code which ran on another thread, e.g., the sampler of a `Stream`
+    if let Some(e) = e.downcast_ref::<PyErr>() {
+        return Python::attach(|py| e.clone_ref(py));
+    }
+    let Some(error) = e.downcast_ref::<Error>() else {
+        return PyRuntimeError::new_err(format!("{:?}", e));
+    };
+    let message = format!("{:#}", e);
+    match error {
+        // An invalid name cannot be in any map
+        Error::NameNotFound(_) | Error::InvalidName(_) => NameNotFoundError::new_err(message),
+        Error::ContigNotFound(_) => ContigNotFoundError::new_err(message),
+        Error::OutOfBounds { .. } | Error::GlobalOutOfBounds { .. } => {
+            OutOfBoundsError::new_err(message)
+        }
+        Error::CacheCorrupted(_) => CacheCorruptedError::new_err(message),
+        Error::Storage(_) => StorageError::new_err(message),
+        Error::InvalidOptions(_) => PyValueError::new_err(message),
+        Error::InvalidSequence(_) => InvalidSequenceError::new_err(message),
+    }
+}
+
+/// How often long operations check for Python signals, see `detach_interruptible`.
+const SIGNAL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Wait without the GIL for the result of an operation on another thread, checking for Python
+/// signals every `SIGNAL_INTERVAL`. On an exception of a signal handler or after `timeout`,
+/// `cancel` is set and the error is returned without waiting for the operation.
+fn wait_for<T: Send>(
+    py: Python,
+    receiver: &mut Receiver<Result<T>>,
+    cancel: &Cancel,
+    timeout: Option<Duration>,
+) -> PyResult<T> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let interval = deadline.map_or(SIGNAL_INTERVAL, |deadline| {
+            SIGNAL_INTERVAL.min(deadline.saturating_duration_since(Instant::now()))
+        });
+        match py.detach(|| receiver.recv_timeout(interval)) {
+            Ok(result) => return result.map_err(|e| to_py_err(&e)),
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = py.check_signals() {
+                    cancel.cancel();
+                    return Err(e);
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    cancel.cancel();
+                    return Err(PyTimeoutError::new_err(format!(
+                        "The operation did not finish within {:?}",
+                        timeout.unwrap()
+                    )));
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(PyRuntimeError::new_err("The operation panicked"))
+            }
+        }
+    }
+}
+
+/// Run `f` without the GIL like `py.detach`, but check for Python signals every
+/// `SIGNAL_INTERVAL`, so that Ctrl-C interrupts it. On an exception of a signal handler, such
+/// as `KeyboardInterrupt`, `cancel` is set, `f` is awaited, as it returns early once it checks
+/// `cancel`, and the exception is raised.
+fn detach_interruptible<T, F>(py: Python, cancel: &Cancel, f: F) -> PyResult<T>
+where
+    T: Send,
+    F: FnOnce() -> Result<T> + Send,
+{
+    std::thread::scope(|scope| {
+        let (sender, mut receiver) = mpsc::channel();
+        let thread = scope.spawn(move || sender.send(f()));
+        let result = wait_for(py, &mut receiver, cancel, None);
+        if result.is_err() {
+            // `f` may need the GIL, e.g., for progress callbacks, before it returns
+            py.detach(move || thread.join().ok());
+        }
+        result
+    })
+}
+
+/// Run `f` without the GIL on the bounded `pools::timeout_pool`, but give up after `timeout`
+/// with a `TimeoutError`. Unlike `detach_interruptible`, `f` is not awaited after a timeout or
+/// signal: a read blocked by a hung network filesystem cannot be aborted, so it is left behind
+/// to finish or block on its own. `f` gets `cancel`, which is set then, and checks it before it
+/// reads, such that reads still queued behind blocked ones are skipped.
+fn detach_with_timeout<T, F>(py: Python, cancel: &Cancel, timeout: Duration, f: F) -> PyResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&Cancel) -> Result<T> + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel();
+    let pool = pools::timeout_pool().map_err(|e| to_py_err(&e))?;
+    let task_cancel = cancel.clone();
+    pool.spawn(move || {
+        // The receiver is gone if the caller gave up already
+        let _ = sender.send(f(&task_cancel));
+    });
+    wait_for(py, &mut receiver, cancel, Some(timeout))
+}
 
-use crate::storage::DynamicStorage;
+/// Values returned without numpy, which numpy-free consumers read through the buffer protocol,
+/// e.g., as a `memoryview`. The bytes are exposed read-only and without copying them.
+#[pyclass(frozen, name = "Buffer")]
+struct PyBuffer(Array1<u8>);
 
+#[pymethods]
+impl PyBuffer {
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        let Some(bytes) = slf.get().0.as_slice() else {
+            return Err(PyBufferError::new_err("The values are not contiguous"));
+        };
+        // Fails for writable requests, and keeps `slf`, which owns the bytes, alive as the
+        // owner of the view
+        let filled = ffi::PyBuffer_FillInfo(
+            view,
+            slf.as_ptr(),
+            bytes.as_ptr() as *mut c_void,
+            bytes.len() as ffi::Py_ssize_t,
+            1,
+            flags,
+        );
+        if filled == -1 {
+            return Err(PyErr::fetch(slf.py()));
+        }
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// `values` as a numpy array, or as a read-only `memoryview` of a `Buffer` with `as_memoryview`.
+fn values_object(py: Python, values: Array1<u8>, as_memoryview: bool) -> PyResult<Bound<PyAny>> {
+    if !as_memoryview {
+        return Ok(values.into_pyarray(py).into_any());
+    }
+    let buffer = Bound::new(py, PyBuffer(values))?;
+    Ok(PyMemoryView::from(buffer.as_any())?.into_any())
+}
+
+/// A timeout in seconds as given to reads.
+fn timeout_duration(seconds: f64) -> PyResult<Duration> {
+    if seconds <= 0.0 {
+        return Err(PyValueError::new_err("timeout must be positive"));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Read a region of a BGZF-compressed FASTA file: `region` is a contig with 0-based `start`
+/// and `length`, or without them a region string such as `chr1:1001-2000`, see
+/// `ParsedRegion`. Missing indices of local files are built next to them.
+#[allow(clippy::too_many_arguments)]
 #[pyfunction]
+#[pyo3(signature = (
+    fasta_path,
+    region,
+    start=None,
+    length=None,
+    gzi_path=None,
+    fai_path=None,
+    one_based=true,
+    end_inclusive=true,
+))]
 fn read_sequence<'py>(
     py: Python<'py>,
     fasta_path: &str,
-    gzi_path: &str,
-    fai_path: &str,
-    chromosome: &str,
-    start: usize,
-    length: usize,
+    region: &str,
+    start: Option<usize>,
+    length: Option<usize>,
+    gzi_path: Option<String>,
+    fai_path: Option<String>,
+    one_based: bool,
+    end_inclusive: bool,
 ) -> PyResult<Bound<'py, PyArray1<u8>>> {
-    read_sequence_(fasta_path, gzi_path, fai_path, chromosome, start, length)
-        .map(|arr| arr.into_pyarray(py))
-        .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))
+    py.detach(|| {
+        read_sequence_(
+            fasta_path,
+            region,
+            start,
+            length,
+            gzi_path,
+            fai_path,
+            one_based,
+            end_inclusive,
+        )
+    })
+    .map(|arr| arr.into_pyarray(py))
+    .map_err(|e| to_py_err(&e))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn read_sequence_(
     fasta_path: &str,
-    gzi_path: &str,
-    fai_path: &str,
-    chromosome: &str,
-    start: usize,
-    length: usize,
+    contig_or_region: &str,
+    start: Option<usize>,
+    length: Option<usize>,
+    gzi_path: Option<String>,
+    fai_path: Option<String>,
+    one_based: bool,
+    end_inclusive: bool,
 ) -> Result<Array1<u8>> {
-    let bgzf_reader = bgzf::io::indexed_reader::Builder::default()
-        .set_index(bgzf::gzi::fs::read(gzi_path)?)
-        .build_from_path(fasta_path)?;
+    let gzi_path = gzi_path.unwrap_or_else(|| format!("{}.gzi", fasta_path));
+    let fai_path = fai_path.unwrap_or_else(|| format!("{}.fai", fasta_path));
+    // The paths may also be URLs, which are read by range requests
+    if !is_remote(fasta_path) {
+        ensure_indices(
+            Path::new(fasta_path),
+            Path::new(&gzi_path),
+            Path::new(&fai_path),
+        )?;
+    }
+    let gzi = bgzf::gzi::io::Reader::new(open_data(Path::new(&gzi_path))?).read_index()?;
+    let fai = fasta::fai::io::Reader::new(BufReader::new(open_data(Path::new(&fai_path))?))
+        .read_index()?;
+    let region = match (start, length) {
+        (Some(start), Some(length)) => {
+            let start_pos = Position::try_from(start + 1)?;
+            let end_pos = Position::try_from(start + length)?;
+            Region::new(contig_or_region, start_pos..=end_pos)
+        }
+        (None, None) => {
+            let parsed =
+                ParsedRegion::parse(contig_or_region, one_based, end_inclusive, |contig| {
+                    fai.as_ref()
+                        .iter()
+                        .any(|record| record.name()[..] == *contig.as_bytes())
+                })?;
+            let start_pos = Position::try_from(parsed.start as usize + 1)?;
+            match parsed.end {
+                Some(end) => {
+                    Region::new(parsed.contig, start_pos..=Position::try_from(end as usize)?)
+                }
+                None => Region::new(parsed.contig, start_pos..),
+            }
+        }
+        _ => bail!("start and length must be given together"),
+    };
+    let bgzf_reader = IndexedBgzfReader::new(open_data(Path::new(fasta_path))?, gzi);
     let mut fasta_reader = fasta::io::indexed_reader::Builder::default()
-        .set_index(fasta::fai::fs::read(fai_path)?)
+        .set_index(fai)
         .build_from_reader(bgzf_reader)?;
-    let start_pos = Position::try_from(start + 1)?;
-    let end_pos = Position::try_from(start + length)?;
-    let region = Region::new(chromosome, start_pos..=end_pos);
     let record = fasta_reader.query(&region)?;
     let sequence = record.sequence().as_ref().to_vec();
     Ok(sequence.into())
 }
 
-#[pyclass(frozen, name = "FastaMap")]
-struct PyFastaMap {
-    storage: DynamicStorage<FastaMap>,
-    root: String,
+/// Contig, 0-based start and length of a samtools-style `region`, see `ParsedRegion`, with the
+/// lengths of the contigs of an entry by `contig_length`.
+fn resolve_region(
+    region: &str,
+    one_based: bool,
+    end_inclusive: bool,
+    contig_length: impl Fn(&[u8]) -> Result<Option<u64>>,
+) -> Result<(String, u64, u64)> {
+    let parsed = ParsedRegion::parse(region, one_based, end_inclusive, |contig| {
+        matches!(contig_length(contig.as_bytes()), Ok(Some(_)))
+    })?;
+    let end = match parsed.end {
+        Some(end) => end,
+        None => contig_length(parsed.contig.as_bytes())?
+            .ok_or_else(|| Error::ContigNotFound(parsed.contig.clone()))?,
+    };
+    Ok((
+        parsed.contig,
+        parsed.start,
+        end.saturating_sub(parsed.start),
+    ))
 }
 
-#[pymethods]
-impl PyFastaMap {
-    #[allow(clippy::too_many_arguments)]
-    #[staticmethod]
-    fn load(
-        py: Python,
-        root: &str,
-        strict: bool,
-        force_build: bool,
-        no_cache: bool,
-        min_contig_length: u64,
-        num_workers: Option<usize>,
-        show_progress: bool,
-        storage_method: &str,
-        names_list: Option<Vec<String>>,
-    ) -> PyResult<Self> {
-        py.detach(|| {
-            cache::load::<FastaMap>(
-                root,
-                ".fasta-map-cache",
-                strict,
-                min_contig_length,
-                num_workers,
-                show_progress,
-                storage_method,
-                no_cache,
-                force_build,
-                names_list,
-            )
+/// A segment as `(name, path, pid, created, size, alive)`.
+type SegmentTuple = (String, String, u32, u64, u64, bool);
+
+fn segment_tuple(segment: Segment) -> SegmentTuple {
+    (
+        segment.name,
+        segment.path.to_string_lossy().into_owned(),
+        segment.pid,
+        segment.created,
+        segment.size,
+        segment.alive,
+    )
+}
+
+#[pyfunction]
+fn list_segments() -> PyResult<Vec<SegmentTuple>> {
+    storage::list_segments()
+        .map(|segments| segments.into_iter().map(segment_tuple).collect())
+        .map_err(|e| to_py_err(&e))
+}
+
+/// Counters of the read paths of all loaders of the process, see `Metrics`.
+#[pyfunction]
+#[pyo3(name = "metrics")]
+fn py_metrics() -> Vec<(&'static str, u64)> {
+    METRICS.snapshot()
+}
+
+#[pyfunction]
+fn reset_metrics() {
+    METRICS.reset()
+}
+
+/// Random-read throughput and latency of `map`, see `benchmark::run`.
+#[pyfunction]
+#[pyo3(name = "benchmark")]
+fn py_benchmark(
+    py: Python,
+    map: WindowSource,
+    n_queries: usize,
+    length: u64,
+    seed: u64,
+    threads: usize,
+) -> PyResult<Vec<(&'static str, f64)>> {
+    let global = map.get_global(py)?;
+    let result = match &map {
+        WindowSource::Fasta(map) => {
+            let roots = map.get().get_roots(py)?;
+            py.detach(|| {
+                benchmark::run(
+                    global,
+                    n_queries,
+                    length,
+                    seed,
+                    threads,
+                    |name, contig, start, length| {
+                        map.get().read(roots, name, contig, start, length)
+                    },
+                )
+            })
+        }
+        WindowSource::Track(map) => {
+            let roots = map.get().get_roots(py)?;
+            py.detach(|| {
+                benchmark::run(
+                    global,
+                    n_queries,
+                    length,
+                    seed,
+                    threads,
+                    |name, contig, start, length| {
+                        roots.get(name).and_then(|(archive, root, name)| {
+                            archive.read_sequence(root, name, contig, start, length)
+                        })
+                    },
+                )
+            })
+        }
+    };
+    result.map_err(|e| to_py_err(&e))
+}
+
+/// Run the command line interface with `args` and return its exit code, see `cli::main`.
+#[pyfunction]
+#[pyo3(name = "cli")]
+fn py_cli(py: Python, args: Vec<String>) -> i32 {
+    py.detach(|| cli::main(args))
+}
+
+/// Export the spans of the build and read paths to `path`, see `trace::start`.
+#[pyfunction]
+fn start_tracing(path: PathBuf, format: &str) -> PyResult<()> {
+    trace::start(&path, format).map_err(|e| to_py_err(&e))
+}
+
+#[pyfunction]
+fn stop_tracing() -> PyResult<()> {
+    trace::stop().map_err(|e| to_py_err(&e))
+}
+
+/// Run builds and batch reads on dedicated pools of `build` and `read` threads, or on the
+/// global rayon pool for `None`, see `pools`.
+#[pyfunction]
+fn set_num_threads(build: Option<usize>, read: Option<usize>) -> PyResult<()> {
+    pools::set_num_threads(Operation::Build, build)
+        .and_then(|()| pools::set_num_threads(Operation::Read, read))
+        .map_err(|e| to_py_err(&e))
+}
+
+/// Retry reads of data files on transient errors, see `RetryPolicy`. A single attempt disables
+/// retries.
+#[pyfunction]
+fn set_retry_policy(attempts: u32, delay: f64, backoff: f64, errors: Vec<String>) -> PyResult<()> {
+    let policy = RetryPolicy::new(attempts, delay, backoff, &errors).map_err(|e| to_py_err(&e))?;
+    retry::set_policy((attempts > 1).then_some(policy));
+    Ok(())
+}
+
+#[pyfunction]
+fn cleanup_segments(prefix: &str, older_than: f64) -> PyResult<Vec<SegmentTuple>> {
+    let older_than = std::time::Duration::try_from_secs_f64(older_than)
+        .map_err(|e| PyRuntimeError::new_err(format!("older_than: {}", e)))?;
+    storage::cleanup_segments(prefix, older_than)
+        .map(|segments| segments.into_iter().map(segment_tuple).collect())
+        .map_err(|e| to_py_err(&e))
+}
+
+/// Report build progress to an optional Python callable `on_progress(done, total, name)`.
+/// Exceptions raised by the callable are reported as unraisable and do not abort the build.
+fn python_progress(show_progress: bool, on_progress: Option<Py<PyAny>>) -> Progress {
+    let callback = on_progress.map(|on_progress| -> ProgressCallback {
+        std::sync::Arc::new(move |done, total, name: &str| {
+            Python::attach(|py| {
+                if let Err(e) = on_progress.call1(py, (done, total, name)) {
+                    e.write_unraisable(py, Some(on_progress.bind(py)));
+                }
+            })
         })
-        .map(|storage| PyFastaMap {
-            storage,
-            root: root.to_string(),
+    });
+    Progress::new(show_progress, callback)
+}
+
+/// Options of `FastaMap.load` and `TrackMap.load`, read from the attributes of a Python
+/// `LoadOptions`.
+#[derive(FromPyObject)]
+struct PyLoadOptions {
+    strict: bool,
+    force_build: bool,
+    no_cache: Option<bool>,
+    min_contig_length: u64,
+    digests: bool,
+    composition: bool,
+    check_indices: bool,
+    num_workers: Option<usize>,
+    io_threads: Option<usize>,
+    show_progress: Option<bool>,
+    on_progress: Option<Py<PyAny>>,
+    storage_method: Option<String>,
+    names: Option<Vec<String>>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    follow_symlinks: bool,
+    checksum: String,
+    verify_checksum: bool,
+    repair: bool,
+    num_shards: Option<usize>,
+    lazy: bool,
+    background: bool,
+    bundle: Option<PathBuf>,
+    ambiguity: String,
+    ambiguity_seed: u64,
+    alphabet: Option<String>,
+    duplicates: String,
+    preload: Vec<String>,
+    preload_budget: Option<u64>,
+    block_cache: Option<u64>,
+    readahead: Option<usize>,
+}
+
+/// Validated `PyLoadOptions` with their defaults resolved.
+struct ResolvedOptions {
+    options: LoadOptions,
+    names: Option<Vec<String>>,
+    progress: Progress,
+    background: bool,
+    reads: ReadPolicy,
+    /// Whether to warn about contigs with identical sequences, see `warn_duplicates`.
+    warn_duplicates: bool,
+    io_threads: Option<usize>,
+    /// Genomes to preload and the preload budget, see `PyFastaMap::preload`.
+    preload: (Vec<String>, Option<u64>),
+    /// Size of the shared block cache in bytes, if any.
+    block_cache: Option<u64>,
+    /// Number of blocks to prefetch for sequential reads, if any.
+    readahead: Option<usize>,
+}
+
+impl PyLoadOptions {
+    /// Resolve the defaults which depend on `names`: all files are memory-mapped from the cache
+    /// with a progress bar, and a subset of names is kept in memory without a cache or bar.
+    /// Invalid options raise a `ValueError`, before anything is loaded in the background.
+    fn resolve(self, num_roots: usize) -> PyResult<ResolvedOptions> {
+        let subset = self.names.is_some();
+        let options = LoadOptions {
+            strict: self.strict,
+            force_build: self.force_build,
+            no_cache: self.no_cache.unwrap_or(subset),
+            min_contig_length: self.min_contig_length,
+            digests: self.digests,
+            composition: self.composition,
+            check_indices: self.check_indices,
+            num_workers: self.num_workers,
+            storage_method: self
+                .storage_method
+                .unwrap_or_else(|| if subset { "memory" } else { "mmap" }.to_string()),
+            include: self.include,
+            exclude: self.exclude,
+            follow_symlinks: self.follow_symlinks,
+            checksum: self.checksum,
+            verify_checksum: self.verify_checksum,
+            repair: self.repair,
+            num_shards: self.num_shards,
+            lazy: self.lazy,
+            bundle: self.bundle,
+        };
+        options.validate().map_err(|e| to_py_err(&e.into()))?;
+        let reads = ReadPolicy::new(
+            &self.ambiguity,
+            self.ambiguity_seed,
+            self.alphabet.as_deref(),
+        )?;
+        let warn_duplicates = match self.duplicates.as_str() {
+            "ignore" => false,
+            "warn" => true,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown duplicates policy {:?}, expected \"ignore\" or \"warn\"",
+                    other
+                )))
+            }
+        };
+        if warn_duplicates && !options.digests {
+            return Err(PyValueError::new_err(
+                "duplicates=\"warn\" requires digests=True",
+            ));
+        }
+        // The check reads the digests of all entries, which would index all of a lazy root
+        if warn_duplicates && options.lazy {
+            return Err(PyValueError::new_err(
+                "duplicates=\"warn\" cannot be combined with lazy=True",
+            ));
+        }
+        if self.io_threads == Some(0) {
+            return Err(PyValueError::new_err("io_threads must be positive"));
+        }
+        if self.preload_budget == Some(0) {
+            return Err(PyValueError::new_err("preload_budget must be positive"));
+        }
+        if let Some(size) = self
+            .block_cache
+            .filter(|&size| size < block_cache::MIN_SIZE)
+        {
+            return Err(PyValueError::new_err(format!(
+                "block_cache of {} bytes is too small, it needs at least {} bytes",
+                size,
+                block_cache::MIN_SIZE
+            )));
+        }
+        if self.readahead == Some(0) {
+            return Err(PyValueError::new_err("readahead must be positive"));
+        }
+        if options.bundle.is_some() && num_roots > 1 {
+            return Err(PyValueError::new_err(
+                "A bundle can only be imported into a single root",
+            ));
+        }
+        Ok(ResolvedOptions {
+            options,
+            names: self.names,
+            progress: python_progress(self.show_progress.unwrap_or(!subset), self.on_progress),
+            background: self.background,
+            reads,
+            warn_duplicates,
+            io_threads: self.io_threads,
+            preload: (self.preload, self.preload_budget),
+            block_cache: self.block_cache,
+            readahead: self.readahead,
         })
-        .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))
     }
+}
 
-    #[getter]
-    fn handle(&self) -> PyResult<Option<Vec<u8>>> {
-        let handle = self.storage.export();
-        Ok(handle)
+/// Raise a `ValueError` if `options` are invalid, e.g., when creating a `LoadOptions`.
+#[pyfunction]
+fn check_load_options(options: PyLoadOptions) -> PyResult<()> {
+    options.resolve(1).map(|_| ())
+}
+
+/// Fields of `Stats` in the order of `stats` of the Python loaders.
+type StatsTuple = (
+    usize,
+    Option<usize>,
+    Option<u64>,
+    Option<u64>,
+    String,
+    Vec<String>,
+);
+
+/// Contig names and lengths as numpy arrays.
+type ContigsArray<'py> = (Bound<'py, PyArray1<Py<PyAny>>>, Bound<'py, PyArray1<u64>>);
+
+/// Padded values, lengths and, if requested, the mask of collated sequences.
+type CollatedTuple<'py> = (
+    Bound<'py, PyArray2<u8>>,
+    Bound<'py, PyArray1<u64>>,
+    Option<Bound<'py, PyArray2<bool>>>,
+);
+
+fn collated_tuple<'py>(py: Python<'py>, collated: Collated, mask: bool) -> CollatedTuple<'py> {
+    let mask = mask.then(|| collated.mask().into_pyarray(py));
+    (
+        collated.values.into_pyarray(py),
+        collated.lengths.into_pyarray(py),
+        mask,
+    )
+}
+
+/// Pad `sequences` with `pad` to `length`, or to the longest sequence, see `collate`.
+#[pyfunction]
+#[pyo3(name = "collate", signature = (sequences, pad, length=None, mask=false))]
+fn py_collate<'py>(
+    py: Python<'py>,
+    sequences: Vec<PyReadonlyArray1<'py, u8>>,
+    pad: u8,
+    length: Option<usize>,
+    mask: bool,
+) -> PyResult<CollatedTuple<'py>> {
+    let views = sequences
+        .iter()
+        .map(|sequence| sequence.as_array())
+        .collect::<Vec<_>>();
+    let collated = py
+        .detach(|| collate(&views, pad, length))
+        .map_err(|e| to_py_err(&e))?;
+    Ok(collated_tuple(py, collated, mask))
+}
+
+fn contigs_array<'py>(py: Python<'py>, contigs: &[(&[u8], u64)]) -> ContigsArray<'py> {
+    let names = contigs
+        .iter()
+        .map(|(contig, _)| PyBytes::new(py, contig).into_any().unbind())
+        .collect::<Vec<_>>();
+    let lengths = contigs
+        .iter()
+        .map(|(_, length)| *length)
+        .collect::<Vec<_>>();
+    (names.into_pyarray(py), lengths.into_pyarray(py))
+}
+
+/// A dedicated pool of `io_threads` threads for the batch reads of a map, if given.
+fn io_pool(io_threads: Option<usize>) -> PyResult<Option<Arc<ThreadPool>>> {
+    io_threads
+        .map(|io_threads| pools::new_pool(io_threads, Operation::Read))
+        .transpose()
+        .map_err(|e| to_py_err(&e))
+}
+
+/// Cancels batch reads from another thread, e.g., a watchdog of a data loader worker. The
+/// reads check the token between windows or entries, so a read blocked in I/O finishes first.
+#[pyclass(frozen, name = "CancellationToken")]
+#[derive(Default)]
+struct PyCancellationToken(Cancel);
+
+impl PyCancellationToken {
+    /// The `Cancel` of an optional token, which is also set by signals during the operation.
+    fn cancel_of(token: Option<PyRef<Self>>) -> Cancel {
+        token.map_or_else(Cancel::default, |token| token.0.clone())
     }
+}
 
-    #[getter]
-    fn root(&self) -> PyResult<&str> {
-        Ok(&self.root)
+#[pymethods]
+impl PyCancellationToken {
+    #[new]
+    fn new() -> Self {
+        Self::default()
     }
 
-    #[staticmethod]
-    fn from_handle(handle: Vec<u8>, root: &str) -> PyResult<Self> {
-        DynamicStorage::<FastaMap>::import(handle)
-            .map(|storage| PyFastaMap {
-                storage,
-                root: root.to_string(),
-            })
-            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))
+    fn cancel(&self) {
+        self.0.cancel();
     }
 
     #[getter]
-    fn names(&self) -> PyResult<Vec<&str>> {
-        Ok(self.storage.as_ref().names())
+    fn cancelled(&self) -> bool {
+        self.0.is_cancelled()
     }
+}
 
-    fn contigs(&self, fasta_name: &str) -> PyResult<Vec<(&[u8], u64)>> {
-        self.storage
-            .as_ref()
-            .contigs(fasta_name)
-            .map_err(|e| PyRuntimeError::new_err(format!("Error getting contigs: {:?}", e)))
+#[pyclass(frozen, name = "FastaMap")]
+struct PyFastaMap {
+    roots: Background<Roots<FastaMap>>,
+    /// Built on first use, as it needs the contigs of all entries.
+    global: OnceLock<GlobalIndex>,
+    /// (name, contig) by MD5 and GA4GH digest, built on first use.
+    by_digest: OnceLock<HashMap<String, (String, Vec<u8>)>>,
+    reads: ReadPolicy,
+    /// Dedicated pool of batch reads, see `install`.
+    io_pool: Option<Arc<ThreadPool>>,
+    /// Genomes whose reads are served from shared memory, see `preload`.
+    preloads: Preloads,
+    /// Decompressed blocks shared with other processes, if enabled.
+    block_cache: OnceLock<Arc<SharedBlockCache>>,
+    /// Prefetching of blocks for sequential reads, if enabled.
+    readahead: OnceLock<Arc<Readahead>>,
+}
+
+/// Checks and replacements of the sequences returned by reads of a `FastaMap`, which do not
+/// apply to statistics such as composition.
+#[derive(Clone)]
+struct ReadPolicy {
+    ambiguity: Ambiguity,
+    /// Reads are checked against the alphabet before the ambiguity policy, if given.
+    alphabet: Option<Alphabet>,
+}
+
+impl ReadPolicy {
+    fn new(ambiguity: &str, ambiguity_seed: u64, alphabet: Option<&str>) -> PyResult<Self> {
+        let ambiguity = Ambiguity::parse(ambiguity, ambiguity_seed).map_err(|e| to_py_err(&e))?;
+        let alphabet = alphabet
+            .map(Alphabet::new)
+            .transpose()
+            .map_err(|e| to_py_err(&e))?;
+        Ok(ReadPolicy {
+            ambiguity,
+            alphabet,
+        })
     }
 
-    fn read_sequence<'py>(
+    fn is_default(&self) -> bool {
+        self.ambiguity == Ambiguity::Keep && self.alphabet.is_none()
+    }
+
+    /// Check and modify `sequence`, which should have `length` bases from `start` of `contig`.
+    fn apply(
+        &self,
+        name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        sequence: &mut Array1<u8>,
+    ) -> Result<()> {
+        let sequence = sequence.as_slice_mut().unwrap();
+        if let Some(alphabet) = &self.alphabet {
+            alphabet.check(name, contig, start, length, sequence)?;
+        }
+        self.ambiguity.apply(name, contig, start, sequence);
+        Ok(())
+    }
+}
+
+impl PyFastaMap {
+    fn new(roots: Background<Roots<FastaMap>>, reads: ReadPolicy) -> Self {
+        PyFastaMap {
+            roots,
+            global: OnceLock::new(),
+            by_digest: OnceLock::new(),
+            reads,
+            io_pool: None,
+            preloads: Preloads::default(),
+            block_cache: OnceLock::new(),
+            readahead: OnceLock::new(),
+        }
+    }
+
+    fn with_io_pool(self, io_pool: Option<Arc<ThreadPool>>) -> Self {
+        PyFastaMap { io_pool, ..self }
+    }
+
+    /// Run the batch read `f` on the pool of this map, or else on the read pool of
+    /// `set_num_threads`, or else on the global rayon pool.
+    fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        pools::install(self.io_pool.as_ref(), Operation::Read, f)
+    }
+
+    /// The loaded roots, waiting for a background build if necessary.
+    fn get_roots(&self, py: Python) -> PyResult<&Roots<FastaMap>> {
+        py.detach(|| self.roots.wait()).map_err(to_py_err)
+    }
+
+    fn read_array<'py>(
         &self,
         py: Python<'py>,
         fasta_name: &str,
@@ -135,114 +876,3049 @@ impl PyFastaMap {
         start: u64,
         length: u64,
     ) -> PyResult<Bound<'py, PyArray1<u8>>> {
-        py.detach(|| {
-            self.storage
-                .as_ref()
-                .read_sequence(&self.root, fasta_name, contig, start, length)
-        })
-        .map(|arr| arr.into_pyarray(py))
-        .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))
+        let roots = self.get_roots(py)?;
+        py.detach(|| self.read(roots, fasta_name, contig, start, length))
+            .map(|arr| arr.into_pyarray(py))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Read a region of `fasta_name` from `roots`, or from memory if the genome is preloaded,
+    /// through the block cache if enabled, or the blocks prefetched by readahead for sequential
+    /// reads, and apply the read policy.
+    fn read(
+        &self,
+        roots: &Roots<FastaMap>,
+        fasta_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> Result<Array1<u8>> {
+        let mut sequence = match self.preloads.read(fasta_name, contig, start, length) {
+            Some(sequence) => sequence,
+            None => {
+                let (archive, root, name) = roots.get(fasta_name)?;
+                let readahead = self.readahead.get();
+                let access = readahead.map_or(Access::Random, |readahead| {
+                    readahead.observe(fasta_name, contig, start, length)
+                });
+                // Without a shared block cache, only sequential reads look up the blocks
+                // prefetched into this process, and the others are read by the map, in
+                // parallel and with retries
+                let store: Option<Arc<dyn BlockStore>> = match (self.block_cache.get(), readahead) {
+                    (Some(cache), _) => Some(cache.clone()),
+                    (None, Some(readahead)) if access != Access::Random => Some(readahead.blocks()),
+                    _ => None,
+                };
+                match store {
+                    Some(store) => {
+                        let span = archive.plan(root, name, contig, start, length)?;
+                        let (raw, next_offset) = block_cache::read_raw(
+                            &*store,
+                            &span.path,
+                            span.checksum,
+                            span.pos,
+                            span.len,
+                        )?;
+                        let sequence = span.strip(raw)?;
+                        if let (Access::Prefetch, Some(readahead)) = (access, readahead) {
+                            readahead.prefetch(
+                                store,
+                                fasta_name,
+                                contig,
+                                span.path,
+                                span.checksum,
+                                next_offset,
+                            );
+                        }
+                        sequence
+                    }
+                    None => archive.read_sequence(root, name, contig, start, length)?,
+                }
+            }
+        };
+        self.reads
+            .apply(fasta_name, contig, start, length, &mut sequence)?;
+        Ok(sequence)
+    }
+
+    fn get_global(&self, py: Python) -> PyResult<&GlobalIndex> {
+        if let Some(global) = self.global.get() {
+            return Ok(global);
+        }
+        let roots = self.get_roots(py)?;
+        let global = py
+            .detach(|| {
+                GlobalIndex::build(roots.names()?, |name| {
+                    roots
+                        .get(name)
+                        .and_then(|(archive, _, name)| archive.contigs(name))
+                })
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok(self.global.get_or_init(|| global))
+    }
+
+    fn get_by_digest(&self, py: Python) -> PyResult<&HashMap<String, (String, Vec<u8>)>> {
+        if let Some(by_digest) = self.by_digest.get() {
+            return Ok(by_digest);
+        }
+        let roots = self.get_roots(py)?;
+        let by_digest = py
+            .detach(|| {
+                let mut by_digest = HashMap::new();
+                for fasta_name in roots.names()? {
+                    let (archive, _, name) = roots.get(&fasta_name)?;
+                    for (contig, md5, sq) in archive.digests(name)? {
+                        let entry = (fasta_name.clone(), contig.to_vec());
+                        by_digest.insert(md5, entry.clone());
+                        by_digest.insert(sq, entry);
+                    }
+                }
+                anyhow::Ok(by_digest)
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok(self.by_digest.get_or_init(|| by_digest))
     }
 }
 
-#[pyclass(frozen, name = "TrackMap")]
-struct PyTrackMap {
-    storage: DynamicStorage<TrackMap>,
-    root: String,
+/// Serves the sequences of a `FastaMap` by digest. The digest lookup is built before the server
+/// starts, so requests never need the GIL.
+#[cfg(feature = "refget")]
+struct FastaMapSource(Py<PyFastaMap>);
+
+#[cfg(feature = "refget")]
+impl FastaMapSource {
+    /// Root, local name and contig of the sequence with `digest`.
+    fn find(&self, digest: &str) -> Result<Option<(&Roots<FastaMap>, &str, &[u8])>> {
+        let map = self.0.get();
+        let roots = map.roots.wait().map_err(|e| anyhow::anyhow!("{:#}", e))?;
+        let by_digest = map.by_digest.get().context("Digest lookup not built")?;
+        Ok(by_digest
+            .get(&normalize_digest(digest))
+            .map(|(name, contig)| (roots, name.as_str(), contig.as_slice())))
+    }
+}
+
+#[cfg(feature = "refget")]
+impl refget::SequenceSource for FastaMapSource {
+    fn metadata(&self, digest: &str) -> Result<Option<refget::SequenceMetadata>> {
+        let Some((roots, fasta_name, contig)) = self.find(digest)? else {
+            return Ok(None);
+        };
+        let (archive, _, name) = roots.get(fasta_name)?;
+        let (md5, sha512t24u) = archive.digest(name, contig)?;
+        let length = archive
+            .contig_length(name, contig)?
+            .ok_or_else(|| Error::ContigNotFound(String::from_utf8_lossy(contig).into_owned()))?;
+        Ok(Some(refget::SequenceMetadata {
+            md5,
+            sha512t24u,
+            length,
+        }))
+    }
+
+    fn read(&self, digest: &str, start: u64, length: u64) -> Result<Vec<u8>> {
+        let (roots, fasta_name, contig) = self
+            .find(digest)?
+            .ok_or_else(|| Error::ContigNotFound(digest.to_string()))?;
+        let (archive, root, name) = roots.get(fasta_name)?;
+        Ok(archive
+            .read_sequence(root, name, contig, start, length)?
+            .into_raw_vec_and_offset()
+            .0)
+    }
+}
+
+/// An embedded refget server for a `FastaMap` loaded with digests, running until `stop` is
+/// called or the server is garbage collected.
+#[cfg(feature = "refget")]
+#[pyclass(frozen, name = "RefgetServer")]
+struct PyRefgetServer {
+    server: Mutex<Option<refget::RefgetServer>>,
+    address: String,
 }
 
+#[cfg(feature = "refget")]
 #[pymethods]
-impl PyTrackMap {
-    #[allow(clippy::too_many_arguments)]
+impl PyRefgetServer {
+    #[new]
+    #[pyo3(signature = (map, address="127.0.0.1:0", num_threads=4))]
+    fn new(py: Python, map: Py<PyFastaMap>, address: &str, num_threads: usize) -> PyResult<Self> {
+        map.get().get_by_digest(py)?;
+        let source = std::sync::Arc::new(FastaMapSource(map));
+        let server = py
+            .detach(|| refget::RefgetServer::start(address, num_threads, source))
+            .map_err(|e| to_py_err(&e))?;
+        Ok(PyRefgetServer {
+            address: server.address(),
+            server: Mutex::new(Some(server)),
+        })
+    }
+
+    /// Address the server listens on as `host:port`.
+    #[getter]
+    fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Stop accepting requests and wait for the requests in progress.
+    fn stop(&self, py: Python) {
+        let server = self.server.lock().unwrap().take();
+        py.detach(|| drop(server));
+    }
+}
+
+#[pymethods]
+impl PyFastaMap {
+    #[staticmethod]
+    fn load(py: Python, roots: Vec<(String, String)>, options: PyLoadOptions) -> PyResult<Self> {
+        let ResolvedOptions {
+            options,
+            names,
+            progress,
+            background,
+            reads,
+            warn_duplicates,
+            io_threads,
+            preload,
+            block_cache,
+            readahead,
+        } = options.resolve(roots.len())?;
+        let cancel = progress.cancel().clone();
+        let load = move || {
+            let prefixes = roots
+                .iter()
+                .map(|(prefix, _)| prefix.as_str())
+                .collect::<Vec<_>>();
+            roots::check_prefixes(&prefixes)?;
+            let names = roots::split_names(&prefixes, names)?;
+            let mut skipped = Vec::new();
+            let storages = roots
+                .iter()
+                .zip(names)
+                .map(|((prefix, root), names)| -> Result<_> {
+                    let cache_name = cache::fasta_cache_name(&options);
+                    let storage =
+                        cache::load::<FastaMap>(root, &cache_name, names, &options, &progress)?;
+                    skipped.extend(
+                        progress
+                            .take_skipped()
+                            .into_iter()
+                            .map(|(name, reason)| (format!("{}{}", prefix, name), reason)),
+                    );
+                    Ok((prefix.clone(), root.clone(), storage))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let roots = Roots::new(storages)?.with_skipped(skipped);
+            if warn_duplicates {
+                duplicates::warn_duplicates(&roots)?;
+            }
+            Ok(roots)
+        };
+        let roots = if background {
+            Background::spawn(load)
+        } else {
+            Background::ready(detach_interruptible(py, &cancel, load)?)
+        };
+        let map = PyFastaMap::new(roots, reads).with_io_pool(io_pool(io_threads)?);
+        if let Some(size) = block_cache {
+            let cache = py
+                .detach(|| SharedBlockCache::new(size))
+                .map_err(|e| to_py_err(&e))?;
+            let _ = map.block_cache.set(Arc::new(cache));
+        }
+        if let Some(num_blocks) = readahead {
+            map.enable_readahead(num_blocks)?;
+        }
+        let (preload, preload_budget) = preload;
+        if !preload.is_empty() || preload_budget.is_some() {
+            map.preload(py, preload, preload_budget)?;
+        }
+        Ok(map)
+    }
+
+    /// Names skipped in non-strict mode while building, as (name, reason).
+    /// Empty if the map was loaded from the cache.
+    #[getter]
+    fn skipped(&self, py: Python) -> PyResult<Vec<(String, String)>> {
+        Ok(self.get_roots(py)?.skipped().to_vec())
+    }
+
+    /// Whether a background build finished, successfully or not.
+    fn ready(&self) -> bool {
+        self.roots.is_ready()
+    }
+
+    /// Block until a background build finished. Errors of the build are raised here.
+    fn wait(&self, py: Python) -> PyResult<()> {
+        self.get_roots(py).map(|_| ())
+    }
+
+    /// Handles of all roots as (prefix, root, handle), or `None` if any storage is not shared.
+    #[getter]
+    fn handle(&self, py: Python) -> PyResult<Option<Vec<RootHandle>>> {
+        self.get_roots(py)?.export().map_err(|e| to_py_err(&e))
+    }
+
+    /// Attach to the roots of `handle`, of which those in `roots` as (prefix, path) were moved.
+    /// The ambiguity policy and alphabet of reads and the pool of batch reads are not part of
+    /// the handle.
     #[staticmethod]
-    fn load(
-        py: Python,
-        root: &str,
-        strict: bool,
-        force_build: bool,
-        no_cache: bool,
-        min_contig_length: u64,
-        num_workers: Option<usize>,
-        show_progress: bool,
+    #[pyo3(signature = (
+        handle,
+        validate=false,
+        roots=vec![],
+        ambiguity="keep",
+        ambiguity_seed=0,
+        alphabet=None,
+        io_threads=None,
+    ))]
+    fn from_handle(
+        handle: Vec<RootHandle>,
+        validate: bool,
+        roots: Vec<(String, String)>,
+        ambiguity: &str,
+        ambiguity_seed: u64,
+        alphabet: Option<&str>,
+        io_threads: Option<usize>,
+    ) -> PyResult<Self> {
+        let reads = ReadPolicy::new(ambiguity, ambiguity_seed, alphabet)?;
+        let io_pool = io_pool(io_threads)?;
+        Roots::<FastaMap>::import(handle, validate, roots)
+            .map(|roots| PyFastaMap::new(Background::ready(roots), reads).with_io_pool(io_pool))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Number of threads of the dedicated pool of batch reads, if any.
+    #[getter]
+    fn io_threads(&self) -> Option<usize> {
+        self.io_pool.as_ref().map(|pool| pool.current_num_threads())
+    }
+
+    /// The ambiguity policy of reads as (policy, seed).
+    #[getter]
+    fn ambiguity(&self) -> (&'static str, u64) {
+        self.reads.ambiguity.name()
+    }
+
+    /// The alphabet reads are checked against, if any.
+    #[getter]
+    fn alphabet(&self) -> Option<&str> {
+        self.reads.alphabet.as_ref().map(Alphabet::letters)
+    }
+
+    /// Write the map to `path` as a bundle, which can be imported with the same files on
+    /// another machine.
+    fn export_bundle(&self, py: Python, path: &str) -> PyResult<()> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.export_bundle(Path::new(path)))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Number of names, contigs and bases, archive size in bytes, storage method and cache
+    /// files, see `Roots::stats`.
+    fn stats(&self, py: Python) -> PyResult<StatsTuple> {
+        let stats = self.get_roots(py)?.stats().map_err(|e| to_py_err(&e))?;
+        Ok((
+            stats.num_names,
+            stats.num_contigs,
+            stats.total_length,
+            stats.archive_size,
+            stats.storage_method,
+            stats.cache_paths,
+        ))
+    }
+
+    /// Hash of the contents of all entries, see `Roots::content_hash`.
+    fn content_hash(&self, py: Python) -> PyResult<String> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.content_hash())
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Number of contigs, total length and file size of each name, see `Roots::summary`.
+    fn summary(&self, py: Python) -> PyResult<Vec<(String, usize, u64, Option<u64>)>> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.summary()).map_err(|e| to_py_err(&e))
+    }
+
+    /// Combine this map with `other` into new archives, without re-indexing.
+    /// Duplicate names are rejected (`on_conflict="error"`), resolved in favor of this map
+    /// (`"first"`), or avoided by namespacing both maps with `prefixes` (`"prefix"`).
+    #[pyo3(signature = (other, on_conflict="error", prefixes=None, storage_method="memory"))]
+    fn union(
+        &self,
+        py: Python,
+        other: &PyFastaMap,
+        on_conflict: &str,
+        prefixes: Option<(String, String)>,
         storage_method: &str,
-        names: Option<Vec<String>>,
     ) -> PyResult<Self> {
+        let (roots, other_roots) = (self.get_roots(py)?, other.get_roots(py)?);
         py.detach(|| {
-            cache::load::<TrackMap>(
-                root,
-                ".track-map-cache",
-                strict,
-                min_contig_length,
-                num_workers,
-                show_progress,
-                storage_method,
-                no_cache,
-                force_build,
-                names,
-            )
+            let policy = match (on_conflict, prefixes) {
+                ("prefix", Some((first, second))) => UnionPolicy::Prefix(first, second),
+                ("prefix", None) => bail!("on_conflict=prefix requires prefixes"),
+                (_, Some(_)) => bail!("prefixes can only be used with on_conflict=prefix"),
+                (name, None) => UnionPolicy::Merge(ConflictPolicy::parse(name)?),
+            };
+            roots.union(other_roots, &policy, storage_method)
         })
-        .map(|storage| PyTrackMap {
-            storage,
-            root: root.to_string(),
+        .map(|roots| {
+            PyFastaMap::new(Background::ready(roots), self.reads.clone())
+                .with_io_pool(self.io_pool.clone())
         })
-        .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))
+        .map_err(|e| to_py_err(&e))
     }
 
-    #[getter]
-    fn handle(&self) -> PyResult<Option<Vec<u8>>> {
-        let handle = self.storage.export();
-        Ok(handle)
+    /// Index the entries `names` without re-indexing the others, see `Roots::with_added`.
+    fn with_added(&self, py: Python, names: Vec<String>) -> PyResult<Self> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.with_added(names))
+            .map(|roots| {
+                PyFastaMap::new(Background::ready(roots), self.reads.clone())
+                    .with_io_pool(self.io_pool.clone())
+            })
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Drop the entries `names` without re-indexing the others, see `Roots::with_removed`.
+    fn with_removed(&self, py: Python, names: Vec<String>) -> PyResult<Self> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.with_removed(names))
+            .map(|roots| {
+                PyFastaMap::new(Background::ready(roots), self.reads.clone())
+                    .with_io_pool(self.io_pool.clone())
+            })
+            .map_err(|e| to_py_err(&e))
     }
 
     #[getter]
-    fn root(&self) -> PyResult<&str> {
-        Ok(&self.root)
+    fn names(&self, py: Python) -> PyResult<Vec<String>> {
+        self.get_roots(py)?.names().map_err(|e| to_py_err(&e))
     }
 
-    #[staticmethod]
-    fn from_handle(handle: Vec<u8>, root: &str) -> PyResult<Self> {
-        DynamicStorage::<TrackMap>::import(handle)
-            .map(|storage| PyTrackMap {
-                storage,
-                root: root.to_string(),
-            })
-            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))
+    fn __len__(&self, py: Python) -> PyResult<usize> {
+        self.get_roots(py)?.num_names().map_err(|e| to_py_err(&e))
+    }
+
+    /// Directories containing names, see `roots::groups`.
+    fn groups(&self, py: Python) -> PyResult<Vec<String>> {
+        let names = self.get_roots(py)?.names().map_err(|e| to_py_err(&e))?;
+        Ok(roots::groups(&names))
+    }
+
+    /// Names in the directory `group` or below it.
+    fn names_in_group(&self, py: Python, group: &str) -> PyResult<Vec<String>> {
+        let names = self.get_roots(py)?.names().map_err(|e| to_py_err(&e))?;
+        Ok(roots::names_in_group(&names, group))
+    }
+
+    /// Weights of names which sample each group with its weight, see `roots::group_weights`.
+    fn group_weights(
+        &self,
+        py: Python,
+        weights: HashMap<String, f64>,
+    ) -> PyResult<HashMap<String, f64>> {
+        let names = self.get_roots(py)?.names().map_err(|e| to_py_err(&e))?;
+        roots::group_weights(&names, &weights).map_err(|e| to_py_err(&e))
+    }
+
+    fn __contains__(&self, py: Python, name: &str) -> PyResult<bool> {
+        self.get_roots(py)?
+            .contains(name)
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Iterate over the names.
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        let names = self.get_roots(py)?.names().map_err(|e| to_py_err(&e))?;
+        PyList::new(py, names)?.try_iter()
     }
 
+    /// Storage method in use, e.g., as chosen by `storage_method="auto"`.
     #[getter]
-    fn names(&self) -> PyResult<Vec<&str>> {
-        Ok(self.storage.as_ref().names())
+    fn storage_method(&self, py: Python) -> PyResult<String> {
+        Ok(self.get_roots(py)?.storage_method())
     }
 
-    fn contigs(&self, fasta_name: &str) -> PyResult<Vec<(&[u8], u64)>> {
-        self.storage
-            .as_ref()
-            .contigs(fasta_name)
-            .map_err(|e| PyRuntimeError::new_err(format!("Error getting contigs: {:?}", e)))
+    /// Does not block on a background build.
+    fn __repr__(&self) -> String {
+        if !self.roots.is_ready() {
+            return "FastaMap(loading)".to_string();
+        }
+        match self.roots.wait() {
+            Ok(roots) => match roots.describe() {
+                Ok(description) => format!("FastaMap({})", description),
+                Err(e) => format!("FastaMap(failed: {:#})", e),
+            },
+            Err(e) => format!("FastaMap(failed: {:#})", e),
+        }
     }
 
-    fn read_sequence<'py>(
+    fn contigs(&self, py: Python, fasta_name: &str) -> PyResult<Vec<(&[u8], u64)>> {
+        self.get_roots(py)?
+            .get(fasta_name)
+            .and_then(|(archive, _, name)| archive.contigs(name))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Contig names as an object array of bytes and contig lengths as an uint64 array.
+    fn contigs_array<'py>(&self, py: Python<'py>, fasta_name: &str) -> PyResult<ContigsArray<'py>> {
+        let contigs = self
+            .get_roots(py)?
+            .get(fasta_name)
+            .and_then(|(archive, _, name)| archive.contigs(name))
+            .map_err(|e| to_py_err(&e))?;
+        Ok(contigs_array(py, &contigs))
+    }
+
+    /// Fields of the metadata sidecar of `fasta_name`, sorted by key.
+    fn metadata(&self, py: Python, fasta_name: &str) -> PyResult<Vec<(&str, &str)>> {
+        self.get_roots(py)?
+            .get(fasta_name)
+            .and_then(|(archive, _, name)| archive.metadata(name))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Names whose metadata field `key` is one of `values`, in the order of `names`.
+    fn filter_by_metadata(
         &self,
-        py: Python<'py>,
-        track_name: &str,
-        contig: &[u8],
-        start: u64,
-        length: u64,
-    ) -> PyResult<Bound<'py, PyArray1<u8>>> {
+        py: Python,
+        key: &str,
+        values: Vec<String>,
+    ) -> PyResult<Vec<String>> {
+        let roots = self.get_roots(py)?;
         py.detach(|| {
-            self.storage
-                .as_ref()
-                .read_sequence(&self.root, track_name, contig, start, length)
+            let mut names = Vec::new();
+            for fasta_name in roots.names()? {
+                let (archive, _, name) = roots.get(&fasta_name)?;
+                if archive
+                    .metadata_value(name, key)?
+                    .is_some_and(|value| values.iter().any(|v| v == value))
+                {
+                    names.push(fasta_name);
+                }
+            }
+            anyhow::Ok(names)
         })
-        .map(|arr| arr.into_pyarray(py))
-        .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// Length of `contig` in the entry `fasta_name`, or `None` if there is no such contig.
+    fn contig_length(&self, py: Python, fasta_name: &str, contig: &[u8]) -> PyResult<Option<u64>> {
+        self.get_roots(py)?
+            .get(fasta_name)
+            .and_then(|(archive, _, name)| archive.contig_length(name, contig))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Contig, 0-based start and length of a samtools-style `region` in the entry `fasta_name`.
+    #[pyo3(signature = (fasta_name, region, one_based=true, end_inclusive=true))]
+    fn resolve_region(
+        &self,
+        py: Python,
+        fasta_name: &str,
+        region: &str,
+        one_based: bool,
+        end_inclusive: bool,
+    ) -> PyResult<(String, u64, u64)> {
+        let (archive, _, name) = self
+            .get_roots(py)?
+            .get(fasta_name)
+            .map_err(|e| to_py_err(&e))?;
+        resolve_region(region, one_based, end_inclusive, |contig| {
+            archive.contig_length(name, contig)
+        })
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// MD5 and GA4GH digest of `contig` in `fasta_name`. Requires a map loaded with `digests`.
+    fn digest(&self, py: Python, fasta_name: &str, contig: &[u8]) -> PyResult<(String, String)> {
+        self.get_roots(py)?
+            .get(fasta_name)
+            .and_then(|(archive, _, name)| archive.digest(name, contig))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Groups of (name, contig) with identical sequences, see `duplicate_contigs`.
+    fn duplicate_contigs(&self, py: Python) -> PyResult<Vec<Vec<(String, Vec<u8>)>>> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| duplicates::duplicate_contigs(roots))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Name and contig of the sequence with an MD5 or GA4GH `digest`, or `None` if there is none.
+    fn find_digest(&self, py: Python, digest: &str) -> PyResult<Option<(String, Vec<u8>)>> {
+        Ok(self
+            .get_by_digest(py)?
+            .get(&normalize_digest(digest))
+            .cloned())
+    }
+
+    /// Read `length` bases starting at `start` of the sequence with an MD5 or GA4GH `digest`.
+    fn read_by_digest<'py>(
+        &self,
+        py: Python<'py>,
+        digest: &str,
+        start: u64,
+        length: u64,
+    ) -> PyResult<Bound<'py, PyArray1<u8>>> {
+        let Some((fasta_name, contig)) = self.get_by_digest(py)?.get(&normalize_digest(digest))
+        else {
+            let e = anyhow::Error::from(Error::ContigNotFound(digest.to_string()));
+            return Err(to_py_err(&e));
+        };
+        self.read_array(py, fasta_name, contig, start, length)
+    }
+
+    /// Intervals of runs of at least `min_length` `N` in `contig` as an array with one
+    /// (start, end) row per run. The contig is scanned on every call.
+    #[pyo3(signature = (fasta_name, contig, min_length=1))]
+    fn gaps<'py>(
+        &self,
+        py: Python<'py>,
+        fasta_name: &str,
+        contig: &[u8],
+        min_length: u64,
+    ) -> PyResult<Bound<'py, PyArray2<u64>>> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| {
+            let (archive, root, name) = roots.get(fasta_name)?;
+            let length = archive.contig_length(name, contig)?.ok_or_else(|| {
+                Error::ContigNotFound(String::from_utf8_lossy(contig).into_owned())
+            })?;
+            let reader = archive.sequence_reader(root, name, contig, 0, length)?;
+            find_gaps(reader, length, min_length)
+        })
+        .map(|gaps| gaps.into_pyarray(py))
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// Gaps of all contigs of `fasta_name` as (contig, gaps), see `gaps`. Contigs are scanned
+    /// in parallel.
+    #[pyo3(signature = (fasta_name, min_length=1))]
+    fn genome_gaps<'py>(
+        &self,
+        py: Python<'py>,
+        fasta_name: &str,
+        min_length: u64,
+    ) -> PyResult<Vec<(Bound<'py, PyBytes>, Bound<'py, PyArray2<u64>>)>> {
+        let roots = self.get_roots(py)?;
+        let gaps = py
+            .detach(|| {
+                let (archive, root, name) = roots.get(fasta_name)?;
+                let contigs = archive.contigs(name)?;
+                self.install(|| {
+                    contigs
+                        .into_par_iter()
+                        .map(|(contig, length)| {
+                            let reader = archive.sequence_reader(root, name, contig, 0, length)?;
+                            Ok((contig, find_gaps(reader, length, min_length)?))
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok(gaps
+            .into_iter()
+            .map(|(contig, gaps)| (PyBytes::new(py, contig), gaps.into_pyarray(py)))
+            .collect())
+    }
+
+    /// Problems of the FASTA files of `fasta_names`, or all names, which do not match their
+    /// indices, as (name, problems) for each name with problems. `deep` also decompresses
+    /// `num_blocks` random blocks of each file. Files are checked in parallel.
+    #[pyo3(signature = (fasta_names=None, deep=false, num_blocks=16, seed=None))]
+    fn verify(
+        &self,
+        py: Python,
+        fasta_names: Option<Vec<String>>,
+        deep: bool,
+        num_blocks: usize,
+        seed: Option<u64>,
+    ) -> PyResult<Vec<(String, Vec<String>)>> {
+        let roots = self.get_roots(py)?;
+        let fasta_names = match fasta_names {
+            Some(fasta_names) => fasta_names,
+            None => roots.names().map_err(|e| to_py_err(&e))?,
+        };
+        let deep = deep.then(|| (num_blocks, seed.unwrap_or_else(rand::random)));
+        let cancel = Cancel::default();
+        detach_interruptible(py, &cancel, || {
+            self.install(|| {
+                fasta_names
+                    .into_par_iter()
+                    .map(|fasta_name| {
+                        cancel.check()?;
+                        let (archive, root, name) = roots.get(&fasta_name)?;
+                        let problems = archive.verify(root, name, deep)?;
+                        Ok((fasta_name, problems))
+                    })
+                    .filter(|result| !matches!(result, Ok((_, problems)) if problems.is_empty()))
+                    .collect::<Result<Vec<_>>>()
+            })
+        })
+    }
+
+    /// Counts of `A`, `C`, `G`, `T` and other bases in windows of `window` bases of the region
+    /// `start` and `length` of `contig`, one window per row, see `composition` in `windows`.
+    fn composition<'py>(
+        &self,
+        py: Python<'py>,
+        fasta_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        window: u64,
+    ) -> PyResult<Bound<'py, PyArray2<u64>>> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| {
+            let (archive, root, name) = roots.get(fasta_name)?;
+            let reader = archive.sequence_reader(root, name, contig, start, length)?;
+            composition(reader, length, window)
+        })
+        .map(|counts| counts.into_pyarray(py))
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// Base counts of all contigs of `fasta_name` as (contig, counts), stored in the cache.
+    /// Requires a map loaded with `composition`.
+    fn genome_composition<'py>(
+        &self,
+        py: Python<'py>,
+        fasta_name: &str,
+    ) -> PyResult<Vec<(Bound<'py, PyBytes>, Bound<'py, PyArray1<u64>>)>> {
+        let roots = self.get_roots(py)?;
+        let composition = roots
+            .get(fasta_name)
+            .and_then(|(archive, _, name)| archive.composition(name))
+            .map_err(|e| to_py_err(&e))?;
+        Ok(composition
+            .into_iter()
+            .map(|(contig, counts)| (PyBytes::new(py, contig), counts.to_vec().into_pyarray(py)))
+            .collect())
+    }
+
+    /// Counts of all `4^k` k-mers in `length` bases from `start` of `contig`, see
+    /// `count_kmers` in `windows`. The region is streamed without holding it in memory.
+    fn count_kmers<'py>(
+        &self,
+        py: Python<'py>,
+        fasta_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        k: usize,
+    ) -> PyResult<Bound<'py, PyArray1<u64>>> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| {
+            let mut counts = kmer_counts(k)?;
+            let (archive, root, name) = roots.get(fasta_name)?;
+            let reader = archive.sequence_reader(root, name, contig, start, length)?;
+            count_kmers(reader, length, &mut counts)?;
+            anyhow::Ok(counts)
+        })
+        .map(|counts| counts.into_pyarray(py))
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// K-mer counts summed over all contigs of `fasta_name`, which are scanned in parallel.
+    /// K-mers across contig boundaries are not counted.
+    fn genome_kmers<'py>(
+        &self,
+        py: Python<'py>,
+        fasta_name: &str,
+        k: usize,
+    ) -> PyResult<Bound<'py, PyArray1<u64>>> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| {
+            let empty = kmer_counts(k)?;
+            let (archive, root, name) = roots.get(fasta_name)?;
+            let contigs = archive.contigs(name)?;
+            self.install(|| {
+                contigs
+                    .into_par_iter()
+                    .try_fold(
+                        || empty.clone(),
+                        |mut counts, (contig, length)| {
+                            let reader = archive.sequence_reader(root, name, contig, 0, length)?;
+                            count_kmers(reader, length, &mut counts)?;
+                            anyhow::Ok(counts)
+                        },
+                    )
+                    .try_reduce(
+                        || empty.clone(),
+                        |mut sums, counts| {
+                            sums.iter_mut()
+                                .zip(counts)
+                                .for_each(|(sum, count)| *sum += count);
+                            Ok(sums)
+                        },
+                    )
+            })
+        })
+        .map(|counts| counts.into_pyarray(py))
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// Total length of all contigs in the global coordinate system, see `GlobalIndex`.
+    fn global_length(&self, py: Python) -> PyResult<u64> {
+        Ok(self.get_global(py)?.length())
+    }
+
+    /// Stable id of `contig` in the entry `fasta_name`.
+    fn global_id(&self, py: Python, fasta_name: &str, contig: &[u8]) -> PyResult<u32> {
+        self.get_global(py)?
+            .id(fasta_name, contig)
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Name and contig of a global id, or `None` if there is no such id.
+    fn global_contig(&self, py: Python, id: u32) -> PyResult<Option<(&str, &[u8])>> {
+        Ok(self.get_global(py)?.contig(id))
+    }
+
+    /// Global position of the first value of `contig` in the entry `fasta_name`.
+    fn global_start(&self, py: Python, fasta_name: &str, contig: &[u8]) -> PyResult<u64> {
+        let global = self.get_global(py)?;
+        let id = global.id(fasta_name, contig).map_err(|e| to_py_err(&e))?;
+        Ok(global.start(id).unwrap())
+    }
+
+    /// Name, contig and offset within the contig of a global position.
+    fn locate(&self, py: Python, position: u64) -> PyResult<(&str, &[u8], u64)> {
+        self.get_global(py)?
+            .locate(position)
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Read `length` values at a global position. The region must lie within one contig.
+    fn read_at<'py>(
+        &self,
+        py: Python<'py>,
+        position: u64,
+        length: u64,
+    ) -> PyResult<Bound<'py, PyArray1<u8>>> {
+        let (fasta_name, contig, start) = self
+            .get_global(py)?
+            .locate_region(position, length)
+            .map_err(|e| to_py_err(&e))?;
+        self.read_array(py, fasta_name, contig, start, length)
+    }
+
+    /// Read a region of `fasta_name`. With `timeout` in seconds, the read raises
+    /// `TimeoutError` if it does not finish in time, e.g., on a hung network filesystem. With
+    /// `as_memoryview`, the sequence is returned as a `memoryview` instead of a numpy array.
+    #[pyo3(signature = (fasta_name, contig, start, length, timeout=None, as_memoryview=false))]
+    fn read_sequence<'py>(
+        slf: &Bound<'py, Self>,
+        fasta_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        timeout: Option<f64>,
+        as_memoryview: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let py = slf.py();
+        let roots = slf.get().get_roots(py)?;
+        let sequence = match timeout {
+            None => py
+                .detach(|| slf.get().read(roots, fasta_name, contig, start, length))
+                .map_err(|e| to_py_err(&e))?,
+            Some(timeout) => {
+                let timeout = timeout_duration(timeout)?;
+                let map = slf.clone().unbind();
+                let (fasta_name, contig) = (fasta_name.to_string(), contig.to_vec());
+                detach_with_timeout(py, &Cancel::default(), timeout, move |cancel| {
+                    cancel.check()?;
+                    let map = map.get();
+                    let roots = map.roots.wait().expect("roots are loaded before the read");
+                    map.read(roots, &fasta_name, &contig, start, length)
+                })?
+            }
+        };
+        values_object(py, sequence, as_memoryview)
+    }
+
+    /// Read the same region of `contig` from several entries in parallel, by default from all
+    /// entries which have the contig. Regions are cut at the end of each contig, such that
+    /// shorter contigs give shorter or empty sequences. Named entries without the contig raise
+    /// an error. The reads stop with an error once `cancel` is cancelled.
+    #[pyo3(signature = (contig, start, length, fasta_names=None, cancel=None))]
+    fn read_sequence_all<'py>(
+        &self,
+        py: Python<'py>,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        fasta_names: Option<Vec<String>>,
+        cancel: Option<PyRef<PyCancellationToken>>,
+    ) -> PyResult<Vec<(String, Bound<'py, PyArray1<u8>>)>> {
+        let roots = self.get_roots(py)?;
+        let skip_missing = fasta_names.is_none();
+        let fasta_names = match fasta_names {
+            Some(fasta_names) => fasta_names,
+            None => roots.names().map_err(|e| to_py_err(&e))?,
+        };
+        let cancel = PyCancellationToken::cancel_of(cancel);
+        let sequences = detach_interruptible(py, &cancel, || {
+            self.install(|| {
+                fasta_names
+                    .into_par_iter()
+                    .map(|fasta_name| -> Result<Option<(String, Array1<u8>)>> {
+                        cancel.check()?;
+                        let (archive, root, name) = roots.get(&fasta_name)?;
+                        let Some(contig_length) = archive.contig_length(name, contig)? else {
+                            if skip_missing {
+                                return Ok(None);
+                            }
+                            let contig = String::from_utf8_lossy(contig).into_owned();
+                            return Err(Error::ContigNotFound(contig).into());
+                        };
+                        let length = length.min(contig_length.saturating_sub(start));
+                        if length == 0 {
+                            return Ok(Some((fasta_name, Array1::zeros(0))));
+                        }
+                        let mut sequence =
+                            archive.read_sequence(root, name, contig, start, length)?;
+                        self.reads
+                            .apply(&fasta_name, contig, start, length, &mut sequence)?;
+                        Ok(Some((fasta_name, sequence)))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+        })?;
+        Ok(sequences
+            .into_iter()
+            .flatten()
+            .map(|(fasta_name, sequence)| (fasta_name, sequence.into_pyarray(py)))
+            .collect())
+    }
+
+    /// Read `queries` in parallel and pad them with `pad` to `length`, or to the longest
+    /// sequence, as values, lengths and, with `mask`, the mask of the values. The reads stop
+    /// with an error once `cancel` is cancelled.
+    #[pyo3(signature = (queries, pad, length=None, mask=false, cancel=None))]
+    fn read_batch<'py>(
+        &self,
+        py: Python<'py>,
+        queries: Vec<Query>,
+        pad: u8,
+        length: Option<usize>,
+        mask: bool,
+        cancel: Option<PyRef<PyCancellationToken>>,
+    ) -> PyResult<CollatedTuple<'py>> {
+        let roots = self.get_roots(py)?;
+        let cancel = PyCancellationToken::cancel_of(cancel);
+        let collated = detach_interruptible(py, &cancel, || {
+            let sequences = self.install(|| {
+                queries
+                    .par_iter()
+                    .map(|(fasta_name, contig, start, length)| {
+                        cancel.check()?;
+                        self.read(roots, fasta_name, contig, *start, *length)
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })?;
+            let views = sequences.iter().map(Array1::view).collect::<Vec<_>>();
+            collate(&views, pad, length)
+        })?;
+        Ok(collated_tuple(py, collated, mask))
+    }
+
+    /// Decompress `fasta_names` into shared memory, to serve their reads by copying the bases.
+    /// With `budget`, the preloaded genomes are limited to that many bytes, evicting the least
+    /// recently read genomes.
+    #[pyo3(signature = (fasta_names, budget=None))]
+    fn preload(&self, py: Python, fasta_names: Vec<String>, budget: Option<u64>) -> PyResult<()> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| {
+            if budget.is_some() {
+                self.preloads.set_budget(budget);
+            }
+            for fasta_name in &fasta_names {
+                if self.preloads.contains(fasta_name) {
+                    continue;
+                }
+                let (archive, root, name) = roots.get(fasta_name)?;
+                let contigs = archive.contigs(name)?;
+                self.install(|| {
+                    self.preloads.insert(fasta_name, contigs, |contig, bases| {
+                        let length = bases.len() as u64;
+                        read_bases(
+                            archive.sequence_reader(root, name, contig, 0, length)?,
+                            bases,
+                        )
+                    })
+                })?;
+            }
+            anyhow::Ok(())
+        })
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// Names and sizes of the preloaded genomes, most recently read first.
+    fn preloaded(&self) -> Vec<(String, u64)> {
+        self.preloads.genomes()
+    }
+
+    /// The handle of the block cache, to attach to it from other processes with
+    /// `attach_block_cache`.
+    #[getter]
+    fn block_cache_handle(&self) -> Option<Vec<u8>> {
+        self.block_cache.get().map(|cache| cache.export())
+    }
+
+    /// Attach to the block cache of another process, which must still exist.
+    fn attach_block_cache(&self, handle: Vec<u8>) -> PyResult<()> {
+        let cache = SharedBlockCache::import(handle).map_err(|e| to_py_err(&e))?;
+        self.block_cache
+            .set(Arc::new(cache))
+            .map_err(|_| PyRuntimeError::new_err("The map already has a block cache"))
+    }
+
+    /// Size in bytes, hits and misses of the block cache over all attached processes.
+    fn block_cache_stats(&self) -> Option<(u64, u64, u64)> {
+        self.block_cache.get().map(|cache| cache.stats())
+    }
+
+    /// Prefetch the next `num_blocks` BGZF blocks in the background once reads of a contig
+    /// turn out to be sequential, into the block cache if enabled or else into memory.
+    fn enable_readahead(&self, num_blocks: usize) -> PyResult<()> {
+        if num_blocks == 0 {
+            return Err(PyValueError::new_err("readahead must be positive"));
+        }
+        self.readahead
+            .set(Arc::new(Readahead::new(num_blocks)))
+            .map_err(|_| PyRuntimeError::new_err("The map already has readahead"))
+    }
+
+    /// Number of blocks prefetched for sequential reads, if readahead is enabled.
+    #[getter]
+    fn readahead(&self) -> Option<usize> {
+        self.readahead.get().map(|readahead| readahead.num_blocks())
+    }
+
+    /// The preload budget and the names and handles of the preloaded genomes, to attach to
+    /// them from other processes with `attach_preloaded`.
+    #[getter]
+    fn preload_handle(&self) -> (Option<u64>, Vec<(String, Vec<u8>)>) {
+        (self.preloads.budget(), self.preloads.handles())
+    }
+
+    /// Attach to genomes preloaded by another process. Genomes which were evicted in the
+    /// meantime are skipped and read from the archive instead.
+    fn attach_preloaded(&self, budget: Option<u64>, handles: Vec<(String, Vec<u8>)>) {
+        self.preloads.set_budget(budget);
+        for (fasta_name, handle) in handles {
+            if let Err(e) = self.preloads.attach(&fasta_name, handle) {
+                debug!("Not attaching to preloaded {}: {:#}", fasta_name, e);
+            }
+        }
+    }
+
+    /// Pack `fasta_names` to two bits per base and write them to `path`, to be opened with
+    /// `PackedGenomes`.
+    fn write_packed(&self, py: Python, fasta_names: Vec<String>, path: &str) -> PyResult<()> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| {
+            let genomes = fasta_names
+                .iter()
+                .map(|fasta_name| {
+                    let (archive, root, name) = roots.get(fasta_name)?;
+                    Ok((fasta_name.as_str(), archive, root, name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let packed = PackedGenomes::from_fasta(&genomes)?;
+            write_direct(&packed, Path::new(path), ChecksumAlgorithm::Xxh3)
+        })
+        .map_err(|e| to_py_err(&e))
+    }
+}
+
+#[pyclass(frozen, name = "TrackMap")]
+struct PyTrackMap {
+    roots: Background<Roots<TrackMap>>,
+    /// Built on first use, as it needs the contigs of all entries.
+    global: OnceLock<GlobalIndex>,
+    /// Dedicated pool of batch reads, see `install`.
+    io_pool: Option<Arc<ThreadPool>>,
+}
+
+impl PyTrackMap {
+    fn new(roots: Background<Roots<TrackMap>>) -> Self {
+        PyTrackMap {
+            roots,
+            global: OnceLock::new(),
+            io_pool: None,
+        }
+    }
+
+    fn with_io_pool(self, io_pool: Option<Arc<ThreadPool>>) -> Self {
+        PyTrackMap { io_pool, ..self }
+    }
+
+    /// Run the batch read `f` on the pool of this map, see `PyFastaMap::install`.
+    fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        pools::install(self.io_pool.as_ref(), Operation::Read, f)
+    }
+
+    /// Read a region of `track_name` from `roots`, see `read_sequence`.
+    #[allow(clippy::too_many_arguments)]
+    fn read_values(
+        roots: &Roots<TrackMap>,
+        track_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        step: usize,
+        value_size: usize,
+        reverse: bool,
+        channels: Option<Vec<usize>>,
+    ) -> Result<Array1<u8>> {
+        let (archive, root, name) = roots.get(track_name)?;
+        let mut bytes = archive.read_sequence(root, name, contig, start, length)?;
+        if step != 1 {
+            bytes = downsample(bytes.as_slice().unwrap(), step, value_size)?.into();
+        }
+        if reverse {
+            let channels = channels.unwrap_or_else(|| vec![0]);
+            bytes = values::reverse(bytes.as_slice().unwrap(), value_size, &channels)?.into();
+        }
+        Ok(bytes)
+    }
+
+    /// The loaded roots, waiting for a background build if necessary.
+    fn get_roots(&self, py: Python) -> PyResult<&Roots<TrackMap>> {
+        py.detach(|| self.roots.wait()).map_err(to_py_err)
+    }
+
+    fn get_global(&self, py: Python) -> PyResult<&GlobalIndex> {
+        if let Some(global) = self.global.get() {
+            return Ok(global);
+        }
+        let roots = self.get_roots(py)?;
+        let global = py
+            .detach(|| {
+                GlobalIndex::build(roots.names()?, |name| {
+                    roots
+                        .get(name)
+                        .and_then(|(archive, _, name)| archive.contigs(name))
+                })
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok(self.global.get_or_init(|| global))
+    }
+}
+
+#[pymethods]
+impl PyTrackMap {
+    #[staticmethod]
+    fn load(py: Python, roots: Vec<(String, String)>, options: PyLoadOptions) -> PyResult<Self> {
+        let ResolvedOptions {
+            options,
+            names,
+            progress,
+            background,
+            reads,
+            // Requires digests, which are rejected below
+            warn_duplicates: _,
+            io_threads,
+            preload,
+            block_cache,
+            readahead,
+        } = options.resolve(roots.len())?;
+        let cancel = progress.cancel().clone();
+        if options.digests
+            || options.composition
+            || options.check_indices
+            || !reads.is_default()
+            || !preload.0.is_empty()
+            || preload.1.is_some()
+            || block_cache.is_some()
+            || readahead.is_some()
+        {
+            return Err(PyValueError::new_err(
+                "Digests, composition, index checks, ambiguity policies, alphabets, preloading, \
+                 block caches and readahead are only supported for FASTA files",
+            ));
+        }
+        let load = move || {
+            let prefixes = roots
+                .iter()
+                .map(|(prefix, _)| prefix.as_str())
+                .collect::<Vec<_>>();
+            roots::check_prefixes(&prefixes)?;
+            let names = roots::split_names(&prefixes, names)?;
+            let mut skipped = Vec::new();
+            let storages = roots
+                .iter()
+                .zip(names)
+                .map(|((prefix, root), names)| -> Result<_> {
+                    let storage = cache::load::<TrackMap>(
+                        root,
+                        cache::TRACK_CACHE_NAME,
+                        names,
+                        &options,
+                        &progress,
+                    )?;
+                    skipped.extend(
+                        progress
+                            .take_skipped()
+                            .into_iter()
+                            .map(|(name, reason)| (format!("{}{}", prefix, name), reason)),
+                    );
+                    Ok((prefix.clone(), root.clone(), storage))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Roots::new(storages)?.with_skipped(skipped))
+        };
+        let roots = if background {
+            Background::spawn(load)
+        } else {
+            Background::ready(detach_interruptible(py, &cancel, load)?)
+        };
+        Ok(PyTrackMap::new(roots).with_io_pool(io_pool(io_threads)?))
+    }
+
+    /// Names skipped in non-strict mode while building, as (name, reason).
+    /// Empty if the map was loaded from the cache.
+    #[getter]
+    fn skipped(&self, py: Python) -> PyResult<Vec<(String, String)>> {
+        Ok(self.get_roots(py)?.skipped().to_vec())
+    }
+
+    /// Whether a background build finished, successfully or not.
+    fn ready(&self) -> bool {
+        self.roots.is_ready()
+    }
+
+    /// Block until a background build finished. Errors of the build are raised here.
+    fn wait(&self, py: Python) -> PyResult<()> {
+        self.get_roots(py).map(|_| ())
+    }
+
+    /// Handles of all roots as (prefix, root, handle), or `None` if any storage is not shared.
+    #[getter]
+    fn handle(&self, py: Python) -> PyResult<Option<Vec<RootHandle>>> {
+        self.get_roots(py)?.export().map_err(|e| to_py_err(&e))
+    }
+
+    /// Attach to the roots of `handle`, of which those in `roots` as (prefix, path) were moved.
+    /// The pool of batch reads is not part of the handle.
+    #[staticmethod]
+    #[pyo3(signature = (handle, validate=false, roots=vec![], io_threads=None))]
+    fn from_handle(
+        handle: Vec<RootHandle>,
+        validate: bool,
+        roots: Vec<(String, String)>,
+        io_threads: Option<usize>,
+    ) -> PyResult<Self> {
+        let io_pool = io_pool(io_threads)?;
+        Roots::<TrackMap>::import(handle, validate, roots)
+            .map(|roots| PyTrackMap::new(Background::ready(roots)).with_io_pool(io_pool))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Number of threads of the dedicated pool of batch reads, if any.
+    #[getter]
+    fn io_threads(&self) -> Option<usize> {
+        self.io_pool.as_ref().map(|pool| pool.current_num_threads())
+    }
+
+    /// Write the map to `path` as a bundle, which can be imported with the same files on
+    /// another machine.
+    fn export_bundle(&self, py: Python, path: &str) -> PyResult<()> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.export_bundle(Path::new(path)))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Number of names, contigs and bases, archive size in bytes, storage method and cache
+    /// files, see `Roots::stats`.
+    fn stats(&self, py: Python) -> PyResult<StatsTuple> {
+        let stats = self.get_roots(py)?.stats().map_err(|e| to_py_err(&e))?;
+        Ok((
+            stats.num_names,
+            stats.num_contigs,
+            stats.total_length,
+            stats.archive_size,
+            stats.storage_method,
+            stats.cache_paths,
+        ))
+    }
+
+    /// Hash of the contents of all entries, see `Roots::content_hash`.
+    fn content_hash(&self, py: Python) -> PyResult<String> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.content_hash())
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Number of contigs, total length and file size of each name, see `Roots::summary`.
+    fn summary(&self, py: Python) -> PyResult<Vec<(String, usize, u64, Option<u64>)>> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.summary()).map_err(|e| to_py_err(&e))
+    }
+
+    /// Combine this map with `other` into new archives, without re-indexing.
+    /// Duplicate names are rejected (`on_conflict="error"`), resolved in favor of this map
+    /// (`"first"`), or avoided by namespacing both maps with `prefixes` (`"prefix"`).
+    #[pyo3(signature = (other, on_conflict="error", prefixes=None, storage_method="memory"))]
+    fn union(
+        &self,
+        py: Python,
+        other: &PyTrackMap,
+        on_conflict: &str,
+        prefixes: Option<(String, String)>,
+        storage_method: &str,
+    ) -> PyResult<Self> {
+        let (roots, other_roots) = (self.get_roots(py)?, other.get_roots(py)?);
+        py.detach(|| {
+            let policy = match (on_conflict, prefixes) {
+                ("prefix", Some((first, second))) => UnionPolicy::Prefix(first, second),
+                ("prefix", None) => bail!("on_conflict=prefix requires prefixes"),
+                (_, Some(_)) => bail!("prefixes can only be used with on_conflict=prefix"),
+                (name, None) => UnionPolicy::Merge(ConflictPolicy::parse(name)?),
+            };
+            roots.union(other_roots, &policy, storage_method)
+        })
+        .map(|roots| PyTrackMap::new(Background::ready(roots)).with_io_pool(self.io_pool.clone()))
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// Index the entries `names` without re-indexing the others, see `Roots::with_added`.
+    fn with_added(&self, py: Python, names: Vec<String>) -> PyResult<Self> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.with_added(names))
+            .map(|roots| {
+                PyTrackMap::new(Background::ready(roots)).with_io_pool(self.io_pool.clone())
+            })
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Drop the entries `names` without re-indexing the others, see `Roots::with_removed`.
+    fn with_removed(&self, py: Python, names: Vec<String>) -> PyResult<Self> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| roots.with_removed(names))
+            .map(|roots| {
+                PyTrackMap::new(Background::ready(roots)).with_io_pool(self.io_pool.clone())
+            })
+            .map_err(|e| to_py_err(&e))
+    }
+
+    #[getter]
+    fn names(&self, py: Python) -> PyResult<Vec<String>> {
+        self.get_roots(py)?.names().map_err(|e| to_py_err(&e))
+    }
+
+    fn __len__(&self, py: Python) -> PyResult<usize> {
+        self.get_roots(py)?.num_names().map_err(|e| to_py_err(&e))
+    }
+
+    /// Directories containing names, see `roots::groups`.
+    fn groups(&self, py: Python) -> PyResult<Vec<String>> {
+        let names = self.get_roots(py)?.names().map_err(|e| to_py_err(&e))?;
+        Ok(roots::groups(&names))
+    }
+
+    /// Names in the directory `group` or below it.
+    fn names_in_group(&self, py: Python, group: &str) -> PyResult<Vec<String>> {
+        let names = self.get_roots(py)?.names().map_err(|e| to_py_err(&e))?;
+        Ok(roots::names_in_group(&names, group))
+    }
+
+    /// Weights of names which sample each group with its weight, see `roots::group_weights`.
+    fn group_weights(
+        &self,
+        py: Python,
+        weights: HashMap<String, f64>,
+    ) -> PyResult<HashMap<String, f64>> {
+        let names = self.get_roots(py)?.names().map_err(|e| to_py_err(&e))?;
+        roots::group_weights(&names, &weights).map_err(|e| to_py_err(&e))
+    }
+
+    fn __contains__(&self, py: Python, name: &str) -> PyResult<bool> {
+        self.get_roots(py)?
+            .contains(name)
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Iterate over the names.
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        let names = self.get_roots(py)?.names().map_err(|e| to_py_err(&e))?;
+        PyList::new(py, names)?.try_iter()
+    }
+
+    /// Storage method in use, e.g., as chosen by `storage_method="auto"`.
+    #[getter]
+    fn storage_method(&self, py: Python) -> PyResult<String> {
+        Ok(self.get_roots(py)?.storage_method())
+    }
+
+    /// Does not block on a background build.
+    fn __repr__(&self) -> String {
+        if !self.roots.is_ready() {
+            return "TrackMap(loading)".to_string();
+        }
+        match self.roots.wait() {
+            Ok(roots) => match roots.describe() {
+                Ok(description) => format!("TrackMap({})", description),
+                Err(e) => format!("TrackMap(failed: {:#})", e),
+            },
+            Err(e) => format!("TrackMap(failed: {:#})", e),
+        }
+    }
+
+    fn contigs(&self, py: Python, fasta_name: &str) -> PyResult<Vec<(&[u8], u64)>> {
+        self.get_roots(py)?
+            .get(fasta_name)
+            .and_then(|(archive, _, name)| archive.contigs(name))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Contig names as an object array of bytes and contig lengths as an uint64 array.
+    fn contigs_array<'py>(&self, py: Python<'py>, fasta_name: &str) -> PyResult<ContigsArray<'py>> {
+        let contigs = self
+            .get_roots(py)?
+            .get(fasta_name)
+            .and_then(|(archive, _, name)| archive.contigs(name))
+            .map_err(|e| to_py_err(&e))?;
+        Ok(contigs_array(py, &contigs))
+    }
+
+    /// Length of `contig` in the entry `fasta_name`, or `None` if there is no such contig.
+    fn contig_length(&self, py: Python, fasta_name: &str, contig: &[u8]) -> PyResult<Option<u64>> {
+        self.get_roots(py)?
+            .get(fasta_name)
+            .and_then(|(archive, _, name)| archive.contig_length(name, contig))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Contig, 0-based start and length of a samtools-style `region` in the entry `fasta_name`.
+    #[pyo3(signature = (fasta_name, region, one_based=true, end_inclusive=true))]
+    fn resolve_region(
+        &self,
+        py: Python,
+        fasta_name: &str,
+        region: &str,
+        one_based: bool,
+        end_inclusive: bool,
+    ) -> PyResult<(String, u64, u64)> {
+        let (archive, _, name) = self
+            .get_roots(py)?
+            .get(fasta_name)
+            .map_err(|e| to_py_err(&e))?;
+        resolve_region(region, one_based, end_inclusive, |contig| {
+            archive.contig_length(name, contig)
+        })
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// Total length of all contigs in the global coordinate system, see `GlobalIndex`.
+    fn global_length(&self, py: Python) -> PyResult<u64> {
+        Ok(self.get_global(py)?.length())
+    }
+
+    /// Stable id of `contig` in the entry `track_name`.
+    fn global_id(&self, py: Python, track_name: &str, contig: &[u8]) -> PyResult<u32> {
+        self.get_global(py)?
+            .id(track_name, contig)
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Name and contig of a global id, or `None` if there is no such id.
+    fn global_contig(&self, py: Python, id: u32) -> PyResult<Option<(&str, &[u8])>> {
+        Ok(self.get_global(py)?.contig(id))
+    }
+
+    /// Global position of the first value of `contig` in the entry `track_name`.
+    fn global_start(&self, py: Python, track_name: &str, contig: &[u8]) -> PyResult<u64> {
+        let global = self.get_global(py)?;
+        let id = global.id(track_name, contig).map_err(|e| to_py_err(&e))?;
+        Ok(global.start(id).unwrap())
+    }
+
+    /// Name, contig and offset within the contig of a global position.
+    fn locate(&self, py: Python, position: u64) -> PyResult<(&str, &[u8], u64)> {
+        self.get_global(py)?
+            .locate(position)
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Read `length` values at a global position. The region must lie within one contig.
+    fn read_at<'py>(
+        &self,
+        py: Python<'py>,
+        position: u64,
+        length: u64,
+    ) -> PyResult<Bound<'py, PyArray1<u8>>> {
+        let (track_name, contig, start) = self
+            .get_global(py)?
+            .locate_region(position, length)
+            .map_err(|e| to_py_err(&e))?;
+        let roots = self.get_roots(py)?;
+        py.detach(|| Self::read_values(roots, track_name, contig, start, length, 1, 1, false, None))
+            .map(|arr| arr.into_pyarray(py))
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// With `step`, only every `step`-th value of `value_size` bytes is returned, e.g., for
+    /// low-resolution views of a track. With `reverse`, the values are returned in reverse
+    /// order, with the interleaved channels of each position permuted by `channels`. With
+    /// `timeout` in seconds, the read raises `TimeoutError` if it does not finish in time.
+    /// With `as_memoryview`, the values are returned as a `memoryview` instead of a numpy array.
+    #[pyo3(signature = (
+        track_name,
+        contig,
+        start,
+        length,
+        step=1,
+        value_size=1,
+        reverse=false,
+        channels=None,
+        timeout=None,
+        as_memoryview=false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn read_sequence<'py>(
+        slf: &Bound<'py, Self>,
+        track_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        step: usize,
+        value_size: usize,
+        reverse: bool,
+        channels: Option<Vec<usize>>,
+        timeout: Option<f64>,
+        as_memoryview: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let py = slf.py();
+        let roots = slf.get().get_roots(py)?;
+        let values = match timeout {
+            None => py
+                .detach(|| {
+                    Self::read_values(
+                        roots, track_name, contig, start, length, step, value_size, reverse,
+                        channels,
+                    )
+                })
+                .map_err(|e| to_py_err(&e))?,
+            Some(timeout) => {
+                let timeout = timeout_duration(timeout)?;
+                let map = slf.clone().unbind();
+                let (track_name, contig) = (track_name.to_string(), contig.to_vec());
+                detach_with_timeout(py, &Cancel::default(), timeout, move |cancel| {
+                    cancel.check()?;
+                    let roots = map
+                        .get()
+                        .roots
+                        .wait()
+                        .expect("roots are loaded before the read");
+                    Self::read_values(
+                        roots,
+                        &track_name,
+                        &contig,
+                        start,
+                        length,
+                        step,
+                        value_size,
+                        reverse,
+                        channels,
+                    )
+                })?
+            }
+        };
+        values_object(py, values, as_memoryview)
+    }
+
+    /// Compare the names, contigs and contig lengths to `fasta_map`, expecting one value of
+    /// `value_size` bytes per base. Returns the missing and extra names, the missing and extra
+    /// contigs of shared names, and the contigs of another length with the expected and actual
+    /// length in bytes.
+    fn validate_against(
+        &self,
+        py: Python,
+        fasta_map: Py<PyFastaMap>,
+        value_size: u64,
+    ) -> PyResult<AlignmentMismatches> {
+        let fasta = fasta_map.get().get_global(py)?;
+        let track = self.get_global(py)?;
+        let report = py.detach(|| AlignmentReport::compare(fasta, track, value_size));
+        Ok((
+            report.missing_names,
+            report.extra_names,
+            report.missing_contigs,
+            report.extra_contigs,
+            report.length_mismatches,
+        ))
+    }
+
+    /// Read `length` values of type `dtype` starting at `start` and reduce them in bins of
+    /// `bin_size` values with `op`, one of "mean", "max", "min" and "sum".
+    #[allow(clippy::too_many_arguments)]
+    fn read_aggregated<'py>(
+        &self,
+        py: Python<'py>,
+        track_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        bin_size: usize,
+        op: &str,
+        dtype: &str,
+    ) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| {
+            let op = Aggregation::parse(op)?;
+            let value_type = ValueType::parse(dtype)?;
+            let size = value_type.size();
+            let bytes = roots.get(track_name).and_then(|(archive, root, name)| {
+                archive.read_sequence(root, name, contig, start * size, length * size)
+            })?;
+            let values = value_type.decode(bytes.as_slice().unwrap());
+            aggregate(&values, bin_size, op).map(Array1::from_vec)
+        })
+        .map(|arr| arr.into_pyarray(py))
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// Read the same region of several tracks in parallel, one track per row. `start` and
+    /// `length` count values of `value_size` bytes.
+    fn read_stacked<'py>(
+        &self,
+        py: Python<'py>,
+        track_names: Vec<String>,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        value_size: u64,
+    ) -> PyResult<Bound<'py, PyArray2<u8>>> {
+        let roots = self.get_roots(py)?;
+        let (start, length) = (start * value_size, length * value_size);
+        py.detach(|| {
+            let rows = self.install(|| {
+                track_names
+                    .par_iter()
+                    .map(|track_name| {
+                        roots.get(track_name).and_then(|(archive, root, name)| {
+                            archive.read_sequence(root, name, contig, start, length)
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })?;
+            let mut stacked = Array2::zeros((rows.len(), length as usize));
+            for (mut row, values) in stacked.outer_iter_mut().zip(rows) {
+                row.assign(&values);
+            }
+            anyhow::Ok(stacked)
+        })
+        .map(|arr| arr.into_pyarray(py))
+        .map_err(|e| to_py_err(&e))
+    }
+
+    /// Encode `track_name` as runs of equal values of `value_size` bytes and write it to `path`,
+    /// to be opened with `RleTrack`.
+    fn write_rle(&self, py: Python, track_name: &str, path: &str, value_size: u64) -> PyResult<()> {
+        let roots = self.get_roots(py)?;
+        py.detach(|| {
+            let track = roots.get(track_name).and_then(|(archive, root, name)| {
+                RleTrack::from_track(archive, root, name, value_size)
+            })?;
+            write_direct(&track, Path::new(path), ChecksumAlgorithm::Xxh3)
+        })
+        .map_err(|e| to_py_err(&e))
+    }
+}
+
+/// Fields of an `AlignmentReport`.
+type AlignmentMismatches = (
+    Vec<String>,
+    Vec<String>,
+    Vec<(String, Vec<u8>)>,
+    Vec<(String, Vec<u8>)>,
+    Vec<(String, Vec<u8>, u64, u64)>,
+);
+
+/// A map to read windows from.
+#[derive(FromPyObject)]
+enum WindowSource {
+    Fasta(Py<PyFastaMap>),
+    Track(Py<PyTrackMap>),
+}
+
+impl WindowSource {
+    fn get_global(&self, py: Python) -> PyResult<&GlobalIndex> {
+        match self {
+            WindowSource::Fasta(map) => map.get().get_global(py),
+            WindowSource::Track(map) => map.get().get_global(py),
+        }
+    }
+
+    /// Read the windows given by global contig ids and starts, one window per row. `cancel`
+    /// is checked before each window.
+    fn read_windows(
+        &self,
+        py: Python,
+        ids: &[u32],
+        starts: &[u64],
+        length: u64,
+        cancel: &Cancel,
+    ) -> PyResult<Array2<u8>> {
+        let global = self.get_global(py)?;
+        match self {
+            WindowSource::Fasta(map) => {
+                let roots = map.get().get_roots(py)?;
+                detach_interruptible(py, cancel, || {
+                    map.get().install(|| {
+                        sampler::read_windows(
+                            global,
+                            ids,
+                            starts,
+                            length,
+                            |name, contig, start, length| {
+                                cancel.check()?;
+                                map.get().read(roots, name, contig, start, length)
+                            },
+                        )
+                    })
+                })
+            }
+            WindowSource::Track(map) => {
+                let roots = map.get().get_roots(py)?;
+                detach_interruptible(py, cancel, || {
+                    map.get().install(|| {
+                        sampler::read_windows(
+                            global,
+                            ids,
+                            starts,
+                            length,
+                            |name, contig, start, length| {
+                                cancel.check()?;
+                                roots.get(name).and_then(|(archive, root, name)| {
+                                    archive.read_sequence(root, name, contig, start, length)
+                                })
+                            },
+                        )
+                    })
+                })
+            }
+        }
+    }
+
+    /// The read policy of FASTA maps, as tracks are returned as stored.
+    fn read_policy(&self) -> Option<&ReadPolicy> {
+        match self {
+            WindowSource::Fasta(map) => Some(&map.get().reads),
+            WindowSource::Track(_) => None,
+        }
+    }
+
+    /// Contigs of `name` and their lengths.
+    fn contigs(&self, py: Python, name: &str) -> PyResult<Vec<(Vec<u8>, u64)>> {
+        let contigs = match self {
+            WindowSource::Fasta(map) => map.get().contigs(py, name)?,
+            WindowSource::Track(map) => map.get().contigs(py, name)?,
+        };
+        Ok(contigs
+            .into_iter()
+            .map(|(contig, length)| (contig.to_vec(), length))
+            .collect())
+    }
+
+    fn sliding_windows(
+        &self,
+        py: Python,
+        name: &str,
+        contig: &[u8],
+        contig_length: u64,
+        window_length: u64,
+        stride: u64,
+    ) -> PyResult<SlidingWindows> {
+        let reader = match self {
+            WindowSource::Fasta(map) => {
+                map.get()
+                    .get_roots(py)?
+                    .get(name)
+                    .and_then(|(archive, root, name)| {
+                        archive.sequence_reader(root, name, contig, 0, contig_length)
+                    })
+            }
+            WindowSource::Track(map) => {
+                map.get()
+                    .get_roots(py)?
+                    .get(name)
+                    .and_then(|(archive, root, name)| {
+                        archive.sequence_reader(root, name, contig, 0, contig_length)
+                    })
+            }
+        };
+        reader
+            .and_then(|reader| SlidingWindows::new(reader, contig_length, window_length, stride))
+            .map_err(|e| to_py_err(&e))
+    }
+}
+
+/// Options of a `Mutator`, read from the attributes of a Python `Mutations`.
+#[derive(FromPyObject)]
+struct PyMutations {
+    substitution_rate: f64,
+    indel_rate: f64,
+    max_indel_length: usize,
+}
+
+impl PyMutations {
+    fn mutator(&self, seed: Option<u64>) -> PyResult<Mutator> {
+        Mutator::new(
+            self.substitution_rate,
+            self.indel_rate,
+            self.max_indel_length,
+            seed,
+        )
+        .map_err(|e| to_py_err(&e))
+    }
+}
+
+/// Raise a `ValueError` if `mutations` are invalid, e.g., when creating a `Mutations`.
+#[pyfunction]
+fn check_mutations(mutations: PyMutations) -> PyResult<()> {
+    mutations.mutator(Some(0)).map(|_| ())
+}
+
+/// Rounds of sampling replacements for rejected windows before giving up.
+const MAX_REJECTION_ROUNDS: usize = 1000;
+
+#[pyclass(frozen, name = "WindowSampler")]
+struct PyWindowSampler {
+    map: WindowSource,
+    sampler: Mutex<WindowSampler>,
+    /// Windows with a larger fraction of `N` are rejected.
+    max_n_fraction: Option<f64>,
+    /// Windows overlapping a blacklisted region are rejected.
+    blacklist: Option<Blacklist>,
+    /// Mutates windows after reading them.
+    mutator: Option<Mutex<Mutator>>,
+}
+
+/// Global contig ids, starts and, if they were read, the values of sampled windows.
+type SampledWindows = (Array1<u32>, Array1<u64>, Option<Array2<u8>>);
+
+impl PyWindowSampler {
+    /// Sample `batch_size` windows, replacing those overlapping the blacklist or with too many
+    /// `N` if `max_n_fraction` is set. The windows are returned if they were read for the check.
+    fn sample_batch(&self, py: Python, batch_size: usize) -> PyResult<SampledWindows> {
+        let global = self.map.get_global(py)?;
+        let sample = |n| py.detach(|| self.sampler.lock().unwrap().sample_batch(global, n));
+        if self.max_n_fraction.is_none() && self.blacklist.is_none() {
+            let (ids, starts) = sample(batch_size);
+            return Ok((ids, starts, None));
+        }
+        let length = self.window_length();
+        let (mut ids, mut starts, mut windows) = (Vec::new(), Vec::new(), Vec::new());
+        for _ in 0..MAX_REJECTION_ROUNDS {
+            if ids.len() == batch_size {
+                break;
+            }
+            let (candidate_ids, candidate_starts): (Vec<_>, Vec<_>) = {
+                let (candidate_ids, candidate_starts) = sample(batch_size - ids.len());
+                candidate_ids
+                    .into_iter()
+                    .zip(candidate_starts)
+                    .filter(|&(id, start)| !self.is_blacklisted(id, start, length))
+                    .unzip()
+            };
+            let Some(max_n_fraction) = self.max_n_fraction else {
+                ids.extend(candidate_ids);
+                starts.extend(candidate_starts);
+                continue;
+            };
+            let candidates = self.map.read_windows(
+                py,
+                &candidate_ids,
+                &candidate_starts,
+                length,
+                &Cancel::default(),
+            )?;
+            for ((&id, &start), window) in candidate_ids
+                .iter()
+                .zip(&candidate_starts)
+                .zip(candidates.outer_iter())
+            {
+                if sampler::n_fraction(window) <= max_n_fraction {
+                    ids.push(id);
+                    starts.push(start);
+                    windows.extend(window.iter());
+                }
+            }
+        }
+        if ids.len() < batch_size {
+            return Err(PyRuntimeError::new_err(format!(
+                "Could not sample {} windows passing the filters after {} rounds",
+                batch_size, MAX_REJECTION_ROUNDS
+            )));
+        }
+        let windows = self
+            .max_n_fraction
+            .map(|_| Array2::from_shape_vec((batch_size, length as usize), windows).unwrap());
+        Ok((ids.into(), starts.into(), windows))
+    }
+
+    /// Sample `batch_size` windows and read them, see `sample`.
+    fn sample_windows(
+        &self,
+        py: Python,
+        batch_size: usize,
+        cancel: &Cancel,
+    ) -> PyResult<(Array1<u32>, Array1<u64>, Array2<u8>)> {
+        let (ids, starts, windows) = self.sample_batch(py, batch_size)?;
+        let windows = match windows {
+            Some(windows) => windows,
+            None => self.map.read_windows(
+                py,
+                ids.as_slice().unwrap(),
+                starts.as_slice().unwrap(),
+                self.window_length(),
+                cancel,
+            )?,
+        };
+        Ok((ids, starts, self.mutate(py, windows)))
+    }
+
+    /// Mutate windows before they are returned, if the sampler has mutations.
+    fn mutate(&self, py: Python, mut windows: Array2<u8>) -> Array2<u8> {
+        if let Some(mutator) = &self.mutator {
+            py.detach(|| mutator.lock().unwrap().mutate(&mut windows));
+        }
+        windows
+    }
+
+    fn is_blacklisted(&self, id: u32, start: u64, length: u64) -> bool {
+        self.blacklist
+            .as_ref()
+            .is_some_and(|blacklist| blacklist.overlaps(id, start, start.saturating_add(length)))
+    }
+}
+
+#[pymethods]
+impl PyWindowSampler {
+    /// Sample windows of `window_length` from `map`, weighted by the number of windows of each
+    /// contig, or first by the per-name `weights` and then uniformly within the name.
+    #[new]
+    /// Windows whose fraction of `N` exceeds `max_n_fraction` or which overlap a region of the
+    /// `blacklist`, given as BED contents per name, are replaced by new samples. With
+    /// `mutations`, windows of FASTA maps are mutated after reading them.
+    #[pyo3(signature = (
+        map,
+        window_length,
+        weights=None,
+        seed=None,
+        max_n_fraction=None,
+        blacklist=None,
+        mutations=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python,
+        map: WindowSource,
+        window_length: u64,
+        weights: Option<HashMap<String, f64>>,
+        seed: Option<u64>,
+        max_n_fraction: Option<f64>,
+        blacklist: Option<HashMap<String, Py<PyBytes>>>,
+        mutations: Option<PyMutations>,
+    ) -> PyResult<Self> {
+        if mutations.is_some() && matches!(map, WindowSource::Track(_)) {
+            return Err(PyValueError::new_err(
+                "Mutations are only supported for FASTA maps",
+            ));
+        }
+        let mutator = mutations
+            .map(|mutations| mutations.mutator(seed).map(Mutex::new))
+            .transpose()?;
+        let global = map.get_global(py)?;
+        let sampler = WindowSampler::new(global, window_length, weights.as_ref(), seed)
+            .map_err(|e| to_py_err(&e))?;
+        let blacklist = blacklist
+            .map(|beds| {
+                let mut blacklist = Blacklist::new(global);
+                for (name, bed) in &beds {
+                    blacklist
+                        .add_bed(global, name, bed.as_bytes(py))
+                        .with_context(|| format!("Error reading the blacklist of {}", name))?;
+                }
+                anyhow::Ok(blacklist)
+            })
+            .transpose()
+            .map_err(|e| to_py_err(&e))?;
+        Ok(PyWindowSampler {
+            map,
+            sampler: Mutex::new(sampler),
+            max_n_fraction,
+            blacklist,
+            mutator,
+        })
+    }
+
+    #[getter]
+    fn window_length(&self) -> u64 {
+        self.sampler.lock().unwrap().window_length()
+    }
+
+    /// Number of windows sampled so far, including those before a restored state.
+    #[getter]
+    fn num_sampled(&self) -> u64 {
+        self.sampler.lock().unwrap().num_sampled()
+    }
+
+    /// The RNG state and number of sampled windows, to resume sampling after a restart.
+    fn state_dict<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.sampler.lock().unwrap().state())
+    }
+
+    /// Restore a state of a sampler with the same window length and weights.
+    fn load_state_dict(&self, state: &[u8]) -> PyResult<()> {
+        self.sampler
+            .lock()
+            .unwrap()
+            .load_state(state)
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Sample `batch_size` windows as global contig ids and starts within the contigs.
+    fn sample_indices<'py>(
+        &self,
+        py: Python<'py>,
+        batch_size: usize,
+    ) -> PyResult<(Bound<'py, PyArray1<u32>>, Bound<'py, PyArray1<u64>>)> {
+        let (ids, starts, _) = self.sample_batch(py, batch_size)?;
+        Ok((ids.into_pyarray(py), starts.into_pyarray(py)))
+    }
+
+    /// Sample `batch_size` windows as global contig ids, starts and values, one window per row.
+    /// The reads stop with an error once `cancel` is cancelled.
+    #[allow(clippy::type_complexity)]
+    #[pyo3(signature = (batch_size, cancel=None))]
+    fn sample<'py>(
+        &self,
+        py: Python<'py>,
+        batch_size: usize,
+        cancel: Option<PyRef<PyCancellationToken>>,
+    ) -> PyResult<(
+        Bound<'py, PyArray1<u32>>,
+        Bound<'py, PyArray1<u64>>,
+        Bound<'py, PyArray2<u8>>,
+    )> {
+        let cancel = PyCancellationToken::cancel_of(cancel);
+        let (ids, starts, windows) = self.sample_windows(py, batch_size, &cancel)?;
+        Ok((
+            ids.into_pyarray(py),
+            starts.into_pyarray(py),
+            windows.into_pyarray(py),
+        ))
+    }
+
+    /// Whether the fraction of `N` in each window is at most `max_n_fraction`.
+    fn filter_windows<'py>(
+        &self,
+        py: Python<'py>,
+        ids: PyReadonlyArray1<u32>,
+        starts: PyReadonlyArray1<u64>,
+        max_n_fraction: f64,
+    ) -> PyResult<Bound<'py, PyArray1<bool>>> {
+        let windows = self.map.read_windows(
+            py,
+            ids.as_slice()?,
+            starts.as_slice()?,
+            self.window_length(),
+            &Cancel::default(),
+        )?;
+        let keep = windows
+            .outer_iter()
+            .map(|window| sampler::n_fraction(window) <= max_n_fraction)
+            .collect::<Vec<_>>();
+        Ok(keep.into_pyarray(py))
+    }
+
+    /// Whether each window given by global contig ids and starts overlaps the blacklist.
+    fn blacklisted<'py>(
+        &self,
+        py: Python<'py>,
+        ids: PyReadonlyArray1<u32>,
+        starts: PyReadonlyArray1<u64>,
+        length: u64,
+    ) -> PyResult<Bound<'py, PyArray1<bool>>> {
+        let (ids, starts) = (ids.as_slice()?, starts.as_slice()?);
+        if ids.len() != starts.len() {
+            return Err(PyRuntimeError::new_err(format!(
+                "Got {} ids but {} starts",
+                ids.len(),
+                starts.len()
+            )));
+        }
+        let blacklisted = ids
+            .iter()
+            .zip(starts)
+            .map(|(&id, &start)| self.is_blacklisted(id, start, length))
+            .collect::<Vec<_>>();
+        Ok(blacklisted.into_pyarray(py))
+    }
+
+    /// Blacklisted intervals of a contig as an array with one (start, end) row per interval.
+    fn blacklist_intervals<'py>(&self, py: Python<'py>, id: u32) -> Bound<'py, PyArray2<u64>> {
+        let intervals = self
+            .blacklist
+            .as_ref()
+            .map_or(&[][..], |blacklist| blacklist.intervals(id))
+            .iter()
+            .flat_map(|&(start, end)| [start, end])
+            .collect::<Vec<_>>();
+        Array2::from_shape_vec((intervals.len() / 2, 2), intervals)
+            .unwrap()
+            .into_pyarray(py)
+    }
+
+    /// Read the windows given by global contig ids and starts, one window per row, mutated if
+    /// the sampler has mutations. The reads stop with an error once `cancel` is cancelled.
+    #[pyo3(signature = (ids, starts, cancel=None))]
+    fn read_windows<'py>(
+        &self,
+        py: Python<'py>,
+        ids: PyReadonlyArray1<u32>,
+        starts: PyReadonlyArray1<u64>,
+        cancel: Option<PyRef<PyCancellationToken>>,
+    ) -> PyResult<Bound<'py, PyArray2<u8>>> {
+        let windows = self.map.read_windows(
+            py,
+            ids.as_slice()?,
+            starts.as_slice()?,
+            self.window_length(),
+            &PyCancellationToken::cancel_of(cancel),
+        )?;
+        Ok(self.mutate(py, windows).into_pyarray(py))
+    }
+}
+
+#[pyclass(frozen, name = "PositionSampler")]
+struct PyPositionSampler {
+    map: Py<PyTrackMap>,
+    sampler: Mutex<PositionSampler>,
+}
+
+#[pymethods]
+impl PyPositionSampler {
+    /// Sample positions of `map` with probability proportional to the track values of type
+    /// `dtype`. The sums of each block of `block_size` values are computed once, by reading the
+    /// whole track.
+    #[new]
+    #[pyo3(signature = (map, dtype, block_size=4096, seed=None))]
+    fn new(
+        py: Python,
+        map: Py<PyTrackMap>,
+        dtype: &str,
+        block_size: u64,
+        seed: Option<u64>,
+    ) -> PyResult<Self> {
+        let value_type = ValueType::parse(dtype).map_err(|e| to_py_err(&e))?;
+        let global = map.get().get_global(py)?;
+        let roots = map.get().get_roots(py)?;
+        let sampler = py
+            .detach(|| {
+                PositionSampler::new(
+                    global,
+                    block_size,
+                    value_type,
+                    |id| {
+                        let (name, contig) = global.contig(id).unwrap();
+                        let length = global.contig_length(id);
+                        roots
+                            .get(name)
+                            .and_then(|(archive, root, name)| {
+                                archive.sequence_reader(root, name, contig, 0, length)
+                            })
+                            .and_then(|reader| block_sums(reader, length, block_size, value_type))
+                    },
+                    seed,
+                )
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok(PyPositionSampler {
+            map,
+            sampler: Mutex::new(sampler),
+        })
+    }
+
+    /// Sum of all values of the track.
+    #[getter]
+    fn total(&self) -> f64 {
+        self.sampler.lock().unwrap().total()
+    }
+
+    /// Sample `batch_size` positions as global contig ids and positions within the contigs.
+    fn sample_indices<'py>(
+        &self,
+        py: Python<'py>,
+        batch_size: usize,
+    ) -> PyResult<(Bound<'py, PyArray1<u32>>, Bound<'py, PyArray1<u64>>)> {
+        let global = self.map.get().get_global(py)?;
+        let roots = self.map.get().get_roots(py)?;
+        let (ids, positions) = py
+            .detach(|| {
+                self.sampler.lock().unwrap().sample_batch(
+                    global,
+                    batch_size,
+                    |id, start, length| {
+                        let (name, contig) = global.contig(id).unwrap();
+                        roots.get(name).and_then(|(archive, root, name)| {
+                            archive.read_sequence(root, name, contig, start, length)
+                        })
+                    },
+                )
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok((ids.into_pyarray(py), positions.into_pyarray(py)))
+    }
+}
+
+#[pyclass(frozen, name = "GcMatchedSampler")]
+struct PyGcMatchedSampler {
+    map: Py<PyFastaMap>,
+    sampler: Mutex<GcMatchedSampler>,
+}
+
+impl PyGcMatchedSampler {
+    /// GC content of the windows given by global contig ids and starts, read in parallel.
+    fn window_gc(&self, py: Python, ids: &[u32], starts: &[u64]) -> Result<Vec<f64>> {
+        let global = self.map.get().get_global(py)?;
+        let roots = self.map.get().get_roots(py)?;
+        let length = self.sampler.lock().unwrap().window_length();
+        py.detach(|| {
+            let windows = sampler::read_windows(
+                global,
+                ids,
+                starts,
+                length,
+                |name, contig, start, length| {
+                    roots.get(name).and_then(|(archive, root, name)| {
+                        archive.read_sequence(root, name, contig, start, length)
+                    })
+                },
+            )?;
+            Ok(windows.outer_iter().map(sampler::gc_content).collect())
+        })
+    }
+}
+
+#[pymethods]
+impl PyGcMatchedSampler {
+    /// Sample negative windows of `window_length` from `map` which are matched to positive
+    /// windows on GC content, see `GcMatchedSampler`. The candidates are binned into `num_bins`
+    /// bins by reading the whole collection once.
+    #[new]
+    #[pyo3(signature = (
+        map, window_length, stride=None, num_bins=20, max_n_fraction=0.1, seed=None
+    ))]
+    fn new(
+        py: Python,
+        map: Py<PyFastaMap>,
+        window_length: u64,
+        stride: Option<u64>,
+        num_bins: usize,
+        max_n_fraction: f64,
+        seed: Option<u64>,
+    ) -> PyResult<Self> {
+        let global = map.get().get_global(py)?;
+        let roots = map.get().get_roots(py)?;
+        let stride = stride.unwrap_or(window_length);
+        let sampler = py
+            .detach(|| {
+                GcMatchedSampler::new(
+                    global,
+                    window_length,
+                    stride,
+                    num_bins,
+                    max_n_fraction,
+                    |id| {
+                        let (name, contig) = global.contig(id).unwrap();
+                        let length = global.contig_length(id);
+                        roots
+                            .get(name)
+                            .and_then(|(archive, root, name)| {
+                                archive.sequence_reader(root, name, contig, 0, length)
+                            })
+                            .and_then(|reader| composition(reader, length, stride))
+                    },
+                    seed,
+                )
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok(PyGcMatchedSampler {
+            map,
+            sampler: Mutex::new(sampler),
+        })
+    }
+
+    #[getter]
+    fn window_length(&self) -> u64 {
+        self.sampler.lock().unwrap().window_length()
+    }
+
+    /// Number of candidate windows in each GC bin.
+    #[getter]
+    fn bin_sizes(&self) -> Vec<usize> {
+        self.sampler.lock().unwrap().bin_sizes()
+    }
+
+    /// GC content of the windows given by global contig ids and starts, NaN without `ACGT`.
+    fn gc_content<'py>(
+        &self,
+        py: Python<'py>,
+        ids: PyReadonlyArray1<u32>,
+        starts: PyReadonlyArray1<u64>,
+    ) -> PyResult<Bound<'py, PyArray1<f64>>> {
+        let gc = self
+            .window_gc(py, ids.as_slice()?, starts.as_slice()?)
+            .map_err(|e| to_py_err(&e))?;
+        Ok(gc.into_pyarray(py))
+    }
+
+    /// Sample `num_per_positive` negatives for each positive window given by global contig ids
+    /// and starts, as global contig ids and starts grouped by positive.
+    #[pyo3(signature = (ids, starts, num_per_positive=1))]
+    fn sample_indices<'py>(
+        &self,
+        py: Python<'py>,
+        ids: PyReadonlyArray1<u32>,
+        starts: PyReadonlyArray1<u64>,
+        num_per_positive: usize,
+    ) -> PyResult<(Bound<'py, PyArray1<u32>>, Bound<'py, PyArray1<u64>>)> {
+        let (ids, starts) = (ids.as_slice()?, starts.as_slice()?);
+        let gc = self.window_gc(py, ids, starts).map_err(|e| to_py_err(&e))?;
+        let positives = ids
+            .iter()
+            .zip(starts)
+            .zip(gc)
+            .map(|((&id, &start), gc)| (id, start, gc))
+            .collect::<Vec<_>>();
+        let (ids, starts) = py
+            .detach(|| {
+                self.sampler
+                    .lock()
+                    .unwrap()
+                    .sample_matched(&positives, num_per_positive)
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok((ids.into_pyarray(py), starts.into_pyarray(py)))
+    }
+}
+
+/// Sequence and the values of each track of a region.
+type GenomeRegion<'py> = (Bound<'py, PyArray1<u8>>, Vec<Bound<'py, PyArray1<u8>>>);
+
+#[pyclass(frozen, name = "GenomeDataset")]
+struct PyGenomeDataset {
+    fasta: Py<PyFastaMap>,
+    /// Track maps and the size of their values in bytes.
+    tracks: Vec<(Py<PyTrackMap>, u64)>,
+}
+
+#[pymethods]
+impl PyGenomeDataset {
+    /// Bind `fasta` to `tracks` with one value of the given size per base, checking that every
+    /// track covers all contigs of `fasta` with matching lengths.
+    #[new]
+    fn new(
+        py: Python,
+        fasta: Py<PyFastaMap>,
+        tracks: Vec<(Py<PyTrackMap>, u64)>,
+    ) -> PyResult<Self> {
+        let fasta_global = fasta.get().get_global(py)?;
+        for (i, (track, value_size)) in tracks.iter().enumerate() {
+            let track_global = track.get().get_global(py)?;
+            check_aligned(fasta_global, track_global, *value_size)
+                .with_context(|| format!("Track {} is not aligned to the FASTA map", i))
+                .map_err(|e| to_py_err(&e))?;
+        }
+        Ok(PyGenomeDataset { fasta, tracks })
+    }
+
+    /// Read `length` bases starting at `start` and the raw bytes of the values of each track
+    /// for the same bases.
+    fn read<'py>(
+        &self,
+        py: Python<'py>,
+        name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> PyResult<GenomeRegion<'py>> {
+        let fasta_roots = self.fasta.get().get_roots(py)?;
+        let track_roots = self
+            .tracks
+            .iter()
+            .map(|(track, value_size)| Ok((track.get().get_roots(py)?, *value_size)))
+            .collect::<PyResult<Vec<_>>>()?;
+        let (sequence, tracks) = py
+            .detach(|| {
+                let sequence = self
+                    .fasta
+                    .get()
+                    .read(fasta_roots, name, contig, start, length)?;
+                let tracks = self.fasta.get().install(|| {
+                    track_roots
+                        .par_iter()
+                        .map(|(roots, value_size)| {
+                            roots.get(name).and_then(|(archive, root, name)| {
+                                archive.read_sequence(
+                                    root,
+                                    name,
+                                    contig,
+                                    start * value_size,
+                                    length * value_size,
+                                )
+                            })
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })?;
+                anyhow::Ok((sequence, tracks))
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok((
+            sequence.into_pyarray(py),
+            tracks
+                .into_iter()
+                .map(|track| track.into_pyarray(py))
+                .collect(),
+        ))
+    }
+}
+
+#[pyclass(frozen, name = "SparseTrack")]
+struct PySparseTrack {
+    track: ArchiveStorage<SparseTrack, MemoryStorage>,
+}
+
+#[pymethods]
+impl PySparseTrack {
+    /// Load a bedGraph file, optionally caching the archived intervals at `cache_path`.
+    #[new]
+    #[pyo3(signature = (path, cache_path=None))]
+    fn new(py: Python, path: &str, cache_path: Option<&str>) -> PyResult<Self> {
+        let track = py
+            .detach(|| SparseTrack::open(Path::new(path), cache_path.map(Path::new)))
+            .map_err(|e| to_py_err(&e))?;
+        Ok(PySparseTrack { track })
+    }
+
+    /// Contigs with at least one interval and their number of intervals, in file order.
+    fn contigs<'py>(&self, py: Python<'py>) -> Vec<(Bound<'py, PyBytes>, usize)> {
+        self.track
+            .as_ref()
+            .contigs()
+            .into_iter()
+            .map(|(name, count)| (PyBytes::new(py, name), count))
+            .collect()
+    }
+
+    /// Read `length` values starting at `start`, with `fill` outside of the intervals.
+    #[pyo3(signature = (contig, start, length, fill=0.0))]
+    fn read_sequence<'py>(
+        &self,
+        py: Python<'py>,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        fill: f32,
+    ) -> Bound<'py, PyArray1<f32>> {
+        py.detach(|| self.track.as_ref().read(contig, start, length, fill))
+            .into_pyarray(py)
+    }
+}
+
+#[pyclass(frozen, name = "RleTrack")]
+struct PyRleTrack {
+    track: ArchiveStorage<RleTrack, MmapStorage>,
+}
+
+#[pymethods]
+impl PyRleTrack {
+    /// Open a track written by `TrackMap.write_rle`.
+    #[new]
+    fn new(py: Python, path: &str) -> PyResult<Self> {
+        let track = py
+            .detach(|| {
+                ArchiveStorage::load(Path::new(path), true)?
+                    .map_err(|rejection| anyhow::anyhow!(rejection))
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok(PyRleTrack { track })
+    }
+
+    #[getter]
+    fn value_size(&self) -> u64 {
+        self.track.as_ref().value_size()
+    }
+
+    #[getter]
+    fn num_runs(&self) -> u64 {
+        self.track.as_ref().num_runs()
+    }
+
+    /// Contigs and their length in bytes.
+    fn contigs<'py>(&self, py: Python<'py>) -> Vec<(Bound<'py, PyBytes>, u64)> {
+        self.track
+            .as_ref()
+            .contigs()
+            .into_iter()
+            .map(|(name, length)| (PyBytes::new(py, name), length))
+            .collect()
+    }
+
+    /// Read `length` bytes starting at byte `start`, decoding the runs.
+    fn read_sequence<'py>(
+        &self,
+        py: Python<'py>,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> PyResult<Bound<'py, PyArray1<u8>>> {
+        py.detach(|| self.track.as_ref().read_sequence(contig, start, length))
+            .map(|arr| arr.into_pyarray(py))
+            .map_err(|e| to_py_err(&e))
+    }
+}
+
+#[pyclass(frozen, name = "MappedTrack")]
+struct PyMappedTrack {
+    track: MappedTrack,
+}
+
+#[pymethods]
+impl PyMappedTrack {
+    /// Map an uncompressed track with its `.idx` index.
+    #[new]
+    fn new(py: Python, path: &str) -> PyResult<Self> {
+        let track = py
+            .detach(|| MappedTrack::open(Path::new(path)))
+            .map_err(|e| to_py_err(&e))?;
+        Ok(PyMappedTrack { track })
+    }
+
+    /// Contigs and their length in bytes.
+    fn contigs<'py>(&self, py: Python<'py>) -> Vec<(Bound<'py, PyBytes>, u64)> {
+        self.track
+            .contigs()
+            .into_iter()
+            .map(|(name, length)| (PyBytes::new(py, name), length))
+            .collect()
+    }
+
+    /// `length` bytes starting at byte `start` as a read-only view into the map, which keeps
+    /// the track alive as its base.
+    fn read_sequence<'py>(
+        slf: &Bound<'py, Self>,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> PyResult<Bound<'py, PyArray1<u8>>> {
+        let bytes = slf
+            .get()
+            .track
+            .slice(contig, start, length)
+            .map_err(|e| to_py_err(&e))?;
+        // The map is only dropped with the track, which the array references as its base, and
+        // the array is made read-only before it is returned
+        let array = unsafe {
+            PyArray1::borrow_from_array(&ArrayView1::from(bytes), slf.clone().into_any())
+        };
+        array.call_method1("setflags", (false,))?;
+        Ok(array)
+    }
+}
+
+#[pyclass(frozen, name = "PackedGenomes")]
+struct PyPackedGenomes {
+    genomes: ArchiveStorage<PackedGenomes, MmapStorage>,
+}
+
+#[pymethods]
+impl PyPackedGenomes {
+    /// Open genomes written by `FastaMap.write_packed`.
+    #[new]
+    fn new(py: Python, path: &str) -> PyResult<Self> {
+        let genomes = py
+            .detach(|| {
+                ArchiveStorage::load(Path::new(path), true)?
+                    .map_err(|rejection| anyhow::anyhow!(rejection))
+            })
+            .map_err(|e| to_py_err(&e))?;
+        Ok(PyPackedGenomes { genomes })
+    }
+
+    fn names(&self) -> Vec<&str> {
+        self.genomes.as_ref().names()
+    }
+
+    /// Contigs of `fasta_name` and their length.
+    fn contigs(&self, fasta_name: &str) -> PyResult<Vec<(&[u8], u64)>> {
+        self.genomes
+            .as_ref()
+            .contigs(fasta_name)
+            .map_err(|e| to_py_err(&e))
+    }
+
+    /// Read `length` bases starting at `start`, decoding them from two bits per base.
+    fn read_sequence<'py>(
+        &self,
+        py: Python<'py>,
+        fasta_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> PyResult<Bound<'py, PyArray1<u8>>> {
+        py.detach(|| {
+            self.genomes
+                .as_ref()
+                .read_sequence(fasta_name, contig, start, length)
+        })
+        .map(|arr| arr.into_pyarray(py))
+        .map_err(|e| to_py_err(&e))
+    }
+}
+
+#[pyclass(frozen, name = "WindowPartition")]
+struct PyWindowPartition {
+    map: WindowSource,
+    partition: WindowPartition,
+}
+
+#[pymethods]
+impl PyWindowPartition {
+    /// The windows of worker `worker_id` out of `num_workers` in `epoch`, see `WindowPartition`.
+    /// With DDP, number the workers of all ranks consecutively.
+    #[new]
+    #[pyo3(signature = (map, window_length, num_workers, worker_id, epoch=0, seed=0, stride=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python,
+        map: WindowSource,
+        window_length: u64,
+        num_workers: u64,
+        worker_id: u64,
+        epoch: u64,
+        seed: u64,
+        stride: Option<u64>,
+    ) -> PyResult<Self> {
+        let global = map.get_global(py)?;
+        let partition = WindowPartition::new(
+            global,
+            window_length,
+            stride.unwrap_or(window_length),
+            num_workers,
+            worker_id,
+            epoch,
+            seed,
+        )
+        .map_err(|e| to_py_err(&e))?;
+        Ok(PyWindowPartition { map, partition })
+    }
+
+    fn __len__(&self) -> usize {
+        self.partition.len() as usize
+    }
+
+    #[getter]
+    fn window_length(&self) -> u64 {
+        self.partition.window_length()
+    }
+
+    /// Global contig ids and starts of `count` windows of this worker starting at `offset`.
+    fn indices<'py>(
+        &self,
+        py: Python<'py>,
+        offset: u64,
+        count: u64,
+    ) -> PyResult<(Bound<'py, PyArray1<u32>>, Bound<'py, PyArray1<u64>>)> {
+        let (ids, starts) = py
+            .detach(|| self.partition.get_batch(offset, count))
+            .map_err(|e| to_py_err(&e))?;
+        Ok((ids.into_pyarray(py), starts.into_pyarray(py)))
+    }
+
+    /// Read `count` windows of this worker starting at `offset`, one window per row.
+    fn read<'py>(
+        &self,
+        py: Python<'py>,
+        offset: u64,
+        count: u64,
+    ) -> PyResult<Bound<'py, PyArray2<u8>>> {
+        let (ids, starts) = py
+            .detach(|| self.partition.get_batch(offset, count))
+            .map_err(|e| to_py_err(&e))?;
+        let windows = self.map.read_windows(
+            py,
+            ids.as_slice().unwrap(),
+            starts.as_slice().unwrap(),
+            self.partition.window_length(),
+            &Cancel::default(),
+        )?;
+        Ok(windows.into_pyarray(py))
+    }
+}
+
+#[pyclass(frozen, name = "WindowIterator")]
+struct PyWindowIterator {
+    map: WindowSource,
+    window_length: u64,
+    stride: u64,
+    /// Name, contig and contig length of all contigs to walk over.
+    targets: Vec<(String, Vec<u8>, u64)>,
+    /// Index of the next target and the windows of the current one.
+    state: Mutex<(usize, Option<SlidingWindows>)>,
+}
+
+#[pymethods]
+impl PyWindowIterator {
+    /// Walk over `contig` of `name`, all contigs of `name`, or all contigs of `map` in the
+    /// order of the global ids. Each contig is read in a single pass.
+    #[new]
+    #[pyo3(signature = (map, window_length, stride=None, name=None, contig=None))]
+    fn new(
+        py: Python,
+        map: WindowSource,
+        window_length: u64,
+        stride: Option<u64>,
+        name: Option<String>,
+        contig: Option<Vec<u8>>,
+    ) -> PyResult<Self> {
+        let targets = match (name, contig) {
+            (None, None) => {
+                let global = map.get_global(py)?;
+                (0..global.num_contigs() as u32)
+                    .map(|id| {
+                        let (name, contig) = global.contig(id).unwrap();
+                        (name.to_string(), contig.to_vec(), global.contig_length(id))
+                    })
+                    .collect()
+            }
+            (Some(name), None) => map
+                .contigs(py, &name)?
+                .into_iter()
+                .map(|(contig, length)| (name.clone(), contig, length))
+                .collect(),
+            (Some(name), Some(contig)) => {
+                let Some((_, length)) = map
+                    .contigs(py, &name)?
+                    .into_iter()
+                    .find(|(c, _)| *c == contig)
+                else {
+                    let e = anyhow::Error::from(Error::ContigNotFound(
+                        String::from_utf8_lossy(&contig).into_owned(),
+                    ));
+                    return Err(to_py_err(&e));
+                };
+                vec![(name, contig, length)]
+            }
+            (None, Some(_)) => return Err(PyRuntimeError::new_err("contig requires name")),
+        };
+        Ok(PyWindowIterator {
+            map,
+            window_length,
+            stride: stride.unwrap_or(window_length),
+            targets,
+            state: Mutex::new((0, None)),
+        })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// The next window as (name, contig, start, values).
+    #[allow(clippy::type_complexity)]
+    fn __next__<'py>(
+        &self,
+        py: Python<'py>,
+    ) -> PyResult<Option<(String, Bound<'py, PyBytes>, u64, Bound<'py, PyArray1<u8>>)>> {
+        let mut state = self.state.lock().unwrap();
+        let (next_target, current) = &mut *state;
+        loop {
+            if let Some(windows) = current {
+                if let Some(window) = windows.next() {
+                    let (start, mut window) = window.map_err(|e| to_py_err(&e))?;
+                    let (name, contig, _) = &self.targets[*next_target - 1];
+                    if let Some(reads) = self.map.read_policy() {
+                        reads
+                            .apply(name, contig, start, self.window_length, &mut window)
+                            .map_err(|e| to_py_err(&e))?;
+                    }
+                    return Ok(Some((
+                        name.clone(),
+                        PyBytes::new(py, contig),
+                        start,
+                        window.into_pyarray(py),
+                    )));
+                }
+                *current = None;
+            }
+            let Some((name, contig, length)) = self.targets.get(*next_target) else {
+                return Ok(None);
+            };
+            *current = Some(self.map.sliding_windows(
+                py,
+                name,
+                contig,
+                *length,
+                self.window_length,
+                self.stride,
+            )?);
+            *next_target += 1;
+        }
+    }
+}
+
+/// A region to read as name, contig, start and length.
+type Query = (String, Vec<u8>, u64, u64);
+
+/// The items of a `Stream`, returned as numpy arrays.
+enum StreamItem {
+    /// The values of a query.
+    Values(Array1<u8>),
+    /// A batch of windows of a sampler.
+    Batch(Encoded),
+}
+
+/// Sends the items of a stream, failing once the stream was dropped.
+type StreamSender<'a> = &'a dyn Fn(StreamItem) -> Result<()>;
+
+#[pyclass(frozen, name = "Stream")]
+struct PyStream {
+    /// The items read ahead, ending with `None`, or `None` once the stream ended.
+    receiver: Mutex<Option<Receiver<Result<Option<StreamItem>>>>>,
+    /// Stops the background thread when the stream is dropped or interrupted.
+    cancel: Cancel,
+}
+
+impl PyStream {
+    /// Run `produce` on a background thread, which reads up to `prefetch` items ahead of the
+    /// consumer and blocks while the queue is full.
+    fn spawn<F>(prefetch: usize, produce: F) -> PyResult<Self>
+    where
+        F: FnOnce(&Cancel, StreamSender) -> Result<()> + Send + 'static,
+    {
+        if prefetch == 0 {
+            return Err(PyValueError::new_err("prefetch must be positive"));
+        }
+        let cancel = Cancel::default();
+        let (sender, receiver) = mpsc::sync_channel(prefetch);
+        {
+            let cancel = cancel.clone();
+            std::thread::Builder::new()
+                .name("fastar-loader-stream".to_string())
+                .spawn(move || {
+                    let send = |item| {
+                        sender
+                            .send(Ok(Some(item)))
+                            .ok()
+                            .context("The stream was dropped")
+                    };
+                    let result = produce(&cancel, &send);
+                    // Fails if the stream was dropped, which needs no result anymore
+                    let _ = sender.send(result.map(|()| None));
+                })?;
+        }
+        Ok(PyStream {
+            receiver: Mutex::new(Some(receiver)),
+            cancel,
+        })
+    }
+}
+
+/// Read `queries` in chunks of `chunk_size`, each with `read_chunk`, and send the values in
+/// order.
+fn send_queries(
+    queries: &[Query],
+    chunk_size: usize,
+    cancel: &Cancel,
+    send: StreamSender,
+    read_chunk: impl Fn(&[Query]) -> Result<Vec<Array1<u8>>>,
+) -> Result<()> {
+    for chunk in queries.chunks(chunk_size) {
+        cancel.check()?;
+        for values in read_chunk(chunk)? {
+            send(StreamItem::Values(values))?;
+        }
+    }
+    Ok(())
+}
+
+#[pymethods]
+impl PyStream {
+    /// Read `queries` of `map` in order, `prefetch` queries ahead and in parallel on the pool
+    /// of batch reads. Sequences are returned with the read policy of the map applied, and
+    /// tracks as stored.
+    #[staticmethod]
+    fn queries(
+        py: Python,
+        map: WindowSource,
+        queries: Vec<Query>,
+        prefetch: usize,
+    ) -> PyResult<Self> {
+        match map {
+            WindowSource::Fasta(map) => {
+                // Wait for a background build here, so that its errors are raised right away
+                map.get().get_roots(py)?;
+                PyStream::spawn(prefetch, move |cancel, send| {
+                    let map = map.get();
+                    let roots = map.roots.wait().map_err(|e| anyhow::anyhow!("{:#}", e))?;
+                    send_queries(&queries, prefetch, cancel, send, |chunk| {
+                        map.install(|| {
+                            chunk
+                                .par_iter()
+                                .map(|(name, contig, start, length)| {
+                                    map.read(roots, name, contig, *start, *length)
+                                })
+                                .collect()
+                        })
+                    })
+                })
+            }
+            WindowSource::Track(map) => {
+                map.get().get_roots(py)?;
+                PyStream::spawn(prefetch, move |cancel, send| {
+                    let map = map.get();
+                    let roots = map.roots.wait().map_err(|e| anyhow::anyhow!("{:#}", e))?;
+                    send_queries(&queries, prefetch, cancel, send, |chunk| {
+                        map.install(|| {
+                            chunk
+                                .par_iter()
+                                .map(|(name, contig, start, length)| {
+                                    roots.get(name).and_then(|(archive, root, name)| {
+                                        archive.read_sequence(root, name, contig, *start, *length)
+                                    })
+                                })
+                                .collect()
+                        })
+                    })
+                })
+            }
+        }
+    }
+
+    /// Sample `num_batches` batches of `batch_size` windows with `sampler`, or batches without
+    /// end, `prefetch` batches ahead. The windows of FASTA maps are encoded by `encoding`. The
+    /// sampler takes the GIL briefly for each batch.
+    #[staticmethod]
+    #[pyo3(signature = (sampler, batch_size, prefetch, num_batches=None, encoding="raw"))]
+    fn sampler(
+        py: Python,
+        sampler: Py<PyWindowSampler>,
+        batch_size: usize,
+        prefetch: usize,
+        num_batches: Option<usize>,
+        encoding: &str,
+    ) -> PyResult<Self> {
+        let encoding = Encoding::parse(encoding).map_err(|e| to_py_err(&e))?;
+        if encoding != Encoding::Raw && matches!(sampler.get().map, WindowSource::Track(_)) {
+            return Err(PyValueError::new_err(
+                "Tracks can only be streamed as raw values",
+            ));
+        }
+        sampler.get().map.get_global(py)?;
+        PyStream::spawn(prefetch, move |cancel, send| {
+            for _ in 0..num_batches.unwrap_or(usize::MAX) {
+                let (_, _, windows) =
+                    Python::attach(|py| sampler.get().sample_windows(py, batch_size, cancel))?;
+                send(StreamItem::Batch(encoding.encode(windows)))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// The next item, waiting without the GIL until it was read. Errors of the reads are
+    /// raised here and end the stream.
+    fn __next__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        let mut receiver = self.receiver.lock().unwrap();
+        let Some(current) = receiver.as_mut() else {
+            return Ok(None);
+        };
+        let item = wait_for(py, current, &self.cancel, None);
+        if !matches!(item, Ok(Some(_))) {
+            *receiver = None;
+        }
+        Ok(item?.map(|item| match item {
+            StreamItem::Values(values) => values.into_pyarray(py).into_any(),
+            StreamItem::Batch(Encoded::Windows(windows)) => windows.into_pyarray(py).into_any(),
+            StreamItem::Batch(Encoded::OneHot(one_hot)) => one_hot.into_pyarray(py).into_any(),
+        }))
+    }
+}
+
+impl Drop for PyStream {
+    fn drop(&mut self) {
+        self.cancel.cancel();
     }
 }
 
 #[pymodule]
 fn fastar_loader(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    // Forward log messages to Python's logging module
+    pyo3_log::init();
     m.add_wrapped(wrap_pyfunction!(read_sequence))?;
+    m.add_wrapped(wrap_pyfunction!(list_segments))?;
+    m.add_wrapped(wrap_pyfunction!(cleanup_segments))?;
+    m.add_wrapped(wrap_pyfunction!(check_load_options))?;
+    m.add_wrapped(wrap_pyfunction!(check_mutations))?;
+    m.add_wrapped(wrap_pyfunction!(py_metrics))?;
+    m.add_wrapped(wrap_pyfunction!(reset_metrics))?;
+    m.add_wrapped(wrap_pyfunction!(py_benchmark))?;
+    m.add_wrapped(wrap_pyfunction!(py_cli))?;
+    m.add_wrapped(wrap_pyfunction!(start_tracing))?;
+    m.add_wrapped(wrap_pyfunction!(stop_tracing))?;
+    m.add_wrapped(wrap_pyfunction!(set_retry_policy))?;
+    m.add_wrapped(wrap_pyfunction!(set_num_threads))?;
+    m.add_wrapped(wrap_pyfunction!(py_collate))?;
+    m.add_class::<PyCancellationToken>()?;
+    m.add_class::<PyBuffer>()?;
     m.add_class::<PyFastaMap>()?;
     m.add_class::<PyTrackMap>()?;
+    m.add_class::<PyWindowSampler>()?;
+    m.add_class::<PyPositionSampler>()?;
+    m.add_class::<PyGcMatchedSampler>()?;
+    m.add_class::<PyGenomeDataset>()?;
+    m.add_class::<PySparseTrack>()?;
+    m.add_class::<PyRleTrack>()?;
+    m.add_class::<PyMappedTrack>()?;
+    m.add_class::<PyPackedGenomes>()?;
+    m.add_class::<PyWindowPartition>()?;
+    m.add_class::<PyWindowIterator>()?;
+    m.add_class::<PyStream>()?;
+    #[cfg(feature = "refget")]
+    m.add_class::<PyRefgetServer>()?;
+    // Optional features this module was built with
+    let features = [
+        ("refget", cfg!(feature = "refget")),
+        ("remote", cfg!(feature = "remote")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect::<Vec<_>>();
+    m.add("FEATURES", features)?;
+    let py = m.py();
+    m.add("FastarLoaderError", py.get_type::<FastarLoaderError>())?;
+    m.add("NameNotFoundError", py.get_type::<NameNotFoundError>())?;
+    m.add("ContigNotFoundError", py.get_type::<ContigNotFoundError>())?;
+    m.add("OutOfBoundsError", py.get_type::<OutOfBoundsError>())?;
+    m.add("CacheCorruptedError", py.get_type::<CacheCorruptedError>())?;
+    m.add("StorageError", py.get_type::<StorageError>())?;
+    m.add(
+        "InvalidSequenceError",
+        py.get_type::<InvalidSequenceError>(),
+    )?;
     Ok(())
 }
@@ -0,0 +1,5 @@
+//! The `fastar-loader` command line interface, to build and check caches without Python.
+
+fn main() {
+    std::process::exit(fastar_loader::cli_main(std::env::args().skip(1).collect()));
+}
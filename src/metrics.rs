@@ -0,0 +1,125 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of the read paths, shared by all maps of the process, to tell whether reading is
+/// the bottleneck. The counters are independent, so relaxed ordering suffices.
+pub(crate) struct Metrics {
+    queries: AtomicU64,
+    bytes_decoded: AtomicU64,
+    blocks_decompressed: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    /// Reads of data files retried after transient errors, see `retry::with_retry`.
+    retries: AtomicU64,
+    /// Data files which are currently open, which is a gauge rather than a counter.
+    open_files: AtomicU64,
+}
+
+pub(crate) static METRICS: Metrics = Metrics {
+    queries: AtomicU64::new(0),
+    bytes_decoded: AtomicU64::new(0),
+    blocks_decompressed: AtomicU64::new(0),
+    cache_hits: AtomicU64::new(0),
+    cache_misses: AtomicU64::new(0),
+    retries: AtomicU64::new(0),
+    open_files: AtomicU64::new(0),
+};
+
+impl Metrics {
+    /// Count a read of `bytes` uncompressed bytes, which spans `blocks` BGZF blocks.
+    pub(crate) fn record_query(&self, bytes: u64, blocks: u64) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        self.bytes_decoded.fetch_add(bytes, Ordering::Relaxed);
+        self.blocks_decompressed
+            .fetch_add(blocks, Ordering::Relaxed);
+    }
+
+    /// Count a lookup in the block cache of remote files.
+    #[cfg_attr(not(feature = "remote"), allow(dead_code))]
+    pub(crate) fn record_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters by name.
+    pub(crate) fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        [
+            ("queries", &self.queries),
+            ("bytes_decoded", &self.bytes_decoded),
+            ("blocks_decompressed", &self.blocks_decompressed),
+            ("cache_hits", &self.cache_hits),
+            ("cache_misses", &self.cache_misses),
+            ("retries", &self.retries),
+            ("open_files", &self.open_files),
+        ]
+        .into_iter()
+        .map(|(name, counter)| (name, counter.load(Ordering::Relaxed)))
+        .collect()
+    }
+
+    /// Reset all counters to zero. The number of open files is kept, as it is not a counter.
+    pub(crate) fn reset(&self) {
+        for counter in [
+            &self.queries,
+            &self.bytes_decoded,
+            &self.blocks_decompressed,
+            &self.cache_hits,
+            &self.cache_misses,
+            &self.retries,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A file which is counted as open in `METRICS` until it is dropped.
+pub(crate) struct TrackedFile<F>(F);
+
+impl<F> TrackedFile<F> {
+    pub(crate) fn new(file: F) -> Self {
+        METRICS.open_files.fetch_add(1, Ordering::Relaxed);
+        TrackedFile(file)
+    }
+}
+
+impl<F> Drop for TrackedFile<F> {
+    fn drop(&mut self) {
+        METRICS.open_files.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<F: Read> Read for TrackedFile<F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<F: Seek> Seek for TrackedFile<F> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_tracked_file() {
+        let mut file = TrackedFile::new(Cursor::new(b"ACGT".to_vec()));
+        file.seek(SeekFrom::Start(2)).unwrap();
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "GT");
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::{bail, Result};
+use numpy::ndarray::Array2;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+
+use crate::error::Error;
+
+/// Bases of substitutions and insertions.
+const BASES: [u8; 4] = *b"ACGT";
+/// Fills windows shortened by deletions.
+const PAD: u8 = b'N';
+
+/// Random substitutions and small indels of windows, to augment training data.
+/// Each window is mutated by its own RNG, seeded from the RNG of the mutator, so that a batch
+/// is mutated in parallel but deterministically.
+pub(crate) struct Mutator {
+    substitution_rate: f64,
+    indel_rate: f64,
+    max_indel_length: usize,
+    rng: ChaCha8Rng,
+}
+
+impl Mutator {
+    /// Rates are per base. Without a seed, the mutator is seeded from the OS.
+    pub(crate) fn new(
+        substitution_rate: f64,
+        indel_rate: f64,
+        max_indel_length: usize,
+        seed: Option<u64>,
+    ) -> Result<Self> {
+        for (name, rate) in [
+            ("substitution_rate", substitution_rate),
+            ("indel_rate", indel_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                bail!(Error::InvalidOptions(format!(
+                    "{} must be between 0 and 1",
+                    name
+                )));
+            }
+        }
+        if indel_rate > 0.0 && max_indel_length == 0 {
+            bail!(Error::InvalidOptions(
+                "max_indel_length must be positive".to_string()
+            ));
+        }
+        let mut rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        // Independent of a sampler seeded the same
+        rng.set_stream(1);
+        Ok(Mutator {
+            substitution_rate,
+            indel_rate,
+            max_indel_length,
+            rng,
+        })
+    }
+
+    /// Mutate each row of `windows`, which must be in standard layout, in place.
+    pub(crate) fn mutate(&mut self, windows: &mut Array2<u8>) {
+        let seeds = (0..windows.nrows())
+            .map(|_| self.rng.gen::<u64>())
+            .collect::<Vec<_>>();
+        let length = windows.ncols();
+        if length == 0 {
+            return;
+        }
+        windows
+            .as_slice_mut()
+            .expect("windows in standard layout")
+            .par_chunks_mut(length)
+            .zip(seeds)
+            .for_each(|(window, seed)| {
+                self.mutate_window(window, &mut ChaCha8Rng::seed_from_u64(seed))
+            });
+    }
+
+    /// Apply indels, re-padding or truncating the window to its length, and then
+    /// substitutions. Only `ACGT` are substituted, keeping their case.
+    fn mutate_window(&self, window: &mut [u8], rng: &mut ChaCha8Rng) {
+        let length = window.len();
+        if self.indel_rate > 0.0 {
+            let original = window.to_vec();
+            let mut mutated = Vec::with_capacity(length + self.max_indel_length);
+            let mut i = 0;
+            while let Some(next) = next_event(rng, self.indel_rate, i, length) {
+                mutated.extend_from_slice(&original[i..next]);
+                let size = rng.gen_range(1..=self.max_indel_length);
+                if rng.gen::<bool>() {
+                    mutated.extend((0..size).map(|_| BASES[rng.gen_range(0..4)]));
+                    mutated.push(original[next]);
+                    i = next + 1;
+                } else {
+                    i = (next + size).min(length);
+                }
+                if mutated.len() >= length {
+                    break;
+                }
+            }
+            mutated.extend_from_slice(&original[i..]);
+            mutated.resize(length, PAD);
+            window.copy_from_slice(&mutated);
+        }
+        let mut i = 0;
+        while let Some(next) = next_event(rng, self.substitution_rate, i, length) {
+            let base = window[next];
+            if let Some(class) = BASES.iter().position(|&b| b == base.to_ascii_uppercase()) {
+                let other = BASES[(class + rng.gen_range(1..4)) % 4];
+                window[next] = if base.is_ascii_lowercase() {
+                    other.to_ascii_lowercase()
+                } else {
+                    other
+                };
+            }
+            i = next + 1;
+        }
+    }
+}
+
+/// Position of the next event at or after `start` and before `end`, where events occur at
+/// each position with probability `rate`. The gaps between events are drawn from a geometric
+/// distribution, so low rates only draw few random numbers.
+fn next_event(rng: &mut ChaCha8Rng, rate: f64, start: usize, end: usize) -> Option<usize> {
+    if rate <= 0.0 || start >= end {
+        return None;
+    }
+    let gap = if rate >= 1.0 {
+        0.0
+    } else {
+        (1.0 - rng.gen::<f64>()).ln() / (1.0 - rate).ln()
+    };
+    let next = start as f64 + gap.floor();
+    (next < end as f64).then_some(next as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn windows() -> Array2<u8> {
+        let row = b"ACGTacgtNN".repeat(100);
+        Array2::from_shape_fn((8, row.len()), |(_, j)| row[j])
+    }
+
+    #[test]
+    fn test_substitutions() {
+        let original = windows();
+        let mut mutated = original.clone();
+        Mutator::new(0.1, 0.0, 0, Some(0))
+            .unwrap()
+            .mutate(&mut mutated);
+        let mut changed = 0;
+        for (&before, &after) in original.iter().zip(&mutated) {
+            if before != after {
+                changed += 1;
+                assert!(
+                    before != b'N' && after.is_ascii_lowercase() == before.is_ascii_lowercase()
+                );
+            }
+        }
+        // 8000 bases, of which 6400 may be substituted at a rate of 10%
+        assert!((400..900).contains(&changed), "{}", changed);
+
+        // Seeded mutators are deterministic
+        let mut again = original.clone();
+        Mutator::new(0.1, 0.0, 0, Some(0))
+            .unwrap()
+            .mutate(&mut again);
+        assert_eq!(again, mutated);
+        let mut all = original.clone();
+        Mutator::new(1.0, 0.0, 0, Some(0)).unwrap().mutate(&mut all);
+        for (&before, &after) in original.iter().zip(&all) {
+            assert_eq!(before != after, before != b'N');
+        }
+    }
+
+    #[test]
+    fn test_indels() {
+        let original = windows();
+        let mut mutated = original.clone();
+        Mutator::new(0.0, 0.01, 3, Some(0))
+            .unwrap()
+            .mutate(&mut mutated);
+        assert_eq!(mutated.dim(), original.dim());
+        assert_ne!(mutated, original);
+        for row in mutated.outer_iter() {
+            assert!(row.iter().all(|base| b"ACGTacgtN".contains(base)));
+        }
+        assert!(Mutator::new(0.0, 0.01, 0, None).is_err());
+        assert!(Mutator::new(1.5, 0.0, 0, None).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::{bail, Result};
+use numpy::ndarray::Array1;
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+use crate::coordinates::GlobalIndex;
+
+/// Rounds of the Feistel network, enough for a well-mixed order of the windows.
+const FEISTEL_ROUNDS: u64 = 4;
+
+/// A pseudo-random permutation of `0..len`, which is evaluated per index instead of being
+/// materialized. A Feistel network permutes the next power of four, and indices falling
+/// outside of `0..len` are mapped again until they fall inside ("cycle walking").
+struct Permutation {
+    len: u64,
+    half_bits: u32,
+    seed: u64,
+}
+
+impl Permutation {
+    fn new(len: u64, seed: u64) -> Self {
+        let bits = (u64::BITS - len.saturating_sub(1).leading_zeros()).max(2);
+        Permutation {
+            len,
+            half_bits: bits.div_ceil(2),
+            seed,
+        }
+    }
+
+    fn get(&self, index: u64) -> u64 {
+        let mut x = index;
+        loop {
+            x = self.feistel(x);
+            if x < self.len {
+                return x;
+            }
+        }
+    }
+
+    fn feistel(&self, x: u64) -> u64 {
+        let mask = (1u64 << self.half_bits) - 1;
+        let (mut left, mut right) = (x >> self.half_bits, x & mask);
+        for round in 0..FEISTEL_ROUNDS {
+            let f = xxh3_64_with_seed(&right.to_le_bytes(), self.seed.wrapping_add(round)) & mask;
+            (left, right) = (right, left ^ f);
+        }
+        (left << self.half_bits) | right
+    }
+}
+
+/// The windows of one worker in one epoch. All contigs are tiled into windows of
+/// `window_length` every `stride` values, which are shuffled by `seed` and `epoch` and dealt
+/// to the workers in turn. Every worker gets the same number of windows, so up to
+/// `num_workers - 1` windows are left out in each epoch.
+pub(crate) struct WindowPartition {
+    window_length: u64,
+    stride: u64,
+    /// Number of windows before each contig, indexed by global id and ending with the total.
+    window_starts: Vec<u64>,
+    permutation: Permutation,
+    num_workers: u64,
+    worker_id: u64,
+}
+
+impl WindowPartition {
+    pub(crate) fn new(
+        global: &GlobalIndex,
+        window_length: u64,
+        stride: u64,
+        num_workers: u64,
+        worker_id: u64,
+        epoch: u64,
+        seed: u64,
+    ) -> Result<Self> {
+        if window_length == 0 || stride == 0 {
+            bail!("window_length and stride must be positive");
+        }
+        if worker_id >= num_workers {
+            bail!(
+                "worker_id {} is out of range for {} workers",
+                worker_id,
+                num_workers
+            );
+        }
+        let mut window_starts = vec![0];
+        let mut total = 0;
+        for id in 0..global.num_contigs() as u32 {
+            let length = global.contig_length(id);
+            if length >= window_length {
+                total += (length - window_length) / stride + 1;
+            }
+            window_starts.push(total);
+        }
+        let mut key = [0u8; 16];
+        key[..8].copy_from_slice(&seed.to_le_bytes());
+        key[8..].copy_from_slice(&epoch.to_le_bytes());
+        Ok(WindowPartition {
+            window_length,
+            stride,
+            window_starts,
+            permutation: Permutation::new(total, xxh3_64_with_seed(&key, 0)),
+            num_workers,
+            worker_id,
+        })
+    }
+
+    pub(crate) fn window_length(&self) -> u64 {
+        self.window_length
+    }
+
+    /// Number of windows of this worker.
+    pub(crate) fn len(&self) -> u64 {
+        self.permutation.len / self.num_workers
+    }
+
+    /// Global contig id and start of the `index`-th window of this worker.
+    pub(crate) fn get(&self, index: u64) -> Result<(u32, u64)> {
+        if index >= self.len() {
+            bail!(
+                "Window {} is out of range for {} windows",
+                index,
+                self.len()
+            );
+        }
+        let window = self
+            .permutation
+            .get(index * self.num_workers + self.worker_id);
+        let id = self.window_starts.partition_point(|&start| start <= window) - 1;
+        let start = (window - self.window_starts[id]) * self.stride;
+        Ok((id as u32, start))
+    }
+
+    /// Global contig ids and starts of `count` windows of this worker starting at `offset`.
+    pub(crate) fn get_batch(&self, offset: u64, count: u64) -> Result<(Array1<u32>, Array1<u64>)> {
+        let (ids, starts): (Vec<_>, Vec<_>) = (offset..offset.saturating_add(count))
+            .map(|index| self.get(index))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        Ok((Array1::from_vec(ids), Array1::from_vec(starts)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_permutation() {
+        for len in [0, 1, 2, 5, 100, 1000] {
+            let permutation = Permutation::new(len, 42);
+            let values = (0..len).map(|i| permutation.get(i)).collect::<HashSet<_>>();
+            assert_eq!(values, (0..len).collect());
+        }
+        let (a, b) = (Permutation::new(1000, 1), Permutation::new(1000, 2));
+        assert!((0..1000).any(|i| a.get(i) != b.get(i)));
+    }
+
+    #[test]
+    fn test_partition() {
+        let contigs = |name: &str| -> Result<Vec<(&'static [u8], u64)>> {
+            Ok(match name {
+                "a" => vec![(b"x".as_slice(), 95), (b"y".as_slice(), 5)],
+                "b" => vec![(b"x".as_slice(), 30)],
+                _ => unreachable!(),
+            })
+        };
+        let global = GlobalIndex::build(vec!["a".to_string(), "b".to_string()], contigs).unwrap();
+        // 9 windows in a/x, none in a/y and 3 in b/x, dealt to 5 workers
+        let windows = (0..5)
+            .flat_map(|worker| {
+                let partition = WindowPartition::new(&global, 10, 10, 5, worker, 3, 0).unwrap();
+                assert_eq!(partition.len(), 2);
+                (0..2).map(move |i| partition.get(i).unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(windows.iter().collect::<HashSet<_>>().len(), 10);
+        for &(id, start) in &windows {
+            assert_ne!(id, 1);
+            assert_eq!(start % 10, 0);
+            assert!(start + 10 <= global.contig_length(id));
+        }
+        let other_epoch = WindowPartition::new(&global, 10, 10, 5, 0, 4, 0).unwrap();
+        let same_epoch = WindowPartition::new(&global, 10, 10, 5, 0, 3, 0).unwrap();
+        assert_eq!(same_epoch.get_batch(0, 2).unwrap().0[0], windows[0].0);
+        assert_eq!(other_epoch.len(), 2);
+        assert!(WindowPartition::new(&global, 10, 10, 5, 5, 0, 0).is_err());
+        assert!(same_epoch.get(2).is_err());
+    }
+}
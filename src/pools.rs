@@ -0,0 +1,128 @@
+use std::sync::{Arc, OnceLock, RwLock};
+
+use anyhow::{bail, Result};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::error::Error;
+
+/// The kinds of operations which can run on dedicated pools instead of the global rayon pool,
+/// e.g., when the embedding process uses rayon itself.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Operation {
+    /// Indexing files when building a cache.
+    Build,
+    /// Batch reads, such as windows of samplers or a region of several entries.
+    Read,
+}
+
+static BUILD_POOL: RwLock<Option<Arc<ThreadPool>>> = RwLock::new(None);
+static READ_POOL: RwLock<Option<Arc<ThreadPool>>> = RwLock::new(None);
+
+impl Operation {
+    fn slot(self) -> &'static RwLock<Option<Arc<ThreadPool>>> {
+        match self {
+            Operation::Build => &BUILD_POOL,
+            Operation::Read => &READ_POOL,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Build => "build",
+            Operation::Read => "read",
+        }
+    }
+}
+
+/// A dedicated pool of `num_threads` threads for `operation`, whose threads are named after it.
+pub(crate) fn new_pool(num_threads: usize, operation: Operation) -> Result<Arc<ThreadPool>> {
+    if num_threads == 0 {
+        bail!(Error::InvalidOptions(
+            "The number of threads must be positive".to_string()
+        ));
+    }
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(move |i| format!("fastar-loader-{}-{}", operation.name(), i))
+        .build()?;
+    Ok(Arc::new(pool))
+}
+
+/// Run `operation` on a dedicated pool of `num_threads` threads, or on the global rayon pool
+/// again with `None`. Operations which are already running keep their pool.
+pub(crate) fn set_num_threads(operation: Operation, num_threads: Option<usize>) -> Result<()> {
+    let pool = num_threads
+        .map(|num_threads| new_pool(num_threads, operation))
+        .transpose()?;
+    *operation.slot().write().unwrap() = pool;
+    Ok(())
+}
+
+/// The dedicated pool of `operation`, if any.
+pub(crate) fn pool(operation: Operation) -> Option<Arc<ThreadPool>> {
+    operation.slot().read().unwrap().clone()
+}
+
+/// Threads of reads with a timeout, see `timeout_pool`.
+const TIMEOUT_THREADS: usize = 16;
+
+static TIMEOUT_POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// The pool of reads with a timeout. A read blocked by a hung filesystem keeps its thread, so
+/// at most `TIMEOUT_THREADS` threads are blocked, and further reads wait in its queue until
+/// they time out themselves.
+pub(crate) fn timeout_pool() -> Result<&'static ThreadPool> {
+    if let Some(pool) = TIMEOUT_POOL.get() {
+        return Ok(pool);
+    }
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(TIMEOUT_THREADS)
+        .thread_name(|i| format!("fastar-loader-timeout-{}", i))
+        .build()?;
+    // Another thread may have set it in the meantime
+    Ok(TIMEOUT_POOL.get_or_init(|| pool))
+}
+
+/// Run `f` on `pool`, or else on the dedicated pool of `operation`, or else on the global
+/// rayon pool.
+pub(crate) fn install<R, F>(pool: Option<&Arc<ThreadPool>>, operation: Operation, f: F) -> R
+where
+    R: Send,
+    F: FnOnce() -> R + Send,
+{
+    match pool.cloned().or_else(|| self::pool(operation)) {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pools() {
+        assert!(new_pool(0, Operation::Read).is_err());
+        let dedicated = new_pool(2, Operation::Read).unwrap();
+        let threads = install(
+            Some(&dedicated),
+            Operation::Read,
+            rayon::current_num_threads,
+        );
+        assert_eq!(threads, 2);
+        let name = install(Some(&dedicated), Operation::Read, || {
+            std::thread::current().name().map(str::to_string)
+        });
+        assert!(name.unwrap().starts_with("fastar-loader-read-"));
+
+        set_num_threads(Operation::Build, Some(3)).unwrap();
+        let threads = install(None, Operation::Build, rayon::current_num_threads);
+        assert_eq!(threads, 3);
+        set_num_threads(Operation::Build, None).unwrap();
+        assert!(pool(Operation::Build).is_none());
+
+        let timeout = timeout_pool().unwrap();
+        assert_eq!(timeout.current_num_threads(), TIMEOUT_THREADS);
+        assert!(std::ptr::eq(timeout, timeout_pool().unwrap()));
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use numpy::ndarray::Array1;
+use rayon::prelude::*;
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::storage::{ArchiveStorage, Schema, ShmemStorage};
+use crate::windows::SequenceReader;
+
+/// Number of bases which are decompressed at once while preloading a contig.
+const CHUNK_BASES: usize = 1 << 20;
+
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct PreloadedContig {
+    name: Vec<u8>,
+    /// Position of the first base of the contig in the bases of the genome.
+    offset: u64,
+    length: u64,
+}
+
+/// The contigs of a genome whose bases are decompressed into shared memory, to serve reads by
+/// copying them. The bases precede the archive in its storage, see `SharedGenome`, so that
+/// they are decompressed in place instead of being serialized.
+#[derive(Archive, Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct PreloadedGenome {
+    /// Sorted by name.
+    contigs: Vec<PreloadedContig>,
+    num_bases: u64,
+}
+
+impl Schema for PreloadedGenome {
+    const NAME: &'static str = "PreloadedGenome";
+    const VERSION: u32 = 2;
+}
+
+impl ArchivedPreloadedGenome {
+    /// The bases of a region within `bases` of the genome, or `None` if the genome has no such
+    /// region.
+    fn slice<'a>(
+        &self,
+        bases: &'a [u8],
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> Option<&'a [u8]> {
+        let i = self
+            .contigs
+            .binary_search_by(|entry| entry.name.as_slice().cmp(contig))
+            .ok()?;
+        let entry = &self.contigs[i];
+        let end = start.checked_add(length)?;
+        if end > u64::from(entry.length) {
+            return None;
+        }
+        let offset = u64::from(entry.offset);
+        bases.get((offset + start) as usize..(offset + end) as usize)
+    }
+}
+
+/// A preloaded genome in shared memory: its bases, followed by the archived contigs.
+type SharedGenome = ArchiveStorage<PreloadedGenome, ShmemStorage>;
+
+/// Write the bases of `contigs` into a new shared genome in parallel, where `fill(contig,
+/// bases)` writes the bases of a contig into its part of the shared memory.
+fn write_shared(
+    contigs: Vec<(&[u8], u64)>,
+    fill: impl Fn(&[u8], &mut [u8]) -> Result<()> + Sync,
+) -> Result<SharedGenome> {
+    let num_bases: u64 = contigs.iter().map(|(_, length)| length).sum();
+    let mut offset = 0;
+    let mut entries: Vec<_> = contigs
+        .iter()
+        .map(|&(name, length)| {
+            offset += length;
+            PreloadedContig {
+                name: name.to_vec(),
+                offset: offset - length,
+                length,
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    let genome = PreloadedGenome {
+        contigs: entries,
+        num_bases,
+    };
+    SharedGenome::with_prefix(genome, num_bases as usize, |bases| {
+        let mut rest = bases;
+        let mut slices = Vec::with_capacity(contigs.len());
+        for &(name, length) in &contigs {
+            let (slice, tail) = std::mem::take(&mut rest).split_at_mut(length as usize);
+            slices.push((name, slice));
+            rest = tail;
+        }
+        slices
+            .into_par_iter()
+            .try_for_each(|(name, slice)| fill(name, slice))
+    })
+}
+
+/// Decompress the bases of a contig from `reader` into `bases`, in chunks of `CHUNK_BASES`.
+pub(crate) fn read_bases(mut reader: SequenceReader, bases: &mut [u8]) -> Result<()> {
+    let mut buf = Vec::with_capacity(CHUNK_BASES);
+    for chunk in bases.chunks_mut(CHUNK_BASES) {
+        buf.clear();
+        reader.read(chunk.len(), &mut buf)?;
+        chunk.copy_from_slice(&buf);
+    }
+    Ok(())
+}
+
+/// Size of a preloaded genome in bytes, as counted against the budget.
+fn size(genome: &SharedGenome) -> u64 {
+    genome.as_ref().num_bases.into()
+}
+
+/// The bases of a preloaded genome.
+fn bases(genome: &SharedGenome) -> &[u8] {
+    genome.prefix(size(genome) as usize)
+}
+
+#[derive(Default)]
+struct PreloadCache {
+    /// Genomes by name, with the time of their last use.
+    genomes: HashMap<String, (Arc<SharedGenome>, u64)>,
+    clock: u64,
+    /// Largest total size of the genomes in bytes, if limited.
+    budget: Option<u64>,
+}
+
+impl PreloadCache {
+    fn touch(&mut self, name: &str) -> Option<Arc<SharedGenome>> {
+        self.clock += 1;
+        let (genome, last_used) = self.genomes.get_mut(name)?;
+        *last_used = self.clock;
+        Some(genome.clone())
+    }
+
+    /// Evict the least recently used genomes until they fit into the budget.
+    fn evict(&mut self) {
+        let Some(budget) = self.budget else {
+            return;
+        };
+        while self
+            .genomes
+            .values()
+            .map(|(genome, _)| size(genome))
+            .sum::<u64>()
+            > budget
+        {
+            let oldest = self
+                .genomes
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                self.genomes.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Genomes decompressed into shared memory, so that reads of them are served by copying the
+/// bases instead of decompressing BGZF blocks. The least recently read genomes are evicted when
+/// the genomes exceed the budget. Other processes attach to the same shared memory by handle.
+#[derive(Default)]
+pub(crate) struct Preloads(Mutex<PreloadCache>);
+
+impl Preloads {
+    /// Whether `name` is preloaded, which counts as a use.
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.0.lock().unwrap().touch(name).is_some()
+    }
+
+    /// Preload the genome `name` of `contigs` into shared memory, evicting other genomes to fit
+    /// it into the budget, where `fill(contig, bases)` writes the bases of a contig, see
+    /// `read_bases`. The budget is checked against the contig lengths before any bases are
+    /// written, and the bases are written into the shared memory directly.
+    pub(crate) fn insert(
+        &self,
+        name: &str,
+        contigs: Vec<(&[u8], u64)>,
+        fill: impl Fn(&[u8], &mut [u8]) -> Result<()> + Sync,
+    ) -> Result<()> {
+        self.check_budget(name, contigs.iter().map(|(_, length)| length).sum())?;
+        self.add(name, write_shared(contigs, fill)?)
+    }
+
+    /// Attach to a genome preloaded by another process from its handle.
+    pub(crate) fn attach(&self, name: &str, handle: Vec<u8>) -> Result<()> {
+        let genome = SharedGenome::import(handle, false)?;
+        // The bases precede the archive within the payload after the header page
+        if genome.size() < page_size::get() as u64 + size(&genome) {
+            bail!(Error::CacheCorrupted(format!(
+                "Preloaded {} is smaller than its bases",
+                name
+            )));
+        }
+        self.add(name, genome)
+    }
+
+    /// Fail if a genome of `num_bases` bases does not fit into the budget on its own.
+    fn check_budget(&self, name: &str, num_bases: u64) -> Result<()> {
+        if let Some(budget) = self.budget().filter(|&budget| num_bases > budget) {
+            bail!(Error::InvalidOptions(format!(
+                "{} has {} bases, more than the preload budget of {} bytes",
+                name, num_bases, budget
+            )));
+        }
+        Ok(())
+    }
+
+    fn add(&self, name: &str, genome: SharedGenome) -> Result<()> {
+        self.check_budget(name, size(&genome))?;
+        let mut cache = self.0.lock().unwrap();
+        cache.clock += 1;
+        let clock = cache.clock;
+        cache
+            .genomes
+            .insert(name.to_string(), (Arc::new(genome), clock));
+        cache.evict();
+        Ok(())
+    }
+
+    /// Limit the total size of the preloaded genomes to `budget` bytes, evicting genomes which
+    /// no longer fit, or remove the limit.
+    pub(crate) fn set_budget(&self, budget: Option<u64>) {
+        let mut cache = self.0.lock().unwrap();
+        cache.budget = budget;
+        cache.evict();
+    }
+
+    pub(crate) fn budget(&self) -> Option<u64> {
+        self.0.lock().unwrap().budget
+    }
+
+    /// Names and sizes of the preloaded genomes, most recently used first.
+    pub(crate) fn genomes(&self) -> Vec<(String, u64)> {
+        let cache = self.0.lock().unwrap();
+        let mut genomes: Vec<_> = cache.genomes.iter().collect();
+        genomes.sort_by_key(|(_, (_, last_used))| std::cmp::Reverse(*last_used));
+        genomes
+            .into_iter()
+            .map(|(name, (genome, _))| (name.clone(), size(genome)))
+            .collect()
+    }
+
+    /// Names and handles of the preloaded genomes, to attach to them in other processes.
+    pub(crate) fn handles(&self) -> Vec<(String, Vec<u8>)> {
+        let cache = self.0.lock().unwrap();
+        cache
+            .genomes
+            .iter()
+            .map(|(name, (genome, _))| (name.clone(), genome.export()))
+            .collect()
+    }
+
+    /// Copy a region of the preloaded genome `name`, or return `None` if the genome is not
+    /// preloaded or has no such region.
+    pub(crate) fn read(
+        &self,
+        name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> Option<Array1<u8>> {
+        // The genome is copied out of the cache, so that it is not locked during the read
+        let genome = self.0.lock().unwrap().touch(name)?;
+        let bases = (*genome)
+            .as_ref()
+            .slice(bases(&genome), contig, start, length)?;
+        Some(Array1::from(bases.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Preload the genome `name` of `contigs` given as (name, bases).
+    fn insert(preloads: &Preloads, name: &str, contigs: &[(&[u8], &[u8])]) -> Result<()> {
+        let lengths = contigs
+            .iter()
+            .map(|&(contig, bases)| (contig, bases.len() as u64))
+            .collect();
+        preloads.insert(name, lengths, |contig, bases| {
+            let (_, source) = contigs.iter().find(|(name, _)| *name == contig).unwrap();
+            bases.copy_from_slice(source);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_preloads() {
+        let preloads = Preloads::default();
+        preloads.set_budget(Some(10));
+        insert(&preloads, "a", &[(b"1", b"ACGT"), (b"2", b"nn")]).unwrap();
+        assert_eq!(preloads.read("a", b"2", 0, 2).unwrap().to_vec(), b"nn");
+        assert_eq!(preloads.read("a", b"1", 1, 3).unwrap().to_vec(), b"CGT");
+        assert!(preloads.read("a", b"1", 1, 4).is_none());
+        assert!(preloads.read("a", b"3", 0, 1).is_none());
+        assert!(preloads.read("b", b"1", 0, 1).is_none());
+
+        // The least recently used genome is evicted to fit the budget
+        insert(&preloads, "b", &[(b"1", b"ACG")]).unwrap();
+        assert!(preloads.contains("a"));
+        insert(&preloads, "c", &[(b"1", b"AC")]).unwrap();
+        assert_eq!(
+            preloads.genomes(),
+            vec![("c".to_string(), 2), ("a".to_string(), 6)]
+        );
+        // A genome beyond the budget is rejected before its bases are written
+        let e = preloads
+            .insert("d", vec![(b"1".as_slice(), 11)], |_, _| panic!("written"))
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::InvalidOptions(_))
+        ));
+        preloads.set_budget(Some(2));
+        assert_eq!(preloads.genomes(), vec![("c".to_string(), 2)]);
+
+        let attached = Preloads::default();
+        let (name, handle) = preloads.handles().pop().unwrap();
+        attached.attach(&name, handle).unwrap();
+        assert_eq!(attached.read("c", b"1", 0, 2).unwrap().to_vec(), b"AC");
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::interrupt::Cancel;
+
+/// Receives the number of indexed entries, the total number of entries and the last name.
+pub(crate) type ProgressCallback = Arc<dyn Fn(u64, u64, &str) + Send + Sync>;
+
+/// Minimum time between two calls of the progress callback.
+const CALLBACK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Name of an entry skipped in non-strict mode and the reason for skipping it.
+pub(crate) type Skipped = (String, String);
+
+/// How to report the progress of a build: as a bar on stderr, to a callback, or both.
+/// Entries skipped during the build are collected for the build report, and the build stops
+/// early once `cancel` is set.
+#[derive(Clone, Default)]
+pub(crate) struct Progress {
+    show_bar: bool,
+    callback: Option<ProgressCallback>,
+    skipped: Arc<Mutex<Vec<Skipped>>>,
+    cancel: Cancel,
+}
+
+impl Progress {
+    pub(crate) fn new(show_bar: bool, callback: Option<ProgressCallback>) -> Self {
+        Progress {
+            show_bar,
+            callback,
+            skipped: Arc::default(),
+            cancel: Cancel::default(),
+        }
+    }
+
+    /// Cancels builds reporting to this progress or its clones.
+    pub(crate) fn cancel(&self) -> &Cancel {
+        &self.cancel
+    }
+
+    /// Entries skipped since the last call, in the order they were skipped.
+    pub(crate) fn take_skipped(&self) -> Vec<Skipped> {
+        std::mem::take(&mut *self.skipped.lock().unwrap())
+    }
+
+    /// Start reporting progress over `total` entries.
+    pub(crate) fn start(&self, total: u64) -> ProgressTracker {
+        let bar = self.show_bar.then(|| {
+            let bar = ProgressBar::new(total);
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
+                )
+                .unwrap()
+                .progress_chars("##-"),
+            );
+            bar
+        });
+        ProgressTracker {
+            bar,
+            callback: self.callback.clone(),
+            skipped: self.skipped.clone(),
+            cancel: self.cancel.clone(),
+            total,
+            state: Mutex::new((0, None)),
+        }
+    }
+}
+
+pub(crate) struct ProgressTracker {
+    bar: Option<ProgressBar>,
+    callback: Option<ProgressCallback>,
+    skipped: Arc<Mutex<Vec<Skipped>>>,
+    cancel: Cancel,
+    total: u64,
+    /// Number of entries done and time of the last callback
+    state: Mutex<(u64, Option<Instant>)>,
+}
+
+impl ProgressTracker {
+    /// Record that `name` was processed. The callback is throttled, except for the last entry.
+    pub(crate) fn inc(&self, name: &str) {
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+        }
+        let Some(callback) = &self.callback else {
+            return;
+        };
+        let done = {
+            let mut state = self.state.lock().unwrap();
+            let (done, last_call) = &mut *state;
+            *done += 1;
+            if *done != self.total && last_call.is_some_and(|t| t.elapsed() < CALLBACK_INTERVAL) {
+                return;
+            }
+            *last_call = Some(Instant::now());
+            *done
+        };
+        // Call without holding the lock, as the callback may block, e.g., on the GIL
+        callback(done, self.total, name);
+    }
+
+    /// Record that `name` was skipped because of `reason`.
+    pub(crate) fn skip(&self, name: &str, reason: String) {
+        self.skipped
+            .lock()
+            .unwrap()
+            .push((name.to_string(), reason));
+    }
+
+    /// Fail if the build was cancelled, see `Progress::cancel`.
+    pub(crate) fn check_cancelled(&self) -> Result<()> {
+        self.cancel.check()
+    }
+
+    pub(crate) fn finish(self) {
+        if let Some(bar) = self.bar {
+            bar.finish_with_message("Indexing complete");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_callback_throttled() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let callback: ProgressCallback = {
+            let calls = calls.clone();
+            Arc::new(move |done, total, name: &str| {
+                calls.lock().unwrap().push((done, total, name.to_string()))
+            })
+        };
+        let tracker = Progress::new(false, Some(callback)).start(3);
+        for name in ["a", "b", "c"] {
+            tracker.inc(name);
+        }
+        tracker.finish();
+        // The first call goes through, the second is throttled and the last is always reported
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![(1, 3, "a".to_string()), (3, 3, "c".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_take_skipped() {
+        let progress = Progress::default();
+        let tracker = progress.start(2);
+        tracker.skip("a", "missing index".to_string());
+        tracker.inc("a");
+        tracker.inc("b");
+        tracker.finish();
+        assert_eq!(
+            progress.take_skipped(),
+            vec![("a".to_string(), "missing index".to_string())]
+        );
+        assert!(progress.take_skipped().is_empty());
+    }
+}
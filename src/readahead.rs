@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use log::debug;
+
+use crate::block_cache::{self, BlockStore};
+
+/// Number of reads which must continue each other before blocks are prefetched.
+const SEQUENTIAL_READS: u32 = 2;
+
+/// Largest number of contigs whose reads are tracked, beyond which tracking starts over.
+const MAX_STREAMS: usize = 1024;
+
+/// The last read of a contig.
+struct Stream {
+    start: u64,
+    end: u64,
+    /// Number of reads in a row which continued the previous read.
+    run: u32,
+    /// Whether blocks are being prefetched for the contig.
+    prefetching: bool,
+}
+
+impl Stream {
+    /// Whether a read from `start` continues this read: it starts at or after this read, and
+    /// at most one read length after its end, e.g., for windows with a stride.
+    fn continues(&self, start: u64) -> bool {
+        start >= self.start && start <= self.end + (self.end - self.start)
+    }
+}
+
+/// How a read continues the previous reads of its contig, see `Readahead::observe`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Access {
+    /// The read does not continue a sequential pattern.
+    Random,
+    /// The read continues a sequential pattern whose blocks are being prefetched.
+    Sequential,
+    /// The read continues a sequential pattern, and the blocks after it should be prefetched.
+    Prefetch,
+}
+
+#[derive(Default)]
+struct BlockLru {
+    /// Blocks by key, with the offset of the following block and the time of their last use.
+    blocks: HashMap<u64, (Vec<u8>, u64, u64)>,
+    clock: u64,
+}
+
+/// An LRU cache of decompressed blocks in the memory of this process, which holds the
+/// prefetched blocks when there is no shared block cache.
+pub(crate) struct LocalBlocks {
+    lru: Mutex<BlockLru>,
+    capacity: usize,
+}
+
+impl LocalBlocks {
+    pub(crate) fn new(capacity: usize) -> Self {
+        LocalBlocks {
+            lru: Mutex::new(BlockLru::default()),
+            capacity,
+        }
+    }
+}
+
+impl BlockStore for LocalBlocks {
+    fn get(&self, key: u64, buf: &mut Vec<u8>) -> Option<u64> {
+        let mut lru = self.lru.lock().unwrap();
+        lru.clock += 1;
+        let clock = lru.clock;
+        let (block, next_offset, last_used) = lru.blocks.get_mut(&key)?;
+        *last_used = clock;
+        buf.extend_from_slice(block);
+        Some(*next_offset)
+    }
+
+    fn next_offset(&self, key: u64) -> Option<u64> {
+        let lru = self.lru.lock().unwrap();
+        lru.blocks.get(&key).map(|(_, next_offset, _)| *next_offset)
+    }
+
+    fn insert(&self, key: u64, block: &[u8], next_offset: u64) {
+        let mut lru = self.lru.lock().unwrap();
+        if !lru.blocks.contains_key(&key) && lru.blocks.len() >= self.capacity {
+            let oldest = lru
+                .blocks
+                .iter()
+                .min_by_key(|(_, (_, _, last_used))| *last_used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                lru.blocks.remove(&oldest);
+            }
+        }
+        lru.clock += 1;
+        let clock = lru.clock;
+        lru.blocks.insert(key, (block.to_vec(), next_offset, clock));
+    }
+}
+
+/// Detects sequential reads of a contig, e.g., windows sweeping over a chromosome, and then
+/// decompresses the following blocks in the background, so that the next reads find them
+/// decompressed instead of waiting for them.
+pub(crate) struct Readahead {
+    /// Number of blocks to prefetch after a sequential read.
+    num_blocks: usize,
+    /// The last read by genome and contig.
+    streams: Mutex<HashMap<(String, Vec<u8>), Stream>>,
+    /// Holds the prefetched blocks when the map has no shared block cache.
+    blocks: Arc<LocalBlocks>,
+}
+
+impl Readahead {
+    pub(crate) fn new(num_blocks: usize) -> Self {
+        Readahead {
+            num_blocks,
+            streams: Mutex::new(HashMap::new()),
+            // Room for the blocks being read and those prefetched for a few contigs at once
+            blocks: Arc::new(LocalBlocks::new(4 * (num_blocks + 1))),
+        }
+    }
+
+    pub(crate) fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    /// The blocks prefetched into the memory of this process.
+    pub(crate) fn blocks(&self) -> Arc<LocalBlocks> {
+        self.blocks.clone()
+    }
+
+    /// Record a read of `length` bases from `start` of `contig`, and return whether it continues
+    /// a sequential pattern and whether blocks are being prefetched for it yet.
+    pub(crate) fn observe(
+        &self,
+        fasta_name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+    ) -> Access {
+        let mut streams = self.streams.lock().unwrap();
+        if streams.len() >= MAX_STREAMS {
+            streams.clear();
+        }
+        let end = start + length;
+        let stream = streams
+            .entry((fasta_name.to_string(), contig.to_vec()))
+            .and_modify(|stream| {
+                stream.run = if stream.continues(start) {
+                    stream.run + 1
+                } else {
+                    1
+                };
+                (stream.start, stream.end) = (start, end);
+            })
+            .or_insert(Stream {
+                start,
+                end,
+                run: 1,
+                prefetching: false,
+            });
+        if stream.run < SEQUENTIAL_READS {
+            Access::Random
+        } else if stream.prefetching {
+            Access::Sequential
+        } else {
+            stream.prefetching = true;
+            Access::Prefetch
+        }
+    }
+
+    /// Prefetch the blocks of `path` from `next_offset`, the block after a read of `contig`
+    /// which `observe` classified as `Access::Prefetch`, into `store` in the background,
+    /// stored per `version` of the file as by `block_cache::read_raw`.
+    pub(crate) fn prefetch(
+        self: &Arc<Self>,
+        store: Arc<dyn BlockStore>,
+        fasta_name: &str,
+        contig: &[u8],
+        path: PathBuf,
+        version: u64,
+        next_offset: u64,
+    ) {
+        let readahead = self.clone();
+        let key = (fasta_name.to_string(), contig.to_vec());
+        rayon::spawn(move || {
+            let num_blocks = readahead.num_blocks;
+            let result = block_cache::prefetch(&*store, &path, version, next_offset, num_blocks);
+            if let Err(e) = result {
+                debug!("Failed to prefetch {}: {:#}", path.display(), e);
+            }
+            if let Some(stream) = readahead.streams.lock().unwrap().get_mut(&key) {
+                stream.prefetching = false;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_blocks() {
+        let blocks = LocalBlocks::new(2);
+        let mut buf = Vec::new();
+        blocks.insert(1, b"AC", 10);
+        blocks.insert(2, b"GT", 20);
+        assert_eq!(blocks.get(1, &mut buf), Some(10));
+        blocks.insert(3, b"NN", 30);
+        assert_eq!(blocks.next_offset(2), None);
+        assert_eq!(blocks.next_offset(3), Some(30));
+        assert_eq!(blocks.get(1, &mut buf), Some(10));
+        assert_eq!(buf, b"ACAC");
+    }
+
+    #[test]
+    fn test_observe() {
+        let readahead = Readahead::new(4);
+        assert_eq!(readahead.observe("a", b"1", 0, 100), Access::Random);
+        assert_eq!(readahead.observe("a", b"1", 100, 100), Access::Prefetch);
+        // Blocks are already being prefetched
+        assert_eq!(readahead.observe("a", b"1", 250, 100), Access::Sequential);
+        readahead
+            .streams
+            .lock()
+            .unwrap()
+            .get_mut(&("a".to_string(), b"1".to_vec()))
+            .unwrap()
+            .prefetching = false;
+        assert_eq!(readahead.observe("a", b"1", 350, 100), Access::Prefetch);
+        // Jumps back or far ahead start over
+        assert_eq!(readahead.observe("a", b"1", 0, 100), Access::Random);
+        assert_eq!(readahead.observe("a", b"1", 1000, 100), Access::Random);
+        assert_eq!(readahead.observe("a", b"2", 1100, 100), Access::Random);
+    }
+}
@@ -0,0 +1,308 @@
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, bail, Result};
+use log::{info, warn};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const SEQUENCE_TYPE: &str = "text/vnd.ga4gh.refget.v2.0.0+plain; charset=us-ascii";
+const JSON_TYPE: &str = "application/vnd.ga4gh.refget.v2.0.0+json";
+
+/// Digests and length of a served sequence.
+pub(crate) struct SequenceMetadata {
+    pub(crate) md5: String,
+    pub(crate) sha512t24u: String,
+    pub(crate) length: u64,
+}
+
+/// Sequences served by a `RefgetServer`, looked up by MD5 or GA4GH digest.
+pub(crate) trait SequenceSource: Send + Sync {
+    /// Metadata of the sequence with `digest`, or `None` if there is no such sequence.
+    fn metadata(&self, digest: &str) -> Result<Option<SequenceMetadata>>;
+
+    /// Read `length` bases starting at `start` of the sequence with `digest`.
+    fn read(&self, digest: &str, start: u64, length: u64) -> Result<Vec<u8>>;
+}
+
+/// Status, content type and body of a response.
+#[derive(Debug, PartialEq)]
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json(status: u16, body: String) -> Self {
+        Reply {
+            status,
+            content_type: JSON_TYPE,
+            body: body.into_bytes(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Reply::json(
+            status,
+            format!(
+                "{{\"status_code\": {}, \"message\": \"{}\"}}",
+                status,
+                message.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+        )
+    }
+}
+
+fn service_info() -> Reply {
+    Reply::json(
+        200,
+        concat!(
+            "{\"id\": \"fastar-loader\", \"name\": \"fastar-loader refget server\", ",
+            "\"type\": {\"group\": \"org.ga4gh\", \"artifact\": \"refget\", ",
+            "\"version\": \"2.0.0\"}, ",
+            "\"refget\": {\"circular_supported\": false, \"algorithms\": [\"md5\", \"ga4gh\"], ",
+            "\"identifier_types\": [], \"subsequence_limit\": null}}"
+        )
+        .to_string(),
+    )
+}
+
+/// Parse the `start` and `end` query parameters.
+fn parse_query(query: &str) -> Result<(Option<u64>, Option<u64>), Reply> {
+    let (mut start, mut end) = (None, None);
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = value
+            .parse::<u64>()
+            .map_err(|_| Reply::error(400, &format!("Invalid {}: {}", key, value)))?;
+        match key {
+            "start" => start = Some(value),
+            "end" => end = Some(value),
+            _ => {}
+        }
+    }
+    Ok((start, end))
+}
+
+/// Parse a `Range: bytes=first-last` header with an inclusive end.
+fn parse_range(range: &str) -> Result<(u64, u64), Reply> {
+    let invalid = || Reply::error(400, &format!("Invalid range: {}", range));
+    let (first, last) = range
+        .trim()
+        .strip_prefix("bytes=")
+        .and_then(|range| range.split_once('-'))
+        .ok_or_else(invalid)?;
+    let first = first.parse::<u64>().map_err(|_| invalid())?;
+    let last = last.parse::<u64>().map_err(|_| invalid())?;
+    if first > last {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+/// Answer a request for `url` with an optional `Range` header.
+fn respond(source: &dyn SequenceSource, method: &Method, url: &str, range: Option<&str>) -> Reply {
+    if *method != Method::Get {
+        return Reply::error(405, "Only GET is supported");
+    }
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let Some(id) = path.strip_prefix("/sequence/") else {
+        return Reply::error(404, "Not found");
+    };
+    if id == "service-info" {
+        return service_info();
+    }
+    let (digest, want_metadata) = match id.strip_suffix("/metadata") {
+        Some(digest) => (digest, true),
+        None => (id, false),
+    };
+    let metadata = match source.metadata(digest) {
+        Ok(Some(metadata)) => metadata,
+        Ok(None) => return Reply::error(404, &format!("Unknown sequence: {}", digest)),
+        Err(e) => return Reply::error(500, &format!("{:#}", e)),
+    };
+    if want_metadata {
+        return Reply::json(
+            200,
+            format!(
+                "{{\"metadata\": {{\"md5\": \"{}\", \"ga4gh\": \"{}\", \"length\": {}, \
+                 \"aliases\": []}}}}",
+                metadata.md5, metadata.sha512t24u, metadata.length
+            ),
+        );
+    }
+    let (start, end) = match parse_query(query) {
+        Ok(query) => query,
+        Err(reply) => return reply,
+    };
+    let (start, end, status) = match (start, end, range) {
+        (None, None, None) => (0, metadata.length, 200),
+        (_, _, Some(range)) if start.is_some() || end.is_some() => {
+            return Reply::error(
+                400,
+                &format!("Range {} given together with start or end", range),
+            )
+        }
+        (_, _, Some(range)) => match parse_range(range) {
+            Ok((first, _)) if first >= metadata.length => {
+                return Reply::error(416, "Range starts beyond the end of the sequence")
+            }
+            // As in HTTP, a range may extend beyond the end
+            Ok((first, last)) => (first, (last + 1).min(metadata.length), 206),
+            Err(reply) => return reply,
+        },
+        (start, end, None) => {
+            let (start, end) = (start.unwrap_or(0), end.unwrap_or(metadata.length));
+            if start > end {
+                return Reply::error(501, "Circular sequences are not supported");
+            }
+            if end > metadata.length {
+                return Reply::error(416, "Range beyond the end of the sequence");
+            }
+            (start, end, 200)
+        }
+    };
+    match source.read(digest, start, end - start) {
+        Ok(body) => Reply {
+            status,
+            content_type: SEQUENCE_TYPE,
+            body,
+        },
+        Err(e) => Reply::error(500, &format!("{:#}", e)),
+    }
+}
+
+fn handle(source: &dyn SequenceSource, request: Request) {
+    let range = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Range"))
+        .map(|header| header.value.as_str().to_string());
+    let reply = respond(source, request.method(), request.url(), range.as_deref());
+    let content_type = Header::from_bytes("Content-Type", reply.content_type).unwrap();
+    let response = Response::from_data(reply.body)
+        .with_status_code(reply.status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to send refget response: {}", e);
+    }
+}
+
+/// An embedded HTTP server for the GA4GH refget protocol, answering requests on a pool of
+/// threads until it is stopped.
+pub(crate) struct RefgetServer {
+    server: Arc<Server>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl RefgetServer {
+    /// Listen on `address`, e.g., `127.0.0.1:0` for a free port, and answer requests with
+    /// `num_threads` threads.
+    pub(crate) fn start(
+        address: &str,
+        num_threads: usize,
+        source: Arc<dyn SequenceSource>,
+    ) -> Result<Self> {
+        if num_threads == 0 {
+            bail!("The refget server needs at least one thread");
+        }
+        let server = Arc::new(
+            Server::http(address).map_err(|e| anyhow!("Could not listen on {}: {}", address, e))?,
+        );
+        let workers = (0..num_threads)
+            .map(|_| {
+                let server = server.clone();
+                let source = source.clone();
+                std::thread::spawn(move || {
+                    // Fails once the server is unblocked by `stop`
+                    while let Ok(request) = server.recv() {
+                        handle(source.as_ref(), request);
+                    }
+                })
+            })
+            .collect();
+        let server = RefgetServer { server, workers };
+        info!("Serving refget on http://{}", server.address());
+        Ok(server)
+    }
+
+    pub(crate) fn address(&self) -> String {
+        self.server.server_addr().to_string()
+    }
+
+    /// Stop accepting requests and wait for the requests in progress.
+    pub(crate) fn stop(&mut self) {
+        for _ in 0..self.workers.len() {
+            self.server.unblock();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for RefgetServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Source;
+
+    impl SequenceSource for Source {
+        fn metadata(&self, digest: &str) -> Result<Option<SequenceMetadata>> {
+            Ok((digest == "SQ.a").then(|| SequenceMetadata {
+                md5: "m".to_string(),
+                sha512t24u: "SQ.a".to_string(),
+                length: 8,
+            }))
+        }
+
+        fn read(&self, _digest: &str, start: u64, length: u64) -> Result<Vec<u8>> {
+            Ok(b"ACGTACGT"[start as usize..][..length as usize].to_vec())
+        }
+    }
+
+    fn get(url: &str, range: Option<&str>) -> Reply {
+        respond(&Source, &Method::Get, url, range)
+    }
+
+    #[test]
+    fn test_respond() {
+        assert_eq!(get("/sequence/SQ.a", None).body, b"ACGTACGT");
+        assert_eq!(get("/sequence/SQ.a?start=2&end=5", None).body, b"GTA");
+        assert_eq!(get("/sequence/SQ.a?start=6", None).body, b"GT");
+        assert_eq!(get("/sequence/SQ.a?start=8", None).body, b"");
+        let reply = get("/sequence/SQ.a", Some("bytes=1-2"));
+        assert_eq!((reply.status, reply.body), (206, b"CG".to_vec()));
+        assert_eq!(get("/sequence/SQ.a", Some("bytes=6-100")).body, b"GT");
+        let metadata = get("/sequence/SQ.a/metadata", None);
+        assert_eq!(metadata.content_type, JSON_TYPE);
+        assert_eq!(
+            String::from_utf8(metadata.body).unwrap(),
+            "{\"metadata\": {\"md5\": \"m\", \"ga4gh\": \"SQ.a\", \"length\": 8, \"aliases\": []}}"
+        );
+        assert_eq!(get("/sequence/service-info", None).status, 200);
+
+        for (url, range, status) in [
+            ("/sequence/SQ.b", None, 404),
+            ("/other", None, 404),
+            ("/sequence/SQ.a?start=5&end=2", None, 501),
+            ("/sequence/SQ.a?end=9", None, 416),
+            ("/sequence/SQ.a?start=x", None, 400),
+            ("/sequence/SQ.a?start=1", Some("bytes=1-2"), 400),
+            ("/sequence/SQ.a", Some("bytes=2-1"), 400),
+            ("/sequence/SQ.a", Some("bytes=8-9"), 416),
+        ] {
+            assert_eq!(get(url, range).status, status, "{}", url);
+        }
+        assert_eq!(
+            respond(&Source, &Method::Post, "/sequence/SQ.a", None).status,
+            405
+        );
+    }
+}
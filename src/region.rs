@@ -0,0 +1,146 @@
+use anyhow::{bail, Context, Result};
+
+/// A region of a contig given as a samtools-style string `contig[:start[-[end]]]`, in which
+/// thousands separators are ignored, e.g., `chr1:1,000-2,000`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ParsedRegion {
+    pub(crate) contig: String,
+    /// 0-based start.
+    pub(crate) start: u64,
+    /// Exclusive end, or `None` for the rest of the contig.
+    pub(crate) end: Option<u64>,
+}
+
+impl ParsedRegion {
+    /// The start is 1-based with `one_based` and 0-based otherwise, and the end is included
+    /// with `end_inclusive`, i.e., samtools uses both and BED files neither. Contig names may
+    /// contain colons, so as in samtools, a region which is a contig by `is_contig` is the
+    /// whole contig, e.g., `HLA-A*01:01`.
+    pub(crate) fn parse(
+        region: &str,
+        one_based: bool,
+        end_inclusive: bool,
+        is_contig: impl Fn(&str) -> bool,
+    ) -> Result<Self> {
+        let split = region
+            .rsplit_once(':')
+            .filter(|(_, range)| is_range(range) && !is_contig(region));
+        let Some((contig, range)) = split else {
+            if region.is_empty() {
+                bail!("Empty region");
+            }
+            return Ok(ParsedRegion {
+                contig: region.to_string(),
+                start: 0,
+                end: None,
+            });
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (start, Some(end).filter(|end| !end.is_empty())),
+            None => (range, None),
+        };
+        let parse = |position: &str| -> Result<u64> {
+            position
+                .replace(',', "")
+                .parse()
+                .with_context(|| format!("Invalid position {:?} in region {:?}", position, region))
+        };
+        // Positions are converted to 0-based starts and exclusive ends
+        let offset = one_based as u64;
+        let start = parse(start)?.checked_sub(offset).with_context(|| {
+            format!("Region {:?} starts at 0, but positions are 1-based", region)
+        })?;
+        let end = end
+            .map(|end| -> Result<u64> {
+                (parse(end)? + end_inclusive as u64)
+                    .checked_sub(offset)
+                    .with_context(|| format!("Region {:?} ends before its start", region))
+            })
+            .transpose()?;
+        if end.is_some_and(|end| end < start) {
+            bail!("Region {:?} ends before its start", region);
+        }
+        Ok(ParsedRegion {
+            contig: contig.to_string(),
+            start,
+            end,
+        })
+    }
+}
+
+/// Whether `range` looks like `start[-[end]]`, with digits and commas only.
+fn is_range(range: &str) -> bool {
+    let is_position =
+        |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit() || b == b',');
+    match range.split_once('-') {
+        Some((start, end)) => is_position(start) && (end.is_empty() || is_position(end)),
+        None => is_position(range),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(contig: &str, start: u64, end: Option<u64>) -> ParsedRegion {
+        ParsedRegion {
+            contig: contig.to_string(),
+            start,
+            end,
+        }
+    }
+
+    fn parse(region: &str, one_based: bool) -> Result<ParsedRegion> {
+        // Both samtools and BED files
+        ParsedRegion::parse(region, one_based, one_based, |contig| {
+            contig == "HLA-A*01:01"
+        })
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(
+            parse("chr1:1,001-2,000", true).unwrap(),
+            region("chr1", 1000, Some(2000))
+        );
+        assert_eq!(
+            parse("chr1:1000-2000", false).unwrap(),
+            region("chr1", 1000, Some(2000))
+        );
+        assert_eq!(
+            parse("chr1:1001", true).unwrap(),
+            region("chr1", 1000, None)
+        );
+        assert_eq!(
+            parse("chr1:1001-", true).unwrap(),
+            region("chr1", 1000, None)
+        );
+        assert_eq!(parse("chr1", true).unwrap(), region("chr1", 0, None));
+        assert_eq!(
+            parse("HLA-A*01:01", true).unwrap(),
+            region("HLA-A*01:01", 0, None)
+        );
+        assert_eq!(
+            parse("HLA-A*01:01:5-6", true).unwrap(),
+            region("HLA-A*01:01", 4, Some(6))
+        );
+        // Without the contig, the suffix is a range
+        assert_eq!(
+            parse("HLA-B*01:01", true).unwrap(),
+            region("HLA-B*01", 0, None)
+        );
+        for invalid in ["", "chr1:0-10", "chr1:20-10"] {
+            assert!(parse(invalid, true).is_err());
+        }
+        let is_contig = |_: &str| false;
+        assert_eq!(
+            ParsedRegion::parse("chr1:1001-2001", true, false, is_contig).unwrap(),
+            region("chr1", 1000, Some(2000))
+        );
+        assert_eq!(
+            ParsedRegion::parse("chr1:1000-1999", false, true, is_contig).unwrap(),
+            region("chr1", 1000, Some(2000))
+        );
+        assert!(ParsedRegion::parse("chr1:1-0", true, false, is_contig).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+#[cfg(feature = "remote")]
+mod store;
+
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::metrics::TrackedFile;
+
+/// File in the mirror of a remote root with the versions of the mirrored files, to fetch only
+/// changed files again.
+const STATE_FILE: &str = ".remote-state.tsv";
+
+/// A data file which is read through its BGZF index, either local or in an object store.
+pub(crate) trait DataFile: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> DataFile for T {}
+
+/// Whether `root` is the URL of an object store, e.g., `s3://bucket/genomes`, instead of a
+/// local directory.
+pub(crate) fn is_remote(root: &str) -> bool {
+    root.contains("://")
+}
+
+/// Open a data file below a local or remote root. Remote files are read by range requests
+/// through a block cache.
+pub(crate) fn open_data(path: &Path) -> Result<Box<dyn DataFile>> {
+    let url = path.to_string_lossy();
+    if !is_remote(&url) {
+        return Ok(Box::new(TrackedFile::new(File::open(path)?)));
+    }
+    #[cfg(feature = "remote")]
+    {
+        Ok(Box::new(TrackedFile::new(store::RemoteFile::open(&url)?)))
+    }
+    #[cfg(not(feature = "remote"))]
+    {
+        anyhow::bail!(
+            "Cannot open {}, fastar_loader was built without the remote feature",
+            url
+        )
+    }
+}
+
+/// Copy the index files of a remote root to a local mirror, in which the data files are empty
+/// placeholders, such that the mirror can be indexed like a local root. Only changed files are
+/// fetched again.
+pub(crate) fn mirror(url: &str, suffix: &str, index_suffixes: &[&str]) -> Result<PathBuf> {
+    #[cfg(feature = "remote")]
+    {
+        store::mirror(url, &mirror_dir(url), suffix, index_suffixes)
+    }
+    #[cfg(not(feature = "remote"))]
+    {
+        let _ = (suffix, index_suffixes);
+        anyhow::bail!(
+            "Cannot load {}, fastar_loader was built without the remote feature",
+            url
+        )
+    }
+}
+
+/// Whether `dir` is the local mirror of a remote root, whose data files are empty placeholders,
+/// see `mirror`.
+pub(crate) fn is_mirror(dir: &Path) -> bool {
+    dir.join(STATE_FILE).is_file()
+}
+
+/// Local directory of the mirror of `url`, below `$FASTAR_LOADER_CACHE_DIR` or the user's cache
+/// directory.
+#[cfg_attr(not(feature = "remote"), allow(dead_code))]
+fn mirror_dir(url: &str) -> PathBuf {
+    let base = std::env::var_os("FASTAR_LOADER_CACHE_DIR")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("XDG_CACHE_HOME").map(|dir| Path::new(&dir).join("fastar-loader"))
+        })
+        .or_else(|| {
+            std::env::var_os("HOME").map(|dir| Path::new(&dir).join(".cache/fastar-loader"))
+        })
+        .unwrap_or_else(|| std::env::temp_dir().join("fastar-loader"));
+    let url = url.trim_end_matches('/');
+    base.join(format!(
+        "{:016x}",
+        xxhash_rust::xxh3::xxh3_64(url.as_bytes())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{ArchivedMap, FastaMap};
+    use crate::progress::Progress;
+    use crate::storage::{ArchiveStorage, MemoryStorage};
+    use crate::util::{copy_test_data, NameSelection};
+
+    #[test]
+    fn test_open_local() {
+        assert!(is_remote("s3://bucket/genomes"));
+        assert!(!is_remote("/data/genomes"));
+        let mut file = open_data(Path::new("test-data/assemblies/GCA_000146045.2.fna.gz")).unwrap();
+        let mut magic = [0u8; 2];
+        file.read_exact(&mut magic).unwrap();
+        assert_eq!(magic, [0x1f, 0x8b]);
+        assert_eq!(mirror_dir("s3://a/b/"), mirror_dir("s3://a/b"));
+        assert_ne!(mirror_dir("s3://a/b"), mirror_dir("s3://a/c"));
+    }
+
+    #[test]
+    fn test_build_mirror() {
+        let dir = copy_test_data("test-data/assemblies");
+        for entry in std::fs::read_dir(dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            if path.to_string_lossy().ends_with(".fna.gz") {
+                File::create(&path).unwrap();
+            }
+        }
+        let build = || {
+            FastaMap::build(
+                dir.path().to_str().unwrap(),
+                true,
+                0,
+                false,
+                false,
+                false,
+                None,
+                &Progress::default(),
+                &NameSelection::default(),
+            )
+        };
+        // Empty data files are truncated in a local root, but placeholders in a mirror
+        assert!(build().is_err());
+        std::fs::write(dir.path().join(STATE_FILE), "").unwrap();
+        let storage = ArchiveStorage::<FastaMap, MemoryStorage>::new(build().unwrap()).unwrap();
+        assert!(storage.as_ref().contains("GCA_000146045.2"));
+    }
+}
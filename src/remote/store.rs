@@ -0,0 +1,350 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::{bail, Context, Result};
+use futures::TryStreamExt;
+use log::info;
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::http::HttpBuilder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, ObjectStoreScheme};
+use tokio::runtime::Runtime;
+use url::Url;
+
+use crate::metrics::METRICS;
+use crate::remote::STATE_FILE;
+
+/// Size of the blocks which are fetched by range requests. A BGZF block is at most 64 KiB, so a
+/// block covers many of them, which suits the mostly sequential reads.
+const BLOCK_SIZE: u64 = 1 << 20;
+
+/// Number of blocks kept in memory, shared by all remote files of the process.
+const CACHE_BLOCKS: usize = 256;
+
+/// Recently read blocks by URL and block number, evicting the least recently used.
+#[derive(Default)]
+struct BlockCache {
+    blocks: HashMap<(Arc<str>, u64), (Arc<[u8]>, u64)>,
+    clock: u64,
+}
+
+impl BlockCache {
+    fn get(&mut self, key: &(Arc<str>, u64)) -> Option<Arc<[u8]>> {
+        self.clock += 1;
+        let (block, last_used) = self.blocks.get_mut(key)?;
+        *last_used = self.clock;
+        Some(block.clone())
+    }
+
+    fn insert(&mut self, key: (Arc<str>, u64), block: Arc<[u8]>) {
+        if self.blocks.len() >= CACHE_BLOCKS {
+            let oldest = self
+                .blocks
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.blocks.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.blocks.insert(key, (block, self.clock));
+    }
+}
+
+/// State shared by all remote files: the runtime for the async object store API, one client
+/// per bucket, the sizes of the files and the block cache.
+struct Remote {
+    runtime: Runtime,
+    stores: Mutex<HashMap<String, Arc<dyn ObjectStore>>>,
+    sizes: Mutex<HashMap<String, u64>>,
+    blocks: Mutex<BlockCache>,
+}
+
+fn remote() -> &'static Remote {
+    static REMOTE: OnceLock<Remote> = OnceLock::new();
+    REMOTE.get_or_init(|| Remote {
+        runtime: tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .expect("Failed to start the runtime for remote reads"),
+        stores: Mutex::default(),
+        sizes: Mutex::default(),
+        blocks: Mutex::default(),
+    })
+}
+
+/// The store of the bucket of `url` and the path of `url` in it. Credentials are read from the
+/// usual environment variables of each provider, e.g., `AWS_ACCESS_KEY_ID`.
+fn store_for(url: &str) -> Result<(Arc<dyn ObjectStore>, ObjectPath)> {
+    let parsed = Url::parse(url).with_context(|| format!("Invalid URL {}", url))?;
+    let (scheme, path) = ObjectStoreScheme::parse(&parsed)
+        .with_context(|| format!("Unsupported object store URL {}", url))?;
+    let bucket = format!("{}://{}", parsed.scheme(), parsed.authority());
+    let mut stores = remote().stores.lock().unwrap();
+    if let Some(store) = stores.get(&bucket) {
+        return Ok((store.clone(), path));
+    }
+    let store: Arc<dyn ObjectStore> = match scheme {
+        ObjectStoreScheme::AmazonS3 => Arc::new(AmazonS3Builder::from_env().with_url(url).build()?),
+        ObjectStoreScheme::GoogleCloudStorage => Arc::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_url(url)
+                .build()?,
+        ),
+        ObjectStoreScheme::MicrosoftAzure => {
+            Arc::new(MicrosoftAzureBuilder::from_env().with_url(url).build()?)
+        }
+        // Static file servers, which only support reading single files
+        ObjectStoreScheme::Http => Arc::new(HttpBuilder::new().with_url(&bucket).build()?),
+        ObjectStoreScheme::Local => Arc::new(LocalFileSystem::new()),
+        _ => bail!("Unsupported object store URL {}", url),
+    };
+    stores.insert(bucket, store.clone());
+    Ok((store, path))
+}
+
+/// A remote file, which fetches the blocks containing the read bytes.
+pub(super) struct RemoteFile {
+    url: Arc<str>,
+    store: Arc<dyn ObjectStore>,
+    path: ObjectPath,
+    size: u64,
+    position: u64,
+}
+
+impl RemoteFile {
+    pub(super) fn open(url: &str) -> Result<Self> {
+        let (store, path) = store_for(url)?;
+        let size = remote().sizes.lock().unwrap().get(url).copied();
+        let size = match size {
+            Some(size) => size,
+            None => {
+                let meta = remote()
+                    .runtime
+                    .block_on(store.head(&path))
+                    .with_context(|| format!("Could not open {}", url))?;
+                remote()
+                    .sizes
+                    .lock()
+                    .unwrap()
+                    .insert(url.to_string(), meta.size);
+                meta.size
+            }
+        };
+        Ok(RemoteFile {
+            url: url.into(),
+            store,
+            path,
+            size,
+            position: 0,
+        })
+    }
+
+    fn block(&self, index: u64) -> Result<Arc<[u8]>> {
+        let key = (self.url.clone(), index);
+        let cached = remote().blocks.lock().unwrap().get(&key);
+        METRICS.record_cache(cached.is_some());
+        if let Some(block) = cached {
+            return Ok(block);
+        }
+        let start = index * BLOCK_SIZE;
+        let end = (start + BLOCK_SIZE).min(self.size);
+        let bytes = remote()
+            .runtime
+            .block_on(self.store.get_range(&self.path, start..end))
+            .with_context(|| format!("Could not read {}", self.url))?;
+        let block: Arc<[u8]> = Arc::from(bytes.as_ref());
+        remote().blocks.lock().unwrap().insert(key, block.clone());
+        Ok(block)
+    }
+}
+
+impl Read for RemoteFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let block = self
+            .block(self.position / BLOCK_SIZE)
+            .map_err(io::Error::other)?;
+        let offset = (self.position % BLOCK_SIZE) as usize;
+        let n = buf.len().min(block.len() - offset);
+        buf[..n].copy_from_slice(&block[offset..offset + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for RemoteFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek before the start of the file",
+            ));
+        };
+        self.position = position;
+        Ok(position)
+    }
+}
+
+fn read_state(path: &Path) -> Result<HashMap<String, String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut state = HashMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let (relative, version) = line.split_once('\t').unwrap_or((&line, ""));
+        state.insert(relative.to_string(), version.to_string());
+    }
+    Ok(state)
+}
+
+/// Mirror the files of `url` ending with `suffix` (as empty placeholders) or one of
+/// `index_suffixes` to `dir`, see `remote::mirror`.
+pub(super) fn mirror(
+    url: &str,
+    dir: &Path,
+    suffix: &str,
+    index_suffixes: &[&str],
+) -> Result<PathBuf> {
+    let (store, prefix) = store_for(url)?;
+    let objects = remote()
+        .runtime
+        .block_on(store.list(Some(&prefix)).try_collect::<Vec<_>>())
+        .with_context(|| format!("Could not list {}", url))?;
+    fs::create_dir_all(dir)?;
+    let state_path = dir.join(STATE_FILE);
+    let old_state = read_state(&state_path)?;
+    let mut state = BTreeMap::new();
+    let mut fetched = 0;
+    for meta in objects {
+        let Some(parts) = meta.location.prefix_match(&prefix) else {
+            continue;
+        };
+        let relative = parts
+            .map(|part| part.as_ref().to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        let local = dir.join(&relative);
+        let version = if relative.ends_with(suffix) {
+            // The size saves a request when the file is opened for reading
+            let data_url = Path::new(url).join(&relative);
+            remote()
+                .sizes
+                .lock()
+                .unwrap()
+                .insert(data_url.to_string_lossy().into_owned(), meta.size);
+            if !local.exists() {
+                fs::create_dir_all(local.parent().unwrap())?;
+                File::create(&local)?;
+            }
+            String::new()
+        } else if index_suffixes.iter().any(|s| relative.ends_with(s)) {
+            let version = meta.e_tag.clone().unwrap_or_else(|| {
+                format!("{}:{}", meta.size, meta.last_modified.timestamp_millis())
+            });
+            if old_state.get(&relative) != Some(&version) || !local.exists() {
+                let bytes = remote()
+                    .runtime
+                    .block_on(async { store.get(&meta.location).await?.bytes().await })
+                    .with_context(|| format!("Could not fetch {}", meta.location))?;
+                fs::create_dir_all(local.parent().unwrap())?;
+                let partial = local.with_extension("partial");
+                fs::write(&partial, &bytes)?;
+                fs::rename(&partial, &local)?;
+                fetched += 1;
+            }
+            version
+        } else {
+            continue;
+        };
+        state.insert(relative, version);
+    }
+    // Files which were removed remotely
+    for relative in old_state.keys().filter(|r| !state.contains_key(*r)) {
+        let _ = fs::remove_file(dir.join(relative));
+    }
+    let mut file = File::create(&state_path)?;
+    for (relative, version) in &state {
+        writeln!(file, "{}\t{}", relative, version)?;
+    }
+    info!(
+        "Mirrored {} files of {} to {}, {} of them changed",
+        state.len(),
+        url,
+        dir.display(),
+        fetched
+    );
+    Ok(dir.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = (0..3 * BLOCK_SIZE + 10)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        fs::write(dir.path().join("data.bin"), &data).unwrap();
+        let url = format!("file://{}/data.bin", dir.path().display());
+        let mut file = RemoteFile::open(&url).unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, data);
+        // A read across a block boundary
+        file.seek(SeekFrom::Start(BLOCK_SIZE - 5)).unwrap();
+        let mut buf = [0u8; 10];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[BLOCK_SIZE as usize - 5..][..10]);
+        assert_eq!(file.seek(SeekFrom::End(-3)).unwrap(), data.len() as u64 - 3);
+        assert!(file.seek(SeekFrom::Current(-(data.len() as i64))).is_err());
+    }
+
+    #[test]
+    fn test_mirror() {
+        let remote_dir = tempfile::tempdir().unwrap();
+        let root = remote_dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        for name in ["a", "sub/b"] {
+            fs::write(root.join(format!("{}.fna.gz", name)), b"data").unwrap();
+            fs::write(root.join(format!("{}.fna.gz.fai", name)), b"fai").unwrap();
+        }
+        fs::write(root.join("other.txt"), b"other").unwrap();
+        let url = format!("file://{}", root.display());
+        let mirror_dir = tempfile::tempdir().unwrap();
+        let dir = mirror(&url, mirror_dir.path(), ".fna.gz", &[".fna.gz.fai"]).unwrap();
+        assert_eq!(fs::read(dir.join("sub/b.fna.gz.fai")).unwrap(), b"fai");
+        assert_eq!(fs::read(dir.join("a.fna.gz")).unwrap(), b"");
+        assert!(!dir.join("other.txt").exists());
+        assert!(crate::remote::is_mirror(&dir));
+
+        // Changed and removed files are updated
+        fs::write(root.join("a.fna.gz.fai"), b"changed").unwrap();
+        fs::remove_file(root.join("sub/b.fna.gz")).unwrap();
+        fs::remove_file(root.join("sub/b.fna.gz.fai")).unwrap();
+        mirror(&url, mirror_dir.path(), ".fna.gz", &[".fna.gz.fai"]).unwrap();
+        assert_eq!(fs::read(dir.join("a.fna.gz.fai")).unwrap(), b"changed");
+        assert!(!dir.join("sub/b.fna.gz").exists());
+        assert!(!dir.join("sub/b.fna.gz.fai").exists());
+    }
+}
@@ -0,0 +1,182 @@
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use log::warn;
+
+use crate::error::Error;
+use crate::metrics::METRICS;
+
+/// Retries of the reads of data files on transient errors, e.g., stale file handles of network
+/// filesystems such as NFS or Lustre.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RetryPolicy {
+    /// Attempts of each read, including the first one.
+    attempts: u32,
+    /// Delay before the first retry, which is multiplied by `backoff` for every further retry.
+    delay: Duration,
+    backoff: f64,
+    /// OS error codes which are retried.
+    errors: Vec<i32>,
+}
+
+/// The policy of all maps of the process, without retries if not set.
+static POLICY: RwLock<Option<RetryPolicy>> = RwLock::new(None);
+
+impl RetryPolicy {
+    /// A policy of `attempts` attempts with `delay` seconds before the first retry, retrying
+    /// errors of the classes `errors`, given by names such as `ESTALE`.
+    pub(crate) fn new(attempts: u32, delay: f64, backoff: f64, errors: &[String]) -> Result<Self> {
+        if attempts == 0 {
+            bail!(Error::InvalidOptions(
+                "attempts must be positive".to_string()
+            ));
+        }
+        let Ok(delay) = Duration::try_from_secs_f64(delay) else {
+            bail!(Error::InvalidOptions(format!("Invalid delay: {}", delay)));
+        };
+        if !(1.0..f64::INFINITY).contains(&backoff) {
+            bail!(Error::InvalidOptions(format!(
+                "Invalid backoff: {}, expected at least 1",
+                backoff
+            )));
+        }
+        let errors = errors
+            .iter()
+            .map(|name| {
+                error_code(name).ok_or_else(|| {
+                    Error::InvalidOptions(format!("Unknown error class: {}", name)).into()
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(RetryPolicy {
+            attempts,
+            delay,
+            backoff,
+            errors,
+        })
+    }
+
+    fn is_retriable(&self, e: &anyhow::Error) -> bool {
+        e.chain()
+            .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .filter_map(std::io::Error::raw_os_error)
+            .any(|code| self.errors.contains(&code))
+    }
+
+    /// The delay after the failed attempt `attempt`, counted from 1.
+    fn delay_after(&self, attempt: u32) -> Duration {
+        let factor = self.backoff.powi(attempt as i32 - 1);
+        Duration::try_from_secs_f64(self.delay.as_secs_f64() * factor).unwrap_or(Duration::MAX)
+    }
+}
+
+/// The OS error code of an error class, for the classes which may be transient.
+fn error_code(name: &str) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        Some(match name {
+            "EAGAIN" => libc::EAGAIN,
+            "ECONNRESET" => libc::ECONNRESET,
+            "EHOSTDOWN" => libc::EHOSTDOWN,
+            "EINTR" => libc::EINTR,
+            "EIO" => libc::EIO,
+            "ENOTCONN" => libc::ENOTCONN,
+            "ESTALE" => libc::ESTALE,
+            "ETIMEDOUT" => libc::ETIMEDOUT,
+            _ => return None,
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = name;
+        None
+    }
+}
+
+/// Set the policy of all maps of the process, or disable retries with `None`.
+pub(crate) fn set_policy(policy: Option<RetryPolicy>) {
+    *POLICY.write().unwrap() = policy;
+}
+
+/// Run `read` of the data file `path`, and retry it by the policy while it fails with a
+/// retriable error. Each retry is logged and counted in `METRICS`. `read` must open the file
+/// itself, as a stale handle cannot be read again.
+pub(crate) fn with_retry<T>(path: &Path, mut read: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        let e = match read() {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        // The policy is only looked up on errors, to keep reads free of the lock
+        let (attempts, delay) = match POLICY.read().unwrap().as_ref() {
+            Some(policy) if attempt < policy.attempts && policy.is_retriable(&e) => {
+                (policy.attempts, policy.delay_after(attempt))
+            }
+            _ => return Err(e),
+        };
+        warn!(
+            "Attempt {} of {} to read {} failed, retrying in {:?}: {:#}",
+            attempt,
+            attempts,
+            path.display(),
+            delay,
+            e
+        );
+        METRICS.record_retry();
+        std::thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_retry() {
+        let path = Path::new("test.fna.gz");
+        let names = ["ESTALE".to_string()];
+        let policy = RetryPolicy::new(3, 0.5, 2.0, &names).unwrap();
+        assert_eq!(policy.delay_after(1), Duration::from_millis(500));
+        assert_eq!(policy.delay_after(3), Duration::from_secs(2));
+        assert!(RetryPolicy::new(0, 0.0, 2.0, &names).is_err());
+        assert!(RetryPolicy::new(3, -1.0, 2.0, &names).is_err());
+        assert!(RetryPolicy::new(3, 0.0, 0.5, &names).is_err());
+        assert!(RetryPolicy::new(3, 0.0, 2.0, &["ENOENT".to_string()]).is_err());
+        set_policy(Some(RetryPolicy::new(3, 0.0, 2.0, &names).unwrap()));
+
+        // Fails twice with a stale handle, then succeeds on the last attempt
+        let mut calls = 0;
+        let result = with_retry(path, || {
+            calls += 1;
+            if calls < 3 {
+                return Err(std::io::Error::from_raw_os_error(libc::ESTALE).into());
+            }
+            Ok(calls)
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Other errors are not retried
+        let mut calls = 0;
+        let result = with_retry(path, || -> Result<()> {
+            calls += 1;
+            Err(std::io::Error::from_raw_os_error(libc::ENOENT).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        // Give up after the last attempt
+        let mut calls = 0;
+        let result = with_retry(path, || -> Result<()> {
+            calls += 1;
+            Err(std::io::Error::from_raw_os_error(libc::ESTALE).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+        set_policy(None);
+    }
+}
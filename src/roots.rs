@@ -0,0 +1,993 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use log::info;
+use rkyv::api::high::HighValidator;
+use rkyv::bytecheck::CheckBytes;
+use rkyv::de::Pool;
+use rkyv::ser::writer::IoWriter;
+use rkyv::util::AlignedVec;
+use rkyv::{rancor, Portable, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::cache::MapBuilder;
+use crate::error::Error;
+use crate::index::{ArchivedMap, ConflictPolicy};
+use crate::progress::Skipped;
+use crate::remote;
+use crate::storage::{
+    read_header, write_direct, ArchiveStorage, ChecksumAlgorithm, DynamicStorage, MemoryStorage,
+    MmapStorage, Rejection, Schema, ShmemStorage,
+};
+use crate::util::name_path;
+
+/// Maps of one or more root directories. The names of each root are namespaced by its prefix.
+pub(crate) struct Roots<T> {
+    roots: Vec<Root<T>>,
+    /// Prefixed names skipped while building the maps, with the reason for skipping them.
+    skipped: Vec<Skipped>,
+}
+
+struct Root<T> {
+    prefix: String,
+    path: String,
+    /// Shared by roots derived from this one which leave it unchanged, see `with_changed`.
+    storage: Arc<DynamicStorage<T>>,
+}
+
+/// How to combine the names of two maps.
+pub(crate) enum UnionPolicy {
+    /// Merge maps of the same root, handling duplicate names with the given policy.
+    Merge(ConflictPolicy),
+    /// Namespace the names of both maps by the given prefixes.
+    Prefix(String, String),
+}
+
+/// Summary of loaded roots, see `Roots::stats`.
+pub(crate) struct Stats {
+    pub(crate) num_names: usize,
+    pub(crate) num_contigs: Option<usize>,
+    pub(crate) total_length: Option<u64>,
+    pub(crate) archive_size: Option<u64>,
+    pub(crate) storage_method: String,
+    pub(crate) cache_paths: Vec<String>,
+}
+
+/// Exported handle of a single root: prefix, path and storage handle.
+pub(crate) type RootHandle = (String, String, Vec<u8>);
+
+/// Ensure that every name belongs to at most one root, i.e., no prefix is a prefix of another.
+pub(crate) fn check_prefixes(prefixes: &[&str]) -> Result<()> {
+    if prefixes.is_empty() {
+        bail!("At least one root is required");
+    }
+    for (i, a) in prefixes.iter().enumerate() {
+        for b in &prefixes[i + 1..] {
+            if a.starts_with(b) || b.starts_with(a) {
+                bail!("Ambiguous root prefixes {:?} and {:?}", a, b);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Split a list of prefixed names into the names of each root, without prefix.
+/// Names which do not belong to any root are reported as an error.
+pub(crate) fn split_names(
+    prefixes: &[&str],
+    names: Option<Vec<String>>,
+) -> Result<Vec<Option<Vec<String>>>> {
+    let Some(names) = names else {
+        return Ok(vec![None; prefixes.len()]);
+    };
+    let mut split = vec![Vec::new(); prefixes.len()];
+    for name in names {
+        let (root, local_name) = prefixes
+            .iter()
+            .enumerate()
+            .find_map(|(i, prefix)| name.strip_prefix(prefix).map(|local| (i, local)))
+            .ok_or_else(|| anyhow!("Name {} does not belong to any root", name))?;
+        split[root].push(local_name.to_string());
+    }
+    Ok(split.into_iter().map(Some).collect())
+}
+
+/// The group of a name: the directory of its path with a trailing slash, including the prefix
+/// of its root, or the empty string for names directly in an unprefixed root.
+fn group_of(name: &str) -> &str {
+    name.rfind('/').map_or("", |i| &name[..=i])
+}
+
+/// A group given with or without trailing slash, such that `a` does not match `ab/`.
+fn normalize_group(group: &str) -> String {
+    if group.is_empty() || group.ends_with('/') {
+        group.to_string()
+    } else {
+        format!("{}/", group)
+    }
+}
+
+/// Directories which contain some of `names` at any depth, with a trailing slash and sorted,
+/// e.g., `clade/` and `clade/genus/` for `clade/genus/genome`.
+pub(crate) fn groups(names: &[String]) -> Vec<String> {
+    let mut groups = BTreeSet::new();
+    for name in names {
+        let mut group = group_of(name);
+        // The parents of a known group are known as well
+        while !group.is_empty() && groups.insert(group.to_string()) {
+            group = group_of(&group[..group.len() - 1]);
+        }
+    }
+    groups.into_iter().collect()
+}
+
+/// Names of `names` in `group` or one of its subdirectories.
+pub(crate) fn names_in_group(names: &[String], group: &str) -> Vec<String> {
+    let group = normalize_group(group);
+    names
+        .iter()
+        .filter(|name| group_of(name).starts_with(&group))
+        .cloned()
+        .collect()
+}
+
+/// Weights of names such that each group of `weights` is sampled with its weight, split
+/// equally between its names, e.g., to sample clades equally regardless of their number of
+/// genomes. Names outside of the groups get no weight. Groups must not contain each other.
+pub(crate) fn group_weights(
+    names: &[String],
+    weights: &HashMap<String, f64>,
+) -> Result<HashMap<String, f64>> {
+    let groups: Vec<(String, f64)> = weights
+        .iter()
+        .map(|(group, &weight)| (normalize_group(group), weight))
+        .collect();
+    for (i, (a, _)) in groups.iter().enumerate() {
+        for (b, _) in &groups[i + 1..] {
+            if a.starts_with(b.as_str()) || b.starts_with(a.as_str()) {
+                bail!(Error::InvalidOptions(format!(
+                    "Groups {:?} and {:?} overlap",
+                    a, b
+                )));
+            }
+        }
+    }
+    let mut name_weights = HashMap::new();
+    for (group, weight) in groups {
+        let members = names_in_group(names, &group);
+        if members.is_empty() {
+            bail!(Error::InvalidOptions(format!(
+                "Group {:?} has no names",
+                group
+            )));
+        }
+        let share = weight / members.len() as f64;
+        name_weights.extend(members.into_iter().map(|name| (name, share)));
+    }
+    Ok(name_weights)
+}
+
+impl<T> Roots<T>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T::Archived:
+        Sync + Send + 'static + Portable + for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
+    T: Sync + Send + 'static,
+    T: Schema,
+    T::Archived: ArchivedMap,
+{
+    /// Combine loaded storages, given as (prefix, root path, storage).
+    pub(crate) fn new(roots: Vec<(String, String, DynamicStorage<T>)>) -> Result<Self> {
+        check_prefixes(
+            &roots
+                .iter()
+                .map(|(prefix, _, _)| prefix.as_str())
+                .collect::<Vec<_>>(),
+        )?;
+        Ok(Roots {
+            roots: roots
+                .into_iter()
+                .map(|(prefix, path, storage)| Root {
+                    prefix,
+                    path,
+                    storage: Arc::new(storage),
+                })
+                .collect(),
+            skipped: Vec::new(),
+        })
+    }
+
+    /// Attach the names skipped while building the maps, for the build report.
+    pub(crate) fn with_skipped(mut self, skipped: Vec<Skipped>) -> Self {
+        self.skipped = skipped;
+        self
+    }
+
+    pub(crate) fn skipped(&self) -> &[Skipped] {
+        &self.skipped
+    }
+
+    /// Find the archive containing `name`.
+    /// Returns the archive, the root path and the name within that root.
+    pub(crate) fn get<'a, 'n>(
+        &'a self,
+        name: &'n str,
+    ) -> Result<(&'a T::Archived, &'a str, &'n str)> {
+        let (root, local_name) = self
+            .roots
+            .iter()
+            .find_map(|root| name.strip_prefix(&root.prefix).map(|local| (root, local)))
+            .ok_or_else(|| Error::NameNotFound(name.to_string()))?;
+        let archive = root.storage.get(local_name)?;
+        Ok((archive, &root.path, local_name))
+    }
+
+    pub(crate) fn names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for root in &self.roots {
+            names.extend(
+                root.storage
+                    .names()?
+                    .into_iter()
+                    .map(|name| format!("{}{}", root.prefix, name)),
+            );
+        }
+        Ok(names)
+    }
+
+    pub(crate) fn num_names(&self) -> Result<usize> {
+        self.roots
+            .iter()
+            .map(|root| root.storage.names().map(|names| names.len()))
+            .sum()
+    }
+
+    /// Whether `name` is in any root, without loading or indexing its entry.
+    pub(crate) fn contains(&self, name: &str) -> Result<bool> {
+        for root in &self.roots {
+            if let Some(local_name) = name.strip_prefix(&root.prefix) {
+                if root.storage.contains(local_name)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Summary of the names, contigs and storages for `__repr__`. Contigs are only counted if
+    /// this does not load shards or index entries.
+    pub(crate) fn describe(&self) -> Result<String> {
+        let contigs = self
+            .roots
+            .iter()
+            .map(|root| root.storage.num_contigs())
+            .sum::<Result<Option<usize>>>()?
+            .map_or("?".to_string(), |contigs| contigs.to_string());
+        let storages = self
+            .roots
+            .iter()
+            .map(|root| match root.storage.cache_path() {
+                Some(path) => format!("{} at {:?}", root.storage.kind(), path),
+                None => root.storage.kind().to_string(),
+            })
+            .collect::<Vec<_>>();
+        Ok(format!(
+            "names={}, contigs={}, storage={}",
+            self.num_names()?,
+            contigs,
+            storages.join(" + ")
+        ))
+    }
+
+    /// Number of names, contigs and bases, size of the archives in bytes, storage method and
+    /// cache files of the roots. Counts and sizes are `None` if they would load shards or index
+    /// entries, see `DynamicStorage`.
+    pub(crate) fn stats(&self) -> Result<Stats> {
+        Ok(Stats {
+            num_names: self.num_names()?,
+            num_contigs: self
+                .roots
+                .iter()
+                .map(|root| root.storage.num_contigs())
+                .sum::<Result<_>>()?,
+            total_length: self
+                .roots
+                .iter()
+                .map(|root| root.storage.total_length())
+                .sum::<Result<_>>()?,
+            archive_size: self
+                .roots
+                .iter()
+                .map(|root| root.storage.archive_size())
+                .sum(),
+            storage_method: self.storage_method(),
+            cache_paths: self
+                .roots
+                .iter()
+                .filter_map(|root| root.storage.cache_path())
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+        })
+    }
+
+    /// Storage method of the roots, e.g., as chosen by `storage_method=auto`. Roots with
+    /// different methods are joined by ` + `.
+    pub(crate) fn storage_method(&self) -> String {
+        let mut kinds = self
+            .roots
+            .iter()
+            .map(|root| root.storage.kind())
+            .collect::<Vec<_>>();
+        kinds.dedup();
+        kinds.join(" + ")
+    }
+
+    /// Handles of all roots, or `None` if a root cannot be shared, see `DynamicStorage::export`.
+    pub(crate) fn export(&self) -> Result<Option<Vec<RootHandle>>> {
+        let mut handles = Vec::new();
+        for root in &self.roots {
+            let Some(handle) = root.storage.export(Path::new(&root.path))? else {
+                return Ok(None);
+            };
+            handles.push((root.prefix.clone(), root.path.clone(), handle));
+        }
+        Ok(Some(handles))
+    }
+
+    /// Attach to exported roots. Roots which were moved since are given as (prefix, path) in
+    /// `moved`. A few index files of each root are compared with its manifest, such that a
+    /// wrong root is rejected instead of yielding garbage reads.
+    pub(crate) fn import(
+        handles: Vec<RootHandle>,
+        validate: bool,
+        moved: Vec<(String, String)>,
+    ) -> Result<Self> {
+        if let Some((prefix, _)) = moved
+            .iter()
+            .find(|(prefix, _)| !handles.iter().any(|(other, _, _)| other == prefix))
+        {
+            bail!("The handle has no root with prefix {:?}", prefix);
+        }
+        let roots = handles
+            .into_iter()
+            .map(|(prefix, path, handle)| {
+                let path = match moved.iter().find(|(other, _)| *other == prefix) {
+                    Some((_, moved_path)) => moved_path.clone(),
+                    None => path,
+                };
+                let storage = DynamicStorage::import(handle, validate, Path::new(&path))?;
+                // The manifest of a remote root describes its local mirror
+                if !remote::is_remote(&path) {
+                    if let Some(reason) = storage.spot_check(Path::new(&path))? {
+                        bail!("Root {} does not match the handle: {}", path, reason);
+                    }
+                }
+                Ok((prefix, path, storage))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(roots)
+    }
+}
+
+impl<T> Roots<T>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T::Archived:
+        Sync + Send + 'static + Portable + for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
+    T::Archived: rkyv::Deserialize<T, rancor::Strategy<Pool, rancor::Error>>,
+    T: Sync + Send + 'static,
+    T: MapBuilder,
+    T::Archived: ArchivedMap,
+{
+    /// Number of contigs, total length and size of the data file of each name, from the
+    /// archives and the sizes in the file system, without opening the data files. The size is
+    /// `None` for missing files and remote roots.
+    pub(crate) fn summary(&self) -> Result<Vec<(String, usize, u64, Option<u64>)>> {
+        let mut summary = Vec::new();
+        for root in &self.roots {
+            for name in root.storage.names()? {
+                let contigs = root.storage.get(name)?.contigs(name)?;
+                let size = if remote::is_remote(&root.path) {
+                    None
+                } else {
+                    let path = name_path(Path::new(&root.path), &format!("{}{}", name, T::SUFFIX));
+                    std::fs::metadata(path).ok().map(|metadata| metadata.len())
+                };
+                summary.push((
+                    format!("{}{}", root.prefix, name),
+                    contigs.len(),
+                    contigs.iter().map(|(_, length)| length).sum(),
+                    size,
+                ));
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Hash of the contents of all entries as 32 hex digits, from their prefixed names and
+    /// checksums in name order. It is the same for the same index files and options, no matter
+    /// the storage, sharding, root paths or modification times, e.g., to record in experiment
+    /// metadata which index a run used. Loads all shards and indexes all entries of a lazy root.
+    pub(crate) fn content_hash(&self) -> Result<String> {
+        let mut names = self.names()?;
+        names.sort();
+        let mut hasher = Xxh3::new();
+        for name in &names {
+            let (archive, _, local_name) = self.get(name)?;
+            let checksum = archive
+                .checksum(local_name)
+                .ok_or_else(|| Error::NameNotFound(name.clone()))?;
+            hasher.update(&(name.len() as u64).to_le_bytes());
+            hasher.update(name.as_bytes());
+            hasher.update(&checksum.to_le_bytes());
+        }
+        Ok(format!("{:032x}", hasher.digest128()))
+    }
+
+    /// Combine the maps of `self` and `other` into new archives with the given storage method.
+    /// Roots with the same prefix are merged, which requires them to have the same path.
+    pub(crate) fn union(
+        &self,
+        other: &Self,
+        policy: &UnionPolicy,
+        storage_method: &str,
+    ) -> Result<Self> {
+        let (conflict_policy, prefixes) = match policy {
+            UnionPolicy::Merge(conflict_policy) => (*conflict_policy, ["", ""]),
+            UnionPolicy::Prefix(first, second) => {
+                (ConflictPolicy::Error, [first.as_str(), second.as_str()])
+            }
+        };
+        let mut maps: Vec<(String, String, T)> = Vec::new();
+        for (side, side_prefix) in [self, other].into_iter().zip(prefixes) {
+            for root in &side.roots {
+                let prefix = format!("{}{}", side_prefix, root.prefix);
+                let map = root.to_map()?;
+                match maps.iter().position(|(existing, _, _)| *existing == prefix) {
+                    Some(i) => {
+                        let (prefix, path, existing) = maps.remove(i);
+                        if path != root.path {
+                            bail!(
+                                "Cannot merge roots {} and {} with the same prefix {:?}, use prefixes to keep them apart",
+                                path,
+                                root.path,
+                                prefix
+                            );
+                        }
+                        maps.insert(i, (prefix, path, existing.merge(map, conflict_policy)?));
+                    }
+                    None => maps.push((prefix, root.path.clone(), map)),
+                }
+            }
+        }
+        let storages = maps
+            .into_iter()
+            .map(|(prefix, path, map)| {
+                let storage: DynamicStorage<T> = match storage_method {
+                    "memory" => ArchiveStorage::<T, MemoryStorage>::new(map)?.into(),
+                    "shmem" => ArchiveStorage::<T, ShmemStorage>::new(map)?.into(),
+                    _ => bail!(
+                        "Unsupported storage method for union: {}, use memory or shmem",
+                        storage_method
+                    ),
+                };
+                Ok((prefix, path, storage))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(storages)
+    }
+
+    /// Index the entries `names`, e.g., genomes added to a root since it was indexed, with the
+    /// options the root was built with, without re-indexing the other entries. Entries which
+    /// exist already are re-indexed.
+    pub(crate) fn with_added(&self, names: Vec<String>) -> Result<Self> {
+        self.with_changed(names, |map, path, names| map.add_entries(path, names))
+    }
+
+    /// Drop the entries `names`, e.g., of retired genomes, without re-indexing the others.
+    pub(crate) fn with_removed(&self, names: Vec<String>) -> Result<Self> {
+        self.with_changed(names, |map, _, names| map.remove_entries(names))
+    }
+
+    /// Apply `change` to the map of each root with some of the prefixed `names`, given the path
+    /// of the root and the names without prefix. A changed map replaces the cache file it was
+    /// loaded from, such that the next load of the root finds it up to date, and is loaded
+    /// with the same storage method. Unchanged roots keep their storage, e.g., the entries a lazy
+    /// root has indexed so far.
+    fn with_changed(
+        &self,
+        names: Vec<String>,
+        change: impl Fn(&mut T, &str, &[String]) -> Result<()>,
+    ) -> Result<Self> {
+        let prefixes: Vec<&str> = self.roots.iter().map(|root| root.prefix.as_str()).collect();
+        let split = split_names(&prefixes, Some(names))?;
+        let roots = self
+            .roots
+            .iter()
+            .zip(split)
+            .map(|(root, names)| {
+                let names = names.unwrap_or_default();
+                if names.is_empty() {
+                    return Ok(Root {
+                        prefix: root.prefix.clone(),
+                        path: root.path.clone(),
+                        storage: root.storage.clone(),
+                    });
+                }
+                let kind = root.storage.kind();
+                if kind == "sharded" || kind == "lazy" {
+                    bail!(Error::InvalidOptions(format!(
+                        "Entries of root {} cannot be added or removed with {} storage",
+                        root.path, kind
+                    )));
+                }
+                let mut map = root.to_map()?;
+                change(&mut map, &root.path, &names)?;
+                let storage: DynamicStorage<T> = match root.storage.cache_path() {
+                    Some(cache_path) => {
+                        let algorithm =
+                            ChecksumAlgorithm::from_id(read_header(cache_path)?.checksum_algorithm)
+                                .unwrap_or(ChecksumAlgorithm::Xxh3);
+                        info!("Writing cache to {}", cache_path.display());
+                        write_direct(&map, cache_path, algorithm)?;
+                        let rejected = |rejection: Rejection| {
+                            Error::CacheCorrupted(format!(
+                                "Newly written cache is rejected: {}",
+                                rejection
+                            ))
+                        };
+                        match kind {
+                            "memory" => ArchiveStorage::<T, MemoryStorage>::load(cache_path, true)?
+                                .map_err(rejected)?
+                                .into(),
+                            "shmem" => ArchiveStorage::<T, ShmemStorage>::load(cache_path, true)?
+                                .map_err(rejected)?
+                                .into(),
+                            _ => ArchiveStorage::<T, MmapStorage>::load(cache_path, true)?
+                                .map_err(rejected)?
+                                .into(),
+                        }
+                    }
+                    // E.g., loaded with `no_cache` or combined with `union`
+                    None => match kind {
+                        "shmem" => ArchiveStorage::<T, ShmemStorage>::new(map)?.into(),
+                        _ => ArchiveStorage::<T, MemoryStorage>::new(map)?.into(),
+                    },
+                };
+                Ok(Root {
+                    prefix: root.prefix.clone(),
+                    path: root.path.clone(),
+                    storage: Arc::new(storage),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Roots {
+            roots,
+            skipped: Vec::new(),
+        })
+    }
+
+    /// Write the map of a single root to `path` as a bundle, which can be imported on another
+    /// machine with the same files, see `cache::load`.
+    pub(crate) fn export_bundle(&self, path: &Path) -> Result<()> {
+        let [root] = self.roots.as_slice() else {
+            bail!(
+                "Only maps with a single root can be exported as a bundle, not {}",
+                self.roots.len()
+            );
+        };
+        write_direct(&root.to_map()?, path, ChecksumAlgorithm::Xxh3)
+    }
+}
+
+impl<T> Root<T>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T::Archived:
+        Sync + Send + 'static + Portable + for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
+    T::Archived: rkyv::Deserialize<T, rancor::Strategy<Pool, rancor::Error>>,
+    T: Sync + Send + 'static,
+    T: MapBuilder,
+    T::Archived: ArchivedMap,
+{
+    /// Deserialize the whole map of this root, combining all shards.
+    fn to_map(&self) -> Result<T> {
+        let mut map: Option<T> = None;
+        for archive in self.storage.archives()? {
+            let shard = rkyv::deserialize::<T, rancor::Error>(archive)?;
+            map = Some(match map {
+                None => shard,
+                Some(map) => map.merge(shard, ConflictPolicy::Error)?,
+            });
+        }
+        map.ok_or_else(|| anyhow!("Root {} has no archives", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::LoadOptions;
+    use crate::index::FastaMap;
+    use crate::progress::Progress;
+    use crate::storage::{type_specific_magic, ArchiveStorage, MemoryStorage};
+    use crate::util::{copy_test_data, NameSelection};
+
+    #[test]
+    fn test_prefixes() {
+        assert!(check_prefixes(&[""]).is_ok());
+        assert!(check_prefixes(&["hot/", "cold/"]).is_ok());
+        assert!(check_prefixes(&["", "cold/"]).is_err());
+        assert!(check_prefixes(&["a/", "a/b/"]).is_err());
+        assert!(check_prefixes(&[]).is_err());
+    }
+
+    #[test]
+    fn test_split_names() {
+        let prefixes = ["hot/", "cold/"];
+        assert_eq!(split_names(&prefixes, None).unwrap(), vec![None, None]);
+        let names = ["hot/a", "cold/b", "hot/c"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(
+            split_names(&prefixes, Some(names)).unwrap(),
+            vec![
+                Some(vec!["a".to_string(), "c".to_string()]),
+                Some(vec!["b".to_string()])
+            ]
+        );
+        assert!(split_names(&prefixes, Some(vec!["warm/a".to_string()])).is_err());
+    }
+
+    #[test]
+    fn test_groups() {
+        let names: Vec<String> = ["a/x/1", "a/x/2", "a/3", "ab/4", "5"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(groups(&names), vec!["a/", "a/x/", "ab/"]);
+        assert_eq!(names_in_group(&names, "a"), vec!["a/x/1", "a/x/2", "a/3"]);
+        assert_eq!(names_in_group(&names, "a/x/"), vec!["a/x/1", "a/x/2"]);
+        assert_eq!(names_in_group(&names, "").len(), 5);
+        assert!(names_in_group(&names, "b").is_empty());
+
+        let weights = HashMap::from([("a".to_string(), 1.0), ("ab/".to_string(), 2.0)]);
+        let name_weights = group_weights(&names, &weights).unwrap();
+        assert_eq!(name_weights.len(), 4);
+        assert_eq!(name_weights["a/x/1"], 1.0 / 3.0);
+        assert_eq!(name_weights["ab/4"], 2.0);
+        let overlapping = HashMap::from([("a/".to_string(), 1.0), ("a/x".to_string(), 1.0)]);
+        assert!(group_weights(&names, &overlapping).is_err());
+        let empty = HashMap::from([("b/".to_string(), 1.0)]);
+        assert!(group_weights(&names, &empty).is_err());
+    }
+
+    fn storage(dir: &tempfile::TempDir) -> DynamicStorage<FastaMap> {
+        let map = FastaMap::build(
+            dir.path().to_str().unwrap(),
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        DynamicStorage::from(ArchiveStorage::<FastaMap, MemoryStorage>::new(map).unwrap())
+    }
+
+    fn single_root(prefix: &str, dir: &tempfile::TempDir) -> Roots<FastaMap> {
+        Roots::new(vec![(
+            prefix.to_string(),
+            dir.path().to_str().unwrap().to_string(),
+            storage(dir),
+        )])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_content_hash() {
+        // Copies of a root have other paths and modification times, but the same contents
+        let first = copy_test_data("test-data/assemblies");
+        let second = copy_test_data("test-data/assemblies");
+        let hash = single_root("", &first).content_hash().unwrap();
+        assert_eq!(hash.len(), 32);
+        assert_eq!(hash, single_root("", &second).content_hash().unwrap());
+        assert_ne!(hash, single_root("hot/", &first).content_hash().unwrap());
+        std::fs::remove_file(second.path().join("GCA_000146045.2.fna.gz")).unwrap();
+        assert_ne!(hash, single_root("", &second).content_hash().unwrap());
+    }
+
+    #[test]
+    fn test_handle_fingerprint() {
+        let dir = copy_test_data("test-data/assemblies");
+        let other_dir = copy_test_data("test-data/assemblies");
+        std::fs::remove_file(other_dir.path().join("GCA_000146045.2.fna.gz")).unwrap();
+        let handle = storage(&dir).export(dir.path()).unwrap().unwrap();
+        let other_handle = storage(&other_dir)
+            .export(other_dir.path())
+            .unwrap()
+            .unwrap();
+        assert!(DynamicStorage::<FastaMap>::import(handle.clone(), true, dir.path()).is_ok());
+
+        // "Memory:", the fingerprint and a colon precede the archive. The fingerprint of one
+        // index with the archive of another is rejected.
+        let (prefix, archive) = handle.split_at(24);
+        assert_ne!(prefix, &other_handle[..24]);
+        let mut mixed = prefix.to_vec();
+        mixed.extend(&other_handle[24..]);
+        let error = DynamicStorage::<FastaMap>::import(mixed, true, dir.path())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("fingerprint"));
+        let mut missing = b"Memory:".to_vec();
+        missing.extend(archive);
+        assert!(DynamicStorage::<FastaMap>::import(missing, true, dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_truncated_mmap() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let load = |num_shards| {
+            let storage = crate::cache::load::<FastaMap>(
+                root,
+                ".fasta-map-cache",
+                None,
+                &LoadOptions {
+                    storage_method: "mmap".to_string(),
+                    num_shards,
+                    ..Default::default()
+                },
+                &Progress::default(),
+            )
+            .unwrap();
+            Roots::new(vec![(String::new(), root.to_string(), storage)]).unwrap()
+        };
+        let single = load(None);
+        let sharded = load(Some(2));
+        let name = "GCA_000146045.2";
+        assert!(single.contains(name).unwrap());
+        assert!(sharded.contains(name).unwrap());
+
+        // Every access to a truncated file fails instead of touching the mapping
+        for entry in std::fs::read_dir(root).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with(".fasta-map-cache")
+            {
+                File::options()
+                    .write(true)
+                    .open(&path)
+                    .unwrap()
+                    .set_len(0)
+                    .unwrap();
+            }
+        }
+        for roots in [&single, &sharded] {
+            let e = roots.names().unwrap_err();
+            assert!(matches!(
+                e.downcast_ref::<Error>(),
+                Some(Error::CacheCorrupted(_))
+            ));
+            assert!(roots.contains(name).is_err());
+            assert!(roots.num_names().is_err());
+            assert!(roots.stats().is_err());
+            assert!(roots.describe().is_err());
+        }
+        assert!(single.export().is_err());
+    }
+
+    #[test]
+    fn test_with_added_and_removed() {
+        let dir = copy_test_data("test-data/assemblies");
+        let root = dir.path().to_str().unwrap();
+        let storage = crate::cache::load::<FastaMap>(
+            root,
+            ".fasta-map-cache",
+            None,
+            &LoadOptions {
+                storage_method: "memory".to_string(),
+                // Leaves out the two shortest chromosomes of the yeast genome
+                min_contig_length: 300_000,
+                ..Default::default()
+            },
+            &Progress::default(),
+        )
+        .unwrap();
+        let roots = Roots::new(vec![(String::new(), root.to_string(), storage)]).unwrap();
+        let cache_path = Path::new(root).join(format!(
+            ".fasta-map-cache-{:016x}",
+            type_specific_magic::<FastaMap>()
+        ));
+        let is_fresh = || {
+            let archive = ArchiveStorage::<FastaMap, MemoryStorage>::load(&cache_path, true)
+                .unwrap()
+                .ok()
+                .unwrap();
+            let selection = NameSelection::default();
+            archive
+                .as_ref()
+                .stale_reason(root, &selection)
+                .unwrap()
+                .is_none()
+        };
+
+        // Retire a genome, whose entry is dropped from the cache file as well
+        let name = "GCA_000146045.2";
+        let stash = tempfile::tempdir().unwrap();
+        let files =
+            [".fna.gz", ".fna.gz.gzi", ".fna.gz.fai"].map(|suffix| format!("{}{}", name, suffix));
+        for file in &files {
+            std::fs::rename(dir.path().join(file), stash.path().join(file)).unwrap();
+        }
+        assert!(!is_fresh());
+        let removed = roots.with_removed(vec![name.to_string()]).unwrap();
+        assert!(!removed.contains(name).unwrap());
+        assert_eq!(removed.num_names().unwrap() + 1, roots.num_names().unwrap());
+        assert!(is_fresh());
+        assert!(removed.with_removed(vec![name.to_string()]).is_err());
+
+        // Adding it back indexes only its entry, with the options of the map
+        for file in &files {
+            std::fs::rename(stash.path().join(file), dir.path().join(file)).unwrap();
+        }
+        let added = removed.with_added(vec![name.to_string()]).unwrap();
+        assert_eq!(added.content_hash().unwrap(), roots.content_hash().unwrap());
+        assert!(is_fresh());
+        assert!(added.with_added(vec!["missing".to_string()]).is_err());
+        assert!(added.with_added(vec!["../outside".to_string()]).is_err());
+
+        // A lazy root is kept as it is if none of its entries change
+        let lazy = crate::cache::load::<FastaMap>(
+            root,
+            ".fasta-map-cache",
+            None,
+            &LoadOptions {
+                lazy: true,
+                ..Default::default()
+            },
+            &Progress::default(),
+        )
+        .unwrap();
+        let other = copy_test_data("test-data/assemblies");
+        let roots = Roots::new(vec![
+            ("lazy/".to_string(), root.to_string(), lazy),
+            (
+                "other/".to_string(),
+                other.path().to_str().unwrap().to_string(),
+                storage(&other),
+            ),
+        ])
+        .unwrap();
+        let removed = roots.with_removed(vec![format!("other/{}", name)]).unwrap();
+        assert!(removed.contains(&format!("lazy/{}", name)).unwrap());
+        assert!(!removed.contains(&format!("other/{}", name)).unwrap());
+        assert!(removed.get(&format!("lazy/{}", name)).is_ok());
+        assert!(roots.with_removed(vec![format!("lazy/{}", name)]).is_err());
+    }
+
+    #[test]
+    fn test_merged_roots() {
+        let hot = copy_test_data("test-data/assemblies");
+        let cold = copy_test_data("test-data/assemblies");
+        let roots = Roots::new(vec![
+            (
+                "hot/".to_string(),
+                hot.path().to_str().unwrap().to_string(),
+                storage(&hot),
+            ),
+            (
+                "cold/".to_string(),
+                cold.path().to_str().unwrap().to_string(),
+                storage(&cold),
+            ),
+        ])
+        .unwrap();
+        let names = roots.names().unwrap();
+        assert!(names.contains(&"hot/GCA_000146045.2".to_string()));
+        assert!(names.contains(&"cold/GCA_000146045.2".to_string()));
+
+        let (archive, root, name) = roots.get("cold/GCA_000146045.2").unwrap();
+        assert_eq!(root, cold.path().to_str().unwrap());
+        assert_eq!(name, "GCA_000146045.2");
+        assert!(!archive.contigs(name).unwrap().is_empty());
+        assert!(roots.get("GCA_000146045.2").is_err());
+    }
+
+    #[test]
+    fn test_union() {
+        let dir = copy_test_data("test-data/assemblies");
+        let other_dir = copy_test_data("test-data/assemblies");
+        let first = single_root("", &dir);
+        let second = single_root("", &dir);
+        let names = first.names().unwrap();
+
+        let policy = UnionPolicy::Merge(ConflictPolicy::Error);
+        assert!(first.union(&second, &policy, "memory").is_err());
+        let policy = UnionPolicy::Merge(ConflictPolicy::FirstWins);
+        let merged = first.union(&second, &policy, "memory").unwrap();
+        assert_eq!(merged.names().unwrap(), names);
+
+        // Different roots cannot share a prefix, as entries are resolved relative to the root
+        let other = single_root("", &other_dir);
+        assert!(first.union(&other, &policy, "memory").is_err());
+        let policy = UnionPolicy::Prefix("a/".to_string(), "b/".to_string());
+        let prefixed = first.union(&other, &policy, "memory").unwrap();
+        assert_eq!(prefixed.names().unwrap().len(), 2 * names.len());
+        let prefixed_name = format!("b/{}", names[0]);
+        let (_, root, name) = prefixed.get(&prefixed_name).unwrap();
+        assert_eq!(root, other_dir.path().to_str().unwrap());
+        assert_eq!(name, names[0]);
+    }
+}
@@ -0,0 +1,686 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use numpy::ndarray::{Array1, Array2, ArrayView1};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::coordinates::GlobalIndex;
+use crate::error::Error;
+use crate::index::{count_bases, NUM_BASE_CLASSES};
+use crate::values::ValueType;
+
+/// Identifies exported sampler states, followed by a version.
+const STATE_MAGIC: &[u8; 4] = b"FLWS";
+const STATE_VERSION: u32 = 1;
+/// Magic, version, fingerprint, seed, stream, word position and number of sampled windows.
+const STATE_LEN: usize = 4 + 4 + 8 + 32 + 8 + 16 + 8;
+
+/// Samples windows of a fixed length from the contigs of a `GlobalIndex`.
+/// Contigs are identified by their global id and weighted by their number of windows, i.e.,
+/// every window of the collection is equally likely. With per-name weights, a name is chosen
+/// by its weight first and a window within it uniformly.
+pub(crate) struct WindowSampler {
+    window_length: u64,
+    /// Cumulative weights of the contigs, indexed by global id.
+    cumulative: Vec<f64>,
+    rng: ChaCha8Rng,
+    /// Number of windows sampled so far.
+    num_sampled: u64,
+    /// Hash of the window length and weights, to only restore states of the same setup.
+    fingerprint: u64,
+}
+
+impl WindowSampler {
+    /// Names missing from `weights` are never sampled. Without a seed, the sampler is seeded
+    /// from the OS.
+    pub(crate) fn new(
+        global: &GlobalIndex,
+        window_length: u64,
+        weights: Option<&HashMap<String, f64>>,
+        seed: Option<u64>,
+    ) -> Result<Self> {
+        if window_length == 0 {
+            bail!("window_length must be positive");
+        }
+        let num_windows = |id: u32| (global.contig_length(id) + 1).saturating_sub(window_length);
+        let ids = 0..global.num_contigs() as u32;
+        let contig_weights = match weights {
+            None => ids.map(|id| num_windows(id) as f64).collect::<Vec<_>>(),
+            Some(weights) => {
+                for (name, &weight) in weights {
+                    if !(weight >= 0.0 && weight.is_finite()) {
+                        bail!("Invalid weight {} for {}", weight, name);
+                    }
+                }
+                let mut windows_per_name = HashMap::<&str, u64>::new();
+                for id in ids.clone() {
+                    let (name, _) = global.contig(id).unwrap();
+                    *windows_per_name.entry(name).or_default() += num_windows(id);
+                }
+                if let Some(name) = weights
+                    .keys()
+                    .find(|name| !windows_per_name.contains_key(name.as_str()))
+                {
+                    bail!(Error::NameNotFound(name.clone()));
+                }
+                ids.map(|id| {
+                    let (name, _) = global.contig(id).unwrap();
+                    match (weights.get(name), windows_per_name[name]) {
+                        (Some(weight), total) if total > 0 => {
+                            weight * num_windows(id) as f64 / total as f64
+                        }
+                        _ => 0.0,
+                    }
+                })
+                .collect()
+            }
+        };
+        let cumulative = contig_weights
+            .iter()
+            .scan(0.0, |total, weight| {
+                *total += weight;
+                Some(*total)
+            })
+            .collect::<Vec<_>>();
+        if !matches!(cumulative.last(), Some(&total) if total > 0.0) {
+            bail!(
+                "No window of length {} can be sampled with the given weights",
+                window_length
+            );
+        }
+        let rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        let mut hasher = Xxh3::new();
+        hasher.update(&window_length.to_le_bytes());
+        for weight in &cumulative {
+            hasher.update(&weight.to_le_bytes());
+        }
+        Ok(WindowSampler {
+            window_length,
+            cumulative,
+            rng,
+            num_sampled: 0,
+            fingerprint: hasher.digest(),
+        })
+    }
+
+    pub(crate) fn window_length(&self) -> u64 {
+        self.window_length
+    }
+
+    /// Sample the global id of a contig and the start of a window within it.
+    pub(crate) fn sample(&mut self, global: &GlobalIndex) -> (u32, u64) {
+        let total = self.cumulative[self.cumulative.len() - 1];
+        loop {
+            let u = self.rng.gen::<f64>() * total;
+            // Contigs without weight have the same cumulative weight as their predecessor and
+            // are never chosen
+            let id = self.cumulative.partition_point(|&c| c <= u);
+            if id == self.cumulative.len() {
+                // Rounding of `u` to `total`
+                continue;
+            }
+            self.num_sampled += 1;
+            let id = id as u32;
+            let max_start = global.contig_length(id) - self.window_length;
+            return (id, self.rng.gen_range(0..=max_start));
+        }
+    }
+
+    pub(crate) fn num_sampled(&self) -> u64 {
+        self.num_sampled
+    }
+
+    /// Export the RNG state and the number of sampled windows, to resume sampling later.
+    pub(crate) fn state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_LEN);
+        state.extend_from_slice(STATE_MAGIC);
+        state.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        state.extend_from_slice(&self.fingerprint.to_le_bytes());
+        state.extend_from_slice(&self.rng.get_seed());
+        state.extend_from_slice(&self.rng.get_stream().to_le_bytes());
+        state.extend_from_slice(&self.rng.get_word_pos().to_le_bytes());
+        state.extend_from_slice(&self.num_sampled.to_le_bytes());
+        state
+    }
+
+    /// Restore a state exported by a sampler with the same window length and weights.
+    pub(crate) fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        if state.len() != STATE_LEN || &state[..4] != STATE_MAGIC {
+            bail!("Invalid sampler state");
+        }
+        let (version, rest) = state[4..].split_at(4);
+        if u32::from_le_bytes(version.try_into()?) != STATE_VERSION {
+            bail!("Unsupported sampler state version");
+        }
+        let (fingerprint, rest) = rest.split_at(8);
+        if u64::from_le_bytes(fingerprint.try_into()?) != self.fingerprint {
+            bail!("Sampler state was exported with a different window length or weights");
+        }
+        let (seed, rest) = rest.split_at(32);
+        let (stream, rest) = rest.split_at(8);
+        let (word_pos, num_sampled) = rest.split_at(16);
+        let mut rng = ChaCha8Rng::from_seed(seed.try_into()?);
+        rng.set_stream(u64::from_le_bytes(stream.try_into()?));
+        rng.set_word_pos(u128::from_le_bytes(word_pos.try_into()?));
+        self.rng = rng;
+        self.num_sampled = u64::from_le_bytes(num_sampled.try_into()?);
+        Ok(())
+    }
+
+    pub(crate) fn sample_batch(
+        &mut self,
+        global: &GlobalIndex,
+        batch_size: usize,
+    ) -> (Array1<u32>, Array1<u64>) {
+        let (ids, starts): (Vec<_>, Vec<_>) = (0..batch_size).map(|_| self.sample(global)).unzip();
+        (Array1::from_vec(ids), Array1::from_vec(starts))
+    }
+}
+
+/// Samples positions with probability proportional to the values of a track, e.g., coverage.
+/// The cumulative sums of the values are indexed per block of `block_size` values, so only
+/// the block of a sampled position is read to locate it. Positions count values, not bytes.
+pub(crate) struct PositionSampler {
+    block_size: u64,
+    value_type: ValueType,
+    /// Global id and start of each block with a positive sum.
+    blocks: Vec<(u32, u64)>,
+    /// Cumulative sums of `blocks`.
+    cumulative: Vec<f64>,
+    rng: ChaCha8Rng,
+}
+
+impl PositionSampler {
+    /// `block_sums` returns the sums of the blocks of a contig given by its global id.
+    pub(crate) fn new<F>(
+        global: &GlobalIndex,
+        block_size: u64,
+        value_type: ValueType,
+        block_sums: F,
+        seed: Option<u64>,
+    ) -> Result<Self>
+    where
+        F: Fn(u32) -> Result<Vec<f64>> + Sync,
+    {
+        if block_size == 0 {
+            bail!("block_size must be positive");
+        }
+        let sums = (0..global.num_contigs() as u32)
+            .into_par_iter()
+            .map(|id| match global.contig_length(id) {
+                0 => Ok(Vec::new()),
+                _ => block_sums(id),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (mut blocks, mut cumulative) = (Vec::new(), Vec::new());
+        let mut total = 0.0;
+        for (id, sums) in sums.into_iter().enumerate() {
+            for (i, sum) in sums.into_iter().enumerate() {
+                if sum > 0.0 {
+                    total += sum;
+                    blocks.push((id as u32, i as u64 * block_size));
+                    cumulative.push(total);
+                }
+            }
+        }
+        if !(total > 0.0 && total.is_finite()) {
+            bail!("The track has no positive values to sample from");
+        }
+        let rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        Ok(PositionSampler {
+            block_size,
+            value_type,
+            blocks,
+            cumulative,
+            rng,
+        })
+    }
+
+    /// Sum of all values of the track.
+    pub(crate) fn total(&self) -> f64 {
+        self.cumulative[self.cumulative.len() - 1]
+    }
+
+    /// Sample `batch_size` positions as global ids and positions within the contigs. `read`
+    /// reads the bytes of a region given as global id, start and length, and the blocks of the
+    /// sampled positions are read in parallel.
+    pub(crate) fn sample_batch<F>(
+        &mut self,
+        global: &GlobalIndex,
+        batch_size: usize,
+        read: F,
+    ) -> Result<(Array1<u32>, Array1<u64>)>
+    where
+        F: Fn(u32, u64, u64) -> Result<Array1<u8>> + Sync,
+    {
+        let total = self.total();
+        let mut draws = Vec::with_capacity(batch_size);
+        while draws.len() < batch_size {
+            let u = self.rng.gen::<f64>() * total;
+            let block = self.cumulative.partition_point(|&c| c <= u);
+            if block == self.cumulative.len() {
+                // Rounding of `u` to `total`
+                continue;
+            }
+            let before = block.checked_sub(1).map_or(0.0, |i| self.cumulative[i]);
+            draws.push((block, u - before));
+        }
+        let size = self.value_type.size();
+        let (ids, positions): (Vec<_>, Vec<_>) = draws
+            .into_par_iter()
+            .map(|(block, mut offset)| {
+                let (id, start) = self.blocks[block];
+                let num_values = global.contig_length(id) / size;
+                let length = self.block_size.min(num_values - start);
+                let weights = self
+                    .value_type
+                    .weights(&read(id, start * size, length * size)?)?;
+                // Rounding may leave a remainder past the last positive value of the block
+                let mut last = None;
+                for (i, &weight) in weights.iter().enumerate() {
+                    if weight > 0.0 {
+                        if offset < weight {
+                            return Ok((id, start + i as u64));
+                        }
+                        last = Some(i);
+                    }
+                    offset -= weight;
+                }
+                match last {
+                    Some(i) => Ok((id, start + i as u64)),
+                    None => bail!("The track changed since the sampler was created"),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        Ok((Array1::from_vec(ids), Array1::from_vec(positions)))
+    }
+}
+
+/// Draws of candidates for a positive before giving up on avoiding the positives.
+const MAX_MATCH_DRAWS: usize = 1000;
+
+/// Samples negative windows matched to positive windows on GC content, e.g., for regulatory
+/// genomics. On construction, the candidate windows of `window_length` starting every `stride`
+/// bases of the collection are binned by their GC content, i.e., the fraction of `C` and `G`
+/// among the `ACGT` bases. A negative is then drawn uniformly from the bin of its positive, or
+/// from the nearest non-empty bin, and never overlaps any of the positives.
+pub(crate) struct GcMatchedSampler {
+    window_length: u64,
+    /// Candidate windows by GC bin, as global id and start.
+    bins: Vec<Vec<(u32, u64)>>,
+    rng: ChaCha8Rng,
+}
+
+impl GcMatchedSampler {
+    /// `block_counts` returns the counts of `A`, `C`, `G`, `T` and other bases in consecutive
+    /// blocks of `stride` bases of a contig given by its global id, see `composition`.
+    /// `window_length` must be a multiple of `stride`, and windows with a larger fraction of
+    /// other bases than `max_n_fraction` are no candidates.
+    pub(crate) fn new<F>(
+        global: &GlobalIndex,
+        window_length: u64,
+        stride: u64,
+        num_bins: usize,
+        max_n_fraction: f64,
+        block_counts: F,
+        seed: Option<u64>,
+    ) -> Result<Self>
+    where
+        F: Fn(u32) -> Result<Array2<u64>> + Sync,
+    {
+        if window_length == 0 || stride == 0 || window_length % stride != 0 {
+            bail!("window_length must be a positive multiple of stride");
+        }
+        if num_bins == 0 {
+            bail!("num_bins must be positive");
+        }
+        if !(0.0..=1.0).contains(&max_n_fraction) {
+            bail!("max_n_fraction must be between 0 and 1");
+        }
+        let blocks_per_window = (window_length / stride) as usize;
+        let candidates = (0..global.num_contigs() as u32)
+            .into_par_iter()
+            .map(|id| -> Result<Vec<_>> {
+                let contig_length = global.contig_length(id);
+                if contig_length < window_length {
+                    return Ok(Vec::new());
+                }
+                // Prefix sums of the GC and ACGT bases of the blocks
+                let (mut gc, mut acgt) = (vec![0], vec![0]);
+                for counts in block_counts(id)?.outer_iter() {
+                    gc.push(gc[gc.len() - 1] + counts[1] + counts[2]);
+                    acgt.push(acgt[acgt.len() - 1] + counts.iter().take(4).sum::<u64>());
+                }
+                let num_windows = ((contig_length - window_length) / stride + 1) as usize;
+                if acgt.len() < num_windows + blocks_per_window {
+                    bail!("Missing block counts of contig {}", id);
+                }
+                Ok((0..num_windows)
+                    .filter_map(|i| {
+                        let end = i + blocks_per_window;
+                        let acgt = acgt[end] - acgt[i];
+                        let unknown = (window_length - acgt) as f64 / window_length as f64;
+                        if acgt == 0 || unknown > max_n_fraction {
+                            return None;
+                        }
+                        let gc = (gc[end] - gc[i]) as f64 / acgt as f64;
+                        Some((gc_bin(gc, num_bins), id, i as u64 * stride))
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut bins = vec![Vec::new(); num_bins];
+        for (bin, id, start) in candidates.into_iter().flatten() {
+            bins[bin].push((id, start));
+        }
+        if bins.iter().all(|bin| bin.is_empty()) {
+            bail!(
+                "No window of length {} has a fraction of unknown bases of at most {}",
+                window_length,
+                max_n_fraction
+            );
+        }
+        let rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        Ok(GcMatchedSampler {
+            window_length,
+            bins,
+            rng,
+        })
+    }
+
+    pub(crate) fn window_length(&self) -> u64 {
+        self.window_length
+    }
+
+    /// Number of candidate windows in each GC bin.
+    pub(crate) fn bin_sizes(&self) -> Vec<usize> {
+        self.bins.iter().map(Vec::len).collect()
+    }
+
+    /// Sample `num_per_positive` negatives for each positive window given by global id, start
+    /// and GC content, as global ids and starts grouped by positive.
+    pub(crate) fn sample_matched(
+        &mut self,
+        positives: &[(u32, u64, f64)],
+        num_per_positive: usize,
+    ) -> Result<(Array1<u32>, Array1<u64>)> {
+        let window_length = self.window_length;
+        let mut sorted = positives
+            .iter()
+            .map(|&(id, start, _)| (id, start))
+            .collect::<Vec<_>>();
+        sorted.sort_unstable();
+        // Windows of the same length overlap if their starts are less than a window apart
+        let overlaps_positive = |id: u32, start: u64| {
+            let first = sorted.partition_point(|&(other_id, other_start)| {
+                (other_id, other_start) < (id, start.saturating_sub(window_length - 1))
+            });
+            sorted.get(first).is_some_and(|&(other_id, other_start)| {
+                other_id == id && other_start < start + window_length
+            })
+        };
+        let (mut ids, mut starts) = (Vec::new(), Vec::new());
+        for &(_, _, gc) in positives {
+            if !(0.0..=1.0).contains(&gc) {
+                bail!("Invalid GC content {} of a positive window", gc);
+            }
+            let bin = nearest_bin(&self.bins, gc_bin(gc, self.bins.len()));
+            for _ in 0..num_per_positive {
+                let candidates = &self.bins[bin];
+                let negative = (0..MAX_MATCH_DRAWS)
+                    .map(|_| candidates[self.rng.gen_range(0..candidates.len())])
+                    .find(|&(id, start)| !overlaps_positive(id, start));
+                let Some((id, start)) = negative else {
+                    bail!(
+                        "Could not sample a negative with GC content {} which does not overlap \
+                         the positives",
+                        gc
+                    );
+                };
+                ids.push(id);
+                starts.push(start);
+            }
+        }
+        Ok((Array1::from_vec(ids), Array1::from_vec(starts)))
+    }
+}
+
+/// Bin of a GC content among `num_bins` bins of equal width.
+fn gc_bin(gc: f64, num_bins: usize) -> usize {
+    ((gc * num_bins as f64) as usize).min(num_bins - 1)
+}
+
+/// The non-empty bin closest to `bin`, preferring lower bins at the same distance.
+fn nearest_bin(bins: &[Vec<(u32, u64)>], bin: usize) -> usize {
+    (0..bins.len())
+        .flat_map(|distance| [bin.checked_sub(distance), Some(bin + distance)])
+        .flatten()
+        .find(|&candidate| bins.get(candidate).is_some_and(|bin| !bin.is_empty()))
+        .unwrap()
+}
+
+/// Fraction of `C` and `G` among the `ACGT` bases of a window, or NaN without any.
+pub(crate) fn gc_content(window: ArrayView1<u8>) -> f64 {
+    let mut counts = [0; NUM_BASE_CLASSES];
+    match window.as_slice() {
+        Some(bases) => count_bases(bases, &mut counts),
+        None => count_bases(&window.to_vec(), &mut counts),
+    }
+    (counts[1] + counts[2]) as f64 / counts[..4].iter().sum::<u64>() as f64
+}
+
+/// Read the windows given by global ids and starts in parallel, one window per row.
+/// `read` reads a region given as name, contig, start and length.
+pub(crate) fn read_windows<F>(
+    global: &GlobalIndex,
+    ids: &[u32],
+    starts: &[u64],
+    length: u64,
+    read: F,
+) -> Result<Array2<u8>>
+where
+    F: Fn(&str, &[u8], u64, u64) -> Result<Array1<u8>> + Sync,
+{
+    if ids.len() != starts.len() {
+        bail!("Got {} ids but {} starts", ids.len(), starts.len());
+    }
+    let rows = ids
+        .par_iter()
+        .zip(starts)
+        .map(|(&id, &start)| {
+            let Some((name, contig)) = global.contig(id) else {
+                bail!("Global id out of range: {}", id);
+            };
+            read(name, contig, start, length)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut windows = Array2::zeros((rows.len(), length as usize));
+    for (mut window, row) in windows.outer_iter_mut().zip(rows) {
+        window.assign(&row);
+    }
+    Ok(windows)
+}
+
+/// Fraction of unknown bases (`N` or `n`) in a window.
+pub(crate) fn n_fraction(window: ArrayView1<u8>) -> f64 {
+    if window.is_empty() {
+        return 0.0;
+    }
+    let n = window
+        .iter()
+        .filter(|&&base| base == b'N' || base == b'n')
+        .count();
+    n as f64 / window.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global() -> GlobalIndex {
+        let contigs = |name: &str| -> Result<Vec<(&'static [u8], u64)>> {
+            Ok(match name {
+                "a" => vec![(b"x".as_slice(), 100), (b"y".as_slice(), 5)],
+                "b" => vec![(b"x".as_slice(), 10)],
+                _ => unreachable!(),
+            })
+        };
+        GlobalIndex::build(vec!["a".to_string(), "b".to_string()], contigs).unwrap()
+    }
+
+    #[test]
+    fn test_sample() {
+        let global = global();
+        let mut sampler = WindowSampler::new(&global, 10, None, Some(0)).unwrap();
+        let mut counts = [0; 3];
+        for _ in 0..1000 {
+            let (id, start) = sampler.sample(&global);
+            assert!(start + 10 <= global.contig_length(id));
+            counts[id as usize] += 1;
+        }
+        // The contig shorter than the window is never sampled, the others by their windows
+        assert_eq!(counts[1], 0);
+        assert!(counts[0] > 900);
+        assert!(counts[2] > 0);
+
+        let mut again = WindowSampler::new(&global, 10, None, Some(0)).unwrap();
+        let mut sampler = WindowSampler::new(&global, 10, None, Some(0)).unwrap();
+        assert_eq!(
+            sampler.sample_batch(&global, 10),
+            again.sample_batch(&global, 10)
+        );
+    }
+
+    #[test]
+    fn test_state() {
+        let global = global();
+        let mut sampler = WindowSampler::new(&global, 10, None, Some(0)).unwrap();
+        sampler.sample_batch(&global, 7);
+        let state = sampler.state();
+        let expected = sampler.sample_batch(&global, 10);
+
+        let mut resumed = WindowSampler::new(&global, 10, None, None).unwrap();
+        resumed.load_state(&state).unwrap();
+        assert_eq!(resumed.num_sampled(), 7);
+        assert_eq!(resumed.sample_batch(&global, 10), expected);
+
+        let mut other = WindowSampler::new(&global, 5, None, None).unwrap();
+        assert!(other.load_state(&state).is_err());
+        assert!(other.load_state(&state[1..]).is_err());
+    }
+
+    #[test]
+    fn test_position_sampler() {
+        let global = global();
+        // Only the positions 3 and 60 of a/x and 2 of b/x have a value, in a ratio of 1:2:1
+        let track = |id: u32| {
+            let mut values = vec![0u8; global.contig_length(id) as usize];
+            match id {
+                0 => (values[3], values[60]) = (1, 2),
+                2 => values[2] = 1,
+                _ => {}
+            }
+            values
+        };
+        let block_sums = |id: u32| -> Result<Vec<f64>> {
+            Ok(track(id)
+                .chunks(16)
+                .map(|block| block.iter().map(|&v| v as f64).sum())
+                .collect())
+        };
+        let read = |id: u32, start: u64, length: u64| -> Result<Array1<u8>> {
+            let values = track(id)[start as usize..(start + length) as usize].to_vec();
+            Ok(Array1::from_vec(values))
+        };
+        let mut sampler =
+            PositionSampler::new(&global, 16, ValueType::U8, block_sums, Some(0)).unwrap();
+        assert_eq!(sampler.total(), 4.0);
+        let (ids, positions) = sampler.sample_batch(&global, 1000, read).unwrap();
+        let mut counts = HashMap::<_, u32>::new();
+        for (id, position) in ids.iter().zip(&positions) {
+            *counts.entry((*id, *position)).or_default() += 1;
+        }
+        assert_eq!(counts.len(), 3);
+        assert!(counts[&(0, 60)] > counts[&(0, 3)] && counts[&(0, 60)] > counts[&(2, 2)]);
+
+        let empty = |id: u32| -> Result<Vec<f64>> {
+            Ok(vec![0.0; global.contig_length(id).div_ceil(16) as usize])
+        };
+        assert!(PositionSampler::new(&global, 16, ValueType::U8, empty, None).is_err());
+    }
+
+    #[test]
+    fn test_n_fraction() {
+        assert_eq!(n_fraction(Array1::from(b"ACNn".to_vec()).view()), 0.5);
+        assert_eq!(n_fraction(Array1::from(b"ACGT".to_vec()).view()), 0.0);
+        assert_eq!(n_fraction(Array1::from(Vec::new()).view()), 0.0);
+    }
+
+    #[test]
+    fn test_gc_matched_sampler() {
+        let global = global();
+        // a/x is AT-rich in its first half and GC-rich in its second, b/x is GC-rich
+        let sequence = |id: u32| match id {
+            0 => [b"AT".repeat(25), b"GC".repeat(25)].concat(),
+            2 => b"GCGCGCGCNN".to_vec(),
+            _ => b"ACGTA".to_vec(),
+        };
+        let block_counts = |id: u32| -> Result<Array2<u64>> {
+            let blocks = sequence(id)
+                .chunks(5)
+                .flat_map(|block| {
+                    let mut counts = [0; NUM_BASE_CLASSES];
+                    count_bases(block, &mut counts);
+                    counts
+                })
+                .collect::<Vec<_>>();
+            Ok(Array2::from_shape_vec((blocks.len() / 5, 5), blocks)?)
+        };
+        let mut sampler =
+            GcMatchedSampler::new(&global, 10, 5, 10, 0.0, block_counts, Some(0)).unwrap();
+        // Windows of a/x start every 5 bases, and the window of b/x has unknown bases
+        assert_eq!(sampler.bin_sizes().iter().sum::<usize>(), 19);
+        let positives = [(0, 0, 0.0), (0, 70, 1.0)];
+        let (ids, starts) = sampler.sample_matched(&positives, 50).unwrap();
+        for (i, (&id, &start)) in ids.iter().zip(&starts).enumerate() {
+            assert_eq!(id, 0);
+            // Matched on GC content, and without overlapping the positive
+            if i < 50 {
+                assert!((10..=40).contains(&start));
+            } else {
+                assert!((50..=60).contains(&start) || start >= 80);
+            }
+        }
+        assert!(sampler.sample_matched(&[(0, 0, 1.5)], 1).is_err());
+        assert!(GcMatchedSampler::new(&global, 10, 3, 10, 0.0, block_counts, None).is_err());
+        assert_eq!(gc_content(Array1::from(b"ACGN".to_vec()).view()), 2.0 / 3.0);
+    }
+
+    #[test]
+    fn test_weights() {
+        let global = global();
+        let weights = HashMap::from([("b".to_string(), 1.0)]);
+        let mut sampler = WindowSampler::new(&global, 10, Some(&weights), Some(0)).unwrap();
+        assert!((0..100).all(|_| sampler.sample(&global) == (2, 0)));
+
+        let weights = HashMap::from([("c".to_string(), 1.0)]);
+        assert!(WindowSampler::new(&global, 10, Some(&weights), None).is_err());
+        let weights = HashMap::from([("a".to_string(), -1.0)]);
+        assert!(WindowSampler::new(&global, 10, Some(&weights), None).is_err());
+        assert!(WindowSampler::new(&global, 200, None, None).is_err());
+    }
+}
@@ -1,29 +1,49 @@
 mod archive;
+mod checksum;
+mod header;
+mod lazy;
 mod memory;
 mod mmap;
+mod sharded;
 mod shmem;
 
 use std::fs::File;
 use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use rkyv::api::high::HighValidator;
+use rkyv::bytecheck::CheckBytes;
 use rkyv::ser::writer::IoWriter;
 use rkyv::util::AlignedVec;
 use rkyv::Serialize;
 use rkyv::{rancor, Portable};
 use std::convert::AsRef;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::error::Error;
+use crate::index::ArchivedMap;
 
 pub(crate) use archive::{
-    type_specific_magic, write_direct, ArchiveStorage, LoadableStorage, SharableStorage, Storage,
+    read_header, schema_id, type_specific_magic, write_direct, ArchiveStorage, LoadableStorage,
+    MutableStorage, Schema, SharableStorage, Storage,
 };
+pub(crate) use checksum::ChecksumAlgorithm;
+pub(crate) use header::{Header, Rejection, FORMAT_VERSION};
+pub(crate) use lazy::LazyStorage;
 pub(crate) use memory::MemoryStorage;
 pub(crate) use mmap::MmapStorage;
-pub(crate) use shmem::ShmemStorage;
+pub(crate) use sharded::{directory_path, shard_path, ShardedStorage};
+pub(crate) use shmem::{
+    cleanup_segments, list_segments, process_alive, shmem_available, Segment, ShmemStorage,
+};
 
 pub(crate) enum DynamicStorage<T> {
     Memory(ArchiveStorage<T, MemoryStorage>),
     Shmem(ArchiveStorage<T, ShmemStorage>),
     Mmap(ArchiveStorage<T, MmapStorage>),
+    Sharded(ShardedStorage<T>),
+    Lazy(LazyStorage<T>),
 }
 
 impl<T> DynamicStorage<T>
@@ -49,18 +69,149 @@ where
             rancor::Error,
         >,
     >,
-    T::Archived: Sync + Send + 'static + Portable,
+    T::Archived:
+        Sync + Send + 'static + Portable + for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
     T: Sync + Send + 'static,
+    T: Schema,
+    T::Archived: ArchivedMap,
 {
-    pub fn as_ref(&self) -> &T::Archived {
+    /// Get the archive containing `name`. For sharded storage, this loads the respective shard,
+    /// and for lazy storage, this indexes the entry. Mapped files are checked for truncation
+    /// first, see `MmapStorage::check`.
+    pub fn get(&self, name: &str) -> Result<&T::Archived> {
+        match self {
+            DynamicStorage::Memory(storage) => Ok(storage.as_ref()),
+            DynamicStorage::Shmem(storage) => Ok(storage.as_ref()),
+            DynamicStorage::Mmap(storage) => {
+                storage.check()?;
+                Ok(storage.as_ref())
+            }
+            DynamicStorage::Sharded(storage) => storage.get(name),
+            DynamicStorage::Lazy(storage) => storage.get(name),
+        }
+    }
+
+    /// Get all archives. For sharded and lazy storage, this loads all shards or entries.
+    pub fn archives(&self) -> Result<Vec<&T::Archived>> {
+        match self {
+            DynamicStorage::Memory(storage) => Ok(vec![storage.as_ref()]),
+            DynamicStorage::Shmem(storage) => Ok(vec![storage.as_ref()]),
+            DynamicStorage::Mmap(storage) => {
+                storage.check()?;
+                Ok(vec![storage.as_ref()])
+            }
+            DynamicStorage::Sharded(storage) => storage.all(),
+            DynamicStorage::Lazy(storage) => storage.all(),
+        }
+    }
+
+    /// Like all accessors of mapped files, this checks them for truncation first, see `get`.
+    pub fn names(&self) -> Result<Vec<&str>> {
+        match self {
+            DynamicStorage::Memory(storage) => Ok(storage.as_ref().names()),
+            DynamicStorage::Shmem(storage) => Ok(storage.as_ref().names()),
+            DynamicStorage::Mmap(storage) => {
+                storage.check()?;
+                Ok(storage.as_ref().names())
+            }
+            DynamicStorage::Sharded(storage) => Ok(storage.directory()?.names()),
+            DynamicStorage::Lazy(storage) => Ok(storage.names()),
+        }
+    }
+
+    /// Whether `name` is in this storage, without loading or indexing its entry.
+    pub fn contains(&self, name: &str) -> Result<bool> {
+        match self {
+            DynamicStorage::Memory(storage) => Ok(storage.as_ref().contains(name)),
+            DynamicStorage::Shmem(storage) => Ok(storage.as_ref().contains(name)),
+            DynamicStorage::Mmap(storage) => {
+                storage.check()?;
+                Ok(storage.as_ref().contains(name))
+            }
+            DynamicStorage::Sharded(storage) => Ok(storage.directory()?.names().contains(&name)),
+            DynamicStorage::Lazy(storage) => Ok(storage.contains(name)),
+        }
+    }
+
+    /// Total number of contigs, or `None` if counting would load all shards or index all
+    /// entries.
+    pub fn num_contigs(&self) -> Result<Option<usize>> {
+        match self {
+            DynamicStorage::Memory(storage) => Ok(Some(storage.as_ref().num_contigs())),
+            DynamicStorage::Shmem(storage) => Ok(Some(storage.as_ref().num_contigs())),
+            DynamicStorage::Mmap(storage) => {
+                storage.check()?;
+                Ok(Some(storage.as_ref().num_contigs()))
+            }
+            DynamicStorage::Sharded(_) | DynamicStorage::Lazy(_) => Ok(None),
+        }
+    }
+
+    /// Total length of all contigs, or `None` like `num_contigs`.
+    pub fn total_length(&self) -> Result<Option<u64>> {
+        match self {
+            DynamicStorage::Memory(storage) => Ok(Some(storage.as_ref().total_length())),
+            DynamicStorage::Shmem(storage) => Ok(Some(storage.as_ref().total_length())),
+            DynamicStorage::Mmap(storage) => {
+                storage.check()?;
+                Ok(Some(storage.as_ref().total_length()))
+            }
+            DynamicStorage::Sharded(_) | DynamicStorage::Lazy(_) => Ok(None),
+        }
+    }
+
+    /// Size of the archive in bytes, of all shard files for sharded storage, or `None` for lazy
+    /// storage, whose entries are indexed on demand.
+    pub fn archive_size(&self) -> Option<u64> {
+        match self {
+            DynamicStorage::Memory(storage) => Some(storage.size()),
+            DynamicStorage::Shmem(storage) => Some(storage.size()),
+            DynamicStorage::Mmap(storage) => Some(storage.size()),
+            DynamicStorage::Sharded(storage) => Some(storage.size()),
+            DynamicStorage::Lazy(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DynamicStorage::Memory(_) => "memory",
+            DynamicStorage::Shmem(_) => "shmem",
+            DynamicStorage::Mmap(_) => "mmap",
+            DynamicStorage::Sharded(_) => "sharded",
+            DynamicStorage::Lazy(_) => "lazy",
+        }
+    }
+
+    /// The cache file backing this storage, if it was loaded from one in this process.
+    pub fn cache_path(&self) -> Option<&Path> {
+        match self {
+            DynamicStorage::Memory(storage) => storage.path(),
+            DynamicStorage::Shmem(storage) => storage.path(),
+            DynamicStorage::Mmap(storage) => storage.path(),
+            DynamicStorage::Sharded(storage) => Some(storage.base()),
+            DynamicStorage::Lazy(_) => None,
+        }
+    }
+
+    /// Returns a reason if a few index files below `root` do not match the manifest.
+    pub fn spot_check(&self, root: &Path) -> Result<Option<String>> {
         match self {
-            DynamicStorage::Memory(storage) => storage.as_ref(),
-            DynamicStorage::Shmem(storage) => storage.as_ref(),
-            DynamicStorage::Mmap(storage) => storage.as_ref(),
+            DynamicStorage::Memory(storage) => Ok(storage.as_ref().spot_check(root)),
+            DynamicStorage::Shmem(storage) => Ok(storage.as_ref().spot_check(root)),
+            DynamicStorage::Mmap(storage) => {
+                storage.check()?;
+                Ok(storage.as_ref().spot_check(root))
+            }
+            DynamicStorage::Sharded(storage) => Ok(storage.directory()?.spot_check(root)),
+            // Entries indexed lazily are read from the root itself
+            DynamicStorage::Lazy(_) => Ok(None),
         }
     }
 
-    pub fn export(&self) -> Option<Vec<u8>> {
+    /// Cache files below `root` are recorded relative to it, such that the handle remains valid
+    /// when the root is moved, see `import`. Handles of a single archive carry its fingerprint,
+    /// see `fingerprint`.
+    pub fn export(&self, root: &Path) -> Result<Option<Vec<u8>>> {
         fn prefix(storage_type: &str, id: Vec<u8>) -> Vec<u8> {
             let mut result = storage_type.as_bytes().to_vec();
             result.push(b':');
@@ -68,34 +219,96 @@ where
             result
         }
 
+        fn relative(root: &Path, id: Vec<u8>) -> Vec<u8> {
+            let path = PathBuf::from(String::from_utf8_lossy(&id).into_owned());
+            match path.strip_prefix(root) {
+                Ok(relative) => relative.to_string_lossy().into_owned().into_bytes(),
+                Err(_) => id,
+            }
+        }
+
+        fn fingerprinted(archive: &impl ArchivedMap, id: Vec<u8>) -> Vec<u8> {
+            let mut result = fingerprint(archive).into_bytes();
+            result.push(b':');
+            result.extend(id);
+            result
+        }
+
         match self {
-            DynamicStorage::Memory(storage) => Some(prefix("Memory", storage.export())),
-            DynamicStorage::Shmem(storage) => Some(prefix("Shmem", storage.export())),
-            DynamicStorage::Mmap(storage) => Some(prefix("Mmap", storage.export())),
+            DynamicStorage::Memory(storage) => Ok(Some(prefix(
+                "Memory",
+                fingerprinted(storage.as_ref(), storage.export()),
+            ))),
+            DynamicStorage::Shmem(storage) => Ok(Some(prefix(
+                "Shmem",
+                fingerprinted(storage.as_ref(), storage.export()),
+            ))),
+            DynamicStorage::Mmap(storage) => {
+                storage.check()?;
+                Ok(Some(prefix(
+                    "Mmap",
+                    fingerprinted(storage.as_ref(), relative(root, storage.export())),
+                )))
+            }
+            // Shards are checked against the directory when they are loaded
+            DynamicStorage::Sharded(storage) => {
+                Ok(Some(prefix("Sharded", relative(root, storage.export()))))
+            }
+            // Entries indexed lazily live in the memory of this process only
+            DynamicStorage::Lazy(_) => Ok(None),
         }
     }
 
-    pub fn import(mut data: Vec<u8>) -> Result<DynamicStorage<T>> {
+    /// Relative paths of cache files are resolved against `root`, which may differ from the
+    /// root of the exporting process.
+    pub fn import(mut data: Vec<u8>, validate: bool, root: &Path) -> Result<DynamicStorage<T>> {
         let colon = data
             .iter()
             .position(|&b| b == b':')
             .ok_or_else(|| anyhow::anyhow!("Invalid handle format: missing colon separator"))?;
         let storage_type = std::str::from_utf8(&data[..colon])?.to_string();
-        let id = data.split_off(colon + 1);
+        let mut id = data.split_off(colon + 1);
+        // Handles of a single archive carry its fingerprint before the id
+        let expected = match storage_type.as_str() {
+            "Memory" | "Shmem" | "Mmap" => {
+                let colon = id.iter().position(|&b| b == b':').ok_or_else(|| {
+                    anyhow::anyhow!("Invalid handle format: missing index fingerprint")
+                })?;
+                let expected = String::from_utf8(id[..colon].to_vec())?;
+                id.drain(..=colon);
+                expected
+            }
+            _ => String::new(),
+        };
+        if storage_type == "Mmap" || storage_type == "Sharded" {
+            // Absolute paths, e.g., of the mirror of a remote root, are kept by `join`
+            id = root
+                .join(String::from_utf8(id)?)
+                .to_string_lossy()
+                .into_owned()
+                .into_bytes();
+        }
 
         match storage_type.as_str() {
             "Memory" => {
-                let storage = ArchiveStorage::<T, MemoryStorage>::import(id)?;
+                let storage = ArchiveStorage::<T, MemoryStorage>::import(id, validate)?;
+                check_fingerprint(storage.as_ref(), &expected, "memory")?;
                 Ok(DynamicStorage::Memory(storage))
             }
             "Shmem" => {
-                let storage = ArchiveStorage::<T, ShmemStorage>::import(id)?;
+                let storage = ArchiveStorage::<T, ShmemStorage>::import(id, validate)?;
+                check_fingerprint(storage.as_ref(), &expected, "shared memory segment")?;
                 Ok(DynamicStorage::Shmem(storage))
             }
             "Mmap" => {
-                let storage = ArchiveStorage::<T, MmapStorage>::import(id)?;
+                let storage = ArchiveStorage::<T, MmapStorage>::import(id, validate)?;
+                check_fingerprint(storage.as_ref(), &expected, "cache file")?;
                 Ok(DynamicStorage::Mmap(storage))
             }
+            "Sharded" => {
+                let storage = ShardedStorage::<T>::import(id, validate)?;
+                Ok(DynamicStorage::Sharded(storage))
+            }
             _ => {
                 anyhow::bail!("Unknown storage type: {}", storage_type);
             }
@@ -103,6 +316,33 @@ where
     }
 }
 
+/// A short hash of the names and checksums of the entries of an archive, which identifies the
+/// files it was built from and the options it was built with.
+fn fingerprint(archive: &impl ArchivedMap) -> String {
+    let mut hasher = Xxh3::new();
+    for name in archive.names() {
+        hasher.update(&(name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update(&archive.checksum(name).unwrap_or(0).to_le_bytes());
+    }
+    format!("{:016x}", hasher.digest())
+}
+
+/// Fails if `archive` does not have the fingerprint a handle was exported with, e.g., because
+/// the segment or cache file the handle refers to was replaced by an index of other files.
+fn check_fingerprint(archive: &impl ArchivedMap, expected: &str, location: &str) -> Result<()> {
+    let actual = fingerprint(archive);
+    if actual != expected {
+        bail!(Error::Storage(format!(
+            "The handle was exported for an index with fingerprint {}, but the {} it refers to \
+             holds an index with fingerprint {}, e.g., one built from other files or with other \
+             options",
+            expected, location, actual
+        )));
+    }
+    Ok(())
+}
+
 impl<T> From<ArchiveStorage<T, MemoryStorage>> for DynamicStorage<T> {
     fn from(storage: ArchiveStorage<T, MemoryStorage>) -> Self {
         DynamicStorage::Memory(storage)
@@ -120,3 +360,15 @@ impl<T> From<ArchiveStorage<T, MmapStorage>> for DynamicStorage<T> {
         DynamicStorage::Mmap(storage)
     }
 }
+
+impl<T> From<ShardedStorage<T>> for DynamicStorage<T> {
+    fn from(storage: ShardedStorage<T>) -> Self {
+        DynamicStorage::Sharded(storage)
+    }
+}
+
+impl<T> From<LazyStorage<T>> for DynamicStorage<T> {
+    fn from(storage: LazyStorage<T>) -> Self {
+        DynamicStorage::Lazy(storage)
+    }
+}
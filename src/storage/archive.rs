@@ -1,17 +1,24 @@
 use anyhow::{Context, Result};
+use rkyv::api::high::HighValidator;
+use rkyv::bytecheck::CheckBytes;
 use rkyv::ser::writer::IoWriter;
 use rkyv::util::AlignedVec;
-use rkyv::Serialize;
 use rkyv::{rancor, Portable};
-use std::collections::hash_map::DefaultHasher;
-use std::fs::{File, OpenOptions};
-use std::hash::Hasher;
+use rkyv::{Archive, Serialize};
+use std::fs::{File, OpenOptions, TryLockError};
 use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::marker::PhantomData;
-use std::path::Path;
-use std::{any::TypeId, hash::Hash};
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::storage::checksum::{ChecksumAlgorithm, CHECKSUM_LEN};
+use crate::storage::header::{Header, Rejection, FORMAT_VERSION, HEADER_LEN};
+use crate::storage::MmapStorage;
+use crate::util::fnv1a;
 
 const BUFFER_SIZE: usize = 8 * 1024 * 1024; // 8 MB buffer for file operations
+/// Alignment of serialized archives, which `rkyv::to_bytes` guarantees with `AlignedVec`.
+const ARCHIVE_ALIGNMENT: usize = 16;
 
 pub(crate) trait Storage: AsRef<[u8]> {
     fn as_ptr(&self) -> *const u8 {
@@ -47,6 +54,8 @@ pub(crate) trait LoadableStorage: Storage {
 
 pub(crate) struct ArchiveStorage<T, S> {
     pub(crate) storage: S,
+    /// File the archive was loaded from, if any.
+    path: Option<PathBuf>,
     phantom_t: PhantomData<T>,
 }
 
@@ -78,32 +87,58 @@ where
         >,
     >,
     T::Archived: 'static + Portable,
+    T: Schema,
     S: MutableStorage,
 {
     pub(crate) fn new(data: T) -> Result<Self> {
-        // We store an additional magic value at the beginning
-        // to verify the data type during access.
-        let magic_value = type_specific_magic::<T::Archived>();
-        // For alignment, we just store the magic value in the first page
-        // and the actual data in the following pages.
-        let mut first_page = Vec::with_capacity(page_size::get());
-        first_page.extend_from_slice(magic_value.to_ne_bytes().as_slice());
-        first_page.resize(page_size::get(), 0);
+        Self::with_prefix(data, 0, |_| Ok(()))
+    }
+
+    /// Like `new`, but with `prefix_len` bytes before the archive, which `fill` writes directly
+    /// into the storage, e.g., to decompress large data in place instead of serializing it.
+    /// The prefix is padded to the alignment of archives and read back with `prefix`.
+    pub(crate) fn with_prefix(
+        data: T,
+        prefix_len: usize,
+        fill: impl FnOnce(&mut [u8]) -> Result<()>,
+    ) -> Result<Self> {
         // Serialize the data to bytes (copy), then forget the original data
         let bytes = rkyv::to_bytes::<rancor::Error>(&data)?;
         std::mem::drop(data);
+        let padded_len = prefix_len.next_multiple_of(ARCHIVE_ALIGNMENT);
+        // We store an additional header at the beginning to verify the data type during access.
+        // For alignment, we just store the header in the first page
+        // and the actual data in the following pages.
+        // No checksum is needed because the data never leaves memory.
+        let header = Header::new(
+            type_specific_magic::<T>(),
+            ChecksumAlgorithm::None,
+            [0u8; CHECKSUM_LEN],
+            (padded_len + bytes.len()) as u64,
+        );
+        let mut first_page = Vec::with_capacity(page_size::get());
+        first_page.extend_from_slice(&header.to_bytes());
+        first_page.resize(page_size::get(), 0);
         // Allocate shared memory
-        let mut storage =
-            S::new(first_page.len() + bytes.len()).context("Failed to create storage")?;
+        let mut storage = S::new(first_page.len() + padded_len + bytes.len())
+            .context(Error::Storage("Failed to create storage".to_string()))?;
         let ptr = storage.as_mut_ptr();
         unsafe {
-            // Write the first page containing the magic value
+            // Write the first page containing the header
             std::ptr::copy_nonoverlapping(first_page.as_ptr(), ptr, page_size::get());
-            // Write the data after the magic value
-            std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr.add(page_size::get()), bytes.len());
+            // Write the data after the header and the prefix. Relative pointers of the archive
+            // and its root at the end stay valid behind the prefix.
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                ptr.add(page_size::get() + padded_len),
+                bytes.len(),
+            );
         }
+        let start = page_size::get();
+        fill(&mut storage.as_ref_mut()[start..start + prefix_len])?;
         Ok(Self {
             storage,
+            path: None,
             phantom_t: PhantomData,
         })
     }
@@ -138,7 +173,7 @@ where
 {
     fn as_ref(&self) -> &T::Archived {
         unsafe {
-            // Skip the first page because it contains the magic value
+            // Skip the first page because it contains the header
             let bytes = std::slice::from_raw_parts(
                 self.storage.as_ptr().add(page_size::get()),
                 self.storage.len() - page_size::get(),
@@ -172,43 +207,24 @@ where
         >,
     >,
     T::Archived: 'static + Portable,
+    T: Schema,
     S: LoadableStorage,
 {
-    pub(crate) fn load(path: &Path) -> Result<Option<Self>> {
-        let storage = S::load(path).context("Could not load storage!")?;
-
-        // Make sure that file is large enough to contain magic and checksum
-        let storage_len = storage.len();
-        if storage_len < page_size::get() {
-            eprintln!("Storage is too small to contain valid data");
-            return Ok(None);
-        }
-        let magic_bytes_slice = &storage.as_ref()[..std::mem::size_of::<u64>()];
-        let checksum_bytes_slice = &storage.as_ref()
-            [std::mem::size_of::<u64>()..std::mem::size_of::<u64>() + std::mem::size_of::<u32>()];
-        let data_bytes_slice = &storage.as_ref()[page_size::get()..storage.len()];
-
-        // Read and verify the magic value
-        let magic = u64::from_le_bytes(magic_bytes_slice.try_into().unwrap());
-        let expected_magic = type_specific_magic::<T::Archived>();
-        if magic != expected_magic {
-            eprintln!("Invalid magic value in file");
-            return Ok(None);
-        }
-
-        // Verify checksum
-        let checksum_read = u32::from_le_bytes(checksum_bytes_slice.try_into().unwrap());
-        let checksum_calculated = crc32fast::hash(data_bytes_slice);
-        if checksum_read != checksum_calculated {
-            eprintln!(
-                "Checksum mismatch: expected {}, computed {}",
-                checksum_read, checksum_calculated
-            );
-            return Ok(None);
+    /// Load an archive from `path`.
+    /// The outer error is an I/O error, the inner one the reason why the archive was rejected.
+    /// Skipping the checksum verification is only safe for trusted files.
+    pub(crate) fn load(
+        path: &Path,
+        verify_checksum: bool,
+    ) -> Result<std::result::Result<Self, Rejection>> {
+        let storage = S::load(path)
+            .with_context(|| Error::Storage(format!("Could not load {}", path.display())))?;
+        if let Err(rejection) = verify_header::<T>(storage.as_ref(), verify_checksum) {
+            return Ok(Err(rejection));
         }
-
-        Ok(Some(Self {
+        Ok(Ok(Self {
             storage,
+            path: Some(path.to_path_buf()),
             phantom_t: PhantomData,
         }))
     }
@@ -237,32 +253,79 @@ where
             rancor::Error,
         >,
     >,
-    T::Archived: 'static + Portable,
+    T::Archived: 'static + Portable + for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
+    T: Schema,
     S: SharableStorage,
 {
     pub(crate) fn export(&self) -> Vec<u8> {
         self.storage.export()
     }
 
-    pub(crate) fn import(id: Vec<u8>) -> Result<Self> {
+    /// Attach to storage exported by another process.
+    /// If `validate` is set, the whole archive is validated with bytecheck instead of trusting
+    /// its creator, which is slower but sound for storage from untrusted or mismatched builds.
+    pub(crate) fn import(id: Vec<u8>, validate: bool) -> Result<Self> {
         // Map the shared memory using the OS ID
-        let storage = S::import(id).context("Failed to open shared memory from ID")?;
-        // Verify the magic value
-        let magic_value = type_specific_magic::<T::Archived>();
-        unsafe {
-            let magic = std::ptr::read(storage.as_ptr() as *const u64);
-            if magic != magic_value {
-                anyhow::bail!("Invalid magic value in shared memory");
-            }
-        }
-        Ok(Self {
+        let storage = S::import(id).context(Error::Storage(
+            "Failed to open shared memory from ID".to_string(),
+        ))?;
+        // Verify the header, but skip the checksum because the data was verified by the creator
+        verify_header::<T>(storage.as_ref(), false)
+            .context(Error::CacheCorrupted("Invalid shared archive".to_string()))?;
+        let archive = Self {
             storage,
+            path: None,
             phantom_t: PhantomData,
-        })
+        };
+        if validate {
+            archive.access_validated().context(Error::CacheCorrupted(
+                "Shared archive failed validation".to_string(),
+            ))?;
+        }
+        Ok(archive)
+    }
+}
+
+impl<T, S> ArchiveStorage<T, S> {
+    pub(crate) fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+impl<T> ArchiveStorage<T, MmapStorage> {
+    /// Fail if the mapped file changed underneath, see `MmapStorage::check`.
+    pub(crate) fn check(&self) -> Result<()> {
+        self.storage.check()
     }
 }
 
-pub(crate) fn write_direct<T>(data: &T, path: &Path) -> Result<()>
+impl<T, S: Storage> ArchiveStorage<T, S> {
+    /// Size of the archive in bytes, including its header page.
+    pub(crate) fn size(&self) -> u64 {
+        self.storage.len() as u64
+    }
+
+    /// The first `len` bytes before the archive, as written by `with_prefix`.
+    pub(crate) fn prefix(&self, len: usize) -> &[u8] {
+        &self.storage.as_ref()[page_size::get()..page_size::get() + len]
+    }
+}
+
+impl<T, S> ArchiveStorage<T, S>
+where
+    T: Archive,
+    T::Archived: Portable + for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
+    S: Storage,
+{
+    /// Access the archived data after validating its structure with bytecheck.
+    pub(crate) fn access_validated(&self) -> Result<&T::Archived> {
+        Ok(rkyv::access::<T::Archived, rancor::Error>(
+            &self.storage.as_ref()[page_size::get()..],
+        )?)
+    }
+}
+
+pub(crate) fn write_direct<T>(data: &T, path: &Path, algorithm: ChecksumAlgorithm) -> Result<()>
 where
     // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
     for<'a> T: Serialize<
@@ -286,25 +349,15 @@ where
         >,
     >,
     T::Archived: 'static + Portable,
+    T: Schema,
 {
-    // Open file for writing and reading, truncating if it exists
-    let mut file = OpenOptions::new()
-        .write(true)
-        .read(true)
-        .create(true)
-        .truncate(true)
-        .open(path)?;
+    let (mut file, replacement) = open_for_writing(path)?;
 
-    // File layout: magic (u64) | checksum (u32) | remaining first page | data
-    // We will write magic and data first, then compute and write checksum
-    let seek_magic = SeekFrom::Start(0);
-    let seek_checksum = SeekFrom::Start(std::mem::size_of::<u64>() as u64);
+    // File layout: header | remaining first page | data
+    // We will write the data first, then compute the checksum and write the header
+    let seek_header = SeekFrom::Start(0);
     let seek_data = SeekFrom::Start(page_size::get() as u64);
 
-    // Calculate magic value and write it
-    file.seek(seek_magic)?;
-    file.write_all(&type_specific_magic::<T::Archived>().to_le_bytes())?;
-
     // Write main data with a buffered writer on top of file.
     // We drop the buffered writer immediately because it is not suitable for reading,
     // which we need later for checksum calculation.
@@ -317,11 +370,12 @@ where
         )?;
         buf_writer.flush()?;
     }
+    let payload_len = file.stream_position()? - page_size::get() as u64;
 
     // Calculate checksum of main data and write it
     file.seek(seek_data)?;
     let mut buffer = vec![0u8; BUFFER_SIZE];
-    let mut hasher = crc32fast::Hasher::new();
+    let mut hasher = algorithm.hasher();
     loop {
         let bytes_read = file.read(buffer.as_mut_slice())?;
         if bytes_read == 0 {
@@ -330,11 +384,135 @@ where
         hasher.update(&buffer[..bytes_read]);
     }
     let checksum = hasher.finalize();
-    file.seek(seek_checksum)?;
-    file.write_all(&checksum.to_le_bytes())?;
+    let header = Header::new(type_specific_magic::<T>(), algorithm, checksum, payload_len);
+    file.seek(seek_header)?;
+    file.write_all(&header.to_bytes())?;
+    if let Some(replacement) = replacement {
+        replacement.rename_to(path)?;
+    }
     Ok(())
 }
 
+/// A new file written next to a cache file in use, see `open_for_writing`. It is removed when
+/// dropped before it replaced the cache file, such that failed writes leave no partial files
+/// behind.
+struct Replacement {
+    path: PathBuf,
+    renamed: bool,
+}
+
+impl Replacement {
+    /// Replace `target` with the complete file.
+    fn rename_to(mut self, target: &Path) -> Result<()> {
+        std::fs::rename(&self.path, target)?;
+        self.renamed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Replacement {
+    fn drop(&mut self) {
+        if !self.renamed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Open `path` for writing and reading, locked exclusively and truncated. Processes which map
+/// the file hold a shared lock on it, see `MmapStorage::load`, so a file in use is not
+/// truncated underneath them: a new file is written next to it instead, which replaces it once
+/// complete, while they keep mapping the old one. Returns the file and the new file, if any.
+fn open_for_writing(path: &Path) -> Result<(File, Option<Replacement>)> {
+    let file = OpenOptions::new()
+        .write(true)
+        .read(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut replacement = path.as_os_str().to_owned();
+            replacement.push(format!(".tmp-{}", std::process::id()));
+            let replacement = Replacement {
+                path: PathBuf::from(replacement),
+                renamed: false,
+            };
+            let file = OpenOptions::new()
+                .write(true)
+                .read(true)
+                .create(true)
+                .truncate(true)
+                .open(&replacement.path)?;
+            return Ok((file, Some(replacement)));
+        }
+        // Files on filesystems without locks, such as some network filesystems, are written
+        // without one
+        Err(TryLockError::Error(_)) => {}
+    }
+    file.set_len(0)?;
+    Ok((file, None))
+}
+
+/// Check that `bytes` start with a valid header for `T` and contain the complete payload.
+fn verify_header<T: Schema>(
+    bytes: &[u8],
+    verify_checksum: bool,
+) -> std::result::Result<Header, Rejection> {
+    if bytes.len() < page_size::get() {
+        return Err(Rejection::TooSmall { len: bytes.len() });
+    }
+    let header = Header::from_bytes(bytes);
+    if header.format_version != FORMAT_VERSION {
+        return Err(Rejection::FormatVersion {
+            found: header.format_version,
+            expected: FORMAT_VERSION,
+            crate_version: header.crate_version,
+        });
+    }
+    let expected_magic = type_specific_magic::<T>();
+    if header.magic != expected_magic {
+        return Err(Rejection::WrongType {
+            found: header.magic,
+            expected: expected_magic,
+            schema: schema_id::<T>(),
+        });
+    }
+    let data = &bytes[page_size::get()..];
+    if data.len() as u64 != header.payload_len {
+        return Err(Rejection::Truncated {
+            expected: header.payload_len,
+            found: data.len() as u64,
+        });
+    }
+    let algorithm = ChecksumAlgorithm::from_id(header.checksum_algorithm).ok_or(
+        Rejection::UnknownChecksumAlgorithm {
+            id: header.checksum_algorithm,
+        },
+    )?;
+    if verify_checksum {
+        let checksum = algorithm.checksum(data);
+        if checksum != header.checksum {
+            return Err(Rejection::Checksum {
+                algorithm,
+                expected: header.checksum,
+                found: checksum,
+            });
+        }
+    }
+    Ok(header)
+}
+
+/// Read the header of the file at `path` without checking it, e.g., to inspect a cache which
+/// fails to load.
+pub(crate) fn read_header(path: &Path) -> Result<Header> {
+    let mut bytes = [0u8; HEADER_LEN];
+    File::open(path)?
+        .read_exact(&mut bytes)
+        .with_context(|| format!("{} is too small to contain a header", path.display()))?;
+    Ok(Header::from_bytes(&bytes))
+}
+
 pub(crate) fn load_bytes<S: MutableStorage>(path: &Path) -> Result<S> {
     let size = std::fs::metadata(path)?.len() as usize;
     let mut storage = S::new(size)?;
@@ -358,25 +536,55 @@ pub(crate) fn load_bytes<S: MutableStorage>(path: &Path) -> Result<S> {
     Ok(storage)
 }
 
-pub(crate) fn type_specific_magic<T: 'static>() -> u64 {
-    let mut hasher = DefaultHasher::new();
-    TypeId::of::<T>().hash(&mut hasher);
-    hasher.finish()
+/// Layout of the rkyv encoding which is part of every schema identifier.
+/// Update when upgrading rkyv or changing its format features.
+const RKYV_LAYOUT: &str = "rkyv-0.8-le-ptr64";
+
+/// Stable identification of the archived layout of a type.
+pub(crate) trait Schema {
+    /// Unique name of the type.
+    const NAME: &'static str;
+    /// Must be incremented whenever the archived layout of the type changes.
+    const VERSION: u32;
+}
+
+/// Human-readable identifier of the schema of `T`.
+pub(crate) fn schema_id<T: Schema>() -> String {
+    format!("{}:v{}:{}", T::NAME, T::VERSION, RKYV_LAYOUT)
+}
+
+/// Magic value derived from the schema identifier of `T`.
+/// This uses FNV-1a so that it stays stable across builds and compiler versions.
+pub(crate) fn type_specific_magic<T: Schema>() -> u64 {
+    fnv1a(schema_id::<T>().as_bytes())
 }
 
 #[cfg(test)]
 mod tests {
     use tempfile::NamedTempFile;
 
-    use crate::index::FastaMap;
+    use crate::index::{ArchivedMap, FastaMap, TrackMap};
+    use crate::progress::Progress;
     use shared_memory::ShmemConf;
 
     use super::*;
     use crate::storage::{MemoryStorage, MmapStorage, ShmemStorage};
+    use crate::util::NameSelection;
 
     #[test]
     fn test_create() {
-        let data = FastaMap::build("test-data/assemblies", true, 0, None, false, None).unwrap();
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
         let container: ArchiveStorage<FastaMap, MemoryStorage> = ArchiveStorage::new(data).unwrap();
         let reference = container.as_ref();
         reference.names();
@@ -384,7 +592,18 @@ mod tests {
 
     #[test]
     fn test_invalid_magic_shmem() {
-        let data = FastaMap::build("test-data/assemblies", true, 0, None, false, None).unwrap();
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
         let container: ArchiveStorage<FastaMap, ShmemStorage> = ArchiveStorage::new(data).unwrap();
         let handle = container.export();
         let os_id = String::from_utf8(handle.clone()).unwrap();
@@ -394,17 +613,61 @@ mod tests {
             // Write an invalid magic value at the beginning of the shared memory
             std::ptr::write(shmem_ptr as *mut u64, 0);
         }
-        let result: Result<ArchiveStorage<FastaMap, ShmemStorage>> = ArchiveStorage::import(handle);
+        let result: Result<ArchiveStorage<FastaMap, ShmemStorage>> =
+            ArchiveStorage::import(handle, false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_validated_import_shmem() {
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        let container: ArchiveStorage<FastaMap, ShmemStorage> = ArchiveStorage::new(data).unwrap();
+        let handle = container.export();
+        let new_container: ArchiveStorage<FastaMap, ShmemStorage> =
+            ArchiveStorage::import(handle.clone(), true).unwrap();
+        assert_eq!(container.as_ref().names(), new_container.as_ref().names());
+
+        // Corrupt the root pointer at the end of the data
+        let os_id = String::from_utf8(handle.clone()).unwrap();
+        let shmem: shared_memory::Shmem = ShmemConf::new().os_id(os_id).open().unwrap();
+        unsafe {
+            let end = shmem.as_ptr().add(shmem.len() - 8);
+            std::ptr::write_unaligned(end as *mut u64, u64::MAX);
+        }
+        let result: Result<ArchiveStorage<FastaMap, ShmemStorage>> =
+            ArchiveStorage::import(handle, true);
         assert!(result.is_err());
     }
 
     #[test]
     fn test_from_os_id() {
-        let data = FastaMap::build("test-data/assemblies", true, 0, None, false, None).unwrap();
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
         let container: ArchiveStorage<FastaMap, ShmemStorage> = ArchiveStorage::new(data).unwrap();
         let os_id = container.export();
         let new_container: ArchiveStorage<FastaMap, ShmemStorage> =
-            ArchiveStorage::import(os_id).unwrap();
+            ArchiveStorage::import(os_id, false).unwrap();
         assert_eq!(container.as_ref().names(), new_container.as_ref().names());
     }
 
@@ -414,69 +677,155 @@ mod tests {
         println!("Magic value: {:#x}", magic_value);
         assert_ne!(magic_value, 0);
         assert_ne!(magic_value, 1);
+        assert_ne!(magic_value, type_specific_magic::<TrackMap>());
     }
 
     #[test]
     fn test_write_and_read_from_file() {
         // Setup shmem fasta map
-        let data = FastaMap::build("test-data/assemblies", true, 0, None, false, None).unwrap();
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
         let container: ArchiveStorage<FastaMap, MemoryStorage> =
             ArchiveStorage::new(data.clone()).unwrap();
         // Write to a temporary file using write_to_file_direct
         let temp_file = NamedTempFile::new().unwrap();
         let temp_path = temp_file.path();
-        write_direct(&data, temp_path).unwrap();
+        write_direct(&data, temp_path, ChecksumAlgorithm::Crc32).unwrap();
         // Load again
         let new_container: ArchiveStorage<FastaMap, MmapStorage> =
-            ArchiveStorage::load(temp_path).unwrap().unwrap();
+            ArchiveStorage::load(temp_path, true).unwrap().unwrap();
         assert_eq!(container.as_ref().names(), new_container.as_ref().names());
     }
 
+    #[test]
+    fn test_write_while_mapped() {
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        write_direct(&data, &path, ChecksumAlgorithm::Crc32).unwrap();
+        let mapped: ArchiveStorage<FastaMap, MmapStorage> =
+            ArchiveStorage::load(&path, true).unwrap().unwrap();
+        // The mapped file is replaced instead of truncated
+        write_direct(&data, &path, ChecksumAlgorithm::Xxh3).unwrap();
+        mapped.check().unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        let reloaded: ArchiveStorage<FastaMap, MmapStorage> =
+            ArchiveStorage::load(&path, true).unwrap().unwrap();
+        assert_eq!(mapped.as_ref().names(), reloaded.as_ref().names());
+        drop((mapped, reloaded));
+        // Without readers, the file is rewritten in place
+        write_direct(&data, &path, ChecksumAlgorithm::Crc32).unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // A write which fails after creating the new file removes it again
+        let reader = File::open(&path).unwrap();
+        reader.lock_shared().unwrap();
+        let (_, replacement) = open_for_writing(&path).unwrap();
+        assert!(replacement.is_some());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+        drop(replacement);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
     #[test]
     fn test_write_and_read_invalid_magic() {
         // Setup shmem fasta map
-        let data = FastaMap::build("test-data/assemblies", true, 0, None, false, None).unwrap();
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
         // Write to a temporary file using write_to_file_direct
         let temp_file = NamedTempFile::new().unwrap();
         let temp_path = temp_file.path();
-        write_direct(&data, temp_path).unwrap();
+        write_direct(&data, temp_path, ChecksumAlgorithm::Crc32).unwrap();
         // Corrupt the magic value in the file
         let mut file = OpenOptions::new().write(true).open(temp_path).unwrap();
         file.write_all(&[0u8; 8]).unwrap();
         file.flush().unwrap();
         drop(file);
         // Attempt to read the shared memory archive back from the file
-        let result: Option<ArchiveStorage<FastaMap, MmapStorage>> =
-            ArchiveStorage::load(temp_path).unwrap();
-        assert!(result.is_none());
+        let result: std::result::Result<ArchiveStorage<FastaMap, MmapStorage>, Rejection> =
+            ArchiveStorage::load(temp_path, true).unwrap();
+        assert!(matches!(result, Err(Rejection::WrongType { .. })));
     }
 
     #[test]
     fn test_truncate_file_to_zero() {
         // Setup shmem fasta map
-        let data = FastaMap::build("test-data/assemblies", true, 0, None, false, None).unwrap();
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
         // Write to a temporary file using write_to_file_direct
         let temp_file = NamedTempFile::new().unwrap();
         let temp_path = temp_file.path();
-        write_direct(&data, temp_path).unwrap();
+        write_direct(&data, temp_path, ChecksumAlgorithm::Crc32).unwrap();
         // Truncate the file to size 0
         let file = OpenOptions::new().write(true).open(temp_path).unwrap();
         file.set_len(0).unwrap();
         drop(file);
         // Attempt to read the shared memory archive back from the file
-        let result: Option<ArchiveStorage<FastaMap, MmapStorage>> =
-            ArchiveStorage::load(temp_path).unwrap();
-        assert!(result.is_none());
+        let result: std::result::Result<ArchiveStorage<FastaMap, MmapStorage>, Rejection> =
+            ArchiveStorage::load(temp_path, true).unwrap();
+        assert!(matches!(result, Err(Rejection::TooSmall { .. })));
     }
 
     #[test]
     fn test_write_and_read_corrupted_data() {
         // Setup shmem fasta map
-        let data = FastaMap::build("test-data/assemblies", true, 0, None, false, None).unwrap();
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
         // Write to a temporary file using write_to_file_direct
         let temp_file = NamedTempFile::new().unwrap();
         let temp_path = temp_file.path();
-        write_direct(&data, temp_path).unwrap();
+        write_direct(&data, temp_path, ChecksumAlgorithm::Crc32).unwrap();
         // Corrupt the data in the file (not the magic value or checksum)
         let data_offset = page_size::get();
         let file_len = std::fs::metadata(temp_path).unwrap().len() as usize;
@@ -489,8 +838,113 @@ mod tests {
         file.flush().unwrap();
         drop(file);
         // Attempt to read the shared memory archive back from the file
-        let result: Option<ArchiveStorage<FastaMap, MmapStorage>> =
-            ArchiveStorage::load(temp_path).unwrap();
-        assert!(result.is_none());
+        let result: std::result::Result<ArchiveStorage<FastaMap, MmapStorage>, Rejection> =
+            ArchiveStorage::load(temp_path, true).unwrap();
+        assert!(matches!(result, Err(Rejection::Checksum { .. })));
+    }
+
+    #[test]
+    fn test_checksum_algorithms() {
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        for name in ["none", "crc32", "xxh3", "blake3"] {
+            let algorithm = ChecksumAlgorithm::parse(name).unwrap();
+            let temp_file = NamedTempFile::new().unwrap();
+            let temp_path = temp_file.path();
+            write_direct(&data, temp_path, algorithm).unwrap();
+            let header = Header::from_bytes(&std::fs::read(temp_path).unwrap());
+            assert_eq!(header.checksum_algorithm, algorithm.to_id());
+            let result: std::result::Result<ArchiveStorage<FastaMap, MmapStorage>, Rejection> =
+                ArchiveStorage::load(temp_path, true).unwrap();
+            assert!(result.is_ok());
+
+            // Corrupt the last byte of the data
+            let file_len = std::fs::metadata(temp_path).unwrap().len();
+            let mut file = OpenOptions::new().write(true).open(temp_path).unwrap();
+            file.seek(SeekFrom::Start(file_len - 1)).unwrap();
+            file.write_all(&[0xff]).unwrap();
+            drop(file);
+            let result: std::result::Result<ArchiveStorage<FastaMap, MmapStorage>, Rejection> =
+                ArchiveStorage::load(temp_path, true).unwrap();
+            assert_eq!(
+                matches!(result, Err(Rejection::Checksum { .. })),
+                algorithm != ChecksumAlgorithm::None
+            );
+            // Skipping verification accepts the file
+            let result: std::result::Result<ArchiveStorage<FastaMap, MmapStorage>, Rejection> =
+                ArchiveStorage::load(temp_path, false).unwrap();
+            assert!(result.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_write_and_read_other_format_version() {
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path();
+        write_direct(&data, temp_path, ChecksumAlgorithm::Crc32).unwrap();
+        // Overwrite the header with one from a legacy format version
+        let mut header = Header::from_bytes(&std::fs::read(temp_path).unwrap());
+        header.format_version = 1;
+        let mut file = OpenOptions::new().write(true).open(temp_path).unwrap();
+        file.write_all(&header.to_bytes()).unwrap();
+        drop(file);
+        let result: std::result::Result<ArchiveStorage<FastaMap, MmapStorage>, Rejection> =
+            ArchiveStorage::load(temp_path, true).unwrap();
+        assert!(matches!(
+            result,
+            Err(Rejection::FormatVersion {
+                found: 1,
+                expected: FORMAT_VERSION,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_write_and_read_truncated_payload() {
+        let data = FastaMap::build(
+            "test-data/assemblies",
+            true,
+            0,
+            false,
+            false,
+            false,
+            None,
+            &Progress::default(),
+            &NameSelection::default(),
+        )
+        .unwrap();
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path();
+        write_direct(&data, temp_path, ChecksumAlgorithm::Crc32).unwrap();
+        let file_len = std::fs::metadata(temp_path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(temp_path).unwrap();
+        file.set_len(file_len - 16).unwrap();
+        drop(file);
+        let result: std::result::Result<ArchiveStorage<FastaMap, MmapStorage>, Rejection> =
+            ArchiveStorage::load(temp_path, true).unwrap();
+        assert!(matches!(result, Err(Rejection::Truncated { .. })));
     }
 }
@@ -0,0 +1,118 @@
+use anyhow::{bail, Result};
+
+/// Length of the checksum field in the header, large enough for the widest algorithm.
+pub(crate) const CHECKSUM_LEN: usize = 32;
+
+/// Checksum algorithm used for the payload of an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChecksumAlgorithm {
+    None,
+    Crc32,
+    Xxh3,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    pub(crate) fn parse(name: &str) -> Result<Self> {
+        match name {
+            "none" => Ok(ChecksumAlgorithm::None),
+            "crc32" => Ok(ChecksumAlgorithm::Crc32),
+            "xxh3" => Ok(ChecksumAlgorithm::Xxh3),
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            _ => bail!("Unknown checksum algorithm: {}", name),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::None => "none",
+            ChecksumAlgorithm::Crc32 => "crc32",
+            ChecksumAlgorithm::Xxh3 => "xxh3",
+            ChecksumAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Number of significant bytes of the checksum.
+    pub(crate) fn len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::None => 0,
+            ChecksumAlgorithm::Crc32 => 4,
+            ChecksumAlgorithm::Xxh3 => 16,
+            ChecksumAlgorithm::Blake3 => 32,
+        }
+    }
+
+    pub(crate) fn to_id(self) -> u32 {
+        match self {
+            ChecksumAlgorithm::None => 0,
+            ChecksumAlgorithm::Crc32 => 1,
+            ChecksumAlgorithm::Xxh3 => 2,
+            ChecksumAlgorithm::Blake3 => 3,
+        }
+    }
+
+    pub(crate) fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(ChecksumAlgorithm::None),
+            1 => Some(ChecksumAlgorithm::Crc32),
+            2 => Some(ChecksumAlgorithm::Xxh3),
+            3 => Some(ChecksumAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn hasher(self) -> Checksummer {
+        match self {
+            ChecksumAlgorithm::None => Checksummer::None,
+            ChecksumAlgorithm::Crc32 => Checksummer::Crc32(crc32fast::Hasher::new()),
+            ChecksumAlgorithm::Xxh3 => Checksummer::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+            ChecksumAlgorithm::Blake3 => Checksummer::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub(crate) fn checksum(self, data: &[u8]) -> [u8; CHECKSUM_LEN] {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// Streaming checksum calculation for any of the supported algorithms.
+pub(crate) enum Checksummer {
+    None,
+    Crc32(crc32fast::Hasher),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Checksummer {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Checksummer::None => {}
+            Checksummer::Crc32(hasher) => hasher.update(data),
+            Checksummer::Xxh3(hasher) => hasher.update(data),
+            Checksummer::Blake3(hasher) => {
+                // Large inputs benefit from multithreaded hashing
+                hasher.update_rayon(data);
+            }
+        }
+    }
+
+    /// Finalize the checksum, zero-padded to `CHECKSUM_LEN` bytes.
+    pub(crate) fn finalize(self) -> [u8; CHECKSUM_LEN] {
+        let mut result = [0u8; CHECKSUM_LEN];
+        match self {
+            Checksummer::None => {}
+            Checksummer::Crc32(hasher) => {
+                result[..4].copy_from_slice(&hasher.finalize().to_le_bytes());
+            }
+            Checksummer::Xxh3(hasher) => {
+                result[..16].copy_from_slice(&hasher.digest128().to_le_bytes());
+            }
+            Checksummer::Blake3(hasher) => {
+                result.copy_from_slice(hasher.finalize().as_bytes());
+            }
+        }
+        result
+    }
+}
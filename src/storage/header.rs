@@ -0,0 +1,183 @@
+use std::fmt;
+
+use crate::storage::checksum::{ChecksumAlgorithm, CHECKSUM_LEN};
+
+/// Version of the archive file format, i.e., the header layout and payload placement.
+/// Archives with a different format version are rejected and rebuilt.
+pub(crate) const FORMAT_VERSION: u32 = 3;
+
+/// Version of this crate, recorded in the header for diagnostics.
+const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const CRATE_VERSION_LEN: usize = 32;
+
+// Header layout (little endian), located at the start of the first page:
+// magic (u64) | checksum algorithm (u32) | format version (u32) | payload length (u64)
+// | crate version (32 bytes) | checksum (32 bytes)
+// The format version must stay at the same offset in all versions.
+const MAGIC_OFFSET: usize = 0;
+const CHECKSUM_ALGORITHM_OFFSET: usize = 8;
+const FORMAT_VERSION_OFFSET: usize = 12;
+const PAYLOAD_LEN_OFFSET: usize = 16;
+const CRATE_VERSION_OFFSET: usize = 24;
+const CHECKSUM_OFFSET: usize = CRATE_VERSION_OFFSET + CRATE_VERSION_LEN;
+pub(crate) const HEADER_LEN: usize = CHECKSUM_OFFSET + CHECKSUM_LEN;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Header {
+    pub(crate) magic: u64,
+    pub(crate) checksum_algorithm: u32,
+    pub(crate) checksum: [u8; CHECKSUM_LEN],
+    pub(crate) format_version: u32,
+    pub(crate) payload_len: u64,
+    pub(crate) crate_version: String,
+}
+
+impl Header {
+    pub(crate) fn new(
+        magic: u64,
+        checksum_algorithm: ChecksumAlgorithm,
+        checksum: [u8; CHECKSUM_LEN],
+        payload_len: u64,
+    ) -> Self {
+        Header {
+            magic,
+            checksum_algorithm: checksum_algorithm.to_id(),
+            checksum,
+            format_version: FORMAT_VERSION,
+            payload_len,
+            crate_version: CRATE_VERSION.to_string(),
+        }
+    }
+
+    pub(crate) fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[MAGIC_OFFSET..CHECKSUM_ALGORITHM_OFFSET].copy_from_slice(&self.magic.to_le_bytes());
+        bytes[CHECKSUM_ALGORITHM_OFFSET..FORMAT_VERSION_OFFSET]
+            .copy_from_slice(&self.checksum_algorithm.to_le_bytes());
+        bytes[FORMAT_VERSION_OFFSET..PAYLOAD_LEN_OFFSET]
+            .copy_from_slice(&self.format_version.to_le_bytes());
+        bytes[PAYLOAD_LEN_OFFSET..CRATE_VERSION_OFFSET]
+            .copy_from_slice(&self.payload_len.to_le_bytes());
+        let version = self.crate_version.as_bytes();
+        let version_len = version.len().min(CRATE_VERSION_LEN);
+        bytes[CRATE_VERSION_OFFSET..CRATE_VERSION_OFFSET + version_len]
+            .copy_from_slice(&version[..version_len]);
+        bytes[CHECKSUM_OFFSET..HEADER_LEN].copy_from_slice(&self.checksum);
+        bytes
+    }
+
+    /// Parse the header from the beginning of `bytes`, which must be at least `HEADER_LEN` long.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let version = &bytes[CRATE_VERSION_OFFSET..CRATE_VERSION_OFFSET + CRATE_VERSION_LEN];
+        let version_len = version
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(version.len());
+        Header {
+            magic: u64_at(MAGIC_OFFSET),
+            checksum_algorithm: u32_at(CHECKSUM_ALGORITHM_OFFSET),
+            checksum: bytes[CHECKSUM_OFFSET..HEADER_LEN].try_into().unwrap(),
+            format_version: u32_at(FORMAT_VERSION_OFFSET),
+            payload_len: u64_at(PAYLOAD_LEN_OFFSET),
+            crate_version: String::from_utf8_lossy(&version[..version_len]).into_owned(),
+        }
+    }
+}
+
+/// Reason why an archive was not accepted.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Rejection {
+    /// The storage is too small to hold the header.
+    TooSmall { len: usize },
+    /// The archive was written with another file format version.
+    FormatVersion {
+        found: u32,
+        expected: u32,
+        crate_version: String,
+    },
+    /// The archive contains another type or another schema version of the same type.
+    WrongType {
+        found: u64,
+        expected: u64,
+        schema: String,
+    },
+    /// The payload length does not match the header.
+    Truncated { expected: u64, found: u64 },
+    /// The header references an unknown checksum algorithm.
+    UnknownChecksumAlgorithm { id: u32 },
+    /// The payload checksum does not match the header.
+    Checksum {
+        algorithm: ChecksumAlgorithm,
+        expected: [u8; CHECKSUM_LEN],
+        found: [u8; CHECKSUM_LEN],
+    },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::TooSmall { len } => {
+                write!(
+                    f,
+                    "storage of {} bytes is too small to contain a header",
+                    len
+                )
+            }
+            Rejection::FormatVersion {
+                found,
+                expected,
+                crate_version,
+            } => {
+                let creator = if crate_version.is_empty() {
+                    "an older fastar-loader".to_string()
+                } else {
+                    format!("fastar-loader {}", crate_version)
+                };
+                write!(
+                    f,
+                    "format version mismatch: written by {} with format version {}, expected {}",
+                    creator, found, expected
+                )
+            }
+            Rejection::WrongType {
+                found,
+                expected,
+                schema,
+            } => write!(
+                f,
+                "wrong type: expected magic {:016x} ({}), found {:016x}",
+                expected, schema, found
+            ),
+            Rejection::Truncated { expected, found } => write!(
+                f,
+                "corrupted: expected {} payload bytes, found {}",
+                expected, found
+            ),
+            Rejection::UnknownChecksumAlgorithm { id } => {
+                write!(f, "unknown checksum algorithm with id {}", id)
+            }
+            Rejection::Checksum {
+                algorithm,
+                expected,
+                found,
+            } => write!(
+                f,
+                "corrupted: {} checksum mismatch, expected {}, computed {}",
+                algorithm.name(),
+                to_hex(&expected[..algorithm.len()]),
+                to_hex(&found[..algorithm.len()])
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
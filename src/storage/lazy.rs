@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{Context, Result};
+use rkyv::api::high::HighValidator;
+use rkyv::bytecheck::CheckBytes;
+use rkyv::ser::writer::IoWriter;
+use rkyv::util::AlignedVec;
+use rkyv::{rancor, Portable, Serialize};
+
+use crate::error::Error;
+use crate::storage::archive::Schema;
+use crate::storage::{ArchiveStorage, MemoryStorage};
+
+type IndexFn<T> = Box<dyn Fn(&str) -> Result<T> + Send + Sync>;
+
+/// A map of which only the names are known upfront. Each entry is indexed on the first access
+/// to it and kept in memory as an archive of its own.
+pub(crate) struct LazyStorage<T> {
+    names: Vec<String>,
+    entries: Vec<OnceLock<ArchiveStorage<T, MemoryStorage>>>,
+    index: IndexFn<T>,
+    load_lock: Mutex<()>,
+}
+
+impl<T> LazyStorage<T>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T::Archived:
+        Sync + Send + 'static + Portable + for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
+    T: Sync + Send + 'static,
+    T: Schema,
+{
+    /// Create the storage for `names`. `index` builds a map containing only the given name.
+    pub(crate) fn new(
+        mut names: Vec<String>,
+        index: impl Fn(&str) -> Result<T> + Send + Sync + 'static,
+    ) -> Self {
+        names.sort();
+        names.dedup();
+        LazyStorage {
+            entries: names.iter().map(|_| OnceLock::new()).collect(),
+            names,
+            index: Box::new(index),
+            load_lock: Mutex::new(()),
+        }
+    }
+
+    pub(crate) fn names(&self) -> Vec<&str> {
+        self.names.iter().map(|name| name.as_str()).collect()
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.names
+            .binary_search_by(|probe| probe.as_str().cmp(name))
+            .is_ok()
+    }
+
+    /// Get the archive of `name`, indexing it if necessary.
+    pub(crate) fn get(&self, name: &str) -> Result<&T::Archived> {
+        let entry = self
+            .names
+            .binary_search_by(|probe| probe.as_str().cmp(name))
+            .map_err(|_| Error::NameNotFound(name.to_string()))?;
+        self.entry(entry)
+    }
+
+    fn entry(&self, entry: usize) -> Result<&T::Archived> {
+        if let Some(archive) = self.entries[entry].get() {
+            return Ok(archive.as_ref());
+        }
+        // Only one thread indexes at a time to avoid indexing an entry twice
+        let _guard = self.load_lock.lock().unwrap();
+        if let Some(archive) = self.entries[entry].get() {
+            return Ok(archive.as_ref());
+        }
+        let name = &self.names[entry];
+        let map = (self.index)(name).with_context(|| format!("Error indexing {}", name))?;
+        let archive = ArchiveStorage::<T, MemoryStorage>::new(map)?;
+        Ok(self.entries[entry].get_or_init(|| archive).as_ref())
+    }
+
+    /// Get the archives of all entries, indexing them if necessary.
+    pub(crate) fn all(&self) -> Result<Vec<&T::Archived>> {
+        (0..self.entries.len())
+            .map(|entry| self.entry(entry))
+            .collect()
+    }
+}
@@ -1,12 +1,53 @@
-use std::{fs::File, path::PathBuf};
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::SystemTime;
 
+use crate::error::Error;
 use crate::storage::{LoadableStorage, SharableStorage, Storage};
-use anyhow::Result;
+use anyhow::{bail, Result};
 use memmap2::Mmap;
 
 pub(crate) struct MmapStorage {
     path: String,
+    /// The mapped file, kept open to hold its shared lock and to detect changes to it, see
+    /// `check`.
+    file: File,
     mmap: Mmap,
+    generation: Generation,
+}
+
+/// Length and modification time of a file, which change when it is truncated or rewritten in
+/// place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Generation {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Generation {
+    fn of(file: &File) -> Result<Self> {
+        let metadata = file.metadata()?;
+        Ok(Generation {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+impl MmapStorage {
+    /// Fail if the mapped file was truncated or modified since it was mapped. Accessing the
+    /// mapping beyond the end of a truncated file kills the process with SIGBUS, e.g., after a
+    /// cleanup script truncated a shared scratch directory, so this is checked before each
+    /// access. A file which is replaced or deleted instead stays mapped and readable.
+    pub(crate) fn check(&self) -> Result<()> {
+        if Generation::of(&self.file)? != self.generation {
+            bail!(Error::CacheCorrupted(format!(
+                "Cache file {} was truncated or modified while it was mapped, load it again",
+                self.path
+            )));
+        }
+        Ok(())
+    }
 }
 
 impl AsRef<[u8]> for MmapStorage {
@@ -23,10 +64,17 @@ impl LoadableStorage for MmapStorage {
         Self: Sized,
     {
         let file = File::open(path)?;
+        // Held until the storage is dropped, so that writers replace the file instead of
+        // truncating it, see `write_direct`. Waits for a writer to finish the file. Files on
+        // filesystems without locks, such as some network filesystems, are mapped without one.
+        let _ = file.lock_shared();
+        let generation = Generation::of(&file)?;
         let mmap = unsafe { Mmap::map(&file)? };
         Ok(MmapStorage {
             path: path.to_string_lossy().into_owned(),
+            file,
             mmap,
+            generation,
         })
     }
 }
@@ -41,3 +89,38 @@ impl SharableStorage for MmapStorage {
         Self::load(&PathBuf::from(path))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        std::fs::write(&path, vec![1u8; 8192]).unwrap();
+        let storage = MmapStorage::load(&path).unwrap();
+        assert!(storage.check().is_ok());
+
+        // A replaced file stays mapped
+        let replacement = dir.path().join("replacement");
+        std::fs::write(&replacement, b"new").unwrap();
+        std::fs::rename(&replacement, &path).unwrap();
+        assert!(storage.check().is_ok());
+        assert_eq!(storage.as_ref()[0], 1);
+
+        // A truncated file is detected without touching the mapping
+        let storage = MmapStorage::load(&path).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        let e = storage.check().unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::CacheCorrupted(_))
+        ));
+    }
+}
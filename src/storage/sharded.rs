@@ -0,0 +1,168 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{Context, Result};
+use rkyv::api::high::HighValidator;
+use rkyv::bytecheck::CheckBytes;
+use rkyv::ser::writer::IoWriter;
+use rkyv::util::AlignedVec;
+use rkyv::{rancor, Archive, Portable, Serialize};
+
+use crate::error::Error;
+use crate::index::{shard_of, ShardDirectory};
+use crate::storage::archive::Schema;
+use crate::storage::{ArchiveStorage, MmapStorage};
+
+/// Path of the directory file of a sharded cache.
+pub(crate) fn directory_path(base: &Path) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(".shards");
+    PathBuf::from(path)
+}
+
+/// Path of a single shard file of a sharded cache.
+pub(crate) fn shard_path(base: &Path, shard: usize) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(format!(".shard-{:04}", shard));
+    PathBuf::from(path)
+}
+
+/// A cache which is split into several shard files, each of which is memory-mapped lazily
+/// on the first access to one of its names.
+pub(crate) struct ShardedStorage<T> {
+    base: PathBuf,
+    directory: ArchiveStorage<ShardDirectory, MmapStorage>,
+    shards: Vec<OnceLock<ArchiveStorage<T, MmapStorage>>>,
+    verify_checksum: bool,
+    validate: bool,
+    load_lock: Mutex<()>,
+}
+
+impl<T> ShardedStorage<T>
+where
+    // Trait bounds for rkyv serialization and deserialization, both to AlignedVec and IoWriter
+    for<'a> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    for<'a, 'b, 'c, 'd> T: Serialize<
+        rancor::Strategy<
+            rkyv::ser::Serializer<
+                &'b mut IoWriter<&'c mut BufWriter<&'d mut File>>,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::ser::sharing::Share,
+            >,
+            rancor::Error,
+        >,
+    >,
+    T::Archived:
+        Sync + Send + 'static + Portable + for<'v> CheckBytes<HighValidator<'v, rancor::Error>>,
+    T: Sync + Send + 'static,
+    T: Schema,
+{
+    /// Open the directory of a sharded cache. The shards themselves are loaded on demand.
+    pub(crate) fn open(base: &Path, verify_checksum: bool) -> Result<Self> {
+        let directory_path = directory_path(base);
+        let directory =
+            ArchiveStorage::<ShardDirectory, MmapStorage>::load(&directory_path, verify_checksum)?
+                .map_err(|rejection| {
+                    Error::CacheCorrupted(format!(
+                        "Shard directory {} rejected: {}",
+                        directory_path.display(),
+                        rejection
+                    ))
+                })?;
+        let num_shards = directory.as_ref().num_shards();
+        Ok(ShardedStorage {
+            base: base.to_path_buf(),
+            directory,
+            shards: (0..num_shards).map(|_| OnceLock::new()).collect(),
+            verify_checksum,
+            validate: false,
+            load_lock: Mutex::new(()),
+        })
+    }
+
+    /// Base path of the shard files.
+    pub(crate) fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// Size of the directory and all shard files in bytes, without loading the shards.
+    pub(crate) fn size(&self) -> u64 {
+        let shards = (0..self.shards.len())
+            .filter_map(|shard| std::fs::metadata(shard_path(&self.base, shard)).ok())
+            .map(|metadata| metadata.len())
+            .sum::<u64>();
+        self.directory.size() + shards
+    }
+
+    /// The directory of names and shards, checked for truncation like the shards.
+    pub(crate) fn directory(&self) -> Result<&<ShardDirectory as Archive>::Archived> {
+        self.directory.check()?;
+        Ok(self.directory.as_ref())
+    }
+
+    /// Get the shard containing `name`, loading it if necessary.
+    pub(crate) fn get(&self, name: &str) -> Result<&T::Archived> {
+        self.shard(shard_of(name, self.shards.len()))
+    }
+
+    /// Get the shard with index `shard`, loading it if necessary. Loaded shards are checked
+    /// for truncation, see `MmapStorage::check`.
+    pub(crate) fn shard(&self, shard: usize) -> Result<&T::Archived> {
+        if let Some(archive) = self.shards[shard].get() {
+            archive.check()?;
+            return Ok(archive.as_ref());
+        }
+        // Only one thread loads shards at a time to avoid loading a shard twice
+        let _guard = self.load_lock.lock().unwrap();
+        if let Some(archive) = self.shards[shard].get() {
+            return Ok(archive.as_ref());
+        }
+        let path = shard_path(&self.base, shard);
+        let archive = ArchiveStorage::<T, MmapStorage>::load(&path, self.verify_checksum)
+            .with_context(|| format!("Error loading shard {}", path.display()))?
+            .map_err(|rejection| {
+                Error::CacheCorrupted(format!(
+                    "Shard {} rejected ({}), reload with force_build=True to rebuild the cache",
+                    path.display(),
+                    rejection
+                ))
+            })?;
+        if self.validate {
+            archive.access_validated().with_context(|| {
+                Error::CacheCorrupted(format!("Shard {} failed validation", path.display()))
+            })?;
+        }
+        Ok(self.shards[shard].get_or_init(|| archive).as_ref())
+    }
+
+    /// Get all shards, loading them if necessary.
+    pub(crate) fn all(&self) -> Result<Vec<&T::Archived>> {
+        (0..self.shards.len())
+            .map(|shard| self.shard(shard))
+            .collect()
+    }
+
+    /// Open a sharded cache from a handle exported by another process.
+    /// If `validate` is set, every shard is validated with bytecheck when it is loaded.
+    pub(crate) fn import(data: Vec<u8>, validate: bool) -> Result<Self> {
+        let base = String::from_utf8(data)?;
+        let mut storage = Self::open(Path::new(&base), false)?;
+        storage.validate = validate;
+        Ok(storage)
+    }
+
+    pub(crate) fn export(&self) -> Vec<u8> {
+        self.base.to_string_lossy().into_owned().into_bytes()
+    }
+}
@@ -1,16 +1,59 @@
+use std::fs::OpenOptions;
+use std::mem::ManuallyDrop;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
 use crate::storage::archive::{
     load_bytes, LoadableStorage, MutableStorage, SharableStorage, Storage,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use memmap2::MmapMut;
 use shared_memory::{Shmem, ShmemConf};
 
+/// Environment variable with a directory on another tmpfs, which is used for shared memory
+/// instead of `/dev/shm`, e.g., if `/dev/shm` is small in a container.
+const SHMEM_DIR_VAR: &str = "FASTAR_LOADER_SHMEM_DIR";
+
+/// Directory backing POSIX shared memory on Linux.
+const DEFAULT_SHMEM_DIR: &str = "/dev/shm";
+
+/// Directory below the temporary directory for shared memory in files on platforms without
+/// POSIX shared memory, i.e., Windows.
+#[cfg_attr(unix, allow(dead_code))]
+const TEMP_SHMEM_DIR: &str = "fastar-loader-shmem";
+
+/// Prefix of exported handles of shared memory in a file, to tell them apart from POSIX ids.
+const FILE_PREFIX: &str = "file:";
+
+/// Prefix of the names of all segments created by this crate, followed by the PID of the
+/// creator, the creation time in seconds since the epoch and a random suffix.
+const SEGMENT_PREFIX: &str = "fastar-loader-";
+
 pub(crate) struct ShmemStorage {
-    shmem: Shmem,
+    backend: Backend,
+}
+
+enum Backend {
+    /// POSIX shared memory, which is removed when its creator drops it.
+    Posix(Shmem),
+    /// A memory-mapped file below `$FASTAR_LOADER_SHMEM_DIR` or, on Windows, the temporary
+    /// directory, which is removed by its creator.
+    File {
+        path: PathBuf,
+        /// Unmapped before removing the file, which Windows refuses for mapped files.
+        mmap: ManuallyDrop<MmapMut>,
+        owner: bool,
+    },
 }
 
 impl AsRef<[u8]> for ShmemStorage {
     fn as_ref(&self) -> &[u8] {
-        unsafe { self.shmem.as_slice() }
+        match &self.backend {
+            Backend::Posix(shmem) => unsafe { shmem.as_slice() },
+            Backend::File { mmap, .. } => &mmap[..],
+        }
     }
 }
 
@@ -18,12 +61,58 @@ impl Storage for ShmemStorage {}
 
 impl MutableStorage for ShmemStorage {
     fn new(size: usize) -> anyhow::Result<Self> {
-        let shmem = ShmemConf::new().size(size).create()?;
-        Ok(ShmemStorage { shmem })
+        let dir = file_dir();
+        let location = dir.as_deref().unwrap_or(Path::new(DEFAULT_SHMEM_DIR));
+        if let Some(available) = free_space(location) {
+            if size as u64 > available {
+                return Err(Error::Storage(format!(
+                    "Shared memory needs {} bytes, but only {} bytes are free in {}. Free some \
+                     space, use storage_method=\"mmap\" or \"auto\", or set {} to a directory \
+                     on a larger tmpfs.",
+                    size,
+                    available,
+                    location.display(),
+                    SHMEM_DIR_VAR
+                ))
+                .into());
+            }
+        }
+        let backend = match dir {
+            None => {
+                let conf = ShmemConf::new()
+                    .size(size)
+                    .os_id(format!("/{}", segment_name()));
+                Backend::Posix(conf.create().with_context(|| {
+                    format!("Could not allocate {} bytes of shared memory", size)
+                })?)
+            }
+            Some(dir) => {
+                std::fs::create_dir_all(&dir)
+                    .with_context(|| format!("Could not create {}", dir.display()))?;
+                let path = dir.join(segment_name());
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .with_context(|| format!("Could not create {}", path.display()))?;
+                file.set_len(size as u64)?;
+                let mmap = unsafe { MmapMut::map_mut(&file)? };
+                Backend::File {
+                    path,
+                    mmap: ManuallyDrop::new(mmap),
+                    owner: true,
+                }
+            }
+        };
+        Ok(ShmemStorage { backend })
     }
 
     fn as_ref_mut(&mut self) -> &mut [u8] {
-        unsafe { self.shmem.as_slice_mut() }
+        match &mut self.backend {
+            Backend::Posix(shmem) => unsafe { shmem.as_slice_mut() },
+            Backend::File { mmap, .. } => &mut mmap[..],
+        }
     }
 }
 
@@ -35,7 +124,12 @@ impl LoadableStorage for ShmemStorage {
 
 impl SharableStorage for ShmemStorage {
     fn export(&self) -> Vec<u8> {
-        self.shmem.get_os_id().as_bytes().to_vec()
+        match &self.backend {
+            Backend::Posix(shmem) => shmem.get_os_id().as_bytes().to_vec(),
+            Backend::File { path, .. } => {
+                format!("{}{}", FILE_PREFIX, path.to_string_lossy()).into_bytes()
+            }
+        }
     }
 
     fn import(data: Vec<u8>) -> Result<Self>
@@ -43,7 +137,301 @@ impl SharableStorage for ShmemStorage {
         Self: Sized,
     {
         let os_id_str = String::from_utf8(data)?;
-        let shmem = ShmemConf::new().os_id(os_id_str).open()?;
-        Ok(ShmemStorage { shmem })
+        let backend = match os_id_str.strip_prefix(FILE_PREFIX) {
+            None => Backend::Posix(ShmemConf::new().os_id(os_id_str).open()?),
+            Some(path) => {
+                let file = OpenOptions::new().read(true).write(true).open(path)?;
+                Backend::File {
+                    path: PathBuf::from(path),
+                    mmap: ManuallyDrop::new(unsafe { MmapMut::map_mut(&file)? }),
+                    owner: false,
+                }
+            }
+        };
+        Ok(ShmemStorage { backend })
+    }
+}
+
+impl Drop for ShmemStorage {
+    fn drop(&mut self) {
+        if let Backend::File { path, mmap, owner } = &mut self.backend {
+            // SAFETY: The mapping is not used after this point
+            unsafe { ManuallyDrop::drop(mmap) };
+            if *owner {
+                // Other processes keep their mappings, but can no longer attach. On Windows, the
+                // removal fails while other processes map the file, which is then left to
+                // `cleanup_segments`.
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// The directory configured by `$FASTAR_LOADER_SHMEM_DIR`, if any.
+fn shmem_dir() -> Option<PathBuf> {
+    std::env::var_os(SHMEM_DIR_VAR)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The directory for shared memory in files, which is `$FASTAR_LOADER_SHMEM_DIR` if set, and
+/// otherwise only used on platforms without POSIX shared memory.
+fn file_dir() -> Option<PathBuf> {
+    #[cfg(unix)]
+    {
+        shmem_dir()
+    }
+    #[cfg(not(unix))]
+    {
+        Some(shmem_dir().unwrap_or_else(|| std::env::temp_dir().join(TEMP_SHMEM_DIR)))
+    }
+}
+
+/// Directory with the segments of POSIX shared memory or of shared memory in files.
+fn segment_dir() -> PathBuf {
+    file_dir().unwrap_or_else(|| PathBuf::from(DEFAULT_SHMEM_DIR))
+}
+
+/// A new, unique name of a segment, which records its creator for `list_segments`.
+fn segment_name() -> String {
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!(
+        "{}{}-{}-{:08x}",
+        SEGMENT_PREFIX,
+        std::process::id(),
+        created,
+        rand::random::<u32>()
+    )
+}
+
+/// The PID of the creator and the creation time of a segment named by `segment_name`.
+fn parse_segment_name(name: &str) -> Option<(u32, u64)> {
+    let mut parts = name.strip_prefix(SEGMENT_PREFIX)?.split('-');
+    let pid = parts.next()?.parse().ok()?;
+    let created = parts.next()?.parse().ok()?;
+    let suffix = parts.next()?;
+    if suffix.is_empty() || !suffix.bytes().all(|b| b.is_ascii_hexdigit()) || parts.next().is_some()
+    {
+        return None;
+    }
+    Some((pid, created))
+}
+
+/// Whether the process `pid` exists. Processes of other users count as alive, and so do all
+/// processes where this cannot be checked.
+pub(crate) fn process_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return true;
+        };
+        // SAFETY: Signal 0 only checks whether the process exists
+        if unsafe { libc::kill(pid, 0) } == 0 {
+            return true;
+        }
+        std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+        use windows_sys::Win32::System::Threading::{
+            GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+        };
+
+        // SAFETY: The handle is checked before use and closed afterwards
+        unsafe {
+            let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+            if handle.is_null() {
+                // Processes which cannot be opened, e.g., of other users, may still exist
+                return std::io::Error::last_os_error().raw_os_error()
+                    != Some(windows_sys::Win32::Foundation::ERROR_INVALID_PARAMETER as i32);
+            }
+            let mut code = 0;
+            let ok = GetExitCodeProcess(handle, &mut code);
+            CloseHandle(handle);
+            ok == 0 || code == STILL_ACTIVE as u32
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+/// A segment of shared memory created by this crate on this node.
+pub(crate) struct Segment {
+    pub(crate) name: String,
+    pub(crate) path: PathBuf,
+    /// PID of the process which created the segment and removes it when dropping it.
+    pub(crate) pid: u32,
+    /// Creation time in seconds since the epoch.
+    pub(crate) created: u64,
+    pub(crate) size: u64,
+    /// Whether the creator is still running, otherwise the segment is an orphan.
+    pub(crate) alive: bool,
+}
+
+/// All segments created by this crate in `/dev/shm` or `$FASTAR_LOADER_SHMEM_DIR`, sorted by
+/// name.
+pub(crate) fn list_segments() -> Result<Vec<Segment>> {
+    let dir = segment_dir();
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Could not list {}", dir.display())),
+    };
+    let mut segments = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let Some((pid, created)) = parse_segment_name(&name) else {
+            continue;
+        };
+        // The segment may have been removed in the meantime
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        segments.push(Segment {
+            path: entry.path(),
+            name,
+            pid,
+            created,
+            size: metadata.len(),
+            alive: process_alive(pid),
+        });
+    }
+    segments.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(segments)
+}
+
+/// Remove the orphaned segments whose name starts with `prefix` and which were created at least
+/// `older_than` ago, and return them. Segments of running processes are never removed.
+/// Processes which are still attached to a removed segment keep their mapping.
+pub(crate) fn cleanup_segments(prefix: &str, older_than: Duration) -> Result<Vec<Segment>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut removed = Vec::new();
+    for segment in list_segments()? {
+        if segment.alive
+            || !segment.name.starts_with(prefix)
+            || now.saturating_sub(segment.created) < older_than.as_secs()
+        {
+            continue;
+        }
+        match std::fs::remove_file(&segment.path) {
+            Ok(()) => {}
+            // Another cleanup was faster
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            // Windows refuses to remove files which other processes still map
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                warn!("Could not remove {}: {}", segment.path.display(), e);
+                continue;
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Could not remove {}", segment.path.display()))
+            }
+        }
+        info!(
+            "Removed orphaned shared memory {} of {} bytes from process {}",
+            segment.name, segment.size, segment.pid
+        );
+        removed.push(segment);
+    }
+    Ok(removed)
+}
+
+/// Free bytes for shared memory, in `/dev/shm` or the directory of shared memory in files, or
+/// `None` if unknown.
+pub(crate) fn shmem_available() -> Option<u64> {
+    free_space(&segment_dir())
+}
+
+/// Free bytes in the filesystem of `path`, or `None` if unknown.
+fn free_space(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: The path is a valid C string and `stat` is only read if the call succeeds
+        let result = unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) };
+        if result != 0 {
+            return None;
+        }
+        let stat = unsafe { stat.assume_init() };
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+        // The directory of shared memory in files is only created on first use
+        let path = path.ancestors().find(|path| path.exists())?;
+        let wide = path
+            .as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect::<Vec<_>>();
+        let mut available = 0u64;
+        // SAFETY: The path is null-terminated and the totals are optional
+        let ok = unsafe {
+            GetDiskFreeSpaceExW(
+                wide.as_ptr(),
+                &mut available,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        (ok != 0).then_some(available)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_space() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(free_space(dir.path()).is_some());
+        assert_eq!(free_space(&dir.path().join("missing")), None);
+    }
+
+    #[test]
+    fn test_segment_name() {
+        let name = segment_name();
+        let (pid, created) = parse_segment_name(&name).unwrap();
+        assert_eq!(pid, std::process::id());
+        assert!(created > 0);
+        assert!(process_alive(pid));
+        assert_eq!(
+            parse_segment_name("fastar-loader-12-34-5678abcd"),
+            Some((12, 34))
+        );
+        for name in [
+            "shmem_1234",
+            "fastar-loader-12-34",
+            "fastar-loader-12-34-",
+            "fastar-loader-x-34-5678abcd",
+            "fastar-loader-12-34-5678abcd-1",
+        ] {
+            assert_eq!(parse_segment_name(name), None);
+        }
     }
 }
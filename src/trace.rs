@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{bail, Context, Result};
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{reload, Layer, Registry};
+
+use crate::error::Error;
+
+type DynLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// The exporter of the spans of the build and read paths. The global subscriber can only be
+/// installed once per process, so exporters are swapped in and out of a reloadable layer.
+struct Exporter {
+    handle: reload::Handle<Option<DynLayer>, Registry>,
+    active: bool,
+    /// Writes the end of a chrome trace when dropped.
+    guard: Option<FlushGuard>,
+}
+
+static EXPORTER: OnceLock<Option<Mutex<Exporter>>> = OnceLock::new();
+
+fn exporter() -> Result<&'static Mutex<Exporter>> {
+    EXPORTER
+        .get_or_init(|| {
+            let (layer, handle) = reload::Layer::new(None);
+            tracing::subscriber::set_global_default(Registry::default().with(layer)).ok()?;
+            Some(Mutex::new(Exporter {
+                handle,
+                active: false,
+                guard: None,
+            }))
+        })
+        .as_ref()
+        .context("Another tracing subscriber is installed in this process")
+}
+
+/// Export spans to `path`, either as a chrome trace, which can be opened in Perfetto or
+/// chrome://tracing, or as a log file with the duration of each span.
+pub(crate) fn start(path: &Path, format: &str) -> Result<()> {
+    let mut exporter = exporter()?.lock().unwrap();
+    if exporter.active {
+        bail!(Error::InvalidOptions(
+            "Tracing is already active, stop it first".to_string()
+        ));
+    }
+    let (layer, guard): (DynLayer, _) = match format {
+        "chrome" => {
+            let file = File::create(path).context("Failed to create trace file")?;
+            let (layer, guard) = ChromeLayerBuilder::<Registry>::new()
+                .writer(file)
+                .include_args(true)
+                .build();
+            (Box::new(layer), Some(guard))
+        }
+        "log" => {
+            let file = File::create(path).context("Failed to create trace file")?;
+            let layer = tracing_subscriber::fmt::layer::<Registry>()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_span_events(FmtSpan::CLOSE);
+            (Box::new(layer), None)
+        }
+        format => bail!(Error::InvalidOptions(format!(
+            "Unknown trace format {}, expected chrome or log",
+            format
+        ))),
+    };
+    exporter.handle.reload(Some(layer))?;
+    exporter.active = true;
+    exporter.guard = guard;
+    Ok(())
+}
+
+/// Stop exporting spans and flush the trace. Does nothing if tracing is not active.
+pub(crate) fn stop() -> Result<()> {
+    let Some(exporter) = EXPORTER.get().and_then(Option::as_ref) else {
+        return Ok(());
+    };
+    let mut exporter = exporter.lock().unwrap();
+    exporter.handle.reload(None)?;
+    exporter.active = false;
+    exporter.guard = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.log");
+        start(&path, "log").unwrap();
+        assert!(start(&path, "log").is_err());
+        tracing::info_span!("query", name = "test").in_scope(|| {});
+        stop().unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.contains("query"));
+
+        let path = dir.path().join("trace.json");
+        start(&path, "chrome").unwrap();
+        tracing::info_span!("decompress").in_scope(|| {});
+        stop().unwrap();
+        let trace = std::fs::read_to_string(&path).unwrap();
+        assert!(trace.trim_end().ends_with(']'));
+        assert!(start(&path, "xml").is_err());
+    }
+}
@@ -1,7 +1,300 @@
-use anyhow::{anyhow, Result};
-use std::path::Path;
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 
-/// Get relative path from root, remove suffix, normalize path separators
+use crate::error::Error;
+
+/// 64-bit FNV-1a hash, which is stable across builds and platforms.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Find all files ending in `suffix` below `root` and return their names relative to `root`.
+/// Symbolic links to files and directories are followed, or skipped if not `follow_symlinks`.
+pub(crate) fn discover_names(
+    root: &str,
+    suffix: &str,
+    follow_symlinks: bool,
+) -> Result<Vec<String>> {
+    let root_path = Path::new(root);
+    let mut paths = Vec::new();
+    // As with a glob, a missing root has no files
+    if !root_path.is_dir() {
+        return Ok(paths);
+    }
+    find_files(
+        root_path,
+        suffix,
+        follow_symlinks,
+        &mut HashSet::new(),
+        &mut paths,
+    )?;
+    paths
+        .iter()
+        .map(|path| get_relative_name_without_suffix(path, root_path, suffix))
+        .collect()
+}
+
+/// Append the files ending in `suffix` below `dir` to `paths`, in sorted order, where
+/// `ancestors` are the directories being listed.
+fn find_files(
+    dir: &Path,
+    suffix: &str,
+    follow_symlinks: bool,
+    ancestors: &mut HashSet<PathBuf>,
+    paths: &mut Vec<PathBuf>,
+) -> Result<()> {
+    // Links may lead back to a directory above, which is not listed again
+    let canonical = dir.canonicalize()?;
+    if follow_symlinks && !ancestors.insert(canonical.clone()) {
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .and_then(|entries| entries.map(|entry| Ok(entry?.path())).collect())
+        .with_context(|| format!("Failed to list {}", dir.display()))?;
+    entries.sort();
+    for path in entries {
+        let mut metadata = fs::symlink_metadata(&path)?;
+        if metadata.file_type().is_symlink() {
+            if !follow_symlinks {
+                continue;
+            }
+            // Dangling links are skipped
+            match fs::metadata(&path) {
+                Ok(target) => metadata = target,
+                Err(_) => continue,
+            }
+        }
+        if metadata.is_dir() {
+            find_files(&path, suffix, follow_symlinks, ancestors, paths)?;
+        } else if path
+            .file_name()
+            .is_some_and(|name| name.as_encoded_bytes().ends_with(suffix.as_bytes()))
+        {
+            paths.push(path);
+        }
+    }
+    ancestors.remove(&canonical);
+    Ok(())
+}
+
+/// Bytes of file names which are not valid UTF-8 are escaped in names as the private-use
+/// characters U+EF80 to U+EFFF, i.e., this base plus the byte, as in OPTU-8.
+#[cfg(unix)]
+const ESCAPE_BASE: u32 = 0xEF00;
+
+#[cfg(unix)]
+fn is_escape(c: char) -> bool {
+    (ESCAPE_BASE + 0x80..=ESCAPE_BASE + 0xFF).contains(&u32::from(c))
+}
+
+/// A path component as a string, escaping bytes which are not valid UTF-8, e.g., of Latin-1
+/// file names, see `ESCAPE_BASE`.
+#[cfg(unix)]
+fn escape_component(component: &OsStr) -> Result<String> {
+    use std::os::unix::ffi::OsStrExt;
+    let mut escaped = String::new();
+    let mut bytes = component.as_bytes();
+    while !bytes.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(bytes) {
+            Ok(valid) => (valid, 0),
+            Err(e) => (
+                std::str::from_utf8(&bytes[..e.valid_up_to()])?,
+                e.error_len().unwrap_or(bytes.len() - e.valid_up_to()),
+            ),
+        };
+        if valid.chars().any(is_escape) {
+            bail!(
+                "{} contains characters U+EF80 to U+EFFF, which are reserved for escaping",
+                component.to_string_lossy()
+            );
+        }
+        escaped.push_str(valid);
+        // Bytes of invalid sequences are never ASCII, so they map to the escape range
+        for &byte in &bytes[valid.len()..valid.len() + invalid] {
+            escaped.extend(char::from_u32(ESCAPE_BASE + u32::from(byte)));
+        }
+        bytes = &bytes[valid.len() + invalid..];
+    }
+    Ok(escaped)
+}
+
+/// File names are Unicode on other platforms, so nothing needs to be escaped.
+#[cfg(not(unix))]
+fn escape_component(component: &OsStr) -> Result<String> {
+    component
+        .to_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("{} is not valid Unicode", component.to_string_lossy()))
+}
+
+/// The path of the file `relative` to `root`, e.g., a name with a suffix, restoring the bytes
+/// escaped by `escape_component`.
+pub(crate) fn name_path(root: &Path, relative: &str) -> PathBuf {
+    #[cfg(unix)]
+    if relative.chars().any(is_escape) {
+        use std::os::unix::ffi::OsStringExt;
+        let mut bytes = Vec::with_capacity(relative.len());
+        for c in relative.chars() {
+            match is_escape(c) {
+                true => bytes.push((u32::from(c) - ESCAPE_BASE) as u8),
+                false => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        return root.join(std::ffi::OsString::from_vec(bytes));
+    }
+    root.join(relative)
+}
+
+/// Ensure that `name` is a relative path below the root, such that names from untrusted sources,
+/// e.g., configs or network requests, cannot resolve to files outside of it.
+pub(crate) fn check_name(name: &str) -> Result<()> {
+    let normal = Path::new(name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if name.is_empty() || !normal {
+        return Err(Error::InvalidName(name.to_string()).into());
+    }
+    Ok(())
+}
+
+/// Glob patterns selecting files by their path relative to root, e.g., `**/contam/**`.
+/// A file is selected if it matches any include pattern (or there are none) and no exclude pattern.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PathFilter {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl PathFilter {
+    pub(crate) fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let parse = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    glob::Pattern::new(pattern)
+                        .map_err(|e| anyhow!("Invalid glob pattern {}: {}", pattern, e))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(PathFilter {
+            include: parse(include)?,
+            exclude: parse(exclude)?,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub(crate) fn matches(&self, relative_path: &str) -> bool {
+        let options = glob::MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let matches_any = |patterns: &[glob::Pattern]| {
+            patterns
+                .iter()
+                .any(|pattern| pattern.matches_with(relative_path, options))
+        };
+        (self.include.is_empty() || matches_any(&self.include)) && !matches_any(&self.exclude)
+    }
+}
+
+/// Names a map is built from: all files below root or an explicit list, narrowed by a filter.
+#[derive(Debug, Clone)]
+pub(crate) struct NameSelection {
+    names: Option<Vec<String>>,
+    filter: PathFilter,
+    /// Whether the files below root are found through symbolic links as well.
+    follow_symlinks: bool,
+}
+
+impl Default for NameSelection {
+    fn default() -> Self {
+        NameSelection::new(None, PathFilter::default(), true)
+    }
+}
+
+impl NameSelection {
+    pub(crate) fn new(
+        names: Option<Vec<String>>,
+        filter: PathFilter,
+        follow_symlinks: bool,
+    ) -> Self {
+        // The order of names does not matter, so normalize it
+        let names = names.map(|mut names| {
+            names.sort();
+            names.dedup();
+            names
+        });
+        NameSelection {
+            names,
+            filter,
+            follow_symlinks,
+        }
+    }
+
+    /// Names of the selected files ending in `suffix` below `root`.
+    pub(crate) fn resolve(&self, root: &str, suffix: &str) -> Result<Vec<String>> {
+        let _span = tracing::info_span!("glob", root, suffix).entered();
+        let names = match &self.names {
+            None => discover_names(root, suffix, self.follow_symlinks)?,
+            Some(names) => {
+                for name in names {
+                    check_name(name)?;
+                }
+                names.clone()
+            }
+        };
+        if self.filter.is_empty() {
+            return Ok(names);
+        }
+        Ok(names
+            .into_iter()
+            .filter(|name| self.filter.matches(&format!("{}{}", name, suffix)))
+            .collect())
+    }
+
+    /// Stable identifier of the selection for cache keys, or `None` if all files are selected.
+    pub(crate) fn key(&self) -> Option<u64> {
+        if self.names.is_none() && self.filter.is_empty() && self.follow_symlinks {
+            return None;
+        }
+        let mut key = self
+            .names
+            .as_ref()
+            .map(|names| names.join("\n"))
+            .unwrap_or_default();
+        if !self.filter.is_empty() {
+            let join = |patterns: &[glob::Pattern]| {
+                patterns
+                    .iter()
+                    .map(|pattern| pattern.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            key.push_str(&format!(
+                "\0include:{}\0exclude:{}",
+                join(&self.filter.include),
+                join(&self.filter.exclude)
+            ));
+        }
+        // Explicit names are not discovered, so links only matter without them
+        if self.names.is_none() && !self.follow_symlinks {
+            key.push_str("\0no-symlinks");
+        }
+        Some(fnv1a(key.as_bytes()))
+    }
+}
+
+/// Get relative path from root, remove suffix, normalize path separators to forward slashes and
+/// escape bytes which are not valid UTF-8, see `escape_component`
 pub(crate) fn get_relative_name_without_suffix(
     path: &Path,
     root: &Path,
@@ -11,42 +304,120 @@ pub(crate) fn get_relative_name_without_suffix(
     let relative_path = path
         .strip_prefix(root)
         .map_err(|_| anyhow!("Path is not under root directory"))?;
-
-    // Get filename without suffix
-    let filename = path
-        .file_name()
-        .ok_or_else(|| anyhow!("No file name found"))?
-        .to_str()
-        .ok_or_else(|| anyhow!("Invalid UTF-8 sequence"))?
+    let components = relative_path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(os_str) => Some(escape_component(os_str)),
+            _ => None,
+        })
+        .collect::<Result<Vec<_>>>()?;
+    components
+        .join("/")
         .strip_suffix(suffix)
-        .ok_or_else(|| anyhow!("Invalid file name"))?;
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Invalid file name"))
+}
+
+/// Copy the top-level files of a test data directory to a fresh temporary directory.
+#[cfg(test)]
+pub(crate) fn copy_test_data(dir: &str) -> tempfile::TempDir {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file() {
+            std::fs::copy(&path, temp_dir.path().join(path.file_name().unwrap())).unwrap();
+        }
+    }
+    temp_dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_filter() {
+        let filter =
+            PathFilter::new(&["**/*.fna.gz".to_string()], &["**/contam/**".to_string()]).unwrap();
+        assert!(filter.matches("a.fna.gz"));
+        assert!(filter.matches("sub/a.fna.gz"));
+        assert!(!filter.matches("contam/a.fna.gz"));
+        assert!(!filter.matches("sub/contam/a.fna.gz"));
+        assert!(!filter.matches("a.track.gz"));
 
-    // Get the directory part of the relative path (all subdirectories)
-    let parent = relative_path.parent();
-    let result = match parent {
-        Some(parent) if parent != Path::new("") => {
-            // Handle nested subdirectories by joining the full relative directory path with filename
-            let normalized_parent = normalize_path_separators(parent)?;
-            format!("{}/{}", normalized_parent, filename)
+        // `*` does not cross directories
+        let filter = PathFilter::new(&["GCF_*".to_string()], &[]).unwrap();
+        assert!(filter.matches("GCF_1.fna.gz"));
+        assert!(!filter.matches("GCF_dir/GCA_1.fna.gz"));
+
+        assert!(PathFilter::new(&["[".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn test_check_name() {
+        for name in ["GCA_1", "foo/bar/GCA_1", "a..b"] {
+            assert!(check_name(name).is_ok(), "{}", name);
         }
-        _ => {
-            // File is directly in root directory
-            filename.to_string()
+        for name in ["", "../GCA_1", "foo/../../GCA_1", "/etc/GCA_1", "./GCA_1"] {
+            assert!(check_name(name).is_err(), "{}", name);
         }
-    };
+        let selection = NameSelection::new(
+            Some(vec!["../outside".to_string()]),
+            PathFilter::default(),
+            true,
+        );
+        assert!(selection
+            .resolve("test-data/assemblies", ".fna.gz")
+            .is_err());
+    }
 
-    Ok(result)
-}
+    #[test]
+    fn test_selection_key() {
+        let all = NameSelection::default();
+        assert_eq!(all.key(), None);
+        let names = |names: &[&str]| Some(names.iter().map(|s| s.to_string()).collect());
+        let ab = NameSelection::new(names(&["a", "b"]), PathFilter::default(), true);
+        let ba = NameSelection::new(names(&["b", "a", "a"]), PathFilter::default(), true);
+        assert_eq!(ab.key(), ba.key());
+        let excluded = NameSelection::new(
+            None,
+            PathFilter::new(&[], &["**/contam/**".to_string()]).unwrap(),
+            true,
+        );
+        assert!(excluded.key().is_some());
+        assert_ne!(excluded.key(), ab.key());
+        let no_links = NameSelection::new(None, PathFilter::default(), false);
+        assert!(no_links.key().is_some());
+    }
 
-/// Normalize path separators to forward slashes for consistent naming
-fn normalize_path_separators(path: &Path) -> Result<String> {
-    use std::path::Component;
-    let components: Vec<_> = path
-        .components()
-        .filter_map(|comp| match comp {
-            Component::Normal(os_str) => os_str.to_str(),
-            _ => None,
-        })
-        .collect();
-    Ok(components.join("/"))
+    #[cfg(unix)]
+    #[test]
+    fn test_discover_names() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        // A Latin-1 file name, which is not valid UTF-8
+        let latin1 = OsStr::from_bytes(b"K\xf6ln.fna.gz");
+        fs::write(root.join("sub").join(latin1), b"").unwrap();
+        fs::write(root.join("a.fna.gz"), b"").unwrap();
+        fs::write(root.join("a.fna.gz.fai"), b"").unwrap();
+        std::os::unix::fs::symlink(root.join("sub"), root.join("link")).unwrap();
+        // A link back to the root, which must not be followed forever
+        std::os::unix::fs::symlink(root, root.join("sub/loop")).unwrap();
+
+        let root_str = root.to_str().unwrap();
+        let names = discover_names(root_str, ".fna.gz", true).unwrap();
+        assert_eq!(names, vec!["a", "link/K\u{eff6}ln", "sub/K\u{eff6}ln"]);
+        let names = discover_names(root_str, ".fna.gz", false).unwrap();
+        assert_eq!(names, vec!["a", "sub/K\u{eff6}ln"]);
+        assert_eq!(
+            name_path(root, &format!("{}.fna.gz", names[1])),
+            root.join("sub").join(latin1)
+        );
+        assert_eq!(name_path(root, "a.fna.gz"), root.join("a.fna.gz"));
+
+        fs::write(root.join("\u{eff6}.fna.gz"), b"").unwrap();
+        assert!(discover_names(root_str, ".fna.gz", false).is_err());
+    }
 }
@@ -0,0 +1,88 @@
+use anyhow::{bail, Result};
+
+use crate::error::Error;
+
+/// Allowed bytes of reads in strict mode, to detect malformed FASTA files, e.g., line widths
+/// which do not match the FAI index and shift reads into headers or other contigs.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Alphabet {
+    allowed: [bool; 256],
+    letters: String,
+}
+
+impl Alphabet {
+    /// An alphabet of the bytes of `letters`, which is case-sensitive.
+    pub(crate) fn new(letters: &str) -> Result<Self> {
+        if letters.is_empty() {
+            bail!(Error::InvalidOptions("The alphabet is empty".to_string()));
+        }
+        let mut allowed = [false; 256];
+        for &letter in letters.as_bytes() {
+            allowed[letter as usize] = true;
+        }
+        Ok(Alphabet {
+            allowed,
+            letters: letters.to_string(),
+        })
+    }
+
+    pub(crate) fn letters(&self) -> &str {
+        &self.letters
+    }
+
+    /// Check that `sequence`, read from `start` of `contig` of `name`, has `length` bases and
+    /// only bases of the alphabet.
+    pub(crate) fn check(
+        &self,
+        name: &str,
+        contig: &[u8],
+        start: u64,
+        length: u64,
+        sequence: &[u8],
+    ) -> Result<()> {
+        let contig = String::from_utf8_lossy(contig);
+        if sequence.len() as u64 != length {
+            bail!(Error::InvalidSequence(format!(
+                "Read {} bases instead of {} at 0-based position {} of {} in {}",
+                sequence.len(),
+                length,
+                start,
+                contig,
+                name
+            )));
+        }
+        if let Some(offset) = sequence.iter().position(|&b| !self.allowed[b as usize]) {
+            bail!(Error::InvalidSequence(format!(
+                "Invalid base {:?} at 0-based position {} of {} in {}, expected one of {:?}",
+                sequence[offset] as char,
+                start + offset as u64,
+                contig,
+                name,
+                self.letters
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let alphabet = Alphabet::new("ACGTN").unwrap();
+        assert!(alphabet.check("a", b"chr1", 10, 4, b"ACGN").is_ok());
+        let error = alphabet.check("a", b"chr1", 10, 4, b"AC>c").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid base '>' at 0-based position 12 of chr1 in a, expected one of \"ACGTN\""
+        );
+        let error = alphabet.check("a", b"chr1", 10, 4, b"ACG").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::InvalidSequence(_))
+        ));
+        assert!(Alphabet::new("").is_err());
+    }
+}
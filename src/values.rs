@@ -0,0 +1,244 @@
+use anyhow::{bail, Result};
+
+/// Type of the values of a track, which are stored little-endian.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ValueType {
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+    F32,
+    F64,
+}
+
+impl ValueType {
+    pub(crate) fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "uint8" => ValueType::U8,
+            "uint16" => ValueType::U16,
+            "uint32" => ValueType::U32,
+            "int8" => ValueType::I8,
+            "int16" => ValueType::I16,
+            "int32" => ValueType::I32,
+            "float32" => ValueType::F32,
+            "float64" => ValueType::F64,
+            _ => bail!("Unsupported track dtype: {}", name),
+        })
+    }
+
+    /// Size of a value in bytes.
+    pub(crate) fn size(self) -> u64 {
+        match self {
+            ValueType::U8 | ValueType::I8 => 1,
+            ValueType::U16 | ValueType::I16 => 2,
+            ValueType::U32 | ValueType::I32 | ValueType::F32 => 4,
+            ValueType::F64 => 8,
+        }
+    }
+
+    /// Number of values in `length` bytes.
+    pub(crate) fn num_values(self, length: u64) -> Result<u64> {
+        if length % self.size() != 0 {
+            bail!(
+                "Track length {} is not a multiple of the value size {}",
+                length,
+                self.size()
+            );
+        }
+        Ok(length / self.size())
+    }
+
+    /// Decode values, ignoring trailing bytes of an incomplete value.
+    pub(crate) fn decode(self, bytes: &[u8]) -> Vec<f64> {
+        bytes
+            .chunks_exact(self.size() as usize)
+            .map(|b| match self {
+                ValueType::U8 => b[0] as f64,
+                ValueType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                ValueType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                ValueType::I8 => b[0] as i8 as f64,
+                ValueType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                ValueType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                ValueType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                ValueType::F64 => f64::from_le_bytes(b.try_into().unwrap()),
+            })
+            .collect()
+    }
+
+    /// Decode values as sampling weights. NaN counts as zero, e.g., for missing coverage, while
+    /// negative and infinite values are rejected.
+    pub(crate) fn weights(self, bytes: &[u8]) -> Result<Vec<f64>> {
+        let weights = self
+            .decode(bytes)
+            .into_iter()
+            .map(|weight| if weight.is_nan() { 0.0 } else { weight })
+            .collect::<Vec<_>>();
+        if let Some(weight) = weights.iter().find(|w| !(**w >= 0.0 && w.is_finite())) {
+            bail!("Invalid track value for sampling: {}", weight);
+        }
+        Ok(weights)
+    }
+}
+
+/// How to reduce the values of a bin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Aggregation {
+    Mean,
+    Max,
+    Min,
+    Sum,
+}
+
+impl Aggregation {
+    pub(crate) fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "mean" => Aggregation::Mean,
+            "max" => Aggregation::Max,
+            "min" => Aggregation::Min,
+            "sum" => Aggregation::Sum,
+            _ => bail!("Unsupported aggregation: {}", name),
+        })
+    }
+}
+
+/// Reduce consecutive bins of `bin_size` values. NaN values are ignored, and a bin of only NaN
+/// is NaN, except for the sum, which is zero.
+pub(crate) fn aggregate(values: &[f64], bin_size: usize, op: Aggregation) -> Result<Vec<f32>> {
+    if bin_size == 0 || values.len() % bin_size != 0 {
+        bail!(
+            "The length {} is not a positive multiple of the bin size {}",
+            values.len(),
+            bin_size
+        );
+    }
+    let bins = values
+        .chunks_exact(bin_size)
+        .map(|bin| {
+            let values = bin.iter().copied().filter(|value| !value.is_nan());
+            let result = match op {
+                Aggregation::Sum => values.sum::<f64>(),
+                Aggregation::Mean => {
+                    let (sum, count) =
+                        values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+                    sum / count as f64
+                }
+                Aggregation::Max => values.reduce(f64::max).unwrap_or(f64::NAN),
+                Aggregation::Min => values.reduce(f64::min).unwrap_or(f64::NAN),
+            };
+            result as f32
+        })
+        .collect();
+    Ok(bins)
+}
+
+/// Keep every `step`-th value of `value_size` bytes, starting with the first.
+pub(crate) fn downsample(bytes: &[u8], step: usize, value_size: usize) -> Result<Vec<u8>> {
+    if step == 0 || value_size == 0 || bytes.len() % value_size != 0 {
+        bail!(
+            "Cannot take every {}th value of {} bytes from {} bytes",
+            step,
+            value_size,
+            bytes.len()
+        );
+    }
+    Ok(bytes
+        .chunks_exact(value_size)
+        .step_by(step)
+        .flatten()
+        .copied()
+        .collect())
+}
+
+/// Reverse the positions of a track with `channels.len()` interleaved channels of `value_size`
+/// bytes per position, e.g., to match a reverse-complemented sequence. Channel `i` of the
+/// result is channel `channels[i]` of the input, e.g., `[1, 0]` to swap stranded coverage.
+pub(crate) fn reverse(bytes: &[u8], value_size: usize, channels: &[usize]) -> Result<Vec<u8>> {
+    let mut sorted = channels.to_vec();
+    sorted.sort_unstable();
+    if sorted.iter().enumerate().any(|(i, &channel)| i != channel) {
+        bail!("Invalid channel permutation: {:?}", channels);
+    }
+    let position_size = value_size * channels.len();
+    if position_size == 0 || bytes.len() % position_size != 0 {
+        bail!(
+            "Cannot reverse {} bytes in positions of {} channels of {} bytes",
+            bytes.len(),
+            channels.len(),
+            value_size
+        );
+    }
+    let mut reversed = Vec::with_capacity(bytes.len());
+    for position in bytes.chunks_exact(position_size).rev() {
+        for &channel in channels {
+            reversed.extend_from_slice(&position[channel * value_size..][..value_size]);
+        }
+    }
+    Ok(reversed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_type() {
+        let bytes = [1.5f32, f32::NAN]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(ValueType::F32.weights(&bytes).unwrap(), vec![1.5, 0.0]);
+        assert!(ValueType::F32.weights(&(-1f32).to_le_bytes()).is_err());
+        assert_eq!(ValueType::U16.weights(&[1, 1]).unwrap(), vec![257.0]);
+        assert_eq!(ValueType::I16.decode(&[0xff, 0xff, 2]), vec![-1.0]);
+        assert_eq!(ValueType::parse("float64").unwrap(), ValueType::F64);
+        assert!(ValueType::parse("float16").is_err());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let values = [1.0, 3.0, f64::NAN, 2.0, f64::NAN, f64::NAN];
+        assert_eq!(
+            aggregate(&values, 2, Aggregation::Sum).unwrap(),
+            vec![4.0, 2.0, 0.0]
+        );
+        let mean = aggregate(&values, 2, Aggregation::Mean).unwrap();
+        assert_eq!(mean[..2], [2.0, 2.0]);
+        assert!(mean[2].is_nan());
+        assert_eq!(aggregate(&values, 3, Aggregation::Max).unwrap()[0], 3.0);
+        assert_eq!(aggregate(&values, 6, Aggregation::Min).unwrap(), vec![1.0]);
+        assert!(aggregate(&values, 4, Aggregation::Sum).is_err());
+        assert!(aggregate(&values, 0, Aggregation::Sum).is_err());
+        assert!(Aggregation::parse("median").is_err());
+    }
+
+    #[test]
+    fn test_downsample() {
+        assert_eq!(downsample(&[1, 2, 3, 4, 5], 2, 1).unwrap(), vec![1, 3, 5]);
+        assert_eq!(
+            downsample(&[1, 2, 3, 4, 5, 6], 2, 2).unwrap(),
+            vec![1, 2, 5, 6]
+        );
+        assert_eq!(downsample(&[1, 2], 5, 1).unwrap(), vec![1]);
+        assert!(downsample(&[1, 2, 3], 1, 2).is_err());
+        assert!(downsample(&[1, 2], 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_reverse() {
+        assert_eq!(reverse(&[1, 2, 3], 1, &[0]).unwrap(), vec![3, 2, 1]);
+        assert_eq!(
+            reverse(&[1, 2, 3, 4, 5, 6, 7, 8], 2, &[0]).unwrap(),
+            vec![7, 8, 5, 6, 3, 4, 1, 2]
+        );
+        // Two channels of one byte, swapped
+        assert_eq!(
+            reverse(&[1, 2, 3, 4, 5, 6], 1, &[1, 0]).unwrap(),
+            vec![6, 5, 4, 3, 2, 1]
+        );
+        assert!(reverse(&[1, 2, 3], 1, &[1, 0]).is_err());
+        assert!(reverse(&[1, 2], 1, &[0, 0]).is_err());
+        assert!(reverse(&[1, 2], 1, &[]).is_err());
+    }
+}
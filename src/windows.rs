@@ -0,0 +1,456 @@
+use std::io::BufRead;
+
+use anyhow::{anyhow, bail, Result};
+use numpy::ndarray::{Array1, Array2};
+
+use crate::bgzf_reader::BgzfReader;
+use crate::error::Error;
+use crate::index::{count_bases, NUM_BASE_CLASSES};
+use crate::remote::DataFile;
+use crate::values::ValueType;
+
+/// Number of bases read at once when scanning a contig, e.g., for gaps.
+const SCAN_CHUNK_SIZE: usize = 1 << 20;
+
+/// Largest supported k-mer length, for which the counts take 128 MiB.
+pub(crate) const MAX_KMER_LENGTH: usize = 12;
+
+/// Reads the values of a contig sequentially from an open BGZF file, after the initial seek.
+pub(crate) struct SequenceReader {
+    reader: BgzfReader<Box<dyn DataFile>>,
+    /// FASTA sequences are split into lines, whose breaks are not part of the sequence.
+    strip_line_breaks: bool,
+}
+
+impl SequenceReader {
+    pub(crate) fn new(reader: BgzfReader<Box<dyn DataFile>>, strip_line_breaks: bool) -> Self {
+        SequenceReader {
+            reader,
+            strip_line_breaks,
+        }
+    }
+
+    /// Append the next `n` values to `buf`.
+    pub(crate) fn read(&mut self, n: usize, buf: &mut Vec<u8>) -> Result<()> {
+        let _span = tracing::debug_span!("decompress", length = n).entered();
+        let target = buf.len() + n;
+        while buf.len() < target {
+            let src = self.reader.fill_buf()?;
+            if src.is_empty() {
+                return Err(anyhow!("End of file reached before reading {} values", n));
+            }
+            let mut consumed = 0;
+            for &value in src {
+                if buf.len() == target {
+                    break;
+                }
+                consumed += 1;
+                if !(self.strip_line_breaks && (value == b'\n' || value == b'\r')) {
+                    buf.push(value);
+                }
+            }
+            self.reader.consume(consumed);
+        }
+        Ok(())
+    }
+}
+
+/// Remove the line breaks from `buf`, the bytes of a region which starts at `column` of a line
+/// of `line_bases` bases and `line_width` bytes as given by a `.fai`, moving the lines between
+/// them in bulk. Each line is checked for stray line breaks or headers with `memchr`, which
+/// searches many bytes at once, and each line break must be `\n` or `\r\n` as wide as the index
+/// says. Returns `false` if `buf` does not have this layout, e.g., for CRLF line endings which
+/// the index does not count, and then leaves `buf` unspecified.
+pub(crate) fn strip_line_breaks(
+    buf: &mut Vec<u8>,
+    column: u64,
+    line_bases: u64,
+    line_width: u64,
+) -> bool {
+    if buf.is_empty() {
+        return true;
+    }
+    if column >= line_bases || line_width <= line_bases {
+        return false;
+    }
+    let terminator = (line_width - line_bases) as usize;
+    let (mut len, mut pos) = (0, 0);
+    // The first line is shorter if the region does not start at the start of a line
+    let mut bases = (line_bases - column) as usize;
+    loop {
+        let line_end = (pos + bases).min(buf.len());
+        if memchr::memchr3(b'\n', b'\r', b'>', &buf[pos..line_end]).is_some() {
+            return false;
+        }
+        buf.copy_within(pos..line_end, len);
+        len += line_end - pos;
+        if line_end == buf.len() {
+            break;
+        }
+        // The region ends with a base, so every line break is followed by another line
+        let next = line_end + terminator;
+        if next >= buf.len() || !matches!(&buf[line_end..next], b"\n" | b"\r\n") {
+            return false;
+        }
+        (pos, bases) = (next, line_bases as usize);
+    }
+    buf.truncate(len);
+    true
+}
+
+/// Windows of `window_length` every `stride` values over a contig, read in a single pass.
+/// Only full windows are returned, so up to `stride - 1` values at the end may be left out.
+pub(crate) struct SlidingWindows {
+    reader: SequenceReader,
+    window_length: u64,
+    stride: u64,
+    /// Start of the next window, which is the start of `window` once filled.
+    next_start: u64,
+    contig_length: u64,
+    window: Vec<u8>,
+}
+
+impl SlidingWindows {
+    /// `reader` must be positioned at the start of the contig.
+    pub(crate) fn new(
+        reader: SequenceReader,
+        contig_length: u64,
+        window_length: u64,
+        stride: u64,
+    ) -> Result<Self> {
+        if window_length == 0 || stride == 0 {
+            bail!("window_length and stride must be positive");
+        }
+        Ok(SlidingWindows {
+            reader,
+            window_length,
+            stride,
+            next_start: 0,
+            contig_length,
+            window: Vec::with_capacity(window_length as usize),
+        })
+    }
+
+    fn advance(&mut self) -> Result<Array1<u8>> {
+        let window_length = self.window_length as usize;
+        if self.window.is_empty() {
+            self.reader.read(window_length, &mut self.window)?;
+        } else if self.stride < self.window_length {
+            // Keep the overlap with the previous window
+            self.window.drain(..self.stride as usize);
+            self.reader.read(self.stride as usize, &mut self.window)?;
+        } else {
+            self.window.clear();
+            self.reader.read(
+                (self.stride - self.window_length) as usize,
+                &mut self.window,
+            )?;
+            self.window.clear();
+            self.reader.read(window_length, &mut self.window)?;
+        }
+        Ok(Array1::from(self.window.clone()))
+    }
+}
+
+impl Iterator for SlidingWindows {
+    /// Start of the window and its values.
+    type Item = Result<(u64, Array1<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.next_start;
+        if start.checked_add(self.window_length)? > self.contig_length {
+            return None;
+        }
+        self.next_start = start.saturating_add(self.stride);
+        Some(self.advance().map(|window| (start, window)))
+    }
+}
+
+/// Intervals `[start, end)` of runs of at least `min_length` unknown bases (`N` or `n`) in a
+/// contig, as an array with one interval per row. `reader` must be positioned at the start.
+pub(crate) fn find_gaps(
+    mut reader: SequenceReader,
+    contig_length: u64,
+    min_length: u64,
+) -> Result<Array2<u64>> {
+    let mut gaps = Vec::new();
+    let mut push = |start: u64, end: u64| {
+        if end - start >= min_length {
+            gaps.extend([start, end]);
+        }
+    };
+    let mut gap_start = None;
+    let mut buf = Vec::with_capacity(SCAN_CHUNK_SIZE);
+    let mut pos = 0;
+    while pos < contig_length {
+        let n = (contig_length - pos).min(SCAN_CHUNK_SIZE as u64);
+        buf.clear();
+        reader.read(n as usize, &mut buf)?;
+        for (i, &base) in buf.iter().enumerate() {
+            let is_unknown = base == b'N' || base == b'n';
+            match (is_unknown, gap_start) {
+                (true, None) => gap_start = Some(pos + i as u64),
+                (false, Some(start)) => {
+                    push(start, pos + i as u64);
+                    gap_start = None;
+                }
+                _ => {}
+            }
+        }
+        pos += n;
+    }
+    if let Some(start) = gap_start {
+        push(start, contig_length);
+    }
+    let num_gaps = gaps.len() / 2;
+    Ok(Array2::from_shape_vec((num_gaps, 2), gaps)?)
+}
+
+/// Zeroed counts of all `4^k` k-mers, after checking `k`.
+pub(crate) fn kmer_counts(k: usize) -> Result<Vec<u64>> {
+    if !(1..=MAX_KMER_LENGTH).contains(&k) {
+        let message = format!("k must be between 1 and {}, not {}", MAX_KMER_LENGTH, k);
+        return Err(Error::InvalidOptions(message).into());
+    }
+    Ok(vec![0; 1 << (2 * k)])
+}
+
+/// Add the k-mers of the next `length` bases of `reader` to `counts` from `kmer_counts`. A k-mer
+/// is counted at the index of its 2-bit encoding with `A`, `C`, `G` and `T` as 0 to 3, i.e., in
+/// lexicographic order, ignoring case. K-mers with other bases such as `N` are skipped.
+pub(crate) fn count_kmers(
+    mut reader: SequenceReader,
+    length: u64,
+    counts: &mut [u64],
+) -> Result<()> {
+    let k = counts.len().trailing_zeros() as usize / 2;
+    let mask = counts.len() - 1;
+    let (mut kmer, mut valid) = (0, 0);
+    let mut buf = Vec::with_capacity(SCAN_CHUNK_SIZE);
+    let mut pos = 0;
+    while pos < length {
+        let n = (length - pos).min(SCAN_CHUNK_SIZE as u64);
+        buf.clear();
+        reader.read(n as usize, &mut buf)?;
+        for &base in &buf {
+            let code = match base {
+                b'A' | b'a' => 0,
+                b'C' | b'c' => 1,
+                b'G' | b'g' => 2,
+                b'T' | b't' => 3,
+                _ => {
+                    valid = 0;
+                    continue;
+                }
+            };
+            kmer = ((kmer << 2) | code) & mask;
+            valid += 1;
+            if valid >= k {
+                counts[kmer] += 1;
+            }
+        }
+        pos += n;
+    }
+    Ok(())
+}
+
+/// Counts of `A`, `C`, `G`, `T` and other bases in consecutive windows of `window` bases of the
+/// next `length` bases of `reader`, one window per row, where the last window may be shorter.
+pub(crate) fn composition(
+    mut reader: SequenceReader,
+    length: u64,
+    window: u64,
+) -> Result<Array2<u64>> {
+    if window == 0 {
+        bail!("window must be positive");
+    }
+    let mut counts = Vec::with_capacity(length.div_ceil(window) as usize * NUM_BASE_CLASSES);
+    let mut buf = Vec::with_capacity(window.min(SCAN_CHUNK_SIZE as u64) as usize);
+    let mut pos = 0;
+    while pos < length {
+        let window_length = (length - pos).min(window);
+        let mut window_counts = [0; NUM_BASE_CLASSES];
+        // Large windows are read in chunks
+        let mut remaining = window_length;
+        while remaining > 0 {
+            let n = remaining.min(SCAN_CHUNK_SIZE as u64);
+            buf.clear();
+            reader.read(n as usize, &mut buf)?;
+            count_bases(&buf, &mut window_counts);
+            remaining -= n;
+        }
+        counts.extend(window_counts);
+        pos += window_length;
+    }
+    let num_windows = counts.len() / NUM_BASE_CLASSES;
+    Ok(Array2::from_shape_vec(
+        (num_windows, NUM_BASE_CLASSES),
+        counts,
+    )?)
+}
+
+/// Sums of consecutive blocks of `block_size` values of a track contig, where the last block
+/// may be shorter. `reader` must be positioned at the start.
+pub(crate) fn block_sums(
+    mut reader: SequenceReader,
+    contig_length: u64,
+    block_size: u64,
+    value_type: ValueType,
+) -> Result<Vec<f64>> {
+    if block_size == 0 {
+        bail!("block_size must be positive");
+    }
+    let num_values = value_type.num_values(contig_length)?;
+    let mut sums = Vec::with_capacity(num_values.div_ceil(block_size) as usize);
+    let mut buf = Vec::with_capacity((block_size * value_type.size()) as usize);
+    let mut pos = 0;
+    while pos < num_values {
+        let n = (num_values - pos).min(block_size);
+        buf.clear();
+        reader.read((n * value_type.size()) as usize, &mut buf)?;
+        sums.push(value_type.weights(&buf)?.iter().sum());
+        pos += n;
+    }
+    Ok(sums)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use noodles::bgzf;
+
+    use super::*;
+
+    /// A reader over `data` compressed to a temporary BGZF file, and the number of values.
+    fn reader(data: &[u8], strip: bool) -> (SequenceReader, u64) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.gz");
+        let mut writer = bgzf::io::Writer::new(File::create(&path).unwrap());
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+        // The open file stays readable after the directory is removed
+        let reader =
+            SequenceReader::new(BgzfReader::new(Box::new(File::open(&path).unwrap())), strip);
+        let length = data.iter().filter(|&&b| !(strip && b == b'\n')).count() as u64;
+        (reader, length)
+    }
+
+    fn windows(data: &[u8], strip: bool, window_length: u64, stride: u64) -> Vec<(u64, Vec<u8>)> {
+        let (reader, length) = reader(data, strip);
+        SlidingWindows::new(reader, length, window_length, stride)
+            .unwrap()
+            .map(|window| {
+                let (start, window) = window.unwrap();
+                (start, window.to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_strip_line_breaks() {
+        let strip = |raw: &[u8], column, line_bases, line_width| {
+            let mut buf = raw.to_vec();
+            strip_line_breaks(&mut buf, column, line_bases, line_width).then_some(buf)
+        };
+        assert_eq!(strip(b"CGT\nACGT\nAC", 1, 4, 5).unwrap(), b"CGTACGTAC");
+        assert_eq!(strip(b"CGT\r\nACGT\r\nAC", 1, 4, 6).unwrap(), b"CGTACGTAC");
+        assert_eq!(strip(b"ACGT", 0, 4, 5).unwrap(), b"ACGT");
+        assert_eq!(strip(b"", 0, 4, 5).unwrap(), b"");
+        // CRLF line endings which the index does not count
+        assert!(strip(b"CGT\r\nACGT\r", 1, 4, 5).is_none());
+        // Lines of irregular width, and a region which runs into the next record
+        assert!(strip(b"ACGT\nACG\nTAC", 0, 4, 5).is_none());
+        assert!(strip(b"AC\n>b\nA", 0, 4, 5).is_none());
+        assert!(strip(b"ACGT\n", 0, 4, 5).is_none());
+    }
+
+    #[test]
+    fn test_sliding_windows() {
+        let expected = |windows: &[(u64, &str)]| {
+            windows
+                .iter()
+                .map(|(start, window)| (*start, window.as_bytes().to_vec()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            windows(b"ACGT\nTGCA\nAA\n", true, 4, 3),
+            expected(&[(0, "ACGT"), (3, "TTGC"), (6, "CAAA")])
+        );
+        assert_eq!(
+            windows(b"ACGT\nTGCA\nAA\n", true, 3, 5),
+            expected(&[(0, "ACG"), (5, "GCA")])
+        );
+        assert_eq!(
+            windows(b"\x01\x02\x0a\x04", false, 2, 2),
+            expected(&[(0, "\x01\x02"), (2, "\x0a\x04")])
+        );
+        assert!(windows(b"ACGT\n", true, 5, 1).is_empty());
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let gaps = |data: &[u8], min_length| {
+            let (reader, length) = reader(data, true);
+            find_gaps(reader, length, min_length).unwrap()
+        };
+        assert_eq!(
+            gaps(b"NNAC\nGNnN\nTN\n", 1),
+            Array2::from(vec![[0, 2], [5, 8], [9, 10]])
+        );
+        assert_eq!(gaps(b"NNAC\nGNnN\nTN\n", 3), Array2::from(vec![[5, 8]]));
+        assert_eq!(gaps(b"ACGT\n", 1).shape(), &[0, 2]);
+    }
+
+    #[test]
+    fn test_count_kmers() {
+        let kmers = |data: &[u8], k| {
+            let (reader, length) = reader(data, true);
+            let mut counts = kmer_counts(k).unwrap();
+            count_kmers(reader, length, &mut counts).unwrap();
+            counts
+        };
+        assert_eq!(kmers(b"ACgT\nNA\n", 1), vec![2, 1, 1, 1]);
+        // AC, CG, GT, but neither TN nor NA
+        let counts = kmers(b"ACgT\nNA\n", 2);
+        assert_eq!(counts.iter().sum::<u64>(), 3);
+        assert_eq!((counts[1], counts[6], counts[11]), (1, 1, 1));
+        assert!(kmers(b"AC\n", 3).iter().all(|&count| count == 0));
+        assert!(kmer_counts(0).is_err());
+        assert!(kmer_counts(MAX_KMER_LENGTH + 1).is_err());
+    }
+
+    #[test]
+    fn test_composition() {
+        let counts = |data: &[u8], window| {
+            let (reader, length) = reader(data, true);
+            composition(reader, length, window)
+        };
+        assert_eq!(
+            counts(b"ACGT\nNNgc\nA\n", 4).unwrap(),
+            Array2::from(vec![[1, 1, 1, 1, 0], [0, 1, 1, 0, 2], [1, 0, 0, 0, 0]])
+        );
+        assert_eq!(counts(b"ACGT\n", 10).unwrap().shape(), &[1, 5]);
+        assert!(counts(b"ACGT\n", 0).is_err());
+    }
+
+    #[test]
+    fn test_block_sums() {
+        let sums = |data: &[u8], block_size, value_type| {
+            let (reader, length) = reader(data, false);
+            block_sums(reader, length, block_size, value_type)
+        };
+        assert_eq!(
+            sums(b"\x01\x02\x03\x04\x05", 2, ValueType::U8).unwrap(),
+            vec![3.0, 7.0, 5.0]
+        );
+        assert_eq!(
+            sums(b"\x01\x00\x02\x01", 1, ValueType::U16).unwrap(),
+            vec![1.0, 258.0]
+        );
+        assert!(sums(b"", 2, ValueType::F32).unwrap().is_empty());
+        assert!(sums(b"\x01", 0, ValueType::U8).is_err());
+        assert!(sums(b"\x01", 1, ValueType::U16).is_err());
+    }
+}
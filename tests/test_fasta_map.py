@@ -1,11 +1,36 @@
+import base64
+import gzip
+import hashlib
+import json
 import multiprocessing
+import os
 import pickle
+import shutil
+import subprocess
+import sys
+import urllib.error
+import urllib.request
 from concurrent.futures import ProcessPoolExecutor
 from pathlib import Path
+from typing import cast
 
+import fastar_loader
 import numpy as np
 import pytest
-from fastar_loader import FastarLoader
+from fastar_loader import (
+    ContigNotFoundError,
+    FastarLoader,
+    GcMatchedSampler,
+    InvalidSequenceError,
+    LoadOptions,
+    Mutations,
+    NameNotFoundError,
+    OutOfBoundsError,
+    PackedLoader,
+    WindowPartition,
+    WindowSampler,
+    set_coordinates,
+)
 from numpy.testing import assert_array_equal
 
 
@@ -51,6 +76,51 @@ def test_custom_names(
             assert loader.contigs(name) == contigs
 
 
+def test_custom_names_cached(
+    assemblies_path: Path,
+    expected_names: list[str],
+    fasta_structure: dict[str, list[tuple[str, int]]],
+) -> None:
+    clean_cache(assemblies_path)
+    for names in [expected_names[:2], expected_names[1::-1]]:
+        loader = FastarLoader(
+            assemblies_path, names=names, no_cache=False, storage_method="mmap"
+        )
+        assert sorted(loader.names) == sorted(expected_names[:2])
+        for name, contigs in fasta_structure.items():
+            if name in expected_names[:2]:
+                assert loader.contigs(name) == contigs
+    # Both orders share one cache file for the subset
+    assert len(list(assemblies_path.glob(".fasta-map-cache-*"))) == 1
+    clean_cache(assemblies_path)
+
+
+def test_include_exclude(assemblies_path: Path, expected_names: list[str]) -> None:
+    clean_cache(assemblies_path)
+    included = FastarLoader(assemblies_path, include=["GCF_*"])
+    assert sorted(included.names) == sorted(n for n in expected_names if n.startswith("GCF_"))
+    excluded = FastarLoader(assemblies_path, exclude=["GCF_*"])
+    assert sorted(excluded.names) == sorted(
+        n for n in expected_names if not n.startswith("GCF_")
+    )
+    # Each filter gets its own cache file
+    assert len(list(assemblies_path.glob(".fasta-map-cache-*"))) == 2
+    clean_cache(assemblies_path)
+
+
+def test_follow_symlinks(assemblies_path: Path, tmp_path: Path, expected_names: list[str]) -> None:
+    (tmp_path / "linked").symlink_to(assemblies_path.resolve(), target_is_directory=True)
+    for path in assemblies_path.glob(f"{expected_names[0]}.*"):
+        shutil.copy(path, tmp_path)
+    options = {"no_cache": True, "storage_method": "memory"}
+    loader = FastarLoader(tmp_path, **options)
+    assert sorted(loader.names) == sorted(
+        [expected_names[0]] + [f"linked/{name}" for name in expected_names]
+    )
+    loader = FastarLoader(tmp_path, follow_symlinks=False, **options)
+    assert loader.names == [expected_names[0]]
+
+
 def test_read_sequence(
     loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
 ) -> None:
@@ -99,7 +169,7 @@ def test_multiprocess(
     clean_cache(assemblies_path)
 
 
-@pytest.mark.parametrize("storage_method", ["shmem", "mmap", "memory"])
+@pytest.mark.parametrize("storage_method", ["shmem", "mmap", "memory", "auto"])
 def test_cache(assemblies_path: Path, storage_method: str) -> None:
     ref = FastarLoader(assemblies_path, no_cache=True, storage_method="memory")
 
@@ -113,6 +183,10 @@ def test_cache(assemblies_path: Path, storage_method: str) -> None:
 
     # Load with cache
     cache = FastarLoader(assemblies_path, storage_method=storage_method)
+    if storage_method == "auto":
+        assert cache.storage_method in ("shmem", "mmap")
+    else:
+        assert cache.storage_method == storage_method
     assert ref.names == cache.names
     for name in ref.names:
         assert ref.contigs(name) == cache.contigs(name)
@@ -142,3 +216,1316 @@ def test_min_contig_length(assemblies_path: Path, expected_names: list[str]) ->
                 assert (contig, length) not in restricted_contigs
         for contig, length in restricted_contigs:
             assert (contig, length) in ref_contigs
+
+
+def test_min_contig_length_cached(
+    assemblies_path: Path, tmp_path: Path, expected_names: list[str]
+) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    min_length = 1_000_000
+    ref = FastarLoader(tmp_path, no_cache=False)
+    # The cache built with another minimum contig length is not reused
+    restricted = FastarLoader(tmp_path, min_contig_length=min_length, no_cache=False)
+    for name in expected_names:
+        assert restricted.contigs(name) == [
+            (contig, length) for contig, length in ref.contigs(name) if length >= min_length
+        ]
+    assert FastarLoader(tmp_path, no_cache=False).contigs(name) == ref.contigs(name)
+
+
+@pytest.mark.parametrize("storage_method", ["shmem", "mmap"])
+def test_pickle_validated(
+    assemblies_path: Path,
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+    storage_method: str,
+) -> None:
+    clean_cache(assemblies_path)
+    loader = FastarLoader(
+        assemblies_path, no_cache=False, storage_method=storage_method, validate_handle=True
+    )
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    unpickled_loader = pickle.loads(pickle.dumps(loader))
+    sequence = unpickled_loader.read_sequence(name, contig, start, length)
+    assert_array_equal(sequence, expected_sequence)
+    clean_cache(assemblies_path)
+
+
+def test_shmem_dir(
+    assemblies_path: Path,
+    tmp_path: Path,
+    monkeypatch: pytest.MonkeyPatch,
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+) -> None:
+    monkeypatch.setenv("FASTAR_LOADER_SHMEM_DIR", str(tmp_path))
+    loader = FastarLoader(assemblies_path, no_cache=True, storage_method="shmem")
+    assert len(list(tmp_path.iterdir())) == 1
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    unpickled_loader = pickle.loads(pickle.dumps(loader))
+    sequence = unpickled_loader.read_sequence(name, contig, start, length)
+    assert_array_equal(sequence, expected_sequence)
+    # The creator removes the file
+    del loader, unpickled_loader
+    assert list(tmp_path.iterdir()) == []
+
+
+def test_read_region(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    end = start + length
+    for region, one_based in [
+        (f"{contig}:{start + 1:,}-{end:,}", True),
+        (f"{contig}:{start}-{end}", False),
+    ]:
+        sequence = loader.read_sequence(name, region, one_based=one_based)
+        assert_array_equal(sequence, expected_sequence)
+    whole = loader.read_sequence(name, contig)
+    assert len(whole) == loader.contig_length(name, contig)
+    assert_array_equal(whole[start:end], expected_sequence)
+    rest = loader.read_sequence(name, f"{contig}:{start + 1}")
+    assert_array_equal(rest[:length], expected_sequence)
+    with pytest.raises(ContigNotFoundError):
+        loader.read_sequence(name, "missing")
+    with pytest.raises(ValueError, match="together"):
+        loader.read_sequence(name, contig, start)
+
+
+def test_coordinates(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    end = start + length
+    # 1-based starts with exclusive ends, e.g., as in some genome browsers
+    region = f"{contig}:{start + 1}-{end + 1}"
+    sequence = loader.read_sequence(name, region, end_inclusive=False)
+    assert_array_equal(sequence, expected_sequence)
+    sequence = loader.read_sequence(name, contig, start + 1, length, one_based=True)
+    assert_array_equal(sequence, expected_sequence)
+    with pytest.raises(ValueError, match="not 1-based"):
+        loader.read_sequence(name, contig, 0, length, one_based=True)
+    try:
+        set_coordinates(one_based=False)
+        sequence = loader.read_sequence(name, f"{contig}:{start}-{end}")
+        assert_array_equal(sequence, expected_sequence)
+        # Calls override the module-level conventions
+        sequence = loader.read_sequence(name, f"{contig}:{start + 1}-{end}", one_based=True)
+        assert_array_equal(sequence, expected_sequence)
+        set_coordinates(one_based=True)
+        sequence = loader.read_sequence(name, contig, start + 1, length)
+        assert_array_equal(sequence, expected_sequence)
+    finally:
+        set_coordinates()
+    assert_array_equal(loader.read_sequence(name, contig, start, length), expected_sequence)
+
+
+def test_read_sequence_all(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    # The copies below foo/ have the same contigs
+    names, sequences = loader.read_sequence_all(contig, start, length)
+    assert set(names) == {name, f"foo/{name}"}
+    for sequence in sequences:
+        assert_array_equal(sequence, expected_sequence)
+    contig_length = loader.contig_length(name, contig)
+    names, batch = loader.read_sequence_all(contig, contig_length - 2, 4, names=[name], pad="N")
+    assert names == [name]
+    assert isinstance(batch, np.ndarray) and batch.shape == (1, 4)
+    assert_array_equal(batch[0, 2:], np.frombuffer(b"NN", dtype=np.uint8))
+    with pytest.raises(ContigNotFoundError):
+        loader.read_sequence_all("missing", 0, 1, names=[name])
+
+
+def test_read_batch(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    queries = [(name, contig, start, length), (name, contig, start, length // 2)]
+    batch = loader.read_batch(queries, mask=True)
+    assert batch.values.shape == (2, length)
+    assert_array_equal(batch.lengths, [length, length // 2])
+    assert_array_equal(batch.values[0], expected_sequence)
+    assert_array_equal(batch.values[1, : length // 2], expected_sequence[: length // 2])
+    assert (batch.values[1, length // 2 :] == ord("N")).all()
+    assert batch.mask is not None
+    assert_array_equal(batch.mask.sum(axis=1), batch.lengths)
+
+    batch = fastar_loader.collate([np.arange(3, dtype=np.uint8), np.ones(1, np.uint8)], length=4)
+    assert_array_equal(batch.values, [[0, 1, 2, 0], [1, 0, 0, 0]])
+    assert batch.mask is None
+    with pytest.raises(ValueError, match="does not fit"):
+        loader.read_batch(queries, length=length - 1)
+
+
+def test_count_kmers(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    counts = loader.count_kmers(name, 1, contig, start, length)
+    sequence = expected_sequence.tobytes().upper()
+    assert counts.tolist() == [sequence.count(base) for base in b"ACGT"]
+    kmers = loader.count_kmers(name, 3, contig)
+    assert kmers.shape == (64,)
+    genome = loader.count_kmers(name, 3)
+    assert np.all(genome >= kmers)
+    with pytest.raises(ValueError):
+        loader.count_kmers(name, 13)
+
+
+def test_composition(
+    assemblies_path: Path, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    loader = FastarLoader(assemblies_path, no_cache=True, storage_method="memory", composition=True)
+    sequence = expected_sequence.tobytes().upper()
+    expected = [sequence.count(base) for base in b"ACGT"]
+    expected.append(len(sequence) - sum(expected))
+    gc, counts = loader.composition(name, contig, start, length)
+    assert counts.tolist() == [expected]
+    assert gc[0] == pytest.approx((expected[1] + expected[2]) / sum(expected[:4]))
+    gc, counts = loader.composition(name, contig, start, length, window=10)
+    assert len(gc) == len(counts) == -(-length // 10)
+    assert counts.sum(axis=0).tolist() == expected
+    # The cached counts of whole contigs match counting them on demand
+    whole = loader.genome_composition(name)
+    assert whole[contig].tolist() == loader.composition(name, contig)[1][0].tolist()
+    with pytest.raises(RuntimeError, match="without composition"):
+        FastarLoader(assemblies_path, no_cache=True, storage_method="memory").genome_composition(
+            name
+        )
+
+
+def test_composition_cached(
+    assemblies_path: Path, tmp_path: Path, expected_names: list[str]
+) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    name = expected_names[0]
+    FastarLoader(tmp_path, no_cache=False)
+    # A cache without composition is not reused for a load requesting it
+    for _ in range(2):
+        loader = FastarLoader(tmp_path, no_cache=False, composition=True)
+        assert len(loader.genome_composition(name)) > 0
+    with pytest.raises(RuntimeError, match="without composition"):
+        FastarLoader(tmp_path, no_cache=False).genome_composition(name)
+
+
+def test_ambiguity(
+    assemblies_path: Path, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    stored = expected_sequence.tobytes()
+    loader = FastarLoader(assemblies_path, no_cache=True, storage_method="memory", ambiguity="n")
+    assert loader.ambiguity == ("n", 0)
+    mapped = loader.read_sequence(name, contig, start, length).tobytes()
+    assert mapped == bytes(
+        base if base in b"ACGTacgt" else (b"n" if chr(base).islower() else b"N")[0]
+        for base in stored
+    )
+
+    loader = FastarLoader(
+        assemblies_path,
+        no_cache=True,
+        storage_method="memory",
+        ambiguity="sample",
+        ambiguity_seed=1,
+    )
+    sampled = loader.read_sequence(name, contig, start, length).tobytes()
+    codes = {"R": "AG", "Y": "CT", "S": "CG", "W": "AT", "K": "GT", "M": "AC", "B": "CGT"}
+    codes.update({"D": "AGT", "H": "ACT", "V": "ACG", "U": "T"})
+    for before, after in zip(stored.decode().upper(), sampled.decode().upper()):
+        assert after in (before if before in "ACGT" else codes.get(before, "N"))
+    # Overlapping reads resolve each position to the same base
+    assert loader.read_sequence(name, contig, start + 1, length - 1).tobytes() == sampled[1:]
+    with pytest.raises(ValueError, match="Unknown ambiguity policy"):
+        LoadOptions(ambiguity="drop")
+
+
+def test_alphabet(
+    assemblies_path: Path, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    stored = expected_sequence.tobytes().decode()
+    loader = FastarLoader(
+        assemblies_path, no_cache=True, storage_method="memory", alphabet="".join(set(stored))
+    )
+    assert loader.alphabet is not None
+    assert_array_equal(loader.read_sequence(name, contig, start, length), expected_sequence)
+    # The first base of the region is missing from the alphabet
+    alphabet = "".join(set(stored) - {stored[0]}) + "X"
+    loader = FastarLoader(
+        assemblies_path, no_cache=True, storage_method="memory", alphabet=alphabet
+    )
+    with pytest.raises(InvalidSequenceError, match=f"0-based position {start} of {contig}"):
+        loader.read_sequence(name, contig, start, length)
+    with pytest.raises(ValueError, match="alphabet is empty"):
+        LoadOptions(alphabet="")
+
+
+def test_load_options(assemblies_path: Path, expected_names: list[str]) -> None:
+    options = LoadOptions(no_cache=True, storage_method="memory")
+    loader = FastarLoader.from_options(assemblies_path, options)
+    assert sorted(loader.names) == sorted(expected_names)
+    assert loader.storage_method == "memory"
+    with pytest.raises(TypeError):
+        LoadOptions(True)  # type: ignore[misc]
+    with pytest.raises(ValueError, match="requires no_cache=false"):
+        LoadOptions(no_cache=True, storage_method="mmap")
+    with pytest.raises(ValueError, match="Unknown storage method"):
+        FastarLoader(assemblies_path, storage_method="disk")
+    with pytest.raises(ValueError, match="single root"):
+        FastarLoader({"a/": assemblies_path, "b/": assemblies_path}, bundle="genomes.bundle")
+
+
+def test_cleanup_segments(
+    assemblies_path: Path, tmp_path: Path, monkeypatch: pytest.MonkeyPatch
+) -> None:
+    monkeypatch.setenv("FASTAR_LOADER_SHMEM_DIR", str(tmp_path))
+    loader = FastarLoader(assemblies_path, no_cache=True, storage_method="shmem")
+    # A segment left behind by a process which has exited
+    process = multiprocessing.Process(target=int)
+    process.start()
+    process.join()
+    orphan = tmp_path / f"fastar-loader-{process.pid}-1-0000abcd"
+    orphan.write_bytes(bytes(16))
+    (tmp_path / "unrelated").touch()
+    segments = {segment.name: segment for segment in fastar_loader.list_segments()}
+    assert len(segments) == 2
+    assert not segments[orphan.name].alive
+    assert segments[orphan.name].size == 16
+    (live,) = [segment for segment in segments.values() if segment.alive]
+    assert live.pid == os.getpid()
+    assert fastar_loader.cleanup_segments(older_than=1e12) == []
+    assert fastar_loader.cleanup_segments(prefix=f"fastar-loader-{os.getpid()}-") == []
+    removed = fastar_loader.cleanup_segments()
+    assert [segment.name for segment in removed] == [orphan.name]
+    assert sorted(path.name for path in tmp_path.iterdir()) == sorted([live.name, "unrelated"])
+    del loader
+
+
+def test_sharded_cache(
+    assemblies_path: Path,
+    expected_names: list[str],
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+) -> None:
+    clean_cache(assemblies_path)
+    FastarLoader(assemblies_path, no_cache=False, storage_method="mmap", num_shards=2)
+    assert len(list(assemblies_path.glob(".fasta-map-cache-*.shard-*"))) == 2
+    loader = FastarLoader(assemblies_path, no_cache=False, storage_method="mmap", num_shards=2)
+    assert sorted(loader.names) == sorted(expected_names)
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    sequence = loader.read_sequence(name, contig, start, length)
+    assert_array_equal(sequence, expected_sequence)
+    unpickled_loader = pickle.loads(pickle.dumps(loader))
+    sequence = unpickled_loader.read_sequence(name, contig, start, length)
+    assert_array_equal(sequence, expected_sequence)
+    clean_cache(assemblies_path)
+
+
+def test_multiple_roots(
+    assemblies_path: Path,
+    expected_names: list[str],
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+) -> None:
+    loader = FastarLoader({"hot/": assemblies_path, "cold/": assemblies_path})
+    assert sorted(loader.names) == sorted(
+        f"{prefix}{name}" for prefix in ["hot/", "cold/"] for name in expected_names
+    )
+    _, name, contig, start, length, expected_sequence = fasta_test_data
+    sequence = loader.read_sequence(f"cold/{name}", contig, start, length)
+    assert_array_equal(sequence, expected_sequence)
+    unpickled_loader = pickle.loads(pickle.dumps(loader))
+    sequence = unpickled_loader.read_sequence(f"hot/{name}", contig, start, length)
+    assert_array_equal(sequence, expected_sequence)
+    with pytest.raises(RuntimeError):
+        FastarLoader({"a/": assemblies_path, "a/b/": assemblies_path})
+
+
+def test_union(assemblies_path: Path, expected_names: list[str]) -> None:
+    first = FastarLoader(assemblies_path, names=expected_names[:2], no_cache=True)
+    second = FastarLoader(assemblies_path, names=expected_names[1:3], no_cache=True)
+    with pytest.raises(RuntimeError):
+        first.union(second)
+    merged = first.union(second, on_conflict="first")
+    assert sorted(merged.names) == sorted(expected_names[:3])
+    prefixed = first.union(second, on_conflict="prefix", prefixes=("a/", "b/"))
+    assert sorted(prefixed.names) == sorted(
+        [f"a/{name}" for name in expected_names[:2]] + [f"b/{name}" for name in expected_names[1:3]]
+    )
+    assert prefixed.contigs(f"b/{expected_names[2]}") == merged.contigs(expected_names[2])
+
+
+def test_with_added_and_removed(
+    assemblies_path: Path,
+    expected_names: list[str],
+    fasta_structure: dict[str, list[tuple[str, int]]],
+) -> None:
+    loader = FastarLoader(assemblies_path, names=expected_names[:2], no_cache=True)
+    added = loader.with_added(expected_names[2:3])
+    assert sorted(added.names) == sorted(expected_names[:3])
+    assert added.contigs(expected_names[2]) == fasta_structure[expected_names[2]]
+    removed = added.with_removed(expected_names[:1])
+    assert sorted(removed.names) == sorted(expected_names[1:3])
+    assert sorted(loader.names) == sorted(expected_names[:2])
+    with pytest.raises(NameNotFoundError):
+        removed.with_removed(expected_names[:1])
+    with pytest.raises(NameNotFoundError):
+        loader.with_added(["../outside"])
+    # Added entries are indexed with the options the loader was built with
+    min_length = 1_000_000
+    restricted = FastarLoader(
+        assemblies_path, names=expected_names[:2], min_contig_length=min_length, no_cache=True
+    )
+    added = restricted.with_added(expected_names[2:3])
+    assert added.contigs(expected_names[2]) == [
+        (contig, length)
+        for contig, length in fasta_structure[expected_names[2]]
+        if length >= min_length
+    ]
+
+
+def test_groups(assemblies_path: Path, tmp_path: Path, expected_names: list[str]) -> None:
+    layout = {
+        "bacteria/firmicutes": expected_names[0],
+        "bacteria": expected_names[1],
+        "archaea": expected_names[2],
+    }
+    for group, name in layout.items():
+        (tmp_path / group).mkdir(parents=True, exist_ok=True)
+        for path in assemblies_path.glob(f"{name}.fna.gz*"):
+            shutil.copy(path, tmp_path / group / path.name)
+    loader = FastarLoader(tmp_path, no_cache=True, storage_method="memory")
+    assert loader.groups() == ["archaea/", "bacteria/", "bacteria/firmicutes/"]
+    assert sorted(loader.names_in_group("bacteria")) == sorted(
+        [f"bacteria/firmicutes/{expected_names[0]}", f"bacteria/{expected_names[1]}"]
+    )
+    assert loader.names_in_group("bacteria/firmicutes/") == [
+        f"bacteria/firmicutes/{expected_names[0]}"
+    ]
+    weights = loader.group_weights({"bacteria": 1.0, "archaea": 1.0})
+    assert weights[f"archaea/{expected_names[2]}"] == 1.0
+    assert weights[f"bacteria/{expected_names[1]}"] == 0.5
+    sampler = WindowSampler(loader, 100, weights=loader.group_weights({"archaea": 1.0}), seed=0)
+    assert {name for name, _, _ in sampler.sample_windows(16)} == {f"archaea/{expected_names[2]}"}
+    with pytest.raises(ValueError, match="overlap"):
+        loader.group_weights({"bacteria": 1.0, "bacteria/firmicutes": 1.0})
+    with pytest.raises(ValueError, match="no names"):
+        loader.group_weights({"fungi": 1.0})
+
+
+def test_lazy(
+    assemblies_path: Path,
+    expected_names: list[str],
+    fasta_structure: dict[str, list[tuple[str, int]]],
+) -> None:
+    clean_cache(assemblies_path)
+    loader = FastarLoader(assemblies_path, lazy=True)
+    assert sorted(loader.names) == sorted(expected_names)
+    for name, contigs in fasta_structure.items():
+        assert loader.contigs(name) == contigs
+    assert len(list(assemblies_path.glob(".fasta-map-cache-*"))) == 0
+
+
+def test_background(assemblies_path: Path, expected_names: list[str]) -> None:
+    loader = FastarLoader(assemblies_path, no_cache=True, storage_method="memory", background=True)
+    loader.wait()
+    assert loader.ready()
+    assert sorted(loader.names) == sorted(expected_names)
+    failed = FastarLoader(assemblies_path, names=["missing"], background=True)
+    with pytest.raises(RuntimeError):
+        failed.wait()
+
+
+def test_on_progress(assemblies_path: Path, expected_names: list[str]) -> None:
+    calls: list[tuple[int, int, str]] = []
+    FastarLoader(
+        assemblies_path,
+        no_cache=True,
+        storage_method="memory",
+        on_progress=lambda done, total, name: calls.append((done, total, name)),
+    )
+    done, total, name = calls[-1]
+    assert done == total == len(expected_names)
+    assert name in expected_names
+
+
+def test_skipped(assemblies_path: Path, tmp_path: Path, expected_names: list[str]) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    (tmp_path / "GCA_000146045.2.fna.gz.fai").unlink()
+    loader = FastarLoader(tmp_path, strict=False, no_cache=True, storage_method="memory")
+    assert [name for name, _ in loader.skipped] == ["GCA_000146045.2"]
+    assert sorted(loader.names) == sorted(set(expected_names) - {"GCA_000146045.2"})
+    assert FastarLoader(assemblies_path, no_cache=True, storage_method="memory").skipped == []
+
+
+def test_exceptions(loader: FastarLoader) -> None:
+    with pytest.raises(NameNotFoundError):
+        loader.contigs("missing")
+    with pytest.raises(ContigNotFoundError):
+        loader.read_sequence("GCA_000146045.2", "missing", 0, 10)
+    contig, length = loader.contigs("GCA_000146045.2")[0]
+    with pytest.raises(OutOfBoundsError):
+        loader.read_sequence("GCA_000146045.2", contig, length - 5, 10)
+    # The dedicated exceptions remain RuntimeErrors for existing callers
+    with pytest.raises(RuntimeError):
+        loader.contigs("missing")
+
+
+def test_path_traversal(loader: FastarLoader, assemblies_path: Path) -> None:
+    with pytest.raises(NameNotFoundError, match="Invalid name"):
+        loader.read_sequence("../assemblies/GCA_000146045.2", "BK006935.2", 0, 10)
+    with pytest.raises(NameNotFoundError, match="Invalid name"):
+        FastarLoader(assemblies_path / "foo", names=["../GCA_000146045.2"])
+
+
+def test_contig_accessors(loader: FastarLoader) -> None:
+    for name in loader.names:
+        contigs = loader.contigs(name)
+        assert loader.contig_dict(name) == dict(contigs)
+        for contig, length in contigs:
+            assert loader.has_contig(name, contig)
+            assert loader.contig_length(name, contig) == length
+        assert not loader.has_contig(name, "missing")
+        with pytest.raises(ContigNotFoundError):
+            loader.contig_length(name, "missing")
+
+
+def test_contigs_array(loader: FastarLoader) -> None:
+    for name in loader.names:
+        names, lengths = loader.contigs_array(name)
+        assert lengths.dtype == np.uint64
+        pairs = [(contig.decode(), int(length)) for contig, length in zip(names, lengths)]
+        assert pairs == loader.contigs(name)
+
+
+def test_dunder_protocol(loader: FastarLoader) -> None:
+    assert len(loader) == len(loader.names)
+    assert list(loader) == loader.names
+    assert loader.names[0] in loader
+    assert "missing" not in loader
+    assert 1 not in loader
+    total = sum(len(loader.contigs(name)) for name in loader.names)
+    assert f"names={len(loader)}, contigs={total}" in repr(loader)
+
+
+def test_global_coordinates(loader: FastarLoader) -> None:
+    position = 0
+    for name in sorted(loader.names):
+        for contig, length in loader.contigs(name):
+            assert loader.global_start(name, contig) == position
+            assert loader.locate(position) == (name, contig, 0)
+            assert loader.locate(position + length - 1) == (name, contig, length - 1)
+            assert loader.global_contig(loader.global_id(name, contig)) == (name, contig)
+            position += length
+    assert loader.global_length() == position
+    with pytest.raises(OutOfBoundsError):
+        loader.locate(position)
+
+    name, contig, offset = loader.locate(1000)
+    assert_array_equal(loader.read_at(1000, 60), loader.read_sequence(name, contig, offset, 60))
+    _, first_length = loader.contigs(sorted(loader.names)[0])[0]
+    with pytest.raises(OutOfBoundsError):
+        loader.read_at(first_length - 10, 20)
+
+
+def test_window_sampler(loader: FastarLoader) -> None:
+    sampler = WindowSampler(loader, 100, seed=0)
+    ids, starts = sampler.sample_indices(50)
+    assert ids.dtype == np.uint32 and starts.dtype == np.uint64
+    assert_array_equal(WindowSampler(loader, 100, seed=0).sample_indices(50)[0], ids)
+
+    windows = sampler.read_windows(ids, starts)
+    assert windows.shape == (50, 100)
+    for window, id, start in zip(windows, ids, starts):
+        name, contig = loader.global_contig(int(id))
+        assert start + 100 <= loader.contig_length(name, contig)
+        assert_array_equal(window, loader.read_sequence(name, contig, int(start), 100))
+    assert sampler.sample_sequences(8).shape == (8, 100)
+
+    name = loader.names[0]
+    weighted = WindowSampler(loader, 100, weights={name: 1.0}, seed=0)
+    assert all(window[0] == name for window in weighted.sample_windows(20))
+    with pytest.raises(NameNotFoundError):
+        WindowSampler(loader, 100, weights={"missing": 1.0})
+
+
+def test_window_sampler_mutations(loader: FastarLoader) -> None:
+    ids, starts = WindowSampler(loader, 1000, max_n_fraction=0.0, seed=0).sample_indices(16)
+    plain = WindowSampler(loader, 1000).read_windows(ids, starts)
+    mutations = Mutations(substitution_rate=0.05)
+    mutated = WindowSampler(loader, 1000, seed=0, mutations=mutations).read_windows(ids, starts)
+    assert mutated.shape == plain.shape
+    assert 0.02 < np.mean(mutated != plain) < 0.08
+    again = WindowSampler(loader, 1000, seed=0, mutations=mutations).read_windows(ids, starts)
+    assert_array_equal(mutated, again)
+
+    indels = Mutations(indel_rate=0.01, max_indel_length=3)
+    shifted = WindowSampler(loader, 1000, seed=0, mutations=indels).read_windows(ids, starts)
+    assert shifted.shape == plain.shape
+    assert not np.array_equal(shifted, plain)
+    with pytest.raises(ValueError, match="between 0 and 1"):
+        Mutations(substitution_rate=2.0)
+    with pytest.raises(ValueError, match="max_indel_length"):
+        Mutations(indel_rate=0.1, max_indel_length=0)
+
+
+def test_window_sampler_state(loader: FastarLoader) -> None:
+    sampler = WindowSampler(loader, 100, seed=0)
+    sampler.sample_indices(10)
+    state = sampler.state_dict()
+    expected = sampler.sample_indices(10)
+
+    resumed = WindowSampler(loader, 100)
+    resumed.load_state_dict(state)
+    assert resumed.num_sampled == 10
+    for actual, wanted in zip(resumed.sample_indices(10), expected):
+        assert_array_equal(actual, wanted)
+    with pytest.raises(RuntimeError):
+        WindowSampler(loader, 50).load_state_dict(state)
+
+
+def test_gc_matched_sampler(loader: FastarLoader) -> None:
+    sampler = GcMatchedSampler(loader, 1000, stride=100, num_bins=10, seed=0)
+    assert sampler.window_length == 1000
+    assert sum(sampler.bin_sizes) > 0
+    positives = WindowSampler(loader, 1000, max_n_fraction=0.1, seed=1).sample_windows(20)
+    negatives = sampler.sample_windows(positives, num_per_positive=2)
+    assert len(negatives) == 40
+
+    bins = np.array(sampler.bin_sizes) > 0
+    for i, (name, contig, start) in enumerate(negatives):
+        positive = positives[i // 2]
+        gc = [
+            sampler.gc_content(np.array([loader.global_id(n, c)]), np.array([s]))[0]
+            for n, c, s in (positive, (name, contig, start))
+        ]
+        # Negatives come from the bin of their positive, or the nearest non-empty one
+        expected = min(int(gc[0] * 10), 9)
+        nearest = min(np.flatnonzero(bins), key=lambda b: abs(b - expected))
+        assert min(int(gc[1] * 10), 9) == nearest
+        assert start + 1000 <= loader.contig_length(name, contig)
+        for other in positives:
+            assert other[:2] != (name, contig) or abs(other[2] - start) >= 1000
+    with pytest.raises(RuntimeError, match="multiple of stride"):
+        GcMatchedSampler(loader, 1000, stride=300)
+
+
+def test_window_partition(loader: FastarLoader) -> None:
+    partitions = [WindowPartition(loader, 10_000, 3, worker, epoch=1) for worker in range(3)]
+    total = sum(
+        (length - 10_000) // 10_000 + 1
+        for name in loader.names
+        for _, length in loader.contigs(name)
+        if length >= 10_000
+    )
+    assert all(len(partition) == total // 3 for partition in partitions)
+    windows = set()
+    for partition in partitions:
+        ids, starts = partition.indices(0, len(partition))
+        windows.update(zip(ids.tolist(), starts.tolist()))
+    assert len(windows) == total // 3 * 3
+
+    partition = partitions[0]
+    ids, starts = partition.indices(0, 2)
+    name, contig = loader.global_contig(int(ids[1]))
+    expected = loader.read_sequence(name, contig, int(starts[1]), 10_000)
+    assert_array_equal(partition.read(0, 2)[1], expected)
+    other_epoch = WindowPartition(loader, 10_000, 3, 0, epoch=2)
+    assert not np.array_equal(other_epoch.indices(0, 10)[1], partition.indices(0, 10)[1])
+
+
+def test_iter_windows(loader: FastarLoader) -> None:
+    name = loader.names[0]
+    contig, length = loader.contigs(name)[0]
+    windows = list(loader.iter_windows(1000, stride=700, name=name, contig=contig))
+    assert [start for _, _, start, _ in windows] == list(range(0, length - 1000 + 1, 700))
+    for window_name, window_contig, start, values in windows[:20]:
+        assert (window_name, window_contig) == (name, contig)
+        assert_array_equal(values, loader.read_sequence(name, contig, start, 1000))
+
+    contigs = [
+        (window_name, window_contig)
+        for window_name, window_contig, start, _ in loader.iter_windows(100_000)
+        if start == 0
+    ]
+    assert contigs == [
+        (window_name, window_contig)
+        for window_name in sorted(loader.names)
+        for window_contig, window_length in loader.contigs(window_name)
+        if window_length >= 100_000
+    ]
+    with pytest.raises(ContigNotFoundError):
+        next(loader.iter_windows(10, name=name, contig="missing"))
+
+
+def test_window_sampler_n_filter(loader: FastarLoader) -> None:
+    sampler = WindowSampler(loader, 200, seed=0)
+    ids, starts = sampler.sample_indices(100)
+    windows = sampler.read_windows(ids, starts)
+    n_fraction = np.isin(windows, [ord("N"), ord("n")]).mean(axis=1)
+    assert_array_equal(sampler.filter_windows(ids, starts, 0.1), n_fraction <= 0.1)
+
+    filtered = WindowSampler(loader, 200, seed=0, max_n_fraction=0.0)
+    windows = filtered.sample_sequences(50)
+    assert windows.shape == (50, 200)
+    assert not np.isin(windows, [ord("N"), ord("n")]).any()
+
+
+def test_window_sampler_blacklist(loader: FastarLoader, tmp_path: Path) -> None:
+    name = loader.names[0]
+    contigs = loader.contigs(name)
+    # Blacklist all but the last 1000 bases of every contig, in a gzipped BED file
+    lines = ["# blacklist", "missing_contig\t0\t10"]
+    lines += [f"{contig}\t0\t{length - 1000}" for contig, length in contigs if length > 1000]
+    bed_path = tmp_path / "blacklist.bed.gz"
+    bed_path.write_bytes(gzip.compress("\n".join(lines).encode()))
+
+    sampler = WindowSampler(loader, 100, weights={name: 1.0}, seed=0, blacklist={name: bed_path})
+    contig, length = next((c, n) for c, n in contigs if n > 1000)
+    assert_array_equal(sampler.blacklist_intervals(name, contig), [[0, length - 1000]])
+    ids, starts = sampler.sample_indices(50)
+    assert not sampler.blacklisted(ids, starts).any()
+    for id, start in zip(ids, starts):
+        window_name, window_contig = loader.global_contig(int(id))
+        window_length = loader.contig_length(window_name, window_contig)
+        assert window_length <= 1000 or start >= window_length - 1000
+
+    id = loader.global_id(name, contig)
+    mask = sampler.blacklisted(np.array([id, id]), np.array([0, length - 1000]), 100)
+    assert_array_equal(mask, [True, False])
+
+
+def test_stats(assemblies_path: Path, tmp_path: Path) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    loader = FastarLoader(tmp_path, storage_method="mmap")
+    stats = loader.stats()
+    summary = loader.summary()
+    assert stats["num_names"] == len(loader.names)
+    assert stats["num_contigs"] == sum(cast(int, row["num_contigs"]) for row in summary)
+    assert stats["total_length"] == sum(cast(int, row["total_length"]) for row in summary)
+    assert stats["storage_method"] == "mmap"
+    (cache_path,) = cast(list[str], stats["cache_paths"])
+    assert stats["archive_size"] == Path(cache_path).stat().st_size
+
+    stats = FastarLoader(tmp_path, lazy=True).stats()
+    assert stats["num_names"] == len(loader.names)
+    assert stats["num_contigs"] is None and stats["cache_paths"] == []
+
+
+def test_content_hash(loader: FastarLoader, assemblies_path: Path, tmp_path: Path) -> None:
+    content_hash = loader.content_hash()
+    assert len(content_hash) == 32
+    # The copy has other modification times and is stored differently
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    for options in [{"storage_method": "mmap"}, {"num_shards": 2}, {"lazy": True}]:
+        assert FastarLoader(tmp_path, force_build=True, **options).content_hash() == content_hash
+    filtered = FastarLoader(tmp_path, no_cache=True, min_contig_length=10_000)
+    assert filtered.content_hash() != content_hash
+
+
+def test_metrics(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, _ = fasta_test_data
+    loader.reset_metrics()
+    assert loader.metrics()["queries"] == 0
+    loader.read_sequence(name, contig, start, length)
+    loader.read_sequence(name, contig, start, length)
+    metrics = loader.metrics()
+    assert metrics["queries"] == 2
+    # Line breaks are decoded along with the bases
+    assert metrics["bytes_decoded"] >= 2 * length
+    assert metrics["blocks_decompressed"] >= 2
+    assert set(metrics) >= {"cache_hits", "cache_misses", "open_files"}
+
+
+def test_tracing(
+    loader: FastarLoader,
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+    tmp_path: Path,
+) -> None:
+    _, name, contig, start, length, _ = fasta_test_data
+    with fastar_loader.tracing(tmp_path / "trace.json"):
+        loader.read_sequence(name, contig, start, length)
+    events = json.loads((tmp_path / "trace.json").read_text())
+    names = {event.get("name") for event in events}
+    assert {"query", "decompress"} <= names
+
+    fastar_loader.start_tracing(tmp_path / "trace.log", format="log")
+    with pytest.raises(ValueError, match="already active"):
+        fastar_loader.start_tracing(tmp_path / "other.log", format="log")
+    loader.read_sequence(name, contig, start, length)
+    fastar_loader.stop_tracing()
+    assert "decompress" in (tmp_path / "trace.log").read_text()
+    # Stopping twice is harmless
+    fastar_loader.stop_tracing()
+    with pytest.raises(ValueError, match="Unknown trace format"):
+        fastar_loader.start_tracing(tmp_path / "trace.xml", format="xml")
+
+
+def test_benchmark(loader: FastarLoader) -> None:
+    result = fastar_loader.benchmark(loader, n_queries=50, length=100, seed=1, threads=2)
+    assert result["n_queries"] == 50
+    assert result["threads"] == 2
+    assert result["bytes_per_second"] == pytest.approx(50 * 100 / result["elapsed"])
+    assert 0 < result["latency_p50"] <= result["latency_p99"] <= result["latency_max"]
+    with pytest.raises(ValueError, match="must be positive"):
+        fastar_loader.benchmark(loader, threads=0)
+
+
+def test_timeout_and_cancel(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected = fasta_test_data
+    sequence = loader.read_sequence(name, contig, start, length, timeout=10.0)
+    assert_array_equal(sequence, expected)
+    with pytest.raises(ValueError, match="timeout must be positive"):
+        loader.read_sequence(name, contig, start, length, timeout=0.0)
+
+    token = fastar_loader.CancellationToken()
+    assert not token.cancelled
+    names, _ = loader.read_sequence_all(contig, start, length, cancel=token)
+    assert name in names
+    token.cancel()
+    assert token.cancelled
+    with pytest.raises(RuntimeError, match="Interrupted"):
+        loader.read_sequence_all(contig, start, length, cancel=token)
+    sampler = WindowSampler(loader, 100, seed=0)
+    with pytest.raises(RuntimeError, match="Interrupted"):
+        sampler.sample_sequences(4, cancel=token)
+
+
+def test_read_sequence_memoryview(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected = fasta_test_data
+    view = loader.read_sequence(name, contig, start, length, as_memoryview=True)
+    assert isinstance(view, memoryview)
+    assert view.readonly
+    assert len(view) == length
+    assert bytes(view) == expected.tobytes()
+    view = loader.read_sequence(name, contig, start, length, timeout=10.0, as_memoryview=True)
+    assert bytes(view) == expected.tobytes()
+
+
+def test_packed_loader(
+    loader: FastarLoader,
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+    tmp_path: Path,
+) -> None:
+    _, name, contig, start, length, expected = fasta_test_data
+    path = tmp_path / "packed.rkyv"
+    loader.write_packed(path, [name])
+    packed = PackedLoader(path)
+    assert packed.names() == [name]
+    assert packed.contigs(name) == loader.contigs(name)
+    assert_array_equal(packed.read_sequence(name, contig, start, length), expected)
+    for packed_contig, contig_length in packed.contigs(name):
+        assert_array_equal(
+            packed.read_sequence(name, packed_contig, 0, contig_length),
+            loader.read_sequence(name, packed_contig, 0, contig_length),
+        )
+    with pytest.raises(OutOfBoundsError):
+        packed.read_sequence(name, contig, 0, loader.contig_length(name, contig) + 1)
+    with pytest.raises(NameNotFoundError):
+        packed.contigs("missing")
+
+
+def test_preload(
+    assemblies_path: Path, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    clean_cache(assemblies_path)
+    _, name, contig, start, length, expected = fasta_test_data
+    loader = FastarLoader(assemblies_path, no_cache=False, storage_method="shmem", preload=[name])
+    size = sum(contig_length for _, contig_length in loader.contigs(name))
+    assert loader.preloaded() == [(name, size)]
+    assert_array_equal(loader.read_sequence(name, contig, start, length), expected)
+    with pytest.raises(OutOfBoundsError):
+        loader.read_sequence(name, contig, 0, loader.contig_length(name, contig) + 1)
+
+    unpickled = pickle.loads(pickle.dumps(loader))
+    assert unpickled.preloaded() == [(name, size)]
+    assert_array_equal(unpickled.read_sequence(name, contig, start, length), expected)
+
+    # Preloading another genome evicts the least recently read one
+    other = next(other for other in loader.names if other != name)
+    other_size = sum(contig_length for _, contig_length in loader.contigs(other))
+    loader.preload([other], budget=max(size, other_size))
+    assert loader.preloaded() == [(other, other_size)]
+    assert_array_equal(loader.read_sequence(name, contig, start, length), expected)
+    with pytest.raises(ValueError, match="preload budget"):
+        loader.preload([name], budget=min(size, other_size) - 1)
+    with pytest.raises(ValueError, match="preload_budget must be positive"):
+        LoadOptions(preload_budget=0)
+    clean_cache(assemblies_path)
+
+
+def test_block_cache(
+    assemblies_path: Path, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    clean_cache(assemblies_path)
+    _, name, contig, start, length, expected = fasta_test_data
+    loader = FastarLoader(
+        assemblies_path, no_cache=False, storage_method="shmem", block_cache=1 << 24
+    )
+    stats = loader.block_cache_stats()
+    assert stats is not None and 0 < stats["size"] <= 1 << 24
+    assert_array_equal(loader.read_sequence(name, contig, start, length), expected)
+    stats = loader.block_cache_stats()
+    assert stats is not None and stats["hits"] == 0 and stats["misses"] > 0
+
+    # An unpickled loader reads the blocks decompressed by the original one
+    unpickled = pickle.loads(pickle.dumps(loader))
+    assert_array_equal(unpickled.read_sequence(name, contig, start, length), expected)
+    unpickled_stats = unpickled.block_cache_stats()
+    assert unpickled_stats is not None
+    assert unpickled_stats["hits"] == stats["misses"]
+    assert unpickled_stats["misses"] == stats["misses"]
+    with pytest.raises(ValueError, match="block_cache"):
+        LoadOptions(block_cache=1024)
+    clean_cache(assemblies_path)
+
+
+def test_readahead(
+    assemblies_path: Path,
+    loader: FastarLoader,
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+) -> None:
+    _, name, contig, _, _, _ = fasta_test_data
+    contig_length = dict(loader.contigs(name))[contig]
+    window = max(contig_length // 50, 1)
+    expected = [
+        loader.read_sequence(name, contig, start, min(window, contig_length - start))
+        for start in range(0, contig_length, window)
+    ]
+    for block_cache in (None, 1 << 24):
+        readahead = FastarLoader(
+            assemblies_path,
+            no_cache=True,
+            storage_method="memory",
+            readahead=4,
+            block_cache=block_cache,
+        )
+        # A sweep over the contig prefetches the blocks which the following windows read
+        for start, sequence in zip(range(0, contig_length, window), expected):
+            assert_array_equal(
+                readahead.read_sequence(name, contig, start, len(sequence)), sequence
+            )
+        # Reads backwards are not sequential and bypass the prefetched blocks
+        windows = list(zip(range(0, contig_length, window), expected))
+        for start, sequence in reversed(windows):
+            assert_array_equal(
+                readahead.read_sequence(name, contig, start, len(sequence)), sequence
+            )
+    with pytest.raises(ValueError, match="readahead must be positive"):
+        LoadOptions(readahead=0)
+
+
+def test_retry_policy(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected = fasta_test_data
+    fastar_loader.set_retry_policy(attempts=3, delay=0.0, errors=["ESTALE", "ETIMEDOUT"])
+    try:
+        assert_array_equal(loader.read_sequence(name, contig, start, length), expected)
+        assert loader.metrics()["retries"] == 0
+    finally:
+        fastar_loader.set_retry_policy()
+    with pytest.raises(ValueError, match="Unknown error class"):
+        fastar_loader.set_retry_policy(attempts=3, errors=["ENOENT"])
+    with pytest.raises(ValueError, match="attempts must be positive"):
+        fastar_loader.set_retry_policy(attempts=0)
+
+
+def test_num_threads(
+    assemblies_path: Path, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected = fasta_test_data
+    fastar_loader.set_num_threads(2)
+    try:
+        loader = FastarLoader(assemblies_path, io_threads=2)
+        assert loader._index_map.io_threads == 2
+        names, sequences = loader.read_sequence_all(contig, start, length)
+        assert name in names
+        for sequence in sequences:
+            assert_array_equal(sequence, expected)
+        loader = pickle.loads(pickle.dumps(loader))
+        assert loader._index_map.io_threads == 2
+    finally:
+        fastar_loader.set_num_threads(None)
+    with pytest.raises(ValueError, match="must be positive"):
+        fastar_loader.set_num_threads(0)
+    with pytest.raises(ValueError, match="io_threads must be positive"):
+        FastarLoader(assemblies_path, io_threads=0)
+
+
+def test_stream(
+    loader: FastarLoader, fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected = fasta_test_data
+    queries = [(name, contig, start, length)] * 10
+    sequences = list(loader.stream(queries, prefetch=2))
+    assert len(sequences) == 10
+    for sequence in sequences:
+        assert_array_equal(sequence, expected)
+
+    sampler = WindowSampler(loader, 100, seed=0)
+    batches = list(loader.stream(sampler, batch_size=4, num_batches=3))
+    assert [batch.shape for batch in batches] == [(4, 100)] * 3
+
+    # Errors end the stream, after the queries before them
+    stream = loader.stream([(name, contig, start, length), (name, "missing", 0, 1)], prefetch=1)
+    assert_array_equal(next(stream), expected)
+    with pytest.raises(ContigNotFoundError):
+        next(stream)
+    with pytest.raises(StopIteration):
+        next(stream)
+    with pytest.raises(ValueError, match="prefetch must be positive"):
+        loader.stream(queries, prefetch=0)
+
+
+def test_genome_iterable_dataset(loader: FastarLoader) -> None:
+    torch = pytest.importorskip("torch")
+    from fastar_loader.torch import GenomeIterableDataset
+
+    dataset = GenomeIterableDataset(loader, 100, batch_size=4, num_batches=5)
+    batches = list(torch.utils.data.DataLoader(dataset, batch_size=None))
+    assert len(batches) == 5
+    assert all(batch.shape == (4, 100) and int(batch.max()) <= 4 for batch in batches)
+    # The batches are dealt to the workers, which sample different windows
+    workers = torch.utils.data.DataLoader(dataset, batch_size=None, num_workers=2)
+    batches = list(workers)
+    assert len(batches) == 5
+    assert not torch.equal(batches[0], batches[1])
+
+    dataset = GenomeIterableDataset(loader, 100, batch_size=4, num_batches=1, encoding="one_hot")
+    (batch,) = list(dataset)
+    assert batch.shape == (4, 100, 4)
+    assert int(batch.sum(dim=2).max()) == 1
+    with pytest.raises(ValueError, match="Unknown encoding"):
+        GenomeIterableDataset(loader, 100, batch_size=4, num_batches=1, encoding="onehot")
+
+
+def test_truncated_mmap_cache(
+    assemblies_path: Path,
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+    tmp_path: Path,
+) -> None:
+    _, name, contig, start, length, expected = fasta_test_data
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    clean_cache(tmp_path)
+    loader = FastarLoader(tmp_path, storage_method="mmap")
+    assert_array_equal(loader.read_sequence(name, contig, start, length), expected)
+    # Truncating the mapped cache file raises an error instead of killing the process
+    for cache_file in tmp_path.glob(".fasta-map-cache*"):
+        os.truncate(cache_file, 0)
+    with pytest.raises(fastar_loader.CacheCorruptedError, match="truncated"):
+        loader.read_sequence(name, contig, start, length)
+    with pytest.raises(fastar_loader.CacheCorruptedError, match="truncated"):
+        _ = loader.names
+    with pytest.raises(fastar_loader.CacheCorruptedError, match="truncated"):
+        _ = name in loader
+
+
+def test_rebuild_while_mapped(
+    assemblies_path: Path,
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+    tmp_path: Path,
+) -> None:
+    _, name, contig, start, length, expected = fasta_test_data
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    clean_cache(tmp_path)
+    loader = FastarLoader(tmp_path, storage_method="mmap")
+    # The rebuild replaces the mapped cache file instead of truncating it
+    rebuilt = FastarLoader(tmp_path, storage_method="mmap", force_build=True)
+    assert_array_equal(loader.read_sequence(name, contig, start, length), expected)
+    assert_array_equal(rebuilt.read_sequence(name, contig, start, length), expected)
+    assert not list(tmp_path.glob(".fasta-map-cache*.tmp-*"))
+
+
+def test_cli(assemblies_path: Path, tmp_path: Path) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+
+    def cli(*args: str) -> subprocess.CompletedProcess[str]:
+        command = [sys.executable, "-m", "fastar_loader", *args, "--no-progress"]
+        return subprocess.run(command, capture_output=True, text=True)
+
+    assert cli("build", str(tmp_path)).returncode == 0
+    assert (tmp_path / ".fasta-map-cache").exists()
+    loader = FastarLoader(tmp_path)
+    assert cli("verify", str(tmp_path)).returncode == 0
+    inspect = cli("inspect", str(tmp_path))
+    assert inspect.returncode == 0
+    assert f"num_names: {len(loader.names)}" in inspect.stdout
+    for name in loader.names:
+        assert f"\n{name}\t" in inspect.stdout
+    unknown = cli("frobnicate")
+    assert unknown.returncode == 2
+    assert "Usage: fastar-loader" in unknown.stderr
+
+
+def test_summary(
+    loader: FastarLoader,
+    assemblies_path: Path,
+    fasta_structure: dict[str, list[tuple[str, int]]],
+    tmp_path: Path,
+) -> None:
+    summary = loader.summary()
+    assert [row["name"] for row in summary] == loader.names
+    for row in summary:
+        name = str(row["name"])
+        assert row["num_contigs"] == len(fasta_structure[name])
+        assert row["total_length"] == sum(length for _, length in fasta_structure[name])
+        assert row["file_size"] == (assemblies_path / f"{name}.fna.gz").stat().st_size
+
+    loader.write_summary(tmp_path / "summary.json")
+    assert json.loads((tmp_path / "summary.json").read_text()) == summary
+    loader.write_summary(tmp_path / "summary.tsv")
+    lines = (tmp_path / "summary.tsv").read_text().splitlines()
+    assert lines[0] == "name\tnum_contigs\ttotal_length\tfile_size"
+    assert lines[1].split("\t") == [str(value) for value in summary[0].values()]
+    with pytest.raises(ValueError, match="Unknown summary format"):
+        loader.write_summary(tmp_path / "summary.csv", format="csv")
+
+    paths = loader.write_chrom_sizes(tmp_path / "sizes")
+    assert len(paths) == len(loader.names)
+    for name, path in zip(loader.names, paths):
+        assert path == tmp_path / "sizes" / f"{name}.chrom.sizes"
+        rows = [line.split("\t") for line in path.read_text().splitlines()]
+        assert [(contig, int(length)) for contig, length in rows] == loader.contigs(name)
+
+
+def test_gaps(loader: FastarLoader) -> None:
+    for name in loader.names[:2]:
+        genome_gaps = loader.gaps(name)
+        assert list(genome_gaps) == [contig for contig, _ in loader.contigs(name)]
+        for contig, length in loader.contigs(name):
+            gaps = genome_gaps[contig]
+            assert gaps.dtype == np.uint64 and gaps.shape[1] == 2
+            assert_array_equal(loader.gaps(name, contig), gaps)
+            sequence = loader.read_sequence(name, contig, 0, length)
+            unknown = np.isin(sequence, [ord("N"), ord("n")])
+            expected = np.zeros(length, dtype=bool)
+            for start, end in gaps:
+                expected[start:end] = True
+            assert_array_equal(unknown, expected)
+            long_gaps = loader.gaps(name, contig, min_length=10)
+            assert all(end - start >= 10 for start, end in long_gaps)
+
+
+def test_check_indices(assemblies_path: Path, tmp_path: Path, expected_names: list[str]) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    loader = FastarLoader(tmp_path, check_indices=True, no_cache=True, storage_method="memory")
+    assert loader.skipped == []
+
+    # A contig beyond the end of the file only fails at read time without the checks
+    name = expected_names[0]
+    with open(tmp_path / f"{name}.fna.gz.fai", "a") as f:
+        f.write("extra\t100\t999999999999\t60\t61\n")
+    assert name in FastarLoader(tmp_path, no_cache=True, storage_method="memory")
+    with pytest.raises(RuntimeError, match="does not match"):
+        FastarLoader(tmp_path, check_indices=True, no_cache=True, storage_method="memory")
+    loader = FastarLoader(
+        tmp_path, check_indices=True, strict=False, no_cache=True, storage_method="memory"
+    )
+    assert [name for name, _ in loader.skipped] == [name]
+    assert "Contig extra ends at offset 1000000000100" in loader.skipped[0][1]
+
+
+def test_check_indices_cached(
+    assemblies_path: Path, tmp_path: Path, expected_names: list[str]
+) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    name = expected_names[0]
+    with open(tmp_path / f"{name}.fna.gz.fai", "a") as f:
+        f.write("extra\t100\t999999999999\t60\t61\n")
+    assert name in FastarLoader(tmp_path, no_cache=False)
+    # The cache built without the checks is not reused for a load requesting them
+    with pytest.raises(RuntimeError, match="does not match"):
+        FastarLoader(tmp_path, check_indices=True, no_cache=False)
+
+
+def test_verify(assemblies_path: Path, tmp_path: Path, expected_names: list[str]) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    loader = FastarLoader(tmp_path, no_cache=True, storage_method="memory")
+    assert loader.verify() == {}
+    assert loader.verify(deep=True, seed=0) == {}
+
+    # A truncated download loses the end-of-file marker and the end of the sequence
+    name = expected_names[0]
+    path = tmp_path / f"{name}.fna.gz"
+    path.write_bytes(path.read_bytes()[: path.stat().st_size // 2])
+    problems = loader.verify()
+    assert list(problems) == [name]
+    assert "The BGZF end-of-file marker is missing" in problems[name]
+    assert loader.verify(names=expected_names[1:]) == {}
+
+
+def test_metadata(assemblies_path: Path, tmp_path: Path, expected_names: list[str]) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    first, second = expected_names[:2]
+    (tmp_path / f"{first}.meta.tsv").write_text("# taxonomy\nclade\tFungi\naccession\tGCA_1\n")
+    loader = FastarLoader(tmp_path)
+    assert loader.metadata(first) == {"accession": "GCA_1", "clade": "Fungi"}
+    assert loader.metadata(second) == {}
+    assert loader.filter_by_metadata("clade", "Fungi") == [first]
+    assert loader.filter_by_metadata("clade", ["Plants"]) == []
+
+    # A new sidecar invalidates the cache
+    (tmp_path / f"{second}.meta.tsv").write_text("clade\tPlants\n")
+    loader = FastarLoader(tmp_path)
+    assert loader.filter_by_metadata("clade", ["Fungi", "Plants"]) == [first, second]
+
+
+def test_digests(assemblies_path: Path, expected_names: list[str]) -> None:
+    name = expected_names[0]
+    with gzip.open(assemblies_path / f"{name}.fna.gz", "rt") as f:
+        contig, *lines = f.read().split(">")[1].splitlines()
+    contig = contig.split()[0]
+    sequence = "".join(lines).upper().encode()
+    md5 = hashlib.md5(sequence).hexdigest()
+    sq = "SQ." + base64.urlsafe_b64encode(hashlib.sha512(sequence).digest()[:24]).decode()
+
+    loader = FastarLoader(assemblies_path, no_cache=True, storage_method="memory", digests=True)
+    assert loader.digest(name, contig) == sq
+    assert loader.digest(name, contig, algorithm="md5") == md5
+    assert loader.find_digest(f"ga4gh:{sq}") == (name, contig)
+    assert loader.find_digest(md5.upper()) == (name, contig)
+    assert loader.find_digest("SQ.unknown") is None
+    assert_array_equal(
+        loader.read_by_digest(md5, 10, 20), loader.read_sequence(name, contig, 10, 20)
+    )
+    with pytest.raises(ContigNotFoundError):
+        loader.read_by_digest("SQ.unknown", 0, 1)
+
+    with pytest.raises(RuntimeError, match="without digests"):
+        FastarLoader(assemblies_path, no_cache=True, storage_method="memory").digest(name, contig)
+
+
+def test_digests_cached(assemblies_path: Path, tmp_path: Path, expected_names: list[str]) -> None:
+    shutil.copytree(assemblies_path, tmp_path, dirs_exist_ok=True)
+    name = expected_names[0]
+    contig = FastarLoader(tmp_path, no_cache=False).contigs(name)[0][0]
+    # A cache without digests is not reused for a load requesting them
+    loader = FastarLoader(tmp_path, no_cache=False, digests=True)
+    assert loader.digest(name, contig).startswith("SQ.")
+    loader = FastarLoader(tmp_path, no_cache=False, digests=True)
+    assert loader.digest(name, contig).startswith("SQ.")
+    with pytest.raises(RuntimeError, match="without digests"):
+        FastarLoader(tmp_path, no_cache=False).digest(name, contig)
+
+
+def test_duplicate_contigs(
+    assemblies_path: Path, tmp_path: Path, expected_names: list[str]
+) -> None:
+    name = expected_names[0]
+    for path in assemblies_path.glob(f"{name}.fna.gz*"):
+        shutil.copy(path, tmp_path / path.name)
+        shutil.copy(path, tmp_path / path.name.replace(name, "copy"))
+    loader = FastarLoader(tmp_path, no_cache=True, storage_method="memory", digests=True)
+    groups = loader.duplicate_contigs()
+    for contig, length in loader.contigs("copy"):
+        if length > 0:
+            assert [(name, contig), ("copy", contig)] in groups
+    FastarLoader(tmp_path, no_cache=True, storage_method="memory", digests=True, duplicates="warn")
+    with pytest.raises(ValueError, match="requires digests"):
+        LoadOptions(duplicates="warn")
+    with pytest.raises(ValueError, match="lazy"):
+        LoadOptions(digests=True, duplicates="warn", lazy=True)
+    with pytest.raises(ValueError, match="duplicates policy"):
+        LoadOptions(digests=True, duplicates="dedup")
+
+
+@pytest.mark.skipif(
+    "refget" not in fastar_loader.fastar_loader.FEATURES, reason="built without refget"
+)
+def test_refget_server(assemblies_path: Path, expected_names: list[str]) -> None:
+    loader = FastarLoader(assemblies_path, no_cache=True, storage_method="memory", digests=True)
+    name = expected_names[0]
+    contig, length = loader.contigs(name)[0]
+    sq = loader.digest(name, contig)
+    server = loader.serve_refget()
+    url = f"http://{server.address}/sequence"
+    try:
+        with urllib.request.urlopen(f"{url}/{sq}?start=10&end=30") as response:
+            assert response.read() == loader.read_sequence(name, contig, 10, 20).tobytes()
+        md5 = loader.digest(name, contig, algorithm="md5")
+        with urllib.request.urlopen(f"{url}/{md5}/metadata") as response:
+            metadata = json.load(response)["metadata"]
+        assert metadata == {"md5": md5, "ga4gh": sq, "length": length, "aliases": []}
+        request = urllib.request.Request(f"{url}/{sq}", headers={"Range": "bytes=0-9"})
+        with urllib.request.urlopen(request) as response:
+            assert response.status == 206
+            assert response.read() == loader.read_sequence(name, contig, 0, 10).tobytes()
+        with pytest.raises(urllib.error.HTTPError) as e:
+            urllib.request.urlopen(f"{url}/SQ.unknown")
+        assert e.value.code == 404
+    finally:
+        server.stop()
+
+
+@pytest.mark.skipif(
+    "remote" not in fastar_loader.fastar_loader.FEATURES, reason="built without remote"
+)
+def test_remote_root(
+    assemblies_path: Path, tmp_path: Path, monkeypatch: pytest.MonkeyPatch
+) -> None:
+    # Local files through the object store API stand in for a bucket
+    monkeypatch.setenv("FASTAR_LOADER_CACHE_DIR", str(tmp_path))
+    remote = FastarLoader(f"file://{assemblies_path.resolve()}", storage_method="memory")
+    local = FastarLoader(assemblies_path, no_cache=True, storage_method="memory")
+    assert remote.names == local.names
+    for name in local.names:
+        contig, length = local.contigs(name)[-1]
+        assert_array_equal(
+            remote.read_sequence(name, contig, length - 100, 100),
+            local.read_sequence(name, contig, length - 100, 100),
+        )
+    # The index files are mirrored, but not the sequences
+    mirrored = [path for path in tmp_path.rglob("*.fna.gz*") if path.is_file()]
+    assert mirrored
+    assert all(path.stat().st_size == 0 for path in mirrored if path.suffix == ".gz")
+
+
+def test_bundle(assemblies_path: Path, tmp_path: Path, expected_names: list[str]) -> None:
+    bundle = tmp_path / "assemblies.bundle"
+    FastarLoader(assemblies_path, no_cache=True, storage_method="memory").export_bundle(bundle)
+    # Copies get new modification times, as on another machine
+    root = tmp_path / "copy"
+    shutil.copytree(assemblies_path, root, copy_function=shutil.copyfile)
+    calls: list[tuple[int, int, str]] = []
+    loader = FastarLoader(
+        root, bundle=bundle, on_progress=lambda *args: calls.append(args), show_progress=False
+    )
+    assert calls == []
+    assert loader.names == expected_names
+    name = expected_names[0]
+    assert loader.contigs(name) == FastarLoader(assemblies_path).contigs(name)
+
+    # The bundle is rejected if the index files differ
+    other = tmp_path / "other"
+    shutil.copytree(assemblies_path, other, copy_function=shutil.copyfile)
+    with open(other / f"{name}.fna.gz.fai", "a") as f:
+        f.write("\n")
+    with pytest.raises(RuntimeError, match="does not match"):
+        FastarLoader(other, bundle=bundle)
+
+
+def test_handle_relocation(
+    assemblies_path: Path, tmp_path: Path, expected_names: list[str]
+) -> None:
+    root = tmp_path / "root"
+    shutil.copytree(assemblies_path, root)
+    loader = FastarLoader(root, storage_method="mmap")
+    name = expected_names[0]
+    contig, _ = loader.contigs(name)[0]
+    expected = loader.read_sequence(name, contig, 0, 100)
+    handle = loader.handle
+    moved = tmp_path / "moved"
+    root.rename(moved)
+    relocated = FastarLoader.from_handle(handle, moved)
+    assert_array_equal(relocated.read_sequence(name, contig, 0, 100), expected)
+
+    # A shared archive attached to the wrong root is rejected
+    shared = FastarLoader(moved, storage_method="shmem")
+    with pytest.raises(RuntimeError, match="does not match"):
+        FastarLoader.from_handle(shared.handle, tmp_path)
@@ -1,6 +1,12 @@
+import http.server
+import shutil
+import threading
+from collections.abc import Iterator
 from pathlib import Path
 
+import fastar_loader
 import numpy as np
+import pytest
 from fastar_loader import read_sequence  # type: ignore
 from numpy.testing import assert_array_equal
 
@@ -19,3 +25,85 @@ def test_read_sequence_implicit_index(
     path, _, contig, start, length, sequence = fasta_test_data
     rust_sequence = read_sequence(str(path), contig, start, length)
     assert_array_equal(rust_sequence, sequence)
+
+
+def test_read_sequence_region(
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray],
+) -> None:
+    path, _, contig, start, length, sequence = fasta_test_data
+    region = f"{contig}:{start + 1}-{start + length}"
+    assert_array_equal(read_sequence(path, region), sequence)
+    with pytest.raises(ValueError, match="together"):
+        read_sequence(path, contig, start)
+
+
+def test_read_sequence_builds_index(
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray], tmp_path: Path
+) -> None:
+    path, _, contig, start, length, sequence = fasta_test_data
+    copy = tmp_path / path.name
+    shutil.copy(path, copy)
+    assert_array_equal(read_sequence(copy, contig, start, length), sequence)
+    assert Path(f"{copy}.gzi").exists()
+    assert Path(f"{copy}.fai").read_bytes() == Path(f"{path}.fai").read_bytes()
+
+
+class _RangeHandler(http.server.BaseHTTPRequestHandler):
+    """A static file server with support for range requests."""
+
+    root: Path
+
+    def do_HEAD(self) -> None:
+        self._send(head=True)
+
+    def do_GET(self) -> None:
+        self._send(head=False)
+
+    def _send(self, head: bool) -> None:
+        path = self.root / self.path.lstrip("/")
+        if not path.is_file():
+            self.send_error(404)
+            return
+        data = path.read_bytes()
+        range_header = self.headers.get("Range")
+        if range_header is None:
+            body = data
+            self.send_response(200)
+        else:
+            first, last = range_header.removeprefix("bytes=").split("-")
+            last = min(int(last), len(data) - 1) if last else len(data) - 1
+            body = data[int(first) : last + 1]
+            self.send_response(206)
+            self.send_header("Content-Range", f"bytes {first}-{last}/{len(data)}")
+        self.send_header("Content-Length", str(len(body)))
+        self.send_header("Last-Modified", self.date_time_string(int(path.stat().st_mtime)))
+        self.send_header("ETag", f'"{path.stat().st_mtime_ns}"')
+        self.end_headers()
+        if not head:
+            self.wfile.write(body)
+
+    def log_message(self, format: str, *args: object) -> None:
+        pass
+
+
+@pytest.fixture()
+def http_root(fasta_test_data: tuple[Path, str, str, int, int, np.ndarray]) -> Iterator[str]:
+    handler = type("Handler", (_RangeHandler,), {"root": fasta_test_data[0].parent})
+    server = http.server.ThreadingHTTPServer(("127.0.0.1", 0), handler)
+    thread = threading.Thread(target=server.serve_forever, daemon=True)
+    thread.start()
+    yield f"http://127.0.0.1:{server.server_address[1]}"
+    server.shutdown()
+
+
+@pytest.mark.skipif(
+    "remote" not in fastar_loader.fastar_loader.FEATURES, reason="built without remote"
+)
+def test_read_sequence_http(
+    fasta_test_data: tuple[Path, str, str, int, int, np.ndarray], http_root: str
+) -> None:
+    path, _, contig, start, length, sequence = fasta_test_data
+    url = f"{http_root}/{path.name}"
+    assert_array_equal(read_sequence(url, contig, start, length), sequence)
+    # Blocks are cached, so a second read does not need to fetch them again
+    assert_array_equal(read_sequence(url, contig, start, length), sequence)
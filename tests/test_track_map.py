@@ -1,11 +1,25 @@
+import gzip
 import multiprocessing
 import pickle
+import shutil
+import warnings
 from concurrent.futures import ProcessPoolExecutor
 from pathlib import Path
 
 import numpy as np
 import pytest
-from fastar_loader import TrackLoader
+from fastar_loader import (
+    FastarLoader,
+    GenomeDataset,
+    MappedTrackLoader,
+    NameNotFoundError,
+    OutOfBoundsError,
+    PositionSampler,
+    RleTrackLoader,
+    SparseTrackLoader,
+    TrackLoader,
+    benchmark,
+)
 from numpy.testing import assert_array_equal
 
 
@@ -26,6 +40,31 @@ def test_structure(loader: TrackLoader, track_structure: dict[str, list[tuple[st
         assert loader.contigs(name) == contigs
 
 
+def test_stats(loader: TrackLoader) -> None:
+    stats = loader.stats()
+    assert stats["num_names"] == len(loader.names)
+    assert stats["num_contigs"] == sum(len(loader.contigs(name)) for name in loader.names)
+    assert stats["storage_method"] == "memory" and stats["cache_paths"] == []
+    assert isinstance(stats["archive_size"], int) and stats["archive_size"] > 0
+
+
+def test_benchmark(loader: TrackLoader) -> None:
+    result = benchmark(loader, n_queries=20, length=10, seed=0)
+    assert result["queries_per_second"] > 0
+    assert result["latency_p90"] <= result["latency_max"]
+
+
+def test_summary(loader: TrackLoader, tracks_path: Path, tmp_path: Path) -> None:
+    for row in loader.summary():
+        name = str(row["name"])
+        assert row["num_contigs"] == len(loader.contigs(name))
+        assert row["file_size"] == (tracks_path / f"{name}.track.gz").stat().st_size
+    name = loader.names[0]
+    (path,) = loader.write_chrom_sizes(tmp_path, names=[name])
+    contig, length = loader.contigs(name)[0]
+    assert path.read_text().splitlines()[0] == f"{contig}\t{length}"
+
+
 def test_custom_num_workers(
     tracks_path: Path, expected_names: list[str], track_structure: dict[str, list[tuple[str, int]]]
 ) -> None:
@@ -150,3 +189,227 @@ def _read_f32(
 ) -> np.ndarray:
     bytes_data = track_loader.read_sequence(name, contig, start * 4, length * 4)
     return np.frombuffer(bytes_data, dtype=np.float32)
+
+
+def test_contig_accessors(loader: TrackLoader) -> None:
+    for name in loader.names:
+        contigs = loader.contigs(name)
+        assert loader.contig_dict(name) == dict(contigs)
+        for contig, length in contigs:
+            assert loader.has_contig(name, contig)
+            assert loader.contig_length(name, contig) == length
+        assert not loader.has_contig(name, "missing")
+
+
+def test_contigs_array(loader: TrackLoader) -> None:
+    for name in loader.names:
+        names, lengths = loader.contigs_array(name)
+        assert lengths.dtype == np.uint64
+        pairs = [(contig.decode(), int(length)) for contig, length in zip(names, lengths)]
+        assert pairs == loader.contigs(name)
+
+
+def test_dunder_protocol(loader: TrackLoader) -> None:
+    assert len(loader) == len(loader.names)
+    assert list(loader) == loader.names
+    assert loader.names[0] in loader
+    assert "missing" not in loader
+    assert repr(loader).startswith("<TrackLoader TrackMap(names=")
+
+
+def test_position_sampler(loader: TrackLoader) -> None:
+    # Every byte is a valid uint8 weight, whatever the track contains
+    sampler = PositionSampler(loader, dtype=np.uint8, block_size=256, seed=0)
+    assert sampler.total > 0
+    ids, positions = sampler.sample_indices(100)
+    assert ids.dtype == np.uint32 and positions.dtype == np.uint64
+    again = PositionSampler(loader, np.uint8, 256, seed=0)
+    assert_array_equal(again.sample_indices(100)[1], positions)
+    for name, contig, position in sampler.sample_positions(20):
+        assert position < loader.contig_length(name, contig)
+        assert loader.read_sequence(name, contig, position, 1)[0] > 0
+    with pytest.raises(RuntimeError):
+        PositionSampler(loader, dtype=np.int8)
+
+
+def test_genome_dataset(loader: TrackLoader, assemblies_path: Path) -> None:
+    fasta = FastarLoader(assemblies_path, no_cache=True, storage_method="memory")
+    dataset = GenomeDataset(fasta, {"coverage": (loader, np.float32)})
+    name = fasta.names[0]
+    contig, length = fasta.contigs(name)[0]
+    start = length // 2
+    sequence, tracks = dataset.read(name, contig, start, 100)
+    assert_array_equal(sequence, fasta.read_sequence(name, contig, start, 100))
+    assert tracks["coverage"].dtype == np.float32
+    assert_array_equal(tracks["coverage"], _read_f32(loader, name, contig, start, 100))
+    with pytest.raises(OutOfBoundsError):
+        dataset.read(name, contig, length - 50, 100)
+    # A value size not matching the contig lengths is rejected up front
+    with pytest.raises(RuntimeError):
+        GenomeDataset(fasta, {"coverage": (loader, np.float64)})
+
+
+def test_read_stacked(
+    loader: TrackLoader, track_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = track_test_data
+    stacked = loader.read_stacked([name, name], contig, start, length)
+    assert stacked.shape == (2, length) and stacked.dtype == np.float32
+    assert_array_equal(stacked[0], expected_sequence)
+    assert_array_equal(stacked[1], expected_sequence)
+    assert loader.read_stacked([], contig, start, length).shape == (0, length)
+    with pytest.raises(NameNotFoundError):
+        loader.read_stacked([name, "missing"], contig, start, length)
+
+
+def test_validate_against(loader: TrackLoader, assemblies_path: Path) -> None:
+    fasta = FastarLoader(assemblies_path, no_cache=True, storage_method="memory")
+    report = loader.validate_against(fasta, np.float32)
+    assert report.ok
+    assert report.missing_names == [] and report.length_mismatches == []
+
+    name = fasta.names[0]
+    fewer = FastarLoader(assemblies_path, names=[name], no_cache=True, storage_method="memory")
+    report = loader.validate_against(fewer, np.float32)
+    assert report.ok
+    assert sorted(report.extra_names) == sorted(n for n in loader.names if n != name)
+
+    report = loader.validate_against(fasta, np.float64)
+    assert not report.ok
+    mismatch = report.length_mismatches[0]
+    assert mismatch[2] == 2 * mismatch[3]
+
+
+def test_read_aggregated(
+    loader: TrackLoader, track_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = track_test_data
+    bin_size = 8
+    length -= length % bin_size
+    bins = expected_sequence[:length].reshape(-1, bin_size).astype(np.float64)
+    for op, reduce in [("mean", np.nanmean), ("max", np.nanmax), ("sum", np.nansum)]:
+        aggregated = loader.read_aggregated(name, contig, start, length, bin_size, op)
+        assert aggregated.dtype == np.float32 and aggregated.shape == (length // bin_size,)
+        with warnings.catch_warnings():
+            # Bins of only NaN
+            warnings.simplefilter("ignore", RuntimeWarning)
+            expected = reduce(bins, axis=1).astype(np.float32)
+        np.testing.assert_allclose(aggregated, expected, rtol=1e-5)
+    with pytest.raises(RuntimeError):
+        loader.read_aggregated(name, contig, start, length, bin_size, "median")
+    with pytest.raises(RuntimeError):
+        loader.read_aggregated(name, contig, start, length + 1, bin_size)
+
+
+def test_read_sequence_step(
+    loader: TrackLoader, track_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = track_test_data
+    values = loader.read_sequence(name, contig, start * 4, length * 4, step=16, value_size=4)
+    assert_array_equal(np.frombuffer(values, dtype=np.float32), expected_sequence[::16])
+    with pytest.raises(RuntimeError):
+        loader.read_sequence(name, contig, start * 4, length * 4 - 1, step=2, value_size=4)
+    # The same read on a separate thread with a timeout
+    values = loader.read_sequence(
+        name, contig, start * 4, length * 4, step=16, value_size=4, timeout=10.0
+    )
+    assert_array_equal(np.frombuffer(values, dtype=np.float32), expected_sequence[::16])
+
+
+def test_stream(
+    loader: TrackLoader, track_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = track_test_data
+    queries = [(name, contig, start * 4, length * 4)] * 5
+    for values in loader.stream(queries, prefetch=2):
+        assert_array_equal(np.frombuffer(values, dtype=np.float32), expected_sequence)
+
+
+def test_read_sequence_reverse(
+    loader: TrackLoader, track_test_data: tuple[Path, str, str, int, int, np.ndarray]
+) -> None:
+    _, name, contig, start, length, expected_sequence = track_test_data
+    length -= length % 2
+    expected_sequence = expected_sequence[:length]
+    values = loader.read_sequence(name, contig, start * 4, length * 4, value_size=4, reverse=True)
+    assert_array_equal(np.frombuffer(values, dtype=np.float32), expected_sequence[::-1])
+
+    # Pairs of consecutive values as two channels, swapped
+    values = loader.read_sequence(
+        name, contig, start * 4, length * 4, value_size=4, reverse=True, channels=[1, 0]
+    )
+    expected = expected_sequence.reshape(-1, 2)[::-1, ::-1].reshape(-1)
+    assert_array_equal(np.frombuffer(values, dtype=np.float32), expected)
+    with pytest.raises(RuntimeError):
+        loader.read_sequence(name, contig, 0, 8, reverse=True, channels=[0, 0])
+
+
+def test_sparse_track(tmp_path: Path) -> None:
+    path = tmp_path / "peaks.bedGraph"
+    path.write_text("track type=bedGraph\nchr2\t5\t8\t2.5\nchr1\t0\t2\t1\nchr1\t4\t6\t-1\n")
+    cache_path = tmp_path / "peaks.cache"
+    for _ in range(2):
+        track = SparseTrackLoader(path, cache_path=cache_path)
+        assert track.contigs() == [("chr2", 1), ("chr1", 2)]
+        assert_array_equal(track.read_sequence("chr1", 1, 5), [1, 0, 0, -1, -1])
+        assert cache_path.exists()
+    values = track.read_sequence("chr2", 6, 4, fill=np.nan)
+    assert_array_equal(values, [2.5, 2.5, np.nan, np.nan])
+    assert values.dtype == np.float32
+    assert_array_equal(track.read_sequence("chrM", 0, 2), [0, 0])
+
+    # A changed file invalidates the cache
+    path.write_text("chr1\t0\t3\t7\n")
+    track = SparseTrackLoader(path, cache_path=cache_path)
+    assert track.contigs() == [("chr1", 1)]
+    assert_array_equal(track.read_sequence("chr1", 0, 4), [7, 7, 7, 0])
+
+    path.write_text("chr1\t0\t3\t7\nchr1\t2\t4\t1\n")
+    with pytest.raises(RuntimeError):
+        SparseTrackLoader(path)
+
+
+def test_rle_track(
+    loader: TrackLoader,
+    track_test_data: tuple[Path, str, str, int, int, np.ndarray],
+    tmp_path: Path,
+) -> None:
+    _, name, contig, start, length, expected_sequence = track_test_data
+    path = tmp_path / f"{name}.track.rle"
+    loader.write_rle(name, path, dtype=np.float32)
+    track = RleTrackLoader(path)
+    assert track.value_size == 4
+    assert track.contigs() == loader.contigs(name)
+    total_length = sum(contig_length for _, contig_length in track.contigs())
+    assert 0 < track.num_runs <= total_length // 4
+    values = track.read_sequence(contig, start * 4, length * 4)
+    assert_array_equal(np.frombuffer(values, dtype=np.float32), expected_sequence)
+    # Unaligned reads return the same bytes as the dense track
+    assert_array_equal(
+        track.read_sequence(contig, start * 4 + 1, 9),
+        loader.read_sequence(name, contig, start * 4 + 1, 9),
+    )
+    with pytest.raises(OutOfBoundsError):
+        track.read_sequence(contig, 0, dict(track.contigs())[contig] + 1)
+
+
+def test_mapped_track(
+    track_test_data: tuple[Path, str, str, int, int, np.ndarray],
+    tracks_path: Path,
+    tmp_path: Path,
+) -> None:
+    _, name, contig, start, length, expected_sequence = track_test_data
+    path = tmp_path / f"{name}.track"
+    with gzip.open(tracks_path / f"{name}.track.gz", "rb") as gz, open(path, "wb") as f:
+        shutil.copyfileobj(gz, f)
+    shutil.copy(tracks_path / f"{name}.track.gz.idx", tmp_path / f"{name}.track.idx")
+    track = MappedTrackLoader(path)
+    values = track.read_sequence(contig, start * 4, length * 4)
+    assert_array_equal(values.view(np.float32), expected_sequence)
+    # A read-only view, which keeps the map alive
+    assert not values.flags.writeable
+    assert values.base is not None
+    del track
+    assert_array_equal(values.view(np.float32), expected_sequence)
+    with pytest.raises(OutOfBoundsError):
+        MappedTrackLoader(path).read_sequence(contig, 0, 1 << 40)